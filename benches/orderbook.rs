@@ -0,0 +1,29 @@
+//! 订单簿基准测试。
+//!
+//! 使用 `cargo bench --bench orderbook` 运行完整规模的场景，
+//! 追加 `-- --smoke` 只运行小规模数据以快速校验。
+use hello_cargo::orderbook::bench::{run_scenario, BenchScale, Scenario};
+
+fn main() {
+    let scale = if std::env::args().any(|arg| arg == "--smoke") {
+        BenchScale::Smoke
+    } else {
+        BenchScale::Full
+    };
+    let filter: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+
+    for scenario in Scenario::ALL {
+        if !filter.is_empty()
+            && !filter
+                .iter()
+                .any(|name| scenario.name().contains(name.as_str()))
+        {
+            continue;
+        }
+        let result = run_scenario(scenario, scale);
+        println!("{result}");
+    }
+}
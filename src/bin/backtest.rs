@@ -0,0 +1,38 @@
+//! 不依赖 Python 的单只股票回测示例。
+//!
+//! ```text
+//! cargo run --bin backtest -- --data ./data --symbol 688007.SH --date 20231201 \
+//!     --mode L2P --orders orders.csv --output ./output
+//! ```
+//!
+//! 订单脚本为 CSV，每行为 `time,side,price,qty,type`，例如 `093000000,buy,10.5,100,L`。
+use hello_cargo::orderbook::backtest::{self, BacktestConfig, USAGE};
+use std::process;
+
+fn main() {
+    let config = match BacktestConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let summary = match backtest::run(&config) {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+
+    if let Some(dir) = &config.output_dir {
+        match backtest::write_manifest(dir, &config, &summary) {
+            Ok(path) => println!("manifest written to {}", path.display()),
+            Err(err) => {
+                eprintln!("error: failed to write manifest: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod orderbook;
+#[cfg(feature = "python")]
+mod libpy;
+#[cfg(feature = "data")]
 mod snapshot_helper;
 use std::{
     sync::{mpsc, Arc, Mutex},
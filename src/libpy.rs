@@ -1,20 +1,15 @@
-use libc::EEXIST;
-use polars::prelude::DataFrame;
+use crate::orderbook::prelude::*;
+use crate::snapshot_helper::*;
 use polars::prelude::*;
-use pyo3::{self, basic::getattr, prelude::*};
-#[warn(unused_imports)]
-mod depth;
-mod snapshot_helper;
-use depth::prelude::*;
-use pyo3::types::{PyDict, PyList};
-use rayon::result;
-use snapshot_helper::*;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::Mutex;
-use std::time;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
 /// TradeMockerRS 是一个用于模拟交易的 Rust 结构体，通过 PyO3 与 Python 进行交互。
@@ -36,6 +31,49 @@ unsafe impl Send for TradeMockerRS {}
 
 unsafe impl Sync for TradeMockerRS {}
 
+create_exception!(trade_mocker_rust, MarketException, PyException);
+create_exception!(trade_mocker_rust, NotFound, MarketException);
+create_exception!(trade_mocker_rust, InvalidRequest, MarketException);
+create_exception!(trade_mocker_rust, DataError, MarketException);
+create_exception!(trade_mocker_rust, RiskRejected, MarketException);
+create_exception!(trade_mocker_rust, Connectivity, MarketException);
+create_exception!(trade_mocker_rust, EndOfData, MarketException);
+
+/// 按 `ErrorCategory` 将 `MarketError` 转换为对应的 Python 异常，异常的 `code` 属性为 `MarketError::code`。
+impl From<MarketError> for PyErr {
+    fn from(err: MarketError) -> PyErr {
+        let message = err.to_string();
+        let py_err = match err.category() {
+            ErrorCategory::NotFound => NotFound::new_err(message),
+            ErrorCategory::InvalidRequest => InvalidRequest::new_err(message),
+            ErrorCategory::DataError => DataError::new_err(message),
+            ErrorCategory::RiskRejected => RiskRejected::new_err(message),
+            ErrorCategory::Connectivity => Connectivity::new_err(message),
+            ErrorCategory::EndOfData => EndOfData::new_err(message),
+        };
+        Python::with_gil(|py| {
+            let _ = py_err.value(py).setattr("code", err.code());
+        });
+        py_err
+    }
+}
+
+/// 将结果序列化为 JSON 字符串，失败时抛出 `MarketException`。
+fn to_json<T: serde::Serialize>(value: &T) -> PyResult<String> {
+    serde_json::to_string(value).map_err(|err| MarketException::new_err(err.to_string()))
+}
+
+/// 锁定交易所，锁被污染时抛出 `MarketException`，不再 panic。
+///
+/// 只借用 `exchange` 字段，持有锁时仍然可以修改 `TradeMockerRS` 的其他字段。
+fn lock_exchange(
+    exchange: &Mutex<Exchange<SkipListMarketDepth>>,
+) -> PyResult<MutexGuard<'_, Exchange<SkipListMarketDepth>>> {
+    exchange
+        .lock()
+        .map_err(|err| MarketException::new_err(err.to_string()))
+}
+
 fn measure_time<F, T>(f: F) -> (T, Duration)
 where
     F: FnOnce() -> T,
@@ -62,6 +100,9 @@ impl TradeMockerRS {
     ///
     /// # 返回
     /// - 返回一个 TradeMockerRS 实例。
+    ///
+    /// # 错误
+    /// - `exchange_mode` 不是 `live` 或 `backtest` 时抛出 `InvalidRequest`。
     #[staticmethod]
     pub fn new(
         mode: &str,
@@ -72,12 +113,12 @@ impl TradeMockerRS {
         data_path: &str,
         exchange_mode: &str,
         verbose: i32,
-    ) -> Self {
+    ) -> PyResult<Self> {
         if !vec!["live", "backtest"].contains(&exchange_mode) {
-            panic!("exchange_mode must be one of [live, backtest] ");
+            return Err(MarketError::ExchangeModeUnsupproted.into());
         }
         let exchange = Exchange::new(exchange_mode, date);
-        Self {
+        Ok(Self {
             exchange: Arc::new(Mutex::new(exchange)),
             exchange_mode: exchange_mode.to_string(),
             file_type: file_type.to_string(),
@@ -88,7 +129,7 @@ impl TradeMockerRS {
             order_to_broker: HashMap::new(),
             need_output: need_output,
             orderbook_level: orderbook_level,
-        }
+        })
     }
 
     /// 发送订单
@@ -101,10 +142,12 @@ impl TradeMockerRS {
     /// - `bs_flag`: 买卖标识，`buy` 或 `sell`。
     ///
     /// # 返回
-    /// - 返回订单 ID，如果失败返回 -1。
+    /// - 返回订单 ID。
     ///
-    pub fn init(&mut self, stock_code: &str) -> bool {
-        if !self.exchange.lock().unwrap().exists_stock(stock_code) {
+    /// # 错误
+    /// - 加载数据或创建经纪人失败时抛出对应的 `MarketException` 子类。
+    pub fn init(&mut self, stock_code: &str) -> PyResult<bool> {
+        if !lock_exchange(&self.exchange)?.exists_stock(stock_code) {
             let mut data = DataCollator::new(
                 stock_code.to_string().clone(),
                 self.file_type.clone(),
@@ -112,11 +155,17 @@ impl TradeMockerRS {
                 self.date.clone(),
                 self.mode.as_str(),
             );
-            data.init();
-            let stock_type = data.da_api.as_mut().unwrap()._stock_type.borrow().clone();
+            data.init()?;
+            let stock_type = data
+                .da_api
+                .as_mut()
+                .ok_or(MarketError::HistoryIsNone)?
+                ._stock_type
+                .borrow()
+                .clone();
             let exchange_mode = ExchangeMode::from_str(self.exchange_mode.as_str())
                 .unwrap_or(ExchangeMode::Backtest);
-            let mut exchange = self.exchange.lock().unwrap();
+            let mut exchange = lock_exchange(&self.exchange)?;
             let market_code = data.exchange_code.clone();
             let snapshot = Rc::new(RefCell::new(OrderBookSnapshot::new(
                 stock_code.to_string(),
@@ -126,30 +175,20 @@ impl TradeMockerRS {
             self.ob_snapshots
                 .insert(stock_code.to_string(), snapshot.clone());
 
-            if let Err(e) = exchange.add_broker(
+            exchange.add_broker(
                 MarketType::from_str(market_code.as_str()).unwrap_or(MarketType::SH),
                 exchange_mode,
                 stock_type,
                 stock_code.to_string(),
                 1.0,
-            ) {
-                eprintln!("Failed to add broker: {}", e);
-                false
-            } else {
-                let _ = exchange.add_data(stock_code, data);
-                let mut hook = get_hook(snapshot.clone());
-                hook.max_level = self.orderbook_level as i64;
-                let _ = exchange.register_orderbook_hook(
-                    stock_code,
-                    HookType::Orderbook,
-                    "snapshot",
-                    hook,
-                );
-                true
-            }
-        } else {
-            true
+            )?;
+            let _ = exchange.add_data(stock_code, data);
+            let mut hook = get_hook(snapshot.clone());
+            hook.max_level = self.orderbook_level as usize;
+            let _ =
+                exchange.register_orderbook_hook(stock_code, HookType::Orderbook, "snapshot", hook);
         }
+        Ok(true)
     }
     pub fn send_order(
         &mut self,
@@ -158,27 +197,22 @@ impl TradeMockerRS {
         order_price: f64,
         order_volume: i64,
         bs_flag: &str,
-    ) -> i64 {
-        let (result, elapsed) = measure_time(|| if !self.init(stock_code) { false } else { true });
-        if !result {
-            return -1;
-        }
+    ) -> PyResult<i64> {
+        let (result, elapsed) = measure_time(|| self.init(stock_code));
+        result?;
         print!("elapsed = {elapsed:?}\n");
-        match self.exchange.lock().unwrap().send_order(
+        let order_id = lock_exchange(&self.exchange)?.send_order(
             "none",
             stock_code,
             order_time,
             order_price,
             order_volume,
             bs_flag,
-        ) {
-            Ok(order_id) => {
-                self.order_to_broker
-                    .insert(order_id, stock_code.to_string());
-                order_id
-            }
-            Err(_) => -1,
-        }
+            None,
+        )?;
+        self.order_to_broker
+            .insert(order_id, stock_code.to_string());
+        Ok(order_id)
     }
 
     /// 撤销订单
@@ -188,90 +222,73 @@ impl TradeMockerRS {
     ///
     /// # 返回
     /// - 成功撤销返回 `true`。
-    pub fn cancel_order(&mut self, order_number: i64) -> bool {
-        let stock_code = self.order_to_broker.get(&order_number).unwrap().clone();
-        self.order_to_broker.remove(&order_number);
-        self.exchange
-            .lock()
-            .unwrap()
-            .cancel_order(stock_code.as_str(), order_number)
-            .is_ok()
+    ///
+    /// # 错误
+    /// - 订单编号不是通过 `send_order` 得到的时抛出 `NotFound`。
+    pub fn cancel_order(&mut self, order_number: i64) -> PyResult<bool> {
+        let stock_code = self
+            .order_to_broker
+            .remove(&order_number)
+            .ok_or(MarketError::OrderNotFound)?;
+        lock_exchange(&self.exchange)?.cancel_order(stock_code.as_str(), order_number)?;
+        Ok(true)
     }
 
     /// 获取待处理订单
     ///
     /// # 返回
     /// - 返回以 JSON 格式表示的待处理订单列表。
-    pub fn get_pending_orders(&self, stock_code: Option<&str>) -> String {
+    pub fn get_pending_orders(&self, stock_code: Option<&str>) -> PyResult<String> {
         let mut orders = HashMap::new();
-        let _ = self.exchange.lock().unwrap().get_orders(
-            &mut orders,
-            &vec![OrderStatus::New, OrderStatus::PartiallyFilled],
-            stock_code,
-        );
-        serde_json::to_string(&orders).unwrap()
+        let _ = lock_exchange(&self.exchange)?.get_open_orders(&mut orders, stock_code);
+        to_json(&orders)
     }
 
-    pub fn get_crurent_time(&self, stock_code: Option<&str>) -> i64 {
-        self.exchange
-            .lock()
-            .unwrap()
+    pub fn get_crurent_time(&self, stock_code: Option<&str>) -> PyResult<i64> {
+        Ok(lock_exchange(&self.exchange)?
             .get_crurent_time(stock_code)
-            .unwrap_or(-1)
+            .unwrap_or(-1))
     }
 
     /// 获取已取消订单
     ///
     /// # 返回
     /// - 返回以 JSON 格式表示的已取消订单列表。
-    pub fn get_cancel_orders(&self, stock_code: Option<&str>) -> String {
+    pub fn get_cancel_orders(&self, stock_code: Option<&str>) -> PyResult<String> {
         let mut orders = HashMap::new();
-        let _ = self.exchange.lock().unwrap().get_orders(
+        let _ = lock_exchange(&self.exchange)?.get_orders(
             &mut orders,
             &vec![OrderStatus::Canceled],
             stock_code,
         );
-        serde_json::to_string(&orders).unwrap()
+        to_json(&orders)
     }
 
-    pub fn get_finished_order(&self, stock_code: Option<&str>) -> String {
+    pub fn get_finished_order(&self, stock_code: Option<&str>) -> PyResult<String> {
         let mut orders = HashMap::new();
-        let _ = self.exchange.lock().unwrap().get_orders(
+        let _ = lock_exchange(&self.exchange)?.get_orders(
             &mut orders,
             &vec![OrderStatus::Filled],
             stock_code,
         );
-        serde_json::to_string(&orders).unwrap()
+        to_json(&orders)
     }
 
-    pub fn elapse(&self, duration: i64, stock_code: Option<&str>) -> i64 {
-        let filled = self
-            .exchange
-            .lock()
-            .unwrap()
-            .elapse(duration, stock_code)
-            .unwrap_or(0);
-        filled
+    pub fn elapse(&self, duration: i64, stock_code: Option<&str>) -> PyResult<i64> {
+        let filled = lock_exchange(&self.exchange)?.elapse(duration, stock_code)?;
+        Ok(filled)
     }
 
-    pub fn get_latest_orders(&self, stock_code: Option<&str>) -> String {
+    pub fn get_latest_orders(&self, stock_code: Option<&str>) -> PyResult<String> {
         let mut orders = HashMap::new();
-        let _ = self
-            .exchange
-            .lock()
-            .unwrap()
-            .get_latest_orders(&mut orders, stock_code);
-        serde_json::to_string(&orders).unwrap()
+        let _ = lock_exchange(&self.exchange)?.get_latest_orders(&mut orders, stock_code);
+        to_json(&orders)
     }
 
-    pub fn get_all_orders(&self, stock_code: Option<&str>) -> String {
+    pub fn get_all_orders(&self, stock_code: Option<&str>) -> PyResult<String> {
         let mut orders = HashMap::new();
-        let _ = self
-            .exchange
-            .lock()
-            .unwrap()
-            .get_orders(&mut orders, &vec![], stock_code);
-        serde_json::to_string(&orders).unwrap()
+        let _ = lock_exchange(&self.exchange)?.get_orders(&mut orders, &vec![], stock_code);
+        to_json(&orders)
     }
 
     pub fn elapse_with_orders(
@@ -279,50 +296,39 @@ impl TradeMockerRS {
         start: i64,
         duration: i64,
         stock_code: Option<&str>,
-    ) -> String {
-        let current_timepoint = self
-            .exchange
-            .lock()
-            .unwrap()
+    ) -> PyResult<String> {
+        let current_timepoint = lock_exchange(&self.exchange)?
             .get_crurent_time(stock_code)
             .unwrap_or(0);
         let expected_duration =
             time_difference_ms_i64(current_timepoint, start).unwrap_or(0) + duration;
-        self.elapse(expected_duration, stock_code);
+        self.elapse(expected_duration, stock_code)?;
         self.get_latest_orders(stock_code)
     }
 
-    pub fn match_order_util_mdtime(&mut self, mkt_clock_time: i64) -> String {
-        let current_time = self.get_crurent_time(None);
+    pub fn match_order_util_mdtime(&mut self, mkt_clock_time: i64) -> PyResult<String> {
+        let current_time = self.get_crurent_time(None)?;
         let duration = time_difference_ms_i64(current_time, mkt_clock_time).unwrap_or(0);
-        let filled = self.exchange.lock().unwrap().elapse(duration, None);
+        lock_exchange(&self.exchange)?.elapse(duration, None)?;
         let mut orders = HashMap::new();
-        let _ = self
-            .exchange
-            .lock()
-            .unwrap()
-            .get_latest_orders(&mut orders, None);
-        serde_json::to_string(&orders).unwrap()
+        let _ = lock_exchange(&self.exchange)?.get_latest_orders(&mut orders, None);
+        to_json(&orders)
     }
 
-    pub fn match_order_util_recvtime(&mut self, mkt_clock_time: i64) -> String {
-        let current_time = self.get_crurent_time(None);
+    pub fn match_order_util_recvtime(&mut self, mkt_clock_time: i64) -> PyResult<String> {
+        let current_time = self.get_crurent_time(None)?;
         let duration = time_difference_ms_i64(current_time, mkt_clock_time).unwrap_or(0);
-        let filled = self.exchange.lock().unwrap().elapse(duration, None);
+        lock_exchange(&self.exchange)?.elapse(duration, None)?;
         let mut orders = HashMap::new();
-        let _ = self
-            .exchange
-            .lock()
-            .unwrap()
-            .get_latest_orders(&mut orders, None);
-        serde_json::to_string(&orders).unwrap()
+        let _ = lock_exchange(&self.exchange)?.get_latest_orders(&mut orders, None);
+        to_json(&orders)
     }
 
-    pub fn restore_real_orderbook(&mut self, snapshot: String) -> bool {
-        let mut exchange: Exchange<SkipListMarketDepth> =
-            serde_json::from_str(&snapshot).expect("Failed to deserialize snapshot");
-        let ret = exchange.recover().unwrap_or(false);
-        ret
+    pub fn restore_real_orderbook(&mut self, snapshot: String) -> PyResult<bool> {
+        let mut exchange: Exchange<SkipListMarketDepth> = serde_json::from_str(&snapshot)
+            .map_err(|err| DataError::new_err(format!("failed to deserialize snapshot: {err}")))?;
+        let ret = exchange.recover()?;
+        Ok(ret)
     }
 
     pub fn add_order_data(
@@ -336,7 +342,7 @@ impl TradeMockerRS {
         md_time_slice: Vec<i64>,
         receive_date_time_slice: Vec<i64>,
         appl_seq_num_slice: Vec<i64>,
-    ) {
+    ) -> PyResult<()> {
         // Create DataFrame
         let order_df = df!(
             "OrderBSFlag" => order_bs_flag_slice,
@@ -348,9 +354,10 @@ impl TradeMockerRS {
             "ReceiveDateTime" => receive_date_time_slice,
             "ApplSeqNum" => appl_seq_num_slice
         )
-        .unwrap();
+        .map_err(|err| DataError::new_err(err.to_string()))?;
 
         // self.exchange.add_order_data(stock_code, order_df);
+        Ok(())
     }
 
     pub fn add_trade_data(
@@ -365,7 +372,7 @@ impl TradeMockerRS {
         md_time_slice: Vec<i64>,
         receive_date_time_slice: Vec<i64>,
         appl_seq_num_slice: Vec<i64>,
-    ) {
+    ) -> PyResult<()> {
         // Create DataFrame
         let trade_df = df!(
             "TradeBSFlag" => trade_bs_flag_slice,
@@ -378,45 +385,101 @@ impl TradeMockerRS {
             "ReceiveDateTime" => receive_date_time_slice,
             "ApplSeqNum" => appl_seq_num_slice
         )
-        .unwrap();
+        .map_err(|err| DataError::new_err(err.to_string()))?;
 
         // self.exchange.add_trade_data(stock_code, trade_df);
+        Ok(())
     }
 
-    pub fn get_current_l3_snapshot(&self, stock_code: &str) -> String {
-        let snapshot = self.ob_snapshots.get(stock_code);
-        if snapshot.is_none() {
-            return "{}".to_string();
-        }
-        let result = serde_json::to_string(snapshot.as_ref().unwrap()).unwrap();
-        result
+    /// 一次性获取多只股票的最优价
+    ///
+    /// # 参数
+    /// - `symbols`: 要查询的股票代码，`None` 表示所有股票，找不到的股票被忽略。
+    ///
+    /// # 返回
+    /// - 返回按列组织的字典，键为 `stock_code`、`best_bid`、`best_ask`、`best_bid_tick`、
+    ///   `best_ask_tick`、`last_price` 和 `timestamp`，每一列都按股票代码排序。
+    pub fn bbo_snapshot(&self, py: Python, symbols: Option<Vec<&str>>) -> PyResult<PyObject> {
+        let rows = lock_exchange(&self.exchange)?
+            .bbo_snapshot(&OrderSourceType::UserOrder, symbols.as_deref());
+        let columns = PyDict::new(py);
+        columns.set_item(
+            "stock_code",
+            rows.iter()
+                .map(|row| row.stock_code.as_str())
+                .collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "best_bid",
+            rows.iter().map(|row| row.best_bid).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "best_ask",
+            rows.iter().map(|row| row.best_ask).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "best_bid_tick",
+            rows.iter().map(|row| row.best_bid_tick).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "best_ask_tick",
+            rows.iter().map(|row| row.best_ask_tick).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "last_price",
+            rows.iter().map(|row| row.last_price).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "timestamp",
+            rows.iter().map(|row| row.timestamp).collect::<Vec<_>>(),
+        )?;
+        Ok(columns.into())
     }
 
-    pub fn presist_l3_data(&mut self, stock_code: &str, clean_up: Option<bool>) -> bool {
-        if !self.need_output {
-            panic!("presist_l3_data Error: param need_output must be setted to ture!");
+    /// 以 Arrow IPC 流的形式获取指定股票的档位
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `max_level`: 每一方返回的最大档位数量。
+    ///
+    /// # 返回
+    /// - 返回 Arrow IPC 流格式的字节，列为 `side`、`level_idx`、`price`、`qty`、`count` 和
+    ///   `is_shadow`，可以用 `pyarrow.ipc.open_stream(data).read_all()` 读取。
+    pub fn levels_ipc(&self, py: Python, stock_code: &str, max_level: usize) -> PyResult<PyObject> {
+        let mut df = lock_exchange(&self.exchange)?.levels_as_dataframe(stock_code, max_level)?;
+        let mut buf = Vec::new();
+        IpcStreamWriter::new(&mut buf)
+            .finish(&mut df)
+            .map_err(|err| MarketError::DataExportError(err.to_string()))?;
+        Ok(PyBytes::new(py, &buf).into())
+    }
+
+    pub fn get_current_l3_snapshot(&self, stock_code: &str) -> PyResult<String> {
+        match self.ob_snapshots.get(stock_code) {
+            Some(snapshot) => to_json(snapshot),
+            None => Ok("{}".to_string()),
         }
-        let sy_time_init: time::SystemTime = time::SystemTime::now();
-        let snapshot = self.ob_snapshots.get(stock_code);
+    }
 
-        if snapshot.is_none() {
-            return false;
+    pub fn presist_l3_data(&mut self, stock_code: &str, clean_up: Option<bool>) -> PyResult<bool> {
+        if !self.need_output {
+            return Err(InvalidRequest::new_err(
+                "presist_l3_data Error: param need_output must be setted to ture!",
+            ));
         }
-        let filled = self
-            .exchange
-            .lock()
-            .unwrap()
-            .elapse(24 * 3600 * 1000, Some(stock_code));
-        let result = snapshot.unwrap().as_ref().borrow().presist();
+        let sy_time_init = Instant::now();
+        let snapshot = match self.ob_snapshots.get(stock_code) {
+            Some(snapshot) => snapshot.clone(),
+            None => return Ok(false),
+        };
+        lock_exchange(&self.exchange)?.elapse(24 * 3600 * 1000, Some(stock_code))?;
+        let result = snapshot.as_ref().borrow().presist();
         println!(
             "presist l2p: {} generate and save parquet total time spend: {:?} us",
             stock_code,
-            time::SystemTime::now()
-                .duration_since(sy_time_init)
-                .unwrap()
-                .as_micros()
+            sy_time_init.elapsed().as_micros()
         );
-        result
+        Ok(result)
     }
 }
 
@@ -464,7 +527,7 @@ fn trade_mocker_instance(
         data_path,
         exchange_mode,
         verbose,
-    );
+    )?;
     let my_class_py = my_class.into_py(py);
     Ok(my_class_py)
 }
@@ -483,12 +546,21 @@ fn trade_mocker_instance(
 /// # 详细说明
 /// - 该模块中注册了 `TradeMockerRS` 类，使其可以在 Python 中直接使用。
 /// - 同时，注册了 `trade_mocker_instance` 工厂函数，使用户可以方便地通过 Python 调用此函数来创建 `TradeMockerRS` 的实例。
+/// - 注册了以 `MarketException` 为基类的异常，Python 代码可以按类型捕获 `MarketError`。
 #[pymodule]
-fn trade_mocker_rust(_py: Python, m: &PyModule) -> PyResult<()> {
+fn trade_mocker_rust(py: Python, m: &PyModule) -> PyResult<()> {
     // 将 `TradeMockerRS` 类注册为 Python 模块中的类。
     m.add_class::<TradeMockerRS>()?;
 
     // 将 `trade_mocker_instance` 函数注册为 Python 模块中的函数。
     m.add_wrapped(wrap_pyfunction!(trade_mocker_instance))?;
+
+    m.add("MarketException", py.get_type::<MarketException>())?;
+    m.add("NotFound", py.get_type::<NotFound>())?;
+    m.add("InvalidRequest", py.get_type::<InvalidRequest>())?;
+    m.add("DataError", py.get_type::<DataError>())?;
+    m.add("RiskRejected", py.get_type::<RiskRejected>())?;
+    m.add("Connectivity", py.get_type::<Connectivity>())?;
+    m.add("EndOfData", py.get_type::<EndOfData>())?;
     Ok(())
 }
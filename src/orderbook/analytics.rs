@@ -0,0 +1,272 @@
+use super::*;
+
+/// A 股连续竞价时段，以 `HHMMSSmmm` 表示。集合竞价和午间休市的时间不计入统计。
+pub const CONTINUOUS_SESSIONS: [(i64, i64); 2] = [(93000000, 113000000), (130000000, 145700000)];
+
+/// 某一时刻的最优价和最优价上的挂单量。
+#[derive(Debug, Clone, Copy)]
+struct BookSample {
+    timestamp: i64,
+    best_bid_tick: i64,
+    best_ask_tick: i64,
+    bid_touch_vol: i64,
+    ask_touch_vol: i64,
+}
+
+impl BookSample {
+    fn has_bid(&self) -> bool {
+        self.best_bid_tick != INVALID_MIN
+    }
+
+    fn has_ask(&self) -> bool {
+        self.best_ask_tick != INVALID_MAX
+    }
+}
+
+/// `BookAnalyticsReport` 汇总回放期间订单簿的时间加权统计。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BookAnalyticsReport {
+    /// 计入统计的连续竞价时间，以毫秒表示
+    pub observed_ms: i64,
+    /// 时间加权平均买卖价差，以 tick 表示，只统计买卖双边都有挂单的时间
+    pub twa_spread_ticks: f64,
+    /// 时间加权平均买卖价差
+    pub twa_spread: f64,
+    /// 最大买卖价差，以 tick 表示
+    pub max_spread_ticks: i64,
+    /// 最大买卖价差
+    pub max_spread: f64,
+    /// 买一档的时间加权平均挂单数量，只统计买盘有挂单的时间
+    pub twa_bid_touch_qty: f64,
+    /// 卖一档的时间加权平均挂单数量，只统计卖盘有挂单的时间
+    pub twa_ask_touch_qty: f64,
+    /// 只有一边有挂单的时间占比
+    pub one_sided_fraction: f64,
+}
+
+/// `BookAnalytics` 在最优价或最优价挂单量变化时采样，将上一次采样的状态按持续时间积分。
+///
+/// 只有落在连续竞价时段内的时间才会被积分，集合竞价阶段和午间休市都被排除。
+#[derive(Debug, Clone)]
+pub struct BookAnalytics {
+    /// 计入统计的时段，以 `HHMMSSmmm` 表示
+    sessions: Vec<(i64, i64)>,
+    last: Option<BookSample>,
+    observed_ms: i64,
+    two_sided_ms: i64,
+    one_sided_ms: i64,
+    spread_tick_ms: i128,
+    bid_ms: i64,
+    bid_vol_ms: i128,
+    ask_ms: i64,
+    ask_vol_ms: i128,
+    max_spread_ticks: Option<i64>,
+}
+
+impl BookAnalytics {
+    pub fn new() -> Self {
+        Self::with_sessions(CONTINUOUS_SESSIONS.to_vec())
+    }
+
+    /// 使用自定义的时段创建统计器。
+    ///
+    /// # 参数
+    /// - `sessions`: 计入统计的时段，每个时段为 `HHMMSSmmm` 表示的左闭右开区间。
+    pub fn with_sessions(sessions: Vec<(i64, i64)>) -> Self {
+        Self {
+            sessions: sessions,
+            last: None,
+            observed_ms: 0,
+            two_sided_ms: 0,
+            one_sided_ms: 0,
+            spread_tick_ms: 0,
+            bid_ms: 0,
+            bid_vol_ms: 0,
+            ask_ms: 0,
+            ask_vol_ms: 0,
+            max_spread_ticks: None,
+        }
+    }
+
+    /// 记录一次采样，并将上一次采样的状态积分到本次采样的时间。
+    ///
+    /// # 参数
+    /// - `timestamp`: 采样时间，早于上一次采样的时间时忽略本次采样。
+    /// - `best_bid_tick`: 最优买价，没有买盘时为 `INVALID_MIN`。
+    /// - `best_ask_tick`: 最优卖价，没有卖盘时为 `INVALID_MAX`。
+    /// - `bid_touch_vol`: 买一档的挂单量。
+    /// - `ask_touch_vol`: 卖一档的挂单量。
+    pub fn record(
+        &mut self,
+        timestamp: i64,
+        best_bid_tick: i64,
+        best_ask_tick: i64,
+        bid_touch_vol: i64,
+        ask_touch_vol: i64,
+    ) {
+        if let Some(prev) = self.last {
+            if timestamp < prev.timestamp {
+                return;
+            }
+            let duration = self.session_ms(prev.timestamp, timestamp);
+            if duration > 0 {
+                self.integrate(&prev, duration);
+            }
+        }
+        self.last = Some(BookSample {
+            timestamp,
+            best_bid_tick,
+            best_ask_tick,
+            bid_touch_vol,
+            ask_touch_vol,
+        });
+    }
+
+    fn integrate(&mut self, sample: &BookSample, duration: i64) {
+        self.observed_ms += duration;
+        if sample.has_bid() {
+            self.bid_ms += duration;
+            self.bid_vol_ms += sample.bid_touch_vol as i128 * duration as i128;
+        }
+        if sample.has_ask() {
+            self.ask_ms += duration;
+            self.ask_vol_ms += sample.ask_touch_vol as i128 * duration as i128;
+        }
+        if sample.has_bid() && sample.has_ask() {
+            let spread_ticks = sample.best_ask_tick - sample.best_bid_tick;
+            self.two_sided_ms += duration;
+            self.spread_tick_ms += spread_ticks as i128 * duration as i128;
+            self.max_spread_ticks = Some(
+                self.max_spread_ticks
+                    .map_or(spread_ticks, |max| cmp::max(max, spread_ticks)),
+            );
+        } else if sample.has_bid() || sample.has_ask() {
+            self.one_sided_ms += duration;
+        }
+    }
+
+    /// 计算 `[from, to)` 与统计时段重叠的毫秒数，跨日时只计算 `from` 当日剩余的时间。
+    fn session_ms(&self, from: i64, to: i64) -> i64 {
+        let from_ms = ms_of_day(from);
+        let to_ms = if from / 1_000_000_000 == to / 1_000_000_000 {
+            ms_of_day(to)
+        } else {
+            ms_of_day(240000000)
+        };
+        self.sessions
+            .iter()
+            .map(|&(start, end)| {
+                let start = cmp::max(from_ms, ms_of_day(start));
+                let end = cmp::min(to_ms, ms_of_day(end));
+                cmp::max(end - start, 0)
+            })
+            .sum()
+    }
+
+    /// 生成统计报告。
+    ///
+    /// # 参数
+    /// - `tick_size`: 价格最小变动单位，用于将 tick 换算为价格。
+    /// - `lot_size`: 每手数量，用于将挂单量换算为实际数量。
+    pub fn report(&self, tick_size: f64, lot_size: f64) -> BookAnalyticsReport {
+        let average = |total: i128, duration: i64| {
+            if duration > 0 {
+                total as f64 / duration as f64
+            } else {
+                0.0
+            }
+        };
+        let twa_spread_ticks = average(self.spread_tick_ms, self.two_sided_ms);
+        let max_spread_ticks = self.max_spread_ticks.unwrap_or(0);
+        BookAnalyticsReport {
+            observed_ms: self.observed_ms,
+            twa_spread_ticks: twa_spread_ticks,
+            twa_spread: twa_spread_ticks * tick_size,
+            max_spread_ticks: max_spread_ticks,
+            max_spread: max_spread_ticks as f64 * tick_size,
+            twa_bid_touch_qty: average(self.bid_vol_ms, self.bid_ms) * lot_size,
+            twa_ask_touch_qty: average(self.ask_vol_ms, self.ask_ms) * lot_size,
+            one_sided_fraction: average(self.one_sided_ms as i128, self.observed_ms),
+        }
+    }
+}
+
+/// 将时间戳中 `HHMMSSmmm` 部分换算为当日的毫秒数。
+fn ms_of_day(timestamp: i64) -> i64 {
+    let only_time = timestamp % 1_000_000_000;
+    let hour = only_time / 10_000_000;
+    let minute = only_time / 100_000 % 100;
+    let millis = only_time % 100_000;
+    (hour * 60 + minute) * 60_000 + millis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::skiplist_orderbook::SkipListMarketDepth;
+    use super::*;
+
+    #[test]
+    fn test_time_weighted_book_analytics() {
+        let mut analytics = BookAnalytics::new();
+        // 集合竞价阶段的状态只从 09:30:00 开始计入
+        analytics.record(20231201092900000, 998, 1002, 5, 7);
+        analytics.record(20231201093100000, 1000, 1001, 10, 20);
+        analytics.record(20231201112900000, 1000, INVALID_MAX, 10, 0);
+        // 午间休市不计入，单边的时间为 11:29-11:30 和 13:00-13:01
+        analytics.record(20231201130100000, 1000, 1003, 4, 6);
+        analytics.record(20231201130200000, 1000, 1003, 4, 6);
+        // 时间倒退的采样被忽略
+        analytics.record(20231201130000000, 1000, 1001, 1, 1);
+
+        let first = 60_000;
+        let second = 7_080_000;
+        let third = 120_000;
+        let fourth = 60_000;
+        let observed = first + second + third + fourth;
+        let two_sided = first + second + fourth;
+
+        let report = analytics.report(0.01, 100.0);
+        assert_eq!(report.observed_ms, observed);
+        let twa_spread_ticks = (4 * first + second + 3 * fourth) as f64 / two_sided as f64;
+        assert_eq!(report.twa_spread_ticks, twa_spread_ticks);
+        assert!((report.twa_spread - twa_spread_ticks * 0.01).abs() < 1e-12);
+        assert_eq!(report.max_spread_ticks, 4);
+        let bid_touch =
+            (5 * first + 10 * second + 10 * third + 4 * fourth) as f64 / observed as f64;
+        assert_eq!(report.twa_bid_touch_qty, bid_touch * 100.0);
+        let ask_touch = (7 * first + 20 * second + 6 * fourth) as f64 / two_sided as f64;
+        assert_eq!(report.twa_ask_touch_qty, ask_touch * 100.0);
+        assert_eq!(report.one_sided_fraction, third as f64 / observed as f64);
+    }
+
+    #[test]
+    fn test_depth_samples_analytics() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        assert!(depth.analytics().is_none());
+        depth.enable_analytics();
+        for (order_id, side, price_tick, vol) in [(1, Side::Buy, 1000, 3), (2, Side::Sell, 1002, 5)]
+        {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                price_tick,
+                vol,
+                20231201093000000,
+                OrderType::L,
+            );
+            depth.add(order_ref).unwrap();
+        }
+        depth.sample_analytics(20231201093000000);
+        depth.sample_analytics(20231201093010000);
+
+        let report = depth.analytics().unwrap();
+        assert_eq!(report.observed_ms, 10_000);
+        assert_eq!(report.twa_spread_ticks, 2.0);
+        assert!((report.twa_spread - 0.02).abs() < 1e-12);
+        assert_eq!(report.twa_bid_touch_qty, 300.0);
+        assert_eq!(report.twa_ask_touch_qty, 500.0);
+        assert_eq!(report.one_sided_fraction, 0.0);
+    }
+}
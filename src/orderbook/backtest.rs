@@ -0,0 +1,460 @@
+use super::dataloader::DataCollator;
+use super::exchange::Exchange;
+use super::hook::{Hook, HookType};
+use super::order::{DispositionReport, StrategySummary};
+use super::schema::SCHEMA_VERSION;
+use super::skiplist_orderbook::SkipListMarketDepth;
+use super::statistics::StatisticsInfo;
+use super::utils::time_difference_ms_i64;
+use super::*;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// 回放结束的时间，晚于 15:00:00 以便完成收盘集合竞价。
+const SESSION_END_TIME: &str = "150100000";
+/// 回放开始的时间，早于开盘集合竞价的第一笔委托。
+const SESSION_START_TIME: &str = "091500000";
+/// 脚本订单使用的账户。
+const SCRIPT_ACCOUNT: &str = "backtest";
+/// 订单簿钩子记录的档位数量。
+const HOOK_LEVELS: usize = 5;
+
+/// 命令行用法说明。
+pub const USAGE: &str = "usage: backtest --data <path> --symbol <code> --date <YYYYMMDD> \
+[--mode ORDER|L2P] [--orders <csv>] [--output <dir>]";
+
+/// `BacktestConfig` 描述一次单只股票、单个交易日的回测。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacktestConfig {
+    /// 行情数据的根目录
+    pub data_path: String,
+    /// 股票代码，例如 `600000.SH`
+    pub stock_code: String,
+    /// 交易日，格式为 `YYYYMMDD`
+    pub date: String,
+    /// 撮合模式，`ORDER` 或 `L2P`
+    pub mode: String,
+    /// 用户订单脚本，CSV 格式，每行为 `time,side,price,qty,type`
+    pub order_script: Option<PathBuf>,
+    /// 写出 `manifest.json` 的目录
+    pub output_dir: Option<PathBuf>,
+}
+
+impl BacktestConfig {
+    /// 解析命令行参数，不包括程序名。
+    ///
+    /// # 错误
+    /// 缺少必填参数、参数没有值或取值不合法时返回 `MarketError::InvalidBacktestArgs`。
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, MarketError> {
+        let mut data_path = None;
+        let mut stock_code = None;
+        let mut date = None;
+        let mut mode = "ORDER".to_string();
+        let mut order_script = None;
+        let mut output_dir = None;
+
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| {
+                MarketError::InvalidBacktestArgs(format!("missing value for {}", flag))
+            })?;
+            match flag.as_str() {
+                "--data" => data_path = Some(value),
+                "--symbol" => stock_code = Some(value),
+                "--date" => date = Some(value),
+                "--mode" => mode = value.to_uppercase(),
+                "--orders" => order_script = Some(PathBuf::from(value)),
+                "--output" => output_dir = Some(PathBuf::from(value)),
+                _ => {
+                    return Err(MarketError::InvalidBacktestArgs(format!(
+                        "unknown argument {}",
+                        flag
+                    )))
+                }
+            }
+        }
+
+        let missing = |name: &str| MarketError::InvalidBacktestArgs(format!("missing {}", name));
+        let config = Self {
+            data_path: data_path.ok_or_else(|| missing("--data"))?,
+            stock_code: stock_code.ok_or_else(|| missing("--symbol"))?,
+            date: date.ok_or_else(|| missing("--date"))?,
+            mode: mode,
+            order_script: order_script,
+            output_dir: output_dir,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 校验交易日格式、撮合模式和股票代码的市场后缀。
+    fn validate(&self) -> Result<(), MarketError> {
+        if self.date.len() != 8 || !self.date.chars().all(|c| c.is_ascii_digit()) {
+            return Err(MarketError::InvalidBacktestArgs(format!(
+                "invalid date {}, expected YYYYMMDD",
+                self.date
+            )));
+        }
+        if !["ORDER", "L2P"].contains(&self.mode.as_str()) {
+            return Err(MarketError::InvalidBacktestArgs(format!(
+                "invalid mode {}, expected ORDER or L2P",
+                self.mode
+            )));
+        }
+        self.market_type()?;
+        Ok(())
+    }
+
+    /// 根据股票代码的后缀判断市场。
+    fn market_type(&self) -> Result<MarketType, MarketError> {
+        if self.stock_code.ends_with(".SH") {
+            Ok(MarketType::SH)
+        } else if self.stock_code.ends_with(".SZ") {
+            Ok(MarketType::SZ)
+        } else {
+            Err(MarketError::InvalidBacktestArgs(format!(
+                "invalid symbol {}, expected a .SH or .SZ suffix",
+                self.stock_code
+            )))
+        }
+    }
+
+    /// 交易日内给定 `HHMMSSmmm` 时间对应的完整时间戳。
+    fn timestamp_at(&self, only_time: &str) -> i64 {
+        format!("{}{}", self.date, only_time).parse().unwrap()
+    }
+}
+
+/// 订单脚本中的一笔用户订单。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptedOrder {
+    /// 下单时间，格式为 `YYYYMMDDHHMMSSmmm`
+    pub time: i64,
+    /// 买卖方向，`buy` 或 `sell`
+    pub bs_flag: String,
+    pub price: f64,
+    pub qty: i64,
+    pub order_type: OrderType,
+}
+
+/// 解析用户订单脚本。
+///
+/// 每行为 `time,side,price,qty,type`，以 `time` 开头的表头行、空行和 `#` 开头的注释行会被跳过。
+/// `time` 可以是完整的 17 位时间戳，也可以是交易日内 9 位的 `HHMMSSmmm`；
+/// `type` 为 `L`、`M`、`N`、`B`、`C` 或 `D`，省略时为限价订单。
+///
+/// # 参数
+/// - `text`: 脚本内容。
+/// - `date`: 交易日，格式为 `YYYYMMDD`，用于补全 9 位的时间。
+///
+/// # 返回值
+/// 按时间排序的订单，同一时间的订单保持脚本中的顺序。
+///
+/// # 错误
+/// 某一行无法解析时返回 `MarketError::InvalidBacktestArgs`，其中包含行号。
+pub fn parse_order_script(text: &str, date: &str) -> Result<Vec<ScriptedOrder>, MarketError> {
+    let mut orders = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("time") {
+            continue;
+        }
+        let invalid = |reason: &str| {
+            MarketError::InvalidBacktestArgs(format!("order script line {}: {}", idx + 1, reason))
+        };
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if fields.len() < 4 || fields.len() > 5 {
+            return Err(invalid("expected time,side,price,qty,type"));
+        }
+
+        let time = match fields[0].len() {
+            17 => fields[0].parse::<i64>().ok(),
+            9 => format!("{}{}", date, fields[0]).parse::<i64>().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| invalid("invalid time"))?;
+        let bs_flag = fields[1].to_lowercase();
+        if bs_flag != "buy" && bs_flag != "sell" {
+            return Err(invalid("side must be buy or sell"));
+        }
+        let price = fields[2]
+            .parse::<f64>()
+            .map_err(|_| invalid("invalid price"))?;
+        let qty = fields[3]
+            .parse::<i64>()
+            .map_err(|_| invalid("invalid qty"))?;
+        let order_type = match fields.get(4) {
+            Some(order_type) if !order_type.is_empty() => {
+                OrderType::from_str(&order_type.to_uppercase()).unwrap()
+            }
+            _ => OrderType::L,
+        };
+        if order_type == OrderType::Unsupported {
+            return Err(invalid("unsupported order type"));
+        }
+
+        orders.push(ScriptedOrder {
+            time,
+            bs_flag,
+            price,
+            qty,
+            order_type,
+        });
+    }
+    orders.sort_by_key(|order| order.time);
+    Ok(orders)
+}
+
+/// 订单簿钩子记录的最新快照。
+#[derive(Debug, Default)]
+struct SnapshotRecorder {
+    updates: usize,
+    best_bid: f64,
+    best_ask: f64,
+    last_price: f64,
+}
+
+fn snapshot_hook(
+    object: &Rc<RefCell<dyn Any>>,
+    info: &StatisticsInfo,
+    bid_levels: &Vec<(f64, f64, i64)>,
+    ask_levels: &Vec<(f64, f64, i64)>,
+    _l3order: &L3OrderRef,
+) -> bool {
+    let mut object = object.borrow_mut();
+    let Some(recorder) = object.downcast_mut::<SnapshotRecorder>() else {
+        return false;
+    };
+    recorder.updates += 1;
+    recorder.best_bid = bid_levels.first().map_or(0.0, |&(price, _, _)| price);
+    recorder.best_ask = ask_levels.first().map_or(0.0, |&(price, _, _)| price);
+    recorder.last_price = info.last_price;
+    true
+}
+
+/// `SessionSummary` 汇总一次回测的结果。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub stock_code: String,
+    pub date: String,
+    /// 回放的历史逐笔消息数量
+    pub history_events: usize,
+    /// 订单簿钩子被调用的次数
+    pub book_updates: usize,
+    /// 成功提交的用户订单数量
+    pub orders_submitted: usize,
+    /// 提交时被拒绝的用户订单数量
+    pub orders_rejected: usize,
+    /// 全部成交的用户订单数量
+    pub orders_filled: usize,
+    /// 已撤销的用户订单数量，包括交易时段不接受的订单
+    pub orders_canceled: usize,
+    /// 用户订单的总成交数量
+    pub filled_qty: f64,
+    /// 用户订单主动成交的数量
+    pub filled_qty_aggressive: f64,
+    /// 用户订单被动成交的数量
+    pub filled_qty_passive: f64,
+    /// 用户订单在集合竞价中成交的数量
+    pub filled_qty_auction: f64,
+    /// 开盘价，没有开盘集合竞价成交时为 0
+    pub open_price: f64,
+    /// 收盘价，没有收盘集合竞价成交时为 0
+    pub close_price: f64,
+    pub last_price: f64,
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub high: f64,
+    pub low: f64,
+    /// 市场总成交量
+    pub total_volume: f64,
+    /// 用户订单的处置结果
+    #[serde(default)]
+    pub disposition: DispositionReport,
+    /// 按策略编号汇总的用户订单成交情况
+    #[serde(default)]
+    pub strategies: BTreeMap<StrategyId, StrategySummary>,
+}
+
+/// 写入 `manifest.json` 的内容，记录回测的输入和结果。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub config: BacktestConfig,
+    pub summary: SessionSummary,
+}
+
+/// 加载行情数据并运行回测。
+///
+/// # 错误
+/// - 参数不合法时返回 `MarketError::InvalidBacktestArgs`。
+/// - 行情文件不存在或无法读取时返回 `MarketError::DataLoadError`。
+/// - 回放过程中的其他错误原样返回。
+pub fn run(config: &BacktestConfig) -> Result<SessionSummary, MarketError> {
+    config.validate()?;
+    let mut data = DataCollator::new(
+        config.stock_code.clone(),
+        "local".to_string(),
+        config.data_path.clone(),
+        config.date.clone(),
+        &config.mode,
+    );
+    data.init()?;
+    run_with_data(config, data)
+}
+
+/// 使用已加载的行情数据运行回测，`config.data_path` 不会被读取。
+///
+/// 回放从 09:15 开始，在每笔脚本订单的时间点提交订单，最后推进到 15:01 完成收盘集合竞价。
+/// 提交时被拒绝的脚本订单只计数，不会中断回测。
+///
+/// # 错误
+/// - 参数不合法或订单脚本无法读取、解析时返回 `MarketError::InvalidBacktestArgs`。
+/// - 回放过程中的其他错误原样返回。
+pub fn run_with_data(
+    config: &BacktestConfig,
+    data: DataCollator,
+) -> Result<SessionSummary, MarketError> {
+    config.validate()?;
+    let market_type = config.market_type()?;
+    let orders = match &config.order_script {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| {
+                MarketError::InvalidBacktestArgs(format!(
+                    "failed to read order script {}: {}",
+                    path.display(),
+                    err
+                ))
+            })?;
+            parse_order_script(&text, &config.date)?
+        }
+        None => Vec::new(),
+    };
+    let history_events = data.len;
+
+    let stock_code = config.stock_code.as_str();
+    let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", &config.date);
+    exchange.add_broker(
+        market_type,
+        ExchangeMode::Backtest,
+        "stock".to_string(),
+        config.stock_code.clone(),
+        100.0,
+    )?;
+    exchange.add_data(stock_code, data)?;
+    let start_time = config.timestamp_at(SESSION_START_TIME);
+    exchange
+        .get_broker_mut(stock_code)
+        .ok_or(MarketError::StockBrokerNotExist)?
+        .set_current_time(start_time);
+
+    let recorder: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(SnapshotRecorder::default()));
+    exchange.register_orderbook_hook(
+        stock_code,
+        HookType::Orderbook,
+        "snapshot",
+        Hook::new(recorder.clone(), snapshot_hook, HOOK_LEVELS),
+    )?;
+
+    let mut orders_submitted = 0;
+    let mut orders_rejected = 0;
+    for order in orders {
+        let now = exchange.get_crurent_time(Some(stock_code))?;
+        if order.time > now {
+            exchange.elapse(time_difference_ms_i64(now, order.time)?, Some(stock_code))?;
+        }
+        match exchange.send_order(
+            SCRIPT_ACCOUNT,
+            stock_code,
+            order.time,
+            order.price,
+            order.qty,
+            &order.bs_flag,
+            Some(order.order_type),
+        ) {
+            Ok(_) => orders_submitted += 1,
+            Err(_) => orders_rejected += 1,
+        }
+    }
+    let now = exchange.get_crurent_time(Some(stock_code))?;
+    let end_time = config.timestamp_at(SESSION_END_TIME);
+    if end_time > now {
+        exchange.elapse(time_difference_ms_i64(now, end_time)?, Some(stock_code))?;
+    }
+
+    let broker = exchange
+        .get_broker(stock_code)
+        .ok_or(MarketError::StockBrokerNotExist)?;
+    let mut info = StatisticsInfo::new();
+    info.from_statistics(
+        broker.market_depth.get_statistics(),
+        broker.tick_size,
+        broker.lot_size,
+    );
+    let mut summary = SessionSummary {
+        stock_code: config.stock_code.clone(),
+        date: config.date.clone(),
+        history_events: history_events,
+        book_updates: 0,
+        orders_submitted: orders_submitted,
+        orders_rejected: orders_rejected,
+        orders_filled: 0,
+        orders_canceled: 0,
+        filled_qty: 0.0,
+        filled_qty_aggressive: info.user_aggressive_qty,
+        filled_qty_passive: info.user_passive_qty,
+        filled_qty_auction: info.user_auction_qty,
+        open_price: broker.open_tick as f64 * broker.tick_size,
+        close_price: broker.close_tick as f64 * broker.tick_size,
+        last_price: 0.0,
+        best_bid: 0.0,
+        best_ask: 0.0,
+        high: info.high,
+        low: info.low,
+        total_volume: info.total_bid_qty + info.total_ask_qty,
+        disposition: broker.disposition_report(),
+        strategies: broker.strategy_summary(),
+    };
+    for order_ref in broker.orders()?.values() {
+        let order = order_ref.borrow();
+        summary.filled_qty += order.filled_qty;
+        match order.status {
+            OrderStatus::Filled => summary.orders_filled += 1,
+            OrderStatus::Canceled => summary.orders_canceled += 1,
+            _ => {}
+        }
+    }
+    if let Some(recorder) = recorder.borrow().downcast_ref::<SnapshotRecorder>() {
+        summary.book_updates = recorder.updates;
+        summary.last_price = recorder.last_price;
+        summary.best_bid = recorder.best_bid;
+        summary.best_ask = recorder.best_ask;
+    }
+    Ok(summary)
+}
+
+/// 将回测的输入和结果写入 `<dir>/manifest.json`，目录不存在时自动创建。
+///
+/// # 返回值
+/// 返回写出的文件路径。
+///
+/// # 错误
+/// 创建目录、序列化或写文件失败时返回 `SnapshotError`。
+pub fn write_manifest(
+    dir: &Path,
+    config: &BacktestConfig,
+    summary: &SessionSummary,
+) -> Result<PathBuf, SnapshotError> {
+    fs::create_dir_all(dir)?;
+    let manifest = Manifest {
+        schema_version: SCHEMA_VERSION,
+        config: config.clone(),
+        summary: summary.clone(),
+    };
+    let path = dir.join("manifest.json");
+    fs::write(&path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(path)
+}
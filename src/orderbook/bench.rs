@@ -0,0 +1,575 @@
+use super::broker::Broker;
+use super::hook::{Hook, HookType};
+use super::order::Order;
+use super::prelude::*;
+use super::statistics::StatisticsInfo;
+use super::synthetic::{FlowConfig, FlowEvent, SyntheticFlow};
+use super::*;
+use std::any::Any;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// 基准测试使用的股票代码。
+const BENCH_STOCK_CODE: &str = "000001.SZ";
+/// 基准测试使用的价格最小变动单位。
+const BENCH_TICK_SIZE: f64 = 0.01;
+/// 基准测试使用的每手数量。
+const BENCH_LOT_SIZE: f64 = 100.0;
+/// 合成逐笔数据的起始时间，位于连续竞价阶段。
+const BENCH_START_TIME: i64 = 20231201093000000;
+/// 回放的截止时间，合成数据在上午连续竞价结束前全部到达。
+const BENCH_END_TIME: i64 = 20231201113000000;
+/// 深度扫单场景中每一方铺开的档位数量。
+const SWEEP_LEVELS: i64 = 50;
+/// 深度扫单场景中每个档位的订单数量。
+const SWEEP_ORDERS_PER_LEVEL: i64 = 4;
+/// 混合回测场景中每提交一笔用户订单对应的历史逐笔数量。
+const USER_ORDER_INTERVAL: usize = 100;
+
+/// `BenchScale` 决定基准测试的数据规模。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchScale {
+    /// 小规模数据，只运行一次，用于单元测试中校验正确性
+    Smoke,
+    /// 完整规模数据，预热后多次采样，用于 `cargo bench`
+    Full,
+}
+
+impl BenchScale {
+    /// 预热运行的次数，预热结果不计入统计。
+    pub fn warmup(&self) -> usize {
+        match self {
+            BenchScale::Smoke => 0,
+            BenchScale::Full => 1,
+        }
+    }
+
+    /// 计入统计的采样次数。
+    pub fn samples(&self) -> usize {
+        match self {
+            BenchScale::Smoke => 1,
+            BenchScale::Full => 10,
+        }
+    }
+}
+
+/// 基准测试的场景。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// 在最优价附近反复挂单和撤单
+    AddCancelChurn,
+    /// 铺满 50 档后由一笔大单一次性扫穿
+    DeepSweep,
+    /// 历史逐笔与用户订单混合回测，并注册 50 档的订单簿钩子
+    MixedBacktest,
+    /// 回放一整天的逐笔委托
+    FullDayReplay,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 4] = [
+        Scenario::AddCancelChurn,
+        Scenario::DeepSweep,
+        Scenario::MixedBacktest,
+        Scenario::FullDayReplay,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::AddCancelChurn => "add_cancel_churn",
+            Scenario::DeepSweep => "deep_sweep_50",
+            Scenario::MixedBacktest => "mixed_backtest_hook_50",
+            Scenario::FullDayReplay => "full_day_replay",
+        }
+    }
+
+    /// 场景在给定规模下处理的事件数量。深度扫单场景中表示扫单的轮数。
+    pub fn size(&self, scale: BenchScale) -> usize {
+        match (self, scale) {
+            (Scenario::AddCancelChurn, BenchScale::Smoke) => 10_000,
+            (Scenario::AddCancelChurn, BenchScale::Full) => 1_000_000,
+            (Scenario::DeepSweep, BenchScale::Smoke) => 20,
+            (Scenario::DeepSweep, BenchScale::Full) => 5_000,
+            (Scenario::MixedBacktest, BenchScale::Smoke) => 5_000,
+            (Scenario::MixedBacktest, BenchScale::Full) => 200_000,
+            (Scenario::FullDayReplay, BenchScale::Smoke) => 10_000,
+            (Scenario::FullDayReplay, BenchScale::Full) => 1_000_000,
+        }
+    }
+
+    /// 场景在给定规模下的期望校验和。
+    ///
+    /// 校验和覆盖成交量、最优价和最终的档位数量，任何改变撮合结果的性能优化都会导致不一致。
+    pub fn expected_checksum(&self, scale: BenchScale) -> u64 {
+        match (self, scale) {
+            (Scenario::AddCancelChurn, BenchScale::Smoke) => 0x8745_9217_c9f4_ae83,
+            (Scenario::AddCancelChurn, BenchScale::Full) => 0xd76d_5c8d_b91b_b4ab,
+            (Scenario::DeepSweep, BenchScale::Smoke) => 0x2840_ea5e_147f_0b95,
+            (Scenario::DeepSweep, BenchScale::Full) => 0x3dbf_63b4_e65a_03c5,
+            (Scenario::MixedBacktest, BenchScale::Smoke) => 0xb079_96d6_48f7_0b0e,
+            (Scenario::MixedBacktest, BenchScale::Full) => 0xce4d_7b62_63dc_7e55,
+            (Scenario::FullDayReplay, BenchScale::Smoke) => 0x5655_87d9_ffff_1856,
+            (Scenario::FullDayReplay, BenchScale::Full) => 0xaf8d_9bc2_8440_0613,
+        }
+    }
+}
+
+/// `Checksum` 以 FNV-1a 算法累积基准测试过程中的关键状态。
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum(u64);
+
+impl Checksum {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET)
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        for byte in value.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// 累积订单簿档位信息，价格换算为 tick，数量换算为手。
+    pub fn write_levels(&mut self, levels: &[(f64, f64, i64)]) {
+        self.write_i64(levels.len() as i64);
+        for &(price, qty, count) in levels {
+            self.write_i64((price / BENCH_TICK_SIZE).round() as i64);
+            self.write_i64((qty / BENCH_LOT_SIZE).round() as i64);
+            self.write_i64(count);
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 单次运行的结果。
+#[derive(Debug, Clone, Copy)]
+pub struct RunOutput {
+    /// 处理的事件数量
+    pub events: usize,
+    /// 运行结束时的校验和
+    pub checksum: u64,
+}
+
+/// `BenchResult` 汇总一个场景多次采样的耗时。
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub scenario: Scenario,
+    pub events: usize,
+    pub checksum: u64,
+    pub samples: Vec<Duration>,
+}
+
+impl BenchResult {
+    fn events_per_sec(&self, elapsed: Duration) -> f64 {
+        self.events as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// 各次采样吞吐量的中位数，单位为事件每秒。
+    pub fn median_events_per_sec(&self) -> f64 {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        self.events_per_sec(sorted[sorted.len() / 2])
+    }
+
+    /// 最快一次采样的吞吐量。
+    pub fn best_events_per_sec(&self) -> f64 {
+        self.events_per_sec(*self.samples.iter().min().unwrap())
+    }
+
+    /// 各次采样耗时的相对标准差，单位为百分比。
+    pub fn relative_std_dev(&self) -> f64 {
+        let secs: Vec<f64> = self.samples.iter().map(|d| d.as_secs_f64()).collect();
+        let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / secs.len() as f64;
+        variance.sqrt() / mean * 100.0
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<24} events={:>9} samples={:>2} median={:>12.0} ev/s best={:>12.0} ev/s rsd={:>5.1}% checksum={:016x}",
+            self.scenario.name(),
+            self.events,
+            self.samples.len(),
+            self.median_events_per_sec(),
+            self.best_events_per_sec(),
+            self.relative_std_dev(),
+            self.checksum,
+        )
+    }
+}
+
+/// 运行一个场景：先预热，再多次采样计时，并校验每次运行的校验和。
+///
+/// 数据准备不计入耗时，计时只覆盖撮合和回放本身。
+///
+/// # 参数
+/// - `scenario`: 要运行的场景。
+/// - `scale`: 数据规模。
+///
+/// # 返回值
+/// 返回该场景的采样结果。
+///
+/// # Panics
+/// 任何一次运行的校验和与期望值不一致时 panic。
+pub fn run_scenario(scenario: Scenario, scale: BenchScale) -> BenchResult {
+    let size = scenario.size(scale);
+    let expected = scenario.expected_checksum(scale);
+    let mut result = BenchResult {
+        scenario,
+        events: 0,
+        checksum: 0,
+        samples: Vec::with_capacity(scale.samples()),
+    };
+
+    for run in 0..scale.warmup() + scale.samples() {
+        let (output, elapsed) = match scenario {
+            Scenario::AddCancelChurn => {
+                let events: Vec<FlowEvent> =
+                    SyntheticFlow::new(churn_config()).take(size).collect();
+                timed(|| add_cancel_churn(&events))
+            }
+            Scenario::DeepSweep => timed(|| deep_sweep(size)),
+            Scenario::MixedBacktest => {
+                let broker = create_backtest_broker(mixed_config(), size, 10);
+                timed(|| mixed_backtest(broker, size))
+            }
+            Scenario::FullDayReplay => {
+                let broker = create_backtest_broker(replay_config(), size, 7);
+                timed(|| full_day_replay(broker))
+            }
+        };
+        assert_eq!(
+            output.checksum,
+            expected,
+            "benchmark {} changed behavior: checksum {:016x}, expected {:016x}",
+            scenario.name(),
+            output.checksum,
+            expected
+        );
+        result.events = output.events;
+        result.checksum = output.checksum;
+        if run >= scale.warmup() {
+            result.samples.push(elapsed);
+        }
+    }
+    result
+}
+
+/// 依次运行所有场景。
+pub fn run_all(scale: BenchScale) -> Vec<BenchResult> {
+    Scenario::ALL
+        .iter()
+        .map(|&scenario| run_scenario(scenario, scale))
+        .collect()
+}
+
+fn timed<F: FnOnce() -> RunOutput>(f: F) -> (RunOutput, Duration) {
+    let start = Instant::now();
+    let output = f();
+    (output, start.elapsed())
+}
+
+fn churn_config() -> FlowConfig {
+    FlowConfig {
+        seed: 1,
+        levels: 5,
+        cancel_permille: 480,
+        take_permille: 0,
+        ..Default::default()
+    }
+}
+
+fn mixed_config() -> FlowConfig {
+    FlowConfig {
+        seed: 2,
+        levels: SWEEP_LEVELS,
+        ..Default::default()
+    }
+}
+
+fn replay_config() -> FlowConfig {
+    FlowConfig {
+        seed: 3,
+        ..Default::default()
+    }
+}
+
+fn write_depth_levels<MD: L3MarketDepth>(checksum: &mut Checksum, depth: &MD, max_level: usize) {
+    let mut bid_levels = Vec::with_capacity(max_level);
+    let mut ask_levels = Vec::with_capacity(max_level);
+    depth.get_orderbook_level(&mut bid_levels, &mut ask_levels, max_level);
+    checksum.write_levels(&bid_levels);
+    checksum.write_levels(&ask_levels);
+}
+
+/// 场景一：只有挂单和撤单，挂单集中在最优价附近的 5 档内。
+fn add_cancel_churn(events: &[FlowEvent]) -> RunOutput {
+    let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, BENCH_TICK_SIZE, BENCH_LOT_SIZE);
+    let source = OrderSourceType::UserOrder;
+    let mut checksum = Checksum::new();
+
+    for event in events {
+        match *event {
+            FlowEvent::Add {
+                order_id,
+                side,
+                price_tick,
+                vol,
+            } => {
+                let order_ref = L3Order::new_ref(
+                    source,
+                    None,
+                    order_id,
+                    side,
+                    price_tick,
+                    vol,
+                    BENCH_START_TIME,
+                    OrderType::L,
+                );
+                depth.add(order_ref).unwrap();
+            }
+            FlowEvent::Cancel { order_id } => {
+                depth.cancel_order(order_id).unwrap();
+            }
+            FlowEvent::Take { .. } => unreachable!("churn flow has no take events"),
+        }
+        checksum.write_i64(depth.best_bid_tick(&source));
+        checksum.write_i64(depth.best_ask_tick(&source));
+    }
+    write_depth_levels(&mut checksum, &depth, 10);
+
+    RunOutput {
+        events: events.len(),
+        checksum: checksum.value(),
+    }
+}
+
+/// 场景二：每轮在一方铺满 50 档，再由对手方一笔大单全部扫穿，买卖方向交替。
+fn deep_sweep(rounds: usize) -> RunOutput {
+    let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, BENCH_TICK_SIZE, BENCH_LOT_SIZE);
+    let source = OrderSourceType::LocalOrder;
+    let mid_tick = FlowConfig::default().mid_tick;
+    let mut checksum = Checksum::new();
+    let mut order_id: OrderId = 0;
+    let mut events = 0;
+
+    for round in 0..rounds {
+        let (resting_side, taker_side, direction) = if round % 2 == 0 {
+            (Side::Sell, Side::Buy, 1)
+        } else {
+            (Side::Buy, Side::Sell, -1)
+        };
+        let mut total_vol = 0;
+        for level in 1..=SWEEP_LEVELS {
+            for idx in 0..SWEEP_ORDERS_PER_LEVEL {
+                order_id += 1;
+                let vol = 1 + (level + idx) % 5;
+                total_vol += vol;
+                let order_ref = L3Order::new_ref(
+                    source,
+                    None,
+                    order_id,
+                    resting_side,
+                    mid_tick + direction * level,
+                    vol,
+                    BENCH_START_TIME,
+                    OrderType::L,
+                );
+                depth.add(order_ref).unwrap();
+                events += 1;
+            }
+        }
+
+        order_id += 1;
+        let taker_ref = L3Order::new_ref(
+            source,
+            None,
+            order_id,
+            taker_side,
+            mid_tick + direction * SWEEP_LEVELS,
+            total_vol,
+            BENCH_START_TIME,
+            OrderType::L,
+        );
+        let filled = depth.match_order(taker_ref, i64::MAX).unwrap();
+        events += 1;
+        checksum.write_i64(filled);
+        checksum.write_i64(depth.last_tick(&source));
+        checksum.write_i64(depth.best_bid_tick(&source));
+        checksum.write_i64(depth.best_ask_tick(&source));
+    }
+    write_depth_levels(&mut checksum, &depth, SWEEP_LEVELS as usize);
+
+    RunOutput {
+        events,
+        checksum: checksum.value(),
+    }
+}
+
+/// 订单簿钩子的累计结果。
+#[derive(Debug, Default)]
+struct HookTally {
+    calls: i64,
+    levels: i64,
+    vol: i64,
+}
+
+fn tally_hook(
+    object: &Rc<RefCell<dyn Any>>,
+    _info: &StatisticsInfo,
+    bid_levels: &Vec<(f64, f64, i64)>,
+    ask_levels: &Vec<(f64, f64, i64)>,
+    _l3order: &L3OrderRef,
+) -> bool {
+    let mut object = object.borrow_mut();
+    let tally = object.downcast_mut::<HookTally>().unwrap();
+    tally.calls += 1;
+    tally.levels += (bid_levels.len() + ask_levels.len()) as i64;
+    tally.vol += bid_levels
+        .iter()
+        .chain(ask_levels.iter())
+        .map(|&(_, qty, _)| (qty / BENCH_LOT_SIZE).round() as i64)
+        .sum::<i64>();
+    true
+}
+
+fn create_backtest_broker(
+    config: FlowConfig,
+    events: usize,
+    step_ms: i64,
+) -> Broker<SkipListMarketDepth> {
+    let history = SyntheticFlow::new(config)
+        .to_history(
+            BENCH_STOCK_CODE,
+            BENCH_START_TIME,
+            step_ms,
+            events,
+            BENCH_TICK_SIZE,
+            BENCH_LOT_SIZE,
+        )
+        .unwrap();
+    let mut broker = Broker::new(
+        ExchangeMode::Backtest,
+        MarketType::SZ,
+        "STOCK".to_string(),
+        BENCH_STOCK_CODE.to_string(),
+        BENCH_TICK_SIZE,
+        BENCH_LOT_SIZE,
+    );
+    broker.init();
+    broker.set_current_time(BENCH_START_TIME);
+    // 合成数据从连续竞价开始，跳过开盘集合竞价
+    broker.open_tick = 1000;
+    broker.add_data(Some(history)).unwrap();
+    broker
+}
+
+/// 场景三：回放历史逐笔，每 100 笔历史数据提交一笔用户订单，并注册 50 档的订单簿钩子。
+fn mixed_backtest(mut broker: Broker<SkipListMarketDepth>, events: usize) -> RunOutput {
+    let tally: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(HookTally::default()));
+    broker
+        .register_orderbook_hook(
+            HookType::Orderbook,
+            "bench",
+            Hook::new(tally.clone(), tally_hook, SWEEP_LEVELS as usize),
+        )
+        .unwrap();
+    let mid_tick = mixed_config().mid_tick;
+    let slice_ms = USER_ORDER_INTERVAL as i64 * 10;
+    let mut user_orders = Vec::with_capacity(events / USER_ORDER_INTERVAL);
+
+    for idx in 0..events / USER_ORDER_INTERVAL {
+        let (bs_flag, price_tick) = if idx % 2 == 0 {
+            ("Buy", mid_tick + 2)
+        } else {
+            ("Sell", mid_tick - 2)
+        };
+        let order_ref = Order::new_ref(
+            None,
+            BENCH_STOCK_CODE.to_string(),
+            broker.timestamp,
+            price_tick as f64 * BENCH_TICK_SIZE,
+            (1 + idx % 5) as f64 * BENCH_LOT_SIZE,
+            bs_flag,
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = idx as OrderId + 1;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(slice_ms).unwrap();
+        broker.sync_order_info();
+        broker.dirty_tracker.clear();
+        user_orders.push(order_ref);
+    }
+
+    let mut checksum = Checksum::new();
+    {
+        let tally = tally.borrow();
+        let tally = tally.downcast_ref::<HookTally>().unwrap();
+        checksum.write_i64(tally.calls);
+        checksum.write_i64(tally.levels);
+        checksum.write_i64(tally.vol);
+    }
+    for order_ref in user_orders.iter() {
+        checksum.write_i64((order_ref.borrow().filled_qty / BENCH_LOT_SIZE).round() as i64);
+    }
+    write_depth_levels(
+        &mut checksum,
+        broker.market_depth.as_ref(),
+        SWEEP_LEVELS as usize,
+    );
+
+    RunOutput {
+        events: events,
+        checksum: checksum.value(),
+    }
+}
+
+/// 场景四：不提交用户订单，回放一整天规模的逐笔委托。
+fn full_day_replay(mut broker: Broker<SkipListMarketDepth>) -> RunOutput {
+    let events = broker.history.as_ref().map_or(0, |history| history.len);
+    broker.goto(BENCH_END_TIME).unwrap();
+
+    let source = OrderSourceType::LocalOrder;
+    let mut checksum = Checksum::new();
+    checksum.write_i64(broker.market_depth.last_tick(&source));
+    checksum.write_i64(broker.market_depth.best_bid_tick(&source));
+    checksum.write_i64(broker.market_depth.best_ask_tick(&source));
+    checksum.write_i64(broker.skipped_orders.len() as i64);
+    write_depth_levels(&mut checksum, broker.market_depth.as_ref(), 10);
+
+    RunOutput {
+        events: events,
+        checksum: checksum.value(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoke_benchmarks() {
+        for result in run_all(BenchScale::Smoke) {
+            assert!(result.events > 0);
+            assert_eq!(result.samples.len(), 1);
+            assert_eq!(
+                result.checksum,
+                result.scenario.expected_checksum(BenchScale::Smoke)
+            );
+        }
+    }
+}
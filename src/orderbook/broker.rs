@@ -1,23 +1,67 @@
-use utils::should_call_auction_on_close;
-
 use super::dataloader::DataCollator;
 use super::*;
 
 use std::{
+    any::Any,
     cmp,
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Debug,
+    fs::File,
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use super::calendar::TradingCalendar;
+use super::fee::FeeModel;
+use super::utils::{
+    adjust_timestamp_milliseconds_i64, lots_to_qty, qty_to_lots, validate_price_qty,
+};
+
+use super::hook::{
+    EventCallback, Hook, HookInfo, HookType, HookWatchdog, LifecycleCallback, LifecycleEvent,
+    LifecycleHook, OrderLifecycle, ReplayCallback, ReplayEvent, SlowHookPolicy,
+    DEFAULT_MAX_HOOK_PANICS,
+};
+use super::market_view::{
+    LevelView, MarketView, MarketViewBuffer, Outage, MARKET_VIEW_CAPACITY, MARKET_VIEW_LEVELS,
 };
+use super::order::{
+    CancelFilter, Disposition, DispositionReport, Order, OrderRef, OrderUserData, ParentOrder,
+    ParentSummary, StrategySummary, UserFill,
+};
+use super::publish::{state_channel, PublishedState, StatePublisher, StateReceiver};
+use super::queue_model::QueueModel;
+use super::reconciliation::{DiscrepancyKind, ReconciliationLog, ReconciliationReport};
+use super::reconstruction::{Divergence, DivergenceKind, L2Snapshot, ReconstructionValidator};
+use super::schema::SCHEMA_VERSION;
+use super::statistics::{ReplaySummary, StatisticsInfo};
+use log::warn;
+
+fn empty_orders() -> Option<HashMap<OrderId, OrderRef>> {
+    Some(HashMap::new())
+}
+
+fn default_max_hook_panics() -> u32 {
+    DEFAULT_MAX_HOOK_PANICS
+}
 
-use super::utils::{adjust_timestamp_milliseconds_i64, is_in_call_auction};
+fn default_hook_timing() -> bool {
+    true
+}
 
-use super::hook::{Hook, HookType};
-use super::order::{Order, OrderRef};
-use super::statistics::StatisticsInfo;
 /// 交易经纪人结构体
 /// `Broker` 结构体管理交易订单、市场深度、以及与订单处理相关的逻辑。
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Broker<MD> {
+    /// 快照的格式版本，序列化时总是写出当前版本，缺失时按版本 1 读取
+    #[serde(
+        default = "schema::legacy_version",
+        serialize_with = "schema::serialize_version",
+        deserialize_with = "schema::deserialize_version"
+    )]
+    pub schema_version: u32,
     /// 交易模式，例如回测模式或实时模式
     pub mode: ExchangeMode,
     /// 市场类型，例如股票市场、期货市场等。
@@ -36,10 +80,29 @@ pub struct Broker<MD> {
     pub latest_seq_number: i64,
     /// 最小价格变动单位
     pub tick_size: f64,
-    /// 最小交易单位
+    /// 最小交易单位，市场深度中的数量以该值为单位
     pub lot_size: f64,
-    /// 前一交易日的收盘价。
-    pub previous_close_price: f64,
+    /// 委托数量的申报规则，根据股票代码推断
+    #[serde(default)]
+    pub lot_rule: LotRule,
+    /// 前一交易日的收盘价，`None` 表示前收盘价未知（例如新股上市首日）。
+    #[serde(deserialize_with = "schema::deserialize_previous_close_price")]
+    pub previous_close_price: Option<f64>,
+    /// 前收盘价未知时使用的参考价格
+    #[serde(default)]
+    pub opening_reference: OpeningReference,
+    /// 涨跌停幅度，例如 0.1 表示 10%，0 表示不设涨跌停
+    #[serde(default)]
+    pub price_limit_ratio: f64,
+    /// 涨跌停且对手方没有流动性时市价订单的处理方式
+    #[serde(default)]
+    pub price_limit_policy: PriceLimitPolicy,
+    /// 用户订单与历史订单在同一时间戳到达时的处理顺序
+    #[serde(default)]
+    pub same_time_priority: SameTimePriority,
+    /// 交易日历，记录提前收盘的半日交易日
+    #[serde(default)]
+    pub calendar: TradingCalendar,
     /// 当前时间戳
     pub timestamp: i64,
     /// 历史数据源
@@ -50,8 +113,14 @@ pub struct Broker<MD> {
     /// 未来时间等待处理的订单，按时间排序
     #[serde(skip)]
     pub waiting_orders: VecDeque<(i64, OrderRef)>,
-    /// 所有用户的订单
+    /// `pending_orders` 的容量上限，`None` 表示不限制
     #[serde(skip)]
+    pub max_pending_orders: Option<usize>,
+    /// `waiting_orders` 的容量上限，`None` 表示不限制
+    #[serde(skip)]
+    pub max_waiting_orders: Option<usize>,
+    /// 所有用户的订单，快照中不保存，反序列化后为空表
+    #[serde(skip, default = "empty_orders")]
     pub orders: Option<HashMap<OrderId, OrderRef>>,
     /// 脏订单跟踪器
     #[serde(skip)]
@@ -60,6 +129,108 @@ pub struct Broker<MD> {
     /// 这里使用 `HookType` 作为键，`Hook` 表示钩子函数，`String` 用于标识钩子的唯一性
     #[serde(skip)]
     pub hooks: HashMap<HookType, HashMap<String, Hook>>,
+    /// 是否正在推进时间，此时不能修改钩子
+    #[serde(skip)]
+    pub elapsing: bool,
+    /// 慢钩子监控的配置，`None` 表示不监控
+    #[serde(skip)]
+    pub hook_watchdog: Option<HookWatchdog>,
+    /// 是否统计钩子的执行耗时，追求极致性能的回测可以关闭
+    #[serde(skip, default = "default_hook_timing")]
+    pub hook_timing: bool,
+    /// 钩子 panic 达到该次数后被注销
+    #[serde(skip, default = "default_max_hook_panics")]
+    pub max_hook_panics: u32,
+    /// 策略可见行情的延迟，单位为毫秒，0 表示不延迟
+    #[serde(skip)]
+    pub md_delay: i64,
+    /// 用户撤单的延迟，单位为毫秒，0 表示立即生效
+    #[serde(default)]
+    pub cancel_latency: i64,
+    /// 新订单得到交易所确认的延迟，单位为毫秒，0 表示订单到达即得到确认
+    #[serde(default)]
+    pub ack_latency: i64,
+    /// 已发出但尚未生效的用户撤单，元素为 (生效时间, 订单 ID)，按生效时间排序
+    #[serde(default)]
+    pub pending_cancels: VecDeque<(i64, OrderId)>,
+    /// 对尚未得到确认的订单发出撤单时的处理方式
+    #[serde(skip)]
+    pub pending_ack_cancel: PendingAckCancel,
+    /// 在订单得到确认后立即执行的撤单
+    #[serde(skip)]
+    pub ack_cancels: Vec<OrderId>,
+    /// 回测中历史订单部分成交后剩余部分的排队位置
+    #[serde(skip)]
+    pub residual_priority: ResidualPriority,
+    /// 为情景分析注入的假设挂单，使用负数订单 ID
+    #[serde(skip)]
+    pub phantom_orders: Vec<L3OrderRef>,
+    /// 延迟行情的快照缓冲区，仅在 `md_delay` 大于 0 时采集
+    #[serde(skip)]
+    pub market_views: MarketViewBuffer,
+    /// 模拟的交易所故障窗口，参见 `schedule_outage`
+    #[serde(skip)]
+    pub outages: Vec<Outage>,
+    /// 用户订单的手续费模型，`None` 表示不收取手续费
+    #[serde(skip)]
+    pub fee_model: Option<FeeModel>,
+    /// 提交时被拒绝的用户订单的汇总，这些订单不在订单表中
+    #[serde(skip)]
+    pub rejected_report: DispositionReport,
+    /// 用户订单的成交回报，按记录的先后顺序排列；挂单的被动成交在同步订单信息时记录
+    #[serde(skip)]
+    pub user_fills: Vec<UserFill>,
+    /// 回放过程中因数据异常被跳过的订单，元素为 (序列号, 订单 ID)
+    #[serde(skip)]
+    pub skipped_orders: Vec<(i64, OrderId)>,
+    /// 已处理的历史逐笔消息数量
+    #[serde(skip)]
+    pub events_processed: usize,
+    /// 时间戳早于之前已处理消息的历史逐笔消息数量，这些消息按当前时间处理
+    #[serde(skip)]
+    pub out_of_order_events: usize,
+    /// 推进时间累计耗费的实际时间
+    #[serde(skip)]
+    pub wall_time: Duration,
+    /// 已处理的历史逐笔消息的主动成交量之和，以手表示
+    #[serde(skip)]
+    pub history_match_vol: i64,
+    /// 集合竞价的成交量之和，以手表示
+    #[serde(skip)]
+    pub auction_vol: i64,
+    /// 回放时每处理多少条历史逐笔消息执行一次对账，`None` 表示不自动对账
+    #[serde(skip)]
+    pub auto_reconcile_every: Option<usize>,
+    /// 自动对账的记录
+    #[serde(skip)]
+    pub reconciliation_log: ReconciliationLog,
+    /// 回放时每处理多少条历史逐笔消息发布一次状态，`None` 表示不发布
+    #[serde(skip)]
+    pub publish_every: Option<usize>,
+    /// 状态的发布端，每个订阅者一个
+    #[serde(skip)]
+    pub state_publishers: Vec<StatePublisher>,
+    /// 最近一次发布的状态序号
+    #[serde(skip)]
+    pub published_seq: u64,
+    /// 订单簿重建校验器，设置后在回放时与交易所快照进行比较
+    #[serde(skip)]
+    pub validator: Option<ReconstructionValidator>,
+    /// 历史主动订单的成交价与历史成交价不一致时的处理方式
+    #[serde(skip)]
+    pub trade_repair_policy: TradeRepairPolicy,
+    /// 已登记的母单，键为母单 ID
+    #[serde(skip)]
+    pub parents: HashMap<OrderId, ParentOrder>,
+    /// 回放历史数据时对每条逐笔消息调用的回调
+    #[serde(skip)]
+    pub event_callback: Option<EventCallback>,
+    /// 用户订单生命周期事件的回调
+    #[serde(skip)]
+    pub lifecycle_callback: Option<LifecycleHook>,
+    /// 是否已经初始化，重复调用 `init` 时不会重新创建订单表
+    #[serde(skip)]
+    pub initialized: bool,
 }
 
 impl<'a, MD> Broker<MD>
@@ -76,7 +247,7 @@ where
     /// * `stock_type` - 股票类型
     /// * `stock_code` - 股票代码
     /// * `tick_size` - 最小价格变动单位
-    /// * `lot_size` - 最小交易单位
+    /// * `lot_size` - 最小交易单位，科创板股票按申报规则改为 1 股
     ///
     /// # 返回
     ///
@@ -89,7 +260,11 @@ where
         tick_size: f64,
         lot_size: f64,
     ) -> Self {
+        // 科创板等非整手申报的股票，市场深度以递增单位而不是整手保存数量
+        let lot_rule = LotRule::from_stock_code(&stock_code, lot_size);
+        let lot_size = lot_rule.unit();
         Self {
+            schema_version: SCHEMA_VERSION,
             mode: mode,
             market_type,
             stock_type: stock_type,
@@ -97,1237 +272,7594 @@ where
             market_depth: MD::new_box(mode.clone(), tick_size.clone(), lot_size.clone()),
             pending_orders: VecDeque::new(),
             waiting_orders: VecDeque::new(),
+            max_pending_orders: None,
+            max_waiting_orders: None,
             timestamp: 19700101000000000,
-            orders: None,
+            orders: empty_orders(),
             latest_seq_number: 0,
             tick_size: tick_size,
             lot_size: lot_size,
-            previous_close_price: 0.0,
+            lot_rule: lot_rule,
+            previous_close_price: None,
+            opening_reference: OpeningReference::default(),
+            price_limit_ratio: 0.0,
+            price_limit_policy: PriceLimitPolicy::default(),
+            same_time_priority: SameTimePriority::default(),
+            calendar: TradingCalendar::new(),
             history: None,
             dirty_tracker: Vec::new(),
             open_tick: 0,
             close_tick: 0,
             hooks: HashMap::new(),
+            elapsing: false,
+            hook_watchdog: None,
+            hook_timing: true,
+            max_hook_panics: DEFAULT_MAX_HOOK_PANICS,
+            md_delay: 0,
+            cancel_latency: 0,
+            ack_latency: 0,
+            pending_ack_cancel: PendingAckCancel::default(),
+            ack_cancels: Vec::new(),
+            residual_priority: ResidualPriority::default(),
+            pending_cancels: VecDeque::new(),
+            phantom_orders: Vec::new(),
+            market_views: MarketViewBuffer::new(MARKET_VIEW_CAPACITY),
+            outages: Vec::new(),
+            fee_model: None,
+            rejected_report: DispositionReport::default(),
+            user_fills: Vec::new(),
+            skipped_orders: Vec::new(),
+            events_processed: 0,
+            out_of_order_events: 0,
+            wall_time: Duration::ZERO,
+            history_match_vol: 0,
+            auction_vol: 0,
+            auto_reconcile_every: None,
+            reconciliation_log: ReconciliationLog::default(),
+            publish_every: None,
+            state_publishers: Vec::new(),
+            published_seq: 0,
+            validator: None,
+            trade_repair_policy: TradeRepairPolicy::default(),
+            parents: HashMap::new(),
+            event_callback: None,
+            lifecycle_callback: None,
+            initialized: false,
         }
     }
 
+    /// 将价格换算为 tick 价格，按四舍五入取整。
+    pub fn price_to_tick(&self, price: f64) -> i64 {
+        (price / self.tick_size).round() as i64
+    }
+
+    /// 将 tick 价格换算为价格。
+    pub fn tick_to_price(&self, tick: i64) -> f64 {
+        tick as f64 * self.tick_size
+    }
+
+    /// 将数量换算为以 `lot_size` 为单位的数量，按四舍五入取整。
+    pub fn qty_to_vol(&self, qty: f64) -> i64 {
+        qty_to_lots(qty, self.lot_size)
+    }
+
+    /// 将以 `lot_size` 为单位的数量换算为数量。
+    pub fn vol_to_qty(&self, vol: i64) -> f64 {
+        lots_to_qty(vol, self.lot_size)
+    }
+
+    /// 设置前收盘价。
+    ///
+    /// # 参数
+    /// - `previous_close_price`: 前收盘价，不大于 0 时视为前收盘价未知。
     pub fn set_previous_close_price(&mut self, previous_close_price: f64) {
-        self.previous_close_price = previous_close_price;
-        let previous_close_tick = (previous_close_price / self.tick_size).round() as i64;
+        self.previous_close_price = (previous_close_price > 0.0).then_some(previous_close_price);
+        let previous_close_tick = self
+            .previous_close_price
+            .map(|price| self.price_to_tick(price));
         self.market_depth
             .set_previous_close_tick(previous_close_tick);
     }
 
-    pub fn register_orderbook_hook(&mut self, hook_type: HookType, name: &str, hook: Hook) {
-        self.hooks
-            .entry(hook_type)
-            .or_insert_with(HashMap::new)
-            .insert(name.to_string(), hook);
+    /// 按除权除息调整订单簿中的挂单，并同步调整对应的用户订单和前收盘价。
+    ///
+    /// 用户订单的价格和剩余数量与订单簿中的挂单保持一致，已成交的数量按拆股比例换算，
+    /// 成交金额保持不变。数量四舍五入后为 0 的挂单被撤销，对应的用户订单变为已撤销状态。
+    /// 尚未进入订单簿的用户订单不做调整。
+    ///
+    /// # 错误
+    /// - 参数无效时返回 `MarketError::InvalidCorporateAction`。
+    /// - 调整后的价格超出有效范围时返回 `MarketError::PriceTickOutOfRange`，订单簿保持不变。
+    pub fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<(), MarketError> {
+        // 先同步此前的成交，之后的成交按调整后的数量计算
+        self.sync_order_info();
+        self.market_depth.apply_corporate_action(action)?;
+        let ratio = match action {
+            CorporateAction::Split(ratio) => ratio,
+            CorporateAction::CashDividend(_) => 1.0,
+        };
+        for (order_id, l3order_ref) in self.market_depth.orders().iter() {
+            let l3order = l3order_ref.borrow();
+            // 被撤销的挂单保留原来的数量，在同步时按普通撤单处理
+            if l3order.side == Side::None {
+                continue;
+            }
+            let mut order = match self.orders.as_ref().and_then(|orders| orders.get(order_id)) {
+                Some(order_ref) => order_ref.borrow_mut(),
+                None => continue,
+            };
+            if order.is_terminal() {
+                continue;
+            }
+            order.price_tick = l3order.price_tick;
+            order.price = self.tick_to_price(l3order.price_tick);
+            order.filled_qty *= ratio;
+            order.filled_qty_aggressive *= ratio;
+            order.filled_qty_passive *= ratio;
+            order.filled_qty_auction *= ratio;
+            order.left_qty = l3order.left_qty(self.lot_size);
+            order.qty = order.filled_qty + order.left_qty;
+            order.queue = self.vol_to_qty(l3order.total_vol_before);
+        }
+        if let Some(price) = self.previous_close_price {
+            let adjusted = match action {
+                CorporateAction::Split(ratio) => price / ratio,
+                CorporateAction::CashDividend(amount) => price - amount,
+            };
+            self.set_previous_close_price(adjusted);
+        }
+        self.sync_order_info();
+        Ok(())
+    }
+
+    /// 设置前收盘价未知时使用的参考价格。
+    ///
+    /// 使用 `OpeningReference::Manual` 时，前收盘价未知的交易日按新股上市首日的
+    /// 涨跌幅限制（`IPO_LIMIT_UP_RATIO` 和 `IPO_LIMIT_DOWN_RATIO`）计算涨跌停价。
+    pub fn set_opening_reference(&mut self, opening_reference: OpeningReference) {
+        self.opening_reference = opening_reference;
     }
 
-    pub fn remove_hook(&mut self, name: &str) {
-        for hooks in self.hooks.values_mut() {
-            hooks.remove(name);
+    /// 返回当日的参考价格。
+    ///
+    /// # 返回值
+    /// 前收盘价已知时返回前收盘价，否则按 `opening_reference` 返回开盘集合竞价的成交价、
+    /// 当日第一笔成交的价格或指定的参考价格；参考价格尚未产生时返回 `None`。
+    pub fn reference_price(&self) -> Option<f64> {
+        if self.previous_close_price.is_some() {
+            return self.previous_close_price;
+        }
+        match self.opening_reference {
+            OpeningReference::FirstAuctionPrice => {
+                (self.open_tick > 0).then(|| self.tick_to_price(self.open_tick))
+            }
+            OpeningReference::FirstTradePrice => self
+                .market_depth
+                .get_statistics()
+                .first_trade_tick
+                .map(|tick| self.tick_to_price(tick)),
+            OpeningReference::Manual(price) => (price > 0.0).then_some(price),
         }
     }
 
-    pub fn init(&mut self) {
-        if self.orders.is_none() {
-            self.orders = Some(HashMap::new());
+    /// 返回计算涨跌停价使用的基准价格、涨幅和跌幅。
+    ///
+    /// 前收盘价已知时按 `price_limit_ratio` 计算；前收盘价未知时只有指定了参考价格才按
+    /// 新股上市首日的涨跌幅计算，否则不设涨跌停。
+    fn price_limit_base(&self) -> Option<(f64, f64, f64)> {
+        match (self.previous_close_price, self.opening_reference) {
+            (Some(price), _) if self.price_limit_ratio > 0.0 => {
+                Some((price, self.price_limit_ratio, self.price_limit_ratio))
+            }
+            (None, OpeningReference::Manual(price)) if price > 0.0 => {
+                Some((price, IPO_LIMIT_UP_RATIO, IPO_LIMIT_DOWN_RATIO))
+            }
+            _ => None,
         }
     }
 
-    pub fn get_current_time(&self) -> i64 {
-        self.timestamp
+    /// 设置涨跌停幅度及涨跌停时市价订单的处理方式。
+    ///
+    /// # 参数
+    /// - `ratio`: 相对前收盘价的涨跌停幅度，例如 0.1 表示 10%，0 表示不设涨跌停。
+    /// - `policy`: 订单簿在涨跌停价上单边时市价订单的处理方式。
+    pub fn set_price_limit(&mut self, ratio: f64, policy: PriceLimitPolicy) {
+        self.price_limit_ratio = ratio;
+        self.price_limit_policy = policy;
     }
 
-    pub fn set_current_time(&mut self, timestamp: i64) {
-        self.timestamp = timestamp
+    /// 返回涨停价的 tick 价格，不设涨跌停时返回 `None`。
+    pub fn limit_up_tick(&self) -> Option<i64> {
+        let (price, up_ratio, _) = self.price_limit_base()?;
+        Some(self.price_to_tick(price * (1.0 + up_ratio)))
     }
 
-    pub fn snapshot(&self) -> String {
-        serde_json::to_string(self).unwrap_or("{}".to_string())
+    /// 返回跌停价的 tick 价格，不设涨跌停时返回 `None`。
+    pub fn limit_down_tick(&self) -> Option<i64> {
+        let (price, _, down_ratio) = self.price_limit_base()?;
+        Some(self.price_to_tick(price * (1.0 - down_ratio)))
     }
 
-    pub fn orders(&self) -> &HashMap<OrderId, OrderRef> {
-        self.orders.as_ref().unwrap()
+    /// 将某一日期标记为提前收盘的半日交易日，收盘集合竞价和连续竞价的结束时间随之提前。
+    ///
+    /// # 参数
+    /// - `date`: 日期，格式为 `20231229`。
+    /// - `close_time`: 当日的收盘时间，格式为 `113000000`。
+    pub fn mark_half_day(&mut self, date: i64, close_time: i64) {
+        self.calendar.mark_half_day(date, close_time);
     }
 
-    /// 生成并返回下一个序列号。
-    /// 每次调用时，最新的序列号递增1。
+    /// 设置用户订单与历史订单在同一时间戳到达时的处理顺序。
     ///
-    /// # 返回值
+    /// 无论 `elapse` 的时间窗口如何切分，同一时间戳上总是按该顺序处理，保证回放结果确定。
+    pub fn set_same_time_priority(&mut self, priority: SameTimePriority) {
+        self.same_time_priority = priority;
+    }
+
+    /// 返回同一时间戳的历史订单中可以先于用户订单处理的序列号上界（不含）。
     ///
-    /// 返回最新的序列号
-    pub fn generate_seq_number(&mut self) -> i64 {
-        self.latest_seq_number += 1;
-        self.latest_seq_number
+    /// # 参数
+    /// - `user_seq`: 用户订单的序列号，推进时间时没有用户订单则为 `None`。
+    fn same_time_seq_limit(&self, user_seq: Option<i64>) -> i64 {
+        match self.same_time_priority {
+            SameTimePriority::HistoryFirst => i64::MAX,
+            SameTimePriority::UserFirst => i64::MIN,
+            SameTimePriority::BySeq => user_seq.unwrap_or(i64::MIN),
+        }
     }
 
-    /// 设置历史数据，并返回操作是否成功。
+    /// 注册一个钩子，同一类型下的名称不能重复。
     ///
     /// # 参数
+    /// - `hook_type`: 钩子的类型。
+    /// - `name`: 钩子的名称，在同一类型下唯一。
+    /// - `hook`: 钩子的实现。
     ///
-    /// * `history` - 一个可选的 `DataCollator`，用于设置历史数据。
+    /// # 错误
+    /// - `MarketError::HookNameExists`: 同一类型下已经注册了同名的钩子，需要覆盖时使用 `replace_hook`。
+    /// - `MarketError::HookModifyWhileElapsing`: 正在推进时间。
+    pub fn register_orderbook_hook(
+        &mut self,
+        hook_type: HookType,
+        name: &str,
+        hook: Hook,
+    ) -> Result<(), MarketError> {
+        if self.elapsing {
+            return Err(MarketError::HookModifyWhileElapsing);
+        }
+        match self
+            .hooks
+            .entry(hook_type)
+            .or_insert_with(HashMap::new)
+            .entry(name.to_string())
+        {
+            Entry::Occupied(_) => Err(MarketError::HookNameExists(name.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(hook);
+                Ok(())
+            }
+        }
+    }
+
+    /// 注册一个钩子，同一类型下已有同名的钩子时将其替换。
     ///
     /// # 返回值
+    /// 被替换的钩子，原来没有同名的钩子时返回 `None`。
     ///
-    /// * `Ok(true)` 如果操作成功。
-    /// * `Err(MarketError)` 如果出现错误。
-    pub fn add_data(&mut self, history: Option<DataCollator>) -> Result<bool, MarketError> {
-        self.history = history;
-        Ok(true)
+    /// # 错误
+    /// - `MarketError::HookModifyWhileElapsing`: 正在推进时间。
+    pub fn replace_hook(
+        &mut self,
+        hook_type: HookType,
+        name: &str,
+        hook: Hook,
+    ) -> Result<Option<Hook>, MarketError> {
+        if self.elapsing {
+            return Err(MarketError::HookModifyWhileElapsing);
+        }
+        Ok(self
+            .hooks
+            .entry(hook_type)
+            .or_insert_with(HashMap::new)
+            .insert(name.to_string(), hook))
     }
 
-    pub fn process_local_order(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let mut filled = 0;
-        let seq = order_ref.borrow().seq;
-
-        let order_time = order_ref.borrow().timestamp;
-        let in_call_auction = is_in_call_auction(order_time, self.market_type)?;
-        let auxiliary_info = order_ref
-            .borrow_mut()
-            .auxiliary_info
-            .as_ref()
-            .unwrap()
-            .clone();
-
-        let match_vol = (auxiliary_info.match_qty / self.lot_size).round() as i64;
-        let orderbook_vol = (auxiliary_info.orderbook_qty / self.lot_size).round() as i64;
-        let initial_vol = (auxiliary_info.initial_qty / self.lot_size).round() as i64;
-
-        if self.mode == ExchangeMode::Live {
-            let price_tick = if match_vol > 0 {
-                (auxiliary_info.match_price / self.tick_size).round() as i64
-            } else if orderbook_vol > 0 {
-                (auxiliary_info.orderbook_price / self.tick_size).round() as i64
-            } else {
-                (auxiliary_info.initial_price / self.tick_size).round() as i64
-            };
-            let mut order = order_ref.borrow_mut();
-            order.price_tick = price_tick;
-            order.vol = initial_vol;
-            order.vol_shadow = order.vol;
-            drop(order);
-            filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-            if filled != initial_vol {
-                self.market_depth.add(order_ref.clone());
-            }
-        } else {
-            // print!(" -- order seq = {seq} , {order_ref:?} --\n");
-            if auxiliary_info.cancel_seq == seq {
-                // print!(
-                //     "== before cancel {:?}\n",
-                //     self.market_depth.get_bid_level(1)
-                // );
-                // print!(
-                //     "== before cancel {:?}\n",
-                //     self.market_depth.get_ask_level(1)
-                // );
-
-                let _ = self.cancel_order_from_ref(order_ref.clone());
-                // print!("== after cancel {:?}\n", self.market_depth.get_bid_level(1));
-                // print!("== after cancel {:?}\n", self.market_depth.get_ask_level(1));
-            } else {
-                if in_call_auction {
-                    let mut order = order_ref.borrow_mut();
-                    order.price_tick =
-                        (auxiliary_info.initial_price / self.tick_size).round() as i64;
-                    order.vol = initial_vol;
-                    order.vol_shadow = order.vol;
-                    drop(order);
-                    let _ = self.market_depth.add(order_ref.clone())?;
-                } else {
-                    if match_vol > 0 {
-                        // print!("== before match {:?}\n", self.market_depth.get_bid_level(1));
-                        // print!("== before match {:?}\n", self.market_depth.get_ask_level(1));
-                        let mut order = order_ref.borrow_mut();
-                        order.price_tick =
-                            (auxiliary_info.match_price / self.tick_size).round() as i64;
-                        order.vol = initial_vol;
-                        order.vol_shadow = order.vol;
-                        drop(order);
-                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-
-                        if orderbook_vol > 0 {
-                            order_ref.borrow_mut().price_tick =
-                                (auxiliary_info.orderbook_price / self.tick_size).round() as i64;
-
-                            let _ = self.market_depth.add(order_ref.clone())?;
-                        }
+    /// 返回所有已注册钩子的描述信息，按钩子类型和名称排序。
+    pub fn hooks_info(&self) -> Vec<HookInfo> {
+        let mut infos: Vec<HookInfo> = self
+            .hooks
+            .iter()
+            .flat_map(|(hook_type, hooks)| {
+                hooks.iter().map(move |(name, hook)| HookInfo {
+                    hook_type: *hook_type,
+                    name: name.clone(),
+                    max_level: hook.max_level,
+                    slow_policy: hook.slow_policy,
+                    stats: hook.stats,
+                    panics: hook.panics,
+                })
+            })
+            .collect();
+        infos.sort_by(|a, b| (a.hook_type as u8, &a.name).cmp(&(b.hook_type as u8, &b.name)));
+        infos
+    }
 
-                        // if filled != match_vol {
-                        //     print!(" ====== filled {filled} shoud be equel to match_vol {match_vol} ======\n");
-                        // }
-                        // print!("== after match  {:?}\n", self.market_depth.get_bid_level(1));
-                        // print!("== after match  {:?}\n", self.market_depth.get_ask_level(1));
-                    } else if orderbook_vol > 0 {
-                        //尝试去匹配用户订单，因为没有历史成交不代表不可能和用户的订单成交
-                        // print!(
-                        //     ">> before orderbook {:?}\n",
-                        //     self.market_depth.get_bid_level(1)
-                        // );
-                        // print!(
-                        //     ">> befeor orderbook  {:?}\n",
-                        //     self.market_depth.get_ask_level(1)
-                        // );
-                        let mut order = order_ref.borrow_mut();
-                        order.price_tick =
-                            (auxiliary_info.orderbook_price / self.tick_size).round() as i64;
-                        order.vol = initial_vol;
-                        order.vol_shadow = order.vol;
-                        drop(order);
-                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-                        let _ = self.market_depth.add(order_ref.clone())?;
-                        // if filled > 0 {
-                        //     print!("----- orderbook filled {filled}\n");
-                        // }
-                        // print!(
-                        //     ">> after orderbook {:?}\n",
-                        //     self.market_depth.get_bid_level(1)
-                        // );
-                        // print!(
-                        //     ">> after orderbook  {:?}\n",
-                        //     self.market_depth.get_ask_level(1)
-                        // );
-                    } else {
-                        let mut order = order_ref.borrow_mut();
-                        // print!("++ before other {:?}\n", self.market_depth.get_bid_level(1));
-                        // print!("++ before other {:?}\n", self.market_depth.get_ask_level(1));
-                        order.price_tick =
-                            (auxiliary_info.initial_price / self.tick_size).round() as i64;
-                        order.vol = (auxiliary_info.initial_qty / self.lot_size).round() as i64;
-                        order.vol_shadow = order.vol;
-                        drop(order);
-                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-                        let _ = self.market_depth.add(order_ref.clone())?;
-                        // if filled > 0 {
-                        //     print!("----- other filled {filled}\n");
-                        // }
-                        // print!("++ after other {:?}\n", self.market_depth.get_bid_level(1));
-                        // print!("++ after other {:?}\n", self.market_depth.get_ask_level(1));
-                    }
-                }
-            }
-        }
+    /// 设置慢钩子监控，`None` 表示不监控。
+    ///
+    /// 钩子连续超过阈值达到次数后记录警告日志，钩子的 `slow_policy` 为
+    /// `SlowHookPolicy::Deregister` 时同时注销该钩子。
+    pub fn set_hook_watchdog(&mut self, watchdog: Option<HookWatchdog>) {
+        self.hook_watchdog = watchdog;
+    }
 
-        Ok(filled)
+    /// 设置是否统计钩子的执行耗时，默认统计。关闭后慢钩子监控同样不生效。
+    pub fn set_hook_timing(&mut self, enabled: bool) {
+        self.hook_timing = enabled;
     }
 
-    /// 根据订单方向和来源获取最佳价格。
+    /// 设置钩子允许 panic 的次数，达到后钩子被注销，默认为 1。
     ///
     /// # 参数
-    /// - `side`: 订单的买卖方向。
-    /// - `source`: 订单的来源。
-    ///
-    /// # 返回值
-    /// - `i64`: 最佳价格。
-    fn get_best_tick(&self, side: &Side, source: &OrderSourceType) -> i64 {
-        match side {
-            Side::Buy => self.market_depth.best_bid_tick(source),
-            _ => self.market_depth.best_ask_tick(source),
-        }
+    /// - `max_panics`: 允许的次数，小于 1 时按 1 处理。
+    pub fn set_max_hook_panics(&mut self, max_panics: u32) {
+        self.max_hook_panics = cmp::max(max_panics, 1);
     }
 
-    /// 处理 `OrderType::L` 订单（普通限价订单）。
+    /// 设置回放历史数据时的逐笔回调。
     ///
-    /// 使用无限价格档位尝试匹配订单，如果未完全成交，将订单添加到市场深度中。
+    /// 与订单簿钩子不同，回调在撮合之前触发，并且覆盖所有逐笔消息，包括未成交的挂单和撤单。
     ///
     /// # 参数
-    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
-    ///
-    /// # 返回值
-    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
-    pub fn match_order_l(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-        if order_ref.borrow().vol > 0 {
-            let best_tick = self.market_depth.add(order_ref)?;
-        }
+    /// - `object`: 传给回调的用户对象。
+    /// - `handler`: 回调函数。
+    pub fn set_event_callback(&mut self, object: Rc<RefCell<dyn Any>>, handler: ReplayCallback) {
+        self.event_callback = Some(EventCallback {
+            object: object,
+            handler: handler,
+        });
+    }
 
-        Ok(filled)
+    pub fn clear_event_callback(&mut self) {
+        self.event_callback = None;
     }
 
-    /// 处理 `OrderType::M` 订单（最优五档即时成交剩余撤销的市价订单）。
-    ///
-    /// 设置订单的价格为无限价格档位，尝试在市场深度中匹配订单，最多匹配五档价格。如果未完全成交，剩余部分订单将被撤销。
+    /// 设置用户订单生命周期事件的回调，例如订单得到交易所确认、延迟生效的撤单被拒绝。
     ///
     /// # 参数
-    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
-    ///
-    /// # 返回值
-    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
-    pub fn match_order_m(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        order_ref.borrow_mut().price_tick = i64::MAX;
-        let filled = self.market_depth.match_order(order_ref.clone(), 5)?;
-        order_ref.borrow_mut().price_tick = 0;
-        if order_ref.borrow().vol > 0 {
-            order_ref.borrow_mut().side = Side::None;
+    /// - `object`: 传给回调的用户对象。
+    /// - `handler`: 回调函数。
+    pub fn set_lifecycle_callback(
+        &mut self,
+        object: Rc<RefCell<dyn Any>>,
+        handler: LifecycleCallback,
+    ) {
+        self.lifecycle_callback = Some(LifecycleHook {
+            object: object,
+            handler: handler,
+        });
+    }
+
+    pub fn clear_lifecycle_callback(&mut self) {
+        self.lifecycle_callback = None;
+    }
+
+    /// 将用户订单的生命周期事件通知给回调。
+    fn notify_lifecycle(&self, order_id: OrderId, event: LifecycleEvent, timestamp: i64) {
+        if let Some(callback) = self.lifecycle_callback.as_ref() {
+            let event = OrderLifecycle {
+                order_id: order_id,
+                event: event,
+                timestamp: timestamp,
+            };
+            (callback.handler)(&callback.object, &event);
         }
-        Ok(filled)
     }
 
-    /// 处理 `OrderType::N` 订单（最优五档即时成交剩余转限价的市价订单）。
-    ///
-    /// 设置订单的价格为无限价格档位，尝试在市场深度中匹配订单，最多匹配五档价格。若有剩余部分，将其以上次成交价格加入市场深度。
+    /// 在撮合之前将历史逐笔消息通知给回调。
+    fn notify_replay_event(&self, seq: i64, order_ref: &L3OrderRef) {
+        if let Some(callback) = self.event_callback.as_ref() {
+            let order = order_ref.borrow();
+            let is_cancel = order
+                .auxiliary_info
+                .as_ref()
+                .map_or(false, |info| info.cancel_seq == seq);
+            let event = ReplayEvent {
+                order_id: order.order_id,
+                seq: seq,
+                timestamp: self.timestamp,
+                order_type: if is_cancel {
+                    OrderType::Cancel
+                } else {
+                    order.order_type
+                },
+            };
+            drop(order);
+            (callback.handler)(&callback.object, &event);
+        }
+    }
+
+    /// 移除指定名称的钩子。
     ///
     /// # 参数
-    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    /// - `hook_type`: 钩子的类型，为 `None` 时在所有类型中移除同名的钩子。
+    /// - `name`: 钩子的名称。
     ///
     /// # 返回值
-    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
-    pub fn match_order_n(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        order_ref.borrow_mut().price_tick = i64::MAX;
-        let source = order_ref.borrow().source;
-        let filled = self.market_depth.match_order(order_ref.clone(), 5)?;
-        if order_ref.borrow().vol > 0 {
-            order_ref.borrow_mut().price_tick = self.market_depth.last_tick(&source);
-            let best_tick = self.market_depth.add(order_ref)?;
+    /// 是否有钩子被移除。
+    ///
+    /// # 错误
+    /// - `MarketError::HookModifyWhileElapsing`: 正在推进时间。
+    pub fn remove_hook(
+        &mut self,
+        hook_type: Option<HookType>,
+        name: &str,
+    ) -> Result<bool, MarketError> {
+        if self.elapsing {
+            return Err(MarketError::HookModifyWhileElapsing);
         }
-        Ok(filled)
+        let removed = match hook_type {
+            Some(hook_type) => self
+                .hooks
+                .get_mut(&hook_type)
+                .map_or(false, |hooks| hooks.remove(name).is_some()),
+            None => self.hooks.values_mut().fold(false, |removed, hooks| {
+                hooks.remove(name).is_some() || removed
+            }),
+        };
+        Ok(removed)
     }
 
-    /// 处理 `OrderType::B` 订单（以本方最优价格申报的市价订单）。
+    /// 初始化经纪人的订单表，已经初始化后再次调用不做任何操作。
+    pub fn init(&mut self) {
+        if self.initialized {
+            return;
+        }
+        if self.orders.is_none() {
+            self.orders = Some(HashMap::new());
+        }
+        self.initialized = true;
+    }
+
+    pub fn get_current_time(&self) -> i64 {
+        self.timestamp
+    }
+
+    pub fn set_current_time(&mut self, timestamp: i64) {
+        self.timestamp = timestamp
+    }
+
+    pub fn snapshot(&self) -> String {
+        let mut buf = Vec::new();
+        match self.write_snapshot(&mut buf) {
+            Ok(_) => String::from_utf8(buf).unwrap_or("{}".to_string()),
+            Err(_) => "{}".to_string(),
+        }
+    }
+
+    /// 以流式方式将经纪人状态序列化写入给定的输出，避免在内存中构造完整的 JSON 字符串。
+    ///
+    /// # 参数
+    /// - `w`: 快照的输出目标。
     ///
-    /// 设置订单价格为市场深度中的最佳买价或卖价，并尝试将订单加入市场深度。
+    /// # 错误
+    /// 序列化或写入失败时返回相应的 `SnapshotError`。
+    pub fn write_snapshot<W: io::Write>(&self, w: W) -> Result<(), SnapshotError> {
+        let mut writer = BufWriter::new(w);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 将经纪人快照直接写入指定路径的文件。
     ///
     /// # 参数
-    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    /// - `path`: 快照文件路径，文件已存在时会被覆盖。
     ///
-    /// # 返回值
-    /// - `Result<i64, MarketError>`: 操作成功返回 `Ok(0)`，失败返回 `Err(MarketError>`。
-    pub fn match_order_b(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let side = order_ref.borrow().side;
-        let source = order_ref.borrow().source;
-        let vol = order_ref.borrow().vol;
-        order_ref.borrow_mut().price_tick = self.get_best_tick(&side, &source);
+    /// # 错误
+    /// 创建文件、序列化或写入失败时返回相应的 `SnapshotError`。
+    pub fn snapshot_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        self.write_snapshot(File::create(path)?)
+    }
 
-        if vol > 0 {
-            self.market_depth.add(order_ref.clone())?;
+    /// 设置策略可见行情的延迟。
+    ///
+    /// 设置后，`UserOrder` 来源的行情查询返回 `timestamp - delay` 时刻之前最新采集的行情快照，
+    /// 订单撮合本身不受影响。延迟为 0 时直接返回实时行情。
+    ///
+    /// # 参数
+    /// - `delay`: 延迟时间，单位为毫秒，小于 0 时按 0 处理。
+    pub fn set_md_delay(&mut self, delay: i64) {
+        self.md_delay = cmp::max(delay, 0);
+        self.market_views.clear();
+        if self.md_delay > 0 {
+            let view = self.capture_market_view();
+            self.market_views.push(view, self.timestamp);
         }
-
-        Ok(0)
     }
 
-    /// 处理 `OrderType::C` 订单（以对手方最优价格申报的市价订单）。
+    /// 安排一次模拟的交易所故障，用于测试策略在故障期间的表现。
     ///
-    /// 设置订单价格为市场深度中的最佳卖价或买价，并尝试将订单匹配到市场深度中。
+    /// 故障窗口为 `[start, end)`，历史数据在窗口内照常回放和撮合。
+    /// `OutageKind::MarketData` 期间不调用 `Orderbook` 钩子，`UserOrder` 来源的行情查询
+    /// 停留在窗口开始前的行情，窗口结束后恢复为实时行情（或延迟行情）。
+    /// `OutageKind::OrderEntry` 期间 `submit_order` 和 `request_cancel` 返回
+    /// `MarketError::GatewayUnavailable`，已挂出的订单照常撮合。多个窗口可以重叠。
     ///
     /// # 参数
-    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    /// - `start`: 故障开始的时间戳。
+    /// - `end`: 故障结束的时间戳，不包含在窗口内。
+    /// - `kind`: 故障类型。
     ///
-    /// # 返回值
-    /// - `Result<i64, MarketError>`: 返回实际成交量。
-    pub fn match_order_c(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let side = order_ref.borrow().side;
-        let source = order_ref.borrow().source;
-        let vol = order_ref.borrow().vol;
-        order_ref.borrow_mut().price_tick = self.get_best_tick(&side.opposite(), &source);
-
-        let filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-
-        if vol > 0 {
-            self.market_depth.add(order_ref.clone())?;
+    /// # 错误
+    /// `end` 不晚于 `start` 时返回 `MarketError::InvalidTimestamp`。
+    pub fn schedule_outage(
+        &mut self,
+        start: i64,
+        end: i64,
+        kind: OutageKind,
+    ) -> Result<(), MarketError> {
+        if end <= start {
+            return Err(MarketError::InvalidTimestamp);
         }
+        self.outages.push(Outage {
+            start,
+            end,
+            kind,
+            frozen: None,
+        });
+        self.freeze_market_views();
+        Ok(())
+    }
 
-        Ok(filled)
+    /// 判断给定时间戳是否处于某一类故障窗口内。
+    pub fn in_outage(&self, kind: OutageKind, timestamp: i64) -> bool {
+        self.outages
+            .iter()
+            .any(|outage| outage.kind == kind && outage.contains(timestamp))
     }
 
-    pub fn match_order_d(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        order_ref.borrow_mut().price_tick = i64::MAX;
-        let is_fullfilled = self
-            .market_depth
-            .try_match_order(order_ref.clone(), i64::MAX)?;
+    /// 为当前时间所在的行情中断窗口采集中断前的行情，并移除已经结束的窗口。
+    ///
+    /// 采集的是策略此刻可见的行情，因此重叠的窗口以及行情延迟可以叠加。
+    fn freeze_market_views(&mut self) {
+        let timestamp = self.timestamp;
+        self.outages.retain(|outage| outage.end > timestamp);
+        let pending = self.outages.iter().any(|outage| {
+            outage.kind == OutageKind::MarketData
+                && outage.frozen.is_none()
+                && outage.contains(timestamp)
+        });
+        if !pending {
+            return;
+        }
+        let view = self.market_view();
+        for outage in self.outages.iter_mut() {
+            if outage.kind == OutageKind::MarketData
+                && outage.frozen.is_none()
+                && outage.contains(timestamp)
+            {
+                outage.frozen = Some(view.clone());
+            }
+        }
+    }
 
-        if is_fullfilled {
-            let filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
-            Ok(filled)
-        } else {
-            order_ref.borrow_mut().side = Side::None;
-            Ok(0)
+    /// 从快照恢复后换上同一交易日的历史数据，从快照保存的位置继续回放。
+    ///
+    /// 快照中的历史数据只保存回放位置 `current_idx`，`history` 必须与生成快照时使用的数据相同。
+    /// 恢复后订单簿中的历史挂单与 `history` 中的订单是同一个订单，参见 `recover`。
+    ///
+    /// # 参数
+    /// - `history`: 完整的当日历史数据。
+    ///
+    /// # 错误
+    /// - `MarketError::RecoverFailed`: `history` 的数据少于快照中已经回放的数量，或订单簿无法恢复。
+    /// - 加载历史数据失败时返回 `DataCollator::init` 的错误。
+    pub fn resume_replay(&mut self, mut history: DataCollator) -> Result<bool, MarketError> {
+        history.init()?;
+        let current_idx = self
+            .history
+            .as_ref()
+            .map_or(0, |history| history.current_idx);
+        if current_idx > history.len {
+            return Err(MarketError::RecoverFailed);
         }
+        history.current_idx = current_idx;
+        self.history = Some(history);
+        self.recover()
     }
 
-    /// 处理订单
+    /// 设置用户撤单的延迟。
     ///
-    /// 该方法根据订单类型 (`OrderType`) 处理传入的订单，并执行相应的操作。根据不同的订单类型，方法会调用不同的匹配函数来处理订单。
+    /// 设置后，`request_cancel` 发出的撤单在 `timestamp + latency` 时刻才生效，
+    /// 在此之前到达的成交仍然会执行。延迟为 0 时撤单立即生效。
     ///
     /// # 参数
+    /// - `latency`: 延迟时间，单位为毫秒，小于 0 时按 0 处理。
+    pub fn set_cancel_latency(&mut self, latency: i64) {
+        self.cancel_latency = cmp::max(latency, 0);
+    }
+
+    /// 设置新订单得到交易所确认的延迟，默认为 0。
     ///
-    /// * `order_type` - 订单类型，指定要处理的订单类型。
-    /// * `l3order_ref` - 订单引用，包含要处理的订单的详细信息。
+    /// 设置后，新订单提交后处于 `OrderStatus::PendingAck` 状态，在到达时间加上延迟后得到交易所确认，
+    /// 记录确认时间 `ack_time` 并进入订单簿。延迟为 0 时订单到达即得到确认。
     ///
-    /// # 返回
+    /// # 参数
+    /// - `latency`: 延迟时间，单位为毫秒，小于 0 时按 0 处理。
+    pub fn set_ack_latency(&mut self, latency: i64) {
+        self.ack_latency = cmp::max(latency, 0);
+    }
+
+    /// 设置对尚未得到确认的订单发出撤单时的处理方式，默认为 `PendingAckCancel::Defer`。
+    pub fn set_pending_ack_cancel(&mut self, policy: PendingAckCancel) {
+        self.pending_ack_cancel = policy;
+    }
+
+    /// 设置回测模式下历史订单部分成交后剩余部分的排队位置，默认为 `ResidualPriority::Reset`。
     ///
-    /// 返回成功成交的订单量。处理失败则返回 `Err`。
-    pub fn process_order(&mut self, l3order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let source = l3order_ref.borrow().source;
-        let result;
-        l3order_ref.borrow_mut().timestamp = self.timestamp;
-        if source == OrderSourceType::LocalOrder {
-            result = self.process_local_order(l3order_ref.clone());
-        } else {
-            if is_in_call_auction(self.timestamp, self.market_type).unwrap_or(false) {
-                let _ = self.market_depth.add(l3order_ref.clone());
-                result = Ok(0);
-            } else {
-                let order_type = l3order_ref.borrow().order_type;
-                let order_id = l3order_ref.borrow().order_id;
-                result = match order_type {
-                    // 处理普通限价订单
-                    OrderType::L => self.match_order_l(l3order_ref.clone()),
-                    // 处理最优五档即时成交剩余撤销的市价订单
-                    OrderType::M => self.match_order_m(l3order_ref.clone()),
-                    // 处理最优五档即时成交剩余转限价的市价订单
-                    OrderType::N => self.match_order_n(l3order_ref.clone()),
-                    // 处理以本方最优价格申报的市价订单
-                    OrderType::B => self.match_order_b(l3order_ref.clone()),
-                    // 处理以对手方最优价格申报的市价订单
-                    OrderType::C => self.match_order_c(l3order_ref.clone()),
-                    // 处理市价全额成交或撤销订单
-                    OrderType::D => self.match_order_d(l3order_ref.clone()),
-                    // 处理取消委托
-                    OrderType::Cancel => self.cancel_order(order_id),
-                    _ => Err(MarketError::OrderTypeUnsupported),
-                };
-            }
+    /// 还原的订单簿与实际数据的排队顺序不一致时，可以调整此设置进行校准。
+    pub fn set_residual_priority(&mut self, policy: ResidualPriority) {
+        self.residual_priority = policy;
+    }
+
+    /// 设置回测模式下用户挂单的排队模型。
+    ///
+    /// 默认情况下排在用户挂单之前的历史挂单撤单后，用户挂单的位置立即前移。
+    /// 设置模型后由模型决定撤单量中有多少计为推进，实时模式不受影响。
+    ///
+    /// # 参数
+    /// - `model`: 排队模型，`None` 恢复默认行为。
+    pub fn set_queue_model(&mut self, model: Option<Box<dyn QueueModel>>) {
+        self.market_depth.set_queue_model(model);
+    }
+
+    /// 设置待处理队列和等待队列的容量上限。
+    ///
+    /// 订单将进入的队列已满时，`submit_order` 返回 `MarketError::OrderQueueFull`，
+    /// 订单不会被登记，状态保持不变。已经在队列中的订单不受影响。
+    ///
+    /// # 参数
+    /// - `max_pending`: `pending_orders` 的容量上限，`None` 表示不限制。
+    /// - `max_waiting`: `waiting_orders` 的容量上限，`None` 表示不限制。
+    pub fn set_queue_limits(&mut self, max_pending: Option<usize>, max_waiting: Option<usize>) {
+        self.max_pending_orders = max_pending;
+        self.max_waiting_orders = max_waiting;
+    }
+
+    /// 采集当前时刻策略可见的实时行情。
+    fn capture_market_view(&self) -> MarketView {
+        let source = OrderSourceType::UserOrder;
+        let mut bid_levels = Vec::with_capacity(MARKET_VIEW_LEVELS);
+        let mut ask_levels = Vec::with_capacity(MARKET_VIEW_LEVELS);
+        self.market_depth
+            .get_orderbook_level(&mut bid_levels, &mut ask_levels, MARKET_VIEW_LEVELS);
+
+        MarketView {
+            timestamp: self.timestamp,
+            best_bid_tick: self.market_depth.best_bid_tick(&source),
+            best_ask_tick: self.market_depth.best_ask_tick(&source),
+            last_tick: self.market_depth.last_tick(&source),
+            best_bid: self.market_depth.best_bid(&source),
+            best_ask: self.market_depth.best_ask(&source),
+            last_price: self.market_depth.last_price(&source),
+            bid_levels,
+            ask_levels,
         }
+    }
 
-        if let Some(hooks) = self.hooks.get_mut(&HookType::Orderbook) {
-            for (_, hook) in hooks.iter_mut() {
-                let mut info: StatisticsInfo = StatisticsInfo::new();
-                let mut bid_orderbook_info: Vec<(f64, f64, i64)> =
-                    Vec::with_capacity(hook.max_level);
-                let mut ask_orderbook_info: Vec<(f64, f64, i64)> =
-                    Vec::with_capacity(hook.max_level);
+    /// 在最优价或最新成交价发生变化时采集行情快照。
+    fn record_market_view(&mut self) {
+        if self.md_delay == 0 {
+            return;
+        }
 
-                info.from_statistics(
-                    self.market_depth.get_statistics(),
-                    self.tick_size,
-                    self.lot_size,
-                );
-                info.last_price = self.market_depth.last_price(&source);
-                info.prev_close_price = self.previous_close_price;
-                self.market_depth.get_orderbook_level(
-                    &mut bid_orderbook_info,
-                    &mut ask_orderbook_info,
-                    hook.max_level,
-                );
-                (hook.handler)(
-                    &hook.object,
-                    &info,
-                    &bid_orderbook_info,
-                    &ask_orderbook_info,
-                    &l3order_ref,
-                );
+        let source = OrderSourceType::UserOrder;
+        if let Some(latest) = self.market_views.latest() {
+            if latest.best_bid_tick == self.market_depth.best_bid_tick(&source)
+                && latest.best_ask_tick == self.market_depth.best_ask_tick(&source)
+                && latest.last_tick == self.market_depth.last_tick(&source)
+            {
+                return;
             }
         }
 
-        result
+        let view = self.capture_market_view();
+        self.market_views.push(view, self.md_cutoff());
     }
-    // 获取订单信息，并根据给定的状态过滤订单。
+
+    /// 策略当前可见的行情的截止时间，即当前时间减去行情延迟。
+    fn md_cutoff(&self) -> i64 {
+        adjust_timestamp_milliseconds_i64(self.timestamp, -self.md_delay).unwrap_or(self.timestamp)
+    }
+
+    /// 返回延迟后策略可见的行情快照，行情中断期间返回中断前的快照。
     ///
-    /// 如果 `filter` 为空，则返回所有订单；如果 `filter` 不为空，则仅返回符合过滤条件的订单。
+    /// 未设置延迟或尚未采集任何快照时返回 `None`，此时应使用实时行情。
+    /// 延迟截止时间之前没有快照时（设置延迟后的前 `md_delay` 毫秒内）返回最早的快照，
+    /// 即设置延迟时采集的行情。
+    fn delayed_market_view(&self) -> Option<&MarketView> {
+        let frozen = self.outages.iter().find_map(|outage| match &outage.frozen {
+            Some(view) if outage.contains(self.timestamp) => Some(view),
+            _ => None,
+        });
+        if frozen.is_some() {
+            return frozen;
+        }
+        if self.md_delay == 0 {
+            return None;
+        }
+        self.market_views
+            .view_at(self.md_cutoff())
+            .or(self.market_views.oldest())
+    }
+
+    /// 回放时每处理 `interval_events` 条历史逐笔消息向订阅者发布一次状态，参见 `subscribe_state`。
     ///
-    /// # 参数
-    /// - `orders`: 一个可变的 `HashMap`，用于存储返回的订单。键为订单 ID，值为订单引用。
-    /// - `filter`: 一个包含订单状态的 `Vec`，用于过滤订单。如果为空，则返回所有订单。
+    /// 发布不会等待订阅者，订阅者读取过慢时丢弃最早的状态。`interval_events` 为 0 时停止发布。
+    pub fn enable_state_publishing(&mut self, interval_events: usize) {
+        self.publish_every = (interval_events > 0).then_some(interval_events);
+    }
+
+    /// 订阅回放过程中发布的状态。
     ///
-    /// # 备注
-    /// - 订单的状态由 `OrderStatus` 枚举定义。
-    /// - 如果 `filter` 为空，方法会遍历 `self.orders` 中的所有订单并将其插入到 `orders` 中。
-    /// - 如果 `filter` 不为空，方法会根据 `filter` 中的状态来筛选订单，并将符合条件的订单插入到 `orders` 中。
-    pub fn get_orders(
-        &mut self,
-        orders: &mut HashMap<OrderId, OrderRef>,
-        filter: &Vec<OrderStatus>,
-    ) {
-        if filter.is_empty() {
-            for (k, v) in self.orders.as_ref().unwrap().iter() {
-                orders.insert(k.clone(), v.clone());
-            }
-        } else {
-            // 否则，根据 filter 过滤订单
-            for (k, v) in self
+    /// # 返回值
+    /// 返回订阅端，可以发送到其他线程读取。经纪商被丢弃后订阅端读取完剩余的状态即结束。
+    pub fn subscribe_state(&mut self) -> StateReceiver {
+        let (publisher, receiver) = state_channel();
+        self.state_publishers.push(publisher);
+        receiver
+    }
+
+    /// 向所有订阅者发布当前状态，已经丢弃的订阅端不再发布。
+    fn publish_state(&mut self) {
+        self.state_publishers
+            .retain(|publisher| publisher.is_subscribed());
+        if self.state_publishers.is_empty() {
+            return;
+        }
+        self.published_seq += 1;
+        let state = PublishedState {
+            seq: self.published_seq,
+            timestamp: self.timestamp,
+            events_processed: self.events_processed,
+            view: self.capture_market_view(),
+            statistics: *self.market_depth.get_statistics(),
+            open_orders: self
                 .orders
-                .as_ref()
-                .unwrap()
                 .iter()
-                .filter(|&(_, v)| filter.contains(&v.borrow().status))
-            {
-                orders.insert(k.clone(), v.clone());
-            }
+                .flat_map(HashMap::values)
+                .filter(|order_ref| order_ref.borrow().is_open())
+                .count(),
+        };
+        for publisher in self.state_publishers.iter() {
+            publisher.publish(state.clone());
         }
     }
-    /// 获取最近的订单
+
+    /// 返回策略可见的行情视图。
     ///
-    /// 获取从上次调用这个方法到现在的最新变动的订单，并将它们添加到传入的 `orders` 中。它会根据 `dirty_tracker` 中记录的脏订单 ID 来筛选和获取订单。之后，会清空 `dirty_tracker`，以准备下一次的订单更新。
+    /// # 返回值
+    /// 设置了行情延迟时返回延迟后的快照，否则返回当前的实时行情。
+    pub fn market_view(&self) -> MarketView {
+        match self.delayed_market_view() {
+            Some(view) => view.clone(),
+            None => self.capture_market_view(),
+        }
+    }
+
+    /// 返回最佳买入价，`UserOrder` 来源的查询会应用行情延迟。
+    pub fn best_bid(&self, source: &OrderSourceType) -> f64 {
+        match self.delayed_market_view() {
+            Some(view) if source == &OrderSourceType::UserOrder => view.best_bid,
+            _ => self.market_depth.best_bid(source),
+        }
+    }
+
+    /// 返回最佳卖出价，`UserOrder` 来源的查询会应用行情延迟。
+    pub fn best_ask(&self, source: &OrderSourceType) -> f64 {
+        match self.delayed_market_view() {
+            Some(view) if source == &OrderSourceType::UserOrder => view.best_ask,
+            _ => self.market_depth.best_ask(source),
+        }
+    }
+
+    /// 返回最新成交价，`UserOrder` 来源的查询会应用行情延迟。
+    pub fn last_price(&self, source: &OrderSourceType) -> f64 {
+        match self.delayed_market_view() {
+            Some(view) if source == &OrderSourceType::UserOrder => view.last_price,
+            _ => self.market_depth.last_price(source),
+        }
+    }
+
+    /// 返回策略可见的买方档位信息 (价格, 数量, 订单数)。
     ///
     /// # 参数
+    /// - `max_level`: 返回的最大档位数量，设置了行情延迟时最多返回 `MARKET_VIEW_LEVELS` 档。
+    pub fn bid_levels(&self, max_level: usize) -> Vec<(f64, f64, i64)> {
+        match self.delayed_market_view() {
+            Some(view) => view.bid_levels.iter().take(max_level).cloned().collect(),
+            None => {
+                let mut bid_levels = Vec::with_capacity(max_level);
+                let mut ask_levels = Vec::new();
+                self.market_depth
+                    .get_orderbook_level(&mut bid_levels, &mut ask_levels, max_level);
+                bid_levels
+            }
+        }
+    }
+
+    /// 返回策略可见的卖方档位信息 (价格, 数量, 订单数)。
     ///
-    /// * `orders` - 用于存储最新订单的映射，将从 `dirty_tracker` 中记录的订单添加到此映射中。
-    ///
-    /// # 返回
-    ///
-    pub fn get_latest_orders(&mut self, orders: &mut HashMap<OrderId, OrderRef>) {
-        // 遍历脏订单跟踪器中的订单 ID
-        for order_id in self.dirty_tracker.drain(..) {
-            // 从订单映射中获取对应的订单
-            if let Some(order_ref) = self.orders.as_ref().unwrap().get(&order_id) {
-                // 将订单添加到结果映射中
-                orders.insert(order_id, order_ref.clone());
+    /// # 参数
+    /// - `max_level`: 返回的最大档位数量，设置了行情延迟时最多返回 `MARKET_VIEW_LEVELS` 档。
+    pub fn ask_levels(&self, max_level: usize) -> Vec<(f64, f64, i64)> {
+        match self.delayed_market_view() {
+            Some(view) => view.ask_levels.iter().take(max_level).cloned().collect(),
+            None => {
+                let mut bid_levels = Vec::new();
+                let mut ask_levels = Vec::with_capacity(max_level);
+                self.market_depth
+                    .get_orderbook_level(&mut bid_levels, &mut ask_levels, max_level);
+                ask_levels
             }
-            // 如果订单在映射中未找到，则不做任何处理，继续处理下一个订单
         }
+    }
 
-        // 清空脏订单跟踪器
-        self.dirty_tracker.clear();
+    /// 返回策略可见的买卖双方档位，买方在前，与 `bid_levels` 和 `ask_levels` 的结果一致。
+    ///
+    /// # 参数
+    /// - `max_level`: 每一方返回的最大档位数量。
+    pub fn level_views(&self, max_level: usize) -> Vec<LevelView> {
+        LevelView::from_levels(
+            &self.bid_levels(max_level),
+            &self.ask_levels(max_level),
+            self.mode == ExchangeMode::Backtest,
+        )
     }
-    /// 提交一个新的订单到经纪人系统
+
+    /// 开启订单簿重建校验。
     ///
-    /// 该方法接收一个订单引用，并将其提交到经纪人系统。如果订单的 ID 已经存在，则返回一个错误；如果订单 ID 不存在，则将订单添加到订单队列中，并根据订单的时间信息决定其处理方式。
+    /// 回放历史数据时，每当逐笔数据的时间越过某个快照的时间戳，
+    /// 就将重建的订单簿与该快照逐档比较并记录差异。
     ///
     /// # 参数
+    /// - `snapshots`: 交易所记录的 L2 快照。
+    /// - `max_level`: 每一方比较的最大档位数量。
+    pub fn set_validator(&mut self, snapshots: Vec<L2Snapshot>, max_level: usize) {
+        self.validator = Some(ReconstructionValidator::new(snapshots, max_level));
+    }
+
+    /// 返回订单簿重建校验发现的所有差异，未开启校验时返回空切片。
+    pub fn divergences(&self) -> &[Divergence] {
+        match self.validator.as_ref() {
+            Some(validator) => validator.divergences(),
+            None => &[],
+        }
+    }
+
+    /// 设置回测中历史主动订单的成交价与历史成交价不一致时的处理方式，
+    /// 默认为 `TradeRepairPolicy::MatchAsIs`。
+    pub fn set_trade_repair_policy(&mut self, policy: TradeRepairPolicy) {
+        self.trade_repair_policy = policy;
+    }
+
+    /// `TradeRepairPolicy::ForcePrice` 时，历史成交价对应的对手方档位不存在就在该价格补上一笔挂单，
+    /// 数量为历史成交数量，使历史主动订单能以历史成交价成交。
     ///
-    /// * `order_ref` - 包含要提交的订单信息的订单引用。订单引用提供了对订单对象的可变访问。
-    ///
-    /// # 返回
+    /// # 返回值
+    /// 补上的挂单，没有补挂单时返回 `None`。
+    fn synthesize_print_level(
+        &mut self,
+        order_ref: &L3OrderRef,
+        match_vol: i64,
+    ) -> Result<Option<L3OrderRef>, MarketError> {
+        if self.trade_repair_policy != TradeRepairPolicy::ForcePrice || match_vol <= 0 {
+            return Ok(None);
+        }
+        let (side, price_tick, timestamp) = {
+            let order = order_ref.borrow();
+            (order.side, order.price_tick, order.timestamp)
+        };
+        let (opposite, resting_vol) = match side {
+            Side::Buy => (Side::Sell, self.market_depth.ask_vol_at_tick(price_tick)),
+            Side::Sell => (Side::Buy, self.market_depth.bid_vol_at_tick(price_tick)),
+            _ => return Ok(None),
+        };
+        if resting_vol > 0 {
+            return Ok(None);
+        }
+        // 补上的挂单不对应任何历史订单，订单 ID 为 0
+        let synthetic = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            0,
+            opposite,
+            price_tick,
+            match_vol,
+            timestamp,
+            OrderType::L,
+        );
+        self.market_depth.add(synthetic.clone())?;
+        Ok(Some(synthetic))
+    }
+
+    /// 历史主动订单成交后，按 `trade_repair_policy` 处理与历史成交价不一致的最后成交价。
     ///
-    /// 返回一个 `Result<usize, MarketError>`。成功提交订单时，返回订单的队列位置；如果订单 ID 已经存在，则返回 `Err(MarketError::OrderIdExist)`。
+    /// # 参数
+    /// - `order_ref`: 历史主动订单。
+    /// - `match_price`: 历史成交价。
+    /// - `filled`: 本次撮合的成交数量，以手表示。
+    fn repair_trade_print(&mut self, order_ref: &L3OrderRef, match_price: f64, filled: i64) {
+        if filled == 0 || self.trade_repair_policy == TradeRepairPolicy::MatchAsIs {
+            return;
+        }
+        let match_tick = self.price_to_tick(match_price);
+        let actual_tick = self.market_depth.last_tick(&OrderSourceType::LocalOrder);
+        if actual_tick == match_tick {
+            return;
+        }
+        match self.trade_repair_policy {
+            TradeRepairPolicy::ForcePrice => self.market_depth.set_last_tick(match_tick),
+            TradeRepairPolicy::Flag => {
+                let order = order_ref.borrow();
+                let qty = self.vol_to_qty(filled);
+                let divergence = Divergence {
+                    timestamp: order.timestamp,
+                    side: order.side,
+                    level: 0,
+                    expected: Some((self.tick_to_price(match_tick), qty)),
+                    actual: Some((self.tick_to_price(actual_tick), qty)),
+                    kind: DivergenceKind::TradePrint {
+                        seq: order.seq,
+                        order_id: order.order_id,
+                    },
+                };
+                // 没有开启重建校验时同样记录，校验器中没有待比较的快照
+                self.validator
+                    .get_or_insert_with(ReconstructionValidator::default)
+                    .record(divergence);
+            }
+            TradeRepairPolicy::MatchAsIs => {}
+        }
+    }
+
+    /// 校验时间戳早于 `time_point` 的快照，`inclusive` 为 `true` 时包含等于 `time_point` 的快照。
+    fn validate_reconstruction(&mut self, time_point: i64, inclusive: bool) {
+        let validator = match self.validator.as_mut() {
+            Some(validator) => validator,
+            None => return,
+        };
+
+        while let Some(timestamp) = validator.next_timestamp() {
+            if timestamp > time_point || (timestamp == time_point && !inclusive) {
+                break;
+            }
+            let snapshot = validator.pop_snapshot().unwrap();
+            let mut bid_levels = Vec::with_capacity(validator.max_level());
+            let mut ask_levels = Vec::with_capacity(validator.max_level());
+            self.market_depth.get_orderbook_level(
+                &mut bid_levels,
+                &mut ask_levels,
+                validator.max_level(),
+            );
+            let levels = LevelView::from_levels(
+                &bid_levels,
+                &ask_levels,
+                self.mode == ExchangeMode::Backtest,
+            );
+            let count = validator.verify(&snapshot, &levels);
+            if count > 0 {
+                info!(
+                    "orderbook reconstruction diverged at {}: {:?}",
+                    snapshot.timestamp,
+                    &validator.divergences()[validator.divergences().len() - count..]
+                );
+            }
+        }
+    }
+
+    /// 返回所有用户订单。
+    ///
+    /// 需要修改订单表时使用 `orders_mut`，它会在订单表缺失时重新创建。
     ///
     /// # 错误
+    /// `orders` 被置为 `None` 时返回 `MarketError::OrdersNotInitialized`，调用 `init` 后重新创建。
+    pub fn orders(&self) -> Result<&HashMap<OrderId, OrderRef>, MarketError> {
+        self.orders
+            .as_ref()
+            .ok_or(MarketError::OrdersNotInitialized)
+    }
+
+    /// 返回所有用户订单的可变引用，订单表缺失时先创建空表。
+    fn orders_mut(&mut self) -> &mut HashMap<OrderId, OrderRef> {
+        self.orders.get_or_insert_with(HashMap::new)
+    }
+
+    /// 生成并返回下一个序列号。
+    /// 每次调用时，最新的序列号递增1。
     ///
-    /// 可能会遇到的错误包括：
+    /// # 返回值
     ///
-    /// * `MarketError::OrderIdExist` - 如果订单 ID 已经存在于订单映射中。
-    pub fn submit_order(&mut self, order_ref: OrderRef) -> Result<usize, MarketError> {
-        // 检查订单 ID 是否已存在
-        match self
-            .orders
+    /// 返回最新的序列号
+    pub fn generate_seq_number(&mut self) -> i64 {
+        self.latest_seq_number += 1;
+        self.latest_seq_number
+    }
+
+    /// 设置历史数据，并返回操作是否成功。
+    ///
+    /// # 参数
+    ///
+    /// * `history` - 一个可选的 `DataCollator`，用于设置历史数据。
+    ///
+    /// # 返回值
+    ///
+    /// * `Ok(true)` 如果操作成功。
+    /// * `Err(MarketError)` 如果出现错误。
+    pub fn add_data(&mut self, history: Option<DataCollator>) -> Result<bool, MarketError> {
+        self.history = history;
+        Ok(true)
+    }
+
+    pub fn process_local_order(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let mut filled = 0;
+        let seq = order_ref.borrow().seq;
+
+        let order_time = order_ref.borrow().timestamp;
+        let in_call_auction = self
+            .calendar
+            .is_in_call_auction(order_time, self.market_type)?;
+        let auxiliary_info = order_ref
+            .borrow_mut()
+            .auxiliary_info
             .as_ref()
             .unwrap()
-            .contains_key(&(order_ref.borrow().order_id))
+            .clone();
+
+        if auxiliary_info.cancel_seq != seq
+            && (!auxiliary_info.initial_qty.is_finite() || auxiliary_info.initial_qty <= 0.0)
         {
-            true => return Err(MarketError::OrderIdExist),
-            false => self
-                .orders
-                .as_mut()
-                .unwrap()
-                .insert(order_ref.borrow().order_id.clone(), order_ref.clone()),
-        };
+            return Err(MarketError::InvalidOrderRequest);
+        }
 
-        let mut order_mut = RefCell::borrow_mut(&order_ref);
+        let match_vol = self.qty_to_vol(auxiliary_info.match_qty);
+        let orderbook_vol = self.qty_to_vol(auxiliary_info.orderbook_qty);
+        let initial_vol = self.qty_to_vol(auxiliary_info.initial_qty);
 
-        order_mut.price_tick = (order_mut.price / self.tick_size).round() as i64;
-        // 根据订单的本地时间处理订单
-        if order_mut.local_time > self.timestamp {
-            // 订单在未来时间点处理
-            self.waiting_orders
-                .push_back((order_mut.local_time, order_ref.clone()));
+        if self.mode == ExchangeMode::Live {
+            // 集合竞价期间的订单只挂单，等待竞价撮合
+            let price_tick = if in_call_auction {
+                self.price_to_tick(auxiliary_info.initial_price)
+            } else if match_vol > 0 {
+                self.price_to_tick(auxiliary_info.match_price)
+            } else if orderbook_vol > 0 {
+                self.price_to_tick(auxiliary_info.orderbook_price)
+            } else {
+                self.price_to_tick(auxiliary_info.initial_price)
+            };
+            let mut order = order_ref.borrow_mut();
+            order.price_tick = price_tick;
+            order.vol = initial_vol;
+            order.vol_shadow = order.vol;
+            drop(order);
+            if !in_call_auction {
+                filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+            }
+            if filled != initial_vol {
+                self.market_depth.add(order_ref.clone())?;
+            }
         } else {
-            // 订单立即处理
-            order_mut.seq = self.generate_seq_number();
-            self.pending_orders.push_back(order_ref.clone());
+            // print!(" -- order seq = {seq} , {order_ref:?} --\n");
+            if auxiliary_info.cancel_seq == seq {
+                // print!(
+                //     "== before cancel {:?}\n",
+                //     self.market_depth.get_bid_level(1)
+                // );
+                // print!(
+                //     "== before cancel {:?}\n",
+                //     self.market_depth.get_ask_level(1)
+                // );
+
+                // 撤单数量小于剩余数量时只撤销部分，订单保留原有的排队位置
+                let cancel_vol = self.qty_to_vol(auxiliary_info.cancel_qty);
+                if cancel_vol > 0 && cancel_vol < order_ref.borrow().vol {
+                    let _ = self
+                        .market_depth
+                        .reduce_order_from_ref(order_ref.clone(), cancel_vol);
+                } else {
+                    let _ = self.cancel_order_from_ref(order_ref.clone());
+                }
+                // print!("== after cancel {:?}\n", self.market_depth.get_bid_level(1));
+                // print!("== after cancel {:?}\n", self.market_depth.get_ask_level(1));
+            } else {
+                if in_call_auction {
+                    let mut order = order_ref.borrow_mut();
+                    order.price_tick = self.price_to_tick(auxiliary_info.initial_price);
+                    order.vol = initial_vol;
+                    order.vol_shadow = order.vol;
+                    drop(order);
+                    let _ = self.market_depth.add(order_ref.clone())?;
+                } else {
+                    if match_vol > 0 {
+                        // print!("== before match {:?}\n", self.market_depth.get_bid_level(1));
+                        // print!("== before match {:?}\n", self.market_depth.get_ask_level(1));
+                        let mut order = order_ref.borrow_mut();
+                        order.price_tick = self.price_to_tick(auxiliary_info.match_price);
+                        order.vol = initial_vol;
+                        order.vol_shadow = order.vol;
+                        drop(order);
+                        let synthetic = self.synthesize_print_level(&order_ref, match_vol)?;
+                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+                        // 更优的档位已经满足部分成交数量时，补上的挂单有剩余，撤销以免留在订单簿中
+                        if let Some(synthetic) = synthetic.filter(|order| order.borrow().vol > 0) {
+                            self.market_depth.cancel_order_from_ref(synthetic)?;
+                        }
+                        self.repair_trade_print(&order_ref, auxiliary_info.match_price, filled);
+
+                        if orderbook_vol > 0 && order_ref.borrow().vol > 0 {
+                            order_ref.borrow_mut().price_tick =
+                                self.price_to_tick(auxiliary_info.orderbook_price);
+
+                            let _ = self.market_depth.add(order_ref.clone())?;
+                            if self.residual_priority == ResidualPriority::Keep {
+                                self.market_depth.move_to_front(order_ref.clone())?;
+                            }
+                        }
+
+                        // if filled != match_vol {
+                        //     print!(" ====== filled {filled} shoud be equel to match_vol {match_vol} ======\n");
+                        // }
+                        // print!("== after match  {:?}\n", self.market_depth.get_bid_level(1));
+                        // print!("== after match  {:?}\n", self.market_depth.get_ask_level(1));
+                    } else if orderbook_vol > 0 {
+                        //尝试去匹配用户订单，因为没有历史成交不代表不可能和用户的订单成交
+                        // print!(
+                        //     ">> before orderbook {:?}\n",
+                        //     self.market_depth.get_bid_level(1)
+                        // );
+                        // print!(
+                        //     ">> befeor orderbook  {:?}\n",
+                        //     self.market_depth.get_ask_level(1)
+                        // );
+                        let mut order = order_ref.borrow_mut();
+                        order.price_tick = self.price_to_tick(auxiliary_info.orderbook_price);
+                        order.vol = initial_vol;
+                        order.vol_shadow = order.vol;
+                        drop(order);
+                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+                        if order_ref.borrow().vol > 0 {
+                            let _ = self.market_depth.add(order_ref.clone())?;
+                        }
+                        // if filled > 0 {
+                        //     print!("----- orderbook filled {filled}\n");
+                        // }
+                        // print!(
+                        //     ">> after orderbook {:?}\n",
+                        //     self.market_depth.get_bid_level(1)
+                        // );
+                        // print!(
+                        //     ">> after orderbook  {:?}\n",
+                        //     self.market_depth.get_ask_level(1)
+                        // );
+                    } else {
+                        let mut order = order_ref.borrow_mut();
+                        // print!("++ before other {:?}\n", self.market_depth.get_bid_level(1));
+                        // print!("++ before other {:?}\n", self.market_depth.get_ask_level(1));
+                        order.price_tick = self.price_to_tick(auxiliary_info.initial_price);
+                        order.vol = self.qty_to_vol(auxiliary_info.initial_qty);
+                        order.vol_shadow = order.vol;
+                        drop(order);
+                        filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+                        if order_ref.borrow().vol > 0 {
+                            let _ = self.market_depth.add(order_ref.clone())?;
+                        }
+                        // if filled > 0 {
+                        //     print!("----- other filled {filled}\n");
+                        // }
+                        // print!("++ after other {:?}\n", self.market_depth.get_bid_level(1));
+                        // print!("++ after other {:?}\n", self.market_depth.get_ask_level(1));
+                    }
+                }
+            }
         }
-        // 计算并返回订单在队列中的位置
-        let queue_position: usize = self.pending_orders.len() + self.waiting_orders.len();
-        Ok(queue_position)
+
+        Ok(filled)
     }
-    /// 模拟时间的推移，并处理所有到期的订单
+
+    /// 根据订单方向和来源获取最佳价格。
     ///
     /// # 参数
+    /// - `side`: 订单的买卖方向。
+    /// - `source`: 订单的来源。
     ///
-    /// * `duration` - 模拟的时间段，以时间单位表示。时间推移将基于此时间段来更新当前时间,单位为毫秒。
-    ///
-    /// # 返回
-    ///
-    /// 返回一个 `Result<bool, MarketError>`。如果成功处理了所有订单并推进了时间，则返回 `Ok(true)`；如果时间点达到历史记录的结束，则返回 `Ok(true)`；如果时间点未到达历史记录的结束，则返回 `Ok(false)`。
+    /// # 返回值
+    /// - `Option<i64>`: 最佳价格，该方向没有挂单时返回 `None`，不会返回 `INVALID_MIN`、`INVALID_MAX` 等哨兵值。
+    fn get_best_tick(&self, side: &Side, source: &OrderSourceType) -> Option<i64> {
+        let best_tick = match side {
+            Side::Buy => self.market_depth.best_bid_tick(source),
+            _ => self.market_depth.best_ask_tick(source),
+        };
+        match best_tick {
+            INVALID_MIN | INVALID_MAX => None,
+            best_tick => Some(best_tick),
+        }
+    }
+
+    /// 订单簿在涨跌停价上单边时，将缺少对手方流动性一侧的市价订单挂在涨跌停价上。
     ///
-    /// # 错误
+    /// 仅对剩余部分可以挂单的市价订单（`N`、`B`、`C`）生效，`M`、`D` 等即时成交的订单仍按原规则处理。
+    /// 例如涨停时只有买单没有卖单，市价买单不会以无效价格撮合，而是以涨停价排队。
     ///
-    /// 如果处理订单时发生错误（例如匹配订单失败），方法会返回相应的 `MarketError`。
-    pub fn elapse(self: &'_ mut Self, duration: i64) -> Result<i64, MarketError> {
-        let time_point = adjust_timestamp_milliseconds_i64(self.timestamp, duration)?;
-        let mut total_filled: i64 = 0;
-
-        //处理pending队列
-        while !self.pending_orders.is_empty() {
-            let order_ref = self.pending_orders.pop_front().unwrap();
-            if order_ref.borrow().status == OrderStatus::Canceled {
-                continue;
-            }
-            let mut order = order_ref.borrow_mut();
-            order.exch_time = self.timestamp;
-            let l3order_ref = order.to_l3order_ref(self.tick_size, self.lot_size);
-            let fillid = self.process_order(l3order_ref)?;
-            if fillid > 0 {
-                order.filled_qty = fillid as f64 * self.lot_size;
-                self.dirty_tracker.push(order.order_id);
-                order.update();
-            }
-            total_filled += fillid;
+    /// # 返回值
+    /// - `Ok(true)`: 订单已挂在涨跌停价上。
+    /// - `Ok(false)`: 不满足条件，订单应按原规则处理。
+    fn rest_at_price_limit(&mut self, order_ref: &L3OrderRef) -> Result<bool, MarketError> {
+        if self.price_limit_policy != PriceLimitPolicy::RestAtLimit {
+            return Ok(false);
+        }
+        let (side, order_type, source) = {
+            let order = order_ref.borrow();
+            (order.side, order.order_type, order.source)
+        };
+        if !matches!(order_type, OrderType::N | OrderType::B | OrderType::C) {
+            return Ok(false);
         }
 
-        self.waiting_orders.make_contiguous().sort();
-        //处理waiting队列
-        while !self.waiting_orders.is_empty() {
-            let timestamp = self.waiting_orders[0].0;
-            if timestamp > time_point {
-                break;
+        let limit_tick = match side {
+            Side::Buy if self.market_depth.best_ask_tick(&source) == INVALID_MAX => {
+                self.limit_up_tick()
             }
-            let (_, order_ref) = self.waiting_orders.pop_front().unwrap();
-            if order_ref.borrow().status == OrderStatus::Canceled {
-                continue;
+            Side::Sell if self.market_depth.best_bid_tick(&source) == INVALID_MIN => {
+                self.limit_down_tick()
             }
-            let _ = self.goto(timestamp.clone());
-            let mut order = order_ref.borrow_mut();
-            let vol = (order.qty / self.lot_size).round() as i64;
-            let l3order_ref = order.to_l3order_ref(self.tick_size, self.lot_size);
-            order.seq = self.generate_seq_number();
-            let fillid = self.process_order(l3order_ref.clone())?;
-            order.exch_time = self.timestamp;
-            if fillid > 0 {
-                order.filled_qty = fillid as f64 * self.lot_size;
-                self.dirty_tracker.push(order.order_id);
-                order.update();
+            _ => None,
+        };
+        match limit_tick {
+            Some(limit_tick) if self.get_best_tick(&side, &source) == Some(limit_tick) => {
+                order_ref.borrow_mut().price_tick = limit_tick;
+                self.market_depth.add(order_ref.clone())?;
+                Ok(true)
             }
-            total_filled += fillid;
+            _ => Ok(false),
         }
-
-        //有可能处理完了waiting队列后，时间还需要继续向前流逝
-        let _ = self.goto(time_point);
-        Ok(total_filled)
     }
 
-    /// 同步订单信息，将市场深度中的订单状态与本地订单进行同步。
-    /// 如果订单被标记为已处理或取消，将从市场深度中移除并更新本地订单状态。
-    pub fn sync_order_info(&mut self) {
-        // 获取市场深度中所有订单的信息
-        let l30orders = self.market_depth.orders_mut();
-
-        // 用于追踪需要从市场深度中移除的订单 ID
-        let mut remove_tracker: Vec<OrderId> = Vec::with_capacity(100);
+    /// 处理 `OrderType::L` 订单（普通限价订单）。
+    ///
+    /// 使用无限价格档位尝试匹配订单，如果未完全成交，将订单添加到市场深度中。
+    ///
+    /// # 参数
+    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    ///
+    /// # 返回值
+    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
+    pub fn match_order_l(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+        if order_ref.borrow().vol > 0 {
+            let best_tick = self.market_depth.add(order_ref)?;
+        }
 
-        for (order_id, l30order) in l30orders.iter_mut() {
-            let mut order = self
-                .orders
-                .as_mut()
-                .unwrap()
-                .get(order_id)
-                .unwrap()
-                .borrow_mut();
-            // print!("{l30order:?}\n");
-            if l30order.borrow().dirty == true {
-                // 同步订单的位置信息和数量
-                order.price = l30order.borrow().price_tick as f64 * self.tick_size;
-                order.queue = l30order.borrow().total_vol_before as f64 * self.lot_size;
-                order.left_qty = l30order.borrow().vol as f64 * self.lot_size;
-                order.filled_qty = order.qty - order.left_qty;
-                order.exch_time = self.timestamp;
-                // 根据订单的成交量和方向更新状态
-                if l30order.borrow().vol == 0 {
-                    remove_tracker.push(order_id.clone());
-                    order.status = OrderStatus::Filled;
-                } else if l30order.borrow().side == Side::None {
-                    remove_tracker.push(order_id.clone());
-                    order.status = OrderStatus::Canceled;
-                }
+        Ok(filled)
+    }
 
-                // 将已修改的订单 ID 添加到脏订单追踪器中
-                self.dirty_tracker.push(order_id.clone());
+    /// 处理 `OrderType::M` 订单（最优五档即时成交剩余撤销的市价订单）。
+    ///
+    /// 不限制价格，尝试在市场深度中匹配订单，最多匹配五档价格。如果未完全成交，剩余部分订单将被撤销。
+    ///
+    /// # 参数
+    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    ///
+    /// # 返回值
+    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
+    pub fn match_order_m(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let filled = self.market_depth.match_order_with_bound(
+            order_ref.clone(),
+            5,
+            MatchBound::Unbounded,
+        )?;
+        if order_ref.borrow().vol > 0 {
+            order_ref.borrow_mut().side = Side::None;
+        }
+        Ok(filled)
+    }
+
+    /// 处理 `OrderType::N` 订单（最优五档即时成交剩余转限价的市价订单）。
+    ///
+    /// 不限制价格，尝试在市场深度中匹配订单，最多匹配五档价格。若有剩余部分，将其以上次成交价格加入市场深度。
+    /// 当日还没有成交时以 `reference_price` 挂单，参考价格也未知时剩余部分被撤销。
+    ///
+    /// # 参数
+    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    ///
+    /// # 返回值
+    /// - `Result<i64, MarketError>`: 返回实际成交量，如果操作失败，返回 `Err(MarketError)`。
+    pub fn match_order_n(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let source = order_ref.borrow().source;
+        let filled = self.market_depth.match_order_with_bound(
+            order_ref.clone(),
+            5,
+            MatchBound::Unbounded,
+        )?;
+        if order_ref.borrow().vol > 0 {
+            let last_tick = self.market_depth.last_tick(&source);
+            let price_tick = if last_tick != INVALID_MIN {
+                Some(last_tick)
+            } else {
+                self.reference_price()
+                    .map(|price| self.price_to_tick(price))
+            };
+            match price_tick {
+                Some(price_tick) => {
+                    order_ref.borrow_mut().price_tick = price_tick;
+                    self.market_depth.add(order_ref)?;
+                }
+                None => order_ref.borrow_mut().side = Side::None,
             }
         }
-        // 从市场深度中移除已处理或取消的订单
-        for idx in remove_tracker {
-            l30orders.remove(&idx);
+        Ok(filled)
+    }
+
+    /// 处理 `OrderType::B` 订单（以本方最优价格申报的市价订单）。
+    ///
+    /// 设置订单价格为市场深度中的最佳买价或卖价，并尝试将订单加入市场深度。本方没有挂单时订单被撤销。
+    ///
+    /// # 参数
+    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    ///
+    /// # 返回值
+    /// - `Result<i64, MarketError>`: 操作成功返回 `Ok(0)`，失败返回 `Err(MarketError>`。
+    pub fn match_order_b(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let side = order_ref.borrow().side;
+        let source = order_ref.borrow().source;
+        let vol = order_ref.borrow().vol;
+        let Some(best_tick) = self.get_best_tick(&side, &source) else {
+            order_ref.borrow_mut().side = Side::None;
+            return Ok(0);
+        };
+        order_ref.borrow_mut().price_tick = best_tick;
+
+        if vol > 0 {
+            self.market_depth.add(order_ref.clone())?;
         }
+
+        Ok(0)
     }
 
-    pub fn goto_end_of_day(&mut self) -> Result<bool, MarketError> {
-        self.goto(i64::MAX)
+    /// 处理 `OrderType::C` 订单（以对手方最优价格申报的市价订单）。
+    ///
+    /// 设置订单价格为市场深度中的最佳卖价或买价，并尝试将订单匹配到市场深度中。对手方没有挂单时订单被撤销。
+    ///
+    /// # 参数
+    /// - `order_ref`: 订单的引用，用于获取和修改订单信息。
+    ///
+    /// # 返回值
+    /// - `Result<i64, MarketError>`: 返回实际成交量。
+    pub fn match_order_c(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let side = order_ref.borrow().side;
+        let source = order_ref.borrow().source;
+        let Some(best_tick) = self.get_best_tick(&side.opposite(), &source) else {
+            order_ref.borrow_mut().side = Side::None;
+            return Ok(0);
+        };
+        order_ref.borrow_mut().price_tick = best_tick;
+
+        let filled = self.market_depth.match_order(order_ref.clone(), i64::MAX)?;
+
+        if order_ref.borrow().vol > 0 {
+            self.market_depth.add(order_ref.clone())?;
+        }
+
+        Ok(filled)
     }
-    /// 将时间推进到指定的时间点，并处理该时间点之前的所有订单
+
+    pub fn match_order_d(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let is_fullfilled = self.market_depth.try_match_order_with_bound(
+            order_ref.clone(),
+            i64::MAX,
+            MatchBound::Unbounded,
+        )?;
+
+        if is_fullfilled {
+            let filled = self.market_depth.match_order_with_bound(
+                order_ref.clone(),
+                i64::MAX,
+                MatchBound::Unbounded,
+            )?;
+            Ok(filled)
+        } else {
+            order_ref.borrow_mut().side = Side::None;
+            Ok(0)
+        }
+    }
+
+    /// 处理订单
     ///
-    /// 该方法根据提供的时间点 (`time_point`) 继续模拟市场，并处理所有在该时间点之前的订单。它会从历史数据源中获取订单，并根据订单信息调用相应的处理方法。处理完成后，时间戳会更新到处理过的最后一个订单的时间。
+    /// 该方法根据订单类型 (`OrderType`) 处理传入的订单，并执行相应的操作。根据不同的订单类型，方法会调用不同的匹配函数来处理订单。
     ///
     /// # 参数
     ///
-    /// * `time_point` - 要推进到的时间点，指定模拟的结束时间。时间点必须大于或等于当前时间戳。
+    /// * `order_type` - 订单类型，指定要处理的订单类型。
+    /// * `l3order_ref` - 订单引用，包含要处理的订单的详细信息。
     ///
     /// # 返回
     ///
-    /// 如果成功推进到指定时间点并处理了所有相关订单，返回 `Ok(true)`。如果在处理过程中没有更多的历史数据可供处理，并且时间戳未达到指定时间点，则返回 `Ok(false)`。如果没有历史数据源可用，返回 `Err(MarketError::HistoryIsNone)`。
+    /// 返回成功成交的订单量。处理失败则返回 `Err`。
+    pub fn process_order(&mut self, l3order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let source = l3order_ref.borrow().source;
+        let result;
+        l3order_ref.borrow_mut().timestamp = self.timestamp;
+        // 行情中断前的行情需要在处理窗口内的第一条消息之前采集
+        self.freeze_market_views();
+        if source == OrderSourceType::LocalOrder {
+            result = self.process_local_order(l3order_ref.clone());
+        } else {
+            if self
+                .calendar
+                .is_in_call_auction(self.timestamp, self.market_type)
+                .unwrap_or(false)
+            {
+                let order_type = l3order_ref.borrow().order_type;
+                let phase = self.calendar.phase(self.timestamp, self.market_type)?;
+                if phase.accepts(order_type) {
+                    let _ = self.market_depth.add(l3order_ref.clone());
+                    result = Ok(0);
+                } else {
+                    result = Err(MarketError::OrderTypeUnsupported);
+                }
+            } else {
+                let order_type = l3order_ref.borrow().order_type;
+                let order_id = l3order_ref.borrow().order_id;
+                result = match self.rest_at_price_limit(&l3order_ref) {
+                    // 涨跌停时已挂在涨跌停价上，不产生成交
+                    Ok(true) => Ok(0),
+                    Ok(false) => match order_type {
+                        // 处理普通限价订单
+                        OrderType::L => self.match_order_l(l3order_ref.clone()),
+                        // 处理最优五档即时成交剩余撤销的市价订单
+                        OrderType::M => self.match_order_m(l3order_ref.clone()),
+                        // 处理最优五档即时成交剩余转限价的市价订单
+                        OrderType::N => self.match_order_n(l3order_ref.clone()),
+                        // 处理以本方最优价格申报的市价订单
+                        OrderType::B => self.match_order_b(l3order_ref.clone()),
+                        // 处理以对手方最优价格申报的市价订单
+                        OrderType::C => self.match_order_c(l3order_ref.clone()),
+                        // 处理市价全额成交或撤销订单
+                        OrderType::D => self.match_order_d(l3order_ref.clone()),
+                        // 处理取消委托
+                        OrderType::Cancel => self.cancel_order(order_id),
+                        _ => Err(MarketError::OrderTypeUnsupported),
+                    },
+                    Err(err) => Err(err),
+                };
+            }
+        }
+
+        self.record_market_view();
+        self.market_depth.sample_analytics(self.timestamp);
+        self.run_orderbook_hooks(&source, &l3order_ref);
+
+        result
+    }
+
+    /// 在当前时间调用所有订单簿钩子，不处理任何订单，用于在固定的时间点采样。
+    ///
+    /// 传给钩子的订单是 `OrderType::None` 类型的占位订单，时间戳为当前时间，钩子据此区分采样和逐笔消息。
+    pub fn sample_hooks(&mut self) {
+        let placeholder = L3Order::new_ref(
+            OrderSourceType::Unknown,
+            None,
+            0,
+            Side::None,
+            0,
+            0,
+            self.timestamp,
+            OrderType::None,
+        );
+        self.run_orderbook_hooks(&OrderSourceType::LocalOrder, &placeholder);
+    }
+
+    /// 依次调用订单簿钩子，行情中断期间不调用。
+    fn run_orderbook_hooks(&mut self, source: &OrderSourceType, l3order_ref: &L3OrderRef) {
+        let timing = self.hook_timing;
+        let max_panics = self.max_hook_panics;
+        let mut slow_hooks = Vec::new();
+        let mut failed_hooks = Vec::new();
+        let md_outage = self.in_outage(OutageKind::MarketData, self.timestamp);
+        if let Some(hooks) = self
+            .hooks
+            .get_mut(&HookType::Orderbook)
+            .filter(|_| !md_outage)
+        {
+            for (name, hook) in hooks.iter_mut() {
+                let mut info: StatisticsInfo = StatisticsInfo::new();
+                let mut bid_orderbook_info: Vec<(f64, f64, i64)> =
+                    Vec::with_capacity(hook.max_level);
+                let mut ask_orderbook_info: Vec<(f64, f64, i64)> =
+                    Vec::with_capacity(hook.max_level);
+
+                info.from_statistics(
+                    self.market_depth.get_statistics(),
+                    self.tick_size,
+                    self.lot_size,
+                );
+                info.last_price = self.market_depth.last_price(source);
+                info.prev_close_price = self.previous_close_price;
+                self.market_depth.get_orderbook_level(
+                    &mut bid_orderbook_info,
+                    &mut ask_orderbook_info,
+                    hook.max_level,
+                );
+                let started = timing.then(Instant::now);
+                // 钩子在订单簿的修改全部完成后才调用，panic 不会让经纪人处于中间状态。钩子只能通过
+                // `RefCell` 访问自己的对象，展开时借用随之释放，对象本身是否完整由钩子负责
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    (hook.handler)(
+                        &hook.object,
+                        &info,
+                        &bid_orderbook_info,
+                        &ask_orderbook_info,
+                        l3order_ref,
+                    )
+                }));
+                if result.is_err() {
+                    hook.panics += 1;
+                    warn!("hook {name} panicked ({}/{max_panics})", hook.panics);
+                    if hook.panics >= max_panics {
+                        failed_hooks.push(name.clone());
+                    }
+                    continue;
+                }
+                if let Some(started) = started {
+                    let elapsed = started.elapsed();
+                    hook.stats.record(elapsed);
+                    if let Some(watchdog) = self.hook_watchdog {
+                        if elapsed > watchdog.threshold {
+                            hook.slow_strikes += 1;
+                        } else {
+                            hook.slow_strikes = 0;
+                        }
+                        if hook.slow_strikes == watchdog.strikes {
+                            warn!(
+                                "hook {name} exceeded {:?} for {} consecutive events, max {:?}",
+                                watchdog.threshold, watchdog.strikes, hook.stats.max
+                            );
+                            if hook.slow_policy == SlowHookPolicy::Deregister {
+                                slow_hooks.push(name.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // 推进时间期间不能通过 `remove_hook` 注销钩子，这里直接移除
+        if let Some(hooks) = self.hooks.get_mut(&HookType::Orderbook) {
+            for name in slow_hooks {
+                warn!("hook {name} deregistered by the slow hook watchdog");
+                hooks.remove(&name);
+            }
+            for name in failed_hooks {
+                warn!("hook {name} deregistered after panicking");
+                hooks.remove(&name);
+            }
+        }
+    }
+    // 获取订单信息，并根据给定的状态过滤订单。
+    ///
+    /// 如果 `filter` 为空，则返回所有订单；如果 `filter` 不为空，则仅返回符合过滤条件的订单。
+    ///
+    /// # 参数
+    /// - `orders`: 一个可变的 `HashMap`，用于存储返回的订单。键为订单 ID，值为订单引用。
+    /// - `filter`: 一个包含订单状态的 `Vec`，用于过滤订单。如果为空，则返回所有订单。
+    ///
+    /// # 备注
+    /// - 订单的状态由 `OrderStatus` 枚举定义。
+    /// - 如果 `filter` 为空，方法会遍历 `self.orders` 中的所有订单并将其插入到 `orders` 中。
+    /// - 如果 `filter` 不为空，方法会根据 `filter` 中的状态来筛选订单，并将符合条件的订单插入到 `orders` 中。
+    pub fn get_orders(
+        &mut self,
+        orders: &mut HashMap<OrderId, OrderRef>,
+        filter: &Vec<OrderStatus>,
+    ) {
+        self.collect_orders(orders, |status| {
+            filter.is_empty() || filter.contains(status)
+        });
+    }
+
+    /// 将状态满足 `predicate` 的订单加入 `orders`。
+    fn collect_orders(
+        &self,
+        orders: &mut HashMap<OrderId, OrderRef>,
+        predicate: impl Fn(&OrderStatus) -> bool,
+    ) {
+        for (k, v) in self
+            .orders
+            .iter()
+            .flatten()
+            .filter(|&(_, v)| predicate(&v.borrow().status))
+        {
+            orders.insert(k.clone(), v.clone());
+        }
+    }
+    /// 访问订单上的自定义数据。
+    ///
+    /// 回调只能修改订单的 `user_data`，撮合引擎使用的字段只能只读访问。
+    ///
+    /// # 参数
+    /// - `order_id`: 订单 ID。
+    /// - `f`: 访问订单数据的回调。
+    ///
+    /// # 返回值
+    /// 返回回调的结果。
     ///
     /// # 错误
+    /// 订单不存在时返回 `MarketError::OrderNotFound`。
+    pub fn with_order_mut<F, R>(&mut self, order_id: OrderId, f: F) -> Result<R, MarketError>
+    where
+        F: FnOnce(&mut OrderUserData) -> R,
+    {
+        let order_ref = self
+            .orders_mut()
+            .get(&order_id)
+            .ok_or(MarketError::OrderNotFound)?;
+        let mut order = order_ref.borrow_mut();
+        Ok(f(&mut OrderUserData::new(&mut order)))
+    }
+
+    /// 获取所有仍在交易中的订单，即尚未得到确认、新订单和部分成交的订单，见 `OrderStatus::is_open`。
     ///
-    /// 返回以下错误：
+    /// # 参数
+    /// - `orders`: 一个可变的 `HashMap`，用于存储返回的订单。键为订单 ID，值为订单引用。
+    pub fn get_open_orders(&self, orders: &mut HashMap<OrderId, OrderRef>) {
+        self.collect_orders(orders, OrderStatus::is_open);
+    }
+
+    /// 获取所有已终止的订单，即已成交、已撤销、已过期或被拒绝的订单，见 `OrderStatus::is_terminal`。
     ///
-    /// * `MarketError::HistoryIsNone` - 如果历史数据源未设置，将返回此错误。
+    /// # 参数
+    /// - `orders`: 一个可变的 `HashMap`，用于存储返回的订单。键为订单 ID，值为订单引用。
+    pub fn get_terminal_orders(&self, orders: &mut HashMap<OrderId, OrderRef>) {
+        self.collect_orders(orders, OrderStatus::is_terminal);
+    }
+    /// 获取最近的订单
     ///
-    /// # 示例
+    /// 获取从上次调用这个方法到现在的最新变动的订单，并将它们添加到传入的 `orders` 中。它会根据 `dirty_tracker` 中记录的脏订单 ID 来筛选和获取订单。之后，会清空 `dirty_tracker`，以准备下一次的订单更新。
     ///
-    /// ```rust
-    /// let mut broker = Broker::new(...);
-    /// let result = broker.goto(1627845600);
-    /// match result {
-    ///     Ok(true) => println!("成功推进到指定时间点并处理所有订单"),
-    ///     Ok(false) => println!("未能推进到指定时间点，可能是因为历史数据已用尽"),
-    ///     Err(e) => println!("发生错误: {:?}", e),
-    /// }
-    /// ```
+    /// # 参数
     ///
-    /// # 说明
+    /// * `orders` - 用于存储最新订单的映射，将从 `dirty_tracker` 中记录的订单添加到此映射中。
     ///
-    /// - 方法首先检查历史数据源是否存在。如果不存在，返回错误。
-    /// - 然后，它会遍历历史数据中的订单，直到处理时间戳达到 `time_point`。
-    /// - 对于每个订单，根据订单的时间和类型，调用 `process_order` 方法来处理订单。
-    /// - 时间戳会更新到当前处理的订单的时间。
-    /// - 如果历史数据源已用尽且时间戳未达到 `time_point`，则返回 `Ok(false)`。
-    pub fn goto(&mut self, time_point: i64) -> Result<bool, MarketError> {
-        info!("goto time_point {time_point}");
-        let mut end_of_history = false;
-        if self.history.is_none() {
-            return Err(MarketError::HistoryIsNone);
-        }
+    /// # 返回
+    ///
+    pub fn get_latest_orders(&mut self, orders: &mut HashMap<OrderId, OrderRef>) {
+        // 遍历脏订单跟踪器中的订单 ID
+        for order_id in self.dirty_tracker.drain(..) {
+            // 从订单映射中获取对应的订单
+            if let Some(order_ref) = self
+                .orders
+                .as_ref()
+                .and_then(|orders| orders.get(&order_id))
+            {
+                // 将订单添加到结果映射中
+                orders.insert(order_id, order_ref.clone());
+            }
+            // 如果订单在映射中未找到，则不做任何处理，继续处理下一个订单
+        }
+
+        // 清空脏订单跟踪器
+        self.dirty_tracker.clear();
+    }
+    /// 按 `submit_order` 的规则检查订单能否被接受，不修改经纪人和订单的状态。
+    ///
+    /// 策略或风控可以在提交前调用，检查通过的订单提交时只可能因为状态在两次调用之间发生变化而被拒绝。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidOrderRequest`: 价格或数量不合法，或数量不符合申报规则。
+    /// - `MarketError::PriceNotOnTick`: 限价订单的价格不是最小价格变动单位的整数倍。
+    /// - `MarketError::PriceOutOfLimit`: 限价订单的价格超出涨跌停价。
+    /// - `MarketError::OrderTypeUnsupported`: 当前交易时段不接受该订单类型。
+    /// - `MarketError::OrderIdExist`: 订单 ID 已存在。
+    /// - `MarketError::OrderQueueFull`: 订单将进入的队列已满。
+    /// - `MarketError::ParentOrderNotExist` / `MarketError::ParentQtyExceeded`: 子单不满足母单的约束。
+    pub fn validate_order(&self, order_ref: &OrderRef) -> Result<(), MarketError> {
+        let order = order_ref.borrow();
+        validate_price_qty(order.price, order.qty, order.order_type)?;
+        if order.order_type != OrderType::Cancel {
+            if !self.lot_rule.is_valid_qty(order.qty) {
+                return Err(MarketError::InvalidOrderRequest);
+            }
+            if !order.order_type.is_market_order() {
+                self.validate_limit_price(order.price)?;
+            }
+        }
+        // 订单在本地时间和当前时间中较晚的时刻进入交易所，集合竞价阶段不接受市价订单
+        let activation_time = cmp::max(order.local_time, self.timestamp);
+        let phase = self.calendar.phase(activation_time, self.market_type)?;
+        if !phase.accepts(order.order_type) {
+            return Err(MarketError::OrderTypeUnsupported);
+        }
+        // 检查订单 ID 是否已存在
+        if self
+            .orders
+            .as_ref()
+            .is_some_and(|orders| orders.contains_key(&order.order_id))
+        {
+            return Err(MarketError::OrderIdExist);
+        }
+        // 订单将进入的队列已满时拒绝，调用方可以在队列消化后重新提交
+        // 有确认延迟时订单先等待交易所确认
+        let delayed = order.local_time > self.timestamp || self.ack_latency > 0;
+        let (queue_len, capacity) = if delayed {
+            (self.waiting_orders.len(), self.max_waiting_orders)
+        } else {
+            (self.pending_orders.len(), self.max_pending_orders)
+        };
+        if let Some(capacity) = capacity {
+            if queue_len >= capacity {
+                return Err(MarketError::OrderQueueFull { capacity: capacity });
+            }
+        }
+        if let Some(parent_id) = order.parent_id {
+            self.check_child(parent_id, order.side, order.qty)?;
+        }
+        Ok(())
+    }
+
+    /// 检查限价订单的价格是否为最小价格变动单位的整数倍，并且不超出涨跌停价。
+    ///
+    /// # 错误
+    /// - `MarketError::PriceNotOnTick`: 价格不是最小价格变动单位的整数倍。
+    /// - `MarketError::PriceOutOfLimit`: 价格超出涨跌停价。
+    fn validate_limit_price(&self, price: f64) -> Result<(), MarketError> {
+        let price_tick = self.price_to_tick(price);
+        if (price / self.tick_size - price_tick as f64).abs() > 1e-6 {
+            return Err(MarketError::PriceNotOnTick);
+        }
+        let above = self.limit_up_tick().is_some_and(|limit| price_tick > limit);
+        let below = self
+            .limit_down_tick()
+            .is_some_and(|limit| price_tick < limit);
+        if above || below {
+            return Err(MarketError::PriceOutOfLimit);
+        }
+        Ok(())
+    }
+
+    /// 提交一个新的订单到经纪人系统
+    ///
+    /// 该方法接收一个订单引用，并将其提交到经纪人系统。如果订单的 ID 已经存在，则返回一个错误；如果订单 ID 不存在，则将订单添加到订单队列中，并根据订单的时间信息决定其处理方式。
+    ///
+    /// # 参数
+    ///
+    /// * `order_ref` - 包含要提交的订单信息的订单引用。订单引用提供了对订单对象的可变访问。
+    ///
+    /// # 返回
+    ///
+    /// 返回一个 `Result<usize, MarketError>`。成功提交订单时，返回订单的队列位置；如果订单 ID 已经存在，则返回 `Err(MarketError::OrderIdExist)`。
+    ///
+    /// # 错误
+    ///
+    /// 可能会遇到的错误包括：
+    ///
+    /// * `MarketError::OrderIdExist` - 如果订单 ID 已经存在于订单映射中。
+    /// * `MarketError::InvalidOrderRequest` - 如果订单的价格或数量不合法，或子单与母单方向不一致、母单已撤销。
+    /// * `MarketError::ParentOrderNotExist` - 如果子单所属的母单没有登记。
+    /// * `MarketError::ParentQtyExceeded` - 如果子单数量超过母单的剩余数量。
+    /// * `MarketError::OrderQueueFull` - 如果订单将进入的队列已达到 `set_queue_limits` 设置的容量上限。
+    /// * `MarketError::PriceNotOnTick` / `MarketError::PriceOutOfLimit` - 如果限价订单的价格不合法，参见 `validate_order`。
+    /// * `MarketError::GatewayUnavailable` - 如果订单到达时处于报单通道中断窗口内，参见 `schedule_outage`。
+    ///
+    /// 被拒绝的订单汇总在 `rejected_report` 中，计入 `disposition_report`。
+    /// 订单 ID 重复的请求不计入，该 ID 已经对应另一笔订单。
+    pub fn submit_order(&mut self, order_ref: OrderRef) -> Result<usize, MarketError> {
+        let result = self.accept_order(order_ref.clone());
+        match &result {
+            Err(MarketError::OrderIdExist) | Ok(_) => {}
+            Err(err) => {
+                let order = order_ref.borrow();
+                // 交易时段不接受的订单被交易所撤销，其余按拒绝原因分类
+                let disposition = if order.status == OrderStatus::Canceled {
+                    Disposition::Canceled
+                } else if err.category() == ErrorCategory::RiskRejected {
+                    Disposition::RiskRejected
+                } else {
+                    Disposition::Rejected
+                };
+                self.rejected_report.record(&order, disposition);
+            }
+        }
+        result
+    }
+
+    fn accept_order(&mut self, order_ref: OrderRef) -> Result<usize, MarketError> {
+        let arrival_time = cmp::max(order_ref.borrow().local_time, self.timestamp);
+        if self.in_outage(OutageKind::OrderEntry, arrival_time) {
+            return Err(MarketError::GatewayUnavailable);
+        }
+        if let Err(err) = self.validate_order(&order_ref) {
+            // 当前交易时段不接受的订单类型视为被交易所撤销
+            if err == MarketError::OrderTypeUnsupported {
+                order_ref.borrow_mut().status = OrderStatus::Canceled;
+            }
+            return Err(err);
+        }
+        // 有确认延迟时，订单在得到确认之后才进入订单簿
+        let activation_time = if self.ack_latency > 0 {
+            adjust_timestamp_milliseconds_i64(arrival_time, self.ack_latency)?
+        } else {
+            order_ref.borrow().local_time
+        };
+        // 子单需要占用母单的剩余数量
+        let parent_id = order_ref.borrow().parent_id;
+        if let Some(parent_id) = parent_id {
+            let order = order_ref.borrow();
+            self.allocate_child(parent_id, order.order_id, order.side, order.qty)?;
+        }
+        self.orders_mut()
+            .insert(order_ref.borrow().order_id.clone(), order_ref.clone());
+
+        let mut order_mut = RefCell::borrow_mut(&order_ref);
+
+        order_mut.price_tick = self.price_to_tick(order_mut.price);
+        // 序列号在提交时确定，同一时间戳按 `SameTimePriority::BySeq` 排序时与历史订单比较
+        order_mut.seq = self.generate_seq_number();
+        if self.ack_latency > 0 {
+            order_mut.status = OrderStatus::PendingAck;
+        }
+        // 根据订单的生效时间处理订单
+        if activation_time > self.timestamp {
+            // 订单在未来时间点处理
+            self.waiting_orders
+                .push_back((activation_time, order_ref.clone()));
+        } else {
+            // 订单立即处理
+            self.pending_orders.push_back(order_ref.clone());
+        }
+        // 计算并返回订单在队列中的位置
+        let queue_position: usize = self.pending_orders.len() + self.waiting_orders.len();
+        Ok(queue_position)
+    }
+
+    /// 撤回一个已提交但还没有进入订单簿的订单，订单从订单表和等待队列中移除，不留下撤单记录。
+    ///
+    /// 用于关联订单中其他腿提交失败时回滚已提交的腿。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在或已经离开等待队列。
+    pub fn withdraw_order(&mut self, order_id: OrderId) -> Result<OrderRef, MarketError> {
+        let queued = self
+            .pending_orders
+            .iter()
+            .any(|order| order.borrow().order_id == order_id)
+            || self
+                .waiting_orders
+                .iter()
+                .any(|(_, order)| order.borrow().order_id == order_id);
+        if !queued {
+            return Err(MarketError::OrderNotFound);
+        }
+        self.pending_orders
+            .retain(|order| order.borrow().order_id != order_id);
+        self.waiting_orders
+            .retain(|(_, order)| order.borrow().order_id != order_id);
+        self.orders_mut()
+            .remove(&order_id)
+            .ok_or(MarketError::OrderNotFound)
+    }
+    /// 登记一个母单，之后可以提交 `parent_id` 指向它的子单。
+    ///
+    /// # 参数
+    /// - `parent_id`: 母单 ID。
+    /// - `qty`: 母单的总数量，所有子单数量之和不能超过该值。
+    /// - `side`: 母单的买卖方向。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderIdExist`: 母单 ID 已经登记。
+    /// - `MarketError::InvalidOrderRequest`: 数量不是大于 0 的有限值，或方向不是买卖之一。
+    pub fn register_parent(
+        &mut self,
+        parent_id: OrderId,
+        qty: f64,
+        side: Side,
+    ) -> Result<(), MarketError> {
+        if !qty.is_finite() || qty <= 0.0 || side == Side::None {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        match self.parents.entry(parent_id) {
+            Entry::Occupied(_) => Err(MarketError::OrderIdExist),
+            Entry::Vacant(entry) => {
+                entry.insert(ParentOrder::new(parent_id, side, qty));
+                Ok(())
+            }
+        }
+    }
+
+    /// 为子单占用母单的剩余数量，并记录子单 ID。
+    /// 检查母单能否为子单分配 `qty` 的数量。
+    fn check_child(&self, parent_id: OrderId, side: Side, qty: f64) -> Result<(), MarketError> {
+        let parent = self
+            .parents
+            .get(&parent_id)
+            .ok_or(MarketError::ParentOrderNotExist)?;
+        if parent.canceled || parent.side != side {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        if qty > parent.remaining_qty() {
+            return Err(MarketError::ParentQtyExceeded);
+        }
+        Ok(())
+    }
+
+    fn allocate_child(
+        &mut self,
+        parent_id: OrderId,
+        order_id: OrderId,
+        side: Side,
+        qty: f64,
+    ) -> Result<(), MarketError> {
+        self.check_child(parent_id, side, qty)?;
+        let parent = self.parents.get_mut(&parent_id).unwrap();
+        parent.allocated_qty += qty;
+        parent.children.push(order_id);
+        Ok(())
+    }
+
+    /// 汇总到目前为止的回放结果，通常在 `goto_end_of_day` 之后调用。
+    pub fn replay_summary(&self) -> ReplaySummary {
+        let statistics = self.market_depth.get_statistics();
+        let mut info = StatisticsInfo::new();
+        info.from_statistics(statistics, self.tick_size, self.lot_size);
+        let price = |tick: i64| (tick != 0).then(|| self.tick_to_price(tick));
+        let traded = statistics.high >= statistics.low;
+        ReplaySummary {
+            events_processed: self.events_processed,
+            events_skipped: self.skipped_orders.len(),
+            events_out_of_order: self.out_of_order_events,
+            total_qty: info.total_bid_qty + info.total_ask_qty,
+            turnover: info.turnover,
+            open: price(self.open_tick),
+            close: price(self.close_tick),
+            high: traded.then(|| info.high),
+            low: traded.then(|| info.low),
+            wall_time: self.wall_time,
+        }
+    }
+
+    /// 核对统计数据、用户订单和订单簿之间的数量，返回发现的差异。
+    ///
+    /// 比较以下三类数据，数量均以手表示：
+    /// - `MarketVolume`: 统计数据的总成交量，期望值为已处理的历史逐笔消息的主动成交量、
+    ///   集合竞价成交量（统计数据中买卖双方各计一次）和用户主动成交量之和，
+    ///   回测模式下用户挂单与历史订单的影子成交也计入统计数据，因此还要加上用户被动和集合竞价成交量。
+    ///   实时模式下用户订单改变了历史订单的成交，此时的差异反映的是市场冲击。
+    /// - `UserFill`: 所有用户订单按流动性角色汇总的成交量与统计数据中对应的成交量。
+    /// - `RestingVolume`: 价格档位记录的挂单量与档位中存活订单的剩余数量之和。
+    ///
+    /// 用户挂单的成交在 `sync_order_info` 时才计入订单和统计数据，对账不会同步订单信息，
+    /// 尚未同步的挂单成交按被动成交计入总成交量的期望值，因此可以在回放过程中调用。
+    pub fn reconcile(&self) -> ReconciliationReport {
+        let statistics = self.market_depth.get_statistics();
+        let mut report = ReconciliationReport::new(self.events_processed);
+
+        let mut expected_market_vol =
+            self.history_match_vol + self.auction_vol + statistics.user_aggressive_vol;
+        if self.mode == ExchangeMode::Backtest {
+            expected_market_vol += statistics.user_passive_vol
+                + statistics.user_auction_vol
+                + self.unsynced_fill_vol();
+        }
+        report.check(
+            DiscrepancyKind::MarketVolume,
+            expected_market_vol,
+            statistics.total_volume(),
+        );
+
+        let (mut aggressive, mut passive, mut auction) = (0.0, 0.0, 0.0);
+        for order_ref in self.orders.iter().flat_map(HashMap::values) {
+            let order = order_ref.borrow();
+            aggressive += order.filled_qty_aggressive;
+            passive += order.filled_qty_passive;
+            auction += order.filled_qty_auction;
+        }
+        for (liquidity, qty, vol) in [
+            (
+                FillLiquidity::Aggressive,
+                aggressive,
+                statistics.user_aggressive_vol,
+            ),
+            (FillLiquidity::Passive, passive, statistics.user_passive_vol),
+            (FillLiquidity::Auction, auction, statistics.user_auction_vol),
+        ] {
+            report.check(
+                DiscrepancyKind::UserFill(liquidity),
+                self.qty_to_vol(qty),
+                vol,
+            );
+        }
+
+        let (level_vol, orders_vol) = self.market_depth.resting_vol();
+        report.check(DiscrepancyKind::RestingVolume, orders_vol, level_vol);
+        report
+    }
+
+    /// 返回某一类差异在自动对账中第一次被发现时已处理的历史逐笔消息数量。
+    pub fn first_discrepancy(&self, kind: DiscrepancyKind) -> Option<usize> {
+        self.reconciliation_log.first_seen(kind)
+    }
+
+    /// 用户挂单在市场深度中已经成交、但尚未由 `sync_order_info` 计入订单的数量，以手表示。
+    fn unsynced_fill_vol(&self) -> i64 {
+        let mut vol = 0;
+        for (order_id, l3order) in self.market_depth.orders().iter() {
+            let order = match self.orders.as_ref().and_then(|orders| orders.get(order_id)) {
+                Some(order_ref) => order_ref.borrow(),
+                None => continue,
+            };
+            if order.is_terminal() {
+                continue;
+            }
+            let filled_qty = l3order.borrow().filled_qty(order.qty, self.lot_size);
+            vol += self.qty_to_vol(filled_qty) - self.qty_to_vol(order.classified_fill_qty());
+        }
+        vol
+    }
+
+    /// 历史逐笔消息记录的主动成交量，撤单消息没有成交。
+    fn history_match_vol_of(&self, order_ref: &L3OrderRef, seq: i64) -> i64 {
+        match order_ref.borrow().auxiliary_info.as_ref() {
+            Some(info) if info.cancel_seq != seq => self.qty_to_vol(info.match_qty),
+            _ => 0,
+        }
+    }
+
+    /// 汇总母单下所有子单的成交情况。
+    ///
+    /// # 参数
+    /// - `parent_id`: 母单 ID。
+    ///
+    /// # 错误
+    /// 母单没有登记时返回 `MarketError::ParentOrderNotExist`。
+    pub fn parent_summary(&self, parent_id: OrderId) -> Result<ParentSummary, MarketError> {
+        let parent = self
+            .parents
+            .get(&parent_id)
+            .ok_or(MarketError::ParentOrderNotExist)?;
+
+        let mut filled_qty = 0.0;
+        let mut filled_amount = 0.0;
+        // 已记录成交价格的成交数量，用于计算成交均价
+        let mut priced_qty = 0.0;
+        let mut open_children = 0;
+        for child_id in parent.children.iter() {
+            if let Some(order_ref) = self.orders.as_ref().and_then(|orders| orders.get(child_id)) {
+                let order = order_ref.borrow();
+                filled_qty += order.filled_qty;
+                filled_amount += order.filled_amount;
+                priced_qty += order.classified_fill_qty();
+                if order.is_open() {
+                    open_children += 1;
+                }
+            }
+        }
+
+        Ok(ParentSummary {
+            total_qty: parent.qty,
+            filled_qty,
+            avg_price: if priced_qty > 0.0 {
+                filled_amount / priced_qty
+            } else {
+                0.0
+            },
+            children: parent.children.clone(),
+            open_children,
+        })
+    }
+
+    /// 按策略编号汇总用户订单的成交情况，各策略的成交数量之和等于用户订单的总成交数量。
+    pub fn strategy_summary(&self) -> BTreeMap<StrategyId, StrategySummary> {
+        let mut summaries: BTreeMap<StrategyId, StrategySummary> = BTreeMap::new();
+        for order_ref in self.orders.iter().flat_map(HashMap::values) {
+            let order = order_ref.borrow();
+            let summary = summaries.entry(order.strategy_id).or_default();
+            summary.order_count += 1;
+            summary.filled_qty += order.filled_qty;
+            summary.filled_qty_aggressive += order.filled_qty_aggressive;
+            summary.filled_qty_passive += order.filled_qty_passive;
+            summary.filled_qty_auction += order.filled_qty_auction;
+            // 先累计成交额，最后换算为均价
+            summary.avg_price += order.filled_amount;
+        }
+        for summary in summaries.values_mut() {
+            let priced_qty = summary.filled_qty_aggressive
+                + summary.filled_qty_passive
+                + summary.filled_qty_auction;
+            summary.avg_price = if priced_qty > 0.0 {
+                summary.avg_price / priced_qty
+            } else {
+                0.0
+            };
+        }
+        summaries
+    }
+
+    /// 返回某个策略的用户订单的成交回报，顺序与 `user_fills` 相同。
+    pub fn strategy_fills(&self, strategy_id: StrategyId) -> Vec<&UserFill> {
+        self.user_fills
+            .iter()
+            .filter(|fill| fill.strategy_id == strategy_id)
+            .collect()
+    }
+
+    /// 设置用户订单的手续费模型，`None` 表示不收取手续费。
+    pub fn set_fee_model(&mut self, model: Option<FeeModel>) {
+        self.fee_model = model;
+    }
+
+    /// 按手续费模型计算用户订单累计成交的手续费，负数表示得到的返佣，没有设置模型时为 0。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在。
+    /// - `MarketError::OrdersNotInitialized`: 订单表没有初始化，参见 `orders`。
+    pub fn order_fee(&self, order_id: OrderId) -> Result<f64, MarketError> {
+        let order_ref = self
+            .orders()?
+            .get(&order_id)
+            .ok_or(MarketError::OrderNotFound)?;
+        Ok(self
+            .fee_model
+            .as_ref()
+            .map_or(0.0, |model| model.order_fee(&order_ref.borrow())))
+    }
+
+    /// 所有用户订单的手续费之和，负数表示返佣超过了支付的手续费。
+    pub fn total_fees(&self) -> f64 {
+        match self.fee_model.as_ref() {
+            Some(model) => self
+                .orders
+                .iter()
+                .flat_map(HashMap::values)
+                .map(|order_ref| model.order_fee(&order_ref.borrow()))
+                .sum(),
+            None => 0.0,
+        }
+    }
+
+    /// 按策略、账户和处置结果汇总当日提交的所有用户订单，包括提交时被拒绝的订单
+    /// 和仍在等待生效的订单。
+    ///
+    /// 订单状态取最近一次同步的结果，参见 `sync_order_info`。
+    pub fn disposition_report(&self) -> DispositionReport {
+        let unactivated: HashSet<OrderId> = self
+            .pending_orders
+            .iter()
+            .chain(self.waiting_orders.iter().map(|(_, order_ref)| order_ref))
+            .map(|order_ref| order_ref.borrow().order_id)
+            .collect();
+        let mut report = self.rejected_report.clone();
+        for order_ref in self.orders.iter().flat_map(HashMap::values) {
+            let order = order_ref.borrow();
+            let disposition = if unactivated.contains(&order.order_id) {
+                Disposition::Unactivated
+            } else {
+                Disposition::of(&order)
+            };
+            report.record(&order, disposition);
+        }
+        report.rejected.sort_unstable();
+        report.unactivated.sort_unstable();
+        report
+    }
+
+    /// 撤销母单，同时对所有仍在交易中的子单发出撤单，子单的撤单与 `request_cancel` 一样延迟生效。
+    ///
+    /// 母单撤销后不再接受新的子单。
+    ///
+    /// # 参数
+    /// - `parent_id`: 母单 ID。
+    ///
+    /// # 返回值
+    /// 撤单被接受的子单 ID。
+    ///
+    /// # 错误
+    /// 母单没有登记时返回 `MarketError::ParentOrderNotExist`。
+    pub fn cancel_parent(&mut self, parent_id: OrderId) -> Result<Vec<OrderId>, MarketError> {
+        let parent = self
+            .parents
+            .get_mut(&parent_id)
+            .ok_or(MarketError::ParentOrderNotExist)?;
+        parent.canceled = true;
+        let children = parent.children.clone();
+
+        Ok(self.cancel_open_orders(children))
+    }
+
+    /// 批量撤销满足过滤条件的用户订单。
+    ///
+    /// 每个订单按 `request_cancel` 处理：没有撤单延迟时在一次调用中撤销，期间不会处理任何行情或订单事件；
+    /// 否则撤单进入等待队列，在 `cancel_latency` 毫秒后生效。
+    ///
+    /// # 参数
+    /// - `filter`: 撤单的过滤条件，`CancelFilter::default()` 表示撤销所有订单。
+    ///
+    /// # 返回值
+    /// 每个满足过滤条件的订单 ID 及其撤单结果，按订单 ID 排序；
+    /// 已成交、已撤销或已拒绝的订单返回 `MarketError::OrderNotFound`，其他错误参见 `request_cancel`。
+    pub fn cancel_all(&mut self, filter: CancelFilter) -> Vec<(OrderId, Result<(), MarketError>)> {
+        let mut order_ids: Vec<OrderId> = self
+            .orders_mut()
+            .iter()
+            .filter(|(_, order_ref)| filter.matches(&order_ref.borrow()))
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        order_ids.sort();
+        self.request_cancels(order_ids)
+    }
+
+    /// 对指定账户所有仍在交易中的用户订单发出撤单，撤单与 `request_cancel` 一样延迟生效。
+    ///
+    /// # 返回值
+    /// 撤单被接受的订单 ID。
+    pub fn cancel_account_orders(&mut self, account: &str) -> Vec<OrderId> {
+        let order_ids: Vec<OrderId> = self
+            .orders_mut()
+            .iter()
+            .filter(|(_, order_ref)| order_ref.borrow().account.as_deref() == Some(account))
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        self.cancel_open_orders(order_ids)
+    }
+
+    /// 对给定订单中仍在交易中的部分发出撤单，返回撤单被接受的订单 ID。
+    fn cancel_open_orders(&mut self, order_ids: Vec<OrderId>) -> Vec<OrderId> {
+        self.request_cancels(order_ids)
+            .into_iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(order_id, _)| order_id)
+            .collect()
+    }
+
+    /// 按 `request_cancel` 的规则对给定订单发出撤单，返回每个订单的撤单结果，顺序与 `order_ids` 相同。
+    ///
+    /// 没有撤单延迟时，立即生效的撤单由 `cancel_orders` 一次执行，只同步一次订单状态。
+    fn request_cancels(
+        &mut self,
+        order_ids: Vec<OrderId>,
+    ) -> Vec<(OrderId, Result<(), MarketError>)> {
+        let gateway_down = self.in_outage(OutageKind::OrderEntry, self.timestamp);
+        let mut results = Vec::with_capacity(order_ids.len());
+        let mut due_now = Vec::new();
+        for order_id in order_ids {
+            let result = if gateway_down {
+                Err(MarketError::GatewayUnavailable)
+            } else if self.is_pending_ack(order_id) {
+                self.cancel_pending_ack(order_id).map(|_| ())
+            } else if self.cancel_latency == 0 {
+                match self.accepts_cancel(self.timestamp) {
+                    Ok(true) => {
+                        due_now.push(order_id);
+                        Ok(())
+                    }
+                    Ok(false) => Err(MarketError::OrderTypeUnsupported),
+                    Err(err) => Err(err),
+                }
+            } else {
+                self.enqueue_cancel(order_id, self.timestamp).map(|_| ())
+            };
+            results.push((order_id, result));
+        }
+        let mut executed: HashMap<OrderId, Result<(), MarketError>> =
+            self.cancel_orders(due_now).into_iter().collect();
+        for (order_id, result) in results.iter_mut() {
+            if let Some(executed) = executed.remove(order_id) {
+                *result = executed;
+            }
+        }
+        results
+    }
+
+    /// 撤销给定的订单并返回每个订单的撤单结果。
+    ///
+    /// 不存在或已处于终止状态的订单返回 `MarketError::OrderNotFound`。
+    /// 每个被撤销的订单只会被加入脏订单追踪器一次。
+    fn cancel_orders(
+        &mut self,
+        order_ids: Vec<OrderId>,
+    ) -> Vec<(OrderId, Result<(), MarketError>)> {
+        let mut results = Vec::with_capacity(order_ids.len());
+        for order_id in order_ids {
+            let order_ref = match self
+                .orders
+                .as_ref()
+                .and_then(|orders| orders.get(&order_id))
+            {
+                Some(order_ref) if !order_ref.borrow().is_terminal() => order_ref.clone(),
+                _ => {
+                    results.push((order_id, Err(MarketError::OrderNotFound)));
+                    continue;
+                }
+            };
+            if self.market_depth.orders().contains_key(&order_id) {
+                // 由 sync_order_info 同步状态并加入脏订单追踪器
+                let _ = self.market_depth.cancel_order(order_id);
+            } else {
+                order_ref.borrow_mut().status = OrderStatus::Canceled;
+                self.dirty_tracker.push(order_id);
+            }
+            results.push((order_id, Ok(())));
+        }
+        self.sync_order_info();
+
+        results
+    }
+
+    /// 模拟时间的推移，并处理所有到期的订单
+    ///
+    /// # 参数
+    ///
+    /// * `duration` - 模拟的时间段，以时间单位表示。时间推移将基于此时间段来更新当前时间,单位为毫秒。
+    ///
+    /// # 返回
+    ///
+    /// 返回一个 `Result<bool, MarketError>`。如果成功处理了所有订单并推进了时间，则返回 `Ok(true)`；如果时间点达到历史记录的结束，则返回 `Ok(true)`；如果时间点未到达历史记录的结束，则返回 `Ok(false)`。
+    ///
+    /// # 错误
+    ///
+    /// 如果处理订单时发生错误（例如匹配订单失败），方法会返回相应的 `MarketError`。
+    pub fn elapse(self: &'_ mut Self, duration: i64) -> Result<i64, MarketError> {
+        self.while_elapsing(|broker| broker.elapse_orders(duration))
+    }
+
+    /// 在推进时间期间执行 `f`，期间不能修改钩子。最外层的调用计入 `wall_time`。
+    fn while_elapsing<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let elapsing = std::mem::replace(&mut self.elapsing, true);
+        let started = Instant::now();
+        let result = f(self);
+        self.elapsing = elapsing;
+        if !elapsing {
+            self.wall_time += started.elapsed();
+        }
+        result
+    }
+
+    /// 处理到期的订单并推进时间，由 `elapse` 调用。
+    fn elapse_orders(&mut self, duration: i64) -> Result<i64, MarketError> {
+        let time_point = adjust_timestamp_milliseconds_i64(self.timestamp, duration)?;
+        let mut total_filled: i64 = 0;
+
+        //处理pending队列
+        while !self.pending_orders.is_empty() {
+            let order_ref = self.pending_orders.pop_front().unwrap();
+            if order_ref.borrow().is_terminal() {
+                continue;
+            }
+            // 先处理同一时间戳上应排在该订单之前的历史订单
+            let seq_limit = self.same_time_seq_limit(Some(order_ref.borrow().seq));
+            let _ = self.advance_to(self.timestamp, seq_limit);
+            if self.acknowledge(&order_ref, self.timestamp) {
+                continue;
+            }
+            let mut order = order_ref.borrow_mut();
+            order.exch_time = self.timestamp;
+            let l3order_ref = order.to_l3order_ref(self.tick_size, self.lot_size);
+            let fillid = match self.process_order(l3order_ref.clone()) {
+                Ok(fillid) => fillid,
+                // 当前交易时段不接受该类型的订单
+                Err(MarketError::OrderTypeUnsupported) => {
+                    order.status = OrderStatus::Canceled;
+                    self.dirty_tracker.push(order.order_id);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if fillid > 0 {
+                order.filled_qty = self.vol_to_qty(fillid);
+                // 订单进入时产生的成交都是主动成交
+                let price =
+                    self.tick_to_price(l3order_ref.borrow().aggressive_amount_tick) / fillid as f64;
+                let fill = order.add_fill(
+                    FillLiquidity::Aggressive,
+                    self.vol_to_qty(fillid),
+                    price,
+                    self.timestamp,
+                );
+                self.user_fills.push(fill);
+                order.price_improvement =
+                    self.tick_to_price(l3order_ref.borrow().price_improvement_tick) * self.lot_size;
+                self.market_depth
+                    .get_statistics_mut()
+                    .add_user_fill(FillLiquidity::Aggressive, fillid);
+                self.dirty_tracker.push(order.order_id);
+                order.update();
+            }
+            if self.cancel_dropped_residual(&mut order, &l3order_ref) {
+                self.dirty_tracker.push(order.order_id);
+            }
+            total_filled += fillid;
+        }
+
+        self.waiting_orders.make_contiguous().sort();
+        //处理waiting队列
+        while !self.waiting_orders.is_empty() {
+            let timestamp = self.waiting_orders[0].0;
+            if timestamp > time_point {
+                break;
+            }
+            // 先于该订单生效的撤单先执行
+            self.apply_due_cancels(timestamp)?;
+            let (_, order_ref) = self.waiting_orders.pop_front().unwrap();
+            if order_ref.borrow().is_terminal() {
+                continue;
+            }
+            let seq_limit = self.same_time_seq_limit(Some(order_ref.borrow().seq));
+            let _ = self.advance_to(timestamp, seq_limit);
+            // 确认前收到的撤单在确认后立即执行，订单不进入订单簿
+            if self.acknowledge(&order_ref, timestamp) {
+                continue;
+            }
+            let mut order = order_ref.borrow_mut();
+            let l3order_ref = order.to_l3order_ref(self.tick_size, self.lot_size);
+            let fillid = match self.process_order(l3order_ref.clone()) {
+                Ok(fillid) => fillid,
+                // 当前交易时段不接受该类型的订单
+                Err(MarketError::OrderTypeUnsupported) => {
+                    order.status = OrderStatus::Canceled;
+                    self.dirty_tracker.push(order.order_id);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            order.exch_time = self.timestamp;
+            if fillid > 0 {
+                order.filled_qty = self.vol_to_qty(fillid);
+                // 订单进入时产生的成交都是主动成交
+                let price =
+                    self.tick_to_price(l3order_ref.borrow().aggressive_amount_tick) / fillid as f64;
+                let fill = order.add_fill(
+                    FillLiquidity::Aggressive,
+                    self.vol_to_qty(fillid),
+                    price,
+                    self.timestamp,
+                );
+                self.user_fills.push(fill);
+                order.price_improvement =
+                    self.tick_to_price(l3order_ref.borrow().price_improvement_tick) * self.lot_size;
+                self.market_depth
+                    .get_statistics_mut()
+                    .add_user_fill(FillLiquidity::Aggressive, fillid);
+                self.dirty_tracker.push(order.order_id);
+                order.update();
+            }
+            if self.cancel_dropped_residual(&mut order, &l3order_ref) {
+                self.dirty_tracker.push(order.order_id);
+            }
+            total_filled += fillid;
+        }
+        self.apply_due_cancels(time_point)?;
+
+        //有可能处理完了waiting队列后，时间还需要继续向前流逝
+        let _ = self.goto(time_point);
+        Ok(total_filled)
+    }
+
+    /// 记录订单在 `timestamp` 得到交易所确认，订单从 `OrderStatus::PendingAck` 变为 `OrderStatus::New`。
+    ///
+    /// 确认后通知生命周期回调，订单确认前收到并暂存的撤单在确认后立即执行。
+    ///
+    /// # 返回值
+    /// 订单被撤销时返回 `true`，此时订单不再进入订单簿。
+    fn acknowledge(&mut self, order_ref: &OrderRef, timestamp: i64) -> bool {
+        let mut order = order_ref.borrow_mut();
+        order.ack_time = Some(timestamp);
+        if order.status != OrderStatus::PendingAck {
+            return false;
+        }
+        order.status = OrderStatus::New;
+        self.dirty_tracker.push(order.order_id);
+        let order_id = order.order_id;
+        drop(order);
+        self.notify_lifecycle(order_id, LifecycleEvent::Acknowledged, timestamp);
+        match self.ack_cancels.iter().position(|id| *id == order_id) {
+            Some(idx) => {
+                self.ack_cancels.remove(idx);
+                let mut order = order_ref.borrow_mut();
+                order.status = OrderStatus::Canceled;
+                order.exch_time = timestamp;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 处理对尚未得到确认的订单发出的撤单，按 `pending_ack_cancel` 暂存或拒绝。
+    ///
+    /// # 错误
+    /// `PendingAckCancel::Reject` 时返回 `MarketError::OrderRequestInProcess`。
+    fn cancel_pending_ack(&mut self, order_id: OrderId) -> Result<i64, MarketError> {
+        match self.pending_ack_cancel {
+            PendingAckCancel::Reject => Err(MarketError::OrderRequestInProcess),
+            PendingAckCancel::Defer => {
+                if !self.ack_cancels.contains(&order_id) {
+                    self.ack_cancels.push(order_id);
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// 订单是否已发出但尚未得到交易所确认。
+    fn is_pending_ack(&self, order_id: OrderId) -> bool {
+        self.orders
+            .as_ref()
+            .and_then(|orders| orders.get(&order_id))
+            .is_some_and(|order_ref| order_ref.borrow().status == OrderStatus::PendingAck)
+    }
+
+    /// 执行生效时间不晚于 `time_point` 的撤单。
+    ///
+    /// 每笔撤单生效前先推进到生效时间，并同步此前的成交，使订单先记录部分成交再记录撤销。
+    fn apply_due_cancels(&mut self, time_point: i64) -> Result<(), MarketError> {
+        while let Some(&(effective_time, order_id)) = self.pending_cancels.front() {
+            if effective_time > time_point {
+                break;
+            }
+            self.pending_cancels.pop_front();
+            if self.history.is_some() {
+                self.advance_to(effective_time, self.same_time_seq_limit(None))?;
+            }
+            self.timestamp = cmp::max(self.timestamp, effective_time);
+            // 生效时所处时段不接受撤单时，撤单被交易所拒绝
+            if !self.accepts_cancel(self.timestamp)? {
+                self.notify_lifecycle(order_id, LifecycleEvent::CancelRejected, self.timestamp);
+                continue;
+            }
+            self.sync_order_info();
+            // 撤单生效前已经全部成交的订单不再撤销
+            let _ = self.cancel_orders(vec![order_id]);
+        }
+        Ok(())
+    }
+
+    /// 即时成交剩余撤销的订单（如 `OrderType::M`）撮合后没有进入市场深度，剩余部分记为撤销数量。
+    ///
+    /// # 返回值
+    /// 有剩余部分被撤销时返回 `true`。
+    fn cancel_dropped_residual(&self, order: &mut Order, l3order_ref: &L3OrderRef) -> bool {
+        let l3order = l3order_ref.borrow();
+        if l3order.side != Side::None || l3order.vol <= 0 {
+            return false;
+        }
+        order.cancel_residual(self.vol_to_qty(l3order.vol));
+        true
+    }
+
+    /// 同步订单信息，将市场深度中的订单状态与本地订单进行同步。
+    /// 如果订单被标记为已处理或取消，将从市场深度中移除并更新本地订单状态。
+    pub fn sync_order_info(&mut self) {
+        // 获取市场深度中所有订单的信息
+        let l30orders = self.market_depth.orders();
+
+        // 用于追踪需要从市场深度中移除的订单 ID
+        let mut remove_tracker: Vec<OrderId> = Vec::with_capacity(100);
+        // 已终止但仍挂在订单簿上的订单，需要先从订单簿中撤出
+        let mut stale_tracker: Vec<L3OrderRef> = Vec::new();
+        // 挂单在两次同步之间新增的成交都是被动成交
+        let mut passive_vol = 0;
+
+        for (order_id, l30order) in l30orders.iter() {
+            // 假设挂单没有对应的用户订单
+            let mut order = match self.orders.as_ref().and_then(|orders| orders.get(order_id)) {
+                Some(order_ref) => order_ref.borrow_mut(),
+                None => continue,
+            };
+            // print!("{l30order:?}\n");
+            // 已终止的订单不再重复处理，只从市场深度中移除
+            if order.is_terminal() {
+                remove_tracker.push(*order_id);
+                let l3order = l30order.borrow();
+                if l3order.side != Side::None && l3order.vol > 0 {
+                    stale_tracker.push(l30order.clone());
+                }
+                continue;
+            }
+            if l30order.borrow().dirty == true {
+                // 同步订单的位置信息和数量
+                order.price = self.tick_to_price(l30order.borrow().price_tick);
+                order.queue = self.vol_to_qty(l30order.borrow().total_vol_before);
+                order.left_qty = l30order.borrow().left_qty(self.lot_size);
+                order.filled_qty = l30order.borrow().filled_qty(order.qty, self.lot_size);
+                order.exch_time = self.timestamp;
+                let vol =
+                    order.filled_lots(self.lot_size) - self.qty_to_vol(order.classified_fill_qty());
+                if vol > 0 {
+                    // 挂单总是以自己的价格被动成交
+                    let price = order.price;
+                    let fill = order.add_fill(
+                        FillLiquidity::Passive,
+                        self.vol_to_qty(vol),
+                        price,
+                        self.timestamp,
+                    );
+                    self.user_fills.push(fill);
+                    passive_vol += vol;
+                }
+                // 根据订单的成交量和方向更新状态
+                if l30order.borrow().vol == 0 {
+                    remove_tracker.push(order_id.clone());
+                    order.status = OrderStatus::Filled;
+                } else if l30order.borrow().side == Side::None {
+                    remove_tracker.push(order_id.clone());
+                    order.status = OrderStatus::Canceled;
+                }
+
+                // 将已修改的订单 ID 添加到脏订单追踪器中
+                self.dirty_tracker.push(order_id.clone());
+            }
+        }
+        for l3order in stale_tracker {
+            let _ = self.market_depth.cancel_order_from_ref(l3order);
+        }
+        // 从市场深度中移除已处理或取消的订单
+        let l30orders = self.market_depth.orders_mut();
+        for idx in remove_tracker {
+            l30orders.remove(&idx);
+        }
+        self.market_depth
+            .get_statistics_mut()
+            .add_user_fill(FillLiquidity::Passive, passive_vol);
+    }
+
+    /// 将市场深度中用户订单尚未区分流动性角色的成交按 `liquidity` 计入订单和统计数据。
+    ///
+    /// 集合竞价前后各调用一次，竞价前的成交计为被动成交，竞价产生的成交计为集合竞价成交。
+    ///
+    /// # 参数
+    /// - `liquidity`: 成交的流动性角色。
+    /// - `price_tick`: 成交价格档位，`None` 表示按订单自己的价格成交。
+    fn classify_user_fills(&mut self, liquidity: FillLiquidity, price_tick: Option<i64>) {
+        let mut total_vol = 0;
+        for (order_id, l3order) in self.market_depth.orders().iter() {
+            let order_ref = match self.orders.as_ref().and_then(|orders| orders.get(order_id)) {
+                Some(order_ref) => order_ref,
+                None => continue,
+            };
+            let mut order = order_ref.borrow_mut();
+            if order.is_terminal() {
+                continue;
+            }
+            let filled_qty = order.qty - self.vol_to_qty(l3order.borrow().vol);
+            let vol = self.qty_to_vol(filled_qty - order.classified_fill_qty());
+            if vol > 0 {
+                let price = self.tick_to_price(price_tick.unwrap_or(l3order.borrow().price_tick));
+                let fill = order.add_fill(liquidity, self.vol_to_qty(vol), price, self.timestamp);
+                self.user_fills.push(fill);
+                total_vol += vol;
+            }
+        }
+        self.market_depth
+            .get_statistics_mut()
+            .add_user_fill(liquidity, total_vol);
+    }
+
+    pub fn goto_end_of_day(&mut self) -> Result<bool, MarketError> {
+        self.goto(i64::MAX)
+    }
+    /// 将时间推进到指定的时间点，并处理该时间点之前的所有订单
+    ///
+    /// 该方法根据提供的时间点 (`time_point`) 继续模拟市场，并处理所有在该时间点之前的订单。它会从历史数据源中获取订单，并根据订单信息调用相应的处理方法。处理完成后，时间戳会更新到处理过的最后一个订单的时间。
+    ///
+    /// # 参数
+    ///
+    /// * `time_point` - 要推进到的时间点，指定模拟的结束时间。时间点必须大于或等于当前时间戳。
+    ///
+    /// # 返回
+    ///
+    /// 如果成功推进到指定时间点并处理了所有相关订单，返回 `Ok(true)`。如果在处理过程中没有更多的历史数据可供处理，并且时间戳未达到指定时间点，则返回 `Ok(false)`。如果没有历史数据源可用，返回 `Err(MarketError::HistoryIsNone)`。
+    ///
+    /// # 错误
+    ///
+    /// 返回以下错误：
+    ///
+    /// * `MarketError::HistoryIsNone` - 如果历史数据源未设置，将返回此错误。
+    ///
+    /// # 示例
+    ///
+    /// ```rust
+    /// let mut broker = Broker::new(...);
+    /// let result = broker.goto(1627845600);
+    /// match result {
+    ///     Ok(true) => println!("成功推进到指定时间点并处理所有订单"),
+    ///     Ok(false) => println!("未能推进到指定时间点，可能是因为历史数据已用尽"),
+    ///     Err(e) => println!("发生错误: {:?}", e),
+    /// }
+    /// ```
+    ///
+    /// # 说明
+    ///
+    /// - 方法首先检查历史数据源是否存在。如果不存在，返回错误。
+    /// - 然后，它会遍历历史数据中的订单，直到处理时间戳达到 `time_point`。
+    /// - 对于每个订单，根据订单的时间和类型，调用 `process_order` 方法来处理订单。
+    /// - 历史订单按序列号的顺序处理，时间戳会更新到当前处理的订单的时间。
+    ///   序列号靠后的订单时间戳可能更早，这时时间戳保持不变，订单按当前时间处理并计入 `out_of_order_events`，
+    ///   时间不会倒退。
+    /// - 如果历史数据源已用尽且时间戳未达到 `time_point`，则返回 `Ok(false)`。
+    /// - 时间戳等于 `time_point` 的历史订单仅在 `SameTimePriority::HistoryFirst` 下处理，
+    ///   其他策略下留给之后到达的用户订单先处理。
+    pub fn goto(&mut self, time_point: i64) -> Result<bool, MarketError> {
+        let same_time_seq = self.same_time_seq_limit(None);
+        self.while_elapsing(|broker| broker.advance_to(time_point, same_time_seq))
+    }
+
+    /// 将时间推进到指定的时间点，该时间戳上的历史订单全部处理，不受 `same_time_priority` 限制。
+    ///
+    /// 用于调用方已经处理完该时间点之前的用户订单、需要确保消费掉该时间戳上所有历史订单的场合。
+    ///
+    /// # 错误
+    /// 没有历史数据源时返回 `MarketError::HistoryIsNone`。
+    pub fn goto_through(&mut self, time_point: i64) -> Result<bool, MarketError> {
+        self.while_elapsing(|broker| broker.advance_to(time_point, i64::MAX))
+    }
+
+    /// 返回下一条尚未处理的历史订单的时间，没有历史数据或已经处理完毕时返回 `None`。
+    pub fn next_event_time(&self) -> Option<i64> {
+        let (_, order_ref) = self.history.as_ref()?.peek()?;
+        let timestamp = order_ref.borrow().timestamp;
+        Some(timestamp)
+    }
+
+    /// 将时间推进到指定的时间点，同一时间戳的历史订单只处理序列号小于 `same_time_seq` 的部分。
+    ///
+    /// 通过预读下一条历史订单，在时间戳边界处停止，不会消耗晚于 `time_point` 的历史订单。
+    fn advance_to(&mut self, time_point: i64, same_time_seq: i64) -> Result<bool, MarketError> {
+        info!("goto time_point {time_point}");
+        let mut end_of_history = false;
+        if self.history.is_none() {
+            return Err(MarketError::HistoryIsNone);
+        }
+
+        loop {
+            let (next_seq, next_time) = match self.history.as_ref().unwrap().peek() {
+                Some((seq, order_ref)) => (seq, order_ref.borrow().timestamp),
+                None => {
+                    end_of_history = true;
+                    break;
+                }
+            };
+            if next_time > time_point || (next_time == time_point && next_seq >= same_time_seq) {
+                break;
+            }
+
+            let (seq, order_ref) = self.history.as_mut().unwrap().next().unwrap();
+            order_ref.borrow_mut().seq = seq;
+            self.events_processed += 1;
+            debug!("history order info {order_ref:?}");
+
+            // 历史数据按序列号排列，时间戳不一定单调，回放时间不能倒退
+            let timestamp = order_ref.borrow().timestamp;
+            if timestamp < self.timestamp {
+                self.out_of_order_events += 1;
+                debug!(
+                    "history order {seq} at {timestamp} is earlier than {}",
+                    self.timestamp
+                );
+            } else {
+                self.timestamp = timestamp;
+            }
+            self.latest_seq_number = cmp::max(self.latest_seq_number, seq);
+            let order_ref_arg = order_ref.clone();
+            // 快照反映其时间戳及之前的逐笔数据，在处理更晚的数据前完成校验
+            self.validate_reconstruction(self.timestamp, false);
+            self.notify_replay_event(seq, &order_ref_arg);
+            if !self
+                .calendar
+                .is_in_call_auction(self.timestamp, self.market_type)
+                .unwrap_or(false)
+                && self.open_tick == 0
+            {
+                self.classify_user_fills(FillLiquidity::Passive, None);
+                let auction_vol;
+                (self.open_tick, auction_vol) = self
+                    .market_depth
+                    .call_auction_with_limits(self.limit_down_tick(), self.limit_up_tick())
+                    .unwrap_or((0, 0));
+                self.auction_vol += auction_vol;
+                self.classify_user_fills(FillLiquidity::Auction, Some(self.open_tick));
+            }
+
+            let match_vol = self.history_match_vol_of(&order_ref_arg, seq);
+            match self.process_order(order_ref_arg.clone()) {
+                Ok(_) => self.history_match_vol += match_vol,
+                // 异常的历史数据不应该中断回放，记录后跳过
+                Err(MarketError::InvalidOrderRequest) => {
+                    let order_id = order_ref_arg.borrow().order_id;
+                    self.skipped_orders.push((seq, order_id));
+                }
+                Err(err) => return Err(err),
+            }
+            if self
+                .auto_reconcile_every
+                .is_some_and(|every| every > 0 && self.events_processed % every == 0)
+            {
+                let report = self.reconcile();
+                if !report.is_consistent() {
+                    warn!("reconciliation failed: {report:?}");
+                }
+                self.reconciliation_log.record(&report);
+            }
+            if self
+                .publish_every
+                .is_some_and(|every| every > 0 && self.events_processed % every == 0)
+            {
+                self.publish_state();
+            }
+        }
+        if end_of_history {
+            self.validate_reconstruction(time_point, true);
+        }
+        self.timestamp = time_point;
+        self.market_depth.sample_analytics(self.timestamp);
+        if self
+            .calendar
+            .should_call_auction_on_close(self.timestamp, self.market_type)?
+            && self.close_tick == 0
+        {
+            self.classify_user_fills(FillLiquidity::Passive, None);
+            let (close_tick, auction_vol) = self
+                .market_depth
+                .call_auction_with_limits(self.limit_down_tick(), self.limit_up_tick())
+                .unwrap_or((0, 0));
+            self.close_tick = close_tick;
+            self.auction_vol += auction_vol;
+            self.classify_user_fills(FillLiquidity::Auction, Some(close_tick));
+        }
+        Ok(end_of_history)
+    }
+
+    /// 尝试通过订单 ID 取消订单。如果在内部订单列表中找到该订单，
+    /// 将其状态标记为已取消。如果未找到，则尝试在市场深度中取消该订单。
+    ///
+    /// # 参数
+    ///
+    /// * `order_id` - 要取消的订单的 ID。
+    ///
+    /// # 返回值
+    ///
+    /// * 如果操作成功，返回 `Ok(0)`。
+    /// * 如果找不到订单或在取消市场深度中的订单时发生错误，返回 `Err(MarketError)`。
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<i64, MarketError> {
+        let _ = self.market_depth.cancel_order(order_id);
+
+        Ok(0)
+    }
+
+    /// 修改挂在订单簿中的用户订单的价格和数量，修改后的订单重新排队。
+    ///
+    /// `qty` 是修改后订单的总数量，包含已经成交的部分，订单簿中剩余的数量为 `qty` 减去已成交的数量。
+    /// 例如 100 手的订单已成交 40 手，改为 50 手后剩余 10 手。
+    ///
+    /// 修改后的订单与新订单一样先与对手方撮合，新价格达到对手方最优价时立即主动成交，剩余部分再挂入订单簿。
+    ///
+    /// # 参数
+    /// - `order_id`: 订单 ID。
+    /// - `price`: 修改后的价格。
+    /// - `qty`: 修改后订单的总数量。
+    ///
+    /// # 返回值
+    /// 成功时返回 `Ok(0)`。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在、已处于终止状态或者还没有进入订单簿。
+    /// - `MarketError::InvalidOrderRequest`: 修改后的数量不大于已成交的数量，或者剩余数量不是整手。
+    /// - `MarketError::PriceNotOnTick` / `MarketError::PriceOutOfLimit`: 新价格不合法，参见 `validate_order`。
+    /// - 其他错误来自 `L3MarketDepth::modify_order` 和 `process_order`。
+    pub fn modify_order(
+        &mut self,
+        order_id: OrderId,
+        price: f64,
+        qty: f64,
+    ) -> Result<i64, MarketError> {
+        // 先同步订单簿中的成交，按最新的已成交数量检查
+        self.sync_order_info();
+        let order_ref = match self
+            .orders
+            .as_ref()
+            .and_then(|orders| orders.get(&order_id))
+        {
+            Some(order_ref) if !order_ref.borrow().is_terminal() => order_ref.clone(),
+            _ => return Err(MarketError::OrderNotFound),
+        };
+        if !self.market_depth.orders().contains_key(&order_id) {
+            return Err(MarketError::OrderNotFound);
+        }
+        let filled_qty = order_ref.borrow().filled_qty;
+        let left_lots = (qty - filled_qty) / self.lot_size;
+        if !qty.is_finite() || qty <= filled_qty || (left_lots - left_lots.round()).abs() > 1e-6 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        self.validate_limit_price(price)?;
+
+        let l3order_ref =
+            self.market_depth
+                .modify_order(order_id, price, qty - filled_qty, self.timestamp)?;
+        {
+            let mut order = order_ref.borrow_mut();
+            order.qty = qty;
+            order.price = price;
+            order.price_tick = l3order_ref.borrow().price_tick;
+        }
+        // 撤出的订单按新价格重新撮合，避免挂入后与对手方交叉
+        let fillid = self.process_order(l3order_ref.clone())?;
+        if fillid > 0 {
+            let mut order = order_ref.borrow_mut();
+            order.filled_qty += self.vol_to_qty(fillid);
+            let price =
+                self.tick_to_price(l3order_ref.borrow().aggressive_amount_tick) / fillid as f64;
+            let fill = order.add_fill(
+                FillLiquidity::Aggressive,
+                self.vol_to_qty(fillid),
+                price,
+                self.timestamp,
+            );
+            self.user_fills.push(fill);
+            order.price_improvement =
+                self.tick_to_price(l3order_ref.borrow().price_improvement_tick) * self.lot_size;
+            self.market_depth
+                .get_statistics_mut()
+                .add_user_fill(FillLiquidity::Aggressive, fillid);
+            order.exch_time = self.timestamp;
+            order.update();
+            self.dirty_tracker.push(order_id);
+        }
+        // 订单簿中的订单已标记为脏，同步后更新剩余数量、价格和排队位置
+        self.sync_order_info();
+        Ok(0)
+    }
+
+    /// 发出用户撤单，撤单在 `cancel_latency` 毫秒后生效。
+    ///
+    /// 延迟为 0 时与 `cancel_order` 相同，立即撤销。否则撤单进入等待队列，
+    /// 在 `elapse` 推进到生效时间时执行，生效前到达的成交仍然会执行。
+    ///
+    /// 撤单生效时所处的时段不接受撤单时（深圳市场的收盘集合竞价），撤单被拒绝，
+    /// 参见 `TradingPhase::accepts_cancel`。延迟生效的撤单在生效时被拒绝，不返回错误，
+    /// 而是通过生命周期回调通知 `LifecycleEvent::CancelRejected`，参见 `set_lifecycle_callback`。
+    ///
+    /// 撤单发出时订单还没有得到交易所确认的，按 `pending_ack_cancel` 在确认后立即撤销或者拒绝撤单。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在或已处于终止状态。
+    /// - `MarketError::InvalidTimestamp`: 生效时间超出可编码的范围。
+    /// - `MarketError::GatewayUnavailable`: 当前处于报单通道中断窗口内。
+    /// - `MarketError::OrderTypeUnsupported`: 没有撤单延迟且当前时段不接受撤单。
+    /// - `MarketError::OrderRequestInProcess`: 订单尚未得到确认且设置为拒绝撤单。
+    pub fn request_cancel(&mut self, order_id: OrderId) -> Result<i64, MarketError> {
+        if self.in_outage(OutageKind::OrderEntry, self.timestamp) {
+            return Err(MarketError::GatewayUnavailable);
+        }
+        if self.is_pending_ack(order_id) {
+            return self.cancel_pending_ack(order_id);
+        }
+        if self.cancel_latency == 0 {
+            if !self.accepts_cancel(self.timestamp)? {
+                return Err(MarketError::OrderTypeUnsupported);
+            }
+            return self.cancel_order(order_id);
+        }
+        self.enqueue_cancel(order_id, self.timestamp)?;
+        Ok(0)
+    }
+
+    /// 判断 `timestamp` 所处的交易时段是否接受用户撤单。
+    fn accepts_cancel(&self, timestamp: i64) -> Result<bool, MarketError> {
+        Ok(self
+            .calendar
+            .phase(timestamp, self.market_type)?
+            .accepts_cancel(self.market_type))
+    }
+
+    /// 把在 `request_time` 发出的撤单加入等待队列，撤单在 `cancel_latency` 毫秒后生效。
+    ///
+    /// # 返回值
+    /// 撤单的生效时间。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在或已处于终止状态。
+    /// - `MarketError::InvalidTimestamp`: 生效时间超出可编码的范围。
+    fn enqueue_cancel(&mut self, order_id: OrderId, request_time: i64) -> Result<i64, MarketError> {
+        match self
+            .orders
+            .as_ref()
+            .and_then(|orders| orders.get(&order_id))
+        {
+            Some(order_ref) if !order_ref.borrow().is_terminal() => {}
+            _ => return Err(MarketError::OrderNotFound),
+        }
+        let effective_time = adjust_timestamp_milliseconds_i64(request_time, self.cancel_latency)?;
+        self.pending_cancels.push_back((effective_time, order_id));
+        self.pending_cancels.make_contiguous().sort();
+        Ok(effective_time)
+    }
+
+    pub fn cancel_order_from_ref(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        let _ = self.market_depth.cancel_order_from_ref(order_ref);
+
+        Ok(0)
+    }
+
+    /// 向订单簿注入一笔假设的挂单，用于研究修改后的订单簿上的执行情况。
+    ///
+    /// 假设挂单直接挂入订单簿，不与对手盘撮合。之后到达的订单会与它成交，
+    /// 但这些成交以及它的挂单和撤单都不计入市场统计。回测模式下它只占用影子成交量，
+    /// 不会改变历史订单之间的撮合。
+    ///
+    /// # 参数
+    /// - `side`: 挂单方向。
+    /// - `price`: 挂单价格。
+    /// - `qty`: 挂单数量。
+    ///
+    /// # 返回值
+    /// 假设挂单的订单 ID，为负数，不会与用户订单和历史订单冲突。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidOrderRequest`: 价格或数量不是正数。
+    /// - `MarketError::MarketSideError`: 方向不是买或卖。
+    pub fn inject_phantom_order(
+        &mut self,
+        side: Side,
+        price: f64,
+        qty: f64,
+    ) -> Result<OrderId, MarketError> {
+        let order_id = -(self.phantom_orders.len() as OrderId) - 1;
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            order_id,
+            side,
+            self.price_to_tick(price),
+            self.qty_to_vol(qty),
+            self.timestamp,
+            OrderType::L,
+        );
+        order_ref.borrow_mut().phantom = true;
+        self.market_depth.add(order_ref.clone())?;
+        self.phantom_orders.push(order_ref);
+        Ok(order_id)
+    }
+
+    /// 撤销所有仍在订单簿中的假设挂单。
+    ///
+    /// # 返回值
+    /// 被撤销的假设挂单数量，已经全部成交的假设挂单不计入。
+    pub fn clear_phantom_orders(&mut self) -> usize {
+        let mut cleared = 0;
+        for order_ref in std::mem::take(&mut self.phantom_orders) {
+            let (order_id, resting) = {
+                let order = order_ref.borrow();
+                (order.order_id, order.vol > 0 && order.side != Side::None)
+            };
+            if resting && self.market_depth.cancel_order_from_ref(order_ref).is_ok() {
+                cleared += 1;
+            }
+            self.market_depth.orders_mut().remove(&order_id);
+        }
+        self.market_depth.clear_phantoms();
+        cleared
+    }
+}
+
+impl<'a, MD> RecoverOp for Broker<MD>
+where
+    MD: L3MarketDepth + Serialize + Deserialize<'a> + RecoverOp + StatisticsOp + SnapshotOp,
+    MarketError: From<<MD as L3MarketDepth>::Error>,
+{
+    fn recover(&mut self) -> Result<bool, MarketError> {
+        self.init();
+        self.market_depth.recover()?;
+        if let Some(history) = self.history.as_mut() {
+            history.init()?;
+            // 订单簿中的历史挂单是快照中的副本，之后的撤单和成交消息需要找到同一个订单
+            if let Some(orders) = history.orders.as_mut() {
+                for order_ref in self.market_depth.resting_orders() {
+                    let order_id = order_ref.borrow().order_id;
+                    if order_ref.borrow().source != OrderSourceType::LocalOrder {
+                        continue;
+                    }
+                    if let Some(history_order) = orders.get_mut(&order_id) {
+                        *history_order = order_ref;
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<'a, MD> Processor for Broker<MD>
+where
+    MD: L3MarketDepth + Serialize + Deserialize<'a> + RecoverOp + StatisticsOp + SnapshotOp,
+    MarketError: From<<MD as L3MarketDepth>::Error>,
+{
+    /// 初始化经纪人，返回历史逐笔消息的数量。
+    fn initialize_data(&mut self) -> Result<i64, MarketError> {
+        self.init();
+        Ok(self
+            .history
+            .as_ref()
+            .map_or(0, |history| history.len as i64))
+    }
+
+    /// 回放剩余的历史数据直到收盘，返回 (已处理的历史逐笔消息数量, 当前时间)。
+    fn process_data(&mut self) -> Result<(i64, i64), MarketError> {
+        self.goto_end_of_day()?;
+        Ok((self.events_processed as i64, self.timestamp))
+    }
+
+    /// 以 `current_timestamp` 为本地时间提交一笔用户订单，参见 `Broker::submit_order`。
+    fn submit_order(
+        &mut self,
+        order_id: OrderId,
+        side: Side,
+        price: f64,
+        qty: f64,
+        order_type: OrderType,
+        current_timestamp: i64,
+    ) -> Result<(), MarketError> {
+        let mut order = Order::new(
+            None,
+            self.stock_code.clone(),
+            price,
+            qty,
+            side,
+            order_type,
+            current_timestamp,
+            OrderSourceType::UserOrder,
+        );
+        order.order_id = order_id;
+        Broker::submit_order(self, Rc::new(RefCell::new(order)))?;
+        Ok(())
+    }
+
+    /// 在 `current_timestamp` 发出撤单，撤单按模拟时间排队，在 `cancel_latency` 毫秒后生效。
+    ///
+    /// 早于经纪人当前时间的请求按当前时间处理。已经到达生效时间的撤单立即执行，
+    /// 订单被标记为已撤销并加入脏订单追踪器；其余撤单在 `elapse` 推进到生效时间时执行。
+    /// 订单还没有得到交易所确认的，与 `request_cancel` 相同，按 `pending_ack_cancel` 处理。
+    ///
+    /// # 错误
+    /// - `MarketError::OrderNotFound`: 订单不存在或已处于终止状态。
+    /// - `MarketError::GatewayUnavailable`: 发出撤单时处于报单通道中断窗口内。
+    /// - `MarketError::OrderRequestInProcess`: 订单尚未得到确认且设置为拒绝撤单。
+    fn cancel(&mut self, order_id: OrderId, current_timestamp: i64) -> Result<(), MarketError> {
+        let request_time = cmp::max(current_timestamp, self.timestamp);
+        if self.in_outage(OutageKind::OrderEntry, request_time) {
+            return Err(MarketError::GatewayUnavailable);
+        }
+        if self.is_pending_ack(order_id) {
+            self.cancel_pending_ack(order_id)?;
+            return Ok(());
+        }
+        let effective_time = self.enqueue_cancel(order_id, request_time)?;
+        if effective_time <= self.timestamp {
+            self.apply_due_cancels(self.timestamp)?;
+        }
+        Ok(())
+    }
+
+    fn orders(&self) -> Result<&HashMap<OrderId, OrderRef>, MarketError> {
+        Broker::orders(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::borrow;
+    use std::str::FromStr;
+
+    use super::utils::time_difference_ms_i64;
+    use super::*;
+    use fee::SideFees;
+    use fixture::BookFixture;
+    use hook::HookStats;
+    use order::Order;
+    use publish::STATE_CHANNEL_CAPACITY;
+    use skiplist_orderbook::SkipListMarketDepth;
+
+    #[test]
+    fn test_broker_new() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        assert_eq!(broker.stock_type, "STOCK");
+        assert_eq!(broker.stock_code, "CODE");
+        assert_eq!(broker.tick_size, 0.01);
+        assert_eq!(broker.lot_size, 100.0);
+        assert!(broker.pending_orders.is_empty());
+        assert!(broker.waiting_orders.is_empty());
+        assert_eq!(broker.timestamp, 19700101000000000);
+        assert!(broker.orders().unwrap().is_empty());
+        assert_eq!(broker.latest_seq_number, 0);
+        assert!(broker.history.is_none());
+        assert!(broker.dirty_tracker.is_empty());
+    }
+
+    #[test]
+    fn test_init_is_idempotent() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "stock".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            20231201093000000,
+            10.0,
+            100.0,
+            "b",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        broker.submit_order(order_ref).unwrap();
+        assert_eq!(broker.orders().unwrap().len(), 1);
+
+        broker.init();
+        assert!(broker.initialized);
+        assert_eq!(broker.orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_submit_order_before_init() {
+        let new_order = |order_id: OrderId| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                20231201100000000,
+                10.0,
+                100.0,
+                "b",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(20231201100000000)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        // 没有调用 `init` 时也可以提交和同步订单
+        broker.submit_order(new_order(1)).unwrap();
+        broker.elapse(10).unwrap();
+        broker.sync_order_info();
+        let mut orders = HashMap::new();
+        broker.get_orders(&mut orders, &vec![]);
+        assert_eq!(orders.len(), 1);
+        broker.get_latest_orders(&mut orders);
+
+        // 从快照恢复、尚未调用 `recover` 的经纪人同样可以接受订单
+        let mut restored: Broker<SkipListMarketDepth> =
+            serde_json::from_str(&broker.snapshot()).unwrap();
+        assert!(restored.orders().unwrap().is_empty());
+        restored.submit_order(new_order(1)).unwrap();
+        assert_eq!(restored.orders().unwrap().len(), 1);
+
+        // 订单表被置为 `None` 后，提交订单会重新创建它
+        restored.orders = None;
+        restored.submit_order(new_order(2)).unwrap();
+        assert_eq!(restored.orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_generate_seq_number() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        assert_eq!(broker.generate_seq_number(), 1);
+        assert_eq!(broker.generate_seq_number(), 2);
+    }
+
+    #[test]
+    fn test_add_data() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        assert!(broker.add_data(None).is_ok());
+    }
+
+    #[test]
+    fn test_get_orders() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let order_ref = Order::new_ref(
+            Some("account1".to_string()),
+            "AAPL".to_string(),
+            1234567890,
+            150.0,
+            10.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::LocalOrder,
+        );
+
+        broker.submit_order(order_ref).unwrap();
+
+        let mut orders = HashMap::new();
+        broker.get_orders(&mut orders, &vec![OrderStatus::New]);
+        assert_eq!(orders.len(), 1);
+    }
+    #[test]
+    fn test_submit_order() {
+        let mode = ExchangeMode::Backtest;
+        let stock_code = String::from("stock");
+        let account = "user1".to_string();
+        let timestamp = 1;
+        let price = 11.2;
+        let qty = 100.0;
+        let bs_flag = "b";
+        let order_type = OrderType::L;
+        let source = OrderSourceType::LocalOrder;
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            mode,
+            MarketType::SH,
+            "stock".to_string(),
+            "stock".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let order_ref = Order::new_ref(
+            Some(account),
+            stock_code,
+            timestamp,
+            price,
+            qty,
+            &bs_flag,
+            order_type,
+            source,
+        );
+
+        let result = broker.submit_order(order_ref);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1); // Assuming this is the expected queue position
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        // Create and submit a test order
+        let order_ref = Order::new_ref(
+            Some("account1".to_string()),
+            "AAPL".to_string(),
+            1234567890,
+            150.0,
+            10.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::LocalOrder,
+        );
+        broker.submit_order(order_ref.clone()).unwrap();
+
+        let order_id = order_ref.borrow().order_id;
+        // Cancel the order
+        let result = broker.cancel_order(order_id);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        // Verify the order status is canceled
+        let order = broker
+            .orders
+            .as_ref()
+            .unwrap()
+            .get(&order_ref.borrow().order_id)
+            .unwrap();
+        assert_eq!(order.borrow().status, OrderStatus::Canceled);
+    }
+
+    #[test]
+    fn test_get_orders_multiple_statuses() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        // 创建多个订单，具有不同的状态，集合竞价阶段不接受市价订单，因此使用连续竞价时段的时间
+        let new_order_ref = Order::new_ref(
+            Some("account1".to_string()),
+            "AAPL".to_string(),
+            20231201100000000,
+            150.0,
+            10.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+
+        let filled_order_ref = Order::new_ref(
+            Some("account2".to_string()),
+            "AAPL".to_string(),
+            20231201100000001,
+            155.0,
+            15.0,
+            "Sell",
+            OrderType::B,
+            OrderSourceType::UserOrder,
+        );
+
+        let canceled_order_ref = Order::new_ref(
+            Some("account3".to_string()),
+            "AAPL".to_string(),
+            20231201100000002,
+            160.0,
+            20.0,
+            "Buy",
+            OrderType::C,
+            OrderSourceType::UserOrder,
+        );
+
+        let new_order_id = 1234567890;
+        let filled_order_id = 1234567891;
+        let canceled_order_id = 1234567892;
+
+        new_order_ref.borrow_mut().order_id = new_order_id;
+        filled_order_ref.borrow_mut().order_id = filled_order_id;
+        canceled_order_ref.borrow_mut().order_id = canceled_order_id;
+        // 提交订单
+        broker.submit_order(new_order_ref.clone()).unwrap();
+        broker.submit_order(filled_order_ref.clone()).unwrap();
+        broker.submit_order(canceled_order_ref.clone()).unwrap();
+
+        // 将状态修改为不同状态以便测试
+        broker
+            .orders
+            .as_mut()
+            .unwrap()
+            .get_mut(&1234567890)
+            .unwrap()
+            .borrow_mut()
+            .status = OrderStatus::New;
+        broker
+            .orders
+            .as_mut()
+            .unwrap()
+            .get_mut(&1234567891)
+            .unwrap()
+            .borrow_mut()
+            .status = OrderStatus::Filled;
+        broker
+            .orders
+            .as_mut()
+            .unwrap()
+            .get_mut(&1234567892)
+            .unwrap()
+            .borrow_mut()
+            .status = OrderStatus::Canceled;
+
+        // 测试获取新订单
+        let mut orders = HashMap::new();
+        broker.get_orders(&mut orders, &vec![OrderStatus::New]);
+        assert_eq!(orders.len(), 1);
+        assert!(orders.contains_key(&1234567890));
+
+        // 清空映射并测试获取已完成订单
+        orders.clear();
+        broker.get_orders(&mut orders, &vec![OrderStatus::Filled]);
+        assert_eq!(orders.len(), 1);
+        assert!(orders.contains_key(&1234567891));
+
+        // 清空映射并测试获取已取消订单
+        orders.clear();
+        broker.get_orders(&mut orders, &vec![OrderStatus::Canceled]);
+        assert_eq!(orders.len(), 1);
+        assert!(orders.contains_key(&1234567892));
+
+        // 清空映射并测试获取多个状态的订单
+        orders.clear();
+        broker.get_orders(&mut orders, &vec![OrderStatus::New, OrderStatus::Filled]);
+        assert_eq!(orders.len(), 2);
+        assert!(orders.contains_key(&1234567890));
+        assert!(orders.contains_key(&1234567891));
+    }
+
+    #[test]
+    fn test_broker_snapshot() {
+        // 创建一个 Broker 实例
+        // 使用 Backtest 模式，股票类型为 "STOCK"，股票代码为 "CODE"，
+        // 最小价格变动单位为 0.01，最小交易单位为 100.0
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        // 调用 snapshot 方法，获取 Broker 实例的 JSON 序列化表示
+        let snapshot = broker.snapshot();
+        print!("{:?}\n", snapshot);
+        print!("{:?}\n", serde_json::to_string(&broker));
+        // 验证 snapshot 返回的 JSON 字符串是否包含期望的字段及其值
+        // 确保交易模式被正确序列化
+        assert!(snapshot.contains(r#""mode":"Backtest""#));
+        // 确保股票类型被正确序列化
+        assert!(snapshot.contains(r#""stock_type":"STOCK""#));
+        // 确保股票代码被正确序列化
+        assert!(snapshot.contains(r#""stock_code":"CODE""#));
+        // 确保最小价格变动单位被正确序列化
+        assert!(snapshot.contains(r#""tick_size":0.01"#));
+        // 确保最小交易单位被正确序列化
+        assert!(snapshot.contains(r#""lot_size":100.0"#));
+        // 确保当前时间戳被正确序列化
+        assert!(snapshot.contains(r#""timestamp":0"#));
+        // 确保最新的序列号被正确序列化
+        assert!(snapshot.contains(r#""latest_seq_number":0"#));
+
+        // 验证 snapshot 返回的 JSON 字符串不包含被跳过序列化的字段
+        // pending_orders、waiting_orders、orders、history 和 dirty_tracker
+        // 被标记为 #[serde(skip)]，因此不应包含在序列化输出中
+        assert!(!snapshot.contains(r#""pending_orders":[]"#));
+        assert!(!snapshot.contains(r#""waiting_orders":[]"#));
+        assert!(!snapshot.contains(r#""dirty_tracker":[]"#));
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    fn test_broker_add_dataloader() {
+        let exchange_mode = "backtest".to_string();
+        let stock_code = "688007.SH".to_string();
+        let file_type = "local".to_string();
+        let data_path = "./data".to_string();
+        let date = "20231201".to_string();
+        let mode = "L2P";
+
+        let mut data = DataCollator::new(
+            stock_code.clone(),
+            file_type.clone(),
+            data_path.clone(),
+            date.clone(),
+            mode.clone(),
+        );
+        data.init().unwrap();
+
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::from_str(&exchange_mode.as_str()).unwrap(),
+            MarketType::SH,
+            "stock".to_string(),
+            stock_code.clone(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let start: i64 = 20231201092521355;
+        let duration = time_difference_ms_i64(broker.timestamp, start).unwrap_or(0);
+        broker.add_data(Some(data));
+        broker.elapse(duration + 10000);
+        print!("{:?}\n", broker.snapshot());
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    fn test_broker_live_mode() {
+        let exchange_mode = "live";
+        let stock_code = "688007.SH".to_string();
+        let file_type = "local".to_string();
+        let data_path = "./data".to_string();
+        let date = "20231201".to_string();
+        let mode = "L2P";
+
+        let mut data = DataCollator::new(
+            stock_code.clone(),
+            file_type.clone(),
+            data_path.clone(),
+            date.clone(),
+            mode.clone(),
+        );
+        data.init().unwrap();
+
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::from_str(&exchange_mode).unwrap(),
+            MarketType::SH,
+            "stock".to_string(),
+            stock_code.clone(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let start: i64 = 20231201092521355;
+        let duration = time_difference_ms_i64(broker.timestamp, start).unwrap_or(0);
+        broker.add_data(Some(data));
+        broker.elapse(duration + 24 * 3600 * 1000);
+        print!("{:?}\n", broker.snapshot());
+    }
+
+    #[test]
+    fn test_process_user_order() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let timestamp = 20231201093021355;
+        broker.set_current_time(timestamp);
+        // Create and submit a local order
+        let buy_order_ref = Order::new_ref(
+            None,
+            "AAPL".to_string(),
+            timestamp,
+            150.0,
+            10.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        buy_order_ref.borrow_mut().order_id = 1;
+        let sell_order_ref = Order::new_ref(
+            None,
+            "AAPL".to_string(),
+            timestamp,
+            150.0,
+            10.0,
+            "Sell",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        sell_order_ref.borrow_mut().order_id = 2;
+        broker.submit_order(buy_order_ref.clone()).unwrap();
+        broker.submit_order(sell_order_ref.clone()).unwrap();
+        // Process the local order
+
+        broker.elapse(1000);
+        broker.sync_order_info();
+
+        // print!("{buy_order_ref:?}\n,{sell_order_ref:?}\n");
+
+        // print!("{:?}\n", broker.market_depth.get_ask_level(2));
+        // print!("{:?}\n", broker.market_depth.get_bid_level(2));
+        // Verify the order status
+        assert_eq!(buy_order_ref.borrow().status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_process_cancel_order() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+
+        let timestamp = 20231201093021355;
+        broker.set_current_time(timestamp);
+
+        // Create and submit a limit order
+        let order_ref = Order::new_ref(
+            None,
+            "AAPL".to_string(),
+            timestamp,
+            150.0,
+            10.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        broker.submit_order(order_ref.clone()).unwrap();
+
+        // Process the order to ensure it is added
+        broker.elapse(1000);
+
+        broker.cancel_order(order_ref.borrow_mut().order_id);
+        // print!("{:?}\n", broker.market_depth.orders);
+        broker.sync_order_info();
+
+        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+    }
+
+    #[test]
+    fn test_md_delay() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let timestamp = 20231201100000000;
+        broker.set_current_time(timestamp);
+        broker
+            .process_order(L3Order::new_ref(
+                OrderSourceType::UserOrder,
+                None,
+                1,
+                Side::Buy,
+                100,
+                1,
+                timestamp,
+                OrderType::L,
+            ))
+            .unwrap();
+
+        broker.set_md_delay(50);
+        // 延迟截止时间之前没有快照，使用设置延迟时采集的快照
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.00).abs() < 1e-9);
+        assert_eq!(broker.bid_levels(5).len(), 1);
+
+        // 价格在 T 时刻发生变化
+        let t = adjust_timestamp_milliseconds_i64(timestamp, 1000).unwrap();
+        broker.set_current_time(t);
+        broker
+            .process_order(L3Order::new_ref(
+                OrderSourceType::UserOrder,
+                None,
+                2,
+                Side::Buy,
+                101,
+                1,
+                t,
+                OrderType::L,
+            ))
+            .unwrap();
+
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.00).abs() < 1e-9);
+        assert!((broker.best_bid(&OrderSourceType::LocalOrder) - 1.01).abs() < 1e-9);
+        assert_eq!(broker.bid_levels(5).len(), 1);
+
+        broker.set_current_time(adjust_timestamp_milliseconds_i64(t, 49).unwrap());
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.00).abs() < 1e-9);
+
+        broker.set_current_time(adjust_timestamp_milliseconds_i64(t, 50).unwrap());
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.01).abs() < 1e-9);
+        assert_eq!(broker.bid_levels(5).len(), 2);
+        assert_eq!(broker.market_view().best_bid_tick, 101);
+
+        // 延迟为 0 时直接返回实时行情
+        broker.set_md_delay(0);
+        broker.set_current_time(t);
+        assert!(broker.market_views.is_empty());
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.01).abs() < 1e-9);
+    }
+
+    #[test]
+    /// 测试一个延迟窗口内的行情变化超过快照缓冲区容量。
+    /// 验证策略看到的仍然是截止时间之前的最新行情，而不是空行情或截止时间之后的行情。
+    fn test_md_delay_buffer_overflow() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let timestamp = 20231201100000000;
+        broker.set_current_time(timestamp);
+        broker.set_md_delay(2000);
+        let buy = |broker: &mut Broker<SkipListMarketDepth>, order_id, price_tick, time| {
+            broker.set_current_time(time);
+            broker
+                .process_order(L3Order::new_ref(
+                    OrderSourceType::UserOrder,
+                    None,
+                    order_id,
+                    Side::Buy,
+                    price_tick,
+                    1,
+                    time,
+                    OrderType::L,
+                ))
+                .unwrap();
+        };
+
+        // T+1s 买一价变为 1.00，T+2.5s 起每毫秒抬高一次买一价，共 1100 次
+        buy(
+            &mut broker,
+            1,
+            100,
+            adjust_timestamp_milliseconds_i64(timestamp, 1000).unwrap(),
+        );
+        let updates = MARKET_VIEW_CAPACITY as i64 + 76;
+        for idx in 0..updates {
+            let time = adjust_timestamp_milliseconds_i64(timestamp, 2500 + idx).unwrap();
+            buy(&mut broker, idx + 2, 101 + idx, time);
+        }
+        assert!(broker.market_views.len() <= MARKET_VIEW_CAPACITY);
+        assert_eq!(
+            broker
+                .market_depth
+                .best_bid_tick(&OrderSourceType::LocalOrder),
+            100 + updates
+        );
+
+        // 截止时间为 T+1.599s，策略看到的是 T+1s 的行情
+        assert_eq!(broker.market_view().best_bid_tick, 100);
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 1.00).abs() < 1e-9);
+    }
+
+    /// 由 (时间, 方向, 价格, 数量) 构造历史逐笔委托数据，序列号从 1 开始依次递增。
+    fn local_history(orders: &[(i64, Side, f64, f64)]) -> DataCollator {
+        let mut data = DataCollator::new(
+            "600000.SH".to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "ORDER",
+        );
+        let mut history = HashMap::new();
+        let mut index_by_seq = VecDeque::new();
+        for (idx, (order_time, side, price, qty)) in orders.iter().enumerate() {
+            let seq = idx as i64 + 1;
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                seq,
+                *side,
+                0,
+                0,
+                *order_time,
+                OrderType::L,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                auxiliary_info.initial_price = *price;
+                auxiliary_info.initial_qty = *qty;
+                auxiliary_info.initial_seq = seq;
+            }
+            history.insert(seq, order_ref);
+            index_by_seq.push_back((seq, seq));
+        }
+        data.len = index_by_seq.len();
+        data.orders = Some(history);
+        data.index_by_seq = Some(index_by_seq);
+        data
+    }
+
+    /// 在 `timestamp` 挂出 3 手 10.00 的用户买单，历史卖单在 120 毫秒后到达，撤单在 100 毫秒后发出。
+    fn cancel_race_broker(
+        timestamp: i64,
+        cancel_latency: i64,
+    ) -> (Broker<SkipListMarketDepth>, OrderRef) {
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        // 历史卖单在 120 毫秒后以 10.00 卖出 1 手
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 120).unwrap();
+        broker
+            .add_data(Some(local_history(&[(sell_time, Side::Sell, 10.0, 100.0)])))
+            .unwrap();
+        broker.set_cancel_latency(cancel_latency);
+
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            timestamp,
+            10.0,
+            300.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 100;
+        broker.submit_order(order_ref.clone()).unwrap();
+        // 撤单延迟不影响订单确认
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        broker.elapse(100).unwrap();
+        broker.sync_order_info();
+        broker.dirty_tracker.clear();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        (broker, order_ref)
+    }
+
+    #[test]
+    fn test_cancel_latency_races_fill() {
+        let timestamp = 20231201100000000;
+        let (mut broker, order_ref) = cancel_race_broker(timestamp, 50);
+        assert_eq!(broker.request_cancel(100), Ok(0));
+        assert_eq!(broker.pending_cancels.len(), 1);
+        assert_eq!(broker.request_cancel(101), Err(MarketError::OrderNotFound));
+
+        // 快照保存尚未生效的撤单
+        let restored: Broker<SkipListMarketDepth> =
+            serde_json::from_str(&broker.snapshot()).unwrap();
+        assert_eq!(restored.pending_cancels, broker.pending_cancels);
+        assert_eq!(restored.cancel_latency, 50);
+
+        // 历史卖单在撤单生效前到达，用户订单部分成交
+        broker.elapse(30).unwrap();
+        broker.sync_order_info();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        assert_eq!(order_ref.borrow().filled_qty, 100.0);
+        assert_eq!(broker.dirty_tracker, vec![100]);
+
+        // 撤单生效时剩余部分被撤销
+        broker.elapse(30).unwrap();
+        assert!(broker.pending_cancels.is_empty());
+        let order = order_ref.borrow();
+        assert_eq!(order.status, OrderStatus::Canceled);
+        assert_eq!(order.filled_qty, 100.0);
+        assert_eq!(broker.dirty_tracker, vec![100, 100, 100]);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+    }
+
+    #[test]
+    fn test_cancel_without_latency_is_instant() {
+        let timestamp = 20231201100000000;
+        let (mut broker, order_ref) = cancel_race_broker(timestamp, 0);
+        assert_eq!(broker.request_cancel(100), Ok(0));
+        assert!(broker.pending_cancels.is_empty());
+
+        // 撤单立即生效，之后到达的历史卖单不会成交
+        broker.elapse(60).unwrap();
+        broker.sync_order_info();
+        let order = order_ref.borrow();
+        assert_eq!(order.status, OrderStatus::Canceled);
+        assert_eq!(order.filled_qty, 0.0);
+        assert_eq!(broker.dirty_tracker, vec![100]);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 1);
+    }
+
+    /// 测试批量撤单与 `request_cancel` 一样在撤单延迟后生效
+    /// 尚未得到确认的订单按 `pending_ack_cancel` 在确认时撤销
+    #[test]
+    fn test_cancel_all_respects_cancel_latency() {
+        let timestamp = 20231201100000000;
+        let (mut broker, order_ref) = cancel_race_broker(timestamp, 50);
+        broker.set_ack_latency(5);
+        let pending_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            broker.timestamp,
+            9.9,
+            100.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        pending_ref.borrow_mut().order_id = 101;
+        broker.submit_order(pending_ref.clone()).unwrap();
+        assert_eq!(pending_ref.borrow().status, OrderStatus::PendingAck);
+
+        assert_eq!(
+            broker.cancel_all(CancelFilter::default()),
+            vec![(100, Ok(())), (101, Ok(()))]
+        );
+        assert_eq!(broker.pending_cancels.len(), 1);
+        assert_eq!(broker.ack_cancels, vec![101]);
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+
+        // 历史卖单在撤单生效前到达，用户订单部分成交；未确认的订单在确认时撤销
+        broker.elapse(30).unwrap();
+        broker.sync_order_info();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        assert_eq!(order_ref.borrow().filled_qty, 100.0);
+        assert_eq!(pending_ref.borrow().status, OrderStatus::Canceled);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(990), 0);
+
+        // 撤单生效时剩余部分被撤销
+        broker.elapse(30).unwrap();
+        assert!(broker.pending_cancels.is_empty());
+        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+        assert_eq!(order_ref.borrow().filled_qty, 100.0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+    }
+
+    #[test]
+    /// 测试订单确认延迟。
+    /// 验证订单确认前处于 `PendingAck` 状态，确认时通知生命周期回调，确认前发出的撤单在确认时生效，订单不进入订单簿。
+    fn test_ack_latency() {
+        fn record(object: &Rc<RefCell<dyn Any>>, event: &OrderLifecycle) {
+            object
+                .borrow_mut()
+                .downcast_mut::<Vec<OrderLifecycle>>()
+                .unwrap()
+                .push(*event);
+        }
+
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 120).unwrap();
+        broker
+            .add_data(Some(local_history(&[(sell_time, Side::Sell, 10.0, 100.0)])))
+            .unwrap();
+        broker.set_ack_latency(5);
+        let events: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<OrderLifecycle>::new()));
+        broker.set_lifecycle_callback(events.clone(), record);
+        let user_order = |order_id: OrderId| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                timestamp,
+                10.0,
+                300.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+
+        // 在 T+2 撤销 T 时刻发出的订单，撤单在 T+5 确认时生效
+        let order_ref = user_order(100);
+        broker.submit_order(order_ref.clone()).unwrap();
+        assert_eq!(order_ref.borrow().status, OrderStatus::PendingAck);
+        assert!(order_ref.borrow().is_open());
+        broker.elapse(2).unwrap();
+        assert_eq!(order_ref.borrow().ack_time, None);
+        assert_eq!(broker.request_cancel(100), Ok(0));
+        assert_eq!(order_ref.borrow().status, OrderStatus::PendingAck);
+        broker.elapse(3).unwrap();
+        {
+            let order = order_ref.borrow();
+            assert_eq!(order.status, OrderStatus::Canceled);
+            assert_eq!(order.ack_time, Some(20231201100000005));
+            assert_eq!(order.exch_time, 20231201100000005);
+        }
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+        assert!(broker.ack_cancels.is_empty());
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        assert!(orders.contains_key(&100));
+
+        // 没有撤单的订单确认后进入订单簿
+        let order_ref = user_order(101);
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(5).unwrap();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        assert_eq!(order_ref.borrow().ack_time, Some(20231201100000010));
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 3);
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        assert!(orders.contains_key(&101));
+
+        // 设置为拒绝时，确认前的撤单返回错误
+        broker.set_pending_ack_cancel(PendingAckCancel::Reject);
+        let order_ref = user_order(102);
+        broker.submit_order(order_ref.clone()).unwrap();
+        assert_eq!(
+            broker.request_cancel(102),
+            Err(MarketError::OrderRequestInProcess)
+        );
+        broker.elapse(5).unwrap();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 6);
+
+        let events = events.borrow();
+        let events = events.downcast_ref::<Vec<OrderLifecycle>>().unwrap();
+        let acks: Vec<(OrderId, i64)> = events
+            .iter()
+            .map(|event| {
+                assert_eq!(event.event, LifecycleEvent::Acknowledged);
+                (event.order_id, event.timestamp)
+            })
+            .collect();
+        assert_eq!(
+            acks,
+            vec![
+                (100, 20231201100000005),
+                (101, 20231201100000010),
+                (102, 20231201100000015),
+            ]
+        );
+    }
+
+    #[test]
+    /// 测试除权时同步调整用户订单。
+    /// 验证 2:1 拆股后部分成交订单的价格、数量和已成交数量按比例换算，数量四舍五入为 0 的挂单被撤销。
+    fn test_corporate_action_rescales_user_orders() {
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.set_previous_close_price(10.0);
+        broker
+            .add_data(Some(local_history(&[
+                (timestamp + 120, Side::Sell, 10.0, 100.0),
+                (timestamp + 300, Side::Sell, 25.0, 100.0),
+            ])))
+            .unwrap();
+        for (order_id, price, qty) in [(1, 10.0, 300.0), (2, 9.98, 100.0)] {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                timestamp,
+                price,
+                qty,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            broker.submit_order(order_ref).unwrap();
+        }
+        broker.elapse(200).unwrap();
+        broker.sync_order_info();
+        let first = broker.orders().unwrap()[&1].clone();
+        let second = broker.orders().unwrap()[&2].clone();
+        assert_eq!(first.borrow().filled_qty, 100.0);
+
+        broker
+            .apply_corporate_action(CorporateAction::Split(2.0))
+            .unwrap();
+        {
+            let order = first.borrow();
+            assert_eq!(order.price, 5.0);
+            assert_eq!(order.qty, 600.0);
+            assert_eq!(order.filled_qty, 200.0);
+            assert_eq!(order.left_qty, 400.0);
+            assert_eq!(order.status, OrderStatus::New);
+        }
+        assert_eq!(second.borrow().price, 4.99);
+        assert_eq!(second.borrow().qty, 200.0);
+        assert_eq!(broker.previous_close_price, Some(5.0));
+
+        // 比例小于 1 时，剩余 2 手的订单调整为 0 手而被撤销
+        broker
+            .apply_corporate_action(CorporateAction::Split(0.2))
+            .unwrap();
+        assert_eq!(second.borrow().status, OrderStatus::Canceled);
+        assert!(!broker.market_depth.orders().contains_key(&2));
+        assert_eq!(broker.market_depth.bid_vol_at_tick(2495), 0);
+        {
+            let order = first.borrow();
+            assert_eq!(order.price, 25.0);
+            assert_eq!(order.filled_qty, 40.0);
+            assert_eq!(order.left_qty, 100.0);
+        }
+
+        // 之后的成交按调整后的数量计算
+        broker.elapse(200).unwrap();
+        broker.sync_order_info();
+        let order = first.borrow();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.filled_qty, 140.0);
+        assert_eq!(order.left_qty, 0.0);
+    }
+
+    #[test]
+    /// 测试修改部分成交的订单。
+    /// 验证修改后的数量不能小于已成交数量，修改成功时剩余数量为新数量减去已成交数量。
+    fn test_modify_partially_filled_order() {
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        // 历史卖单在 120 毫秒后以 10.00 卖出 40 手
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 120).unwrap();
+        let history = local_history(&[(sell_time, Side::Sell, 10.0, 4000.0)]);
+        broker.add_data(Some(history)).unwrap();
+
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            timestamp,
+            10.0,
+            10000.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 100;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(200).unwrap();
+        broker.sync_order_info();
+        assert_eq!(order_ref.borrow().filled_qty, 4000.0);
+
+        assert_eq!(
+            broker.modify_order(100, 10.0, 3000.0),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(order_ref.borrow().qty, 10000.0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 60);
+
+        assert_eq!(broker.modify_order(100, 10.0, 5000.0), Ok(0));
+        let order = order_ref.borrow();
+        assert_eq!(order.qty, 5000.0);
+        assert_eq!(order.filled_qty, 4000.0);
+        assert_eq!(order.left_qty, 1000.0);
+        assert_eq!(order.status, OrderStatus::New);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 10);
+    }
+
+    #[test]
+    /// 测试修改后价格达到对手方最优价的订单。
+    /// 验证修改后的订单先与对手方撮合再挂出剩余部分，订单簿不会交叉，超出涨跌停价的修改被拒绝。
+    fn test_modify_order_matches_across_spread() {
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.set_previous_close_price(10.0);
+        broker.set_price_limit(0.1, PriceLimitPolicy::default());
+        // 历史卖单在 50 毫秒后以 10.05 挂出 20 手
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 50).unwrap();
+        let history = local_history(&[(sell_time, Side::Sell, 10.05, 2000.0)]);
+        broker.add_data(Some(history)).unwrap();
+
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            timestamp,
+            10.0,
+            3000.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 100;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(100).unwrap();
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 30);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1005), 20);
+
+        assert_eq!(
+            broker.modify_order(100, 11.01, 3000.0),
+            Err(MarketError::PriceOutOfLimit)
+        );
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 30);
+
+        // 改价到 10.05 后吃掉 20 手卖单，剩余 10 手挂在 10.05
+        assert_eq!(broker.modify_order(100, 10.05, 3000.0), Ok(0));
+        {
+            let order = order_ref.borrow();
+            assert_eq!(order.status, OrderStatus::PartiallyFilled);
+            assert_eq!(order.filled_qty, 2000.0);
+            assert_eq!(order.filled_qty_aggressive, 2000.0);
+            assert_eq!(order.left_qty, 1000.0);
+            assert_eq!(order.price, 10.05);
+        }
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1005), 10);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1005), 0);
+        let source = OrderSourceType::LocalOrder;
+        assert_eq!(broker.market_depth.best_bid_tick(&source), 1005);
+        assert!(broker.market_depth.best_ask_tick(&source) > 1005);
+        assert_eq!(broker.user_fills.len(), 1);
+        assert_eq!(broker.user_fills[0].liquidity, FillLiquidity::Aggressive);
+        assert_eq!(broker.user_fills[0].price, 10.05);
+    }
+
+    #[test]
+    fn test_processor_cancel_marks_order_canceled() {
+        let timestamp = 20231201100000000;
+        let (mut broker, order_ref) = cancel_race_broker(timestamp, 0);
+        let processor: &mut dyn Processor = &mut broker;
+        // 请求时间早于经纪人当前时间，按当前时间立即生效
+        processor.cancel(100, timestamp).unwrap();
+        assert_eq!(
+            processor.cancel(100, timestamp),
+            Err(MarketError::OrderNotFound)
+        );
+        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        assert!(orders.contains_key(&100));
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+
+        // 有撤单延迟时撤单按模拟时间排队，生效前到达的成交仍然执行
+        let (mut broker, order_ref) = cancel_race_broker(timestamp, 50);
+        let processor: &mut dyn Processor = &mut broker;
+        processor.cancel(100, timestamp + 110).unwrap();
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+        assert_eq!(broker.pending_cancels.len(), 1);
+        broker.elapse(60).unwrap();
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        assert!(orders.contains_key(&100));
+        let order = order_ref.borrow();
+        assert_eq!(order.status, OrderStatus::Canceled);
+        assert_eq!(order.filled_qty, 100.0);
+    }
+
+    #[test]
+    /// 测试通过 `Processor` 撤销尚未得到确认的订单。
+    /// 验证撤单不进入撤单等待队列，而是按 `pending_ack_cancel` 在确认时生效或者被拒绝。
+    fn test_processor_cancel_pending_ack() {
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 120).unwrap();
+        broker
+            .add_data(Some(local_history(&[(sell_time, Side::Sell, 10.0, 100.0)])))
+            .unwrap();
+        broker.set_ack_latency(5);
+        broker.set_cancel_latency(50);
+
+        // 确认前的撤单在 T+5 确认时生效，不等待撤单延迟
+        let processor: &mut dyn Processor = &mut broker;
+        processor
+            .submit_order(100, Side::Buy, 10.0, 300.0, OrderType::L, timestamp)
+            .unwrap();
+        processor.cancel(100, timestamp + 2).unwrap();
+        assert!(broker.pending_cancels.is_empty());
+        assert_eq!(broker.ack_cancels, vec![100]);
+        broker.elapse(5).unwrap();
+        {
+            let order = broker.orders().unwrap()[&100].borrow();
+            assert_eq!(order.status, OrderStatus::Canceled);
+            assert_eq!(order.ack_time, Some(20231201100000005));
+        }
+        assert!(broker.ack_cancels.is_empty());
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+
+        // 设置为拒绝时，确认前的撤单返回错误，订单确认后进入订单簿
+        broker.set_pending_ack_cancel(PendingAckCancel::Reject);
+        let processor: &mut dyn Processor = &mut broker;
+        processor
+            .submit_order(101, Side::Buy, 10.0, 300.0, OrderType::L, timestamp + 5)
+            .unwrap();
+        assert_eq!(
+            processor.cancel(101, timestamp + 5),
+            Err(MarketError::OrderRequestInProcess)
+        );
+        broker.elapse(5).unwrap();
+        assert!(broker.pending_cancels.is_empty());
+        assert_eq!(
+            broker.orders().unwrap()[&101].borrow().status,
+            OrderStatus::New
+        );
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 3);
+    }
+
+    #[test]
+    fn test_strategy_summary() {
+        let timestamp = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(timestamp)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        let sell_time = adjust_timestamp_milliseconds_i64(timestamp, 120).unwrap();
+        broker
+            .add_data(Some(local_history(&[
+                (sell_time, Side::Sell, 10.0, 300.0),
+                (timestamp + 130, Side::Sell, 10.01, 100.0),
+            ])))
+            .unwrap();
+
+        // 两个策略在同一价位排队，策略 1 另有一笔不会成交的买单；
+        // 策略 2 之后以 10.05 的限价主动买入挂在 10.01 的历史卖单
+        let orders = [
+            (100, 1, timestamp, 10.0, 100.0),
+            (101, 2, timestamp, 10.0, 200.0),
+            (102, 1, timestamp, 9.99, 100.0),
+            (103, 2, timestamp + 150, 10.05, 100.0),
+        ];
+        for (order_id, strategy_id, local_time, price, qty) in orders {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                local_time,
+                price,
+                qty,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                order.order_id = order_id;
+                order.strategy_id = strategy_id;
+            }
+            broker.submit_order(order_ref).unwrap();
+        }
+        broker.elapse(200).unwrap();
+        broker.sync_order_info();
+
+        let summaries = broker.strategy_summary();
+        assert_eq!(summaries.len(), 2);
+        let first = summaries[&1];
+        assert_eq!(first.order_count, 2);
+        assert_eq!(first.filled_qty, 100.0);
+        assert_eq!(first.filled_qty_passive, 100.0);
+        assert_eq!(first.avg_price, 10.0);
+        let second = summaries[&2];
+        assert_eq!(second.order_count, 2);
+        assert_eq!(second.filled_qty, 300.0);
+        assert_eq!(second.filled_qty_aggressive, 100.0);
+        // 主动成交按卖单的价格 10.01 而不是限价 10.05 计算均价
+        assert!((second.avg_price - 3001.0 / 300.0).abs() < 1e-9);
+
+        // 成交回报按策略拆分后与各策略的汇总一致
+        for (strategy_id, summary) in summaries.iter() {
+            let fills = broker.strategy_fills(*strategy_id);
+            assert!(fills.iter().all(|fill| fill.strategy_id == *strategy_id));
+            let qty: f64 = fills.iter().map(|fill| fill.qty).sum();
+            assert_eq!(qty, summary.filled_qty);
+        }
+        let mut prices: Vec<f64> = broker
+            .strategy_fills(2)
+            .iter()
+            .map(|fill| fill.price)
+            .collect();
+        prices.sort_by(f64::total_cmp);
+        assert_eq!(prices, vec![10.0, 10.01]);
+
+        // 各策略的成交量之和等于全部用户订单的成交量
+        let total: f64 = broker
+            .orders()
+            .unwrap()
+            .values()
+            .map(|order_ref| order_ref.borrow().filled_qty)
+            .sum();
+        assert_eq!(first.filled_qty + second.filled_qty, total);
+    }
+
+    #[test]
+    fn test_phantom_orders() {
+        let timestamp = 20231201100000000;
+        for mode in [ExchangeMode::Live, ExchangeMode::Backtest] {
+            let (mut broker, _) = BookFixture::new(mode)
+                .timestamp(timestamp)
+                .open(10.0)
+                .build_broker()
+                .unwrap();
+            broker.add_data(Some(local_history(&[]))).unwrap();
+
+            assert_eq!(
+                broker.inject_phantom_order(Side::Sell, 10.01, 200.0),
+                Ok(-1)
+            );
+            assert_eq!(
+                broker.inject_phantom_order(Side::Sell, 10.02, 300.0),
+                Ok(-2)
+            );
+            assert_eq!(
+                broker.inject_phantom_order(Side::Sell, 10.03, 0.0),
+                Err(MarketError::InvalidOrderRequest)
+            );
+            assert_eq!(broker.phantom_orders.len(), 2);
+            assert!(broker.market_depth.has_phantoms());
+
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                timestamp,
+                10.02,
+                400.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = 100;
+            broker.submit_order(order_ref.clone()).unwrap();
+            broker.elapse(10).unwrap();
+            broker.sync_order_info();
+
+            // 用户买单吃掉 10.01 的假设挂单，并在 10.02 部分成交
+            let order = order_ref.borrow();
+            assert_eq!(order.status, OrderStatus::Filled, "{mode:?}");
+            assert_eq!(order.filled_qty, 400.0);
+            drop(order);
+            // 与假设挂单的成交、假设挂单的挂单都不计入市场统计
+            let statistics = broker.market_depth.get_statistics();
+            assert_eq!(statistics.total_bid_vol + statistics.total_ask_vol, 0);
+            assert_eq!(statistics.total_ask_order, 0);
+
+            // 只剩 10.02 的假设挂单被撤销
+            assert_eq!(broker.clear_phantom_orders(), 1);
+            assert!(broker.phantom_orders.is_empty());
+            assert!(!broker.market_depth.has_phantoms());
+            assert!(!broker.market_depth.orders().contains_key(&-1));
+            assert!(!broker.market_depth.orders().contains_key(&-2));
+            assert_eq!(broker.market_depth.ask_vol_at_tick(1002), 0);
+            assert_eq!(broker.market_depth.get_statistics().total_cancel_count, 0);
+        }
+    }
+
+    #[test]
+    fn test_sh_auction_orders_rest_until_cross() {
+        let at = |time: i64| 20231201000000000 + time;
+        let history = [
+            (at(92000000), Side::Sell, 10.0, 200.0),
+            // 集合竞价期间可以与卖单成交的买单
+            (at(92100000), Side::Buy, 10.05, 100.0),
+            (at(92200000), Side::Buy, 10.02, 100.0),
+            // 连续竞价的第一笔委托触发开盘集合竞价
+            (at(93000100), Side::Sell, 10.5, 100.0),
+        ];
+        for mode in [ExchangeMode::Live, ExchangeMode::Backtest] {
+            let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+                mode,
+                MarketType::SH,
+                "STOCK".to_string(),
+                "600000.SH".to_string(),
+                0.01,
+                100.0,
+            );
+            broker.init();
+            broker.set_current_time(at(91500000));
+            broker.add_data(Some(local_history(&history))).unwrap();
+            broker.goto(at(92400000)).unwrap();
+
+            // 集合竞价期间的订单只挂单，不连续撮合
+            let depth = &broker.market_depth;
+            assert_eq!(broker.open_tick, 0);
+            assert_eq!(depth.best_bid_tick(&OrderSourceType::LocalOrder), 1005);
+            assert_eq!(depth.best_ask_tick(&OrderSourceType::LocalOrder), 1000);
+            assert_eq!(depth.ask_vol_at_tick(1000), 2);
+            assert_eq!(depth.last_tick(&OrderSourceType::LocalOrder), INVALID_MIN);
+
+            // 离开集合竞价时段后按单一的竞价价格撮合，可成交的买单不按自身价格或卖单价格成交。
+            // 买卖量在 10.00 到 10.02 之间平衡，竞价价格取两者的中间价
+            broker.goto(at(93001000)).unwrap();
+            let depth = &broker.market_depth;
+            assert_eq!(broker.open_tick, 1001);
+            assert_eq!(depth.last_tick(&OrderSourceType::LocalOrder), 1001);
+            assert_eq!(depth.ask_vol_at_tick(1000), 0);
+            assert_eq!(depth.bid_vol_at_tick(1005), 0);
+            assert_eq!(
+                depth.best_bid_tick(&OrderSourceType::LocalOrder),
+                INVALID_MIN
+            );
+            assert_eq!(depth.best_ask_tick(&OrderSourceType::LocalOrder), 1050);
+        }
+    }
+
+    #[test]
+    fn test_submit_order_invalid_price_qty() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+
+        let order_ref = Order::new_ref(
+            None,
+            "CODE".to_string(),
+            20231201093021355,
+            0.0,
+            100.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        assert_eq!(
+            broker.submit_order(order_ref),
+            Err(MarketError::InvalidOrderRequest)
+        );
+
+        let order_ref = Order::new_ref(
+            None,
+            "CODE".to_string(),
+            20231201093021355,
+            10.0,
+            -100.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 2;
+        assert_eq!(
+            broker.submit_order(order_ref),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert!(broker.orders().unwrap().is_empty());
+        assert!(broker.pending_orders.is_empty());
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        broker.set_previous_close_price(10.0);
+        broker.set_price_limit(0.1, PriceLimitPolicy::default());
+        let new_order = |order_id: OrderId, price: f64| {
+            let order_ref = Order::new_ref(
+                None,
+                "CODE".to_string(),
+                20231201093021355,
+                price,
+                100.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+
+        assert_eq!(
+            broker.validate_order(&new_order(1, 10.005)),
+            Err(MarketError::PriceNotOnTick)
+        );
+        assert_eq!(
+            broker.validate_order(&new_order(2, 11.01)),
+            Err(MarketError::PriceOutOfLimit)
+        );
+        let order_ref = new_order(3, 10.01);
+        assert_eq!(broker.validate_order(&order_ref), Ok(()));
+        // 检查不会把订单加入队列，也不会修改订单
+        assert!(broker.orders().unwrap().is_empty());
+        assert!(broker.pending_orders.is_empty());
+        assert!(broker.waiting_orders.is_empty());
+        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+
+        // 提交时按同样的规则检查
+        assert_eq!(
+            broker.submit_order(new_order(1, 10.005)),
+            Err(MarketError::PriceNotOnTick)
+        );
+        broker.submit_order(order_ref.clone()).unwrap();
+        assert_eq!(
+            broker.validate_order(&order_ref),
+            Err(MarketError::OrderIdExist)
+        );
+        assert_eq!(broker.orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_skips_corrupt_order() {
+        fn create_local_order(
+            order_id: OrderId,
+            side: Side,
+            seq: i64,
+            timestamp: i64,
+            price: f64,
+            qty: f64,
+        ) -> L3OrderRef {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                0,
+                0,
+                timestamp,
+                OrderType::L,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                auxiliary_info.initial_price = price;
+                auxiliary_info.initial_qty = qty;
+                auxiliary_info.initial_seq = seq;
+            }
+            order_ref
+        }
+
+        let timestamp = 20231201093000000;
+        let mut data = DataCollator::new(
+            "000001.SZ".to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "ORDER",
+        );
+        let mut orders = HashMap::new();
+        let mut index_by_seq = VecDeque::new();
+        for (order_id, side, price, qty) in [
+            (1, Side::Buy, 10.0, 100.0),
+            (2, Side::Sell, 10.5, -100.0),
+            (3, Side::Sell, 10.5, 200.0),
+        ] {
+            let order_time = adjust_timestamp_milliseconds_i64(timestamp, order_id * 10).unwrap();
+            orders.insert(
+                order_id,
+                create_local_order(order_id, side, order_id, order_time, price, qty),
+            );
+            index_by_seq.push_back((order_id, order_id));
+        }
+        data.len = index_by_seq.len();
+        data.orders = Some(orders);
+        data.index_by_seq = Some(index_by_seq);
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .stock(MarketType::SZ, "000001.SZ")
+            .timestamp(timestamp)
+            // 跳过开盘集合竞价，只验证连续竞价阶段的回放
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(data)).unwrap();
+        broker
+            .goto(adjust_timestamp_milliseconds_i64(timestamp, 1000).unwrap())
+            .unwrap();
+
+        assert_eq!(broker.skipped_orders, vec![(2, 2)]);
+        assert_eq!(
+            broker
+                .market_depth
+                .best_bid_tick(&OrderSourceType::LocalOrder),
+            1000
+        );
+        assert_eq!(
+            broker
+                .market_depth
+                .best_ask_tick(&OrderSourceType::LocalOrder),
+            1050
+        );
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1050), 2);
+    }
+
+    #[test]
+    fn test_l2p_reconstruction_matches_snapshots() {
+        let timestamp = 20231201093000000;
+        let mut data = DataCollator::new(
+            "000001.SZ".to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "L2P",
+        );
+        let mut orders = HashMap::new();
+        let mut index_by_seq = VecDeque::new();
+        // (订单 ID, 方向, 委托价, 委托量, 成交价, 成交量, 挂单价, 挂单量)
+        for (order_id, side, price, qty, match_price, match_qty, book_price, book_qty) in [
+            (1, Side::Buy, 10.0, 300.0, 0.0, 0.0, 10.0, 300.0),
+            (2, Side::Sell, 10.0, 100.0, 10.0, 100.0, 0.0, 0.0),
+            (3, Side::Sell, 10.1, 200.0, 0.0, 0.0, 10.1, 200.0),
+            (4, Side::Buy, 10.1, 300.0, 10.1, 200.0, 10.1, 100.0),
+        ] {
+            let order_time = adjust_timestamp_milliseconds_i64(timestamp, order_id * 10).unwrap();
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                0,
+                0,
+                order_time,
+                OrderType::L,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                auxiliary_info.initial_price = price;
+                auxiliary_info.initial_qty = qty;
+                auxiliary_info.initial_seq = order_id;
+                auxiliary_info.match_price = match_price;
+                auxiliary_info.match_qty = match_qty;
+                auxiliary_info.orderbook_price = book_price;
+                auxiliary_info.orderbook_qty = book_qty;
+            }
+            orders.insert(order_id, order_ref);
+            index_by_seq.push_back((order_id, order_id));
+        }
+        data.len = index_by_seq.len();
+        data.orders = Some(orders);
+        data.index_by_seq = Some(index_by_seq);
+
+        let snapshot_time =
+            |offset: i64| adjust_timestamp_milliseconds_i64(timestamp, offset).unwrap();
+        let snapshots = vec![
+            L2Snapshot {
+                timestamp: snapshot_time(15),
+                bid_levels: vec![(10.0, 300.0)],
+                ask_levels: vec![],
+            },
+            L2Snapshot {
+                timestamp: snapshot_time(20),
+                bid_levels: vec![(10.0, 200.0)],
+                ask_levels: vec![],
+            },
+            L2Snapshot {
+                timestamp: snapshot_time(35),
+                bid_levels: vec![(10.0, 200.0)],
+                ask_levels: vec![(10.1, 200.0)],
+            },
+            L2Snapshot {
+                timestamp: snapshot_time(500),
+                bid_levels: vec![(10.1, 100.0), (10.0, 200.0)],
+                ask_levels: vec![],
+            },
+        ];
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .stock(MarketType::SZ, "000001.SZ")
+            .timestamp(timestamp)
+            // 跳过开盘集合竞价，只验证连续竞价阶段的回放
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.set_validator(snapshots, 5);
+        broker.add_data(Some(data)).unwrap();
+        broker.goto(snapshot_time(1000)).unwrap();
+
+        let validator = broker.validator.as_ref().unwrap();
+        assert_eq!(validator.checked(), 4);
+        assert!(
+            broker.divergences().is_empty(),
+            "{:?}",
+            broker.divergences()
+        );
+    }
+
+    #[test]
+    fn test_market_order_rests_at_limit_up() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        broker.set_previous_close_price(10.0);
+        broker.set_price_limit(0.1, PriceLimitPolicy::RestAtLimit);
+        assert_eq!(broker.limit_up_tick(), Some(1100));
+        assert_eq!(broker.limit_down_tick(), Some(900));
+
+        let timestamp = 20231201100000000;
+        broker.set_current_time(timestamp);
+        // 涨停：买方排在涨停价上，卖方没有流动性
+        broker
+            .process_order(L3Order::new_ref(
+                OrderSourceType::UserOrder,
+                None,
+                1,
+                Side::Buy,
+                1100,
+                3,
+                timestamp,
+                OrderType::L,
+            ))
+            .unwrap();
+
+        let market_buy = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            2,
+            Side::Buy,
+            0,
+            2,
+            timestamp,
+            OrderType::C,
+        );
+        assert_eq!(broker.process_order(market_buy.clone()), Ok(0));
+        assert_eq!(market_buy.borrow().price_tick, 1100);
+        assert_eq!(market_buy.borrow().vol, 2);
+        assert_eq!(
+            broker
+                .market_depth
+                .best_bid_tick(&OrderSourceType::UserOrder),
+            1100
+        );
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1100), 5);
+
+        // 以涨停价卖出的限价单与买单成交
+        let limit_sell = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            3,
+            Side::Sell,
+            1100,
+            4,
+            timestamp,
+            OrderType::L,
+        );
+        assert_eq!(broker.process_order(limit_sell.clone()), Ok(4));
+        assert_eq!(limit_sell.borrow().vol, 0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1100), 1);
+        assert_eq!(market_buy.borrow().vol, 1);
+    }
+
+    #[test]
+    fn test_same_time_priority_is_slice_independent() {
+        fn create_history(timestamp: i64) -> DataCollator {
+            let mut data = DataCollator::new(
+                "600000.SH".to_string(),
+                "vector".to_string(),
+                "".to_string(),
+                "20231201".to_string(),
+                "ORDER",
+            );
+            let mut orders = HashMap::new();
+            let mut index_by_seq = VecDeque::new();
+            // 卖单先挂出，随后在用户订单到达的同一毫秒有一笔历史买单
+            for (seq, side, offset) in [(1, Side::Sell, 100), (2, Side::Buy, 500)] {
+                let order_time = adjust_timestamp_milliseconds_i64(timestamp, offset).unwrap();
+                let order_ref = L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    seq,
+                    side,
+                    0,
+                    0,
+                    order_time,
+                    OrderType::L,
+                );
+                {
+                    let mut order = order_ref.borrow_mut();
+                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                    auxiliary_info.initial_price = 10.0;
+                    auxiliary_info.initial_qty = 100.0;
+                    auxiliary_info.initial_seq = seq;
+                }
+                orders.insert(seq, order_ref);
+                index_by_seq.push_back((seq, seq));
+            }
+            data.len = index_by_seq.len();
+            data.orders = Some(orders);
+            data.index_by_seq = Some(index_by_seq);
+            data
+        }
+
+        fn run(priority: SameTimePriority, latest_seq: i64, slices: &[i64]) -> (f64, i64) {
+            let timestamp = 20231201100000000;
+            let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+                .timestamp(timestamp)
+                // 跳过开盘集合竞价，只验证连续竞价阶段的回放
+                .open(10.0)
+                .build_broker()
+                .unwrap();
+            broker.set_same_time_priority(priority);
+            broker.latest_seq_number = latest_seq;
+            broker.add_data(Some(create_history(timestamp))).unwrap();
+
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                adjust_timestamp_milliseconds_i64(timestamp, 500).unwrap(),
+                10.0,
+                100.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = 100;
+            broker.submit_order(order_ref.clone()).unwrap();
+
+            for duration in slices {
+                broker.elapse(*duration).unwrap();
+                broker.sync_order_info();
+            }
+            let filled_qty = order_ref.borrow().filled_qty;
+            (filled_qty, broker.market_depth.bid_vol_at_tick(1000))
+        }
+
+        let one_slice = [1000];
+        let ten_slices = [100; 10];
+        for (priority, latest_seq, expected_filled) in [
+            (SameTimePriority::HistoryFirst, 0, 0.0),
+            (SameTimePriority::UserFirst, 0, 100.0),
+            // 用户订单序列号为 3，历史买单序列号为 2，历史买单先处理
+            (SameTimePriority::BySeq, 2, 0.0),
+            // 用户订单序列号为 1，先于历史买单处理
+            (SameTimePriority::BySeq, 0, 100.0),
+        ] {
+            let result = run(priority, latest_seq, &one_slice);
+            assert_eq!(
+                result,
+                run(priority, latest_seq, &ten_slices),
+                "{priority:?}"
+            );
+            assert_eq!(result, (expected_filled, 1), "{priority:?}");
+        }
+    }
+
+    #[test]
+    fn test_cancel_all_skips_terminal_orders() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let timestamp = 20231201100000000;
+        broker.set_current_time(timestamp);
+
+        let mut order_refs = Vec::new();
+        for (order_id, local_time) in [
+            (1, timestamp),
+            (2, timestamp),
+            (3, timestamp),
+            (
+                4,
+                adjust_timestamp_milliseconds_i64(timestamp, 5000).unwrap(),
+            ),
+        ] {
+            let order_ref = Order::new_ref(
+                None,
+                "CODE".to_string(),
+                local_time,
+                10.0,
+                10.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            broker.submit_order(order_ref.clone()).unwrap();
+            order_refs.push(order_ref);
+        }
+        broker.elapse(1000).unwrap();
+        broker.sync_order_info();
+
+        // 订单 2、3 已处于终止状态，订单 1 在订单簿中，订单 4 仍在等待队列中
+        order_refs[1].borrow_mut().status = OrderStatus::Filled;
+        order_refs[2].borrow_mut().status = OrderStatus::Rejected;
+
+        let results = broker.cancel_all(CancelFilter::default());
+        assert_eq!(
+            results,
+            vec![
+                (1, Ok(())),
+                (2, Err(MarketError::OrderNotFound)),
+                (3, Err(MarketError::OrderNotFound)),
+                (4, Ok(())),
+            ]
+        );
+        assert_eq!(order_refs[0].borrow().status, OrderStatus::Canceled);
+        assert_eq!(order_refs[1].borrow().status, OrderStatus::Filled);
+        assert_eq!(order_refs[2].borrow().status, OrderStatus::Rejected);
+        assert_eq!(order_refs[3].borrow().status, OrderStatus::Canceled);
+
+        let mut open_orders = HashMap::new();
+        broker.get_open_orders(&mut open_orders);
+        assert!(open_orders.is_empty());
+        assert!(broker
+            .cancel_all(CancelFilter::default())
+            .iter()
+            .all(|(_, result)| result.is_err()));
+    }
+
+    /// 测试同步订单信息时将已终止的用户订单从市场深度中移除
+    /// 仍挂在订单簿上的已终止订单同时从订单簿中撤出
+    #[test]
+    fn test_sync_order_info_removes_terminal_orders() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let timestamp = 20231201100000000;
+        broker.set_current_time(timestamp);
+
+        let mut order_refs = Vec::new();
+        for (order_id, price) in [(1, 10.0), (2, 9.9)] {
+            let order_ref = Order::new_ref(
+                None,
+                "CODE".to_string(),
+                timestamp,
+                price,
+                10.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            broker.submit_order(order_ref.clone()).unwrap();
+            order_refs.push(order_ref);
+        }
+        broker.elapse(1000).unwrap();
+        broker.sync_order_info();
+        assert_eq!(broker.market_depth.orders().len(), 2);
+
+        order_refs[0].borrow_mut().status = OrderStatus::Rejected;
+        broker.sync_order_info();
+        assert!(!broker.market_depth.orders().contains_key(&1));
+        assert!(broker.market_depth.orders().contains_key(&2));
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 9.9).abs() < 1e-9);
+
+        let mut terminal_orders = HashMap::new();
+        broker.get_terminal_orders(&mut terminal_orders);
+        assert_eq!(terminal_orders.keys().collect::<Vec<_>>(), vec![&1]);
+        let mut open_orders = HashMap::new();
+        broker.get_open_orders(&mut open_orders);
+        assert_eq!(open_orders.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn test_cancel_all_with_filter() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "CODE".to_string(),
+            0.01,
+            1.0,
+        );
+        broker.init();
+        let timestamp = 20231201100000000;
+        let later = adjust_timestamp_milliseconds_i64(timestamp, 500).unwrap();
+        broker.set_current_time(timestamp);
+
+        let mut order_refs = Vec::new();
+        for (order_id, account, local_time, price, bs_flag) in [
+            (1, "a", timestamp, 10.0, "Buy"),
+            (2, "a", timestamp, 10.5, "Buy"),
+            (3, "a", timestamp, 9.0, "Buy"),
+            (4, "b", timestamp, 10.2, "Buy"),
+            (5, "a", timestamp, 11.0, "Sell"),
+            (6, "a", later, 10.1, "Buy"),
+        ] {
+            let order_ref = Order::new_ref(
+                Some(account.to_string()),
+                "CODE".to_string(),
+                local_time,
+                price,
+                10.0,
+                bs_flag,
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            broker.submit_order(order_ref.clone()).unwrap();
+            order_refs.push(order_ref);
+        }
+        broker.elapse(1000).unwrap();
+        broker.sync_order_info();
+        // 订单 2 已经成交
+        order_refs[1].borrow_mut().status = OrderStatus::Filled;
+        broker.get_latest_orders(&mut HashMap::new());
+
+        let results = broker.cancel_all(CancelFilter {
+            account: Some("a".to_string()),
+            side: Some(Side::Buy),
+            price_range: Some((9.5, 10.5)),
+            older_than: Some(later),
+        });
+        assert_eq!(
+            results,
+            vec![(1, Ok(())), (2, Err(MarketError::OrderNotFound))]
+        );
+        assert_eq!(order_refs[0].borrow().status, OrderStatus::Canceled);
+        for idx in [2, 3, 4, 5] {
+            assert_eq!(order_refs[idx].borrow().status, OrderStatus::New);
+        }
+        // 被撤销的订单只被加入脏订单追踪器一次
+        assert_eq!(broker.dirty_tracker, vec![1]);
+    }
+
+    #[test]
+    fn test_event_callback_fires_for_every_history_event() {
+        fn record(object: &Rc<RefCell<dyn Any>>, event: &ReplayEvent) {
+            object
+                .borrow_mut()
+                .downcast_mut::<Vec<ReplayEvent>>()
+                .unwrap()
+                .push(*event);
+        }
+
+        let timestamp = 20231201100000000;
+        let mut data = DataCollator::new(
+            "600000.SH".to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "ORDER",
+        );
+        let mut orders = HashMap::new();
+        // 一笔卖单、一笔不成交的买单、一笔成交的买单，最后撤销不成交的买单
+        for (order_id, side, price) in [
+            (1, Side::Sell, 10.0),
+            (2, Side::Buy, 9.9),
+            (3, Side::Buy, 10.0),
+        ] {
+            let order_time = adjust_timestamp_milliseconds_i64(timestamp, order_id * 10).unwrap();
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                0,
+                0,
+                order_time,
+                OrderType::L,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                auxiliary_info.initial_price = price;
+                auxiliary_info.initial_qty = 100.0;
+                auxiliary_info.initial_seq = order_id;
+            }
+            orders.insert(order_id, order_ref);
+        }
+        orders[&2]
+            .borrow_mut()
+            .auxiliary_info
+            .as_mut()
+            .unwrap()
+            .cancel_seq = 4;
+        let index_by_seq: VecDeque<(i64, i64)> =
+            VecDeque::from(vec![(1, 1), (2, 2), (3, 3), (4, 2)]);
+        data.len = index_by_seq.len();
+        data.orders = Some(orders);
+        data.index_by_seq = Some(index_by_seq.clone());
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(timestamp)
+            // 跳过开盘集合竞价，只验证连续竞价阶段的回放
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        let events: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<ReplayEvent>::new()));
+        broker.set_event_callback(events.clone(), record);
+        broker.add_data(Some(data)).unwrap();
+        broker
+            .goto(adjust_timestamp_milliseconds_i64(timestamp, 1000).unwrap())
+            .unwrap();
+
+        let events = events.borrow();
+        let events = events.downcast_ref::<Vec<ReplayEvent>>().unwrap();
+        assert_eq!(events.len(), index_by_seq.len());
+        for (event, &(seq, order_id)) in events.iter().zip(index_by_seq.iter()) {
+            assert_eq!(event.seq, seq);
+            assert_eq!(event.order_id, order_id);
+        }
+        let order_types: Vec<OrderType> = events.iter().map(|event| event.order_type).collect();
+        assert_eq!(
+            order_types,
+            vec![OrderType::L, OrderType::L, OrderType::L, OrderType::Cancel]
+        );
+        assert_eq!(
+            events[0].timestamp,
+            adjust_timestamp_milliseconds_i64(timestamp, 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_star_market_lot_rule() {
+        let timestamp = 20231201100000000;
+        let mut data = DataCollator::new(
+            "688007.SH".to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "ORDER",
+        );
+        // 历史数据中 203 股的卖单按同样的规则加载
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            1,
+            Side::Sell,
+            0,
+            0,
+            adjust_timestamp_milliseconds_i64(timestamp, 10).unwrap(),
+            OrderType::L,
+        );
+        {
+            let mut order = order_ref.borrow_mut();
+            let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.initial_price = 10.0;
+            auxiliary_info.initial_qty = 203.0;
+            auxiliary_info.initial_seq = 1;
+        }
+        data.len = 1;
+        data.orders = Some(HashMap::from([(1, order_ref)]));
+        data.index_by_seq = Some(VecDeque::from(vec![(1, 1)]));
+
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "688007.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        assert_eq!(
+            broker.lot_rule,
+            LotRule::MinPlusIncrement {
+                min: 200.0,
+                increment: 1.0
+            }
+        );
+        assert_eq!(broker.lot_size, 1.0);
+        broker.init();
+        broker.set_current_time(timestamp);
+        // 跳过开盘集合竞价，只验证连续竞价阶段的回放
+        broker.open_tick = 1000;
+        broker.add_data(Some(data)).unwrap();
+        broker.elapse(100).unwrap();
+        assert!(broker.skipped_orders.is_empty());
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 203);
+
+        let create_order = |order_id: OrderId, qty: f64| {
+            let order_ref = Order::new_ref(
+                None,
+                "688007.SH".to_string(),
+                broker.timestamp,
+                10.0,
+                qty,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+        let odd_order = create_order(1, 50.0);
+        let star_order = create_order(2, 203.0);
+        assert_eq!(
+            broker.submit_order(odd_order),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        broker.submit_order(star_order.clone()).unwrap();
+        broker.elapse(100).unwrap();
+        broker.sync_order_info();
+        assert_eq!(star_order.borrow().filled_qty, 203.0);
+    }
+
+    #[test]
+    fn test_board_lot_rule_unchanged() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        assert_eq!(broker.lot_rule, LotRule::BoardLot { lot: 100.0 });
+        assert_eq!(broker.lot_size, 100.0);
+        broker.init();
+        broker.set_current_time(20231201100000000);
+
+        // 非整手的数量仍按整手四舍五入，不会被拒绝
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            broker.timestamp,
+            10.0,
+            250.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        broker.submit_order(order_ref).unwrap();
+        broker.elapse(100).unwrap();
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 3);
+    }
+
+    #[test]
+    fn test_order_user_data() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        broker.set_current_time(20231201100000000);
+
+        for (order_id, bs_flag) in [(1, "Sell"), (2, "Buy")] {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                broker.timestamp,
+                10.0,
+                100.0,
+                bs_flag,
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            if order_id == 2 {
+                order_ref.borrow_mut().user_data =
+                    Some(serde_json::json!({"slice": 3, "signal": "s1"}));
+            }
+            broker.submit_order(order_ref).unwrap();
+        }
+        broker.elapse(100).unwrap();
+        broker.sync_order_info();
+
+        // 成交回报中保留提交时设置的自定义数据
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        let order = orders[&2].borrow();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(
+            order.user_data,
+            Some(serde_json::json!({"slice": 3, "signal": "s1"}))
+        );
+        let exported = serde_json::to_value(&*order).unwrap();
+        assert_eq!(exported["user_data"]["signal"], "s1");
+        drop(order);
+
+        let filled_qty = broker
+            .with_order_mut(2, |meta| {
+                meta.set(serde_json::json!({"slice": 4}));
+                meta.order().filled_qty
+            })
+            .unwrap();
+        assert_eq!(filled_qty, 100.0);
+        assert_eq!(
+            orders[&2].borrow().user_data,
+            Some(serde_json::json!({"slice": 4}))
+        );
+        assert_eq!(
+            broker.with_order_mut(99, |meta| meta.take()),
+            Err(MarketError::OrderNotFound)
+        );
+    }
 
-        while self.timestamp <= time_point {
-            if self.history.as_ref().unwrap().is_last() {
-                end_of_history = true;
-                break;
-            }
+    /// 测试订单表被置为 `None` 时访问订单不会 panic
+    /// `orders` 返回错误，修改订单的方法重新创建空的订单表
+    #[test]
+    fn test_orders_not_initialized() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        broker.orders = None;
+        assert_eq!(
+            broker.orders().err(),
+            Some(MarketError::OrdersNotInitialized)
+        );
+        assert_eq!(broker.order_fee(1), Err(MarketError::OrdersNotInitialized));
+        assert_eq!(broker.total_fees(), 0.0);
+        assert!(broker.strategy_summary().is_empty());
 
-            let (seq, order_ref) = self.history.as_mut().unwrap().next().unwrap();
-            order_ref.borrow_mut().seq = seq;
-            debug!("history order info {order_ref:?}");
+        assert_eq!(
+            broker.with_order_mut(1, |meta| meta.take()),
+            Err(MarketError::OrderNotFound)
+        );
+        assert!(broker.orders().unwrap().is_empty());
+    }
 
-            self.timestamp = order_ref.borrow().timestamp.clone();
-            let order_ref_arg = order_ref.clone();
-            if !is_in_call_auction(self.timestamp, self.market_type).unwrap_or(false)
-                && self.open_tick == 0
-            {
-                (self.open_tick, _) = self.market_depth.call_auction().unwrap_or((0, 0));
+    #[test]
+    fn test_order_type_rejected_by_phase() {
+        use OrderType::{B, C, D, L, M, N};
+        let order_types = [L, M, N, B, C, D];
+        // (时间, 交易时段, 按 order_types 顺序是否接受)
+        let table = [
+            (
+                20231201092000000,
+                TradingPhase::OpeningAuction,
+                [true, false, false, false, false, false],
+            ),
+            (
+                20231201100000000,
+                TradingPhase::Continuous,
+                [true, true, true, true, true, true],
+            ),
+            (
+                20231201145800000,
+                TradingPhase::ClosingAuction,
+                [true, false, false, false, false, false],
+            ),
+        ];
+        for (market_type, stock_code) in
+            [(MarketType::SH, "600000.SH"), (MarketType::SZ, "000001.SZ")]
+        {
+            for (timestamp, phase, accepted) in table {
+                assert_eq!(
+                    TradingPhase::from_timestamp(timestamp, market_type),
+                    Ok(phase)
+                );
+                for (idx, order_type) in order_types.into_iter().enumerate() {
+                    let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+                        ExchangeMode::Backtest,
+                        market_type,
+                        "stock".to_string(),
+                        stock_code.to_string(),
+                        0.01,
+                        100.0,
+                    );
+                    broker.init();
+                    broker.set_current_time(timestamp);
+                    let order_ref = Order::new_ref(
+                        None,
+                        stock_code.to_string(),
+                        timestamp,
+                        10.0,
+                        100.0,
+                        "Buy",
+                        order_type,
+                        OrderSourceType::UserOrder,
+                    );
+                    let result = broker.submit_order(order_ref.clone());
+                    assert_eq!(phase.accepts(order_type), accepted[idx]);
+                    if accepted[idx] {
+                        assert!(
+                            result.is_ok(),
+                            "{:?} {:?} {:?}",
+                            market_type,
+                            phase,
+                            order_type
+                        );
+                        assert_eq!(order_ref.borrow().status, OrderStatus::New);
+                    } else {
+                        assert_eq!(result, Err(MarketError::OrderTypeUnsupported));
+                        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+                        assert!(broker.orders.as_ref().unwrap().is_empty());
+                    }
+                }
             }
-
-            let filled = self.process_order(order_ref_arg)?;
         }
-        self.timestamp = time_point;
-        if should_call_auction_on_close(self.timestamp, self.market_type)? && self.close_tick == 0 {
-            let (close_tick, _) = self.market_depth.call_auction().unwrap_or((0, 0));
-            self.close_tick = close_tick;
+        assert_eq!(
+            TradingPhase::from_timestamp(20231201120000000, MarketType::SH),
+            Ok(TradingPhase::Break)
+        );
+        // 14:57:00.000 已经属于收盘集合竞价
+        for market_type in [MarketType::SH, MarketType::SZ] {
+            assert_eq!(
+                TradingPhase::from_timestamp(20231201145659999, market_type),
+                Ok(TradingPhase::Continuous)
+            );
+            assert_eq!(
+                TradingPhase::from_timestamp(20231201145700000, market_type),
+                Ok(TradingPhase::ClosingAuction)
+            );
         }
-        Ok(end_of_history)
+        assert!(TradingPhase::OpeningAuction.accepts(OrderType::Cancel));
+
+        // 集合竞价阶段到达的市价订单在撮合时同样被拒绝
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SZ,
+            "stock".to_string(),
+            "000001.SZ".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        broker.set_current_time(20231201092000000);
+        let l3order_ref = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            1,
+            Side::Buy,
+            1000,
+            1,
+            20231201092000000,
+            OrderType::M,
+        );
+        assert_eq!(
+            broker.process_order(l3order_ref),
+            Err(MarketError::OrderTypeUnsupported)
+        );
+        assert!(broker.market_depth.orders().is_empty());
     }
 
-    /// 尝试通过订单 ID 取消订单。如果在内部订单列表中找到该订单，
-    /// 将其状态标记为已取消。如果未找到，则尝试在市场深度中取消该订单。
-    ///
-    /// # 参数
-    ///
-    /// * `order_id` - 要取消的订单的 ID。
-    ///
-    /// # 返回值
-    ///
-    /// * 如果操作成功，返回 `Ok(0)`。
-    /// * 如果找不到订单或在取消市场深度中的订单时发生错误，返回 `Err(MarketError)`。
-    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<i64, MarketError> {
-        let _ = self.market_depth.cancel_order(order_id);
+    #[test]
+    /// 测试上海和深圳市场的收盘集合竞价。
+    /// 验证 14:58 到达的主动历史买单只挂单，在 15:00 收盘竞价时成交；
+    /// 深圳市场在收盘集合竞价期间拒绝用户撤单并通知生命周期回调，上海市场仍然接受。
+    fn test_closing_auction_by_market() {
+        fn record(object: &Rc<RefCell<dyn Any>>, event: &OrderLifecycle) {
+            object
+                .borrow_mut()
+                .downcast_mut::<Vec<OrderLifecycle>>()
+                .unwrap()
+                .push(*event);
+        }
 
-        Ok(0)
-    }
+        for (market_type, stock_code) in
+            [(MarketType::SZ, "000001.SZ"), (MarketType::SH, "600000.SH")]
+        {
+            let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+                .stock(market_type, stock_code)
+                .timestamp(20231201145500000)
+                .open(10.0)
+                .build_broker()
+                .unwrap();
+            broker
+                .add_data(Some(local_history(&[
+                    (20231201145600000, Side::Sell, 10.0, 100.0),
+                    (20231201145800000, Side::Buy, 10.0, 100.0),
+                ])))
+                .unwrap();
+            let events: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<OrderLifecycle>::new()));
+            broker.set_lifecycle_callback(events.clone(), record);
+            // 不会成交的用户买单，用于验证收盘集合竞价期间的撤单
+            let order_ref = Order::new_ref(
+                None,
+                stock_code.to_string(),
+                20231201145500000,
+                9.9,
+                100.0,
+                "Buy",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = 100;
+            broker.submit_order(order_ref.clone()).unwrap();
 
-    pub fn cancel_order_from_ref(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
-        let _ = self.market_depth.cancel_order_from_ref(order_ref);
+            // 推进到 14:59
+            broker.elapse(240000).unwrap();
+            // 买单在收盘集合竞价期间到达，与卖单价格交叉但没有成交
+            let source = OrderSourceType::LocalOrder;
+            assert_eq!(broker.market_depth.best_bid_tick(&source), 1000);
+            assert_eq!(broker.market_depth.best_ask_tick(&source), 1000);
+            assert_ne!(broker.market_depth.last_tick(&source), 1000);
+            assert_eq!(broker.close_tick, 0);
+            assert_eq!(broker.auction_vol, 0);
 
-        Ok(0)
+            // 没有撤单延迟时，深圳市场直接拒绝撤单
+            if market_type == MarketType::SZ {
+                assert_eq!(
+                    broker.request_cancel(100),
+                    Err(MarketError::OrderTypeUnsupported)
+                );
+            }
+            // 有撤单延迟时，撤单在生效时被深圳市场拒绝
+            broker.set_cancel_latency(100);
+            assert_eq!(broker.request_cancel(100), Ok(0), "{market_type:?}");
+            broker.elapse(200).unwrap();
+            broker.sync_order_info();
+            let events = events.borrow();
+            let events = events.downcast_ref::<Vec<OrderLifecycle>>().unwrap();
+            if market_type == MarketType::SZ {
+                assert_eq!(order_ref.borrow().status, OrderStatus::New);
+                assert_eq!(
+                    events.as_slice(),
+                    &[OrderLifecycle {
+                        order_id: 100,
+                        event: LifecycleEvent::CancelRejected,
+                        timestamp: 20231201145900100,
+                    }]
+                );
+            } else {
+                assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+                assert!(events.is_empty());
+            }
+
+            broker.goto(20231201150100000).unwrap();
+            assert_eq!(broker.close_tick, 1000);
+            assert_eq!(broker.auction_vol, 1);
+        }
     }
-}
 
-impl<'a, MD> RecoverOp for Broker<MD>
-where
-    MD: L3MarketDepth + Serialize + Deserialize<'a> + RecoverOp + StatisticsOp + SnapshotOp,
-    MarketError: From<<MD as L3MarketDepth>::Error>,
-{
-    fn recover(&mut self) -> Result<bool, MarketError> {
-        self.init();
-        if self.history.is_some() {
-            self.history.as_mut().unwrap().init();
+    #[test]
+    fn test_fill_liquidity_split() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let user_order = |order_id, timestamp, price, qty, bs_flag| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                timestamp,
+                price,
+                qty,
+                bs_flag,
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+        // 实盘模式下用户订单参与集合竞价
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        // 一笔远离用户订单价格的历史买单，用于触发开盘集合竞价，种子 2 生成的第一笔委托是买单
+        let history = SyntheticFlow::new(FlowConfig {
+            seed: 2,
+            mid_tick: 500,
+            cancel_permille: 0,
+            take_permille: 0,
+            ..Default::default()
+        })
+        .to_history("600000.SH", 20231201093000000, 100, 1, 0.01, 100.0)
+        .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(20231201092000000);
+
+        // 集合竞价阶段挂出的订单在开盘时成交
+        broker
+            .submit_order(user_order(1, 20231201092000000, 10.0, 200.0, "Sell"))
+            .unwrap();
+        broker
+            .submit_order(user_order(2, 20231201092000000, 10.0, 100.0, "Buy"))
+            .unwrap();
+        broker.elapse(11 * 60 * 1000).unwrap();
+        broker.sync_order_info();
+
+        // 订单 3 主动吃掉订单 1 剩余的 100，剩余的 200 挂单后被订单 4 成交
+        broker
+            .submit_order(user_order(3, broker.timestamp, 10.0, 300.0, "Buy"))
+            .unwrap();
+        broker.elapse(100).unwrap();
+        broker.sync_order_info();
+        broker
+            .submit_order(user_order(4, broker.timestamp, 10.0, 200.0, "Sell"))
+            .unwrap();
+        broker.elapse(100).unwrap();
+        broker.sync_order_info();
+
+        let expected = [
+            (1, 0.0, 100.0, 100.0),
+            (2, 0.0, 0.0, 100.0),
+            (3, 100.0, 200.0, 0.0),
+            (4, 200.0, 0.0, 0.0),
+        ];
+        let mut totals = (0.0, 0.0, 0.0);
+        for (order_id, aggressive, passive, auction) in expected {
+            let order = broker.orders().unwrap()[&order_id].borrow();
+            assert_eq!(order.status, OrderStatus::Filled, "order {}", order_id);
+            assert_eq!(
+                (
+                    order.filled_qty_aggressive,
+                    order.filled_qty_passive,
+                    order.filled_qty_auction
+                ),
+                (aggressive, passive, auction),
+                "order {}",
+                order_id
+            );
+            assert_eq!(order.classified_fill_qty(), order.filled_qty);
+            totals.0 += aggressive;
+            totals.1 += passive;
+            totals.2 += auction;
         }
 
-        Ok(true)
+        let mut info = StatisticsInfo::new();
+        info.from_statistics(broker.market_depth.get_statistics(), 0.01, 100.0);
+        assert_eq!(
+            (
+                info.user_aggressive_qty,
+                info.user_passive_qty,
+                info.user_auction_qty
+            ),
+            totals
+        );
     }
-}
-#[cfg(test)]
-mod tests {
-    use core::borrow;
-    use std::str::FromStr;
-
-    use super::utils::time_difference_ms_i64;
-    use super::*;
-    use order::Order;
-    use skiplist_orderbook::SkipListMarketDepth;
 
     #[test]
-    fn test_broker_new() {
+    fn test_submit_order_queue_limits() {
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
             MarketType::SH,
-            "STOCK".to_string(),
-            "CODE".to_string(),
+            "stock".to_string(),
+            "600000.SH".to_string(),
             0.01,
             100.0,
         );
         broker.init();
-        assert_eq!(broker.stock_type, "STOCK");
-        assert_eq!(broker.stock_code, "CODE");
-        assert_eq!(broker.tick_size, 0.01);
-        assert_eq!(broker.lot_size, 100.0);
+        broker.timestamp = 20231201093000000;
+        broker.set_queue_limits(Some(2), Some(1));
+        let mut next_id = 0;
+        let mut new_order = |local_time: i64| {
+            next_id += 1;
+            let order_ref = Order::new_ref(
+                Some("user1".to_string()),
+                "600000.SH".to_string(),
+                local_time,
+                10.0,
+                100.0,
+                "b",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = next_id;
+            order_ref
+        };
+
+        assert_eq!(
+            broker.submit_order(new_order(20231201093000000)).unwrap(),
+            1
+        );
+        assert_eq!(
+            broker.submit_order(new_order(20231201093000000)).unwrap(),
+            2
+        );
+        let overflow = new_order(20231201093000000);
+        assert!(matches!(
+            broker.submit_order(overflow.clone()),
+            Err(MarketError::OrderQueueFull { capacity: 2 })
+        ));
+        // 被拒绝的订单不会登记，状态保持不变
+        assert!(!broker
+            .orders
+            .as_ref()
+            .unwrap()
+            .contains_key(&overflow.borrow().order_id));
+        assert_eq!(overflow.borrow().status, OrderStatus::New);
+
+        // 等待队列单独计算容量
+        assert_eq!(
+            broker.submit_order(new_order(20231201093100000)).unwrap(),
+            3
+        );
+        assert!(matches!(
+            broker.submit_order(new_order(20231201093200000)),
+            Err(MarketError::OrderQueueFull { capacity: 1 })
+        ));
+        assert_eq!(broker.pending_orders.len(), 2);
+        assert_eq!(broker.waiting_orders.len(), 1);
+
+        // 队列消化后可以重新提交，取消上限后不再限制
+        broker.elapse(0).unwrap();
         assert!(broker.pending_orders.is_empty());
-        assert!(broker.waiting_orders.is_empty());
-        assert_eq!(broker.timestamp, 19700101000000000);
-        assert!(broker.orders().is_empty());
-        assert_eq!(broker.latest_seq_number, 0);
-        assert!(broker.history.is_none());
-        assert!(broker.dirty_tracker.is_empty());
+        assert!(broker.submit_order(overflow).is_ok());
+        broker.set_queue_limits(None, None);
+        for _ in 0..3 {
+            assert!(broker.submit_order(new_order(20231201093000000)).is_ok());
+        }
+    }
+
+    fn noop_hook(
+        _object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        _bids: &Vec<(f64, f64, i64)>,
+        _asks: &Vec<(f64, f64, i64)>,
+        _l3order: &L3OrderRef,
+    ) -> bool {
+        true
     }
 
     #[test]
-    fn test_generate_seq_number() {
+    fn test_hook_registration() {
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
             MarketType::SH,
-            "STOCK".to_string(),
-            "CODE".to_string(),
+            "stock".to_string(),
+            "600000.SH".to_string(),
             0.01,
             100.0,
         );
-        broker.init();
-        assert_eq!(broker.generate_seq_number(), 1);
-        assert_eq!(broker.generate_seq_number(), 2);
+        let object: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0usize));
+        let new_hook = |max_level: usize| Hook::new(object.clone(), noop_hook, max_level);
+
+        broker
+            .register_orderbook_hook(HookType::Orderbook, "snapshot", new_hook(5))
+            .unwrap();
+        broker
+            .register_orderbook_hook(HookType::Orderbook, "analytics", new_hook(1))
+            .unwrap();
+        // 同名的钩子不会被覆盖
+        assert_eq!(
+            broker.register_orderbook_hook(HookType::Orderbook, "snapshot", new_hook(10)),
+            Err(MarketError::HookNameExists("snapshot".to_string()))
+        );
+        assert_eq!(
+            broker.hooks_info(),
+            vec![
+                HookInfo {
+                    hook_type: HookType::Orderbook,
+                    name: "analytics".to_string(),
+                    max_level: 1,
+                    slow_policy: SlowHookPolicy::Warn,
+                    stats: HookStats::default(),
+                    panics: 0,
+                },
+                HookInfo {
+                    hook_type: HookType::Orderbook,
+                    name: "snapshot".to_string(),
+                    max_level: 5,
+                    slow_policy: SlowHookPolicy::Warn,
+                    stats: HookStats::default(),
+                    panics: 0,
+                },
+            ]
+        );
+
+        let replaced = broker
+            .replace_hook(HookType::Orderbook, "snapshot", new_hook(10))
+            .unwrap();
+        assert_eq!(replaced.unwrap().max_level, 5);
+        assert!(broker
+            .replace_hook(HookType::Orderbook, "depth", new_hook(20))
+            .unwrap()
+            .is_none());
+        assert_eq!(broker.hooks_info()[2].max_level, 10);
+
+        assert_eq!(
+            broker.remove_hook(Some(HookType::Orderbook), "snapshot"),
+            Ok(true)
+        );
+        assert_eq!(
+            broker.remove_hook(Some(HookType::Orderbook), "snapshot"),
+            Ok(false)
+        );
+        assert_eq!(broker.remove_hook(None, "depth"), Ok(true));
+        assert_eq!(broker.remove_hook(None, "missing"), Ok(false));
+        let names: Vec<String> = broker
+            .hooks_info()
+            .into_iter()
+            .map(|info| info.name)
+            .collect();
+        assert_eq!(names, vec!["analytics".to_string()]);
+
+        // 推进时间期间不能修改钩子
+        broker.elapsing = true;
+        assert_eq!(
+            broker.register_orderbook_hook(HookType::Orderbook, "late", new_hook(1)),
+            Err(MarketError::HookModifyWhileElapsing)
+        );
+        assert!(broker
+            .replace_hook(HookType::Orderbook, "analytics", new_hook(1))
+            .is_err());
+        assert_eq!(
+            broker.remove_hook(None, "analytics"),
+            Err(MarketError::HookModifyWhileElapsing)
+        );
+        broker.elapsing = false;
+        assert_eq!(broker.hooks_info().len(), 1);
+    }
+
+    fn slow_hook(
+        object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        _bids: &Vec<(f64, f64, i64)>,
+        _asks: &Vec<(f64, f64, i64)>,
+        _l3order: &L3OrderRef,
+    ) -> bool {
+        std::thread::sleep(Duration::from_millis(2));
+        *object.borrow_mut().downcast_mut::<usize>().unwrap() += 1;
+        true
     }
 
     #[test]
-    fn test_add_data() {
+    fn test_slow_hook_watchdog() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
-            MarketType::SH,
+            MarketType::SZ,
             "STOCK".to_string(),
-            "CODE".to_string(),
+            "000001.SZ".to_string(),
             0.01,
             100.0,
         );
         broker.init();
-        assert!(broker.add_data(None).is_ok());
+        // 不生成撤单，每笔历史数据的时间各不相同
+        let config = FlowConfig {
+            cancel_permille: 0,
+            ..FlowConfig::default()
+        };
+        let history = SyntheticFlow::new(config)
+            .to_history("000001.SZ", 20231201093000000, 100, 30, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(20231201093000000);
+
+        let calls: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0usize));
+        let object: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0usize));
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "slow",
+                Hook::new(calls.clone(), slow_hook, 5).with_slow_policy(SlowHookPolicy::Deregister),
+            )
+            .unwrap();
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "noop",
+                Hook::new(object.clone(), noop_hook, 5),
+            )
+            .unwrap();
+
+        // 前 10 笔历史数据只统计耗时
+        broker.goto(20231201093000900).unwrap();
+        assert_eq!(broker.events_processed, 10);
+        let infos = broker.hooks_info();
+        let slow = &infos[1].stats;
+        assert_eq!(infos[1].name, "slow");
+        assert_eq!(slow.count, 10);
+        assert!(slow.max >= Duration::from_millis(2));
+        assert!(slow.total >= Duration::from_millis(20));
+        assert!(slow.max <= slow.total);
+        let noop = &infos[0].stats;
+        assert_eq!(noop.count, 10);
+        assert!(noop.max < slow.max);
+
+        // 连续 3 次超过阈值后慢钩子被注销，其他钩子不受影响
+        broker.set_hook_watchdog(Some(HookWatchdog {
+            threshold: Duration::from_millis(1),
+            strikes: 3,
+        }));
+        broker.goto(20231201093001900).unwrap();
+        assert_eq!(broker.events_processed, 20);
+        assert_eq!(*calls.borrow().downcast_ref::<usize>().unwrap(), 13);
+        let infos = broker.hooks_info();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].name, "noop");
+        assert_eq!(infos[0].stats.count, 20);
+
+        // 关闭耗时统计后不再记录
+        broker.set_hook_timing(false);
+        broker.goto(20231201093002900).unwrap();
+        assert_eq!(broker.events_processed, 30);
+        assert_eq!(broker.hooks_info()[0].stats.count, 20);
+    }
+
+    fn flaky_hook(
+        object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        bids: &Vec<(f64, f64, i64)>,
+        _asks: &Vec<(f64, f64, i64)>,
+        _l3order: &L3OrderRef,
+    ) -> bool {
+        let mut object = object.borrow_mut();
+        let snapshots = object.downcast_mut::<Vec<usize>>().unwrap();
+        if snapshots.len() == 2 {
+            panic!("flaky hook failed on the third call");
+        }
+        snapshots.push(bids.len());
+        true
+    }
+
+    struct FlakyObserver;
+
+    impl observer::DepthObserver for FlakyObserver {
+        fn on_add(&mut self, _order: &L3Order) {
+            panic!("flaky observer failed");
+        }
     }
 
     #[test]
-    fn test_get_orders() {
+    /// 测试钩子和观察者 panic 时回放不中断。
+    /// 验证 panic 的钩子和观察者被注销，之前的快照保留，其他钩子不受影响。
+    fn test_hook_panic_isolation() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
-            MarketType::SH,
+            MarketType::SZ,
             "STOCK".to_string(),
-            "CODE".to_string(),
+            "000001.SZ".to_string(),
             0.01,
             100.0,
         );
         broker.init();
-        let order_ref = Order::new_ref(
-            Some("account1".to_string()),
-            "AAPL".to_string(),
-            1234567890,
-            150.0,
-            10.0,
-            "Buy",
-            OrderType::L,
-            OrderSourceType::LocalOrder,
+        let config = FlowConfig {
+            cancel_permille: 0,
+            ..FlowConfig::default()
+        };
+        let history = SyntheticFlow::new(config)
+            .to_history("000001.SZ", 20231201093000000, 100, 20, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(20231201093000000);
+
+        let snapshots: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<usize>::new()));
+        let object: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0usize));
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "flaky",
+                Hook::new(snapshots.clone(), flaky_hook, 5),
+            )
+            .unwrap();
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "noop",
+                Hook::new(object.clone(), noop_hook, 5),
+            )
+            .unwrap();
+        assert!(broker
+            .market_depth
+            .register_observer(Box::new(FlakyObserver)));
+
+        broker.goto(20231201093000900).unwrap();
+        assert_eq!(broker.events_processed, 10);
+        // 第三次调用 panic 后钩子被注销，之前的两个快照仍然保留
+        assert_eq!(
+            snapshots
+                .borrow()
+                .downcast_ref::<Vec<usize>>()
+                .unwrap()
+                .len(),
+            2
         );
+        let infos = broker.hooks_info();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].name, "noop");
+        assert_eq!(infos[0].stats.count, 10);
+        assert_eq!(infos[0].panics, 0);
+        assert!(broker.market_depth.observers.is_empty());
 
-        broker.submit_order(order_ref).unwrap();
+        // 允许多次 panic 时钩子保留，HookInfo 记录 panic 次数
+        broker.set_max_hook_panics(3);
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "flaky",
+                Hook::new(snapshots.clone(), flaky_hook, 5),
+            )
+            .unwrap();
+        broker.goto(20231201093001100).unwrap();
+        assert_eq!(broker.events_processed, 12);
+        let infos = broker.hooks_info();
+        assert_eq!(infos[0].name, "flaky");
+        assert_eq!(infos[0].panics, 2);
+    }
 
-        let mut orders = HashMap::new();
-        broker.get_orders(&mut orders, &vec![OrderStatus::New]);
-        assert_eq!(orders.len(), 1);
+    #[test]
+    fn test_replay_summary() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SZ,
+            "STOCK".to_string(),
+            "000001.SZ".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let history = SyntheticFlow::new(FlowConfig::default())
+            .to_history("000001.SZ", 20231201093000000, 100, 500, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(20231201091500000);
+        assert_eq!(broker.replay_summary().events_processed, 0);
+        broker.goto(20231201150100000).unwrap();
+
+        let summary = broker.replay_summary();
+        let mut info = StatisticsInfo::new();
+        info.from_statistics(
+            broker.market_depth.get_statistics(),
+            broker.tick_size,
+            broker.lot_size,
+        );
+        assert_eq!(summary.events_processed, 500);
+        assert_eq!(summary.events_skipped, broker.skipped_orders.len());
+        assert!(summary.total_qty > 0.0);
+        assert_eq!(summary.total_qty, info.total_bid_qty + info.total_ask_qty);
+        assert_eq!(summary.turnover, info.turnover);
+        // 开盘集合竞价只有一笔委托，没有产生开盘价
+        assert_eq!(broker.open_tick, 0);
+        assert_eq!(summary.open, None);
+        assert_eq!(
+            summary.close,
+            (broker.close_tick != 0).then(|| broker.close_tick as f64 * 0.01)
+        );
+        assert_eq!(summary.high, Some(info.high));
+        assert_eq!(summary.low, Some(info.low));
+        assert!(summary.high >= summary.low);
+        assert!(summary.wall_time > Duration::ZERO);
     }
+
     #[test]
-    fn test_submit_order() {
-        let mode = ExchangeMode::Backtest;
-        let stock_code = String::from("stock");
-        let account = "user1".to_string();
-        let timestamp = 1;
-        let price = 11.2;
-        let qty = 100.0;
-        let bs_flag = "b";
-        let order_type = OrderType::L;
-        let source = OrderSourceType::LocalOrder;
+    fn test_half_day_close_auction() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let new_broker = |half_day: bool| {
+            let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+                ExchangeMode::Backtest,
+                MarketType::SZ,
+                "STOCK".to_string(),
+                "000001.SZ".to_string(),
+                0.01,
+                100.0,
+            );
+            broker.init();
+            if half_day {
+                broker.mark_half_day(20231229, 113000000);
+            }
+            let history = SyntheticFlow::new(FlowConfig::default())
+                .to_history("000001.SZ", 20231229093000000, 100, 500, 0.01, 100.0)
+                .unwrap();
+            broker.add_data(Some(history)).unwrap();
+            broker.set_current_time(20231229091500000);
+            broker.goto(20231229112600000).unwrap();
+            // 收盘前挂出一对可以相互成交的委托，保证收盘集合竞价产生成交
+            let price_tick = broker.market_depth.best_bid_tick;
+            for (order_id, side) in [(i64::MAX - 1, Side::Buy), (i64::MAX - 2, Side::Sell)] {
+                let order_ref = L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    order_id,
+                    side,
+                    price_tick,
+                    10,
+                    20231229112600000,
+                    OrderType::L,
+                );
+                broker.market_depth.add(order_ref).unwrap();
+            }
+            broker
+        };
+
+        let mut broker = new_broker(true);
+        assert!(broker.calendar.is_half_day(20231229112600000));
+        assert_eq!(
+            broker.calendar.phase(20231229112800000, MarketType::SZ),
+            Ok(TradingPhase::ClosingAuction)
+        );
+        assert_eq!(
+            broker.calendar.phase(20231229112600000, MarketType::SZ),
+            Ok(TradingPhase::Continuous)
+        );
+        // 收盘集合竞价提前到 11:30 收盘时进行
+        broker.goto(20231229112900000).unwrap();
+        assert_eq!(broker.close_tick, 0);
+        broker.goto(20231229113100000).unwrap();
+        assert_ne!(broker.close_tick, 0);
+
+        // 正常交易日 11:30 之后仍然是午间休市，收盘集合竞价在 15:00 之后进行
+        let mut broker = new_broker(false);
+        assert_eq!(
+            broker.calendar.phase(20231229112800000, MarketType::SZ),
+            Ok(TradingPhase::Continuous)
+        );
+        broker.goto(20231229113100000).unwrap();
+        assert_eq!(broker.close_tick, 0);
+        broker.goto(20231229150100000).unwrap();
+        assert_ne!(broker.close_tick, 0);
+    }
+
+    #[test]
+    fn test_price_and_qty_conversions() {
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
-            mode,
+            ExchangeMode::Backtest,
             MarketType::SH,
-            "stock".to_string(),
-            "stock".to_string(),
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
             0.01,
             100.0,
         );
         broker.init();
+        broker.timestamp = 20231201093000000;
+
+        // tick 价格和数量换算后可以无损换算回来
+        for tick in [0, 1, 999, 1000, 1001, 170_000, 999_999] {
+            assert_eq!(broker.price_to_tick(broker.tick_to_price(tick)), tick);
+        }
+        for vol in [0, 1, 3, 100, 12_345] {
+            assert_eq!(broker.qty_to_vol(broker.vol_to_qty(vol)), vol);
+        }
+        assert_eq!(broker.price_to_tick(12.345000001), 1235);
+        assert_eq!(broker.qty_to_vol(149.0), 1);
+
+        // 各个换算的位置结果一致
+        broker.set_previous_close_price(11.11);
+        assert_eq!(
+            broker.market_depth.previous_close_tick,
+            Some(broker.price_to_tick(11.11))
+        );
+        broker.set_price_limit(0.1, PriceLimitPolicy::default());
+        assert_eq!(
+            broker.limit_up_tick(),
+            Some(broker.price_to_tick(11.11 * 1.1))
+        );
+        assert_eq!(
+            broker.limit_down_tick(),
+            Some(broker.price_to_tick(11.11 * 0.9))
+        );
+
         let order_ref = Order::new_ref(
-            Some(account),
-            stock_code,
-            timestamp,
-            price,
-            qty,
-            &bs_flag,
-            order_type,
-            source,
+            None,
+            "600000.SH".to_string(),
+            20231201093000000,
+            12.21,
+            300.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
         );
+        order_ref.borrow_mut().order_id = 1;
+        broker.submit_order(order_ref.clone()).unwrap();
+        assert_eq!(order_ref.borrow().price_tick, broker.price_to_tick(12.21));
+        let l3order_ref = order_ref
+            .borrow()
+            .to_l3order_ref(broker.tick_size, broker.lot_size);
+        assert_eq!(l3order_ref.borrow().vol, broker.qty_to_vol(300.0));
 
-        let result = broker.submit_order(order_ref);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 1); // Assuming this is the expected queue position
+        broker.elapse(0).unwrap();
+        let order = order_ref.borrow();
+        assert_eq!(
+            order.price,
+            broker.tick_to_price(broker.price_to_tick(12.21))
+        );
+        assert_eq!(order.left_qty, broker.vol_to_qty(3));
     }
 
     #[test]
-    fn test_cancel_order() {
+    fn test_market_order_residual_canceled() {
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
             MarketType::SH,
             "STOCK".to_string(),
-            "CODE".to_string(),
+            "600000.SH".to_string(),
             0.01,
             100.0,
         );
         broker.init();
-        // Create and submit a test order
+        broker.timestamp = 20231201093000000;
+        // 已经开盘，成交按档位价格计算
+        broker.market_depth.market_statistics.open_tick = 1000;
+        // 卖方六档，每档一手
+        for (order_id, price_tick) in (1000..1006).enumerate() {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id as OrderId + 1,
+                Side::Sell,
+                price_tick,
+                1,
+                20231201092900000,
+                OrderType::L,
+            );
+            broker.market_depth.add(order_ref).unwrap();
+        }
+
         let order_ref = Order::new_ref(
-            Some("account1".to_string()),
-            "AAPL".to_string(),
-            1234567890,
-            150.0,
-            10.0,
+            None,
+            "600000.SH".to_string(),
+            20231201093000000,
+            0.0,
+            1000.0,
             "Buy",
-            OrderType::L,
-            OrderSourceType::LocalOrder,
+            OrderType::M,
+            OrderSourceType::UserOrder,
         );
+        order_ref.borrow_mut().order_id = 100;
         broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
 
-        let order_id = order_ref.borrow().order_id;
-        // Cancel the order
-        let result = broker.cancel_order(order_id);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        // 最多成交五档，剩余部分被撤销
+        let order = order_ref.borrow();
+        assert_eq!(order.filled_qty, 500.0);
+        assert_eq!(order.canceled_qty, 500.0);
+        assert_eq!(order.filled_qty + order.canceled_qty, order.qty);
+        assert_eq!(order.left_qty, 0.0);
+        assert_eq!(order.status, OrderStatus::Canceled);
+        assert!(!broker.market_depth.orders().contains_key(&100));
 
-        // Verify the order status is canceled
-        let order = broker
-            .orders
-            .as_ref()
-            .unwrap()
-            .get(&order_ref.borrow().order_id)
-            .unwrap();
-        assert_eq!(order.borrow().status, OrderStatus::Canceled);
+        let mut orders = HashMap::new();
+        broker.get_latest_orders(&mut orders);
+        assert!(orders.contains_key(&100));
+        let report: serde_json::Value = serde_json::to_value(&*order).unwrap();
+        assert_eq!(report["canceled_qty"], serde_json::json!(500.0));
     }
 
     #[test]
-    fn test_get_orders_multiple_statuses() {
+    fn test_partial_fill_lots() {
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Backtest,
             MarketType::SH,
             "STOCK".to_string(),
-            "CODE".to_string(),
+            "600000.SH".to_string(),
             0.01,
             100.0,
         );
         broker.init();
-        // 创建多个订单，具有不同的状态
-        let new_order_ref = Order::new_ref(
-            Some("account1".to_string()),
-            "AAPL".to_string(),
-            1234567890,
-            150.0,
-            10.0,
+        broker.timestamp = 20231201093000000;
+        broker.market_depth.market_statistics.open_tick = 1000;
+        let ask_ref = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            1,
+            Side::Sell,
+            1000,
+            2,
+            20231201092900000,
+            OrderType::L,
+        );
+        broker.market_depth.add(ask_ref).unwrap();
+
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            20231201093000000,
+            10.01,
+            500.0,
             "Buy",
             OrderType::L,
             OrderSourceType::UserOrder,
         );
+        order_ref.borrow_mut().order_id = 100;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
+        broker.sync_order_info();
 
-        let filled_order_ref = Order::new_ref(
-            Some("account2".to_string()),
-            "AAPL".to_string(),
-            1234567891,
-            155.0,
-            15.0,
-            "Sell",
-            OrderType::B,
-            OrderSourceType::UserOrder,
+        // 成交两手，剩余三手挂在订单簿上
+        let order = order_ref.borrow();
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        assert_eq!(order.filled_lots(broker.lot_size), 2);
+        assert_eq!(order.left_lots(broker.lot_size), 3);
+        assert_eq!(
+            order.filled_qty,
+            order.filled_lots(broker.lot_size) as f64 * broker.lot_size
+        );
+        assert_eq!(
+            order.left_qty,
+            order.left_lots(broker.lot_size) as f64 * broker.lot_size
         );
+        let l3order = broker.market_depth.orders()[&100].clone();
+        assert_eq!(l3order.borrow().left_qty(broker.lot_size), order.left_qty);
+        assert_eq!(
+            l3order.borrow().filled_qty(order.qty, broker.lot_size),
+            order.filled_qty
+        );
+    }
 
-        let canceled_order_ref = Order::new_ref(
-            Some("account3".to_string()),
-            "AAPL".to_string(),
-            1234567892,
-            160.0,
-            20.0,
-            "Buy",
-            OrderType::C,
-            OrderSourceType::UserOrder,
+    #[test]
+    fn test_ipo_day_without_previous_close() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
         );
+        broker.init();
+        broker.timestamp = 20231201093000000;
+        broker.set_price_limit(0.1, PriceLimitPolicy::default());
 
-        let new_order_id = 1234567890;
-        let filled_order_id = 1234567891;
-        let canceled_order_id = 1234567892;
+        // 前收盘价未知且没有指定参考价格时不设涨跌停
+        assert_eq!(broker.previous_close_price, None);
+        assert_eq!(broker.market_depth.previous_close_tick, None);
+        assert_eq!(broker.reference_price(), None);
+        assert_eq!(broker.limit_up_tick(), None);
+        assert_eq!(broker.limit_down_tick(), None);
+        broker.set_opening_reference(OpeningReference::FirstTradePrice);
+        assert_eq!(broker.reference_price(), None);
 
-        new_order_ref.borrow_mut().order_id = new_order_id;
-        filled_order_ref.borrow_mut().order_id = filled_order_id;
-        canceled_order_ref.borrow_mut().order_id = canceled_order_id;
-        // 提交订单
-        broker.submit_order(new_order_ref.clone()).unwrap();
-        broker.submit_order(filled_order_ref.clone()).unwrap();
-        broker.submit_order(canceled_order_ref.clone()).unwrap();
+        let new_order = |order_id: OrderId| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                20231201093000000,
+                0.0,
+                1000.0,
+                "Buy",
+                OrderType::N,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
 
-        // 将状态修改为不同状态以便测试
-        broker
-            .orders
-            .as_mut()
-            .unwrap()
-            .get_mut(&1234567890)
-            .unwrap()
-            .borrow_mut()
-            .status = OrderStatus::New;
-        broker
-            .orders
-            .as_mut()
-            .unwrap()
-            .get_mut(&1234567891)
-            .unwrap()
+        // 当日还没有成交也没有参考价格，N 型订单的剩余部分被撤销而不是挂在无效价格上
+        let order_ref = new_order(1);
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
+        assert_eq!(order_ref.borrow().canceled_qty, 1000.0);
+        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+        assert!(broker.market_depth.orders().is_empty());
+
+        // 指定参考价格后按新股上市首日的涨跌幅计算涨跌停价
+        broker.set_opening_reference(OpeningReference::Manual(10.0));
+        assert_eq!(broker.reference_price(), Some(10.0));
+        assert_eq!(broker.limit_up_tick(), Some(1440));
+        assert_eq!(broker.limit_down_tick(), Some(640));
+
+        // N 型订单的剩余部分以参考价格挂单
+        let order_ref = new_order(2);
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
+        assert_eq!(order_ref.borrow().canceled_qty, 0.0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 10);
+
+        // 快照中前收盘价写出为 null，读取后仍然未知
+        let snapshot: serde_json::Value = serde_json::from_str(&broker.snapshot()).unwrap();
+        assert!(snapshot["previous_close_price"].is_null());
+        assert!(snapshot["market_depth"]["previous_close_tick"].is_null());
+        let restored: Broker<SkipListMarketDepth> =
+            serde_json::from_str(&broker.snapshot()).unwrap();
+        assert_eq!(restored.previous_close_price, None);
+        assert_eq!(restored.opening_reference, OpeningReference::Manual(10.0));
+        assert_eq!(restored.limit_up_tick(), Some(1440));
+
+        // 前收盘价已知时按前收盘价和涨跌停幅度计算
+        broker.set_previous_close_price(11.0);
+        assert_eq!(broker.reference_price(), Some(11.0));
+        assert_eq!(broker.limit_up_tick(), Some(1210));
+        assert_eq!(broker.limit_down_tick(), Some(990));
+        broker.set_previous_close_price(0.0);
+        assert_eq!(broker.previous_close_price, None);
+        assert_eq!(broker.limit_up_tick(), Some(1440));
+    }
+
+    /// 直接修改统计数据，用于在测试中制造对账差异。
+    fn inject_accounting_error(
+        broker: &mut Broker<SkipListMarketDepth>,
+        f: impl FnOnce(&mut Statistics),
+    ) {
+        f(broker.market_depth.get_statistics_mut());
+    }
+
+    /// 一笔买方挂单之后是 10 对卖出挂单和主动买入，共 21 条逐笔消息，间隔 10 毫秒。
+    fn reconcile_broker() -> (Broker<SkipListMarketDepth>, i64) {
+        let start = 20231201100000000;
+        let time = |idx: i64| adjust_timestamp_milliseconds_i64(start, idx * 10).unwrap();
+        let mut orders = vec![(time(0), Side::Buy, 9.9, 200.0)];
+        for idx in 0..10 {
+            orders.push((time(2 * idx + 1), Side::Sell, 10.0, 100.0));
+            orders.push((time(2 * idx + 2), Side::Buy, 10.0, 100.0));
+        }
+        let history = local_history(&orders);
+        for seq in (3..=21).step_by(2) {
+            let order_ref = history.orders.as_ref().unwrap()[&seq].clone();
+            let mut order = order_ref.borrow_mut();
+            let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.match_price = 10.0;
+            auxiliary_info.match_qty = 100.0;
+        }
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        (broker, start)
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let (mut broker, start) = reconcile_broker();
+        broker.goto_end_of_day().unwrap();
+        let report = broker.reconcile();
+        assert_eq!(report.events_processed, 21);
+        assert!(report.is_consistent(), "{report:?}");
+        assert_eq!(broker.market_depth.get_statistics().total_volume(), 10);
+
+        // 价格档位与其中的订单不一致
+        broker.market_depth.bid_depth.get_mut(&-990).unwrap().vol += 1;
+        inject_accounting_error(&mut broker, |statistics| statistics.user_passive_vol += 3);
+        let report = broker.reconcile();
+        assert_eq!(report.discrepancies.len(), 2);
+        let resting = report.get(DiscrepancyKind::RestingVolume).unwrap();
+        assert_eq!((resting.expected, resting.actual), (2, 3));
+        let passive = report
+            .get(DiscrepancyKind::UserFill(FillLiquidity::Passive))
+            .unwrap();
+        assert_eq!(passive.magnitude(), 3);
+        // 实时模式下用户被动成交来自历史订单的成交，不影响总成交量的期望值
+        assert_eq!(report.get(DiscrepancyKind::MarketVolume), None);
+
+        // 自动对账定位差异第一次出现的区间
+        let (mut broker, _) = reconcile_broker();
+        broker.auto_reconcile_every = Some(4);
+        let time_point = adjust_timestamp_milliseconds_i64(start, 95).unwrap();
+        broker.goto(time_point).unwrap();
+        assert_eq!(broker.events_processed, 10);
+        inject_accounting_error(&mut broker, |statistics| statistics.total_bid_vol += 1);
+        broker.goto_end_of_day().unwrap();
+        assert_eq!(broker.reconciliation_log.runs, 5);
+        // 差异出现在第 10 条之后，第一次发现于第 12 条之后的对账
+        assert_eq!(
+            broker.first_discrepancy(DiscrepancyKind::MarketVolume),
+            Some(12)
+        );
+        assert_eq!(
+            broker.first_discrepancy(DiscrepancyKind::RestingVolume),
+            None
+        );
+        let market = broker.reconcile();
+        assert_eq!(
+            market
+                .get(DiscrepancyKind::MarketVolume)
+                .unwrap()
+                .magnitude(),
+            1
+        );
+    }
+
+    /// 测试对账不会同步订单信息
+    /// 回测模式下尚未同步的用户挂单成交计入总成交量的期望值
+    #[test]
+    fn test_reconcile_before_sync() {
+        let start = 20231201100000000;
+        let later = adjust_timestamp_milliseconds_i64(start, 10).unwrap();
+        let mut history = local_history(&[(later, Side::Sell, 10.0, 200.0)]);
+        history.orders.as_ref().unwrap()[&1]
             .borrow_mut()
-            .status = OrderStatus::Filled;
-        broker
-            .orders
+            .auxiliary_info
             .as_mut()
             .unwrap()
-            .get_mut(&1234567892)
-            .unwrap()
-            .borrow_mut()
-            .status = OrderStatus::Canceled;
+            .match_qty = 0.0;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.auto_reconcile_every = Some(1);
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            start,
+            10.0,
+            100.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(20).unwrap();
 
-        // 测试获取新订单
-        let mut orders = HashMap::new();
-        broker.get_orders(&mut orders, &vec![OrderStatus::New]);
-        assert_eq!(orders.len(), 1);
-        assert!(orders.contains_key(&1234567890));
+        // 自动对账没有同步订单信息
+        assert_eq!(order_ref.borrow().filled_qty, 0.0);
+        assert!(broker.dirty_tracker.is_empty());
+        assert_eq!(broker.reconciliation_log.runs, 1);
+        assert_eq!(
+            broker.first_discrepancy(DiscrepancyKind::MarketVolume),
+            None
+        );
+        assert!(broker.reconcile().is_consistent());
 
-        // 清空映射并测试获取已完成订单
-        orders.clear();
-        broker.get_orders(&mut orders, &vec![OrderStatus::Filled]);
-        assert_eq!(orders.len(), 1);
-        assert!(orders.contains_key(&1234567891));
+        broker.sync_order_info();
+        assert_eq!(order_ref.borrow().filled_qty, 100.0);
+        assert!(broker.reconcile().is_consistent());
+    }
 
-        // 清空映射并测试获取已取消订单
-        orders.clear();
-        broker.get_orders(&mut orders, &vec![OrderStatus::Canceled]);
-        assert_eq!(orders.len(), 1);
-        assert!(orders.contains_key(&1234567892));
+    #[test]
+    fn test_price_improvement() {
+        let start = 20231201100000000;
+        let history = local_history(&[(start, Side::Sell, 10.0, 200.0)]);
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        // 开盘之前的成交按进入订单的价格成交
+        broker.market_depth.market_statistics.open_tick = 1000;
+        broker.goto(start + 10).unwrap();
 
-        // 清空映射并测试获取多个状态的订单
-        orders.clear();
-        broker.get_orders(&mut orders, &vec![OrderStatus::New, OrderStatus::Filled]);
-        assert_eq!(orders.len(), 2);
-        assert!(orders.contains_key(&1234567890));
-        assert!(orders.contains_key(&1234567891));
+        let new_order = |order_id: OrderId, price: f64, qty: f64, bs_flag: &str| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                broker.timestamp,
+                price,
+                qty,
+                bs_flag,
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+        // 限价 10.01 的买单与 10.00 的卖单成交 2 手，每手改善 1 个 tick
+        let buy = new_order(1, 10.01, 300.0, "b");
+        let sell = new_order(2, 9.99, 100.0, "s");
+        broker.submit_order(buy.clone()).unwrap();
+        broker.elapse(10).unwrap();
+        broker.sync_order_info();
+        assert_eq!(buy.borrow().filled_qty, 200.0);
+        assert!((buy.borrow().price_improvement - 2.0).abs() < 1e-9);
+
+        // 限价 9.99 的卖单与挂在 10.01 的买单成交，每手改善 2 个 tick
+        broker.submit_order(sell.clone()).unwrap();
+        broker.elapse(10).unwrap();
+        broker.sync_order_info();
+        assert_eq!(sell.borrow().filled_qty, 100.0);
+        assert!((sell.borrow().price_improvement - 2.0).abs() < 1e-9);
+        // 被动成交按限价成交，没有价格改善
+        assert_eq!(buy.borrow().status, OrderStatus::Filled);
+        assert!((buy.borrow().price_improvement - 2.0).abs() < 1e-9);
+    }
+
+    fn counting_hook(
+        object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        _bids: &Vec<(f64, f64, i64)>,
+        _asks: &Vec<(f64, f64, i64)>,
+        _l3order: &L3OrderRef,
+    ) -> bool {
+        *object.borrow_mut().downcast_mut::<usize>().unwrap() += 1;
+        true
     }
 
     #[test]
-    fn test_broker_snapshot() {
-        // 创建一个 Broker 实例
-        // 使用 Backtest 模式，股票类型为 "STOCK"，股票代码为 "CODE"，
-        // 最小价格变动单位为 0.01，最小交易单位为 100.0
-        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
-            ExchangeMode::Backtest,
-            MarketType::SH,
-            "STOCK".to_string(),
-            "CODE".to_string(),
-            0.01,
-            100.0,
+    fn test_market_data_outage() {
+        let start = 20231201100000000;
+        let history = local_history(&[
+            (start, Side::Buy, 10.0, 100.0),
+            (start + 100, Side::Buy, 10.01, 100.0),
+            (start + 300, Side::Buy, 10.02, 100.0),
+        ]);
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        let calls: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0usize));
+        broker
+            .register_orderbook_hook(
+                HookType::Orderbook,
+                "bbo",
+                Hook::new(calls.clone(), counting_hook, 1),
+            )
+            .unwrap();
+        let call_count = || *calls.borrow().downcast_ref::<usize>().unwrap();
+
+        assert_eq!(
+            broker.schedule_outage(start + 50, start + 50, OutageKind::MarketData),
+            Err(MarketError::InvalidTimestamp)
         );
-        broker.init();
-        // 调用 snapshot 方法，获取 Broker 实例的 JSON 序列化表示
-        let snapshot = broker.snapshot();
-        print!("{:?}\n", snapshot);
-        print!("{:?}\n", serde_json::to_string(&broker));
-        // 验证 snapshot 返回的 JSON 字符串是否包含期望的字段及其值
-        // 确保交易模式被正确序列化
-        assert!(snapshot.contains(r#""mode":"Backtest""#));
-        // 确保股票类型被正确序列化
-        assert!(snapshot.contains(r#""stock_type":"STOCK""#));
-        // 确保股票代码被正确序列化
-        assert!(snapshot.contains(r#""stock_code":"CODE""#));
-        // 确保最小价格变动单位被正确序列化
-        assert!(snapshot.contains(r#""tick_size":0.01"#));
-        // 确保最小交易单位被正确序列化
-        assert!(snapshot.contains(r#""lot_size":100.0"#));
-        // 确保当前时间戳被正确序列化
-        assert!(snapshot.contains(r#""timestamp":0"#));
-        // 确保最新的序列号被正确序列化
-        assert!(snapshot.contains(r#""latest_seq_number":0"#));
+        broker
+            .schedule_outage(start + 50, start + 200, OutageKind::MarketData)
+            .unwrap();
+        broker.goto(start + 10).unwrap();
+        assert_eq!(call_count(), 1);
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 10.0).abs() < 1e-9);
 
-        // 验证 snapshot 返回的 JSON 字符串不包含被跳过序列化的字段
-        // pending_orders、waiting_orders、orders、history 和 dirty_tracker
-        // 被标记为 #[serde(skip)]，因此不应包含在序列化输出中
-        assert!(!snapshot.contains(r#""pending_orders":[]"#));
-        assert!(!snapshot.contains(r#""waiting_orders":[]"#));
-        assert!(!snapshot.contains(r#""dirty_tracker":[]"#));
+        // 中断期间历史数据照常回放，但钩子不被调用，策略可见的行情停留在中断前
+        broker.goto(start + 150).unwrap();
+        assert_eq!(broker.events_processed, 2);
+        assert_eq!(call_count(), 1);
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 10.0).abs() < 1e-9);
+        assert!((broker.best_bid(&OrderSourceType::LocalOrder) - 10.01).abs() < 1e-9);
+        assert_eq!(broker.market_view().best_bid_tick, 1000);
+
+        // 窗口结束后恢复为实时行情
+        broker.set_current_time(start + 200);
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 10.01).abs() < 1e-9);
+        broker.goto(start + 310).unwrap();
+        assert_eq!(call_count(), 2);
+        assert!((broker.best_bid(&OrderSourceType::UserOrder) - 10.02).abs() < 1e-9);
+        assert!(broker.outages.is_empty());
     }
 
     #[test]
-    fn test_broker_add_dataloader() {
-        let exchange_mode = "backtest".to_string();
-        let stock_code = "688007.SH".to_string();
-        let file_type = "local".to_string();
-        let data_path = "./data".to_string();
-        let date = "20231201".to_string();
-        let mode = "L2P";
+    fn test_order_entry_outage() {
+        let start = 20231201100000000;
+        let history = local_history(&[(start + 100, Side::Sell, 10.0, 100.0)]);
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker
+            .schedule_outage(start + 50, start + 200, OutageKind::OrderEntry)
+            .unwrap();
 
-        let mut data = DataCollator::new(
-            stock_code.clone(),
-            file_type.clone(),
-            data_path.clone(),
-            date.clone(),
-            mode.clone(),
+        let new_order = |order_id: OrderId, local_time: i64| {
+            let order_ref = Order::new_ref(
+                None,
+                "600000.SH".to_string(),
+                local_time,
+                10.0,
+                100.0,
+                "b",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref
+        };
+        let resting = new_order(1, start);
+        broker.submit_order(resting.clone()).unwrap();
+        broker.elapse(10).unwrap();
+        // 在窗口开始之后才到达的订单同样被拒绝
+        assert_eq!(
+            broker.submit_order(new_order(2, start + 60)),
+            Err(MarketError::GatewayUnavailable)
         );
-        data.init();
 
-        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
-            ExchangeMode::from_str(&exchange_mode.as_str()).unwrap(),
-            MarketType::SH,
-            "stock".to_string(),
-            stock_code.clone(),
-            0.01,
-            1.0,
+        broker.goto(start + 60).unwrap();
+        assert_eq!(
+            broker.submit_order(new_order(3, start + 60)),
+            Err(MarketError::GatewayUnavailable)
         );
-        broker.init();
-        let start: i64 = 20231201092521355;
-        let duration = time_difference_ms_i64(broker.timestamp, start).unwrap_or(0);
-        broker.add_data(Some(data));
-        broker.elapse(duration + 10000);
-        print!("{:?}\n", broker.snapshot());
+        assert_eq!(
+            broker.request_cancel(1),
+            Err(MarketError::GatewayUnavailable)
+        );
+
+        // 已挂出的订单在中断期间照常成交
+        broker.goto(start + 150).unwrap();
+        broker.sync_order_info();
+        assert_eq!(resting.borrow().status, OrderStatus::Filled);
+        assert_eq!(resting.borrow().filled_qty, 100.0);
+
+        broker.goto(start + 200).unwrap();
+        assert!(broker.submit_order(new_order(4, start + 200)).is_ok());
+    }
+
+    /// 上午和下午各有几笔历史订单，下午撤销一笔上午挂出的买单。
+    fn full_day_history() -> DataCollator {
+        let morning = 20231201100000000;
+        let afternoon = 20231201130000000;
+        let mut history = local_history(&[
+            (morning + 10, Side::Buy, 10.0, 300.0),
+            (morning + 20, Side::Buy, 9.99, 200.0),
+            (morning + 30, Side::Sell, 10.02, 200.0),
+            (morning + 40, Side::Sell, 10.01, 100.0),
+            (afternoon + 10, Side::Sell, 10.0, 100.0),
+            (afternoon + 20, Side::Buy, 10.02, 200.0),
+        ]);
+        history.orders.as_ref().unwrap()[&2]
+            .borrow_mut()
+            .auxiliary_info
+            .as_mut()
+            .unwrap()
+            .cancel_seq = 7;
+        history.index_by_seq.as_mut().unwrap().push_back((7, 2));
+        history.len += 1;
+        history
     }
 
     #[test]
-    fn test_broker_live_mode() {
-        let exchange_mode = "live";
-        let stock_code = "688007.SH".to_string();
-        let file_type = "local".to_string();
-        let data_path = "./data".to_string();
-        let date = "20231201".to_string();
-        let mode = "L2P";
+    fn test_resume_replay_from_snapshot() {
+        let noon = 20231201120000000;
+        let close = 20231201143000000;
+        let fixture = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(20231201100000000)
+            .open(10.0);
+        let (mut full, _) = fixture.build_broker().unwrap();
+        full.add_data(Some(full_day_history())).unwrap();
+        full.goto(close).unwrap();
 
-        let mut data = DataCollator::new(
-            stock_code.clone(),
-            file_type.clone(),
-            data_path.clone(),
-            date.clone(),
-            mode.clone(),
+        let (mut morning, _) = fixture.build_broker().unwrap();
+        morning.add_data(Some(full_day_history())).unwrap();
+        morning.goto(noon).unwrap();
+        let snapshot = morning.snapshot();
+
+        let mut resumed: Broker<SkipListMarketDepth> = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(
+            resumed.resume_replay(local_history(&[])),
+            Err(MarketError::RecoverFailed)
         );
-        data.init();
+        resumed.resume_replay(full_day_history()).unwrap();
+        assert_eq!(resumed.history.as_ref().unwrap().current_idx, 4);
+        resumed.goto(close).unwrap();
+
+        let book = |broker: &Broker<SkipListMarketDepth>| -> serde_json::Value {
+            serde_json::from_str(&broker.market_depth.snapshot()).unwrap()
+        };
+        assert_eq!(book(&resumed), book(&full));
+        // 下午撤销的上午挂单已经离开订单簿
+        assert_eq!(resumed.market_depth.bid_vol_at_tick(999), 0);
+        assert_eq!(resumed.market_depth.resting_orders().len(), 2);
+    }
+
+    #[test]
+    fn test_market_orders_never_rest_at_sentinel() {
+        let timestamp = 20231201100000000;
+        let in_range = |tick: i64| tick > 0 && tick <= PRICE_TICK_LIMIT;
+        let mut order_id = 0;
+        for mode in [ExchangeMode::Backtest, ExchangeMode::Live] {
+            for open_tick in [0, 1000] {
+                for two_sided in [false, true] {
+                    for order_type in [
+                        OrderType::M,
+                        OrderType::N,
+                        OrderType::B,
+                        OrderType::C,
+                        OrderType::D,
+                    ] {
+                        for side in [Side::Buy, Side::Sell] {
+                            let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+                                mode,
+                                MarketType::SH,
+                                "STOCK".to_string(),
+                                "600000.SH".to_string(),
+                                0.01,
+                                100.0,
+                            );
+                            broker.init();
+                            broker.set_current_time(timestamp);
+                            broker.market_depth.market_statistics.open_tick = open_tick;
+                            if two_sided {
+                                for (side, price_tick) in [(Side::Buy, 999), (Side::Sell, 1001)] {
+                                    order_id += 1;
+                                    broker
+                                        .market_depth
+                                        .add(L3Order::new_ref(
+                                            OrderSourceType::LocalOrder,
+                                            None,
+                                            order_id,
+                                            side,
+                                            price_tick,
+                                            1,
+                                            timestamp,
+                                            OrderType::L,
+                                        ))
+                                        .unwrap();
+                                }
+                            }
+
+                            order_id += 1;
+                            let _ = broker.process_order(L3Order::new_ref(
+                                OrderSourceType::UserOrder,
+                                None,
+                                order_id,
+                                side,
+                                0,
+                                3,
+                                timestamp,
+                                OrderType::M,
+                            ));
+                            let _ = broker.process_order(L3Order::new_ref(
+                                OrderSourceType::UserOrder,
+                                None,
+                                order_id + 1_000_000,
+                                side,
+                                0,
+                                3,
+                                timestamp,
+                                order_type,
+                            ));
+
+                            let depth = &broker.market_depth;
+                            let case =
+                                format!("{mode:?} {open_tick} {two_sided} {order_type:?} {side:?}");
+                            for (price_tick, _) in depth.bid_depth.iter() {
+                                assert!(in_range(-*price_tick), "{case}: bid {price_tick}");
+                            }
+                            for (price_tick, _) in depth.ask_depth.iter() {
+                                assert!(in_range(*price_tick), "{case}: ask {price_tick}");
+                            }
+                            let last_tick = depth.last_tick(&OrderSourceType::UserOrder);
+                            assert!(last_tick == INVALID_MIN || in_range(last_tick), "{case}");
+                        }
+                    }
+                }
+            }
+        }
 
+        // 卖出的市价订单与买方档位成交，不再因为哨兵价格直接跳过
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
-            ExchangeMode::from_str(&exchange_mode).unwrap(),
+            ExchangeMode::Live,
             MarketType::SH,
-            "stock".to_string(),
-            stock_code.clone(),
+            "STOCK".to_string(),
+            "600000.SH".to_string(),
             0.01,
-            1.0,
+            100.0,
         );
         broker.init();
-        let start: i64 = 20231201092521355;
-        let duration = time_difference_ms_i64(broker.timestamp, start).unwrap_or(0);
-        broker.add_data(Some(data));
-        broker.elapse(duration + 24 * 3600 * 1000);
-        print!("{:?}\n", broker.snapshot());
+        broker.set_current_time(timestamp);
+        broker.market_depth.market_statistics.open_tick = 1000;
+        broker
+            .market_depth
+            .add(L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                1,
+                Side::Buy,
+                999,
+                2,
+                timestamp,
+                OrderType::L,
+            ))
+            .unwrap();
+        let filled = broker
+            .process_order(L3Order::new_ref(
+                OrderSourceType::UserOrder,
+                None,
+                2,
+                Side::Sell,
+                0,
+                3,
+                timestamp,
+                OrderType::M,
+            ))
+            .unwrap();
+        assert_eq!(filled, 2);
+        assert_eq!(
+            broker.market_depth.last_tick(&OrderSourceType::UserOrder),
+            999
+        );
+
+        // 直接以哨兵价格挂单会被拒绝
+        for price_tick in [INVALID_MAX, PRICE_TICK_LIMIT + 1] {
+            let result = broker.market_depth.add(L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                3,
+                Side::Sell,
+                price_tick,
+                1,
+                timestamp,
+                OrderType::L,
+            ));
+            assert_eq!(result, Err(MarketError::PriceTickOutOfRange(price_tick)));
+        }
+        assert!(broker.market_depth.ask_depth.is_empty());
     }
 
+    /// 测试实时模式下历史订单挂单失败时返回错误
+    /// 与回测模式一样，价格超出有效范围的订单不会被静默丢弃
     #[test]
-    fn test_process_user_order() {
+    fn test_live_local_order_add_error_propagates() {
+        let timestamp = 20231201100000000;
         let mut broker: Broker<SkipListMarketDepth> = Broker::new(
             ExchangeMode::Live,
             MarketType::SH,
             "STOCK".to_string(),
-            "CODE".to_string(),
+            "600000.SH".to_string(),
             0.01,
-            1.0,
+            100.0,
         );
         broker.init();
-        let timestamp = 20231201093021355;
         broker.set_current_time(timestamp);
-        // Create and submit a local order
-        let buy_order_ref = Order::new_ref(
-            None,
-            "AAPL".to_string(),
-            timestamp,
-            150.0,
-            10.0,
-            "Buy",
-            OrderType::L,
-            OrderSourceType::UserOrder,
+        let price_tick = PRICE_TICK_LIMIT + 1;
+        let mut history =
+            local_history(&[(timestamp, Side::Sell, price_tick as f64 * 0.01, 100.0)]);
+        let order_ref = history.orders.as_mut().unwrap().remove(&1).unwrap();
+
+        assert_eq!(
+            broker.process_local_order(order_ref),
+            Err(MarketError::PriceTickOutOfRange(price_tick))
         );
-        buy_order_ref.borrow_mut().order_id = 1;
-        let sell_order_ref = Order::new_ref(
+        assert!(broker.market_depth.ask_depth.is_empty());
+    }
+
+    #[test]
+    fn test_out_of_order_timestamps_do_not_rewind() {
+        fn record(object: &Rc<RefCell<dyn Any>>, event: &ReplayEvent) {
+            if let Some(timestamps) = object.borrow_mut().downcast_mut::<Vec<i64>>() {
+                timestamps.push(event.timestamp);
+            }
+        }
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(20231201100000000)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        // 按序列号排列，第 3 条和第 5 条的时间戳早于之前的消息
+        broker
+            .add_data(Some(local_history(&[
+                (20231201100000010, Side::Buy, 9.98, 100.0),
+                (20231201100000030, Side::Sell, 10.02, 100.0),
+                (20231201100000020, Side::Buy, 9.99, 100.0),
+                (20231201100000040, Side::Sell, 10.03, 100.0),
+                (20231201100000005, Side::Buy, 9.97, 100.0),
+            ])))
+            .unwrap();
+        let timestamps: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<i64>::new()));
+        broker.set_event_callback(timestamps.clone(), record);
+
+        // 遇到时间戳晚于目标时间的消息即停止，之后时间戳更早的消息也留到下一次处理
+        broker.goto(20231201100000025).unwrap();
+        assert_eq!(broker.events_processed, 1);
+        broker.goto(20231201100000030).unwrap();
+        assert_eq!(broker.events_processed, 3);
+        assert_eq!(broker.out_of_order_events, 1);
+        assert_eq!(broker.timestamp, 20231201100000030);
+        broker.goto(20231201100001000).unwrap();
+        assert_eq!(broker.events_processed, 5);
+        assert_eq!(broker.out_of_order_events, 2);
+        assert_eq!(broker.replay_summary().events_out_of_order, 2);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(997), 1);
+
+        let timestamps = timestamps.borrow();
+        let timestamps = timestamps.downcast_ref::<Vec<i64>>().unwrap();
+        assert_eq!(
+            timestamps,
+            &vec![
+                20231201100000010,
+                20231201100000030,
+                20231201100000030,
+                20231201100000040,
+                20231201100000040,
+            ]
+        );
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    /// 用合成委托流回放 200 笔历史数据的经纪商。
+    fn publishing_broker() -> Broker<SkipListMarketDepth> {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .stock(MarketType::SZ, "000001.SZ")
+            .build_broker()
+            .unwrap();
+        let history = SyntheticFlow::new(FlowConfig::default())
+            .to_history("000001.SZ", 20231201093000000, 100, 200, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker
+    }
+
+    #[test]
+    /// 测试在另一个线程读取回放过程中发布的状态。
+    /// 验证收到的状态序号连续递增，时间戳和已处理消息数量不减少，经纪商丢弃后读取结束。
+    fn test_state_publishing() {
+        let mut broker = publishing_broker();
+        broker.enable_state_publishing(10);
+        let receiver = broker.subscribe_state();
+        let consumer = std::thread::spawn(move || {
+            let mut states = Vec::new();
+            while let Some(state) = receiver.recv() {
+                states.push(state);
+            }
+            states
+        });
+
+        broker.goto_end_of_day().unwrap();
+        let events_processed = broker.events_processed;
+        drop(broker);
+        let states = consumer.join().unwrap();
+
+        assert_eq!(states.len(), events_processed / 10);
+        for (idx, state) in states.iter().enumerate() {
+            assert_eq!(state.seq, idx as u64 + 1);
+            assert_eq!(state.events_processed, (idx + 1) * 10);
+        }
+        assert!(states
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    }
+
+    #[test]
+    /// 测试订阅者不读取状态时的回放。
+    /// 验证回放处理完所有消息，订阅端只保留最新的状态，更早的状态被丢弃。
+    fn test_state_publishing_stalled_consumer() {
+        let mut broker = publishing_broker();
+        broker.enable_state_publishing(1);
+        let receiver = broker.subscribe_state();
+
+        broker.goto_end_of_day().unwrap();
+        let events_processed = broker.events_processed;
+        assert!(events_processed > STATE_CHANNEL_CAPACITY);
+        assert_eq!(broker.published_seq, events_processed as u64);
+        assert_eq!(
+            receiver.dropped(),
+            events_processed - STATE_CHANNEL_CAPACITY
+        );
+        let seqs: Vec<u64> = receiver.drain().iter().map(|state| state.seq).collect();
+        let first = (events_processed - STATE_CHANNEL_CAPACITY) as u64 + 1;
+        assert_eq!(seqs, (first..=events_processed as u64).collect::<Vec<_>>());
+        assert!(!receiver.is_closed());
+
+        // 订阅端丢弃后不再发布
+        drop(receiver);
+        broker.publish_state();
+        assert!(broker.state_publishers.is_empty());
+    }
+
+    #[test]
+    /// 测试深圳部分撤单消息的回放。
+    /// 验证 1000 股的历史卖单撤销 400 股后保留 600 股在原价位，之后的历史买单与剩余的 600 股成交。
+    fn test_sz_partial_cancel_reduces_order() {
+        let start = 20231201100000000;
+        let mut history = local_history(&[
+            (start + 10, Side::Sell, 10.0, 1000.0),
+            (start + 30, Side::Buy, 10.0, 600.0),
+        ]);
+        {
+            let orders = history.orders.as_ref().unwrap();
+            let mut sell = orders[&1].borrow_mut();
+            let auxiliary_info = sell.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.cancel_seq = 3;
+            auxiliary_info.cancel_qty = 400.0;
+            auxiliary_info.orderbook_price = 10.0;
+            auxiliary_info.orderbook_qty = 600.0;
+            let mut buy = orders[&2].borrow_mut();
+            let auxiliary_info = buy.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.match_price = 10.0;
+            auxiliary_info.match_qty = 600.0;
+        }
+        // 撤单消息在买单之前到达
+        let index_by_seq = history.index_by_seq.as_mut().unwrap();
+        index_by_seq.insert(1, (3, 1));
+        history.len += 1;
+        let sell = history.orders.as_ref().unwrap()[&1].clone();
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .stock(MarketType::SZ, "000001.SZ")
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+
+        broker.goto(start + 20).unwrap();
+        assert_eq!(sell.borrow().vol, 6);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 6);
+        assert_eq!(broker.market_depth.get_statistics().total_cancel_vol, 4);
+
+        broker.goto(start + 40).unwrap();
+        assert_eq!(sell.borrow().vol, 0);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 0);
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1000), 0);
+    }
+
+    /// 回放一笔部分主动成交的历史买单，剩余部分挂在已有用户买单的 10.00 档位，返回历史买单和用户买单。
+    fn replay_residual(policy: ResidualPriority) -> (L3OrderRef, L3OrderRef) {
+        let start = 20231201100000000;
+        let history = local_history(&[(start + 10, Side::Buy, 10.0, 300.0)]);
+        let buy = history.orders.as_ref().unwrap()[&1].clone();
+        {
+            let mut order = buy.borrow_mut();
+            let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.match_price = 10.0;
+            auxiliary_info.match_qty = 100.0;
+            auxiliary_info.orderbook_price = 10.0;
+            auxiliary_info.orderbook_qty = 200.0;
+        }
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(start)
+            .open(10.0)
+            .first_order_id(100)
+            .ask(10.0, 100.0, 1)
+            .build_broker()
+            .unwrap();
+        broker.set_residual_priority(policy);
+        let user = L3Order::new_ref(
+            OrderSourceType::UserOrder,
             None,
-            "AAPL".to_string(),
-            timestamp,
-            150.0,
-            10.0,
-            "Sell",
+            200,
+            Side::Buy,
+            1000,
+            1,
+            start,
             OrderType::L,
-            OrderSourceType::UserOrder,
         );
-        sell_order_ref.borrow_mut().order_id = 2;
-        broker.submit_order(buy_order_ref.clone()).unwrap();
-        broker.submit_order(sell_order_ref.clone()).unwrap();
-        // Process the local order
+        broker.market_depth.add(user.clone()).unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.goto(start + 20).unwrap();
+        (buy, user)
+    }
 
-        broker.elapse(1000);
-        broker.sync_order_info();
+    #[test]
+    /// 测试历史订单部分成交后剩余部分的排队位置。
+    /// 验证 `Reset` 时剩余部分排在用户买单之后，`Keep` 时排在队首，用户买单后移。
+    fn test_residual_priority() {
+        let (buy, user) = replay_residual(ResidualPriority::Reset);
+        assert_eq!(buy.borrow().vol, 2);
+        assert_eq!(buy.borrow().price_tick, 1000);
+        assert_eq!(user.borrow().idx, 1);
+        assert_eq!(buy.borrow().idx, 2);
 
-        // print!("{buy_order_ref:?}\n,{sell_order_ref:?}\n");
+        let (buy, user) = replay_residual(ResidualPriority::Keep);
+        assert_eq!(buy.borrow().vol, 2);
+        assert_eq!(buy.borrow().idx, 1);
+        assert_eq!(user.borrow().idx, 2);
+    }
 
-        // print!("{:?}\n", broker.market_depth.get_ask_level(2));
-        // print!("{:?}\n", broker.market_depth.get_bid_level(2));
-        // Verify the order status
-        assert_eq!(buy_order_ref.borrow().status, OrderStatus::Filled);
+    /// 订单簿只有 `ask_tick` 的一手卖单，回放一笔历史成交价为 10.01 的主动买单。
+    fn replay_trade_through(
+        policy: TradeRepairPolicy,
+        ask_tick: i64,
+    ) -> Broker<SkipListMarketDepth> {
+        let start = 20231201100000000;
+        let history = local_history(&[(start + 10, Side::Buy, 10.01, 100.0)]);
+        {
+            let mut order = history.orders.as_ref().unwrap()[&1].borrow_mut();
+            let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+            auxiliary_info.match_price = 10.01;
+            auxiliary_info.match_qty = 100.0;
+        }
+
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Backtest)
+            .timestamp(start)
+            .open(10.0)
+            .first_order_id(100)
+            .ask(ask_tick as f64 * 0.01, 100.0, 1)
+            .build_broker()
+            .unwrap();
+        broker.set_trade_repair_policy(policy);
+        broker.add_data(Some(history)).unwrap();
+        broker.goto(start + 20).unwrap();
+        broker
     }
 
     #[test]
-    fn test_process_cancel_order() {
-        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
-            ExchangeMode::Live,
-            MarketType::SH,
-            "STOCK".to_string(),
-            "CODE".to_string(),
-            0.01,
-            1.0,
+    /// 测试历史成交价对应的档位不在重建的订单簿中时的处理方式。
+    /// 验证 `MatchAsIs` 按 10.00 成交，`ForcePrice` 的最新成交价为历史成交价，`Flag` 在差异报告中记录价差。
+    fn test_trade_repair_policy() {
+        let broker = replay_trade_through(TradeRepairPolicy::MatchAsIs, 1000);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 0);
+        assert_eq!(
+            broker.market_depth.last_tick(&OrderSourceType::LocalOrder),
+            1000
         );
-        broker.init();
+        assert!(broker.divergences().is_empty());
 
-        let timestamp = 20231201093021355;
-        broker.set_current_time(timestamp);
+        let broker = replay_trade_through(TradeRepairPolicy::ForcePrice, 1000);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1000), 0);
+        // 更优的档位满足了成交数量，补上的挂单被撤销
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1001), 0);
+        assert_eq!(
+            broker.market_depth.last_tick(&OrderSourceType::LocalOrder),
+            1001
+        );
+        assert!(broker.divergences().is_empty());
+
+        let broker = replay_trade_through(TradeRepairPolicy::Flag, 1000);
+        assert_eq!(
+            broker.market_depth.last_tick(&OrderSourceType::LocalOrder),
+            1000
+        );
+        let divergences = broker.divergences();
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(
+            divergences[0].kind,
+            DivergenceKind::TradePrint {
+                seq: 1,
+                order_id: 1
+            }
+        );
+        assert_eq!(divergences[0].side, Side::Buy);
+        assert_eq!(divergences[0].expected, Some((10.01, 100.0)));
+        assert_eq!(divergences[0].actual, Some((10.0, 100.0)));
+    }
+
+    #[test]
+    /// 测试订单簿中没有不差于历史成交价的档位时的处理方式。
+    /// 验证 `MatchAsIs` 没有成交，`ForcePrice` 补上 10.01 的档位后以历史成交价成交，10.02 的卖单不受影响。
+    fn test_trade_repair_synthesizes_missing_level() {
+        let broker = replay_trade_through(TradeRepairPolicy::MatchAsIs, 1002);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1002), 1);
+        assert_ne!(
+            broker.market_depth.last_tick(&OrderSourceType::LocalOrder),
+            1001
+        );
+
+        let broker = replay_trade_through(TradeRepairPolicy::ForcePrice, 1002);
+        assert_eq!(
+            broker.market_depth.last_tick(&OrderSourceType::LocalOrder),
+            1001
+        );
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1001), 0);
+        assert_eq!(broker.market_depth.ask_vol_at_tick(1002), 1);
+        // 历史买单全部成交，没有剩余挂在订单簿中
+        assert_eq!(broker.market_depth.bid_vol_at_tick(1001), 0);
+    }
+
+    #[test]
+    /// 测试日终的用户订单处置报告。
+    /// 验证全部成交、部分成交后撤销、被风控拒绝和从未生效的订单分别计入对应的策略、账户和处置结果。
+    fn test_disposition_report() {
+        let start = 20231201100000000;
+        let history = local_history(&[
+            (start + 120, Side::Sell, 10.0, 100.0),
+            (start + 130, Side::Sell, 10.0, 100.0),
+        ]);
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+
+        let new_order = |order_id: OrderId,
+                         account: &str,
+                         strategy_id: StrategyId,
+                         local_time: i64,
+                         qty: f64| {
+            let order_ref = Order::new_ref(
+                Some(account.to_string()),
+                "600000.SH".to_string(),
+                local_time,
+                10.0,
+                qty,
+                "b",
+                OrderType::L,
+                OrderSourceType::UserOrder,
+            );
+            order_ref.borrow_mut().order_id = order_id;
+            order_ref.borrow_mut().strategy_id = strategy_id;
+            order_ref
+        };
+        broker
+            .submit_order(new_order(1, "acct", 1, start, 100.0))
+            .unwrap();
+        broker
+            .submit_order(new_order(2, "acct", 1, start, 300.0))
+            .unwrap();
+        broker.elapse(200).unwrap();
+        assert_eq!(broker.request_cancel(2), Ok(0));
+        broker.sync_order_info();
+
+        // 子单数量超过母单的剩余数量，被风控拒绝
+        broker.register_parent(90, 50.0, Side::Buy).unwrap();
+        let child = new_order(3, "risk", 2, start + 200, 100.0);
+        child.borrow_mut().parent_id = Some(90);
+        assert_eq!(
+            broker.submit_order(child),
+            Err(MarketError::ParentQtyExceeded)
+        );
+        // 报单通道中断不是风控拒绝
+        broker
+            .schedule_outage(start + 200, start + 300, OutageKind::OrderEntry)
+            .unwrap();
+        assert_eq!(
+            broker.submit_order(new_order(5, "risk", 2, start + 200, 100.0)),
+            Err(MarketError::GatewayUnavailable)
+        );
+        // 订单 ID 重复的请求不计入报告
+        assert_eq!(
+            broker.submit_order(new_order(1, "acct", 1, start + 300, 100.0)),
+            Err(MarketError::OrderIdExist)
+        );
+        // 下午才生效的订单在日终前一直在等待队列中
+        broker
+            .submit_order(new_order(4, "risk", 2, 20231201140000000, 200.0))
+            .unwrap();
+
+        let report = broker.disposition_report();
+        let stats = |strategy_id, account, disposition| {
+            report
+                .get(strategy_id, account, disposition)
+                .map(|stats| (stats.order_count, stats.total_qty, stats.filled_qty))
+        };
+        assert_eq!(
+            stats(1, "acct", Disposition::Filled),
+            Some((1, 100.0, 100.0))
+        );
+        assert_eq!(
+            stats(1, "acct", Disposition::PartiallyFilledCanceled),
+            Some((1, 300.0, 100.0))
+        );
+        assert_eq!(
+            stats(2, "risk", Disposition::RiskRejected),
+            Some((1, 100.0, 0.0))
+        );
+        assert_eq!(
+            stats(2, "risk", Disposition::Rejected),
+            Some((1, 100.0, 0.0))
+        );
+        assert_eq!(
+            stats(2, "risk", Disposition::Unactivated),
+            Some((1, 200.0, 0.0))
+        );
+        assert_eq!(stats(1, "acct", Disposition::Canceled), None);
+        assert_eq!(stats(1, "acct", Disposition::Rejected), None);
+        assert_eq!(report.rejected, vec![3, 5]);
+        assert_eq!(report.rejected_omitted, 0);
+        assert_eq!(report.unactivated, vec![4]);
+
+        let json = serde_json::to_string(&report).unwrap();
+        let restored: DispositionReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, report);
+    }
+
+    /// 按 maker 费率扣除返佣为 -0.01%、taker 费率为 0.03% 计算手续费，回放 `user_time` 提交的买单，
+    /// 返回买单的手续费。
+    fn user_buy_fee(
+        history: DataCollator,
+        user_time: i64,
+        price: f64,
+        order_type: OrderType,
+    ) -> f64 {
+        let start = 20231201100000000;
+        let (mut broker, _) = BookFixture::new(ExchangeMode::Live)
+            .timestamp(start)
+            .open(10.0)
+            .build_broker()
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_fee_model(Some(FeeModel::symmetric(SideFees {
+            maker_rate: 0.0001,
+            taker_rate: 0.0003,
+            maker_rebate: 0.0002,
+        })));
+        broker.goto(user_time).unwrap();
 
-        // Create and submit a limit order
         let order_ref = Order::new_ref(
-            None,
-            "AAPL".to_string(),
-            timestamp,
-            150.0,
-            10.0,
-            "Buy",
-            OrderType::L,
+            Some("acct".to_string()),
+            "600000.SH".to_string(),
+            user_time,
+            price,
+            100.0,
+            "b",
+            order_type,
             OrderSourceType::UserOrder,
         );
         order_ref.borrow_mut().order_id = 1;
         broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(start + 200 - user_time).unwrap();
+        broker.sync_order_info();
+        assert_eq!(order_ref.borrow().status, OrderStatus::Filled);
+        assert_eq!(broker.order_fee(1).unwrap(), broker.total_fees());
+        broker.order_fee(1).unwrap()
+    }
 
-        // Process the order to ensure it is added
-        broker.elapse(1000);
+    #[test]
+    /// 测试按流动性角色收取手续费。
+    /// 验证挂单被历史卖单成交时得到 maker 返佣，主动成交和市价订单按实际成交金额支付 taker 费用。
+    fn test_fee_model_maker_taker() {
+        let start = 20231201100000000;
 
-        broker.cancel_order(order_ref.borrow_mut().order_id);
-        // print!("{:?}\n", broker.market_depth.orders);
-        broker.sync_order_info();
+        // 买单先挂在 10.00，历史卖单到达后被动成交 1 手，成交金额 1000
+        let passive = user_buy_fee(
+            local_history(&[(start + 100, Side::Sell, 10.0, 100.0)]),
+            start + 10,
+            10.0,
+            OrderType::L,
+        );
+        assert!((passive - (-0.1)).abs() < 1e-9);
 
-        assert_eq!(order_ref.borrow().status, OrderStatus::Canceled);
+        // 限价 10.01 的买单主动与挂在 10.00 的历史卖单成交，成交金额 1000
+        let aggressive = user_buy_fee(
+            local_history(&[(start + 10, Side::Sell, 10.0, 100.0)]),
+            start + 100,
+            10.01,
+            OrderType::L,
+        );
+        assert!((aggressive - 0.3).abs() < 1e-9);
+
+        // 没有价格的市价买单同样按成交价 10.00 计算成交金额
+        let market = user_buy_fee(
+            local_history(&[(start + 10, Side::Sell, 10.0, 100.0)]),
+            start + 100,
+            0.0,
+            OrderType::C,
+        );
+        assert!((market - 0.3).abs() < 1e-9);
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use super::*;
+
+/// 正常交易日的收盘时间，以 `HHMMSSmmm` 表示。
+pub const NORMAL_CLOSE_TIME: i64 = 150000000;
+/// 半日交易日（节假日前的提前收盘）默认的收盘时间，以 `HHMMSSmmm` 表示。
+pub const HALF_DAY_CLOSE_TIME: i64 = 113000000;
+/// 收盘集合竞价持续的分钟数。
+pub const CLOSING_AUCTION_MINUTES: i64 = 3;
+/// 上午连续竞价的开始时间，以 `HHMMSSmmm` 表示。
+pub const MORNING_OPEN_TIME: i64 = 93000000;
+/// 午间休市的开始时间，以 `HHMMSSmmm` 表示。
+pub const BREAK_START_TIME: i64 = 113000000;
+/// 午间休市的结束时间，即下午连续竞价的开始时间，以 `HHMMSSmmm` 表示。
+pub const BREAK_END_TIME: i64 = 130000000;
+
+/// `TradingCalendar` 记录提前收盘的半日交易日，并据此划分交易时段。
+///
+/// 没有标记的日期按正常交易日处理，结果与 `TradingPhase::from_timestamp` 和
+/// `should_call_auction_on_close` 一致。半日交易日在收盘前 `CLOSING_AUCTION_MINUTES`
+/// 分钟进入收盘集合竞价，连续竞价随之提前结束。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TradingCalendar {
+    /// 半日交易日的日期（`YYYYMMDD`）到收盘时间（`HHMMSSmmm`）的映射
+    half_days: BTreeMap<i64, i64>,
+}
+
+impl TradingCalendar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 将某一日期标记为半日交易日。
+    ///
+    /// # 参数
+    /// - `date`: 日期，格式为 `20231229`。
+    /// - `close_time`: 当日的收盘时间，格式为 `113000000`。
+    pub fn mark_half_day(&mut self, date: i64, close_time: i64) {
+        self.half_days.insert(date, close_time);
+    }
+
+    /// 取消某一日期的半日交易日标记，返回该日期之前是否被标记。
+    pub fn unmark_half_day(&mut self, date: i64) -> bool {
+        self.half_days.remove(&date).is_some()
+    }
+
+    /// 判断给定时间戳所在的日期是否为半日交易日。
+    pub fn is_half_day(&self, timestamp: i64) -> bool {
+        self.half_days.contains_key(&(timestamp / 1_000_000_000))
+    }
+
+    /// 返回给定时间戳所在日期的收盘时间，以 `HHMMSSmmm` 表示。
+    pub fn close_time(&self, timestamp: i64) -> i64 {
+        self.half_days
+            .get(&(timestamp / 1_000_000_000))
+            .copied()
+            .unwrap_or(NORMAL_CLOSE_TIME)
+    }
+
+    /// 返回给定时间戳所在日期收盘集合竞价的开始时间，以 `HHMMSSmmm` 表示，开始时间属于收盘集合竞价。
+    pub fn closing_auction_start(&self, timestamp: i64) -> i64 {
+        shift_minutes(self.close_time(timestamp), -CLOSING_AUCTION_MINUTES)
+    }
+
+    /// 根据时间戳判断所处的交易时段，半日交易日的收盘集合竞价提前开始。
+    ///
+    /// # 错误
+    /// 市场类型未知时返回 `MarketError::MarketTypeUnknownError`。
+    pub fn phase(&self, timestamp: i64, market: MarketType) -> Result<TradingPhase, MarketError> {
+        let phase = TradingPhase::from_timestamp(timestamp, market)?;
+        let only_time = timestamp % 1_000_000_000;
+        if phase != TradingPhase::OpeningAuction
+            && only_time >= self.closing_auction_start(timestamp)
+        {
+            return Ok(TradingPhase::ClosingAuction);
+        }
+        Ok(phase)
+    }
+
+    /// 判断是否处于集合竞价时段，参见 `is_in_call_auction`。
+    pub fn is_in_call_auction(
+        &self,
+        timestamp: i64,
+        market: MarketType,
+    ) -> Result<bool, MarketError> {
+        Ok(matches!(
+            self.phase(timestamp, market)?,
+            TradingPhase::OpeningAuction | TradingPhase::ClosingAuction
+        ))
+    }
+
+    /// 判断是否应该调用收盘竞价，参见 `should_call_auction_on_close`。
+    pub fn should_call_auction_on_close(
+        &self,
+        timestamp: i64,
+        market: MarketType,
+    ) -> Result<bool, MarketError> {
+        TradingPhase::from_timestamp(timestamp, market)?;
+        Ok(timestamp % 1_000_000_000 > self.close_time(timestamp))
+    }
+
+    /// 返回晚于 `timestamp` 的下一个采样网格时间点，只在当日查找。
+    ///
+    /// 上午的网格从 `MORNING_OPEN_TIME` 开始，下午的网格从 `BREAK_END_TIME` 开始，每隔 `grid_ms`
+    /// 毫秒一个时间点。午间休市 `[BREAK_START_TIME, BREAK_END_TIME)` 内没有时间点，
+    /// 收盘时间在网格上时包含收盘时间。半日交易日在收盘后没有时间点。
+    ///
+    /// # 参数
+    /// - `timestamp`: 格式为 `20230801093939123` 的时间戳。
+    /// - `grid_ms`: 网格间隔，以毫秒为单位。
+    ///
+    /// # 返回值
+    /// 下一个网格时间点的时间戳，当日已经没有网格时间点时返回 `None`。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidGridInterval`: `grid_ms` 不是正数。
+    pub fn next_grid_time(&self, timestamp: i64, grid_ms: i64) -> Result<Option<i64>, MarketError> {
+        if grid_ms <= 0 {
+            return Err(MarketError::InvalidGridInterval(grid_ms));
+        }
+        let date = timestamp / 1_000_000_000;
+        let now = time_to_ms(timestamp % 1_000_000_000);
+        let close = time_to_ms(self.close_time(timestamp));
+        let break_start = time_to_ms(BREAK_START_TIME);
+        // 每个时段的 (开始时间, 最后一个可以采样的时间)，均以当日的毫秒数表示
+        let sessions = [
+            (
+                time_to_ms(MORNING_OPEN_TIME),
+                if close > break_start {
+                    break_start - 1
+                } else {
+                    close
+                },
+            ),
+            (time_to_ms(BREAK_END_TIME), close),
+        ];
+        for (start, last) in sessions {
+            if now >= last {
+                continue;
+            }
+            let steps = if now < start {
+                0
+            } else {
+                (now - start) / grid_ms + 1
+            };
+            let grid_time = start + steps * grid_ms;
+            if grid_time <= last {
+                return Ok(Some(date * 1_000_000_000 + ms_to_time(grid_time)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// 将 `HHMMSSmmm` 表示的时间转换为当日的毫秒数。
+fn time_to_ms(time: i64) -> i64 {
+    (time / 10_000_000 * 3600 + time / 100_000 % 100 * 60 + time / 1_000 % 100) * 1_000
+        + time % 1_000
+}
+
+/// 将当日的毫秒数转换为 `HHMMSSmmm` 表示的时间。
+fn ms_to_time(ms: i64) -> i64 {
+    let seconds = ms / 1_000;
+    seconds / 3600 * 10_000_000 + seconds / 60 % 60 * 100_000 + seconds % 60 * 1_000 + ms % 1_000
+}
+
+/// 将 `HHMMSSmmm` 表示的时间平移给定的分钟数。
+fn shift_minutes(time: i64, minutes: i64) -> i64 {
+    let total = time / 10_000_000 * 60 + time / 100_000 % 100 + minutes;
+    total / 60 * 10_000_000 + total % 60 * 100_000 + time % 100_000
+}
@@ -15,6 +15,28 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use super::MarketError;
+
+/// 默认的行情文件相对路径模板。
+///
+/// 支持的占位符：`{exchange}`、`{stock_type}`、`{data_type}`、`{month}`、`{symbol}`。
+pub const DEFAULT_PATH_TEMPLATE: &str =
+    "{exchange}_{stock_type}_{data_type}_Auction_Month/month={month}/{exchange}_{stock_type}_{data_type}_Auction_{symbol}_{month}.parquet";
+
+/// 默认的 HDFS 行情根目录。
+pub const DEFAULT_HDFS_BASE: &str = "/htdata/mdc/MDCProvider/";
+
+/// 默认的 HDFS 打开文件重试次数。
+pub const DEFAULT_RETRY_COUNT: usize = 3;
+
+/// 默认的首次重试等待时间，之后每次重试加倍。
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 重试等待时间的上限。
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone)]
 pub struct DataApi {
@@ -24,11 +46,87 @@ pub struct DataApi {
     pub _price_unit: RefCell<f64>, //100表示保留2位小数
     pub _broker_mod: String,
     pub _data_path: String,
+    pub _path_template: String,
+    pub _hdfs_base: String,
+    pub _retry_count: usize,
+    pub _retry_backoff: Duration,
     pub fs: Option<Arc<Client>>,
 }
 
+/// `DataApiConfig` 保存访问 HDFS 所需的环境变量默认值。
+///
+/// 只有在环境变量尚未设置时才会使用这里的值，已有的环境配置不会被覆盖。
+#[derive(Debug, Clone)]
+pub struct DataApiConfig {
+    pub java_home: String,
+    pub java_tool_options: String,
+    pub arrow_libhdfs_dir: String,
+    pub hadoop_home: String,
+    pub hadoop_conf_dir: String,
+    pub yarn_conf_dir: String,
+    pub ld_library_path: String,
+}
+
+impl Default for DataApiConfig {
+    fn default() -> Self {
+        Self {
+            java_home: "/usr/java/latest/".to_string(),
+            java_tool_options: "-Xss1280K".to_string(),
+            arrow_libhdfs_dir: "/opt/cloudera/parcels/CDH/lib/impala/lib/".to_string(),
+            hadoop_home: "/opt/cloudera/parcels/CDH/lib/hadoop".to_string(),
+            hadoop_conf_dir: "/opt/cloudera/parcels/CDH/lib/hadoop/etc/hadoop".to_string(),
+            yarn_conf_dir: "/opt/cloudera/parcels/CDH/lib/hadoop/etc/hadoop".to_string(),
+            ld_library_path: "/usr/java/latest/jre/lib/amd64/server/".to_string(),
+        }
+    }
+}
+
+impl DataApiConfig {
+    /// 将配置写入环境变量，已经存在的环境变量保持不变。
+    pub fn apply_env(&self) {
+        let vars = [
+            ("JAVA_HOME", &self.java_home),
+            ("JAVA_TOOL_OPTIONS", &self.java_tool_options),
+            ("ARROW_LIBHDFS_DIR", &self.arrow_libhdfs_dir),
+            ("HADOOP_HOME", &self.hadoop_home),
+            ("HADOOP_CONF_DIR", &self.hadoop_conf_dir),
+            ("YARN_CONF_DIR", &self.yarn_conf_dir),
+            ("LD_LIBRARY_PATH", &self.ld_library_path),
+        ];
+        for (key, value) in vars {
+            if env::var_os(key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+    }
+}
+
 impl DataApi {
     pub fn new(date: String, file_type: String, broker_mod: String, data_path: String) -> Self {
+        Self::new_with_config(
+            date,
+            file_type,
+            broker_mod,
+            data_path,
+            DataApiConfig::default(),
+        )
+    }
+
+    /// 使用指定的环境变量配置创建 `DataApi` 实例。
+    ///
+    /// # 参数
+    /// - `date`: 交易日期，格式为 `YYYYMMDD`。
+    /// - `file_type`: 文件类型，可选 `hdfs`、`local` 或 `vector`。
+    /// - `broker_mod`: 数据模式。
+    /// - `data_path`: 本地行情文件根目录。
+    /// - `config`: 环境变量默认值，仅在对应环境变量未设置时生效。
+    pub fn new_with_config(
+        date: String,
+        file_type: String,
+        broker_mod: String,
+        data_path: String,
+        config: DataApiConfig,
+    ) -> Self {
         let _date = date;
         let _file_type = file_type;
         let _stock_type = RefCell::new("unknow".to_string());
@@ -44,25 +142,7 @@ impl DataApi {
         let _broker_mod = broker_mod;
         let _data_path = data_path;
 
-        env::set_var("JAVA_HOME", "/usr/java/latest/");
-        env::set_var("JAVA_TOOL_OPTIONS", "-Xss1280K");
-        env::set_var(
-            "ARROW_LIBHDFS_DIR",
-            "/opt/cloudera/parcels/CDH/lib/impala/lib/",
-        );
-        env::set_var("HADOOP_HOME", "/opt/cloudera/parcels/CDH/lib/hadoop");
-        env::set_var(
-            "HADOOP_CONF_DIR",
-            "/opt/cloudera/parcels/CDH/lib/hadoop/etc/hadoop",
-        );
-        env::set_var(
-            "YARN_CONF_DIR",
-            "/opt/cloudera/parcels/CDH/lib/hadoop/etc/hadoop",
-        );
-        env::set_var(
-            "LD_LIBRARY_PATH",
-            "$LD_LIBRARY_PATH:/usr/java/latest/jre/lib/amd64/server/",
-        );
+        config.apply_env();
 
         let fs = if _file_type == "hdfs" {
             Some(Arc::new(ClientBuilder::new("default").connect().unwrap()))
@@ -77,10 +157,83 @@ impl DataApi {
             _price_unit,
             _broker_mod,
             _data_path,
+            _path_template: DEFAULT_PATH_TEMPLATE.to_string(),
+            _hdfs_base: DEFAULT_HDFS_BASE.to_string(),
+            _retry_count: DEFAULT_RETRY_COUNT,
+            _retry_backoff: DEFAULT_RETRY_BACKOFF,
             fs,
         }
     }
 
+    /// 设置行情文件的相对路径模板。
+    ///
+    /// # 参数
+    /// - `template`: 路径模板，支持 `{exchange}`、`{stock_type}`、`{data_type}`、`{month}`、`{symbol}` 占位符。
+    pub fn set_path_template(&mut self, template: &str) {
+        self._path_template = template.to_string();
+    }
+
+    /// 设置 HDFS 行情根目录，仅在 `hdfs` 文件类型下生效。
+    ///
+    /// # 参数
+    /// - `base`: HDFS 上的行情根目录。
+    pub fn set_hdfs_base(&mut self, base: &str) {
+        self._hdfs_base = base.to_string();
+    }
+
+    /// 设置 HDFS 打开文件失败时的重试次数。
+    ///
+    /// # 参数
+    /// - `retry_count`: 首次失败后的最大重试次数，0 表示不重试。
+    pub fn set_retry_count(&mut self, retry_count: usize) {
+        self._retry_count = retry_count;
+    }
+
+    /// 设置首次重试前的等待时间，之后每次重试加倍，最长不超过 `MAX_RETRY_BACKOFF`。
+    ///
+    /// # 参数
+    /// - `backoff`: 首次重试前的等待时间，0 表示不等待。
+    pub fn set_retry_backoff(&mut self, backoff: Duration) {
+        self._retry_backoff = backoff;
+    }
+
+    /// 第 `attempt` 次重试前的等待时间，从 1 开始计数。
+    fn retry_delay(&self, attempt: usize) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1) as u32)
+            .unwrap_or(u32::MAX);
+        self._retry_backoff
+            .saturating_mul(factor)
+            .min(MAX_RETRY_BACKOFF)
+    }
+
+    /// 根据路径模板生成行情文件的相对路径。
+    ///
+    /// # 参数
+    /// - `exchange`: 交易所代码，例如 `XSHE` 或 `XSHG`。
+    /// - `stock_type`: 标的类型，例如 `Stock` 或 `Fund`。
+    /// - `data_type`: 数据类型，例如 `Order` 或 `Transaction`。
+    /// - `month`: 年月，格式为 `YYYYMM`。
+    /// - `symbol`: 标的代码，例如 `000001.SZ`。
+    ///
+    /// # 返回值
+    /// 返回替换占位符后的相对路径。
+    pub fn render_path(
+        &self,
+        exchange: &str,
+        stock_type: &str,
+        data_type: &str,
+        month: &str,
+        symbol: &str,
+    ) -> String {
+        self._path_template
+            .replace("{exchange}", exchange)
+            .replace("{stock_type}", stock_type)
+            .replace("{data_type}", data_type)
+            .replace("{month}", month)
+            .replace("{symbol}", symbol)
+    }
+
     fn gen_bs_for_trans(&self, trade_bs_flag: &Series, trade_type: &Series) -> Series {
         let mut res: Vec<&str> = vec![];
         let s_len = trade_bs_flag.len();
@@ -177,19 +330,15 @@ impl DataApi {
         return df_trans;
     }
 
-    fn load_marketdata(&self, symbol: &str, data_type: &str) -> DataFrame {
+    fn load_marketdata(&self, symbol: &str, data_type: &str) -> Result<DataFrame, MarketError> {
         if (*self._stock_type.borrow()) == "unknow" {
             match self.load_marketdata_by_type(symbol, data_type, "Stock") {
-                Ok(df) => return df,
-                Err(err) => match self.load_marketdata_by_type(symbol, data_type, "Fund") {
-                    Ok(df) => return df,
-                    Err(error_msg) => panic!("{}", error_msg.as_str()),
-                },
+                Ok(df) => Ok(df),
+                Err(_) => self.load_marketdata_by_type(symbol, data_type, "Fund"),
             }
         } else {
             let stock_type = (*self._stock_type.borrow()).clone();
             self.load_marketdata_by_type(symbol, data_type, stock_type.as_str())
-                .unwrap()
         }
     }
 
@@ -198,7 +347,7 @@ impl DataApi {
         symbol: &str,
         data_type: &str,
         stock_type: &str,
-    ) -> Result<DataFrame, String> {
+    ) -> Result<DataFrame, MarketError> {
         // 根据标的获取SZ或SH
         let exchange_code = &symbol[symbol.len() - 2..];
         let date_month = &self._date[0..6];
@@ -216,29 +365,18 @@ impl DataApi {
         } else {
             data_type_str = "Order";
         }
-        if exchange_code == "SZ" {
-            sub_path = format!(
-                "XSHE_{}_{}_Auction_Month/month={}/XSHE_{}_{}_Auction_{}_{}.parquet",
-                stock_type_str,
-                data_type_str,
-                date_month,
-                stock_type_str,
-                data_type_str,
-                symbol,
-                date_month
-            );
+        let exchange_str = if exchange_code == "SZ" {
+            "XSHE"
         } else {
-            sub_path = format!(
-                "XSHG_{}_{}_Auction_Month/month={}/XSHG_{}_{}_Auction_{}_{}.parquet",
-                stock_type_str,
-                data_type_str,
-                date_month,
-                stock_type_str,
-                data_type_str,
-                symbol,
-                date_month
-            );
-        }
+            "XSHG"
+        };
+        sub_path = self.render_path(
+            exchange_str,
+            stock_type_str,
+            data_type_str,
+            date_month,
+            symbol,
+        );
         dbg!(&sub_path);
         if self._file_type == "local" {
             let base_path = Path::new(&self._data_path);
@@ -246,7 +384,7 @@ impl DataApi {
             let error_msg: String = format!("行情文件不存在：{}！", file_path.to_str().unwrap());
             if *self._stock_type.borrow() == "unknow" {
                 if std::fs::metadata(&file_path).is_err() {
-                    return Err(error_msg.to_string());
+                    return Err(MarketError::DataLoadError(error_msg));
                 } else {
                     // 内部可变性
                     let mut variable1 = self._stock_type.borrow_mut();
@@ -257,22 +395,32 @@ impl DataApi {
                     }
                 }
             }
-            let mut file = match std::fs::File::open(file_path) {
+            let mut file = match std::fs::File::open(&file_path) {
                 Ok(f) => f,
-                Err(err) => return Err(error_msg.to_string()),
+                Err(err) => return Err(MarketError::DataLoadError(format!("{error_msg} {err}"))),
             };
-            df_mdc = ParquetReader::new(&mut file).finish().unwrap();
+            df_mdc = ParquetReader::new(&mut file).finish().map_err(|err| {
+                MarketError::DataLoadError(format!(
+                    "行情文件解析失败：{}！{}",
+                    file_path.to_str().unwrap(),
+                    err
+                ))
+            })?;
         } else {
             let fs = match self.fs.as_ref() {
                 Some(value) => value,
-                None => panic!("no value"),
+                None => {
+                    return Err(MarketError::DataLoadError(
+                        "HDFS 客户端未初始化！".to_string(),
+                    ))
+                }
             };
-            let base_path = Path::new(&"/htdata/mdc/MDCProvider/");
+            let base_path = Path::new(&self._hdfs_base);
             let file_path = base_path.join(sub_path);
             let error_msg: String = format!("行情文件不存在：{}！", file_path.to_str().unwrap());
             if *self._stock_type.borrow() == "unknow" {
                 if fs.metadata(&file_path.to_str().unwrap()).is_err() {
-                    return Err(error_msg.to_string());
+                    return Err(MarketError::DataLoadError(error_msg));
                 } else {
                     // 内部可变性
                     let mut variable = self._stock_type.borrow_mut();
@@ -283,20 +431,48 @@ impl DataApi {
                     }
                 }
             }
-            let mut f = match fs.open_file().read(true).open(&file_path.to_str().unwrap()) {
-                Ok(file) => file,
-                Err(err) => return Err(error_msg.to_string()),
+            // HDFS 打开文件可能因为网络抖动等原因短暂失败，按配置的次数重试，每次等待的时间按指数增长
+            let mut attempt = 0;
+            let mut f = loop {
+                match fs.open_file().read(true).open(&file_path.to_str().unwrap()) {
+                    Ok(file) => break file,
+                    Err(err) => {
+                        if attempt >= self._retry_count {
+                            return Err(MarketError::DataLoadError(format!(
+                                "{error_msg} 重试 {attempt} 次后仍失败：{err}"
+                            )));
+                        }
+                        attempt += 1;
+                        sleep(self.retry_delay(attempt));
+                    }
+                }
             };
             let mut buf: Vec<u8> = Vec::new();
-            let n = f.read_to_end(&mut buf).unwrap();
+            f.read_to_end(&mut buf).map_err(|err| {
+                MarketError::DataLoadError(format!(
+                    "行情文件读取失败：{}！{}",
+                    file_path.to_str().unwrap(),
+                    err
+                ))
+            })?;
             let reader = Cursor::new(&buf);
-            df_mdc = ParquetReader::new(reader).finish().unwrap();
+            df_mdc = ParquetReader::new(reader).finish().map_err(|err| {
+                MarketError::DataLoadError(format!(
+                    "行情文件解析失败：{}！{}",
+                    file_path.to_str().unwrap(),
+                    err
+                ))
+            })?;
         }
         Ok(df_mdc)
     }
 
-    pub fn load_transaction_data(&self, symbol: &str, transform: bool) -> DataFrame {
-        let mut df_mdc = self.load_marketdata(symbol, "Transaction");
+    pub fn load_transaction_data(
+        &self,
+        symbol: &str,
+        transform: bool,
+    ) -> Result<DataFrame, MarketError> {
+        let mut df_mdc = self.load_marketdata(symbol, "Transaction")?;
         df_mdc = df_mdc
             .lazy()
             .filter(col("MDDate").eq(lit(self._date.to_string())))
@@ -307,19 +483,18 @@ impl DataApi {
         df_mdc = df_mdc
             .lazy()
             .with_columns([col("MDTime").cast(DataType::Int64) + lit(date_int)])
-            .filter(col("MDTime").lt(lit(date_int + 150000000)))
             .collect()
             .unwrap();
         if transform {
             let df_trans = self.transform_trans_data(df_mdc);
-            return df_trans;
+            return Ok(df_trans);
         } else {
             df_mdc = df_mdc
                 .lazy()
                 .filter(col("MDDate").is_in(lit(&*self._date)))
                 .collect()
                 .unwrap();
-            return df_mdc;
+            return Ok(df_mdc);
         }
     }
 
@@ -421,9 +596,9 @@ impl DataApi {
         return df_order;
     }
 
-    fn _load_order_data(&self, symbol: &str, transform: bool) -> DataFrame {
+    fn _load_order_data(&self, symbol: &str, transform: bool) -> Result<DataFrame, MarketError> {
         let exchange_code = &symbol[symbol.len() - 2..];
-        let mut df_mdc = self.load_marketdata(symbol, "Order");
+        let mut df_mdc = self.load_marketdata(symbol, "Order")?;
         let column_vec = df_mdc.get_column_names_owned();
         for colume in column_vec {
             if colume == "SecurityStatus" {
@@ -445,7 +620,6 @@ impl DataApi {
         df_mdc = df_mdc
             .lazy()
             .with_columns([col("MDTime").cast(DataType::Int64) + lit(date_int)])
-            .filter(col("MDTime").lt(lit(date_int + 150000000)))
             .collect()
             .unwrap();
 
@@ -460,14 +634,14 @@ impl DataApi {
         }
         if transform {
             let df_order = self.transform_order_data(df_mdc);
-            return df_order;
+            return Ok(df_order);
         } else {
             df_mdc = df_mdc
                 .lazy()
                 .filter(col("MDDate").is_in(lit(&*self._date)))
                 .collect()
                 .unwrap();
-            return df_mdc;
+            return Ok(df_mdc);
         }
     }
 
@@ -593,16 +767,15 @@ impl DataApi {
         return df_order_;
     }
 
-    pub fn load_order_data(&self, symbol: &str, transform: bool) -> DataFrame {
+    pub fn load_order_data(&self, symbol: &str, transform: bool) -> Result<DataFrame, MarketError> {
         if self._broker_mod == "ORDER".to_string()
             || self._broker_mod == "L2P".to_string().to_uppercase()
         {
-            let df_order = self._load_order_data(symbol, transform);
-            return df_order;
+            self._load_order_data(symbol, transform)
         } else {
-            let df_trans_ = self.load_transaction_data(symbol, true);
+            let df_trans_ = self.load_transaction_data(symbol, true)?;
             let df_order = self.process_trans_data(&df_trans_);
-            return df_order;
+            Ok(df_order)
         }
     }
 }
@@ -615,7 +788,7 @@ fn test_load_transaction_data_1() {
         "ORDER".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_trans = data_api.load_transaction_data("000001.SZ", true);
+    let df_trans = data_api.load_transaction_data("000001.SZ", true).unwrap();
     println!("{:?}", df_trans);
 }
 
@@ -627,7 +800,7 @@ fn test_load_transaction_data_2() {
         "ORDER".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_trans = data_api.load_transaction_data("600000.SH", true);
+    let df_trans = data_api.load_transaction_data("600000.SH", true).unwrap();
     println!("{:?}", df_trans);
 }
 
@@ -639,7 +812,7 @@ fn test_load_order_data_1() {
         "ORDER".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_order = data_api.load_order_data("000001.SZ", true);
+    let df_order = data_api.load_order_data("000001.SZ", true).unwrap();
     println!("{:?}", df_order);
 }
 
@@ -651,7 +824,7 @@ fn test_load_order_data_2() {
         "ORDER".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_order = data_api.load_order_data("600000.SH", true);
+    let df_order = data_api.load_order_data("600000.SH", true).unwrap();
     println!("{:?}", df_order);
 }
 
@@ -663,7 +836,7 @@ fn test_load_order_data_3() {
         "TRANS".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_order = data_api.load_order_data("000001.SZ", true);
+    let df_order = data_api.load_order_data("000001.SZ", true).unwrap();
     println!("{:?}", df_order);
 }
 
@@ -675,6 +848,72 @@ fn test_load_order_data_4() {
         "TRANS".to_string(),
         "/root/mdc_data".to_string(),
     );
-    let df_order = data_api.load_order_data("600000.SH", true);
+    let df_order = data_api.load_order_data("600000.SH", true).unwrap();
     println!("{:?}", df_order);
 }
+
+#[test]
+fn test_render_path() {
+    let mut data_api = DataApi::new(
+        "20230726".to_string(),
+        "local".to_string(),
+        "ORDER".to_string(),
+        "/root/mdc_data".to_string(),
+    );
+    assert_eq!(
+        data_api.render_path("XSHE", "Stock", "Order", "202307", "000001.SZ"),
+        "XSHE_Stock_Order_Auction_Month/month=202307/XSHE_Stock_Order_Auction_000001.SZ_202307.parquet"
+    );
+
+    data_api.set_path_template("{exchange}/{month}/{stock_type}/{data_type}/{symbol}.parquet");
+    data_api.set_hdfs_base("/data/l2/");
+    assert_eq!(
+        data_api.render_path("XSHG", "Fund", "Transaction", "202302", "510050.SH"),
+        "XSHG/202302/Fund/Transaction/510050.SH.parquet"
+    );
+    assert_eq!(data_api._hdfs_base, "/data/l2/");
+}
+
+#[test]
+fn test_retry_delay() {
+    let mut data_api = DataApi::new(
+        "20230726".to_string(),
+        "local".to_string(),
+        "ORDER".to_string(),
+        "/root/mdc_data".to_string(),
+    );
+    assert_eq!(data_api.retry_delay(1), Duration::from_millis(100));
+    assert_eq!(data_api.retry_delay(2), Duration::from_millis(200));
+    assert_eq!(data_api.retry_delay(4), Duration::from_millis(800));
+    assert_eq!(data_api.retry_delay(10), MAX_RETRY_BACKOFF);
+    assert_eq!(data_api.retry_delay(100), MAX_RETRY_BACKOFF);
+
+    data_api.set_retry_backoff(Duration::ZERO);
+    assert_eq!(data_api.retry_delay(3), Duration::ZERO);
+}
+
+#[test]
+fn test_env_not_overwritten() {
+    env::set_var("JAVA_HOME", "/opt/custom/java");
+    let _data_api = DataApi::new(
+        "20230726".to_string(),
+        "local".to_string(),
+        "ORDER".to_string(),
+        "/root/mdc_data".to_string(),
+    );
+    assert_eq!(env::var("JAVA_HOME").unwrap(), "/opt/custom/java");
+}
+
+#[test]
+fn test_load_missing_file() {
+    let data_api = DataApi::new(
+        "20230726".to_string(),
+        "local".to_string(),
+        "ORDER".to_string(),
+        "/nonexistent/mdc_data".to_string(),
+    );
+    match data_api.load_order_data("000001.SZ", false) {
+        Err(MarketError::DataLoadError(msg)) => assert!(msg.contains("000001.SZ")),
+        other => panic!("unexpected result: {:?}", other.map(|df| df.shape())),
+    }
+}
@@ -1,13 +1,105 @@
 use std::collections::VecDeque;
 use std::str::FromStr;
 
+#[cfg(feature = "data")]
 use super::dataapi::DataApi;
+#[cfg(feature = "data")]
 use super::utils::is_in_call_auction;
 use super::*;
+#[cfg(feature = "data")]
 use polars::export::num::ToPrimitive;
+#[cfg(feature = "data")]
 use polars::prelude::*;
+#[cfg(feature = "data")]
 use rayon::prelude::*;
 
+/// 加载行情数据时发现的数据异常。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DataIssue {
+    /// 成交数量为负数或不是有效数值的成交记录
+    InvalidTradeQty { seq: i64, qty: f64 },
+    /// 主动成交数量超过委托数量的订单，通常是缺少委托记录或者成交被重复计入
+    MatchQtyExceedsInitial {
+        order_id: OrderId,
+        match_qty: f64,
+        initial_qty: f64,
+    },
+    /// 成交引用的 `OrderIndex` 在委托数据中不存在，无法转换为订单编号，该方的成交被跳过
+    UnknownTradeOrderIndex { seq: i64, order_index: i64 },
+}
+
+/// 加载逐笔成交时对数量为 0、负数或无效数值的成交记录的处理方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum InvalidTradePolicy {
+    /// 跳过数量为 0 的成交；数量为负数或无效数值的成交同样跳过，并记录到 `data_issues`
+    #[default]
+    Skip,
+    /// 保留所有成交记录，按原始数据累加成交数量
+    Keep,
+}
+
+/// `post_init` 排序回放队列时，两个订单的回放序号相同时的排序依据。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SeqTiebreak {
+    /// 按委托记录的序号 `initial_seq` 排序，与交易所接收委托的顺序一致；
+    /// 没有委托记录的订单排在最后，仍然相同时按订单 ID 排序
+    #[default]
+    InitialSeq,
+    /// 按订单 ID 排序
+    OrderId,
+}
+
+/// 上海市场逐笔成交中 `TradeBuyNo`、`TradeSellNo` 使用的订单编号。
+///
+/// 上海市场的委托数据同时有订单编号 `OrderNO` 和表示时间顺序的技术编号 `OrderIndex`，
+/// 部分年份的成交数据引用的是 `OrderIndex`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TradeIdNamespace {
+    /// 比较成交引用的编号分别在 `OrderNO` 和 `OrderIndex` 中找到的数量，选择找到更多的一种，
+    /// 数量相同时使用 `OrderNO`
+    #[default]
+    Auto,
+    /// 成交引用委托的 `OrderNO`
+    OrderNo,
+    /// 成交引用委托的 `OrderIndex`
+    OrderIndex,
+}
+
+/// 交易时段的时间窗口，时间以当日 `HHMMSSmmm` 表示，起止时间都包含在窗口内。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionWindow {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl SessionWindow {
+    /// 返回市场的默认交易时段，从 09:15:00 开盘集合竞价到 15:00:30，
+    /// 收盘集合竞价在 15:00:00 之后几秒内发布的成交都在窗口内。
+    pub fn for_market(market_type: MarketType) -> Self {
+        match market_type {
+            MarketType::SH | MarketType::SZ | MarketType::Unknown => Self {
+                start: 91500000,
+                end: 150030000,
+            },
+        }
+    }
+
+    /// 将 `YYYYMMDDHHMMSSmmm` 格式的时间戳转换为当日的 `HHMMSSmmm`。
+    fn time_of_day(timestamp: i64) -> i64 {
+        timestamp % 1000000000
+    }
+
+    /// 时间戳是否早于交易时段。
+    pub fn is_pre_session(&self, timestamp: i64) -> bool {
+        Self::time_of_day(timestamp) < self.start
+    }
+
+    /// 时间戳是否晚于交易时段。
+    pub fn is_post_session(&self, timestamp: i64) -> bool {
+        Self::time_of_day(timestamp) > self.end
+    }
+}
+
 /// `DataCollator` 结构体用于聚合和处理交易所和股票的订单和交易数据。
 ///
 /// # 字段
@@ -35,8 +127,10 @@ pub struct DataCollator {
     pub file_type: String,       // 数据文件类型（如本地或HDFS）
     pub data_path: String,       // 数据路径
     pub source: OrderSourceType, // 订单来源类型
+    #[cfg(feature = "data")]
     #[serde(skip)]
     pub df_order: Option<DataFrame>, // 订单数据框架
+    #[cfg(feature = "data")]
     #[serde(skip)]
     pub df_trade: Option<DataFrame>, // 交易数据框架
     #[serde(skip)]
@@ -49,9 +143,46 @@ pub struct DataCollator {
     pub current_idx: usize, // 当前处理的索引
     #[serde(skip)]
     pub len: usize, // 队列长度
+    #[cfg(feature = "data")]
     #[serde(skip)]
     pub da_api: Option<DataApi>, // 数据 API 对象
     mode: String,
+    /// 数据是否已经加载，重复调用 `init` 时不会重新加载
+    #[serde(skip)]
+    pub initialized: bool,
+    /// 成交数量异常的成交记录的处理方式
+    #[serde(default)]
+    pub invalid_trade_policy: InvalidTradePolicy,
+    /// 加载数据时发现的数据异常
+    #[serde(skip)]
+    pub data_issues: Vec<DataIssue>,
+    /// 交易时段，`None` 时使用 `SessionWindow::for_market` 给出的默认时段
+    #[serde(default)]
+    pub session_window: Option<SessionWindow>,
+    /// 是否保留交易时段以外的行情记录，保留时仍然会计入 `filtered_rows_*`
+    #[serde(default)]
+    pub keep_out_of_session: bool,
+    /// 早于交易时段的委托和成交记录数量，例如交易所的测试消息
+    #[serde(skip)]
+    pub filtered_rows_pre_session: usize,
+    /// 晚于交易时段的委托和成交记录数量
+    #[serde(skip)]
+    pub filtered_rows_post_session: usize,
+    /// 回放序号相同的订单之间的排序依据
+    #[serde(default)]
+    pub seq_tiebreak: SeqTiebreak,
+    /// 上海市场成交数据引用的订单编号，`Auto` 时在加载成交时自动判断
+    #[serde(default)]
+    pub trade_id_namespace: TradeIdNamespace,
+    /// 加载上海市场成交时实际使用的订单编号，不会是 `Auto`
+    #[serde(skip)]
+    pub resolved_trade_id_namespace: Option<TradeIdNamespace>,
+    /// 上海市场委托的 `OrderIndex` 到 `OrderNO` 的映射，委托数据没有 `OrderIndex` 列时为空
+    #[serde(skip)]
+    pub order_no_by_index: HashMap<i64, OrderId>,
+    /// 由成交创建、没有委托记录的订单数量
+    #[serde(skip)]
+    pub phantom_orders: usize,
 }
 
 impl DataCollator {
@@ -87,20 +218,52 @@ impl DataCollator {
             file_type: file_type,
             data_path: data_path,
             source: OrderSourceType::LocalOrder,
+            #[cfg(feature = "data")]
             df_order: None,
+            #[cfg(feature = "data")]
             df_trade: None,
             is_last: false,
             orders: None,
             index_by_seq: None,
             current_idx: 0,
             len: 0,
+            #[cfg(feature = "data")]
             da_api: None,
             mode: mode_upper,
+            initialized: false,
+            invalid_trade_policy: InvalidTradePolicy::default(),
+            data_issues: Vec::new(),
+            session_window: None,
+            keep_out_of_session: false,
+            filtered_rows_pre_session: 0,
+            filtered_rows_post_session: 0,
+            seq_tiebreak: SeqTiebreak::default(),
+            trade_id_namespace: TradeIdNamespace::default(),
+            resolved_trade_id_namespace: None,
+            order_no_by_index: HashMap::new(),
+            phantom_orders: 0,
         }
     }
 
     /// 初始化 `DataCollator`，根据交易所类型加载数据。
-    pub fn init(&mut self) {
+    ///
+    /// 数据只会加载一次，已经初始化后再次调用不做任何操作。文件类型不是 "local" 或 "hdfs" 时
+    /// 不加载文件，保留由外部数据源填充的订单。
+    ///
+    /// # 错误
+    /// 如果行情文件不存在或读取、解析失败，返回 `MarketError::DataLoadError`。
+    #[cfg(feature = "data")]
+    pub fn init(&mut self) -> Result<(), MarketError> {
+        if self.initialized {
+            return Ok(());
+        }
+        if self.file_type != "local" && self.file_type != "hdfs" {
+            self.orders.get_or_insert_with(HashMap::new);
+            self.index_by_seq.get_or_insert_with(VecDeque::new);
+            self.len = self.index_by_seq.as_ref().unwrap().len();
+            self.initialized = true;
+            return Ok(());
+        }
         let restrict_aggressive_order =
             !self.stock_code.is_empty() && self.stock_code.chars().nth(0) == Some('3');
         // 检查并设置交易所代码
@@ -119,18 +282,13 @@ impl DataCollator {
             self.data_path.clone().to_string(),
         );
 
-        // 加载订单和交易数据（根据文件类型判断是否加载）
-        let (df_order, df_trade) = if self.file_type == "local" || self.file_type == "hdfs" {
-            (
-                da_api.load_order_data(&self.stock_code, false),
-                da_api.load_transaction_data(&self.stock_code, false),
-            )
-        } else {
-            (DataFrame::default(), DataFrame::default())
-        };
+        // 加载订单和交易数据
+        let df_order = da_api.load_order_data(&self.stock_code, false)?;
+        let df_trade = da_api.load_transaction_data(&self.stock_code, false)?;
 
         self.df_order = Some(df_order);
         self.df_trade = Some(df_trade);
+        self.apply_session_window()?;
         self.orders = Some(HashMap::new());
         self.index_by_seq = Some(VecDeque::new());
         self.da_api = Some(da_api);
@@ -140,6 +298,71 @@ impl DataCollator {
         } else {
             self.init_sh();
         }
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// 初始化 `DataCollator`。
+    ///
+    /// 未启用 `data` feature 时不能从文件加载行情，只接受由外部数据源填充的订单，
+    /// 例如通过 [`DataCollator::from_orders`] 构造的实例。
+    ///
+    /// # 错误
+    /// 文件类型为 "local" 或 "hdfs" 时返回 `MarketError::DataLoadError`。
+    #[cfg(not(feature = "data"))]
+    pub fn init(&mut self) -> Result<(), MarketError> {
+        if self.initialized {
+            return Ok(());
+        }
+        if self.file_type == "local" || self.file_type == "hdfs" {
+            return Err(MarketError::DataLoadError(
+                "loading market data requires the `data` feature".to_string(),
+            ));
+        }
+        self.orders.get_or_insert_with(HashMap::new);
+        self.index_by_seq.get_or_insert_with(VecDeque::new);
+        self.len = self.index_by_seq.as_ref().unwrap().len();
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// 由外部数据源提供的历史订单构造 `DataCollator`，不依赖行情文件。
+    ///
+    /// 订单按 `seq` 排序回放，`seq` 相同时按订单 ID 排序。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `date`: 数据日期，格式为 `%Y%m%d`。
+    /// - `orders`: 历史订单，订单 ID 必须唯一。
+    pub fn from_orders(stock_code: String, date: String, orders: Vec<L3OrderRef>) -> Self {
+        let mut collator = Self::new(
+            stock_code,
+            "vector".to_string(),
+            String::new(),
+            date,
+            "ORDER",
+        );
+        let mut index_by_seq: VecDeque<(i64, OrderId)> = orders
+            .iter()
+            .map(|order_ref| {
+                let order = order_ref.borrow();
+                (order.seq, order.order_id)
+            })
+            .collect();
+        index_by_seq.make_contiguous().sort();
+        collator.len = index_by_seq.len();
+        collator.index_by_seq = Some(index_by_seq);
+        collator.orders = Some(
+            orders
+                .into_iter()
+                .map(|order_ref| {
+                    let order_id = order_ref.borrow().order_id;
+                    (order_id, order_ref)
+                })
+                .collect(),
+        );
+        collator.initialized = true;
+        collator
     }
 
     pub fn get_next_timestamp(&self) -> Option<i64> {
@@ -159,6 +382,95 @@ impl DataCollator {
                 .clone(),
         )
     }
+
+    /// 返回下一个待处理的订单，但不移动游标。
+    ///
+    /// # 返回值
+    /// 返回 (序列号, 订单引用)，与随后调用 `next` 得到的结果相同；数据已处理完时返回 `None`。
+    pub fn peek(&self) -> Option<(i64, &L3OrderRef)> {
+        if self.is_last() {
+            return None;
+        }
+        let (idx, order_id) = self.index_by_seq.as_ref().unwrap()[self.current_idx];
+        Some((idx, self.orders.as_ref().unwrap().get(&order_id).unwrap()))
+    }
+
+    /// 返回生效的交易时段。
+    pub fn session_window(&self) -> SessionWindow {
+        self.session_window.unwrap_or_else(|| {
+            SessionWindow::for_market(
+                MarketType::from_str(&self.exchange_code).unwrap_or(MarketType::Unknown),
+            )
+        })
+    }
+
+    /// 统计委托和成交数据中交易时段以外的记录，并在 `keep_out_of_session` 为 `false` 时将其移除。
+    ///
+    /// # 错误
+    /// 数据中缺少 `MDTime` 列或过滤失败时返回 `MarketError::DataLoadError`。
+    #[cfg(feature = "data")]
+    fn apply_session_window(&mut self) -> Result<(), MarketError> {
+        let window = self.session_window();
+        let to_error = |err: PolarsError| MarketError::DataLoadError(err.to_string());
+        for df in [self.df_order.as_mut(), self.df_trade.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            let md_time = df
+                .column("MDTime")
+                .map_err(to_error)?
+                .i64()
+                .map_err(to_error)?;
+            let mut pre_session = 0;
+            let mut post_session = 0;
+            let mask: BooleanChunked = md_time
+                .into_iter()
+                .map(|md_time| match md_time {
+                    Some(md_time) if window.is_pre_session(md_time) => {
+                        pre_session += 1;
+                        false
+                    }
+                    Some(md_time) if window.is_post_session(md_time) => {
+                        post_session += 1;
+                        false
+                    }
+                    _ => true,
+                })
+                .collect();
+            self.filtered_rows_pre_session += pre_session;
+            self.filtered_rows_post_session += post_session;
+            if !self.keep_out_of_session && pre_session + post_session > 0 {
+                *df = df.filter(&mask).map_err(to_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 按 `invalid_trade_policy` 判断成交记录是否应该计入订单的成交数量。
+    ///
+    /// # 返回值
+    /// 成交记录应被计入时返回 `true`；被跳过时返回 `false`，异常的数量会被记录到 `data_issues`。
+    #[cfg(feature = "data")]
+    fn accept_trade_qty(
+        policy: InvalidTradePolicy,
+        data_issues: &mut Vec<DataIssue>,
+        seq: i64,
+        qty: f64,
+    ) -> bool {
+        if policy == InvalidTradePolicy::Keep {
+            return true;
+        }
+        if qty == 0.0 {
+            return false;
+        }
+        if !qty.is_finite() || qty < 0.0 {
+            data_issues.push(DataIssue::InvalidTradeQty { seq, qty });
+            return false;
+        }
+        true
+    }
+
+    #[cfg(feature = "data")]
     fn load_order_sz(&mut self) {
         let order_no_col = self
             .df_order
@@ -256,6 +568,7 @@ impl DataCollator {
         }
     }
     /// 加载订单数据，并将其存储在 `orders` 和 `index_by_seq` 中。
+    #[cfg(feature = "data")]
     fn load_order_sh(&mut self) {
         // 提取 `df_order` 数据框中的各列
         let order_no_col = self
@@ -314,6 +627,14 @@ impl DataCollator {
             .unwrap()
             .i64()
             .unwrap();
+        // 部分数据没有 `OrderIndex` 列，此时成交只能按 `OrderNO` 关联
+        let order_index_col = self
+            .df_order
+            .as_ref()
+            .unwrap()
+            .column("OrderIndex")
+            .ok()
+            .and_then(|column| column.i64().ok());
 
         for idx in 0..self.df_order.as_ref().unwrap().height() {
             let order_no = order_no_col.get(idx).unwrap();
@@ -330,29 +651,38 @@ impl DataCollator {
             let qty = order_qty_col.get(idx).unwrap();
 
             if !is_cancel {
-                let order_ref = L3Order::new_ref(
-                    OrderSourceType::LocalOrder,
-                    None,
-                    order_no,
-                    Side::from_str(&side).unwrap(),
-                    0,
-                    0,
-                    md_time,
-                    order_type,
-                );
+                if let Some(order_index) = order_index_col.and_then(|column| column.get(idx)) {
+                    self.order_no_by_index.insert(order_index, order_no);
+                }
+                let side = Side::from_str(&side).unwrap();
+                // 先由成交记录创建的订单保留已经计入的成交，委托数量在此基础上累加
+                let order_ref = self
+                    .orders
+                    .as_mut()
+                    .unwrap()
+                    .entry(order_no)
+                    .or_insert_with(|| {
+                        L3Order::new_ref(
+                            OrderSourceType::LocalOrder,
+                            None,
+                            order_no,
+                            side,
+                            0,
+                            0,
+                            md_time,
+                            order_type,
+                        )
+                    })
+                    .clone();
 
                 let mut order = order_ref.borrow_mut();
+                order.side = side;
+                order.timestamp = md_time;
+                order.order_type = order_type;
                 let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
                 auxiliary_info.initial_price = order_price_col.get(idx).unwrap();
-                auxiliary_info.initial_qty = qty;
+                auxiliary_info.initial_qty += qty;
                 auxiliary_info.initial_seq = seq_num;
-
-                self.orders
-                    .as_mut()
-                    .unwrap()
-                    .insert(order_no, order_ref.clone());
-
-                print!("== load order ==  {order:?}\n");
             } else {
                 let order_ref = self.orders.as_mut().unwrap().get(&order_no).unwrap();
                 let mut order = order_ref.borrow_mut();
@@ -362,11 +692,11 @@ impl DataCollator {
                     .as_mut()
                     .unwrap()
                     .push_back((seq_num, order_no));
-                print!("== load cancel ==  {order:?}\n");
             }
         }
     }
     /// 加载深圳交易所的交易数据，并更新订单信息。
+    #[cfg(feature = "data")]
     fn load_trade_sz(&mut self) {
         let bs_flag_col = self
             .df_trade
@@ -455,6 +785,14 @@ impl DataCollator {
             };
 
             if !is_cancel {
+                if !Self::accept_trade_qty(
+                    self.invalid_trade_policy,
+                    &mut self.data_issues,
+                    seq_num,
+                    qty,
+                ) {
+                    continue;
+                }
                 let buy_order_ref = self
                     .orders
                     .as_mut()
@@ -501,6 +839,8 @@ impl DataCollator {
                 let mut order = order_ref.borrow_mut();
                 let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
                 auxiliary_info.cancel_seq = seq_num;
+                // 深圳的撤单消息带有撤销数量，可能只撤销部分剩余数量
+                auxiliary_info.cancel_qty = qty;
 
                 self.index_by_seq
                     .as_mut()
@@ -514,7 +854,8 @@ impl DataCollator {
     ///
     /// 该方法处理上海交易所的交易数据。对于每一笔交易，方法会根据买卖订单编号
     /// 更新对应订单的成交数量。如果订单在 `orders` 中尚未存在，则会创建新的订单
-    /// 并添加到 `orders` 和 `index_by_seq` 中。
+    /// 并添加到 `orders` 中，参见 `link_trade_sh`。
+    #[cfg(feature = "data")]
     fn load_trade_sh(&mut self) {
         let bs_flag_col = self
             .df_trade
@@ -581,9 +922,17 @@ impl DataCollator {
             .i64()
             .unwrap();
 
+        let namespace = self.resolve_trade_id_namespace(
+            buy_no_col
+                .into_iter()
+                .chain(sell_no_col.into_iter())
+                .flatten(),
+        );
+        self.resolved_trade_id_namespace = Some(namespace);
+
         for idx in 0..self.df_trade.as_ref().unwrap().height() {
-            let buy_order_id = buy_no_col.get(idx).unwrap();
-            let sell_order_id = sell_no_col.get(idx).unwrap();
+            let buy_no = buy_no_col.get(idx).unwrap();
+            let sell_no = sell_no_col.get(idx).unwrap();
             let qty = trade_qty_col.get(idx).unwrap();
             let trade_price = trade_price_col.get(idx).unwrap();
             let trade_type = OrderType::from_i32(trade_type_col.get(idx).unwrap()).unwrap();
@@ -591,133 +940,199 @@ impl DataCollator {
             let seq_num = seq_num_col.get(idx).unwrap();
 
             let side = if bs_flag_col.get(idx).unwrap() == 1 {
-                "B"
+                Side::Buy
             } else {
-                "S"
+                Side::Sell
             };
-
-            match self.orders.as_mut().unwrap().get_mut(&buy_order_id) {
-                Some(order_ref) => {
-                    let mut order = order_ref.borrow_mut();
-                    let timestamp = order.timestamp.clone();
-                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
-
-                    if side == "B" {
-                        auxiliary_info.match_price = trade_price;
-                        auxiliary_info.match_qty += qty;
-                        if auxiliary_info.match_seq == i64::MAX {
-                            auxiliary_info.match_seq = seq_num;
-                        }
-                        auxiliary_info.match_count += 1;
-                        if !is_in_call_auction(timestamp, MarketType::SH).unwrap_or(false) {
-                            auxiliary_info.initial_qty += qty;
-                        }
-                    } else {
-                        auxiliary_info.orderbook_price = trade_price;
-                        auxiliary_info.orderbook_qty += qty;
-                        auxiliary_info.orderbook_seq = seq_num;
-                    }
-                    print!("== buy some side = {side} , seq = {seq_num} ,  ==  {order:?}\n");
-                }
-                None => {
-                    let order_ref = L3Order::new_ref(
-                        OrderSourceType::LocalOrder,
-                        None,
-                        buy_order_id,
-                        Side::Buy,
-                        0,
-                        0,
-                        md_time,
-                        OrderType::None,
-                    );
-
-                    let mut order = order_ref.borrow_mut();
-                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
-
-                    auxiliary_info.match_price = trade_price;
-                    auxiliary_info.match_qty += qty;
-                    auxiliary_info.match_seq = seq_num;
-                    auxiliary_info.match_count += 1;
-
-                    auxiliary_info.initial_qty += qty;
-
-                    self.orders
-                        .as_mut()
-                        .unwrap()
-                        .insert(buy_order_id, order_ref.clone());
-                    print!("== buy none side = {side} , seq = {seq_num} ,  == {order:?}\n");
-                }
+            if !Self::accept_trade_qty(
+                self.invalid_trade_policy,
+                &mut self.data_issues,
+                seq_num,
+                qty,
+            ) {
+                continue;
             }
 
-            match self.orders.as_mut().unwrap().get_mut(&sell_order_id) {
-                Some(order_ref) => {
-                    let mut order = order_ref.borrow_mut();
-                    let timestamp = order.timestamp.clone();
-                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
-
-                    if side == "S" {
-                        auxiliary_info.match_price = trade_price;
-                        auxiliary_info.match_qty += qty;
-                        if auxiliary_info.match_seq == i64::MAX {
-                            auxiliary_info.match_seq = seq_num;
-                        }
-                        auxiliary_info.match_count += 1;
-
-                        if !is_in_call_auction(timestamp, MarketType::SZ).unwrap_or(false) {
-                            auxiliary_info.initial_qty += qty;
-                        }
-                    } else {
-                        auxiliary_info.orderbook_price = trade_price;
-                        auxiliary_info.orderbook_qty += qty;
-                        auxiliary_info.orderbook_seq = seq_num;
+            // 按成交本身的时间判断是否为集合竞价成交，由成交创建的订单没有委托时间
+            let in_auction = is_in_call_auction(md_time, MarketType::SH).unwrap_or(false);
+            for (trade_id, order_side) in [(buy_no, Side::Buy), (sell_no, Side::Sell)] {
+                let order_id = match self.trade_order_id(namespace, trade_id) {
+                    Some(order_id) => order_id,
+                    None => {
+                        self.data_issues.push(DataIssue::UnknownTradeOrderIndex {
+                            seq: seq_num,
+                            order_index: trade_id,
+                        });
+                        continue;
                     }
-                    print!("== sell some == side = {side} , seq = {seq_num} , {order:?}\n");
-                }
-                None => {
-                    let order_ref = L3Order::new_ref(
-                        OrderSourceType::LocalOrder,
-                        None,
-                        sell_order_id,
-                        Side::Sell,
-                        0,
-                        0,
-                        md_time,
-                        OrderType::None,
-                    );
+                };
+                Self::link_trade_sh(
+                    self.orders.as_mut().unwrap(),
+                    order_id,
+                    order_side,
+                    side == order_side,
+                    in_auction,
+                    trade_price,
+                    qty,
+                    md_time,
+                    seq_num,
+                );
+            }
+        }
+    }
 
-                    let mut order = order_ref.borrow_mut();
-                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+    /// 判断上海市场成交数据引用的订单编号，`trade_id_namespace` 不是 `Auto` 时直接使用设置的值。
+    ///
+    /// 需要在加载委托之后、关联成交之前调用，此时 `orders` 中只有委托记录创建的订单。
+    ///
+    /// # 参数
+    /// - `trade_ids`: 成交数据中的买方和卖方订单编号。
+    #[cfg(feature = "data")]
+    fn resolve_trade_id_namespace(&self, trade_ids: impl Iterator<Item = i64>) -> TradeIdNamespace {
+        if self.trade_id_namespace != TradeIdNamespace::Auto {
+            return self.trade_id_namespace;
+        }
+        if self.order_no_by_index.is_empty() {
+            return TradeIdNamespace::OrderNo;
+        }
+        let orders = self.orders.as_ref().unwrap();
+        let (mut order_no_hits, mut order_index_hits) = (0usize, 0usize);
+        for trade_id in trade_ids {
+            order_no_hits += usize::from(orders.contains_key(&trade_id));
+            order_index_hits += usize::from(self.order_no_by_index.contains_key(&trade_id));
+        }
+        if order_index_hits > order_no_hits {
+            TradeIdNamespace::OrderIndex
+        } else {
+            TradeIdNamespace::OrderNo
+        }
+    }
 
-                    auxiliary_info.match_price = trade_price;
-                    auxiliary_info.match_qty += qty;
-                    auxiliary_info.match_seq = seq_num;
-                    auxiliary_info.match_count += 1;
+    /// 将成交引用的编号转换为订单编号。
+    ///
+    /// # 返回值
+    /// `OrderIndex` 中没有的编号返回 `None`，原值属于另一套编号，不能当作订单编号使用。
+    #[cfg(feature = "data")]
+    fn trade_order_id(&self, namespace: TradeIdNamespace, trade_id: i64) -> Option<OrderId> {
+        match namespace {
+            TradeIdNamespace::OrderIndex => self.order_no_by_index.get(&trade_id).copied(),
+            _ => Some(trade_id),
+        }
+    }
 
-                    auxiliary_info.initial_qty += qty;
+    /// 将一笔上海市场的成交计入买方或卖方订单，订单不存在时由成交创建。
+    ///
+    /// 上海市场的委托记录给出的是主动成交之后剩余的数量，因此连续竞价中的主动成交要加回
+    /// `initial_qty`；集合竞价的委托记录给出完整的委托数量，集合竞价成交不再累加。
+    /// 已有委托记录的订单和由成交创建的订单使用相同的规则，结果与委托和成交的加载顺序无关。
+    ///
+    /// # 参数
+    /// - `orders`: 已加载的订单。
+    /// - `order_id`: 订单编号。
+    /// - `side`: 订单的买卖方向。
+    /// - `is_aggressor`: 订单是否为这笔成交的主动方。
+    /// - `in_auction`: 成交是否发生在集合竞价阶段。
+    /// - `trade_price`、`qty`、`md_time`、`seq_num`: 成交的价格、数量、时间和序号。
+    #[cfg(feature = "data")]
+    fn link_trade_sh(
+        orders: &mut HashMap<OrderId, L3OrderRef>,
+        order_id: OrderId,
+        side: Side,
+        is_aggressor: bool,
+        in_auction: bool,
+        trade_price: f64,
+        qty: f64,
+        md_time: i64,
+        seq_num: i64,
+    ) {
+        let order_ref = orders.entry(order_id).or_insert_with(|| {
+            L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                0,
+                0,
+                md_time,
+                OrderType::None,
+            )
+        });
+        let mut order = order_ref.borrow_mut();
+        let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
 
-                    self.orders
-                        .as_mut()
-                        .unwrap()
-                        .insert(sell_order_id, order_ref.clone());
-                    print!("== sell none side = {side} , seq = {seq_num} , == {order:?}\n");
-                }
+        if is_aggressor {
+            auxiliary_info.match_price = trade_price;
+            auxiliary_info.match_qty += qty;
+            if auxiliary_info.match_seq == i64::MAX {
+                auxiliary_info.match_seq = seq_num;
             }
+            auxiliary_info.match_count += 1;
+            if !in_auction {
+                auxiliary_info.initial_qty += qty;
+            }
+        } else {
+            auxiliary_info.orderbook_price = trade_price;
+            auxiliary_info.orderbook_qty += qty;
+            auxiliary_info.orderbook_seq = seq_num;
         }
     }
 
+    /// 检查每个订单的主动成交数量不超过委托数量，违反的订单记录到 `data_issues`。
+    #[cfg(feature = "data")]
+    fn check_match_qty(&mut self) {
+        let mut issues: Vec<DataIssue> = self
+            .orders
+            .as_ref()
+            .unwrap()
+            .iter()
+            .filter_map(|(order_id, order_ref)| {
+                let order = order_ref.borrow();
+                let auxiliary_info = order.auxiliary_info.as_ref()?;
+                (auxiliary_info.match_qty > auxiliary_info.initial_qty + 1e-6).then_some(
+                    DataIssue::MatchQtyExceedsInitial {
+                        order_id: *order_id,
+                        match_qty: auxiliary_info.match_qty,
+                        initial_qty: auxiliary_info.initial_qty,
+                    },
+                )
+            })
+            .collect();
+        issues.sort_by_key(|issue| match issue {
+            DataIssue::MatchQtyExceedsInitial { order_id, .. } => *order_id,
+            _ => 0,
+        });
+        self.data_issues.extend(issues);
+    }
+
+    #[cfg(feature = "data")]
     fn init_sz(&mut self) {
         self.load_order_sz();
         self.load_trade_sz();
         self.post_init();
     }
 
+    #[cfg(feature = "data")]
     fn init_sh(&mut self) {
         self.load_order_sh();
         self.load_trade_sh();
         self.post_init();
     }
 
+    #[cfg(feature = "data")]
     fn post_init(&mut self) {
+        self.check_match_qty();
+        self.phantom_orders = self
+            .orders
+            .as_ref()
+            .unwrap()
+            .values()
+            .filter(|order_ref| {
+                order_ref
+                    .borrow()
+                    .auxiliary_info
+                    .is_some_and(|info| info.initial_seq == i64::MAX)
+            })
+            .count();
         for (order_id, order_ref) in self.orders.as_ref().unwrap().iter() {
             let seq = order_ref
                 .borrow()
@@ -731,7 +1146,23 @@ impl DataCollator {
                 .unwrap()
                 .push_back((seq, order_id.clone()));
         }
-        self.index_by_seq.as_mut().unwrap().make_contiguous().sort();
+        // 回放序号取多个序号中的最小值，不同订单可能相同，按 `seq_tiebreak` 确定先后
+        let orders = self.orders.as_ref().unwrap();
+        let tiebreak = self.seq_tiebreak;
+        self.index_by_seq
+            .as_mut()
+            .unwrap()
+            .make_contiguous()
+            .sort_by_key(|&(seq, order_id)| {
+                let secondary = match tiebreak {
+                    SeqTiebreak::InitialSeq => orders
+                        .get(&order_id)
+                        .and_then(|order_ref| order_ref.borrow().auxiliary_info)
+                        .map_or(i64::MAX, |info| info.initial_seq),
+                    SeqTiebreak::OrderId => order_id,
+                };
+                (seq, secondary, order_id)
+            });
         self.len = self.index_by_seq.as_ref().unwrap().len();
     }
 }
@@ -779,6 +1210,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "data")]
     fn test_new2() {
         let exchange_mode = "backtest".to_string();
         let stock_code = "688007.SH".to_string();
@@ -788,7 +1220,7 @@ mod tests {
         let mode = "L2P";
 
         let mut data = DataCollator::new(stock_code, file_type, data_path, date, mode);
-        data.init();
+        data.init().unwrap();
         print!("data len = {}\n", data.len);
         for i in 1..=data.len {
             print!("{:?}\n", data.next());
@@ -796,6 +1228,459 @@ mod tests {
         print!("data current_idx = {}\n", data.current_idx)
     }
 
+    #[test]
+    fn test_peek() {
+        let mut collator = create_test_collator();
+        let mut orders = HashMap::new();
+        let mut index_by_seq = VecDeque::new();
+        for (seq, order_id) in [(1, 11), (2, 12)] {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                Side::Buy,
+                1000,
+                1,
+                20240830093000000 + seq,
+                OrderType::L,
+            );
+            orders.insert(order_id, order_ref);
+            index_by_seq.push_back((seq, order_id));
+        }
+        collator.len = index_by_seq.len();
+        collator.orders = Some(orders);
+        collator.index_by_seq = Some(index_by_seq);
+
+        for _ in 0..collator.len {
+            let (peek_seq, peek_order) = collator.peek().map(|(seq, o)| (seq, o.clone())).unwrap();
+            // 多次 peek 不会移动游标
+            assert_eq!(collator.peek().unwrap().0, peek_seq);
+            let current_idx = collator.current_idx;
+            let (next_seq, next_order) = collator.next().unwrap();
+            assert_eq!(next_seq, peek_seq);
+            assert!(Rc::ptr_eq(next_order, &peek_order));
+            assert_eq!(collator.current_idx, current_idx + 1);
+        }
+        assert!(collator.peek().is_none());
+        assert!(collator.next().is_none());
+    }
+
+    #[test]
+    fn test_from_orders() {
+        let orders: Vec<L3OrderRef> = [(3, 13), (1, 11), (2, 12)]
+            .into_iter()
+            .map(|(seq, order_id)| {
+                let order_ref = L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    order_id,
+                    Side::Buy,
+                    1000,
+                    1,
+                    20240830093000000 + seq,
+                    OrderType::L,
+                );
+                order_ref.borrow_mut().seq = seq;
+                order_ref
+            })
+            .collect();
+        let mut collator =
+            DataCollator::from_orders("600519.SH".to_string(), "20240830".to_string(), orders);
+        // 外部数据源构造的实例已经初始化，不会访问数据文件
+        collator.init().unwrap();
+        assert_eq!(collator.len, 3);
+        assert_eq!(collator.get_next_timestamp(), Some(20240830093000001));
+
+        let mut replayed = Vec::new();
+        while let Some((seq, order_ref)) = collator.next() {
+            replayed.push((seq, order_ref.borrow().order_id));
+        }
+        assert_eq!(replayed, vec![(1, 11), (2, 12), (3, 13)]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "data"))]
+    fn test_init_without_data_feature() {
+        let mut collator = create_test_collator();
+        assert!(matches!(
+            collator.init(),
+            Err(MarketError::DataLoadError(_))
+        ));
+        assert!(!collator.initialized);
+    }
+
+    #[test]
+    fn test_init_is_idempotent() {
+        let mut collator = create_test_collator();
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            11,
+            Side::Buy,
+            1000,
+            1,
+            20240830093000001,
+            OrderType::L,
+        );
+        collator.orders = Some(HashMap::from([(11, order_ref)]));
+        collator.index_by_seq = Some(VecDeque::from([(1, 11)]));
+        collator.len = 1;
+        // 模拟已经加载过数据的状态，再次初始化不会访问数据文件，也不会清空已加载的订单
+        collator.initialized = true;
+        collator.next();
+
+        collator.init().unwrap();
+        collator.init().unwrap();
+        assert_eq!(collator.len, 1);
+        assert_eq!(collator.orders.as_ref().unwrap().len(), 1);
+        assert_eq!(collator.index_by_seq.as_ref().unwrap().len(), 1);
+        assert_eq!(collator.current_idx, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    fn test_invalid_trade_qty_is_skipped() {
+        let load = |policy: InvalidTradePolicy| {
+            let mut collator = create_test_collator();
+            collator.invalid_trade_policy = policy;
+            collator.df_order = Some(
+                df!(
+                    "OrderNO" => [1i64, 2],
+                    "OrderBSFlag" => [1i32, 2],
+                    "OrderType" => [2i32, 2],
+                    "OrderPrice" => [10.0f64, 10.0],
+                    "OrderQty" => [300.0f64, 100.0],
+                    "MDTime" => [20240830093000000i64, 20240830093001000],
+                    "ApplSeqNum" => [1i64, 2],
+                )
+                .unwrap(),
+            );
+            collator.df_trade = Some(
+                df!(
+                    "TradeBSFlag" => [2i32, 2, 2],
+                    "TradeBuyNo" => [1i64, 1, 1],
+                    "TradeSellNo" => [2i64, 2, 2],
+                    "TradeType" => [2i32, 2, 2],
+                    "TradePrice" => [10.0f64, 10.0, 10.0],
+                    "TradeQty" => [100.0f64, 0.0, -50.0],
+                    "MDTime" => [20240830093001000i64, 20240830093001000, 20240830093001000],
+                    "ApplSeqNum" => [3i64, 4, 5],
+                )
+                .unwrap(),
+            );
+            collator.orders = Some(HashMap::new());
+            collator.index_by_seq = Some(VecDeque::new());
+            collator.init_sh();
+            collator
+        };
+
+        let collator = load(InvalidTradePolicy::Skip);
+        let orders = collator.orders.as_ref().unwrap();
+        let buy_info = orders[&1].borrow().auxiliary_info.unwrap();
+        let sell_info = orders[&2].borrow().auxiliary_info.unwrap();
+        assert_eq!(buy_info.orderbook_qty, 100.0);
+        assert_eq!(sell_info.match_qty, 100.0);
+        assert_eq!(sell_info.match_count, 1);
+        assert_eq!(
+            collator.data_issues,
+            vec![DataIssue::InvalidTradeQty { seq: 5, qty: -50.0 }]
+        );
+
+        // 保留原始数据时所有成交都被计入
+        let collator = load(InvalidTradePolicy::Keep);
+        let sell_info = collator.orders.as_ref().unwrap()[&2]
+            .borrow()
+            .auxiliary_info
+            .unwrap();
+        assert_eq!(sell_info.match_qty, 50.0);
+        assert_eq!(sell_info.match_count, 3);
+        assert!(collator.data_issues.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    fn test_session_window_filter() {
+        let load = |keep_out_of_session: bool| {
+            let mut collator = create_test_collator();
+            collator.keep_out_of_session = keep_out_of_session;
+            // 09:10 的交易所测试消息和 15:00:01 的收盘集合竞价委托
+            collator.df_order = Some(
+                df!(
+                    "OrderNO" => [1i64, 2, 3],
+                    "OrderBSFlag" => [1i32, 1, 2],
+                    "OrderType" => [2i32, 2, 2],
+                    "OrderPrice" => [10.0f64, 10.0, 10.0],
+                    "OrderQty" => [100.0f64, 300.0, 100.0],
+                    "MDTime" => [20240830091000000i64, 20240830093000000, 20240830150001000],
+                    "ApplSeqNum" => [1i64, 2, 3],
+                )
+                .unwrap(),
+            );
+            collator.df_trade = Some(
+                df!(
+                    "TradeBSFlag" => [2i32],
+                    "TradeBuyNo" => [2i64],
+                    "TradeSellNo" => [3i64],
+                    "TradeType" => [2i32],
+                    "TradePrice" => [10.0f64],
+                    "TradeQty" => [100.0f64],
+                    "MDTime" => [20240830150001000i64],
+                    "ApplSeqNum" => [4i64],
+                )
+                .unwrap(),
+            );
+            collator.apply_session_window().unwrap();
+            collator.orders = Some(HashMap::new());
+            collator.index_by_seq = Some(VecDeque::new());
+            collator.init_sh();
+            collator
+        };
+
+        let collator = load(false);
+        assert_eq!(collator.filtered_rows_pre_session, 1);
+        assert_eq!(collator.filtered_rows_post_session, 0);
+        let orders = collator.orders.as_ref().unwrap();
+        assert!(!orders.contains_key(&1));
+        // 收盘集合竞价的委托和成交被保留
+        assert!(orders.contains_key(&3));
+        assert_eq!(orders[&3].borrow().auxiliary_info.unwrap().match_qty, 100.0);
+        assert_eq!(collator.df_trade.as_ref().unwrap().height(), 1);
+
+        // 保留交易时段以外的记录时仍然统计数量
+        let collator = load(true);
+        assert_eq!(collator.filtered_rows_pre_session, 1);
+        assert!(collator.orders.as_ref().unwrap().contains_key(&1));
+
+        let window = SessionWindow::for_market(MarketType::SH);
+        assert!(!window.is_post_session(20240830150030000));
+        assert!(window.is_post_session(20240830150031000));
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    /// 测试上海市场委托和成交的关联与加载顺序无关。
+    /// 验证连续竞价的主动成交加回委托数量，集合竞价成交不累加，缺少委托记录的集合竞价成交被记录为数据异常。
+    fn test_sh_trade_linkage_order_independent() {
+        let load = |trade_first: bool| {
+            let mut collator = create_test_collator();
+            // 1 号买单连续竞价中主动成交 100 后剩余 200；3、4 号为集合竞价委托，7 号缺少委托记录
+            collator.df_order = Some(
+                df!(
+                    "OrderNO" => [1i64, 2, 3, 4],
+                    "OrderBSFlag" => [1i32, 2, 1, 2],
+                    "OrderType" => [2i32, 2, 2, 2],
+                    "OrderPrice" => [10.0f64, 10.0, 10.0, 10.0],
+                    "OrderQty" => [200.0f64, 200.0, 600.0, 500.0],
+                    "MDTime" => [20240830093001000i64, 20240830093000000, 20240830092000000, 20240830092001000],
+                    "ApplSeqNum" => [3i64, 1, 4, 5],
+                )
+                .unwrap(),
+            );
+            // 5 号买单没有委托记录，连续竞价中全部主动成交
+            collator.df_trade = Some(
+                df!(
+                    "TradeBSFlag" => [0i32, 0, 1, 1],
+                    "TradeBuyNo" => [3i64, 3, 1, 5],
+                    "TradeSellNo" => [4i64, 7, 2, 2],
+                    "TradeType" => [2i32, 2, 2, 2],
+                    "TradePrice" => [10.0f64, 10.0, 10.0, 10.0],
+                    "TradeQty" => [500.0f64, 100.0, 100.0, 100.0],
+                    "MDTime" => [20240830092500000i64, 20240830092500000, 20240830093001000, 20240830093002000],
+                    "ApplSeqNum" => [6i64, 7, 8, 9],
+                )
+                .unwrap(),
+            );
+            collator.orders = Some(HashMap::new());
+            collator.index_by_seq = Some(VecDeque::new());
+            if trade_first {
+                collator.load_trade_sh();
+                collator.load_order_sh();
+            } else {
+                collator.load_order_sh();
+                collator.load_trade_sh();
+            }
+            collator.post_init();
+            collator
+        };
+
+        let summary = |collator: &DataCollator| {
+            let mut orders: Vec<(OrderId, f64, f64, f64, i64)> = collator
+                .orders
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|(order_id, order_ref)| {
+                    let info = order_ref.borrow().auxiliary_info.unwrap();
+                    (
+                        *order_id,
+                        info.initial_qty,
+                        info.match_qty,
+                        info.orderbook_qty,
+                        info.orderbook_seq(),
+                    )
+                })
+                .collect();
+            orders.sort_by_key(|order| order.0);
+            orders
+        };
+
+        let order_first = load(false);
+        assert_eq!(
+            summary(&order_first),
+            vec![
+                (1, 300.0, 100.0, 0.0, 3),
+                (2, 200.0, 0.0, 200.0, 1),
+                (3, 600.0, 0.0, 600.0, 4),
+                (4, 500.0, 500.0, 0.0, 5),
+                (5, 100.0, 100.0, 0.0, 9),
+                (7, 0.0, 100.0, 0.0, 7),
+            ]
+        );
+        assert_eq!(
+            order_first.data_issues,
+            vec![DataIssue::MatchQtyExceedsInitial {
+                order_id: 7,
+                match_qty: 100.0,
+                initial_qty: 0.0,
+            }]
+        );
+
+        let trade_first = load(true);
+        assert_eq!(summary(&trade_first), summary(&order_first));
+        assert_eq!(trade_first.data_issues, order_first.data_issues);
+        assert_eq!(trade_first.len, order_first.len);
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    /// 测试回放序号相同的订单的排序。
+    /// 验证默认按委托序号排序，先到达的订单先回放；设置为按订单 ID 时按 ID 排序。
+    fn test_post_init_seq_tiebreak() {
+        let replay = |tiebreak: SeqTiebreak| {
+            let mut collator = create_test_collator();
+            collator.seq_tiebreak = tiebreak;
+            let mut orders = HashMap::new();
+            // 两个订单的回放序号都是 3，20 号订单的委托序号更小
+            for (order_id, initial_seq, orderbook_seq) in [(10, 5, 3), (20, 4, 3)] {
+                let order_ref = L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    order_id,
+                    Side::Buy,
+                    0,
+                    0,
+                    20240830093000000,
+                    OrderType::L,
+                );
+                {
+                    let mut order = order_ref.borrow_mut();
+                    let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                    auxiliary_info.initial_seq = initial_seq;
+                    auxiliary_info.orderbook_seq = orderbook_seq;
+                }
+                orders.insert(order_id, order_ref);
+            }
+            collator.orders = Some(orders);
+            collator.index_by_seq = Some(VecDeque::new());
+            collator.post_init();
+            let mut replayed = Vec::new();
+            while let Some((seq, order_ref)) = collator.next() {
+                replayed.push((seq, order_ref.borrow().order_id));
+            }
+            replayed
+        };
+
+        assert_eq!(replay(SeqTiebreak::InitialSeq), vec![(3, 20), (3, 10)]);
+        assert_eq!(replay(SeqTiebreak::OrderId), vec![(3, 10), (3, 20)]);
+    }
+
+    #[test]
+    #[cfg(feature = "data")]
+    /// 测试上海市场成交按 `OrderNO` 或 `OrderIndex` 关联委托。
+    /// 验证自动判断成交使用的编号，关联后没有多余的订单；指定错误的编号时缺失的订单被计数。
+    fn test_sh_trade_id_namespace() {
+        let load = |buy_ids: [i64; 2], sell_ids: [i64; 2], namespace: TradeIdNamespace| {
+            let mut collator = create_test_collator();
+            collator.trade_id_namespace = namespace;
+            collator.df_order = Some(
+                df!(
+                    "OrderNO" => [101i64, 102, 103],
+                    "OrderIndex" => [1i64, 2, 3],
+                    "OrderBSFlag" => [2i32, 2, 1],
+                    "OrderType" => [2i32, 2, 2],
+                    "OrderPrice" => [10.0f64, 10.0, 10.0],
+                    "OrderQty" => [100.0f64, 100.0, 100.0],
+                    "MDTime" => [20240830093000000i64, 20240830093000000, 20240830093001000],
+                    "ApplSeqNum" => [1i64, 2, 3],
+                )
+                .unwrap(),
+            );
+            collator.df_trade = Some(
+                df!(
+                    "TradeBSFlag" => [1i32, 1],
+                    "TradeBuyNo" => buy_ids,
+                    "TradeSellNo" => sell_ids,
+                    "TradeType" => [2i32, 2],
+                    "TradePrice" => [10.0f64, 10.0],
+                    "TradeQty" => [100.0f64, 100.0],
+                    "MDTime" => [20240830093001000i64, 20240830093002000],
+                    "ApplSeqNum" => [4i64, 5],
+                )
+                .unwrap(),
+            );
+            collator.orders = Some(HashMap::new());
+            collator.index_by_seq = Some(VecDeque::new());
+            collator.init_sh();
+            collator
+        };
+
+        // 103 号买单与 101 号卖单成交，无委托记录的 104 号买单与 102 号卖单成交
+        let by_order_no = load([103, 104], [101, 102], TradeIdNamespace::Auto);
+        let by_order_index = load([3, 104], [1, 2], TradeIdNamespace::Auto);
+        for (collator, namespace, expected_ids) in [
+            (
+                &by_order_no,
+                TradeIdNamespace::OrderNo,
+                vec![101, 102, 103, 104],
+            ),
+            (
+                &by_order_index,
+                TradeIdNamespace::OrderIndex,
+                vec![101, 102, 103],
+            ),
+        ] {
+            assert_eq!(collator.resolved_trade_id_namespace, Some(namespace));
+            let orders = collator.orders.as_ref().unwrap();
+            let mut order_ids: Vec<OrderId> = orders.keys().copied().collect();
+            order_ids.sort();
+            assert_eq!(order_ids, expected_ids);
+            let info = |order_id: OrderId| orders[&order_id].borrow().auxiliary_info.unwrap();
+            assert_eq!(info(103).match_qty, 100.0);
+            assert_eq!(info(101).orderbook_qty, 100.0);
+            assert_eq!(info(102).orderbook_qty, 100.0);
+        }
+        assert_eq!(by_order_no.phantom_orders, 1);
+        assert!(by_order_no.data_issues.is_empty());
+        // 不存在的 `OrderIndex` 不能当作订单编号，跳过该方的成交并记录
+        assert_eq!(by_order_index.phantom_orders, 0);
+        assert_eq!(
+            by_order_index.data_issues,
+            vec![DataIssue::UnknownTradeOrderIndex {
+                seq: 5,
+                order_index: 104
+            }]
+        );
+
+        // 全部成交都能找到委托时没有由成交创建的订单
+        let matched = load([3, 3], [1, 2], TradeIdNamespace::Auto);
+        assert_eq!(matched.phantom_orders, 0);
+        assert_eq!(matched.orders.as_ref().unwrap().len(), 3);
+
+        // 指定的编号与数据不符时，成交找不到委托
+        let mismatched = load([3, 3], [1, 2], TradeIdNamespace::OrderNo);
+        assert_eq!(mismatched.phantom_orders, 3);
+    }
+
     // // 测试初始化
     // #[test]
     // fn test_init() {
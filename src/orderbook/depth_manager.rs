@@ -1,5 +1,9 @@
 use super::*;
 
+use super::broker::Broker;
+use super::exchange::Exchange;
+use super::observer::DepthObserver;
+use super::skiplist_orderbook::SkipListMarketDepth;
 use skiplist::SkipMap;
 use std::{cell::RefCell, rc::Rc};
 
@@ -114,6 +118,149 @@ where
     }
 }
 
+/// 合并行情中单只股票的最优买卖价，没有买单或卖单时价格为 `NaN`，tick 为 `INVALID_MIN` 或 `INVALID_MAX`。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SymbolBbo {
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub best_bid_tick: i64,
+    pub best_ask_tick: i64,
+}
+
+impl SymbolBbo {
+    fn new(best_bid_tick: i64, best_ask_tick: i64, tick_size: f64) -> Self {
+        let mut bbo = Self {
+            best_bid: f64::NAN,
+            best_ask: f64::NAN,
+            best_bid_tick: INVALID_MIN,
+            best_ask_tick: INVALID_MAX,
+        };
+        bbo.set_tick(Side::Buy, best_bid_tick, tick_size);
+        bbo.set_tick(Side::Sell, best_ask_tick, tick_size);
+        bbo
+    }
+
+    fn set_tick(&mut self, side: Side, best_tick: i64, tick_size: f64) {
+        let price = match best_tick {
+            INVALID_MIN | INVALID_MAX => f64::NAN,
+            best_tick => best_tick as f64 * tick_size,
+        };
+        match side {
+            Side::Buy => {
+                self.best_bid_tick = best_tick;
+                self.best_bid = price;
+            }
+            _ => {
+                self.best_ask_tick = best_tick;
+                self.best_ask = price;
+            }
+        }
+    }
+}
+
+/// 合并行情的回调，参数为最优价发生变化的股票代码及其最新的最优价。
+pub type BboCallback = Box<dyn FnMut(&str, &SymbolBbo)>;
+
+/// `ConsolidatedFeed` 汇总多只股票的最优价，任意一只股票的最优价变化时调用回调。
+///
+/// 合并行情通过在每个经纪商的市场深度上注册 `DepthObserver` 订阅最优价变化，
+/// 最优价是真实订单簿的价格，与 `BestMode::RealOnly` 一致。
+/// 克隆得到的合并行情与原对象共享同一份最优价表和回调。
+#[derive(Clone)]
+pub struct ConsolidatedFeed {
+    bbo: Rc<RefCell<HashMap<String, SymbolBbo>>>,
+    callback: Rc<RefCell<BboCallback>>,
+}
+
+impl ConsolidatedFeed {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: FnMut(&str, &SymbolBbo) + 'static,
+    {
+        Self {
+            bbo: Rc::new(RefCell::new(HashMap::new())),
+            callback: Rc::new(RefCell::new(Box::new(callback))),
+        }
+    }
+
+    /// 订阅一个经纪商的最优价变化，订阅时以经纪商当前的最优价初始化，不调用回调。
+    ///
+    /// # 返回值
+    /// 订阅成功返回 `true`，市场深度的观察者数量已达到上限时返回 `false`。
+    pub fn subscribe(&self, broker: &mut Broker<SkipListMarketDepth>) -> bool {
+        let tick_size = broker.tick_size;
+        let observer = SymbolObserver {
+            stock_code: broker.stock_code.clone(),
+            tick_size: tick_size,
+            feed: self.clone(),
+        };
+        if !broker.market_depth.register_observer(Box::new(observer)) {
+            return false;
+        }
+        let bbo = SymbolBbo::new(
+            broker
+                .market_depth
+                .best_bid_tick_with_mode(BestMode::RealOnly),
+            broker
+                .market_depth
+                .best_ask_tick_with_mode(BestMode::RealOnly),
+            tick_size,
+        );
+        self.bbo.borrow_mut().insert(broker.stock_code.clone(), bbo);
+        true
+    }
+
+    /// 订阅交易所中所有经纪商的最优价变化。
+    ///
+    /// # 返回值
+    /// 订阅成功的经纪商数量。
+    pub fn subscribe_all(&self, exchange: &mut Exchange<SkipListMarketDepth>) -> usize {
+        exchange
+            .broker_map
+            .values_mut()
+            .map(|broker| self.subscribe(broker))
+            .filter(|subscribed| *subscribed)
+            .count()
+    }
+
+    /// 返回指定股票的最新最优价，股票没有订阅时返回 `None`。
+    pub fn bbo(&self, stock_code: &str) -> Option<SymbolBbo> {
+        self.bbo.borrow().get(stock_code).copied()
+    }
+
+    /// 返回所有已订阅股票的最新最优价。
+    pub fn snapshot(&self) -> HashMap<String, SymbolBbo> {
+        self.bbo.borrow().clone()
+    }
+
+    fn on_best_change(&self, stock_code: &str, side: Side, best_tick: i64, tick_size: f64) {
+        let bbo = {
+            let mut bbo_map = self.bbo.borrow_mut();
+            let bbo = bbo_map
+                .entry(stock_code.to_string())
+                .or_insert_with(|| SymbolBbo::new(INVALID_MIN, INVALID_MAX, tick_size));
+            bbo.set_tick(side, best_tick, tick_size);
+            *bbo
+        };
+        // 先释放最优价表，回调中可以查询合并行情
+        (self.callback.borrow_mut())(stock_code, &bbo);
+    }
+}
+
+/// 把单个市场深度的最优价变化转发给合并行情，并标记股票代码。
+struct SymbolObserver {
+    stock_code: String,
+    tick_size: f64,
+    feed: ConsolidatedFeed,
+}
+
+impl DepthObserver for SymbolObserver {
+    fn on_best_change(&mut self, side: Side, _prev_tick: i64, best_tick: i64) {
+        self.feed
+            .on_best_change(&self.stock_code, side, best_tick, self.tick_size);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,6 +331,70 @@ mod tests {
         // print!("{output:?}")
     }
 
+    #[test]
+    /// 测试两个经纪商接入同一个合并行情。
+    /// 验证每只股票最优价变化时回调收到标记了对应股票代码的最优价。
+    fn test_consolidated_feed() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for stock_code in ["AAPL", "MSFT"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(timestamp);
+        }
+        let updates: Rc<RefCell<Vec<(String, SymbolBbo)>>> = Rc::new(RefCell::new(Vec::new()));
+        let received = updates.clone();
+        let feed = ConsolidatedFeed::new(move |stock_code, bbo| {
+            received.borrow_mut().push((stock_code.to_string(), *bbo));
+        });
+        assert_eq!(feed.subscribe_all(&mut exchange), 2);
+        assert!(feed.bbo("AAPL").unwrap().best_bid.is_nan());
+
+        exchange
+            .send_order("a", "AAPL", timestamp, 10.0, 100, "buy", None)
+            .unwrap();
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+        let aapl_updates = updates.borrow().len();
+        {
+            let updates = updates.borrow();
+            assert!(aapl_updates > 0);
+            assert!(updates.iter().all(|(stock_code, _)| stock_code == "AAPL"));
+            let (_, bbo) = updates.last().unwrap();
+            assert_eq!(bbo.best_bid_tick, 1000);
+            assert!((bbo.best_bid - 10.0).abs() < 1e-9);
+            assert!(bbo.best_ask.is_nan());
+        }
+
+        exchange
+            .send_order("a", "MSFT", timestamp, 20.0, 100, "sell", None)
+            .unwrap();
+        exchange.elapse(1000, Some("MSFT")).unwrap();
+        {
+            let updates = updates.borrow();
+            assert!(updates.len() > aapl_updates);
+            assert!(updates[aapl_updates..]
+                .iter()
+                .all(|(stock_code, _)| stock_code == "MSFT"));
+            let (_, bbo) = updates.last().unwrap();
+            assert_eq!(bbo.best_ask_tick, 2000);
+            assert!(bbo.best_bid.is_nan());
+        }
+        let snapshot = feed.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["AAPL"].best_bid_tick, 1000);
+        assert_eq!(snapshot["MSFT"].best_ask_tick, 2000);
+    }
+
     #[test]
     fn test_get_orderbook_level() {
         let mut output: Vec<(f64, f64, i64)> = Vec::with_capacity(100);
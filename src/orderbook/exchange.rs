@@ -1,14 +1,36 @@
 use dataloader::DataCollator;
-use hook::{Hook, HookType};
+use hook::{Hook, HookInfo, HookStats, HookType, HookWatchdog};
 
 use super::broker::Broker;
-use super::order::{Order, OrderRef};
-use super::utils::adjust_timestamp_milliseconds_i64;
+use super::calendar::{BREAK_END_TIME, BREAK_START_TIME};
+use super::idempotency::{IdempotencyCache, IdempotentRequest};
+use super::linked::{LinkCancel, LinkCancelReason, LinkGroup, LinkMode, NewOrderRequest};
+#[cfg(feature = "data")]
+use super::market_view::levels_to_dataframe;
+use super::market_view::{BboRow, LevelView};
+use super::order::{
+    CancelFilter, DispositionReport, Order, OrderRef, OrderUserData, ParentSummary,
+    StrategySummary, UserFill,
+};
+use super::publish::StateReceiver;
+use super::risk::{
+    AccountEvent, BreakerState, BreakerStatus, CircuitBreaker, CircuitBreakerConfig, HaltReason,
+};
+use super::statistics::ReplaySummary;
+use super::utils::{adjust_timestamp_milliseconds_i64, time_difference_ms_i64};
+use super::worker::ReplayWorker;
 use super::*;
+#[cfg(feature = "data")]
+use polars::prelude::DataFrame;
+use std::collections::BTreeMap;
 use std::marker;
-use std::ops::Neg;
+use std::ops::{BitOr, Neg};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::sleep;
+use std::time::Duration;
 
 /// `Exchange` 结构体表示一个交易所，用于管理多个经纪商和订单相关的操作。
 ///
@@ -28,15 +50,97 @@ pub struct Exchange<MD> {
     pub latest_order_id: i64,
     /// 全局时间
     pub timestamp: i64,
+    /// 按账户设置的风控熔断器
+    #[serde(skip)]
+    pub circuit_breakers: HashMap<String, CircuitBreaker>,
+    /// 账户熔断和恢复交易的事件
+    #[serde(skip)]
+    pub account_events: Vec<AccountEvent>,
+    /// 带幂等键的下单和撤单请求
+    #[serde(default)]
+    pub idempotency: IdempotencyCache,
+    /// `LinkMode::OcoCancel` 登记的关联订单组，随检查点保存，恢复后继续联动撤单
+    #[serde(default)]
+    pub link_groups: Vec<LinkGroup>,
+    /// 因关联订单联动而发出的撤单
+    #[serde(skip)]
+    pub link_cancels: Vec<LinkCancel>,
+    /// `run_paced` 是否按墙上时间等待午间休市，默认为 `false`，即休市期间立即跳过
+    #[serde(skip)]
+    pub pace_lunch_break: bool,
+    /// `spawn_workers` 创建的回放线程，键为股票代码
+    #[serde(skip, default = "HashMap::new")]
+    workers: HashMap<String, ReplayWorker<MD>>,
 }
 
 unsafe impl<MD> Send for Exchange<MD> {}
 
 unsafe impl<MD> Sync for Exchange<MD> {}
 
+/// `Exchange::elapse_to_grid` 的结果。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridReport {
+    /// 每只股票触发采样的网格时间点数量
+    pub grid_points: BTreeMap<String, usize>,
+    /// 推进期间用户订单的成交量之和
+    pub total_filled: i64,
+}
+
+/// 按墙上时间回放的进度，由 `Exchange::start_paced` 创建，传给 `Exchange::step_paced` 逐步推进。
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacedReplay {
+    /// 回放倍速
+    pub speed: f64,
+    /// 参与回放的股票
+    pub stock_codes: Vec<String>,
+    /// 是否回放所有股票，此时同时推进交易所的时间
+    pub all_stocks: bool,
+    /// 回放时钟，即已经推进到的最晚的历史订单时间
+    pub clock: i64,
+}
+
+/// `Exchange::recreate_broker` 从旧经纪商保留到新经纪商的状态，可以用 `|` 组合。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreserveFlags(u8);
+
+impl PreserveFlags {
+    /// 不保留任何状态
+    pub const NONE: Self = Self(0);
+    /// 参考数据：申报规则、涨跌停幅度和处理方式、前收盘价未知时的参考价格、
+    /// 同一时间戳的处理顺序和交易日历
+    pub const REFERENCE_DATA: Self = Self(1);
+    /// 已注册的钩子及其配置，以及慢钩子监控的配置
+    pub const HOOKS: Self = Self(1 << 1);
+    /// 前收盘价
+    pub const PREVIOUS_CLOSE: Self = Self(1 << 2);
+    /// 市场深度的统计数据
+    pub const STATISTICS: Self = Self(1 << 3);
+    /// 保留以上所有状态
+    pub const ALL: Self = Self(0b1111);
+
+    /// 是否包含 `other` 中的所有状态。
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PreserveFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 impl<'a, MD> Exchange<MD>
 where
-    MD: L3MarketDepth + Serialize + Deserialize<'a> + RecoverOp + StatisticsOp + SnapshotOp,
+    MD: L3MarketDepth
+        + Serialize
+        + Deserialize<'a>
+        + RecoverOp
+        + StatisticsOp
+        + SnapshotOp
+        + 'static,
     MarketError: From<<MD as L3MarketDepth>::Error>,
 {
     /// 创建一个新的 `Exchange` 实例。
@@ -47,15 +151,37 @@ where
     ///
     /// # 返回值
     /// 返回一个 `Exchange` 实例。
+    ///
+    /// # Panics
+    /// `mode` 不是合法的交易模式时 panic，需要处理错误时使用 `try_new`。
     pub fn new(mode: &str, data: &str) -> Self {
-        Self {
-            mode: ExchangeMode::from_str(&mode).unwrap(),
+        Self::try_new(mode, data).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// 创建一个新的 `Exchange` 实例，交易模式不合法时返回错误。
+    ///
+    /// # 参数
+    /// - `mode`: 交易所的模式，`backtest` 或 `live`，不区分大小写。
+    /// - `data`: 当前日期（字符串形式）。
+    ///
+    /// # 错误
+    /// `mode` 不是合法的交易模式时返回 `MarketError::UnknownExchangeMode`。
+    pub fn try_new(mode: &str, data: &str) -> Result<Self, MarketError> {
+        Ok(Self {
+            mode: ExchangeMode::from_str(mode)?,
             broker_map: HashMap::new(),
             date: Some(data.to_string()),
             latest_seq: 0,
             latest_order_id: 0,
             timestamp: 19700101000000000,
-        }
+            circuit_breakers: HashMap::new(),
+            account_events: Vec::new(),
+            idempotency: IdempotencyCache::default(),
+            link_groups: Vec::new(),
+            link_cancels: Vec::new(),
+            pace_lunch_break: false,
+            workers: HashMap::new(),
+        })
     }
 
     pub fn exists_stock(&self, stock_code: &str) -> bool {
@@ -78,25 +204,346 @@ where
         let mut total_filled: i64 = 0;
 
         if stock_code.is_none() {
+            // 先把推进任务分发给所有回放线程，再在调用线程上推进其余的经纪商
+            let mut pending = Vec::with_capacity(self.workers.len());
+            for worker in self.workers.values() {
+                pending.push(worker.start(move |broker| Self::elapse_broker(broker, duration))?);
+            }
+            let mut result = Ok(());
             for (_, broker) in self.broker_map.iter_mut() {
-                let filled = broker.elapse(duration)?;
-                total_filled += filled;
-                broker.sync_order_info();
+                match Self::elapse_broker(broker, duration) {
+                    Ok(filled) => total_filled += filled,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
             }
+            // 等待所有回放线程完成后再返回，即使调用线程上的经纪商已经出错
+            for receiver in pending {
+                let filled = receiver
+                    .recv()
+                    .unwrap_or(Err(MarketError::WorkerUnavailable));
+                match filled {
+                    Ok(filled) => total_filled += filled,
+                    Err(err) => {
+                        if result.is_ok() {
+                            result = Err(err);
+                        }
+                    }
+                }
+            }
+            result?;
             self.timestamp = adjust_timestamp_milliseconds_i64(self.timestamp, duration)?;
+        } else if let Some(worker) = self.workers.get(stock_code.unwrap()) {
+            total_filled += worker.call(move |broker| Self::elapse_broker(broker, duration))??;
         } else {
             let broker = self
                 .broker_map
                 .get_mut(stock_code.unwrap())
                 .ok_or(MarketError::StockBrokerNotExist)?;
-            let filled = broker.elapse(duration)?;
-            total_filled += filled;
-            broker.sync_order_info();
+            total_filled += Self::elapse_broker(broker, duration)?;
         }
+        self.update_circuit_breakers();
+        self.update_linked_orders();
 
         Ok(total_filled)
     }
 
+    /// 推进经纪商并同步订单信息，返回用户订单的成交量。
+    fn elapse_broker(broker: &mut Broker<MD>, duration: i64) -> Result<i64, MarketError> {
+        let filled = broker.elapse(duration)?;
+        broker.sync_order_info();
+        Ok(filled)
+    }
+
+    /// 为每只股票创建一个常驻的回放线程，并在线程上调用 `build` 创建该股票的经纪商。
+    ///
+    /// 经纪商只在各自的回放线程上创建和访问，不会在线程之间移动；`build` 中可以同时加载历史数据，
+    /// 各股票的数据加载和撮合因此并行执行。之后 `elapse` 推进所有股票时，把推进的时间段分发给
+    /// 各回放线程，等待所有线程完成后再汇总结果；`send_order` 等下单方法把订单发送到对应的回放线程。
+    /// 其余需要访问经纪商的操作通过 `with_worker` 在回放线程上执行，`get_broker` 等方法只能访问
+    /// 不在回放线程上的经纪商。
+    ///
+    /// # 参数
+    /// - `stock_codes`: 需要创建回放线程的股票代码。
+    /// - `build`: 创建经纪商的函数，参数为股票代码。
+    ///
+    /// # 错误
+    /// - `StockBrokerIdExist`: 某只股票已经有经纪商或回放线程，此时不会创建任何回放线程。
+    /// - `build` 返回的错误，或者无法创建线程时的 `WorkerUnavailable`，
+    ///   此时本次调用中已经创建的回放线程会被停止。
+    pub fn spawn_workers<F>(&mut self, stock_codes: &[&str], build: F) -> Result<(), MarketError>
+    where
+        F: Fn(&str) -> Result<Broker<MD>, MarketError> + Send + Sync + 'static,
+    {
+        if stock_codes.iter().any(|stock_code| {
+            self.broker_map.contains_key(*stock_code) || self.workers.contains_key(*stock_code)
+        }) {
+            return Err(MarketError::StockBrokerIdExist);
+        }
+
+        let build = Arc::new(build);
+        let mut spawned = HashMap::new();
+        for stock_code in stock_codes {
+            let build = Arc::clone(&build);
+            let code = stock_code.to_string();
+            // 出错时已经创建的回放线程随 `spawned` 一起丢弃
+            let worker = ReplayWorker::spawn(stock_code, move || build(&code))?;
+            spawned.insert(stock_code.to_string(), worker);
+        }
+        self.workers.extend(spawned);
+        Ok(())
+    }
+
+    /// 停止所有回放线程，线程上的经纪商和其中的订单随之丢弃。
+    pub fn shutdown_workers(&mut self) {
+        self.workers.clear();
+    }
+
+    /// 在股票的回放线程上对经纪商执行 `f`，并等待返回结果。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `f`: 在回放线程上执行的函数，它的返回值需要能够发送到调用线程。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 股票没有回放线程。
+    /// - `WorkerUnavailable`: 回放线程已经退出，或者 `f` 发生 panic。
+    pub fn with_worker<R, F>(&self, stock_code: &str, f: F) -> Result<R, MarketError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Broker<MD>) -> R + Send + 'static,
+    {
+        self.workers
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?
+            .call(f)
+    }
+
+    /// 将经纪商的时间推进到 `until`，并在途经的每个采样网格时间点上调用订单簿钩子。
+    ///
+    /// 网格时间点由 `TradingCalendar::next_grid_time` 给出，与交易所每隔 `grid_ms` 毫秒发布的
+    /// 官方快照对齐，午间休市内没有时间点。推进按网格时间点分段进行，每段在时间点上停止，
+    /// 晚于时间点的逐笔消息留到下一段处理；到达时间点后即使期间没有逐笔消息也会调用钩子，
+    /// 钩子收到的订单为 `OrderType::None` 类型的占位订单，参见 `Broker::sample_hooks`。
+    ///
+    /// # 参数
+    /// - `grid_ms`: 网格间隔，以毫秒为单位，交易所快照为 3000。
+    /// - `until`: 推进的目标时间，格式为 `20230801093939123`，等于网格时间点时包含该时间点。
+    /// - `stock_code`: 只推进指定的股票，为 `None` 时推进所有股票。
+    ///
+    /// # 返回值
+    /// 每只股票在 `(当前时间, until]` 内采样的网格时间点数量以及期间用户订单的成交量。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidGridInterval`: `grid_ms` 不是正数。
+    /// - `MarketError::StockBrokerNotExist`: 指定股票的经纪商不存在。
+    /// - `MarketError::HistoryIsNone`: 有经纪商没有历史数据，无法推进时间，此时所有经纪商都不推进。
+    /// - 其他错误来自于经纪商的 `elapse` 方法。
+    pub fn elapse_to_grid(
+        &mut self,
+        grid_ms: i64,
+        until: i64,
+        stock_code: Option<&str>,
+    ) -> Result<GridReport, MarketError> {
+        if grid_ms <= 0 {
+            return Err(MarketError::InvalidGridInterval(grid_ms));
+        }
+        let stock_codes: Vec<String> = match stock_code {
+            Some(stock_code) if !self.exists_stock(stock_code) => {
+                return Err(MarketError::StockBrokerNotExist)
+            }
+            Some(stock_code) => vec![stock_code.to_string()],
+            None => self.broker_map.keys().cloned().collect(),
+        };
+        // 没有历史数据时 `elapse` 不推进时间，逐个网格时间点推进将无法结束
+        if stock_codes
+            .iter()
+            .any(|stock_code| self.broker_map[stock_code].history.is_none())
+        {
+            return Err(MarketError::HistoryIsNone);
+        }
+
+        let mut report = GridReport::default();
+        for stock_code in stock_codes {
+            let broker = self.broker_map.get_mut(&stock_code).unwrap();
+            let mut grid_points = 0;
+            while let Some(grid_time) = broker
+                .calendar
+                .next_grid_time(broker.timestamp, grid_ms)?
+                .filter(|grid_time| *grid_time <= until)
+            {
+                let duration = time_difference_ms_i64(broker.timestamp, grid_time)?;
+                report.total_filled += broker.elapse(duration)?;
+                broker.sample_hooks();
+                grid_points += 1;
+            }
+            if broker.timestamp < until {
+                let duration = time_difference_ms_i64(broker.timestamp, until)?;
+                report.total_filled += broker.elapse(duration)?;
+            }
+            broker.sync_order_info();
+            report.grid_points.insert(stock_code, grid_points);
+        }
+        if stock_code.is_none() {
+            self.timestamp = cmp::max(self.timestamp, until);
+        }
+        self.update_circuit_breakers();
+        self.update_linked_orders();
+
+        Ok(report)
+    }
+
+    /// 按墙上时间的节奏回放历史数据，用于演示和模拟交易。
+    ///
+    /// 反复调用 `step_paced`，直到历史数据全部处理完毕或者 `stop` 被置位。`stop` 在每一步之前检查，
+    /// 可以由其他线程设置。回放期间一直持有交易所的可变引用，需要在两步之间提交订单的调用方
+    /// （例如服务端或 Python）应当用 `start_paced` 和 `step_paced` 自己驱动回放。
+    ///
+    /// # 参数
+    /// - `speed`: 回放倍速，`1.0` 为实时，`f64::INFINITY` 表示不等待，尽快回放。
+    /// - `stock_code`: 只回放指定的股票，为 `None` 时回放所有股票。
+    /// - `stop`: 停止标志。
+    ///
+    /// # 错误
+    /// 参见 `start_paced` 和 `step_paced`。
+    pub fn run_paced(
+        &mut self,
+        speed: f64,
+        stock_code: Option<&str>,
+        stop: Arc<AtomicBool>,
+    ) -> Result<(), MarketError> {
+        let mut replay = self.start_paced(speed, stock_code)?;
+        while !stop.load(Ordering::Acquire) {
+            if !self.step_paced(&mut replay)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// 开始按墙上时间回放，回放时钟从参与回放的经纪商中最晚的当前时间开始。
+    ///
+    /// # 参数
+    /// - `speed`: 回放倍速，`1.0` 为实时，`f64::INFINITY` 表示不等待，尽快回放。
+    /// - `stock_code`: 只回放指定的股票，为 `None` 时回放所有股票。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidPacingSpeed`: `speed` 不是正数。
+    /// - `MarketError::StockBrokerNotExist`: 指定股票的经纪商不存在。
+    pub fn start_paced(
+        &self,
+        speed: f64,
+        stock_code: Option<&str>,
+    ) -> Result<PacedReplay, MarketError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(MarketError::InvalidPacingSpeed(speed.to_string()));
+        }
+        let stock_codes: Vec<String> = match stock_code {
+            Some(stock_code) if !self.exists_stock(stock_code) => {
+                return Err(MarketError::StockBrokerNotExist)
+            }
+            Some(stock_code) => vec![stock_code.to_string()],
+            None => self.broker_map.keys().cloned().collect(),
+        };
+        let clock = stock_codes
+            .iter()
+            .map(|stock_code| self.broker_map[stock_code].timestamp)
+            .max()
+            .unwrap_or(self.timestamp);
+        Ok(PacedReplay {
+            speed,
+            stock_codes,
+            all_stocks: stock_code.is_none(),
+            clock,
+        })
+    }
+
+    /// 按墙上时间回放一步。
+    ///
+    /// 预读下一条历史订单的时间，等待两条消息之间的时间间隔除以倍速的墙上时间后，将经纪商推进到
+    /// 该时间并处理该时间戳上的全部历史订单。`pace_lunch_break` 为 `false` 时午间休市不计入等待时间。
+    ///
+    /// # 返回值
+    /// 推进了一步返回 `true`；历史数据已经处理完毕，或者推进后没有处理任何历史订单时返回 `false`。
+    ///
+    /// # 错误
+    /// - `MarketError::StockBrokerNotExist`: 回放的股票已经被移除。
+    /// - 其他错误来自于经纪商的 `elapse` 和 `goto_through` 方法。
+    pub fn step_paced(&mut self, replay: &mut PacedReplay) -> Result<bool, MarketError> {
+        let mut next_time = None;
+        let mut events_before = 0;
+        for stock_code in replay.stock_codes.iter() {
+            let broker = self
+                .broker_map
+                .get(stock_code)
+                .ok_or(MarketError::StockBrokerNotExist)?;
+            events_before += broker.events_processed;
+            next_time = match (next_time, broker.next_event_time()) {
+                (Some(current), Some(time)) => Some(cmp::min(current, time)),
+                (current, time) => current.or(time),
+            };
+        }
+        let next_time = match next_time {
+            Some(next_time) => next_time,
+            None => return Ok(false),
+        };
+        let wait_ms = if next_time > replay.clock {
+            self.paced_wait_ms(replay.clock, next_time)?
+        } else {
+            0
+        };
+        if wait_ms > 0 && replay.speed.is_finite() {
+            sleep(Duration::from_secs_f64(
+                wait_ms as f64 / 1000.0 / replay.speed,
+            ));
+        }
+        replay.clock = cmp::max(replay.clock, next_time);
+
+        let mut events_after = 0;
+        for stock_code in replay.stock_codes.iter() {
+            let broker = self.broker_map.get_mut(stock_code).unwrap();
+            let duration = if broker.timestamp < next_time {
+                time_difference_ms_i64(broker.timestamp, next_time)?
+            } else {
+                0
+            };
+            broker.elapse(duration)?;
+            // `same_time_priority` 可能把该时间戳上的历史订单留到用户订单之后，这里全部处理掉，
+            // 否则下一步预读到的仍是同一条历史订单
+            if broker.history.is_some() {
+                broker.goto_through(next_time)?;
+            }
+            broker.sync_order_info();
+            events_after += broker.events_processed;
+        }
+        if replay.all_stocks {
+            self.timestamp = cmp::max(self.timestamp, next_time);
+        }
+        self.update_circuit_breakers();
+        self.update_linked_orders();
+
+        Ok(events_after > events_before)
+    }
+
+    /// 返回 `run_paced` 从 `from` 推进到 `to` 需要等待的模拟时间，以毫秒为单位。
+    ///
+    /// `pace_lunch_break` 为 `false` 时扣除与当日午间休市重叠的部分。
+    fn paced_wait_ms(&self, from: i64, to: i64) -> Result<i64, MarketError> {
+        let total = time_difference_ms_i64(from, to)?;
+        if self.pace_lunch_break {
+            return Ok(total);
+        }
+        let date = to / 1_000_000_000 * 1_000_000_000;
+        let break_start = cmp::max(from, date + BREAK_START_TIME);
+        let break_end = cmp::min(to, date + BREAK_END_TIME);
+        if break_start >= break_end {
+            return Ok(total);
+        }
+        Ok(total - time_difference_ms_i64(break_start, break_end)?)
+    }
+
     /// 从指定经纪商的订单簿中检索订单，并根据给定的状态筛选订单。
     ///
     /// # 参数
@@ -125,6 +572,34 @@ where
         }
         Ok(true)
     }
+
+    /// 从指定经纪商检索所有仍在交易中的订单，见 `OrderStatus::is_open`。
+    ///
+    /// # 参数
+    /// - `orders`: 一个可变的 `HashMap`，用于存储符合条件的订单。
+    /// - `stock_code`: 经纪商的股票代码，为 `None` 时检索所有经纪商。
+    ///
+    /// # 错误
+    /// 指定的经纪商不存在时返回 `MarketError::StockBrokerNotExist`。
+    pub fn get_open_orders(
+        &self,
+        orders: &mut HashMap<OrderId, OrderRef>,
+        stock_code: Option<&str>,
+    ) -> Result<bool, MarketError> {
+        match stock_code {
+            None => {
+                for broker in self.broker_map.values() {
+                    broker.get_open_orders(orders);
+                }
+            }
+            Some(stock_code) => self
+                .broker_map
+                .get(stock_code)
+                .ok_or(MarketError::StockBrokerNotExist)?
+                .get_open_orders(orders),
+        }
+        Ok(true)
+    }
     /// 从经纪商的订单簿中检索最新的订单。
     ///
     /// # 参数
@@ -202,6 +677,106 @@ where
 
         Ok(true)
     }
+
+    /// 从交易所移除指定股票的经纪商，例如股票停牌或者需要释放内存时。
+    ///
+    /// 关联订单组中该股票的腿随之移除，剩余不足两条腿的组不再需要联动，一并移除。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    ///
+    /// # 返回值
+    /// 被移除的经纪商，调用方可以在丢弃前保存快照。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn remove_broker(&mut self, stock_code: &str) -> Result<Broker<MD>, MarketError> {
+        let broker = self
+            .broker_map
+            .remove(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        self.prune_link_groups(stock_code);
+        Ok(broker)
+    }
+
+    /// 移除关联订单组中指定股票的腿，并丢弃剩余不足两条腿的组。
+    fn prune_link_groups(&mut self, stock_code: &str) {
+        for group in self.link_groups.iter_mut() {
+            group.legs.retain(|(code, _)| code != stock_code);
+        }
+        self.link_groups.retain(|group| group.legs.len() > 1);
+    }
+
+    /// 用同一只股票的新经纪商替换现有的经纪商，并按 `preserve` 保留旧经纪商的部分状态。
+    ///
+    /// 新经纪商的订单簿、订单、历史数据和时间都是初始状态，旧经纪商的订单随之丢弃，
+    /// 关联订单组中该股票的腿也一并移除。
+    /// 保留的钩子按原有的配置注册到新经纪商上，耗时统计重新开始。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `preserve`: 需要保留的状态，参见 `PreserveFlags`。
+    ///
+    /// # 返回值
+    /// 新经纪商的可变引用。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn recreate_broker(
+        &mut self,
+        stock_code: &str,
+        preserve: PreserveFlags,
+    ) -> Result<&mut Broker<MD>, MarketError> {
+        let mut old = self
+            .broker_map
+            .remove(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        self.prune_link_groups(stock_code);
+        let mut broker: Broker<MD> = Broker::new(
+            old.mode,
+            old.market_type,
+            old.stock_type.clone(),
+            old.stock_code.clone(),
+            old.tick_size,
+            old.lot_size,
+        );
+        broker.init();
+
+        if preserve.contains(PreserveFlags::REFERENCE_DATA) {
+            broker.lot_rule = old.lot_rule;
+            broker.opening_reference = old.opening_reference;
+            broker.price_limit_ratio = old.price_limit_ratio;
+            broker.price_limit_policy = old.price_limit_policy;
+            broker.same_time_priority = old.same_time_priority;
+            broker.calendar = old.calendar.clone();
+        }
+        if preserve.contains(PreserveFlags::STATISTICS) {
+            *broker.market_depth.get_statistics_mut() = *old.market_depth.get_statistics();
+        }
+        // 统计数据中的前收盘价与经纪商保持一致
+        let previous_close_price = match preserve.contains(PreserveFlags::PREVIOUS_CLOSE) {
+            true => old.previous_close_price,
+            false => None,
+        };
+        broker.set_previous_close_price(previous_close_price.unwrap_or(0.0));
+        if preserve.contains(PreserveFlags::HOOKS) {
+            broker.hooks = std::mem::take(&mut old.hooks);
+            for hook in broker
+                .hooks
+                .values_mut()
+                .flat_map(|hooks| hooks.values_mut())
+            {
+                hook.stats = HookStats::default();
+                hook.slow_strikes = 0;
+            }
+            broker.hook_watchdog = old.hook_watchdog;
+            broker.hook_timing = old.hook_timing;
+        }
+
+        self.broker_map.insert(stock_code.to_string(), broker);
+        Ok(self.broker_map.get_mut(stock_code).unwrap())
+    }
+
     /// 将数据添加到指定经纪商的数据收集器中。
     ///
     /// # 参数
@@ -261,6 +836,19 @@ where
         }
     }
 
+    /// 获取所有经纪商的当前时间戳。
+    ///
+    /// 多只股票回放时各经纪商独立推进时间，驱动程序可以据此检查各股票的时间是否一致。
+    ///
+    /// # 返回值
+    /// 返回股票代码到对应经纪商当前时间戳的映射，时间戳为 17 位整数，格式为 `YYYYMMDDHHMMSSmmm`。
+    pub fn current_time_all(&self) -> HashMap<String, i64> {
+        self.broker_map
+            .iter()
+            .map(|(stock_code, broker)| (stock_code.clone(), broker.get_current_time()))
+            .collect()
+    }
+
     /// 获取指定股票代码的经纪商的不可变引用。
     ///
     /// # 参数
@@ -272,6 +860,22 @@ where
         self.broker_map.get(stock_code)
     }
 
+    /// 遍历所有经纪商，顺序不固定。
+    ///
+    /// # 返回值
+    /// 返回 (股票代码, 经纪商) 的迭代器。
+    pub fn brokers(&self) -> impl Iterator<Item = (&String, &Broker<MD>)> {
+        self.broker_map.iter()
+    }
+
+    /// 以可变引用遍历所有经纪商，顺序不固定，用于对每只股票执行相同的操作。
+    ///
+    /// # 返回值
+    /// 返回 (股票代码, 经纪商可变引用) 的迭代器。
+    pub fn brokers_mut(&mut self) -> impl Iterator<Item = (&String, &mut Broker<MD>)> {
+        self.broker_map.iter_mut()
+    }
+
     /// 生成一个新的订单序列号。
     ///
     /// # 返回值
@@ -296,7 +900,7 @@ where
             .get(stock_code)
             .ok_or(MarketError::StockBrokerNotExist)?;
 
-        Ok(broker.market_depth.best_bid(source))
+        Ok(broker.best_bid(source))
     }
 
     /// 获取指定股票代码的最佳买入价。
@@ -313,7 +917,7 @@ where
             .get(stock_code)
             .ok_or(MarketError::StockBrokerNotExist)?;
 
-        Ok(broker.market_depth.best_ask(source))
+        Ok(broker.best_ask(source))
     }
 
     pub fn best_bid_tick(
@@ -350,6 +954,103 @@ where
         Ok(broker.market_depth.best_ask_tick(source))
     }
 
+    /// 按 `mode` 获取指定股票代码的最佳买入价，不应用行情延迟。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `mode`: 使用真实、影子或两者合并的订单簿，参见 `BestMode`。
+    ///
+    /// # 返回值
+    /// 返回最佳买入价，没有买单时为 `NaN`。
+    pub fn best_bid_with_mode(&self, stock_code: &str, mode: BestMode) -> Result<f64, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+
+        Ok(broker.market_depth.best_bid_with_mode(mode))
+    }
+
+    /// 按 `mode` 获取指定股票代码的最佳卖出价，不应用行情延迟。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `mode`: 使用真实、影子或两者合并的订单簿，参见 `BestMode`。
+    ///
+    /// # 返回值
+    /// 返回最佳卖出价，没有卖单时为 `NaN`。
+    pub fn best_ask_with_mode(&self, stock_code: &str, mode: BestMode) -> Result<f64, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+
+        Ok(broker.market_depth.best_ask_with_mode(mode))
+    }
+
+    /// 一次性获取多只股票的最优价，按股票代码排序。
+    ///
+    /// 每一行的价格与 `best_bid`、`best_ask`、`best_bid_tick` 和 `best_ask_tick` 的结果一致。
+    ///
+    /// # 参数
+    /// - `source`: 订单来源类型。
+    /// - `symbols`: 要查询的股票代码，`None` 表示所有股票，找不到经纪商的股票被忽略。
+    pub fn bbo_snapshot(&self, source: &OrderSourceType, symbols: Option<&[&str]>) -> Vec<BboRow> {
+        let row = |(stock_code, broker): (&String, &Broker<MD>)| BboRow {
+            stock_code: stock_code.clone(),
+            best_bid: broker.best_bid(source),
+            best_ask: broker.best_ask(source),
+            best_bid_tick: broker.market_depth.best_bid_tick(source),
+            best_ask_tick: broker.market_depth.best_ask_tick(source),
+            last_price: broker.last_price(source),
+            timestamp: broker.timestamp,
+        };
+        let mut rows: Vec<BboRow> = match symbols {
+            Some(symbols) => symbols
+                .iter()
+                .filter_map(|stock_code| self.broker_map.get_key_value(*stock_code))
+                .map(row)
+                .collect(),
+            None => self.broker_map.iter().map(row).collect(),
+        };
+        rows.sort_by(|a, b| a.stock_code.cmp(&b.stock_code));
+        rows.dedup_by(|a, b| a.stock_code == b.stock_code);
+        rows
+    }
+
+    /// 获取指定股票策略可见的买卖双方档位，买方按价格从高到低，卖方按价格从低到高。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `max_level`: 每一方返回的最大档位数量。
+    pub fn levels(
+        &self,
+        stock_code: &str,
+        max_level: usize,
+    ) -> Result<Vec<LevelView>, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+
+        Ok(broker.level_views(max_level))
+    }
+
+    /// 以 `DataFrame` 的形式获取指定股票的档位，列定义参见 `levels_to_dataframe`。
+    ///
+    /// # 错误
+    /// - 找不到经纪人时返回 `MarketError::StockBrokerNotExist`。
+    /// - 构造 `DataFrame` 失败时返回 `MarketError::DataExportError`。
+    #[cfg(feature = "data")]
+    pub fn levels_as_dataframe(
+        &self,
+        stock_code: &str,
+        max_level: usize,
+    ) -> Result<DataFrame, MarketError> {
+        let levels = self.levels(stock_code, max_level)?;
+        levels_to_dataframe(&levels).map_err(|err| MarketError::DataExportError(err.to_string()))
+    }
+
     /// 获取指定股票代码的最佳卖出价对应的价格档位。
     ///
     /// # 参数
@@ -453,31 +1154,299 @@ where
         Ok(true)
     }
 
-    /// 注册一个订单簿钩子。
+    /// 设置指定股票在前收盘价未知时使用的参考价格，参见 `Broker::set_opening_reference`。
     ///
     /// # 参数
-    /// - `stock_code`: 要注册钩子的股票代码。
-    /// - `hook_type`: 钩子的类型，使用 `HookType` 枚举。
-    /// - `name`: 钩子的名称，用于标识钩子。
-    /// - `hook`: 实际的钩子实现，使用 `Hook` 类型。
-    ///
-    /// # 返回值
-    /// 返回 `Ok(true)` 表示钩子已成功注册。
+    /// - `stock_code`: 股票代码。
+    /// - `opening_reference`: 前收盘价未知时使用的参考价格。
     ///
     /// # 错误
     /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
-    pub fn register_orderbook_hook(
+    pub fn set_opening_reference(
         &mut self,
         stock_code: &str,
-        hook_type: HookType,
-        name: &str,
-        hook: Hook,
+        opening_reference: OpeningReference,
     ) -> Result<bool, MarketError> {
-        let mut broker = self
+        let broker = self
             .broker_map
             .get_mut(stock_code)
             .ok_or(MarketError::StockBrokerNotExist)?;
-        broker.register_orderbook_hook(hook_type, name, hook);
+        broker.set_opening_reference(opening_reference);
+        Ok(true)
+    }
+
+    /// 批量设置多只股票的前收盘价，找不到经纪商的股票不影响其他股票的设置。
+    ///
+    /// # 参数
+    /// - `closes`: 股票代码到前收盘价的映射。
+    ///
+    /// # 返回值
+    /// 返回成功设置前收盘价的股票数量。
+    ///
+    /// # 错误
+    /// 存在找不到经纪商的股票时返回 `MarketError::StockBrokersNotExist`，其中包含已设置的数量
+    /// 以及按代码排序的未知股票列表。
+    pub fn set_prev_close_batch(
+        &mut self,
+        closes: &HashMap<String, f64>,
+    ) -> Result<usize, MarketError> {
+        let mut applied = 0;
+        let mut unknown = Vec::new();
+        for (stock_code, price) in closes {
+            match self.broker_map.get_mut(stock_code) {
+                Some(broker) => {
+                    broker.set_previous_close_price(*price);
+                    applied += 1;
+                }
+                None => unknown.push(stock_code.clone()),
+            }
+        }
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(MarketError::StockBrokersNotExist {
+                applied: applied,
+                stock_codes: unknown,
+            });
+        }
+        Ok(applied)
+    }
+
+    /// 设置指定股票的策略可见行情延迟。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `delay`: 延迟时间，单位为毫秒，0 表示不延迟。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_md_delay(&mut self, stock_code: &str, delay: i64) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_md_delay(delay);
+        Ok(true)
+    }
+
+    /// 设置指定股票的撤单延迟，参见 `Broker::set_cancel_latency`。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `latency`: 延迟时间，单位为毫秒，0 表示撤单立即生效。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_cancel_latency(
+        &mut self,
+        stock_code: &str,
+        latency: i64,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_cancel_latency(latency);
+        Ok(true)
+    }
+
+    /// 设置指定股票的订单确认延迟，参见 `Broker::set_ack_latency`。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `latency`: 延迟时间，单位为毫秒，0 表示订单到达即得到确认。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_ack_latency(&mut self, stock_code: &str, latency: i64) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_ack_latency(latency);
+        Ok(true)
+    }
+
+    /// 设置指定股票的订单队列容量上限。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `max_pending`: 当前时间待处理队列的容量上限，`None` 表示不限制。
+    /// - `max_waiting`: 未来时间等待队列的容量上限，`None` 表示不限制。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_queue_limits(
+        &mut self,
+        stock_code: &str,
+        max_pending: Option<usize>,
+        max_waiting: Option<usize>,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_queue_limits(max_pending, max_waiting);
+        Ok(true)
+    }
+
+    /// 设置指定股票的涨跌停幅度及涨跌停时市价订单的处理方式。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `ratio`: 相对前收盘价的涨跌停幅度，例如 0.1 表示 10%，0 表示不设涨跌停。
+    /// - `policy`: 订单簿在涨跌停价上单边时市价订单的处理方式。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_price_limit(
+        &mut self,
+        stock_code: &str,
+        ratio: f64,
+        policy: PriceLimitPolicy,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_price_limit(ratio, policy);
+        Ok(true)
+    }
+
+    /// 设置指定股票的用户订单与历史订单在同一时间戳到达时的处理顺序。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_same_time_priority(
+        &mut self,
+        stock_code: &str,
+        priority: SameTimePriority,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_same_time_priority(priority);
+        Ok(true)
+    }
+
+    /// 注册一个订单簿钩子。
+    ///
+    /// # 参数
+    /// - `stock_code`: 要注册钩子的股票代码。
+    /// - `hook_type`: 钩子的类型，使用 `HookType` 枚举。
+    /// - `name`: 钩子的名称，用于标识钩子。
+    /// - `hook`: 实际的钩子实现，使用 `Hook` 类型。
+    ///
+    /// # 返回值
+    /// 返回 `Ok(true)` 表示钩子已成功注册。
+    ///
+    /// # 错误
+    /// - 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    /// - 如果同一类型下已经注册了同名的钩子，返回 `MarketError::HookNameExists`。
+    pub fn register_orderbook_hook(
+        &mut self,
+        stock_code: &str,
+        hook_type: HookType,
+        name: &str,
+        hook: Hook,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.register_orderbook_hook(hook_type, name, hook)?;
+        Ok(true)
+    }
+
+    /// 注册一个订单簿钩子，已有同名的钩子时将其替换。
+    ///
+    /// # 返回值
+    /// 被替换的钩子，原来没有同名的钩子时返回 `None`。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn replace_hook(
+        &mut self,
+        stock_code: &str,
+        hook_type: HookType,
+        name: &str,
+        hook: Hook,
+    ) -> Result<Option<Hook>, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.replace_hook(hook_type, name, hook)
+    }
+
+    /// 移除指定股票上的钩子。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `hook_type`: 钩子的类型，为 `None` 时在所有类型中移除同名的钩子。
+    /// - `name`: 钩子的名称。
+    ///
+    /// # 返回值
+    /// 是否有钩子被移除。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn remove_hook(
+        &mut self,
+        stock_code: &str,
+        hook_type: Option<HookType>,
+        name: &str,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.remove_hook(hook_type, name)
+    }
+
+    /// 返回指定股票上已注册钩子的描述信息。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn hooks_info(&self, stock_code: &str) -> Result<Vec<HookInfo>, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        Ok(broker.hooks_info())
+    }
+
+    /// 设置指定股票的慢钩子监控，参见 `Broker::set_hook_watchdog`。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_hook_watchdog(
+        &mut self,
+        stock_code: &str,
+        watchdog: Option<HookWatchdog>,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_hook_watchdog(watchdog);
+        Ok(true)
+    }
+
+    /// 设置指定股票是否统计钩子的执行耗时，参见 `Broker::set_hook_timing`。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn set_hook_timing(
+        &mut self,
+        stock_code: &str,
+        enabled: bool,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.set_hook_timing(enabled);
         Ok(true)
     }
 
@@ -490,49 +1459,431 @@ where
     /// - 返回新订单的 ID 或者在失败时返回错误。
     ///
     /// # 参数
-    /// - `stock_code`: 目标股票代码，指定订单将被发送到哪个经纪商。
-    /// - `order_time`: 订单的下单时间，使用 17 位整数表示，格式应为 YYYYMMDDHHMMSSSSS。
-    /// - `order_price`: 订单的价格，以浮点数表示。
-    /// - `order_volume`: 订单的数量，以整数表示。
-    /// - `bs_flag`: 标记订单是买入还是卖出，具体取值可能依赖于业务逻辑。
+    /// - `acc`: 账户名称。字符串 `"none"`（不区分大小写）表示不属于任何账户，
+    ///   这种写法已不推荐使用，新代码请改用 `send_order_with_account` 并传入 `None`。
+    /// - `stock_code`: 目标股票代码，指定订单将被发送到哪个经纪商。
+    /// - `order_time`: 订单的下单时间，使用 17 位整数表示，格式应为 YYYYMMDDHHMMSSSSS。
+    /// - `order_price`: 订单的价格，以浮点数表示。
+    /// - `order_volume`: 订单的数量，以整数表示。
+    /// - `bs_flag`: 标记订单是买入还是卖出，具体取值可能依赖于业务逻辑。
+    ///
+    /// # 返回值
+    /// - `Ok(OrderId)`: 如果操作成功，返回新创建的订单 ID。
+    /// - `Err(MarketError)`: 如果操作失败，返回错误。可能的错误包括订单时间无效或经纪商不存在。
+    ///
+    /// # 错误
+    /// - `InvalidOrderRequest`: 如果订单时间不是 17 位整数，或者订单价格、数量不合法。
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn send_order(
+        &mut self,
+        acc: &str,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<OrderId, MarketError> {
+        self.send_order_with_account(
+            parse_account(acc),
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+        )
+    }
+
+    /// 以显式的账户发送新订单，账户名称按原样使用，不会把 `"none"` 解释为没有账户。
+    ///
+    /// # 参数
+    /// - `account`: 账户名称，`None` 表示订单不属于任何账户，不参与账户熔断统计。
+    /// - 其余参数与 `send_order` 相同。
+    ///
+    /// # 错误
+    /// - `AccountHalted`: 账户已被熔断。
+    /// - 其余错误与 `send_order` 相同。
+    pub fn send_order_with_account(
+        &mut self,
+        account: Option<&str>,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<OrderId, MarketError> {
+        self.submit_new_order(
+            account,
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+            None,
+            0,
+        )
+    }
+
+    /// 带幂等键发送新订单，重试同一个请求时不会重复下单。
+    ///
+    /// # 参数
+    /// - `key`: 调用方生成的幂等键。
+    /// - 其余参数与 `send_order` 相同。
+    ///
+    /// # 返回值
+    /// - `Ok(OrderId)`: 新订单的 ID；键已经成功使用过且参数一致时返回原订单的 ID，不会创建新订单。
+    ///
+    /// # 错误
+    /// - `IdempotencyConflict`: 键已经使用过但参数不一致。
+    /// - 其余错误与 `send_order` 相同，失败的请求不会记录幂等键，可以重试。
+    pub fn send_order_idempotent(
+        &mut self,
+        key: &str,
+        acc: &str,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<OrderId, MarketError> {
+        let request = IdempotentRequest::NewOrder {
+            account: acc.to_string(),
+            stock_code: stock_code.to_string(),
+            order_time: order_time,
+            order_price: order_price,
+            order_volume: order_volume,
+            bs_flag: bs_flag.to_string(),
+            order_type: order_type,
+        };
+        if let Some(order_id) = self.idempotency.lookup(key, &request)? {
+            return Ok(order_id);
+        }
+        let order_id = self.send_order(
+            acc,
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+        )?;
+        self.idempotency.insert(key, request, order_id);
+        Ok(order_id)
+    }
+
+    /// 设置幂等缓存保留的键数量，超出的键按最久未使用的顺序淘汰。
+    pub fn set_idempotency_capacity(&mut self, capacity: usize) {
+        self.idempotency.set_capacity(capacity);
+    }
+
+    /// 以指定的策略编号发送新订单，用于在同一次回放中区分多个策略的成交。
+    ///
+    /// # 参数
+    /// - `strategy_id`: 策略编号，成交按该编号汇总到 `strategy_summary`。
+    /// - 其余参数与 `send_order` 相同。
+    ///
+    /// # 错误
+    /// 与 `send_order` 相同。
+    pub fn send_strategy_order(
+        &mut self,
+        acc: &str,
+        strategy_id: StrategyId,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<OrderId, MarketError> {
+        self.submit_new_order(
+            parse_account(acc),
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+            None,
+            strategy_id,
+        )
+    }
+
+    /// 为已登记的母单发送一个子单，并返回子单的订单 ID。
+    ///
+    /// # 参数
+    /// - `parent_id`: 母单 ID，需要先通过 `register_parent` 登记。
+    /// - 其余参数与 `send_order` 相同。
+    ///
+    /// # 返回值
+    /// - `Ok(OrderId)`: 如果操作成功，返回新创建的子单 ID。
+    ///
+    /// # 错误
+    /// - `InvalidOrderRequest`: 订单时间、价格或数量不合法，或子单与母单方向不一致、母单已撤销。
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - `ParentOrderNotExist`: 如果母单没有登记。
+    /// - `ParentQtyExceeded`: 如果所有子单数量之和将超过母单数量。
+    pub fn send_child_order(
+        &mut self,
+        acc: &str,
+        stock_code: &str,
+        parent_id: OrderId,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<OrderId, MarketError> {
+        self.submit_new_order(
+            parse_account(acc),
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+            Some(parent_id),
+            0,
+        )
+    }
+
+    fn submit_new_order(
+        &mut self,
+        account: Option<&str>,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+        parent_id: Option<OrderId>,
+        strategy_id: StrategyId,
+    ) -> Result<OrderId, MarketError> {
+        let account = account.map(str::to_string);
+        // 已熔断的账户不能再下新单
+        if let Some(breaker) = account
+            .as_ref()
+            .and_then(|account| self.circuit_breakers.get(account))
+        {
+            if breaker.is_halted() {
+                return Err(MarketError::AccountHalted);
+            }
+        }
+
+        let result = self.place_new_order(
+            account.clone(),
+            stock_code,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+            parent_id,
+            strategy_id,
+        );
+        if let Some(account) = account {
+            self.record_order_result(&account, result.is_ok());
+        }
+        result
+    }
+
+    fn place_new_order(
+        &mut self,
+        account: Option<String>,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+        parent_id: Option<OrderId>,
+        strategy_id: StrategyId,
+    ) -> Result<OrderId, MarketError> {
+        // 生成新的订单 ID
+        let order_id = self.generate_order_num();
+        let order = Self::new_user_order(
+            account,
+            stock_code,
+            order_id,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+        )?;
+        order.borrow_mut().parent_id = parent_id;
+        order.borrow_mut().strategy_id = strategy_id;
+        if let Some(worker) = self.workers.get(stock_code) {
+            // 订单刚刚创建，没有其他引用，取出订单数据发送到回放线程上提交
+            let order = Rc::try_unwrap(order)
+                .map_err(|_| MarketError::InvalidOrderRequest)?
+                .into_inner();
+            worker.call(move |broker| broker.submit_order(Rc::new(RefCell::new(order))))??;
+            return Ok(order_id);
+        }
+        // 获取经纪商
+        let broker = match self.broker_map.get_mut(stock_code) {
+            Some(broker) => broker,
+            None => return Err(MarketError::StockBrokerNotExist),
+        };
+        // 提交订单
+        match broker.submit_order(order) {
+            Ok(_) => Ok(order_id),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// 发送一组关联订单，所有腿要么同时进入市场，要么都不进入。
+    ///
+    /// 所有腿先按 `validate_order` 的规则逐一检查，全部通过后才会生成订单 ID 并提交；
+    /// 各腿的订单时间统一为所有腿的订单时间和相关经纪商当前时间中最晚的一个，
+    /// 保证它们在同一时刻进入各自的经纪商。提交过程中任意一条腿失败时，
+    /// 已经提交的腿会从经纪商中撤回，订单 ID 也会回退。
+    ///
+    /// `LinkMode::OcoCancel` 还会登记这组订单，之后推进时间时任意一条腿全部成交，
+    /// 其余仍在交易中的腿会被撤销，撤单记录在 `link_cancels` 中。
+    ///
+    /// # 参数
+    /// - `legs`: 各条腿的下单参数。
+    /// - `link_mode`: 腿之间的联动方式。
     ///
     /// # 返回值
-    /// - `Ok(OrderId)`: 如果操作成功，返回新创建的订单 ID。
-    /// - `Err(MarketError)`: 如果操作失败，返回错误。可能的错误包括订单时间无效或经纪商不存在。
+    /// 按 `legs` 顺序排列的订单 ID。
     ///
     /// # 错误
-    /// - `InvalidOrderRequest`: 如果订单时间不是 17 位整数。
-    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
-    pub fn send_order(
+    /// - `InvalidOrderRequest`: `legs` 为空，或者某条腿的订单时间、价格或数量不合法。
+    /// - `AccountHalted`: 某条腿的账户已被熔断。
+    /// - `StockBrokerNotExist`: 某条腿的股票代码没有对应的经纪商。
+    /// - 其余错误参见 `Broker::validate_order` 和 `Broker::submit_order`。
+    pub fn send_linked_orders(
         &mut self,
-        acc: &str,
+        legs: Vec<NewOrderRequest>,
+        link_mode: LinkMode,
+    ) -> Result<Vec<OrderId>, MarketError> {
+        if legs.is_empty() {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        let mut activation_time = 0;
+        for leg in legs.iter() {
+            let broker = self
+                .broker_map
+                .get(&leg.stock_code)
+                .ok_or(MarketError::StockBrokerNotExist)?;
+            activation_time = cmp::max(activation_time, cmp::max(leg.order_time, broker.timestamp));
+        }
+
+        // 先检查所有的腿，任何一条不通过时不生成订单 ID
+        for (i, leg) in legs.iter().enumerate() {
+            let order_id = self.latest_order_id + 1 + i as i64;
+            if let Err(err) = self.validate_linked_leg(leg, order_id, activation_time) {
+                if let Some(account) = leg.account.as_ref() {
+                    self.record_order_result(account, false);
+                }
+                return Err(err);
+            }
+        }
+
+        let latest_order_id = self.latest_order_id;
+        let mut placed: Vec<(String, OrderId)> = Vec::with_capacity(legs.len());
+        for leg in legs.iter() {
+            let order_id = self.generate_order_num();
+            let result = Self::new_user_order(
+                leg.account.clone(),
+                &leg.stock_code,
+                order_id,
+                activation_time,
+                leg.order_price,
+                leg.order_volume,
+                &leg.bs_flag,
+                leg.order_type,
+            )
+            .and_then(|order| {
+                self.broker_map
+                    .get_mut(&leg.stock_code)
+                    .ok_or(MarketError::StockBrokerNotExist)?
+                    .submit_order(order)
+            });
+            if let Err(err) = result {
+                // 撤回已经提交的腿
+                for (stock_code, order_id) in placed {
+                    if let Some(broker) = self.broker_map.get_mut(&stock_code) {
+                        let _ = broker.withdraw_order(order_id);
+                    }
+                }
+                self.latest_order_id = latest_order_id;
+                if let Some(account) = leg.account.as_ref() {
+                    self.record_order_result(account, false);
+                }
+                return Err(err);
+            }
+            placed.push((leg.stock_code.clone(), order_id));
+        }
+
+        for leg in legs.iter() {
+            if let Some(account) = leg.account.as_ref() {
+                self.record_order_result(account, true);
+            }
+        }
+        let order_ids = placed.iter().map(|(_, order_id)| *order_id).collect();
+        if link_mode == LinkMode::OcoCancel {
+            self.link_groups.push(LinkGroup::new(placed));
+        }
+        Ok(order_ids)
+    }
+
+    /// 按 `order_id` 和统一的订单时间检查关联订单中的一条腿能否被接受。
+    fn validate_linked_leg(
+        &self,
+        leg: &NewOrderRequest,
+        order_id: OrderId,
+        order_time: i64,
+    ) -> Result<(), MarketError> {
+        if let Some(breaker) = leg
+            .account
+            .as_ref()
+            .and_then(|account| self.circuit_breakers.get(account))
+        {
+            if breaker.is_halted() {
+                return Err(MarketError::AccountHalted);
+            }
+        }
+        let order = Self::new_user_order(
+            leg.account.clone(),
+            &leg.stock_code,
+            order_id,
+            order_time,
+            leg.order_price,
+            leg.order_volume,
+            &leg.bs_flag,
+            leg.order_type,
+        )?;
+        self.get_broker(&leg.stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?
+            .validate_order(&order)
+    }
+
+    /// 创建一个用户订单，订单时间需要是 17 位整数。
+    fn new_user_order(
+        account: Option<String>,
         stock_code: &str,
+        order_id: OrderId,
         order_time: i64,
         order_price: f64,
         order_volume: i64,
         bs_flag: &str,
         order_type: Option<OrderType>,
-    ) -> Result<OrderId, MarketError> {
-        // 生成新的订单 ID
-        let order_id = self.generate_order_num();
-
+    ) -> Result<OrderRef, MarketError> {
         // 验证订单时间是否符合 17 位长度
         let order_time_str = order_time.to_string();
         if order_time_str.len() != 17 {
             return Err(MarketError::InvalidOrderRequest); // 使用自定义错误处理
         }
-        // 获取经纪商
-        let broker = match self.broker_map.get_mut(stock_code) {
-            Some(broker) => broker,
-            None => return Err(MarketError::StockBrokerNotExist),
-        };
-        let account = match acc.to_lowercase().as_str() {
-            "none" => None,
-            _ => Some(acc.to_string()),
-        };
         // 创建订单
         let order_type = order_type.unwrap_or(OrderType::L); // 默认订单类型
-        let order = Order::new_ref(
+        let order = Order::try_new_ref(
             account,
             stock_code.to_string(),
             order_time,
@@ -541,44 +1892,523 @@ where
             bs_flag,
             order_type,
             OrderSourceType::UserOrder,
-        );
-
+        )?;
         order.borrow_mut().order_id = order_id;
-        // 提交订单
-        match broker.submit_order(order) {
-            Ok(_) => Ok(order_id),
-            Err(err) => Err(err),
+        Ok(order)
+    }
+
+    /// 检查 `send_order` 发送的订单能否被接受，不生成订单 ID，也不会把订单加入队列。
+    ///
+    /// 参数与 `send_order` 相同，订单按下一个将要生成的订单 ID 检查。
+    ///
+    /// # 错误
+    /// - `AccountHalted`: 账户已被熔断。
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - 其余错误参见 `Broker::validate_order`。
+    pub fn validate_order(
+        &self,
+        acc: &str,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Result<(), MarketError> {
+        let account = parse_account(acc).map(str::to_string);
+        if let Some(breaker) = account
+            .as_ref()
+            .and_then(|account| self.circuit_breakers.get(account))
+        {
+            if breaker.is_halted() {
+                return Err(MarketError::AccountHalted);
+            }
         }
+        let order = Self::new_user_order(
+            account,
+            stock_code,
+            self.latest_order_id + 1,
+            order_time,
+            order_price,
+            order_volume,
+            bs_flag,
+            order_type,
+        )?;
+        self.get_broker(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?
+            .validate_order(&order)
     }
 
     /// 取消指定股票的订单。
     ///
+    /// 设置了撤单延迟时，撤单在延迟后才生效，参见 `Broker::request_cancel`。
+    ///
     /// # 参数
     /// - `stock_code`: 要取消订单的股票代码。
     /// - `order_id`: 要取消的订单 ID。
     ///
     /// # 返回值
-    /// 返回 `Ok(true)` 表示订单已成功取消。
+    /// 返回 `Ok(true)` 表示撤单已发出。
     ///
     /// # 错误
-    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    /// - 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    /// - 如果处于报单通道中断窗口内，返回 `MarketError::GatewayUnavailable`。
+    /// - 如果当前时段不接受撤单（深圳市场的收盘集合竞价），返回 `MarketError::OrderTypeUnsupported`。
+    /// - 如果订单尚未得到确认且设置为拒绝撤单，返回 `MarketError::OrderRequestInProcess`。
     pub fn cancel_order(&mut self, stock_code: &str, order_id: i64) -> Result<bool, MarketError> {
         let broker = match self.broker_map.get_mut(stock_code) {
             Some(broker) => broker,
             None => return Err(MarketError::StockBrokerNotExist),
         };
 
-        broker.cancel_order(order_id);
+        match broker.request_cancel(order_id) {
+            Err(MarketError::GatewayUnavailable) => return Err(MarketError::GatewayUnavailable),
+            Err(MarketError::OrderTypeUnsupported) => {
+                return Err(MarketError::OrderTypeUnsupported)
+            }
+            Err(MarketError::OrderRequestInProcess) => {
+                return Err(MarketError::OrderRequestInProcess)
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// 订阅指定股票回放过程中发布的状态，需要先调用 `Broker::enable_state_publishing`。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    ///
+    /// # 返回值
+    /// 返回订阅端，可以发送到其他线程读取，参见 `Broker::subscribe_state`。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn subscribe_state(&mut self, stock_code: &str) -> Result<StateReceiver, MarketError> {
+        Ok(self
+            .get_broker_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?
+            .subscribe_state())
+    }
+
+    /// 修改指定股票的挂单的价格和数量，参见 `Broker::modify_order`。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `order_id`: 要修改的订单 ID。
+    /// - `price`: 修改后的价格。
+    /// - `qty`: 修改后订单的总数量，包含已经成交的部分。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - `InvalidOrderRequest`: 修改后的数量不大于已成交的数量。
+    /// - 其他错误来自 `Broker::modify_order`。
+    pub fn modify_order(
+        &mut self,
+        stock_code: &str,
+        order_id: OrderId,
+        price: f64,
+        qty: f64,
+    ) -> Result<bool, MarketError> {
+        self.get_broker_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?
+            .modify_order(order_id, price, qty)?;
+        Ok(true)
+    }
+
+    /// 带幂等键取消订单，重试同一个请求时不会重复撤单。
+    ///
+    /// # 参数
+    /// - `key`: 调用方生成的幂等键。
+    /// - `stock_code`: 要取消订单的股票代码。
+    /// - `order_id`: 要取消的订单 ID。
+    ///
+    /// # 错误
+    /// - `IdempotencyConflict`: 键已经使用过但参数不一致。
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn cancel_order_idempotent(
+        &mut self,
+        key: &str,
+        stock_code: &str,
+        order_id: OrderId,
+    ) -> Result<bool, MarketError> {
+        let request = IdempotentRequest::Cancel {
+            stock_code: stock_code.to_string(),
+            order_id: order_id,
+        };
+        if self.idempotency.lookup(key, &request)?.is_some() {
+            return Ok(true);
+        }
+        self.cancel_order(stock_code, order_id)?;
+        self.idempotency.insert(key, request, order_id);
+        Ok(true)
+    }
+
+    /// 登记一个母单，之后可以通过 `send_child_order` 为其发送子单。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `parent_id`: 母单 ID，由调用方指定，在同一股票下唯一。
+    /// - `qty`: 母单的总数量。
+    /// - `side`: 母单的买卖方向。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - `OrderIdExist`: 如果母单 ID 已经登记。
+    /// - `InvalidOrderRequest`: 如果数量或方向不合法。
+    pub fn register_parent(
+        &mut self,
+        stock_code: &str,
+        parent_id: OrderId,
+        qty: f64,
+        side: Side,
+    ) -> Result<bool, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.register_parent(parent_id, qty, side)?;
         Ok(true)
     }
 
+    /// 汇总指定母单下所有子单的成交情况。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - `ParentOrderNotExist`: 如果母单没有登记。
+    pub fn parent_summary(
+        &self,
+        stock_code: &str,
+        parent_id: OrderId,
+    ) -> Result<ParentSummary, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.parent_summary(parent_id)
+    }
+
+    /// 汇总指定股票到目前为止的回放结果。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn replay_summary(&self, stock_code: &str) -> Result<ReplaySummary, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        Ok(broker.replay_summary())
+    }
+
+    /// 按策略编号汇总指定股票上用户订单的成交情况，参见 `Broker::strategy_summary`。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn strategy_summary(
+        &self,
+        stock_code: &str,
+    ) -> Result<BTreeMap<StrategyId, StrategySummary>, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        Ok(broker.strategy_summary())
+    }
+
+    /// 返回指定股票上某个策略的成交回报，参见 `Broker::strategy_fills`。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    pub fn strategy_fills(
+        &self,
+        stock_code: &str,
+        strategy_id: StrategyId,
+    ) -> Result<Vec<UserFill>, MarketError> {
+        let broker = self
+            .broker_map
+            .get(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        Ok(broker
+            .strategy_fills(strategy_id)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// 汇总所有股票上用户订单的处置结果，参见 `Broker::disposition_report`。
+    pub fn disposition_report(&self) -> DispositionReport {
+        let mut report = DispositionReport::default();
+        for broker in self.broker_map.values() {
+            report.merge(&broker.disposition_report());
+        }
+        report
+    }
+
+    /// 撤销母单及其所有仍可能成交的子单，参见 [`Broker::cancel_parent`]。
+    ///
+    /// # 返回值
+    /// 撤单被接受的子单 ID。
+    ///
+    /// # 错误
+    /// - `StockBrokerNotExist`: 如果给定股票代码的经纪商不存在。
+    /// - `ParentOrderNotExist`: 如果母单没有登记。
+    pub fn cancel_parent(
+        &mut self,
+        stock_code: &str,
+        parent_id: OrderId,
+    ) -> Result<Vec<OrderId>, MarketError> {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.cancel_parent(parent_id)
+    }
+
+    /// 批量撤销满足过滤条件的订单，参见 [`Broker::cancel_all`]。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码，为 `None` 时撤销所有股票的订单。
+    /// - `filter`: 撤单的过滤条件。
+    ///
+    /// # 返回值
+    /// 每个满足过滤条件的订单 ID 及其撤单结果。
+    ///
+    /// # 错误
+    /// 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    pub fn cancel_all(
+        &mut self,
+        stock_code: Option<&str>,
+        filter: CancelFilter,
+    ) -> Result<Vec<(OrderId, Result<(), MarketError>)>, MarketError> {
+        let mut results = Vec::new();
+        if stock_code.is_none() {
+            for (_, broker) in self.broker_map.iter_mut() {
+                results.extend(broker.cancel_all(filter.clone()));
+            }
+        } else {
+            let broker = self
+                .broker_map
+                .get_mut(stock_code.unwrap())
+                .ok_or(MarketError::StockBrokerNotExist)?;
+            results.extend(broker.cancel_all(filter));
+        }
+        Ok(results)
+    }
+
+    /// 访问指定股票订单上的自定义数据，参见 [`Broker::with_order_mut`]。
+    ///
+    /// # 错误
+    /// - 如果提供的 `stock_code` 在 `broker_map` 中找不到对应的券商，返回 `MarketError::StockBrokerNotExist`。
+    /// - 订单不存在时返回 `MarketError::OrderNotFound`。
+    pub fn with_order_mut<F, R>(
+        &mut self,
+        stock_code: &str,
+        order_id: OrderId,
+        f: F,
+    ) -> Result<R, MarketError>
+    where
+        F: FnOnce(&mut OrderUserData) -> R,
+    {
+        let broker = self
+            .broker_map
+            .get_mut(stock_code)
+            .ok_or(MarketError::StockBrokerNotExist)?;
+        broker.with_order_mut(order_id, f)
+    }
+
+    /// 为账户设置风控熔断器，已有的熔断器会被替换。
+    ///
+    /// # 参数
+    /// - `account`: 账户名称，与 `send_order` 的 `acc` 参数一致。
+    /// - `config`: 熔断阈值。
+    pub fn set_circuit_breaker(&mut self, account: &str, config: CircuitBreakerConfig) {
+        self.circuit_breakers
+            .insert(account.to_string(), CircuitBreaker::new(config));
+    }
+
+    /// 手动恢复已熔断账户的交易。
+    ///
+    /// # 错误
+    /// 如果账户没有设置熔断器，返回 `MarketError::CircuitBreakerNotExist`。
+    pub fn reset_circuit_breaker(&mut self, account: &str) -> Result<(), MarketError> {
+        let breaker = self
+            .circuit_breakers
+            .get_mut(account)
+            .ok_or(MarketError::CircuitBreakerNotExist)?;
+        if breaker.is_halted() {
+            breaker.reset();
+            self.account_events.push(AccountEvent {
+                account: account.to_string(),
+                timestamp: self.timestamp,
+                state: BreakerState::Active,
+            });
+        }
+        Ok(())
+    }
+
+    /// 查询账户的熔断状态以及距离各项阈值的余量。
+    ///
+    /// # 错误
+    /// 如果账户没有设置熔断器，返回 `MarketError::CircuitBreakerNotExist`。
+    pub fn circuit_breaker_status(&self, account: &str) -> Result<BreakerStatus, MarketError> {
+        self.circuit_breakers
+            .get(account)
+            .map(|breaker| breaker.status())
+            .ok_or(MarketError::CircuitBreakerNotExist)
+    }
+
+    /// 返回账户熔断和恢复交易的事件。
+    pub fn account_events(&self) -> &[AccountEvent] {
+        &self.account_events
+    }
+
+    /// 记录下单结果，连续被拒绝的订单过多时熔断。
+    fn record_order_result(&mut self, account: &str, accepted: bool) {
+        let reason = match self.circuit_breakers.get_mut(account) {
+            Some(breaker) => {
+                if accepted {
+                    breaker.record_accept();
+                } else {
+                    breaker.record_reject();
+                }
+                breaker.check()
+            }
+            None => return,
+        };
+        if let Some(reason) = reason {
+            self.halt_account(account, reason);
+        }
+    }
+
+    /// 检查 `LinkMode::OcoCancel` 登记的关联订单，某条腿全部成交后撤销同组其余的腿。
+    ///
+    /// 撤单因报单通道中断或当前时段不接受撤单而失败时，该组保持未触发状态，下次推进时间时重试。
+    fn update_linked_orders(&mut self) {
+        let mut cancels = Vec::new();
+        for group in self.link_groups.iter_mut().filter(|group| !group.triggered) {
+            let filled = group.legs.iter().find(|(stock_code, order_id)| {
+                self.broker_map
+                    .get(stock_code)
+                    .and_then(|broker| broker.orders.as_ref())
+                    .and_then(|orders| orders.get(order_id))
+                    .is_some_and(|order| order.borrow().status == OrderStatus::Filled)
+            });
+            let filled_order_id = match filled {
+                Some((_, order_id)) => *order_id,
+                None => continue,
+            };
+            let mut triggered = true;
+            for (stock_code, order_id) in group.legs.iter() {
+                if *order_id == filled_order_id {
+                    continue;
+                }
+                let broker = match self.broker_map.get_mut(stock_code) {
+                    Some(broker) => broker,
+                    None => continue,
+                };
+                let active = broker
+                    .orders
+                    .as_ref()
+                    .and_then(|orders| orders.get(order_id))
+                    .is_some_and(|order| !order.borrow().is_terminal());
+                let requested = self
+                    .link_cancels
+                    .iter()
+                    .any(|cancel| cancel.order_id == *order_id);
+                if !active || requested {
+                    continue;
+                }
+                match broker.request_cancel(*order_id) {
+                    Ok(_) => {
+                        broker.sync_order_info();
+                        cancels.push(LinkCancel {
+                            stock_code: stock_code.clone(),
+                            order_id: *order_id,
+                            timestamp: broker.timestamp,
+                            reason: LinkCancelReason::SiblingFilled(filled_order_id),
+                        });
+                    }
+                    Err(MarketError::GatewayUnavailable | MarketError::OrderTypeUnsupported) => {
+                        triggered = false
+                    }
+                    Err(_) => {}
+                }
+            }
+            group.triggered = triggered;
+        }
+        self.link_cancels.extend(cancels);
+    }
+
+    /// 根据各经纪商的成交回报和最新成交价更新账户盈亏，并检查熔断阈值。
+    fn update_circuit_breakers(&mut self) {
+        if self.circuit_breakers.is_empty() {
+            return;
+        }
+        for (stock_code, broker) in self.broker_map.iter() {
+            let mark_price = broker.market_depth.last_price(&OrderSourceType::UserOrder);
+            for (account, breaker) in self.circuit_breakers.iter_mut() {
+                breaker.on_fills(account, stock_code, &broker.user_fills);
+                breaker.set_mark_price(stock_code, mark_price);
+            }
+        }
+
+        let halted: Vec<(String, HaltReason)> = self
+            .circuit_breakers
+            .iter_mut()
+            .filter_map(|(account, breaker)| {
+                breaker.check().map(|reason| (account.clone(), reason))
+            })
+            .collect();
+        for (account, reason) in halted {
+            self.halt_account(&account, reason);
+        }
+    }
+
+    /// 记录熔断事件，并按配置撤销账户所有仍在交易中的订单。
+    fn halt_account(&mut self, account: &str, reason: HaltReason) {
+        self.account_events.push(AccountEvent {
+            account: account.to_string(),
+            timestamp: self.timestamp,
+            state: BreakerState::Halted(reason),
+        });
+        let cancel_on_halt = self
+            .circuit_breakers
+            .get(account)
+            .map_or(false, |breaker| breaker.config().cancel_on_halt);
+        if cancel_on_halt {
+            for broker in self.broker_map.values_mut() {
+                broker.cancel_account_orders(account);
+            }
+        }
+    }
+
     pub fn snapshot(&self, stock_code: &str) -> String {
         if let Some(broker) = self.broker_map.get(&stock_code.to_string()) {
-            serde_json::to_string(broker).unwrap_or("{}".to_string())
+            broker.snapshot()
         } else {
             String::new()
         }
     }
+
+    /// 将指定股票的经纪人快照以流式方式写入文件。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `path`: 快照文件路径，文件已存在时会被覆盖。
+    ///
+    /// # 错误
+    /// - 如果提供的 `stock_code` 找不到对应的券商，返回 `SnapshotError::StockBrokerNotExist`。
+    /// - 创建文件、序列化或写入失败时返回相应的 `SnapshotError`。
+    pub fn snapshot_to_file<P: AsRef<Path>>(
+        &self,
+        stock_code: &str,
+        path: P,
+    ) -> Result<(), SnapshotError> {
+        match self.broker_map.get(stock_code) {
+            Some(broker) => broker.snapshot_to_file(path),
+            None => Err(SnapshotError::StockBrokerNotExist),
+        }
+    }
 }
 
 impl<'a, MD> RecoverOp for Exchange<MD>
@@ -594,12 +2424,24 @@ where
     }
 }
 
+/// 解析 `send_order` 等接口传入的账户名称，`"none"`（不区分大小写）表示没有账户。
+///
+/// 仅为兼容旧的调用方式保留，新代码应直接传入 `Option<&str>`。
+fn parse_account(acc: &str) -> Option<&str> {
+    match acc.to_lowercase().as_str() {
+        "none" => None,
+        _ => Some(acc),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::skiplist_orderbook::SkipListMarketDepth;
 
-    use super::utils::time_difference_ms_i64;
+    use super::statistics::StatisticsInfo;
     use super::*;
+    use std::any::Any;
+    use std::collections::VecDeque;
 
     #[test]
     /// 测试 Exchange 的创建。
@@ -612,6 +2454,28 @@ mod tests {
         assert_eq!(exchange.latest_order_id, 0);
     }
 
+    #[test]
+    fn test_exchange_try_new() {
+        let err = Exchange::<SkipListMarketDepth>::try_new("bcaktest", "2023/01/01")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            MarketError::UnknownExchangeMode("bcaktest".to_string())
+        );
+        let message = err.to_string();
+        assert!(message.contains("\"bcaktest\""), "{message}");
+        assert!(message.contains("backtest, live"), "{message}");
+
+        for (mode, expected) in [
+            ("backtest", ExchangeMode::Backtest),
+            ("Live", ExchangeMode::Live),
+        ] {
+            let exchange = Exchange::<SkipListMarketDepth>::try_new(mode, "2023/01/01").unwrap();
+            assert_eq!(exchange.mode, expected);
+        }
+    }
+
     #[test]
     /// 测试成功添加经纪商。
     /// 验证添加经纪商后，`broker_map` 是否包含指定的股票代码。
@@ -641,77 +2505,832 @@ mod tests {
             100.0,
         );
 
-        assert!(result.is_err());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_add_broker_error_already_exists() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let _ = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        let result = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    /// 测试成功发送订单。
+    /// 验证订单发送后，返回的订单 ID 是否有效。
+    fn test_send_order_success() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let _ = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        let result = exchange.send_order(
+            "none",
+            "AAPL",
+            20230101123456789, // 17 位时间戳
+            150.0,
+            10,
+            "buy",
+            None,
+        );
+        assert!(result.is_ok());
+        let order_id = result.unwrap();
+        assert!(order_id > 0);
+    }
+
+    #[test]
+    /// 测试发送订单时订单时间无效的错误。
+    /// 验证如果订单时间不是 17 位整数，会返回 `InvalidOrderRequest` 错误。
+    fn test_send_order_error_invalid_order_time() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let _ = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        let result = exchange.send_order(
+            "none",
+            "AAPL",
+            2023010112345678, // 无效的订单时间
+            150.0,
+            10,
+            "buy",
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    /// 测试发送价格或数量不合法的订单。
+    /// 验证限价订单价格小于等于 0 或数量小于等于 0 时返回 `InvalidOrderRequest` 错误，市价订单忽略价格。
+    fn test_send_order_error_invalid_price_qty() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let _ = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        let result = exchange.send_order("none", "AAPL", 20230101123456789, 0.0, 10, "buy", None);
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        let result = exchange.send_order("none", "AAPL", 20230101123456789, -1.0, 10, "buy", None);
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        let result = exchange.send_order("none", "AAPL", 20230101123456789, 150.0, 0, "buy", None);
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        let result = exchange.send_order(
+            "none",
+            "AAPL",
+            20230101123456789,
+            0.0,
+            10,
+            "buy",
+            Some(OrderType::C),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_brokers() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/12/01");
+        for stock_code in ["600000.SH", "600036.SH"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+        }
+
+        // 在每只股票的订单簿中挂一笔买单
+        for (idx, (_, broker)) in exchange.brokers_mut().enumerate() {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                idx as OrderId + 1,
+                Side::Buy,
+                1000,
+                2,
+                20231201100000000,
+                OrderType::L,
+            );
+            broker.market_depth.add(order_ref).unwrap();
+        }
+
+        let mut stock_codes: Vec<&String> = exchange.brokers().map(|(code, _)| code).collect();
+        stock_codes.sort();
+        assert_eq!(stock_codes, ["600000.SH", "600036.SH"]);
+        let total_bid_order: i64 = exchange
+            .brokers()
+            .map(|(_, broker)| broker.market_depth.get_statistics().total_bid_order)
+            .sum();
+        assert_eq!(total_bid_order, 2);
+    }
+
+    #[test]
+    /// 测试获取所有经纪商的当前时间戳。
+    /// 验证各经纪商推进到不同时间后，返回的映射分别反映各自的时间。
+    fn test_current_time_all() {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        assert!(exchange.current_time_all().is_empty());
+        for stock_code in ["600000.SH", "600036.SH"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Backtest,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            let history = SyntheticFlow::new(FlowConfig::default())
+                .to_history(stock_code, 20231201093000000, 100, 100, 0.01, 100.0)
+                .unwrap();
+            exchange.add_data(stock_code, history).unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(20231201093000000);
+        }
+
+        exchange.elapse(1000, Some("600000.SH")).unwrap();
+        exchange.elapse(3000, Some("600036.SH")).unwrap();
+        let times = exchange.current_time_all();
+        assert_eq!(times.len(), 2);
+        assert_eq!(times["600000.SH"], 20231201093001000);
+        assert_eq!(times["600036.SH"], 20231201093003000);
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let _ = exchange.add_broker(
+            MarketType::SH,
+            ExchangeMode::Live,
+            "stock".to_string(),
+            "AAPL".to_string(),
+            100.0,
+        );
+        let result =
+            exchange.validate_order("none", "AAPL", 20230101123456789, 150.005, 10, "buy", None);
+        assert_eq!(result, Err(MarketError::PriceNotOnTick));
+        let result =
+            exchange.validate_order("none", "AAPL", 20230101123456789, 150.0, 10, "buy", None);
+        assert_eq!(result, Ok(()));
+        let result =
+            exchange.validate_order("none", "MSFT", 20230101123456789, 150.0, 10, "buy", None);
+        assert_eq!(result, Err(MarketError::StockBrokerNotExist));
+        // 检查不消耗订单 ID，也不会把订单加入队列
+        assert_eq!(exchange.latest_order_id, 0);
+        assert!(exchange
+            .get_broker("AAPL")
+            .unwrap()
+            .orders()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    /// 测试母单和子单。
+    /// 验证三个子单部分成交后母单汇总正确，超出母单数量的子单被拒绝，撤销母单会撤销未完成的子单。
+    fn test_parent_child_orders() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/12/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Live,
+                "stock".to_string(),
+                "AAPL".to_string(),
+                100.0,
+            )
+            .unwrap();
+        let timestamp = 20231201093021355;
+        let broker = exchange.get_broker_mut("AAPL").unwrap();
+        broker.set_current_time(timestamp);
+        // 开盘集合竞价已经完成，连续竞价按挂单的价格成交
+        broker.market_depth.market_statistics.open_tick = 15000;
+
+        let parent_id = 1000;
+        exchange
+            .register_parent("AAPL", parent_id, 900.0, Side::Buy)
+            .unwrap();
+        assert_eq!(
+            exchange.register_parent("AAPL", parent_id, 900.0, Side::Buy),
+            Err(MarketError::OrderIdExist)
+        );
+
+        // 对手方卖单先挂在 149.0，只能部分满足三个子单，子单以卖单的价格成交
+        exchange
+            .send_order("none", "AAPL", timestamp, 149.0, 700, "sell", None)
+            .unwrap();
+        let mut children = Vec::new();
+        for _ in 0..3 {
+            let child_id = exchange
+                .send_child_order(
+                    "none", "AAPL", parent_id, timestamp, 150.0, 300, "buy", None,
+                )
+                .unwrap();
+            children.push(child_id);
+        }
+        // 与母单方向不一致的子单被拒绝
+        assert_eq!(
+            exchange
+                .send_child_order("none", "AAPL", parent_id, timestamp, 150.0, 100, "sell", None),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            exchange.send_child_order("none", "AAPL", 1, timestamp, 150.0, 100, "buy", None),
+            Err(MarketError::ParentOrderNotExist)
+        );
+
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+
+        let summary = exchange.parent_summary("AAPL", parent_id).unwrap();
+        assert_eq!(summary.total_qty, 900.0);
+        assert_eq!(summary.filled_qty, 700.0);
+        assert!((summary.avg_price - 149.0).abs() < 1e-9);
+        assert_eq!(summary.children, children);
+        assert_eq!(summary.open_children, 1);
+
+        // 第四个子单超过母单剩余数量
+        assert_eq!(
+            exchange
+                .send_child_order("none", "AAPL", parent_id, timestamp, 150.0, 100, "buy", None),
+            Err(MarketError::ParentQtyExceeded)
+        );
+
+        // 撤销母单会撤销所有未完成的子单
+        let canceled = exchange.cancel_parent("AAPL", parent_id).unwrap();
+        assert_eq!(canceled, vec![children[2]]);
+        let summary = exchange.parent_summary("AAPL", parent_id).unwrap();
+        assert_eq!(summary.open_children, 0);
+        assert_eq!(summary.filled_qty, 700.0);
+        let broker = exchange.get_broker("AAPL").unwrap();
+        let child = broker.orders().unwrap()[&children[2]].clone();
+        assert_eq!(child.borrow().status, OrderStatus::Canceled);
+        assert_eq!(child.borrow().parent_id, Some(parent_id));
+    }
+
+    #[test]
+    /// 测试账户风控熔断。
+    /// 验证成交导致亏损超过上限后账户被熔断、挂单被撤销、新订单被拒绝，手动恢复后可以继续下单。
+    fn test_circuit_breaker_halts_account() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Live,
+                "stock".to_string(),
+                "AAPL".to_string(),
+                100.0,
+            )
+            .unwrap();
+        let timestamp = 20231201093021355;
+        exchange
+            .get_broker_mut("AAPL")
+            .unwrap()
+            .set_current_time(timestamp);
+        exchange.set_circuit_breaker(
+            "strat",
+            CircuitBreakerConfig {
+                max_loss: Some(500.0),
+                cancel_on_halt: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            exchange.reset_circuit_breaker("other"),
+            Err(MarketError::CircuitBreakerNotExist)
+        );
+
+        // 以 10.0 买入 1000 股
+        exchange
+            .send_order("mm", "AAPL", timestamp, 10.0, 1000, "sell", None)
+            .unwrap();
+        exchange
+            .send_order("strat", "AAPL", timestamp, 10.0, 1000, "buy", None)
+            .unwrap();
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+        let status = exchange.circuit_breaker_status("strat").unwrap();
+        assert_eq!(status.state, BreakerState::Active);
+        assert_eq!(status.position_value, 10000.0);
+        assert_eq!(status.loss_headroom, Some(500.0));
+
+        let working_id = exchange
+            .send_order("strat", "AAPL", timestamp, 8.0, 100, "buy", None)
+            .unwrap();
+
+        // 最新成交价跌到 9.0，亏损 1000 超过上限
+        exchange
+            .send_order("mm", "AAPL", timestamp, 9.0, 100, "buy", None)
+            .unwrap();
+        exchange
+            .send_order("mm", "AAPL", timestamp, 9.0, 100, "sell", None)
+            .unwrap();
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+
+        let status = exchange.circuit_breaker_status("strat").unwrap();
+        assert_eq!(status.state, BreakerState::Halted(HaltReason::LossLimit));
+        assert_eq!(status.pnl, -1000.0);
+        assert_eq!(status.loss_headroom, Some(-500.0));
+        assert_eq!(exchange.account_events().len(), 1);
+        assert_eq!(exchange.account_events()[0].account, "strat");
+        let working = exchange.get_broker("AAPL").unwrap().orders().unwrap()[&working_id].clone();
+        assert_eq!(working.borrow().status, OrderStatus::Canceled);
+
+        assert_eq!(
+            exchange.send_order("strat", "AAPL", timestamp, 9.0, 100, "buy", None),
+            Err(MarketError::AccountHalted)
+        );
+        // 其他账户不受影响
+        assert!(exchange
+            .send_order("mm", "AAPL", timestamp, 8.0, 100, "buy", None)
+            .is_ok());
+
+        exchange.reset_circuit_breaker("strat").unwrap();
+        assert_eq!(
+            exchange.account_events().last().unwrap().state,
+            BreakerState::Active
+        );
+        assert!(exchange
+            .send_order("strat", "AAPL", timestamp, 9.0, 100, "buy", None)
+            .is_ok());
+        assert_eq!(
+            exchange.circuit_breaker_status("strat").unwrap().state,
+            BreakerState::Active
+        );
+    }
+
+    #[test]
+    /// 测试市价订单的成交计入熔断器。
+    /// 验证市价买单按实际成交价计入持仓成本，随后价格下跌触发亏损熔断。
+    fn test_circuit_breaker_market_order_fill() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Live,
+                "stock".to_string(),
+                "AAPL".to_string(),
+                100.0,
+            )
+            .unwrap();
+        let timestamp = 20231201093021355;
+        exchange
+            .get_broker_mut("AAPL")
+            .unwrap()
+            .set_current_time(timestamp);
+        exchange.set_circuit_breaker(
+            "strat",
+            CircuitBreakerConfig {
+                max_loss: Some(500.0),
+                ..Default::default()
+            },
+        );
+
+        exchange
+            .send_order("mm", "AAPL", timestamp, 10.0, 1000, "sell", None)
+            .unwrap();
+        exchange
+            .send_order(
+                "strat",
+                "AAPL",
+                timestamp,
+                0.0,
+                1000,
+                "buy",
+                Some(OrderType::C),
+            )
+            .unwrap();
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+        let fills = &exchange.get_broker("AAPL").unwrap().user_fills;
+        let fill = fills
+            .iter()
+            .find(|fill| fill.account.as_deref() == Some("strat"))
+            .unwrap();
+        assert_eq!(fill.price, 10.0);
+        assert_eq!(fill.qty, 1000.0);
+        let status = exchange.circuit_breaker_status("strat").unwrap();
+        assert_eq!(status.pnl, 0.0);
+        assert_eq!(status.position_value, 10000.0);
+
+        exchange
+            .send_order("mm", "AAPL", timestamp, 9.0, 100, "buy", None)
+            .unwrap();
+        exchange
+            .send_order("mm", "AAPL", timestamp, 9.0, 100, "sell", None)
+            .unwrap();
+        exchange.elapse(1000, Some("AAPL")).unwrap();
+        let status = exchange.circuit_breaker_status("strat").unwrap();
+        assert_eq!(status.pnl, -1000.0);
+        assert_eq!(status.state, BreakerState::Halted(HaltReason::LossLimit));
+    }
+
+    #[test]
+    /// 测试跨股票的批量撤单。
+    /// 验证不指定股票时所有经纪商上满足过滤条件的订单都被撤销。
+    fn test_cancel_all_across_brokers() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for stock_code in ["AAPL", "MSFT"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(timestamp);
+        }
+        let aapl_buy = exchange
+            .send_order("a", "AAPL", timestamp, 10.0, 100, "buy", None)
+            .unwrap();
+        let aapl_sell = exchange
+            .send_order("a", "AAPL", timestamp, 11.0, 100, "sell", None)
+            .unwrap();
+        let msft_buy = exchange
+            .send_order("a", "MSFT", timestamp, 20.0, 100, "buy", None)
+            .unwrap();
+        exchange.elapse(1000, None).unwrap();
+
+        let filter = CancelFilter {
+            side: Some(Side::Buy),
+            ..Default::default()
+        };
+        assert_eq!(
+            exchange.cancel_all(Some("TSLA"), filter.clone()),
+            Err(MarketError::StockBrokerNotExist)
+        );
+        let mut results = exchange.cancel_all(None, filter).unwrap();
+        results.sort_by_key(|(order_id, _)| *order_id);
+        assert_eq!(results, vec![(aapl_buy, Ok(())), (msft_buy, Ok(()))]);
+        let broker = exchange.get_broker("AAPL").unwrap();
+        assert_eq!(
+            broker.orders().unwrap()[&aapl_sell].borrow().status,
+            OrderStatus::New
+        );
+    }
+
+    #[test]
+    /// 测试关联订单的原子进入。
+    /// 验证任意一条腿不合法时其他腿不会留下订单，全部合法时各腿的订单时间相同。
+    fn test_linked_orders_atomic_entry() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for (stock_code, current_time) in [("AAPL", timestamp), ("MSFT", timestamp + 500)] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(current_time);
+        }
+
+        let legs = vec![
+            NewOrderRequest::new(Some("a"), "AAPL", timestamp, 10.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "TSLA", timestamp, 20.0, 100, "sell", None),
+        ];
+        assert_eq!(
+            exchange.send_linked_orders(legs, LinkMode::AtomicEntry),
+            Err(MarketError::StockBrokerNotExist)
+        );
+        let legs = vec![
+            NewOrderRequest::new(Some("a"), "AAPL", timestamp, 10.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "MSFT", timestamp, 20.005, 100, "sell", None),
+        ];
+        assert_eq!(
+            exchange.send_linked_orders(legs, LinkMode::AtomicEntry),
+            Err(MarketError::PriceNotOnTick)
+        );
+        assert_eq!(exchange.latest_order_id, 0);
+        let broker = exchange.get_broker("AAPL").unwrap();
+        assert_eq!(broker.orders.as_ref().map_or(0, |orders| orders.len()), 0);
+        assert!(broker.pending_orders.is_empty());
+        assert!(broker.waiting_orders.is_empty());
+
+        let legs = vec![
+            NewOrderRequest::new(Some("a"), "AAPL", timestamp, 10.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "MSFT", timestamp, 20.0, 100, "sell", None),
+        ];
+        let order_ids = exchange
+            .send_linked_orders(legs, LinkMode::AtomicEntry)
+            .unwrap();
+        assert_eq!(order_ids, vec![1, 2]);
+        // 两条腿都在 MSFT 经纪商的当前时间进入市场
+        for (stock_code, order_id) in [("AAPL", 1), ("MSFT", 2)] {
+            let broker = exchange.get_broker(stock_code).unwrap();
+            assert_eq!(
+                broker.orders().unwrap()[&order_id].borrow().local_time,
+                timestamp + 500
+            );
+        }
+        assert!(exchange.link_groups.is_empty());
+    }
+
+    #[test]
+    /// 测试一条腿全部成交后撤销另一条腿。
+    /// 验证未成交的腿被撤销，撤单原因记录了成交的腿。
+    fn test_linked_orders_oco_cancel() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for stock_code in ["AAPL", "MSFT"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(timestamp);
+        }
+        exchange
+            .send_order("mm", "AAPL", timestamp, 10.0, 100, "sell", None)
+            .unwrap();
+        let legs = vec![
+            NewOrderRequest::new(Some("a"), "AAPL", timestamp, 10.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "MSFT", timestamp, 20.0, 100, "buy", None),
+        ];
+        let order_ids = exchange
+            .send_linked_orders(legs, LinkMode::OcoCancel)
+            .unwrap();
+        exchange.elapse(1000, None).unwrap();
+
+        let leg_a = exchange.get_broker("AAPL").unwrap().orders().unwrap()[&order_ids[0]].clone();
+        let leg_b = exchange.get_broker("MSFT").unwrap().orders().unwrap()[&order_ids[1]].clone();
+        assert_eq!(leg_a.borrow().status, OrderStatus::Filled);
+        assert_eq!(leg_b.borrow().status, OrderStatus::Canceled);
+        assert_eq!(exchange.link_cancels.len(), 1);
+        assert_eq!(exchange.link_cancels[0].order_id, order_ids[1]);
+        assert_eq!(
+            exchange.link_cancels[0].reason,
+            LinkCancelReason::SiblingFilled(order_ids[0])
+        );
+        assert!(exchange.link_groups[0].triggered);
+
+        // 再次推进时间不会重复撤单
+        exchange.elapse(1000, None).unwrap();
+        assert_eq!(exchange.link_cancels.len(), 1);
+
+        // 关联订单组随检查点保存
+        let checkpoint = serde_json::to_string(&exchange).unwrap();
+        let restored: Exchange<SkipListMarketDepth> = serde_json::from_str(&checkpoint).unwrap();
+        assert_eq!(restored.link_groups, exchange.link_groups);
+    }
+
+    #[test]
+    /// 测试批量查询最优价。
+    /// 验证每一行与单只股票的查询结果一致，包括单边和空订单簿的 `NaN`。
+    fn test_bbo_snapshot() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for stock_code in ["MSFT", "AAPL", "TSLA"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(timestamp);
+        }
+        exchange
+            .send_order("a", "AAPL", timestamp, 10.0, 100, "buy", None)
+            .unwrap();
+        exchange
+            .send_order("a", "AAPL", timestamp, 10.5, 100, "sell", None)
+            .unwrap();
+        // MSFT 只有买单，TSLA 的订单簿为空
+        exchange
+            .send_order("a", "MSFT", timestamp, 20.0, 100, "buy", None)
+            .unwrap();
+        exchange.elapse(1000, None).unwrap();
+
+        let same = |a: f64, b: f64| (a.is_nan() && b.is_nan()) || a == b;
+        let source = OrderSourceType::UserOrder;
+        let rows = exchange.bbo_snapshot(&source, None);
+        let codes: Vec<&str> = rows.iter().map(|row| row.stock_code.as_str()).collect();
+        assert_eq!(codes, vec!["AAPL", "MSFT", "TSLA"]);
+        for row in rows.iter() {
+            let stock_code = row.stock_code.as_str();
+            let broker = exchange.get_broker(stock_code).unwrap();
+            assert!(same(
+                row.best_bid,
+                exchange.best_bid(stock_code, &source).unwrap()
+            ));
+            assert!(same(
+                row.best_ask,
+                exchange.best_ask(stock_code, &source).unwrap()
+            ));
+            assert_eq!(
+                row.best_bid_tick,
+                exchange.best_bid_tick(stock_code, &source).unwrap()
+            );
+            assert_eq!(
+                row.best_ask_tick,
+                exchange.best_ask_tick(stock_code, &source).unwrap()
+            );
+            assert!(same(row.last_price, broker.last_price(&source)));
+            assert_eq!(row.timestamp, broker.timestamp);
+        }
+        assert_eq!(rows[0].best_bid, 10.0);
+        assert_eq!(rows[0].best_ask, 10.5);
+        assert!(rows[1].best_ask.is_nan());
+        assert!(rows[2].best_bid.is_nan() && rows[2].best_ask.is_nan());
+
+        // 过滤时忽略未知股票和重复的代码
+        let rows = exchange.bbo_snapshot(&source, Some(&["TSLA", "AAPL", "NVDA", "AAPL"]));
+        let codes: Vec<&str> = rows.iter().map(|row| row.stock_code.as_str()).collect();
+        assert_eq!(codes, vec!["AAPL", "TSLA"]);
     }
 
     #[test]
-    fn test_add_broker_error_already_exists() {
-        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
-        let _ = exchange.add_broker(
-            MarketType::SH,
-            ExchangeMode::Live,
-            "stock".to_string(),
-            "AAPL".to_string(),
-            100.0,
-        );
-        let result = exchange.add_broker(
-            MarketType::SH,
-            ExchangeMode::Live,
-            "stock".to_string(),
-            "AAPL".to_string(),
-            100.0,
-        );
-        assert!(result.is_err());
-    }
+    #[cfg(feature = "data")]
+    /// 测试以 `DataFrame` 导出档位。
+    /// 验证列的类型，以及买方价格从高到低、卖方价格从低到高排列且都为正数。
+    fn test_levels_as_dataframe() {
+        use polars::prelude::DataType;
 
-    #[test]
-    /// 测试成功发送订单。
-    /// 验证订单发送后，返回的订单 ID 是否有效。
-    fn test_send_order_success() {
         let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
-        let _ = exchange.add_broker(
-            MarketType::SH,
-            ExchangeMode::Live,
-            "stock".to_string(),
-            "AAPL".to_string(),
-            100.0,
+        let timestamp = 20231201093021355;
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Live,
+                "stock".to_string(),
+                "AAPL".to_string(),
+                1.0,
+            )
+            .unwrap();
+        exchange
+            .get_broker_mut("AAPL")
+            .unwrap()
+            .set_current_time(timestamp);
+        for (price, volume, side) in [
+            (9.9, 200, "buy"),
+            (10.0, 100, "buy"),
+            (10.0, 300, "buy"),
+            (10.6, 500, "sell"),
+            (10.5, 100, "sell"),
+        ] {
+            exchange
+                .send_order("a", "AAPL", timestamp, price, volume, side, None)
+                .unwrap();
+        }
+        exchange.elapse(1000, None).unwrap();
+
+        let df = exchange.levels_as_dataframe("AAPL", 5).unwrap();
+        assert_eq!(df.height(), 4);
+        let dtypes: Vec<DataType> = df.dtypes();
+        assert_eq!(
+            dtypes,
+            vec![
+                DataType::String,
+                DataType::Int64,
+                DataType::Float64,
+                DataType::Float64,
+                DataType::Int64,
+                DataType::Boolean,
+            ]
         );
-        let result = exchange.send_order(
-            "none",
-            "AAPL",
-            20230101123456789, // 17 位时间戳
-            150.0,
-            10,
-            "buy",
-            None,
+
+        let side: Vec<&str> = df
+            .column("side")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let level_idx: Vec<i64> = df
+            .column("level_idx")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let price: Vec<f64> = df
+            .column("price")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let qty: Vec<f64> = df
+            .column("qty")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let count: Vec<i64> = df
+            .column("count")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(side, vec!["BUY", "BUY", "SELL", "SELL"]);
+        assert_eq!(level_idx, vec![0, 1, 0, 1]);
+        for (actual, expected) in price.iter().zip([10.0, 9.9, 10.5, 10.6]) {
+            assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+        }
+        assert_eq!(qty, vec![400.0, 200.0, 100.0, 500.0]);
+        assert_eq!(count, vec![2, 1, 1, 1]);
+        assert!(df
+            .column("is_shadow")
+            .unwrap()
+            .bool()
+            .unwrap()
+            .into_no_null_iter()
+            .all(|is_shadow| !is_shadow));
+
+        // 与 `levels` 返回的档位一致
+        assert_eq!(exchange.levels("AAPL", 5).unwrap().len(), df.height());
+        assert_eq!(
+            exchange.levels_as_dataframe("NVDA", 5).unwrap_err(),
+            MarketError::StockBrokerNotExist
         );
-        assert!(result.is_ok());
-        let order_id = result.unwrap();
-        assert!(order_id > 0);
     }
 
     #[test]
-    /// 测试发送订单时订单时间无效的错误。
-    /// 验证如果订单时间不是 17 位整数，会返回 `InvalidOrderRequest` 错误。
-    fn test_send_order_error_invalid_order_time() {
+    /// 测试批量设置前收盘价。
+    /// 验证已知股票的前收盘价全部生效，未知股票通过错误返回。
+    fn test_set_prev_close_batch() {
         let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
-        let _ = exchange.add_broker(
-            MarketType::SH,
-            ExchangeMode::Live,
-            "stock".to_string(),
-            "AAPL".to_string(),
-            100.0,
-        );
-        let result = exchange.send_order(
-            "none",
-            "AAPL",
-            2023010112345678, // 无效的订单时间
-            150.0,
-            10,
-            "buy",
-            None,
+        for stock_code in ["AAPL", "MSFT"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+        }
+        let mut closes = HashMap::new();
+        closes.insert("AAPL".to_string(), 10.0);
+        closes.insert("MSFT".to_string(), 20.5);
+        assert_eq!(exchange.set_prev_close_batch(&closes), Ok(2));
+
+        closes.insert("AAPL".to_string(), 11.0);
+        closes.insert("TSLA".to_string(), 30.0);
+        assert_eq!(
+            exchange.set_prev_close_batch(&closes),
+            Err(MarketError::StockBrokersNotExist {
+                applied: 2,
+                stock_codes: vec!["TSLA".to_string()],
+            })
         );
-        assert!(result.is_err());
+        let aapl = exchange.get_broker("AAPL").unwrap();
+        assert_eq!(aapl.previous_close_price, Some(11.0));
+        assert_eq!(aapl.market_depth.previous_close_tick, Some(1100));
+        let msft = exchange.get_broker("MSFT").unwrap();
+        assert_eq!(msft.previous_close_price, Some(20.5));
     }
 
     #[test]
@@ -775,6 +3394,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "data")]
     fn test_elpase() {
         let exchange_mode = "backtest".to_string();
         let stock_code = "688007.SH".to_string();
@@ -792,7 +3412,7 @@ mod tests {
             date.clone(),
             mode.clone(),
         );
-        data.init();
+        data.init().unwrap();
         let _ = exchange.add_broker(
             MarketType::SH,
             ExchangeMode::from_str(&exchange_mode.as_str()).unwrap_or(ExchangeMode::Backtest),
@@ -816,4 +3436,758 @@ mod tests {
         print!("{:?}\n", orders);
         print!("{}\n", exchange.snapshot(stock_code.as_str()));
     }
+
+    #[test]
+    /// 测试带幂等键的下单和撤单。
+    /// 验证重放同一个键返回原订单 ID 且只创建一个订单，参数不一致时返回 `IdempotencyConflict`，
+    /// 幂等键在序列化和恢复后仍然有效。
+    fn test_idempotent_order_entry() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Backtest,
+                "stock".to_string(),
+                "600000.SH".to_string(),
+                100.0,
+            )
+            .unwrap();
+        exchange.get_broker_mut("600000.SH").unwrap().init();
+        let send = |exchange: &mut Exchange<SkipListMarketDepth>, key: &str, price: f64| {
+            exchange.send_order_idempotent(
+                key,
+                "user1",
+                "600000.SH",
+                20231201093000000,
+                price,
+                100,
+                "buy",
+                None,
+            )
+        };
+
+        let order_id = send(&mut exchange, "req-1", 10.0).unwrap();
+        assert_eq!(send(&mut exchange, "req-1", 10.0), Ok(order_id));
+        assert_eq!(
+            exchange
+                .get_broker("600000.SH")
+                .unwrap()
+                .orders()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            send(&mut exchange, "req-1", 10.01),
+            Err(MarketError::IdempotencyConflict("req-1".to_string()))
+        );
+        assert_ne!(send(&mut exchange, "req-2", 10.0).unwrap(), order_id);
+
+        assert_eq!(
+            exchange.cancel_order_idempotent("cancel-1", "600000.SH", order_id),
+            Ok(true)
+        );
+        assert_eq!(
+            exchange.cancel_order_idempotent("cancel-1", "600000.SH", order_id),
+            Ok(true)
+        );
+        assert_eq!(
+            exchange.cancel_order_idempotent("cancel-1", "600000.SH", order_id + 1),
+            Err(MarketError::IdempotencyConflict("cancel-1".to_string()))
+        );
+
+        let checkpoint = serde_json::to_string(&exchange).unwrap();
+        let mut restored: Exchange<SkipListMarketDepth> =
+            serde_json::from_str(&checkpoint).unwrap();
+        assert_eq!(restored.idempotency, exchange.idempotency);
+        assert_eq!(send(&mut restored, "req-1", 10.0), Ok(order_id));
+    }
+
+    #[test]
+    /// 测试显式传入账户发送订单。
+    /// 验证 `None` 表示没有账户，账户名称按原样保存，旧的 `"none"` 写法仍然表示没有账户。
+    fn test_send_order_with_account() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Backtest,
+                "stock".to_string(),
+                "600000.SH".to_string(),
+                100.0,
+            )
+            .unwrap();
+        exchange.get_broker_mut("600000.SH").unwrap().init();
+        let mut send = |account: Option<&str>| {
+            exchange
+                .send_order_with_account(
+                    account,
+                    "600000.SH",
+                    20231201093000000,
+                    10.0,
+                    100,
+                    "buy",
+                    None,
+                )
+                .unwrap()
+        };
+        let anonymous = send(None);
+        let named = send(Some("user1"));
+        // 显式传入时 "none" 是普通的账户名称
+        let literal = send(Some("None"));
+        let legacy = exchange
+            .send_order(
+                "NONE",
+                "600000.SH",
+                20231201093000000,
+                10.0,
+                100,
+                "buy",
+                None,
+            )
+            .unwrap();
+
+        let orders = exchange.get_broker("600000.SH").unwrap().orders().unwrap();
+        let account = |order_id: OrderId| orders[&order_id].borrow().account.clone();
+        assert_eq!(account(anonymous), None);
+        assert_eq!(account(named), Some("user1".to_string()));
+        assert_eq!(account(literal), Some("None".to_string()));
+        assert_eq!(account(legacy), None);
+    }
+
+    fn record_hook(
+        object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        _bid: &Vec<(f64, f64, i64)>,
+        _ask: &Vec<(f64, f64, i64)>,
+        l3order: &L3OrderRef,
+    ) -> bool {
+        let l3order = l3order.borrow();
+        if let Some(calls) = object.borrow_mut().downcast_mut::<Vec<(OrderType, i64)>>() {
+            calls.push((l3order.order_type, l3order.timestamp));
+        }
+        true
+    }
+
+    #[test]
+    /// 测试按网格时间点推进时间。
+    /// 验证采样时间与交易所 3 秒快照对齐，午间休市内没有采样，逐笔消息不会越过采样时间点处理。
+    fn test_elapse_to_grid_across_lunch_break() {
+        let stock_code = "600000.SH";
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", "20231201");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Backtest,
+                "stock".to_string(),
+                stock_code.to_string(),
+                100.0,
+            )
+            .unwrap();
+
+        let mut data = DataCollator::new(
+            stock_code.to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "20231201".to_string(),
+            "ORDER",
+        );
+        let mut history = HashMap::new();
+        let mut index_by_seq = VecDeque::new();
+        for (seq, order_time) in [(1, 20231201112955000), (2, 20231201130001000)] {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                seq,
+                Side::Buy,
+                0,
+                0,
+                order_time,
+                OrderType::L,
+            );
+            {
+                let mut order = order_ref.borrow_mut();
+                let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                auxiliary_info.initial_price = 10.0;
+                auxiliary_info.initial_qty = 100.0;
+                auxiliary_info.initial_seq = seq;
+            }
+            history.insert(seq, order_ref);
+            index_by_seq.push_back((seq, seq));
+        }
+        data.len = index_by_seq.len();
+        data.orders = Some(history);
+        data.index_by_seq = Some(index_by_seq);
+        exchange.add_data(stock_code, data).unwrap();
+
+        let broker = exchange.get_broker_mut(stock_code).unwrap();
+        broker.set_current_time(20231201112952500);
+        broker.open_tick = 1000;
+        let calls: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<(OrderType, i64)>::new()));
+        exchange
+            .register_orderbook_hook(
+                stock_code,
+                HookType::Orderbook,
+                "grid",
+                Hook::new(calls.clone(), record_hook, 5),
+            )
+            .unwrap();
+
+        assert_eq!(
+            exchange.elapse_to_grid(0, 20231201130006000, None),
+            Err(MarketError::InvalidGridInterval(0))
+        );
+        let report = exchange
+            .elapse_to_grid(3000, 20231201130006000, None)
+            .unwrap();
+        assert_eq!(report.grid_points.get(stock_code), Some(&5));
+        assert_eq!(
+            exchange.get_crurent_time(Some(stock_code)).unwrap(),
+            20231201130006000
+        );
+
+        let calls = calls.borrow();
+        let calls = calls.downcast_ref::<Vec<(OrderType, i64)>>().unwrap();
+        assert_eq!(
+            calls,
+            &vec![
+                (OrderType::None, 20231201112954000),
+                (OrderType::L, 20231201112955000),
+                (OrderType::None, 20231201112957000),
+                (OrderType::None, 20231201130000000),
+                (OrderType::L, 20231201130001000),
+                (OrderType::None, 20231201130003000),
+                (OrderType::None, 20231201130006000),
+            ]
+        );
+    }
+
+    #[test]
+    /// 测试没有历史数据的经纪商按网格时间点推进时间。
+    /// 验证返回 `HistoryIsNone` 错误而不是无限循环，有历史数据的经纪商也不被推进。
+    fn test_elapse_to_grid_without_history() {
+        let mut exchange = paced_exchange();
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Backtest,
+                "stock".to_string(),
+                "600036.SH".to_string(),
+                100.0,
+            )
+            .unwrap();
+        exchange
+            .get_broker_mut("600036.SH")
+            .unwrap()
+            .set_current_time(20231201093000000);
+
+        assert_eq!(
+            exchange.elapse_to_grid(3000, 20231201093006000, Some("600036.SH")),
+            Err(MarketError::HistoryIsNone)
+        );
+        assert_eq!(
+            exchange.elapse_to_grid(3000, 20231201093006000, None),
+            Err(MarketError::HistoryIsNone)
+        );
+        assert_eq!(exchange.current_time_all()["600000.SH"], 20231201093000000);
+        assert_eq!(exchange.current_time_all()["600036.SH"], 20231201093000000);
+
+        let report = exchange
+            .elapse_to_grid(3000, 20231201093006000, Some("600000.SH"))
+            .unwrap();
+        assert_eq!(report.grid_points.get("600000.SH"), Some(&2));
+    }
+
+    fn paced_exchange() -> Exchange<SkipListMarketDepth> {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Backtest,
+                "stock".to_string(),
+                "600000.SH".to_string(),
+                100.0,
+            )
+            .unwrap();
+        let history = SyntheticFlow::new(FlowConfig::default())
+            .to_history("600000.SH", 20231201093000000, 100, 10, 0.01, 100.0)
+            .unwrap();
+        exchange.add_data("600000.SH", history).unwrap();
+        exchange
+            .get_broker_mut("600000.SH")
+            .unwrap()
+            .set_current_time(20231201093000000);
+        exchange
+    }
+
+    fn stop_hook(
+        object: &Rc<RefCell<dyn Any>>,
+        _info: &StatisticsInfo,
+        _bid: &Vec<(f64, f64, i64)>,
+        _ask: &Vec<(f64, f64, i64)>,
+        _l3order: &L3OrderRef,
+    ) -> bool {
+        if let Some((calls, stop)) = object
+            .borrow_mut()
+            .downcast_mut::<(usize, Arc<AtomicBool>)>()
+        {
+            *calls += 1;
+            if *calls == 3 {
+                stop.store(true, Ordering::Release);
+            }
+        }
+        true
+    }
+
+    #[test]
+    /// 测试按墙上时间的节奏回放。
+    /// 验证回放处理的历史订单及其顺序与直接推进时间相同，午间休市不计入等待时间。
+    fn test_run_paced() {
+        let stock_code = "600000.SH";
+        let mut plain = paced_exchange();
+        let mut paced = paced_exchange();
+        let mut calls = Vec::new();
+        for exchange in [&mut plain, &mut paced] {
+            let object: Rc<RefCell<dyn Any>> =
+                Rc::new(RefCell::new(Vec::<(OrderType, i64)>::new()));
+            exchange
+                .register_orderbook_hook(
+                    stock_code,
+                    HookType::Orderbook,
+                    "record",
+                    Hook::new(object.clone(), record_hook, 5),
+                )
+                .unwrap();
+            calls.push(object);
+        }
+        plain.elapse(1000, None).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        assert_eq!(
+            paced.run_paced(0.0, None, stop.clone()),
+            Err(MarketError::InvalidPacingSpeed("0".to_string()))
+        );
+        assert_eq!(
+            paced.run_paced(1.0, Some("AAPL"), stop.clone()),
+            Err(MarketError::StockBrokerNotExist)
+        );
+        paced.run_paced(1e6, None, stop.clone()).unwrap();
+        let broker = paced.get_broker(stock_code).unwrap();
+        assert_eq!(broker.events_processed, 10);
+        assert_eq!(broker.next_event_time(), None);
+        assert_eq!(broker.timestamp, 20231201093000900);
+        assert_eq!(paced.timestamp, 20231201093000900);
+
+        let plain_calls = calls[0].borrow();
+        let paced_calls = calls[1].borrow();
+        let plain_calls = plain_calls.downcast_ref::<Vec<(OrderType, i64)>>().unwrap();
+        let paced_calls = paced_calls.downcast_ref::<Vec<(OrderType, i64)>>().unwrap();
+        assert!(!plain_calls.is_empty());
+        assert_eq!(paced_calls, plain_calls);
+
+        // 历史数据处理完毕后立即返回
+        paced.run_paced(f64::INFINITY, None, stop).unwrap();
+        assert_eq!(paced.get_broker(stock_code).unwrap().events_processed, 10);
+
+        // 11:29:00 到 13:01:00 只等待休市前后各一分钟
+        assert_eq!(
+            paced.paced_wait_ms(20231201112900000, 20231201130100000),
+            Ok(120000)
+        );
+        assert_eq!(
+            paced.paced_wait_ms(20231201120000000, 20231201130000500),
+            Ok(500)
+        );
+        paced.pace_lunch_break = true;
+        assert_eq!(
+            paced.paced_wait_ms(20231201112900000, 20231201130100000),
+            Ok(5520000)
+        );
+    }
+
+    #[test]
+    /// 测试同一时间戳上用户订单优先时按墙上时间回放。
+    /// 验证 `UserFirst` 和 `BySeq` 下每一步都处理掉该时间戳上的历史订单，回放能够结束，
+    /// 逐步推进时可以在两步之间提交用户订单。
+    fn test_run_paced_same_time_priority() {
+        let stock_code = "600000.SH";
+        for priority in [SameTimePriority::UserFirst, SameTimePriority::BySeq] {
+            let mut exchange = paced_exchange();
+            exchange
+                .set_same_time_priority(stock_code, priority)
+                .unwrap();
+            exchange
+                .run_paced(f64::INFINITY, None, Arc::new(AtomicBool::new(false)))
+                .unwrap();
+            let broker = exchange.get_broker(stock_code).unwrap();
+            assert_eq!(broker.events_processed, 10);
+            assert_eq!(broker.next_event_time(), None);
+        }
+
+        let mut exchange = paced_exchange();
+        exchange
+            .set_same_time_priority(stock_code, SameTimePriority::UserFirst)
+            .unwrap();
+        let mut replay = exchange.start_paced(f64::INFINITY, None).unwrap();
+        assert!(exchange.step_paced(&mut replay).unwrap());
+        assert_eq!(exchange.get_broker(stock_code).unwrap().events_processed, 1);
+        let order_id = exchange
+            .send_order("a", stock_code, replay.clock, 9.0, 100, "buy", None)
+            .unwrap();
+        while exchange.step_paced(&mut replay).unwrap() {}
+        assert_eq!(
+            exchange.get_broker(stock_code).unwrap().events_processed,
+            10
+        );
+        assert!(exchange
+            .get_broker(stock_code)
+            .unwrap()
+            .orders()
+            .unwrap()
+            .contains_key(&order_id));
+    }
+
+    #[test]
+    /// 测试回放过程中设置停止标志。
+    /// 验证回放在当前一步结束后停止，清除标志后从停止处继续回放。
+    fn test_run_paced_stop() {
+        let stock_code = "600000.SH";
+        let mut exchange = paced_exchange();
+        let stop = Arc::new(AtomicBool::new(true));
+        exchange.run_paced(1e6, None, stop.clone()).unwrap();
+        assert_eq!(exchange.get_broker(stock_code).unwrap().events_processed, 0);
+
+        stop.store(false, Ordering::Release);
+        let object: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new((0usize, stop.clone())));
+        exchange
+            .register_orderbook_hook(
+                stock_code,
+                HookType::Orderbook,
+                "stop",
+                Hook::new(object, stop_hook, 5),
+            )
+            .unwrap();
+        exchange
+            .run_paced(1e6, Some(stock_code), stop.clone())
+            .unwrap();
+        let broker = exchange.get_broker(stock_code).unwrap();
+        assert!(broker.events_processed < 10);
+        assert!(broker.next_event_time().is_some());
+
+        stop.store(false, Ordering::Release);
+        exchange.run_paced(1e6, Some(stock_code), stop).unwrap();
+        let broker = exchange.get_broker(stock_code).unwrap();
+        assert_eq!(broker.events_processed, 10);
+        assert_eq!(broker.next_event_time(), None);
+    }
+
+    fn recreate_exchange() -> Exchange<SkipListMarketDepth> {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/12/01");
+        exchange
+            .add_broker(
+                MarketType::SH,
+                ExchangeMode::Live,
+                "stock".to_string(),
+                "600000.SH".to_string(),
+                100.0,
+            )
+            .unwrap();
+        let broker = exchange.get_broker_mut("600000.SH").unwrap();
+        broker.set_current_time(20231201100000000);
+        broker.set_price_limit(0.1, PriceLimitPolicy::default());
+        broker.set_previous_close_price(10.0);
+        exchange
+            .send_order("a", "600000.SH", 20231201100000000, 10.5, 100, "buy", None)
+            .unwrap();
+        exchange.elapse(1000, None).unwrap();
+        exchange
+    }
+
+    #[test]
+    /// 测试移除经纪商时清理关联订单组。
+    /// 验证被移除股票的腿从组中删除，剩余不足两条腿的组被丢弃。
+    fn test_remove_broker_prunes_link_groups() {
+        let mut exchange = Exchange::<SkipListMarketDepth>::new("live", "2023/01/01");
+        let timestamp = 20231201093021355;
+        for stock_code in ["AAPL", "MSFT", "GOOG"] {
+            exchange
+                .add_broker(
+                    MarketType::SH,
+                    ExchangeMode::Live,
+                    "stock".to_string(),
+                    stock_code.to_string(),
+                    100.0,
+                )
+                .unwrap();
+            exchange
+                .get_broker_mut(stock_code)
+                .unwrap()
+                .set_current_time(timestamp);
+        }
+        let legs = vec![
+            NewOrderRequest::new(Some("a"), "AAPL", timestamp, 10.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "MSFT", timestamp, 20.0, 100, "buy", None),
+            NewOrderRequest::new(Some("a"), "GOOG", timestamp, 30.0, 100, "buy", None),
+        ];
+        let order_ids = exchange
+            .send_linked_orders(legs, LinkMode::OcoCancel)
+            .unwrap();
+
+        exchange.remove_broker("AAPL").unwrap();
+        assert_eq!(exchange.link_groups.len(), 1);
+        assert_eq!(
+            exchange.link_groups[0].legs,
+            vec![
+                ("MSFT".to_string(), order_ids[1]),
+                ("GOOG".to_string(), order_ids[2]),
+            ]
+        );
+
+        exchange
+            .recreate_broker("MSFT", PreserveFlags::NONE)
+            .unwrap();
+        assert!(exchange.link_groups.is_empty());
+    }
+
+    #[test]
+    /// 测试移除经纪商。
+    /// 验证移除后股票不再存在，返回的经纪商保留原有的订单，重复移除返回错误。
+    fn test_remove_broker() {
+        let mut exchange = recreate_exchange();
+
+        let broker = exchange.remove_broker("600000.SH").unwrap();
+        assert_eq!(broker.stock_code, "600000.SH");
+        assert_eq!(broker.orders().unwrap().len(), 1);
+        assert!(!exchange.exists_stock("600000.SH"));
+        assert_eq!(
+            exchange.remove_broker("600000.SH").err(),
+            Some(MarketError::StockBrokerNotExist)
+        );
+        // 移除后仍然可以推进其他股票的时间
+        exchange.elapse(1000, None).unwrap();
+    }
+
+    #[test]
+    /// 测试保留参考数据和前收盘价重建经纪商。
+    /// 验证新经纪商的订单簿和订单为空，涨跌停价仍然生效。
+    fn test_recreate_broker_reference_data() {
+        let stock_code = "600000.SH";
+        let mut exchange = recreate_exchange();
+        assert_eq!(
+            exchange
+                .get_broker(stock_code)
+                .unwrap()
+                .orders()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            exchange.recreate_broker("AAPL", PreserveFlags::ALL).err(),
+            Some(MarketError::StockBrokerNotExist)
+        );
+
+        let broker = exchange
+            .recreate_broker(
+                stock_code,
+                PreserveFlags::REFERENCE_DATA | PreserveFlags::PREVIOUS_CLOSE,
+            )
+            .unwrap();
+        assert!(broker.orders().unwrap().is_empty());
+        assert_eq!(
+            broker
+                .market_depth
+                .best_bid_tick(&OrderSourceType::UserOrder),
+            INVALID_MIN
+        );
+        assert_eq!(broker.previous_close_price, Some(10.0));
+        assert_eq!(broker.limit_up_tick(), Some(1100));
+        assert_eq!(broker.limit_down_tick(), Some(900));
+        // 统计数据没有保留，但前收盘价与经纪商一致
+        let statistics = broker.market_depth.get_statistics();
+        assert_eq!(statistics.total_bid_num, 0);
+        assert_eq!(statistics.previous_close_tick, Some(1000));
+        broker.set_current_time(20231201100000000);
+        assert_eq!(
+            exchange.send_order("a", stock_code, 20231201100000000, 11.5, 100, "buy", None),
+            Err(MarketError::PriceOutOfLimit)
+        );
+
+        // 不保留前收盘价时不设涨跌停
+        let broker = exchange
+            .recreate_broker(stock_code, PreserveFlags::REFERENCE_DATA)
+            .unwrap();
+        assert_eq!(broker.previous_close_price, None);
+        assert_eq!(broker.limit_up_tick(), None);
+    }
+
+    #[test]
+    /// 测试重建经纪商时保留或丢弃钩子。
+    /// 验证不保留钩子时钩子列表为空，保留时钩子的配置不变、耗时统计重新开始。
+    fn test_recreate_broker_hooks() {
+        let stock_code = "600000.SH";
+        let mut exchange = recreate_exchange();
+        let calls: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(Vec::<(OrderType, i64)>::new()));
+        exchange
+            .register_orderbook_hook(
+                stock_code,
+                HookType::Orderbook,
+                "record",
+                Hook::new(calls.clone(), record_hook, 5),
+            )
+            .unwrap();
+        exchange
+            .send_order("a", stock_code, 20231201100001000, 10.5, 100, "sell", None)
+            .unwrap();
+        exchange.elapse(1000, None).unwrap();
+        assert_eq!(exchange.hooks_info(stock_code).unwrap().len(), 1);
+        let statistics = *exchange
+            .get_broker(stock_code)
+            .unwrap()
+            .market_depth
+            .get_statistics();
+
+        exchange
+            .recreate_broker(stock_code, PreserveFlags::HOOKS | PreserveFlags::STATISTICS)
+            .unwrap();
+        let infos = exchange.hooks_info(stock_code).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].name, "record");
+        assert_eq!(infos[0].max_level, 5);
+        assert_eq!(infos[0].stats, HookStats::default());
+        let broker = exchange.get_broker(stock_code).unwrap();
+        let preserved = broker.market_depth.get_statistics();
+        assert_eq!(preserved.total_bid_num, statistics.total_bid_num);
+        assert_eq!(preserved.total_bid_vol, statistics.total_bid_vol);
+        assert_eq!(preserved.previous_close_tick, None);
+        assert_eq!(broker.previous_close_price, None);
+
+        exchange
+            .recreate_broker(stock_code, PreserveFlags::REFERENCE_DATA)
+            .unwrap();
+        assert!(exchange.hooks_info(stock_code).unwrap().is_empty());
+    }
+
+    fn synthetic_broker(stock_code: &str) -> Result<Broker<SkipListMarketDepth>, MarketError> {
+        use synthetic::{FlowConfig, SyntheticFlow};
+
+        let seed = stock_code.bytes().map(u64::from).sum();
+        let mut broker = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SH,
+            "stock".to_string(),
+            stock_code.to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let history = SyntheticFlow::new(FlowConfig {
+            seed,
+            ..FlowConfig::default()
+        })
+        .to_history(stock_code, 20231201093000000, 100, 200, 0.01, 100.0)?;
+        broker.add_data(Some(history))?;
+        broker.set_current_time(20231201093000000);
+        Ok(broker)
+    }
+
+    fn order_states(
+        broker: &mut Broker<SkipListMarketDepth>,
+    ) -> Vec<(OrderId, OrderStatus, f64, i64)> {
+        let mut states: Vec<_> = broker
+            .orders()
+            .unwrap()
+            .values()
+            .map(|order| {
+                let order = order.borrow();
+                (
+                    order.order_id,
+                    order.status,
+                    order.filled_qty,
+                    order.exch_time,
+                )
+            })
+            .collect();
+        states.sort_by_key(|state| state.0);
+        states
+    }
+
+    #[test]
+    /// 测试在回放线程上推进多只股票。
+    /// 验证每次推进的成交量和每笔用户订单的状态与在调用线程上依次推进相同。
+    fn test_spawn_workers() {
+        let stock_codes = ["600000.SH", "600036.SH", "601318.SH"];
+        let mut sequential = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        for stock_code in stock_codes {
+            sequential.broker_map.insert(
+                stock_code.to_string(),
+                synthetic_broker(stock_code).unwrap(),
+            );
+        }
+        let mut parallel = Exchange::<SkipListMarketDepth>::new("backtest", "2023/12/01");
+        parallel
+            .spawn_workers(&stock_codes, synthetic_broker)
+            .unwrap();
+        assert!(parallel.get_broker(stock_codes[0]).is_none());
+        assert_eq!(
+            parallel.spawn_workers(&stock_codes[..1], synthetic_broker),
+            Err(MarketError::StockBrokerIdExist)
+        );
+
+        for exchange in [&mut sequential, &mut parallel] {
+            for stock_code in stock_codes {
+                for (price, bs_flag) in [(10.05, "buy"), (9.95, "sell")] {
+                    exchange
+                        .send_order(
+                            "a",
+                            stock_code,
+                            20231201093001000,
+                            price,
+                            500,
+                            bs_flag,
+                            None,
+                        )
+                        .unwrap();
+                }
+            }
+        }
+
+        let mut total_filled = 0;
+        for _ in 0..5 {
+            let filled = sequential.elapse(5000, None).unwrap();
+            assert_eq!(parallel.elapse(5000, None).unwrap(), filled);
+            total_filled += filled;
+        }
+        assert!(total_filled > 0);
+        assert_eq!(parallel.timestamp, sequential.timestamp);
+
+        for stock_code in stock_codes {
+            let expected = order_states(sequential.get_broker_mut(stock_code).unwrap());
+            assert_eq!(expected.len(), 2);
+            assert_eq!(
+                parallel.with_worker(stock_code, order_states).unwrap(),
+                expected
+            );
+            let events_processed = sequential.get_broker(stock_code).unwrap().events_processed;
+            assert_eq!(
+                parallel
+                    .with_worker(stock_code, |broker| broker.events_processed)
+                    .unwrap(),
+                events_processed
+            );
+        }
+        assert_eq!(
+            parallel.elapse(5000, Some(stock_codes[0])).unwrap(),
+            sequential.elapse(5000, Some(stock_codes[0])).unwrap()
+        );
+
+        // 停止回放线程后线程上的经纪商随之丢弃
+        parallel.shutdown_workers();
+        assert_eq!(
+            parallel.with_worker(stock_codes[0], |broker| broker.events_processed),
+            Err(MarketError::StockBrokerNotExist)
+        );
+    }
 }
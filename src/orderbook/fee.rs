@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use super::order::Order;
+use super::{OrderSourceType, Side};
+
+/// 单边（买或卖）的费率，按成交金额的比例计算。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SideFees {
+    /// 挂单被动成交（maker）的费率
+    pub maker_rate: f64,
+    /// 主动成交（taker）的费率，集合竞价的成交同样按此费率收取
+    pub taker_rate: f64,
+    /// 被动成交的返佣比例，从 maker 费率中扣除，扣除后为负数时订单得到返佣
+    pub maker_rebate: f64,
+}
+
+/// `FeeModel` 按买卖方向和成交的流动性角色计算用户订单的手续费。
+///
+/// 挂单被动成交按 maker 费率扣除返佣后收取，主动成交和集合竞价成交按 taker 费率收取。
+/// 只有用户订单收取手续费，历史订单不受影响；`exempt_accounts` 中的账户既不收费也没有返佣。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeModel {
+    /// 买入成交的费率
+    pub buy: SideFees,
+    /// 卖出成交的费率
+    pub sell: SideFees,
+    /// 免收手续费的账户
+    pub exempt_accounts: HashSet<String>,
+}
+
+impl FeeModel {
+    /// 创建买卖双方费率相同的手续费模型。
+    pub fn symmetric(fees: SideFees) -> Self {
+        Self {
+            buy: fees,
+            sell: fees,
+            exempt_accounts: HashSet::new(),
+        }
+    }
+
+    /// 将账户加入免收手续费的名单。
+    pub fn exempt(mut self, account: &str) -> Self {
+        self.exempt_accounts.insert(account.to_string());
+        self
+    }
+
+    /// 订单是否免收手续费，历史订单和名单中账户的订单都免收。
+    pub fn is_exempt(&self, order: &Order) -> bool {
+        order.source != OrderSourceType::UserOrder
+            || order
+                .account
+                .as_ref()
+                .is_some_and(|account| self.exempt_accounts.contains(account))
+    }
+
+    /// 计算订单累计成交的手续费。
+    ///
+    /// 成交金额按各笔成交的实际成交价计算，被动成交的金额按 maker 费率收取，
+    /// 主动成交和集合竞价成交的金额按 taker 费率收取。
+    ///
+    /// # 返回值
+    /// 正数表示支付的手续费，负数表示得到的返佣。
+    pub fn order_fee(&self, order: &Order) -> f64 {
+        if self.is_exempt(order) {
+            return 0.0;
+        }
+        let fees = match order.side {
+            Side::Buy => &self.buy,
+            Side::Sell => &self.sell,
+            _ => return 0.0,
+        };
+        let maker_amount = order.filled_amount_passive;
+        let taker_amount = order.filled_amount - order.filled_amount_passive;
+        (fees.maker_rate - fees.maker_rebate) * maker_amount + fees.taker_rate * taker_amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{FillLiquidity, OrderType};
+    use super::*;
+
+    fn filled_order(account: &str, side: Side, passive: f64, aggressive: f64) -> Order {
+        let mut order = Order::new(
+            Some(account.to_string()),
+            "600000.SH".to_string(),
+            10.0,
+            passive + aggressive,
+            side,
+            OrderType::L,
+            20231201100000000,
+            OrderSourceType::UserOrder,
+        );
+        order.add_fill(FillLiquidity::Passive, passive, 10.0, 20231201100000000);
+        order.add_fill(
+            FillLiquidity::Aggressive,
+            aggressive,
+            10.0,
+            20231201100000000,
+        );
+        order.filled_qty = passive + aggressive;
+        order
+    }
+
+    #[test]
+    /// 测试按方向和流动性角色计算手续费。
+    /// 验证被动成交得到返佣、主动成交支付 taker 费用，免收账户和历史订单没有手续费。
+    fn test_order_fee() {
+        let model = FeeModel {
+            buy: SideFees {
+                maker_rate: 0.0001,
+                taker_rate: 0.0003,
+                maker_rebate: 0.0002,
+            },
+            sell: SideFees {
+                maker_rate: 0.0001,
+                taker_rate: 0.0013,
+                maker_rebate: 0.0,
+            },
+            exempt_accounts: HashSet::new(),
+        }
+        .exempt("mm");
+
+        let buy = filled_order("acct", Side::Buy, 100.0, 100.0);
+        assert!((model.order_fee(&buy) - (-0.1 + 0.3)).abs() < 1e-9);
+        let sell = filled_order("acct", Side::Sell, 100.0, 100.0);
+        assert!((model.order_fee(&sell) - (0.1 + 1.3)).abs() < 1e-9);
+
+        assert_eq!(
+            model.order_fee(&filled_order("mm", Side::Buy, 100.0, 100.0)),
+            0.0
+        );
+        let mut history = filled_order("acct", Side::Buy, 100.0, 100.0);
+        history.source = OrderSourceType::LocalOrder;
+        assert_eq!(model.order_fee(&history), 0.0);
+    }
+}
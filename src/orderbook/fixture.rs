@@ -0,0 +1,329 @@
+use super::broker::Broker;
+use super::skiplist_orderbook::SkipListMarketDepth;
+use super::*;
+
+/// `BookFixture` 按价格档位构造订单簿，用于在单元测试中得到指定的盘口，不需要回放逐笔数据。
+///
+/// 每个档位的数量平均拆分为指定数量的历史订单（`OrderSourceType::LocalOrder`），
+/// 除不尽的手数从第一笔订单开始依次多分一手。订单按 `bid`、`ask` 的调用顺序通过
+/// `L3MarketDepth::add` 挂入订单簿，订单 ID 默认从 1 开始依次递增。
+///
+/// ```ignore
+/// let (depth, orders) = BookFixture::new(ExchangeMode::Backtest)
+///     .bid(10.01, 200.0, 1)
+///     .bid(10.00, 500.0, 2)
+///     .ask(10.02, 300.0, 1)
+///     .build_depth()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct BookFixture {
+    mode: ExchangeMode,
+    market_type: MarketType,
+    stock_code: String,
+    tick_size: f64,
+    lot_size: f64,
+    timestamp: i64,
+    /// 买方档位，(价格, 数量, 订单数)
+    bids: Vec<(f64, f64, usize)>,
+    /// 卖方档位，(价格, 数量, 订单数)
+    asks: Vec<(f64, f64, usize)>,
+    last: Option<f64>,
+    prev_close: Option<f64>,
+    open: Option<f64>,
+    first_order_id: OrderId,
+}
+
+/// `BookFixture` 创建的订单 ID，按档位的添加顺序排列，每个档位内按排队顺序排列。
+///
+/// 历史订单不在市场深度的订单表中，测试中撤单时通过 `get` 取得订单引用，
+/// 再调用 `L3MarketDepth::cancel_order_from_ref`。
+#[derive(Debug, Clone, Default)]
+pub struct FixtureOrders {
+    pub bids: Vec<Vec<OrderId>>,
+    pub asks: Vec<Vec<OrderId>>,
+    refs: HashMap<OrderId, L3OrderRef>,
+}
+
+impl FixtureOrders {
+    /// 按添加顺序返回所有订单 ID，买方在前。
+    pub fn all(&self) -> Vec<OrderId> {
+        self.bids
+            .iter()
+            .chain(self.asks.iter())
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// 返回订单的引用。
+    pub fn get(&self, order_id: OrderId) -> Option<L3OrderRef> {
+        self.refs.get(&order_id).cloned()
+    }
+}
+
+impl BookFixture {
+    /// 创建一个空的订单簿构造器，默认为上海市场的 `600000.SH`，最小变动价位 0.01，每手 100 股，
+    /// 时间为 `20231201093000000`。
+    pub fn new(mode: ExchangeMode) -> Self {
+        Self {
+            mode,
+            market_type: MarketType::SH,
+            stock_code: "600000.SH".to_string(),
+            tick_size: 0.01,
+            lot_size: 100.0,
+            timestamp: 20231201093000000,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            last: None,
+            prev_close: None,
+            open: None,
+            first_order_id: 1,
+        }
+    }
+
+    /// 设置市场和股票代码，只影响 `build_broker`。
+    pub fn stock(mut self, market_type: MarketType, stock_code: &str) -> Self {
+        self.market_type = market_type;
+        self.stock_code = stock_code.to_string();
+        self
+    }
+
+    /// 设置最小变动价位，档位价格按该值换算为价格档位。
+    pub fn tick_size(mut self, tick_size: f64) -> Self {
+        self.tick_size = tick_size;
+        self
+    }
+
+    /// 设置每手的股数，档位数量按该值换算为手数。
+    pub fn lot_size(mut self, lot_size: f64) -> Self {
+        self.lot_size = lot_size;
+        self
+    }
+
+    /// 设置订单的时间，`build_broker` 同时把经纪商的当前时间设为该时间。
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// 添加一个买方档位，`qty` 拆分为 `orders` 笔订单。
+    pub fn bid(mut self, price: f64, qty: f64, orders: usize) -> Self {
+        self.bids.push((price, qty, orders));
+        self
+    }
+
+    /// 添加一个卖方档位，`qty` 拆分为 `orders` 笔订单。
+    pub fn ask(mut self, price: f64, qty: f64, orders: usize) -> Self {
+        self.asks.push((price, qty, orders));
+        self
+    }
+
+    /// 设置最新成交价。
+    pub fn last(mut self, price: f64) -> Self {
+        self.last = Some(price);
+        self
+    }
+
+    /// 设置前收盘价。
+    pub fn prev_close(mut self, price: f64) -> Self {
+        self.prev_close = Some(price);
+        self
+    }
+
+    /// 设置第一笔订单的 ID，避免与测试中回放的历史订单 ID 重复。
+    pub fn first_order_id(mut self, order_id: OrderId) -> Self {
+        self.first_order_id = order_id;
+        self
+    }
+
+    /// 设置开盘价，构造出的订单簿视为开盘集合竞价已经完成。
+    pub fn open(mut self, price: f64) -> Self {
+        self.open = Some(price);
+        self
+    }
+
+    /// 构造市场深度。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidOrderRequest`: 某个档位的数量不是整手，手数少于订单数，
+    ///   或者构造出的最优买价不低于最优卖价。
+    /// - 其他错误来自 `L3MarketDepth::add`。
+    pub fn build_depth(&self) -> Result<(SkipListMarketDepth, FixtureOrders), MarketError> {
+        let mut depth = SkipListMarketDepth::new(self.mode, self.tick_size, self.lot_size);
+        let orders = self.populate(&mut depth)?;
+        if let Some(prev_close) = self.prev_close {
+            depth.set_previous_close_tick(Some(self.price_to_tick(prev_close)));
+        }
+        if let Some(open) = self.open {
+            depth.market_statistics.open_tick = self.price_to_tick(open);
+        }
+        Ok((depth, orders))
+    }
+
+    /// 构造已初始化的经纪商，订单挂入经纪商的市场深度。
+    ///
+    /// # 错误
+    /// 与 `build_depth` 相同。
+    pub fn build_broker(
+        &self,
+    ) -> Result<(Broker<SkipListMarketDepth>, FixtureOrders), MarketError> {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            self.mode,
+            self.market_type,
+            "stock".to_string(),
+            self.stock_code.clone(),
+            self.tick_size,
+            self.lot_size,
+        );
+        broker.init();
+        broker.set_current_time(self.timestamp);
+        let orders = self.populate(&mut broker.market_depth)?;
+        if let Some(prev_close) = self.prev_close {
+            broker.set_previous_close_price(prev_close);
+        }
+        if let Some(open) = self.open {
+            broker.open_tick = self.price_to_tick(open);
+            broker.market_depth.market_statistics.open_tick = broker.open_tick;
+        }
+        Ok((broker, orders))
+    }
+
+    fn populate(&self, depth: &mut SkipListMarketDepth) -> Result<FixtureOrders, MarketError> {
+        let mut next_order_id = self.first_order_id;
+        let mut orders = FixtureOrders::default();
+        for (side, levels) in [(Side::Buy, &self.bids), (Side::Sell, &self.asks)] {
+            for &(price, qty, count) in levels.iter() {
+                let mut order_ids = Vec::with_capacity(count);
+                for vol in self.split_level(qty, count)? {
+                    let order_ref = L3Order::new_ref(
+                        OrderSourceType::LocalOrder,
+                        None,
+                        next_order_id,
+                        side,
+                        self.price_to_tick(price),
+                        vol,
+                        self.timestamp,
+                        OrderType::L,
+                    );
+                    depth.add(order_ref.clone())?;
+                    orders.refs.insert(next_order_id, order_ref);
+                    order_ids.push(next_order_id);
+                    next_order_id += 1;
+                }
+                match side {
+                    Side::Buy => orders.bids.push(order_ids),
+                    _ => orders.asks.push(order_ids),
+                }
+            }
+        }
+        if depth.best_bid_tick != INVALID_MIN
+            && depth.best_ask_tick != INVALID_MAX
+            && depth.best_bid_tick >= depth.best_ask_tick
+        {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        // 回测模式的影子最优价在整理档位时更新
+        depth.update_bid_depth()?;
+        depth.update_ask_depth()?;
+        if let Some(last) = self.last {
+            depth.set_last_tick(self.price_to_tick(last));
+        }
+        Ok(orders)
+    }
+
+    /// 把一个档位的数量拆分为 `count` 笔订单的手数。
+    fn split_level(&self, qty: f64, count: usize) -> Result<Vec<i64>, MarketError> {
+        let lots = (qty / self.lot_size).round();
+        if count == 0 || lots < count as f64 || (qty / self.lot_size - lots).abs() > 1e-6 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        let lots = lots as i64;
+        let count = count as i64;
+        Ok((0..count)
+            .map(|i| lots / count + i64::from(i < lots % count))
+            .collect())
+    }
+
+    fn price_to_tick(&self, price: f64) -> i64 {
+        (price / self.tick_size).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::order::Order;
+    use super::*;
+
+    #[test]
+    /// 测试档位拆分和订单 ID。
+    /// 验证除不尽的手数分给靠前的订单，影子订单簿与真实订单簿的最优价一致。
+    fn test_book_fixture_levels() {
+        let (depth, orders) = BookFixture::new(ExchangeMode::Backtest)
+            .bid(10.01, 200.0, 1)
+            .bid(10.00, 500.0, 2)
+            .ask(10.02, 300.0, 1)
+            .last(10.01)
+            .prev_close(9.9)
+            .build_depth()
+            .unwrap();
+        assert_eq!(orders.bids, vec![vec![1], vec![2, 3]]);
+        assert_eq!(orders.asks, vec![vec![4]]);
+        assert_eq!(orders.all(), vec![1, 2, 3, 4]);
+        assert_eq!(orders.get(2).unwrap().borrow().vol, 3);
+        assert_eq!(orders.get(3).unwrap().borrow().vol, 2);
+        for source in [OrderSourceType::LocalOrder, OrderSourceType::UserOrder] {
+            assert_eq!(depth.best_bid_tick(&source), 1001);
+            assert_eq!(depth.best_ask_tick(&source), 1002);
+            assert_eq!(depth.last_tick(&source), 1001);
+        }
+        assert_eq!(depth.previous_close_tick, Some(990));
+
+        let mut depth = depth;
+        depth.cancel_order_from_ref(orders.get(3).unwrap()).unwrap();
+        let level = depth.bid_depth.get(&-1000).unwrap();
+        assert_eq!((level.vol, level.count), (3, 1));
+
+        assert_eq!(
+            BookFixture::new(ExchangeMode::Live)
+                .bid(10.02, 100.0, 1)
+                .ask(10.01, 100.0, 1)
+                .build_depth()
+                .err(),
+            Some(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            BookFixture::new(ExchangeMode::Live)
+                .bid(10.00, 150.0, 1)
+                .build_depth()
+                .err(),
+            Some(MarketError::InvalidOrderRequest)
+        );
+    }
+
+    #[test]
+    /// 用 `BookFixture` 重写 `test_market_order_residual_canceled` 的场景。
+    /// 验证市价订单最多成交五档，剩余部分被撤销。
+    fn test_book_fixture_market_order_residual() {
+        let mut fixture = BookFixture::new(ExchangeMode::Backtest);
+        for i in 0..6 {
+            fixture = fixture.ask(10.0 + i as f64 * 0.01, 100.0, 1);
+        }
+        let (mut broker, _) = fixture.open(10.0).build_broker().unwrap();
+
+        let order_ref = Order::new_ref(
+            None,
+            "600000.SH".to_string(),
+            20231201093000000,
+            0.0,
+            1000.0,
+            "Buy",
+            OrderType::M,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 100;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
+        assert_eq!(order_ref.borrow().filled_qty, 500.0);
+        assert_eq!(order_ref.borrow().canceled_qty, 500.0);
+    }
+}
@@ -1,11 +1,81 @@
 use super::statistics::StatisticsInfo;
 use super::*;
 use std::any::Any;
+use std::time::Duration;
+
+/// 钩子默认允许 panic 的次数，达到后钩子被注销。
+pub const DEFAULT_MAX_HOOK_PANICS: u32 = 1;
+
 #[derive(Debug)]
 pub struct Hook {
     pub object: Rc<RefCell<dyn Any>>,
     pub handler: OrderbookHook,
     pub max_level: usize,
+    /// 连续超过慢钩子阈值达到次数后的处理方式
+    pub slow_policy: SlowHookPolicy,
+    /// 钩子执行耗时的统计
+    pub stats: HookStats,
+    /// 连续超过慢钩子阈值的次数
+    pub(crate) slow_strikes: u32,
+    /// 钩子 panic 的次数
+    pub panics: u32,
+}
+
+impl Hook {
+    pub fn new(object: Rc<RefCell<dyn Any>>, handler: OrderbookHook, max_level: usize) -> Self {
+        Self {
+            object: object,
+            handler: handler,
+            max_level: max_level,
+            slow_policy: SlowHookPolicy::default(),
+            stats: HookStats::default(),
+            slow_strikes: 0,
+            panics: 0,
+        }
+    }
+
+    /// 设置钩子连续超过慢钩子阈值后的处理方式。
+    pub fn with_slow_policy(mut self, slow_policy: SlowHookPolicy) -> Self {
+        self.slow_policy = slow_policy;
+        self
+    }
+}
+
+/// 钩子执行耗时的统计。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct HookStats {
+    /// 调用次数
+    pub count: u64,
+    /// 累计耗时
+    pub total: Duration,
+    /// 单次调用的最大耗时
+    pub max: Duration,
+}
+
+impl HookStats {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = cmp::max(self.max, elapsed);
+    }
+}
+
+/// 钩子连续超过慢钩子阈值后的处理方式。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum SlowHookPolicy {
+    /// 只记录警告日志
+    #[default]
+    Warn,
+    /// 记录警告日志并注销钩子
+    Deregister,
+}
+
+/// 慢钩子监控的配置，钩子单次执行超过 `threshold` 记一次，连续 `strikes` 次后按钩子的
+/// `slow_policy` 处理。
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct HookWatchdog {
+    pub threshold: Duration,
+    pub strikes: u32,
 }
 
 pub type OrderbookHook = fn(
@@ -16,8 +86,69 @@ pub type OrderbookHook = fn(
     l3order: &L3OrderRef,  // current order info
 ) -> bool;
 
+/// 回放历史数据时每条逐笔消息的信息。
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct ReplayEvent {
+    pub order_id: OrderId,
+    pub seq: i64,
+    /// 回放时间，时间戳早于之前已处理消息的历史消息为当前的回放时间
+    pub timestamp: i64,
+    /// 解析后的订单类型，撤单消息为 `OrderType::Cancel`
+    pub order_type: OrderType,
+}
+
+pub type ReplayCallback = fn(&Rc<RefCell<dyn Any>>, &ReplayEvent);
+
+/// 回放历史数据时对每条逐笔消息调用的回调，在撮合之前触发。
+#[derive(Debug)]
+pub struct EventCallback {
+    pub object: Rc<RefCell<dyn Any>>,
+    pub handler: ReplayCallback,
+}
+
+/// 用户订单生命周期中的事件。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum LifecycleEvent {
+    /// 订单得到交易所确认，从 `OrderStatus::PendingAck` 变为 `OrderStatus::New`
+    Acknowledged,
+    /// 延迟生效的撤单被交易所拒绝，订单状态不变，例如深圳市场收盘集合竞价期间的撤单
+    CancelRejected,
+}
+
+/// 用户订单生命周期事件的信息。
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct OrderLifecycle {
+    pub order_id: OrderId,
+    pub event: LifecycleEvent,
+    /// 事件发生的交易所时间
+    pub timestamp: i64,
+}
+
+pub type LifecycleCallback = fn(&Rc<RefCell<dyn Any>>, &OrderLifecycle);
+
+/// 用户订单生命周期事件的回调，参见 `Broker::set_lifecycle_callback`。
+#[derive(Debug)]
+pub struct LifecycleHook {
+    pub object: Rc<RefCell<dyn Any>>,
+    pub handler: LifecycleCallback,
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, Hash)]
 #[repr(u8)]
 pub enum HookType {
     Orderbook = 0,
 }
+
+/// 已注册钩子的描述信息，用于查看经纪人上注册了哪些钩子。
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HookInfo {
+    pub hook_type: HookType,
+    pub name: String,
+    /// 传给钩子的订单簿档位数量
+    pub max_level: usize,
+    pub slow_policy: SlowHookPolicy,
+    /// 钩子执行耗时的统计，关闭耗时统计时不更新
+    pub stats: HookStats,
+    /// 钩子 panic 的次数
+    pub panics: u32,
+}
@@ -0,0 +1,206 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// 幂等缓存默认保留的键数量。
+pub const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 10_000;
+
+/// 带幂等键的请求参数，重放同一个键时用于判断参数是否与原请求一致。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IdempotentRequest {
+    /// 新订单
+    NewOrder {
+        account: String,
+        stock_code: String,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: String,
+        order_type: Option<OrderType>,
+    },
+    /// 撤单
+    Cancel {
+        stock_code: String,
+        order_id: OrderId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IdempotencyEntry {
+    request: IdempotentRequest,
+    /// 原请求对应的订单 ID
+    order_id: OrderId,
+    /// 最近一次使用该键的逻辑时间，用于淘汰最久未使用的键
+    last_used: u64,
+}
+
+/// `IdempotencyCache` 按幂等键记录已经成功处理的请求。
+///
+/// 缓存的容量有限，超过容量时淘汰最久未使用的键。缓存随 `Exchange` 一起序列化，
+/// 从检查点恢复后重放的请求仍然可以识别。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyCache {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<String, IdempotencyEntry>,
+    /// 按使用顺序记录的 (键, 逻辑时间)，时间与 `last_used` 不一致的记录已经过期，淘汰时跳过
+    recency: VecDeque<(String, u64)>,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_CAPACITY)
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity,
+            clock: 0,
+            entries: HashMap::new(),
+            recency: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 修改缓存的容量，超出新容量的键按最久未使用的顺序淘汰。
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > self.capacity {
+            self.evict_oldest();
+        }
+    }
+
+    /// 查找幂等键对应的原请求。
+    ///
+    /// # 参数
+    /// - `key`: 幂等键。
+    /// - `request`: 本次请求的参数。
+    ///
+    /// # 返回值
+    /// 键已经使用过且参数一致时返回原请求的订单 ID，键没有使用过时返回 `None`。
+    ///
+    /// # 错误
+    /// 键已经使用过但参数不一致时返回 `MarketError::IdempotencyConflict`。
+    pub fn lookup(
+        &mut self,
+        key: &str,
+        request: &IdempotentRequest,
+    ) -> Result<Option<OrderId>, MarketError> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) if &entry.request == request => {
+                entry.last_used = self.clock;
+                let order_id = entry.order_id;
+                self.touch(key);
+                Ok(Some(order_id))
+            }
+            Some(_) => Err(MarketError::IdempotencyConflict(key.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// 记录一个已经成功处理的请求，缓存已满时淘汰最久未使用的键。
+    pub fn insert(&mut self, key: &str, request: IdempotentRequest, order_id: OrderId) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+            self.evict_oldest();
+        }
+        self.entries.insert(
+            key.to_string(),
+            IdempotencyEntry {
+                request: request,
+                order_id: order_id,
+                last_used: self.clock,
+            },
+        );
+        self.touch(key);
+    }
+
+    /// 记录键在当前逻辑时间被使用，过期记录过多时压缩使用顺序。
+    fn touch(&mut self, key: &str) {
+        self.recency.push_back((key.to_string(), self.clock));
+        if self.recency.len() > 2 * self.capacity.max(1) {
+            let entries = &self.entries;
+            self.recency
+                .retain(|(key, used)| entries.get(key).is_some_and(|e| e.last_used == *used));
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((key, used)) = self.recency.pop_front() {
+            if self
+                .entries
+                .get(&key)
+                .is_some_and(|entry| entry.last_used == used)
+            {
+                self.entries.remove(&key);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cancel(order_id: OrderId) -> IdempotentRequest {
+        IdempotentRequest::Cancel {
+            stock_code: "600000.SH".to_string(),
+            order_id: order_id,
+        }
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache = IdempotencyCache::new(2);
+        cache.insert("a", cancel(1), 1);
+        cache.insert("b", cancel(2), 2);
+        // 访问 a 之后，b 成为最久未使用的键
+        assert_eq!(cache.lookup("a", &cancel(1)), Ok(Some(1)));
+        cache.insert("c", cancel(3), 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.lookup("b", &cancel(2)), Ok(None));
+        assert_eq!(cache.lookup("c", &cancel(3)), Ok(Some(3)));
+        assert_eq!(
+            cache.lookup("a", &cancel(2)),
+            Err(MarketError::IdempotencyConflict("a".to_string()))
+        );
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.lookup("c", &cancel(3)), Ok(Some(3)));
+    }
+
+    #[test]
+    /// 测试反复访问同一个键后的淘汰顺序。
+    /// 验证过期的使用记录被压缩，不会无限增长，也不影响最久未使用的键被淘汰。
+    fn test_cache_compacts_recency() {
+        let mut cache = IdempotencyCache::new(2);
+        cache.insert("a", cancel(1), 1);
+        cache.insert("b", cancel(2), 2);
+        for _ in 0..100 {
+            assert_eq!(cache.lookup("b", &cancel(2)), Ok(Some(2)));
+        }
+        assert!(cache.recency.len() <= 4);
+        cache.insert("c", cancel(3), 3);
+        assert_eq!(cache.lookup("a", &cancel(1)), Ok(None));
+        assert_eq!(cache.lookup("b", &cancel(2)), Ok(Some(2)));
+        assert_eq!(cache.lookup("c", &cancel(3)), Ok(Some(3)));
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+
+/// 关联订单中的一条腿，参数与 `Exchange::send_order_with_account` 相同。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewOrderRequest {
+    /// 账户名称，`None` 表示订单不属于任何账户
+    pub account: Option<String>,
+    pub stock_code: String,
+    /// 订单时间，所有腿统一使用其中最晚的时间进入交易所
+    pub order_time: i64,
+    pub order_price: f64,
+    pub order_volume: i64,
+    pub bs_flag: String,
+    pub order_type: Option<OrderType>,
+}
+
+impl NewOrderRequest {
+    pub fn new(
+        account: Option<&str>,
+        stock_code: &str,
+        order_time: i64,
+        order_price: f64,
+        order_volume: i64,
+        bs_flag: &str,
+        order_type: Option<OrderType>,
+    ) -> Self {
+        Self {
+            account: account.map(str::to_string),
+            stock_code: stock_code.to_string(),
+            order_time: order_time,
+            order_price: order_price,
+            order_volume: order_volume,
+            bs_flag: bs_flag.to_string(),
+            order_type: order_type,
+        }
+    }
+}
+
+/// 关联订单之间的联动方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkMode {
+    /// 所有腿要么在同一时刻全部进入市场，要么都不进入
+    AtomicEntry,
+    /// 在 `AtomicEntry` 的基础上，任意一条腿全部成交后撤销其余的腿
+    OcoCancel,
+}
+
+/// 关联订单被撤销的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkCancelReason {
+    /// 同组的另一条腿已全部成交，记录该腿的订单 ID
+    SiblingFilled(OrderId),
+}
+
+/// `LinkMode::OcoCancel` 登记的一组关联订单。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkGroup {
+    /// 每条腿的股票代码和订单 ID，按提交顺序排列
+    pub legs: Vec<(String, OrderId)>,
+    /// 是否已经有腿全部成交并撤销了其余的腿
+    pub triggered: bool,
+}
+
+impl LinkGroup {
+    pub fn new(legs: Vec<(String, OrderId)>) -> Self {
+        Self {
+            legs: legs,
+            triggered: false,
+        }
+    }
+}
+
+/// 因关联订单联动而发出的撤单。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkCancel {
+    pub stock_code: String,
+    pub order_id: OrderId,
+    pub timestamp: i64,
+    pub reason: LinkCancelReason,
+}
@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use super::{OutageKind, Side};
+
+#[cfg(feature = "data")]
+use polars::prelude::*;
+
+/// 行情快照中保存的最大档位数量。
+pub const MARKET_VIEW_LEVELS: usize = 10;
+
+/// 行情快照环形缓冲区的默认容量。
+pub const MARKET_VIEW_CAPACITY: usize = 1024;
+
+/// `MarketView` 表示某一时刻策略可观察到的行情状态。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarketView {
+    /// 快照采集时的时间戳
+    pub timestamp: i64,
+    /// 最佳买入价的 tick 价格
+    pub best_bid_tick: i64,
+    /// 最佳卖出价的 tick 价格
+    pub best_ask_tick: i64,
+    /// 最新成交价的 tick 价格
+    pub last_tick: i64,
+    /// 最佳买入价，无买单时为 `NaN`
+    pub best_bid: f64,
+    /// 最佳卖出价，无卖单时为 `NaN`
+    pub best_ask: f64,
+    /// 最新成交价
+    pub last_price: f64,
+    /// 买方档位信息 (价格, 数量, 订单数)
+    pub bid_levels: Vec<(f64, f64, i64)>,
+    /// 卖方档位信息 (价格, 数量, 订单数)
+    pub ask_levels: Vec<(f64, f64, i64)>,
+}
+
+/// `BboRow` 是某一只股票的最优价，用于一次性查询多只股票。
+///
+/// 价格与对应的单只股票查询接口一致，没有买单或卖单时为 `NaN`。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BboRow {
+    pub stock_code: String,
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub best_bid_tick: i64,
+    pub best_ask_tick: i64,
+    pub last_price: f64,
+    /// 经纪人的当前时间戳
+    pub timestamp: i64,
+}
+
+/// `LevelView` 是订单簿某一方的一个价格档位，用于逐档比较和导出。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LevelView {
+    pub side: Side,
+    /// 档位序号，从 0 开始，0 为最优价
+    pub level_idx: usize,
+    /// 档位价格，买卖双方都为正数
+    pub price: f64,
+    pub qty: f64,
+    /// 档位中的订单数量
+    pub count: i64,
+    /// `qty` 是否为回测模式下包含用户订单影响的影子数量
+    pub is_shadow: bool,
+}
+
+impl LevelView {
+    /// 将 `get_orderbook_level` 输出的档位转换为 `LevelView`，买方在前。
+    ///
+    /// # 参数
+    /// - `bid_levels`: 买方档位 (价格, 数量, 订单数)，按价格从高到低排列。
+    /// - `ask_levels`: 卖方档位 (价格, 数量, 订单数)，按价格从低到高排列。
+    /// - `is_shadow`: 数量是否为影子数量。
+    pub fn from_levels(
+        bid_levels: &[(f64, f64, i64)],
+        ask_levels: &[(f64, f64, i64)],
+        is_shadow: bool,
+    ) -> Vec<LevelView> {
+        let side_levels = |side: Side, levels: &[(f64, f64, i64)]| {
+            levels
+                .iter()
+                .enumerate()
+                .map(|(level_idx, &(price, qty, count))| LevelView {
+                    side,
+                    level_idx,
+                    price: price.abs(),
+                    qty,
+                    count,
+                    is_shadow,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut views = side_levels(Side::Buy, bid_levels);
+        views.extend(side_levels(Side::Sell, ask_levels));
+        views
+    }
+}
+
+/// 将档位转换为 `DataFrame`，每个档位一行。
+///
+/// | 列 | 类型 | 说明 |
+/// | --- | --- | --- |
+/// | `side` | str | `BUY` 或 `SELL` |
+/// | `level_idx` | i64 | 档位序号，从 0 开始 |
+/// | `price` | f64 | 档位价格 |
+/// | `qty` | f64 | 档位数量 |
+/// | `count` | i64 | 档位中的订单数量 |
+/// | `is_shadow` | bool | 数量是否为影子数量 |
+#[cfg(feature = "data")]
+pub fn levels_to_dataframe(levels: &[LevelView]) -> PolarsResult<DataFrame> {
+    let side: Vec<&str> = levels.iter().map(|level| level.side.as_ref()).collect();
+    let level_idx: Vec<i64> = levels.iter().map(|level| level.level_idx as i64).collect();
+    let price: Vec<f64> = levels.iter().map(|level| level.price).collect();
+    let qty: Vec<f64> = levels.iter().map(|level| level.qty).collect();
+    let count: Vec<i64> = levels.iter().map(|level| level.count).collect();
+    let is_shadow: Vec<bool> = levels.iter().map(|level| level.is_shadow).collect();
+    df!(
+        "side" => side,
+        "level_idx" => level_idx,
+        "price" => price,
+        "qty" => qty,
+        "count" => count,
+        "is_shadow" => is_shadow,
+    )
+}
+
+/// `MarketViewBuffer` 是按时间顺序保存行情快照的环形缓冲区，
+/// 用于向策略提供延迟后的行情视图。
+#[derive(Debug, Default)]
+pub struct MarketViewBuffer {
+    views: VecDeque<MarketView>,
+    capacity: usize,
+}
+
+impl MarketViewBuffer {
+    /// 创建一个新的 `MarketViewBuffer` 实例。
+    ///
+    /// # 参数
+    /// - `capacity`: 缓冲区容量，超过容量时丢弃最旧的快照。
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            views: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    /// 追加一个行情快照，缓冲区已满时丢弃最旧的快照。
+    ///
+    /// 最旧的快照是时间戳不晚于 `time_point` 的最新快照时保留它，改为丢弃其后最旧的快照，
+    /// 使 `view_at(time_point)` 不会因为截止时间之后的快照过多而找不到快照。
+    ///
+    /// # 参数
+    /// - `view`: 新采集的快照。
+    /// - `time_point`: 当前的截止时间戳。
+    pub fn push(&mut self, view: MarketView, time_point: i64) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.views.len() >= self.capacity {
+            let keep_front = self.views.len() > 1
+                && self.views[0].timestamp <= time_point
+                && self.views[1].timestamp > time_point;
+            self.views.remove(usize::from(keep_front));
+        }
+        self.views.push_back(view);
+    }
+
+    /// 返回最新采集的快照。
+    pub fn latest(&self) -> Option<&MarketView> {
+        self.views.back()
+    }
+
+    /// 返回最早采集的快照。
+    pub fn oldest(&self) -> Option<&MarketView> {
+        self.views.front()
+    }
+
+    /// 返回时间戳不晚于 `time_point` 的最新快照。
+    ///
+    /// # 参数
+    /// - `time_point`: 截止时间戳。
+    ///
+    /// # 返回值
+    /// 如果所有快照都晚于 `time_point` 或缓冲区为空，返回 `None`，不会返回截止时间之后的快照。
+    pub fn view_at(&self, time_point: i64) -> Option<&MarketView> {
+        self.views
+            .iter()
+            .rev()
+            .find(|view| view.timestamp <= time_point)
+    }
+
+    pub fn clear(&mut self) {
+        self.views.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+}
+
+/// `Outage` 是一个模拟的交易所故障时间窗口 `[start, end)`。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outage {
+    pub start: i64,
+    pub end: i64,
+    pub kind: OutageKind,
+    /// 行情中断开始前的行情快照，在窗口内处理第一条消息前采集
+    pub frozen: Option<MarketView>,
+}
+
+impl Outage {
+    /// 判断时间戳是否处于故障窗口内。
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_view(timestamp: i64, best_bid_tick: i64) -> MarketView {
+        MarketView {
+            timestamp,
+            best_bid_tick,
+            best_ask_tick: i64::MAX,
+            last_tick: 0,
+            best_bid: best_bid_tick as f64 * 0.01,
+            best_ask: f64::NAN,
+            last_price: 0.0,
+            bid_levels: Vec::new(),
+            ask_levels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_view_at() {
+        let mut buffer = MarketViewBuffer::new(2);
+        assert!(buffer.view_at(100).is_none());
+
+        buffer.push(create_view(100, 1), 100);
+        buffer.push(create_view(200, 2), 200);
+        buffer.push(create_view(300, 3), 300);
+        assert_eq!(buffer.len(), 2);
+
+        assert_eq!(buffer.view_at(250).unwrap().best_bid_tick, 2);
+        assert_eq!(buffer.view_at(300).unwrap().best_bid_tick, 3);
+        // 所有快照都晚于截止时间时不返回任何快照
+        assert!(buffer.view_at(150).is_none());
+        assert_eq!(buffer.latest().unwrap().best_bid_tick, 3);
+    }
+
+    #[test]
+    /// 测试缓冲区已满时保留截止时间之前的最新快照。
+    /// 验证截止时间之后的快照超过容量时丢弃的是截止时间之后最旧的快照。
+    fn test_push_keeps_view_before_cutoff() {
+        let mut buffer = MarketViewBuffer::new(3);
+        buffer.push(create_view(100, 1), 100);
+        for idx in 2..=5 {
+            buffer.push(create_view(200 + idx, idx), 150);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.view_at(150).unwrap().best_bid_tick, 1);
+        assert_eq!(buffer.oldest().unwrap().best_bid_tick, 1);
+        assert_eq!(buffer.latest().unwrap().best_bid_tick, 5);
+
+        // 截止时间越过第二个快照后，不再需要保留最旧的快照
+        buffer.push(create_view(206, 6), 204);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.view_at(204).unwrap().best_bid_tick, 4);
+    }
+}
@@ -0,0 +1,389 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+#[cfg(feature = "data")]
+use std::{cmp, ops::Range};
+
+use super::broker::Broker;
+use super::observer::{DepthObserver, FillEvent};
+use super::skiplist_orderbook::SkipListMarketDepth;
+use super::*;
+
+#[cfg(feature = "data")]
+use polars::prelude::*;
+#[cfg(feature = "data")]
+use std::io::{self, Write};
+
+/// 写出 parquet 时每个行组的最大行数。
+pub const MESSAGE_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// 逐笔消息流中一条记录的类型。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageEventType {
+    /// 订单进入订单簿
+    Add,
+    /// 订单从订单簿中撤销
+    Cancel,
+    /// 主动成交订单与一个价格档位成交
+    Trade,
+}
+
+impl MessageEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageEventType::Add => "add",
+            MessageEventType::Cancel => "cancel",
+            MessageEventType::Trade => "trade",
+        }
+    }
+}
+
+/// 列式保存的逐笔消息流，价格和数量以 tick 和手表示，写出时再换算。
+#[derive(Debug, Default)]
+struct MessageColumns {
+    tick_size: f64,
+    lot_size: f64,
+    seq: Vec<i64>,
+    timestamp: Vec<i64>,
+    event_type: Vec<MessageEventType>,
+    order_id: Vec<OrderId>,
+    side: Vec<Side>,
+    price_tick: Vec<i64>,
+    vol: Vec<i64>,
+    aggressor_flag: Vec<bool>,
+    best_bid_after: Vec<i64>,
+    best_ask_after: Vec<i64>,
+    user_flag: Vec<bool>,
+    /// 当前的最优买价和最优卖价
+    best_bid_tick: i64,
+    best_ask_tick: i64,
+    /// 尚未收到最优价变化通知的记录从这一行开始
+    open_from: usize,
+    /// 连续成交的主动成交订单，同一订单的多条成交记录共享成交后的最优价
+    open_trade: Option<(OrderSourceType, OrderId)>,
+}
+
+impl MessageColumns {
+    fn len(&self) -> usize {
+        self.seq.len()
+    }
+
+    fn push(
+        &mut self,
+        seq: i64,
+        timestamp: i64,
+        event_type: MessageEventType,
+        order_id: OrderId,
+        side: Side,
+        price_tick: i64,
+        vol: i64,
+        user_flag: bool,
+    ) {
+        self.seq.push(seq);
+        self.timestamp.push(timestamp);
+        self.event_type.push(event_type);
+        self.order_id.push(order_id);
+        self.side.push(side);
+        self.price_tick.push(price_tick);
+        self.vol.push(vol);
+        self.aggressor_flag
+            .push(event_type == MessageEventType::Trade);
+        self.best_bid_after.push(self.best_bid_tick);
+        self.best_ask_after.push(self.best_ask_tick);
+        self.user_flag.push(user_flag);
+    }
+
+    fn push_order(&mut self, event_type: MessageEventType, order: &L3Order) {
+        self.open_from = self.len();
+        self.open_trade = None;
+        self.push(
+            order.seq,
+            order.timestamp,
+            event_type,
+            order.order_id,
+            order.side,
+            order.price_tick,
+            order.vol,
+            order.source == OrderSourceType::UserOrder,
+        );
+    }
+
+    /// 将 `rows` 范围内的记录转换为 `DataFrame`。
+    #[cfg(feature = "data")]
+    fn to_dataframe(&self, rows: Range<usize>) -> PolarsResult<DataFrame> {
+        let price = |ticks: &[i64], invalid: i64| -> Vec<Option<f64>> {
+            ticks
+                .iter()
+                .map(|tick| (*tick != invalid).then(|| *tick as f64 * self.tick_size))
+                .collect()
+        };
+        let side: Vec<&str> = self.side[rows.clone()]
+            .iter()
+            .map(|side| match side {
+                Side::Buy | Side::Sell => side.as_ref(),
+                _ => "NONE",
+            })
+            .collect();
+        let event_type: Vec<&str> = self.event_type[rows.clone()]
+            .iter()
+            .map(|kind| kind.as_str())
+            .collect();
+        let order_id: Vec<i64> = self.order_id[rows.clone()]
+            .iter()
+            .map(|id| *id as i64)
+            .collect();
+        let price_values: Vec<f64> = self.price_tick[rows.clone()]
+            .iter()
+            .map(|tick| *tick as f64 * self.tick_size)
+            .collect();
+        let qty: Vec<f64> = self.vol[rows.clone()]
+            .iter()
+            .map(|vol| *vol as f64 * self.lot_size)
+            .collect();
+        df!(
+            "seq" => &self.seq[rows.clone()],
+            "timestamp" => &self.timestamp[rows.clone()],
+            "event_type" => event_type,
+            "order_id" => order_id,
+            "side" => side,
+            "price" => price_values,
+            "qty" => qty,
+            "aggressor_flag" => &self.aggressor_flag[rows.clone()],
+            "best_bid_after" => price(&self.best_bid_after[rows.clone()], INVALID_MIN),
+            "best_ask_after" => price(&self.best_ask_after[rows.clone()], INVALID_MAX),
+            "user_flag" => &self.user_flag[rows],
+        )
+    }
+}
+
+/// `MessageStreamWriter` 作为市场深度的观察者，记录撮合引擎处理的每一个挂单、撤单和成交事件。
+///
+/// 与 `DepthRecorder` 不同，用户订单产生的事件也会被记录，并以 `user_flag` 标记。
+/// 没有调用 `attach` 的经纪人不会注册观察者，关闭时没有额外开销。
+///
+/// 写出的 parquet 文件每个事件一行，列定义如下：
+///
+/// | 列 | 类型 | 说明 |
+/// | --- | --- | --- |
+/// | `seq` | i64 | 引起该事件的订单的序列号 |
+/// | `timestamp` | i64 | 引起该事件的订单的时间戳 |
+/// | `event_type` | str | `add`、`cancel` 或 `trade` |
+/// | `order_id` | i64 | 挂单或撤单的订单编号，成交时为主动成交订单的编号 |
+/// | `side` | str | 订单方向，成交时为主动成交的方向，取值为 `BUY` 或 `SELL` |
+/// | `price` | f64 | 挂单或撤单的价格，成交时为成交价 |
+/// | `qty` | f64 | 挂单或撤单的剩余数量，成交时为成交数量 |
+/// | `aggressor_flag` | bool | 是否为主动成交记录 |
+/// | `best_bid_after` | f64 | 事件处理后的最优买价，没有买盘时为空 |
+/// | `best_ask_after` | f64 | 事件处理后的最优卖价，没有卖盘时为空 |
+/// | `user_flag` | bool | 是否由用户订单引起，成交记录的主动方或被动方是用户订单时均为 `true` |
+///
+/// 同一主动成交订单在多个价格档位上的成交记录共享成交后的最优价。
+#[derive(Debug, Clone, Default)]
+pub struct MessageStreamWriter {
+    columns: Rc<RefCell<MessageColumns>>,
+}
+
+impl MessageStreamWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 将写入器注册到经纪人的市场深度上，之后该经纪人处理的事件都会被记录。
+    ///
+    /// # 返回值
+    /// 注册成功返回 `true`，市场深度的观察者数量已满时返回 `false`。
+    pub fn attach(&self, broker: &mut Broker<SkipListMarketDepth>) -> bool {
+        {
+            let mut columns = self.columns.borrow_mut();
+            columns.tick_size = broker.tick_size;
+            columns.lot_size = broker.lot_size;
+            columns.best_bid_tick = broker.market_depth.best_bid_tick;
+            columns.best_ask_tick = broker.market_depth.best_ask_tick;
+        }
+        broker
+            .market_depth
+            .register_observer(Box::new(self.clone()))
+    }
+
+    /// 已经记录的事件数量。
+    pub fn len(&self) -> usize {
+        self.columns.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.borrow().len() == 0
+    }
+
+    /// 以 `DataFrame` 的形式返回到目前为止的消息流，列定义参见 `MessageStreamWriter`。
+    #[cfg(feature = "data")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let columns = self.columns.borrow();
+        columns.to_dataframe(0..columns.len())
+    }
+
+    /// 以 parquet 格式写出到目前为止的消息流，每 `MESSAGE_ROW_GROUP_SIZE` 行为一个行组。
+    ///
+    /// 通过 polars 的分批写入器逐个行组写出，同一时间只构造一个行组的 `DataFrame`。
+    ///
+    /// # 错误
+    /// 构造或写出数据失败时返回 `SnapshotError::Io`。
+    #[cfg(feature = "data")]
+    pub fn write_parquet<W: Write>(&self, w: W) -> Result<(), SnapshotError> {
+        let to_io = |err: PolarsError| io::Error::new(io::ErrorKind::Other, err.to_string());
+        let columns = self.columns.borrow();
+        let schema = columns.to_dataframe(0..0).map_err(to_io)?.schema();
+        let mut writer = ParquetWriter::new(w)
+            .with_compression(ParquetCompression::Snappy)
+            .batched(&schema)
+            .map_err(to_io)?;
+        for start in (0..columns.len()).step_by(MESSAGE_ROW_GROUP_SIZE) {
+            let end = cmp::min(start + MESSAGE_ROW_GROUP_SIZE, columns.len());
+            let df = columns.to_dataframe(start..end).map_err(to_io)?;
+            writer.write_batch(&df).map_err(to_io)?;
+        }
+        writer.finish().map_err(to_io)?;
+        Ok(())
+    }
+}
+
+impl DepthObserver for MessageStreamWriter {
+    fn on_fill(&mut self, event: &FillEvent) {
+        if event.vol == 0 {
+            return;
+        }
+        let mut columns = self.columns.borrow_mut();
+        let aggressor = (event.source, event.order_id);
+        if columns.open_trade != Some(aggressor) {
+            columns.open_from = columns.len();
+            columns.open_trade = Some(aggressor);
+        }
+        columns.push(
+            event.seq,
+            event.timestamp,
+            MessageEventType::Trade,
+            event.order_id,
+            event.side.opposite(),
+            event.price_tick,
+            event.vol,
+            // 主动方或被动方任意一方是用户订单
+            event.source == OrderSourceType::UserOrder || event.passive_user_vol > 0,
+        );
+    }
+
+    fn on_add(&mut self, order: &L3Order) {
+        self.columns
+            .borrow_mut()
+            .push_order(MessageEventType::Add, order);
+    }
+
+    fn on_cancel(&mut self, order: &L3Order) {
+        self.columns
+            .borrow_mut()
+            .push_order(MessageEventType::Cancel, order);
+    }
+
+    fn on_best_change(&mut self, side: Side, _prev_tick: i64, best_tick: i64) {
+        let mut columns = self.columns.borrow_mut();
+        let columns = &mut *columns;
+        let (best, after) = match side {
+            Side::Buy => (&mut columns.best_bid_tick, &mut columns.best_bid_after),
+            _ => (&mut columns.best_ask_tick, &mut columns.best_ask_after),
+        };
+        *best = best_tick;
+        for value in after[columns.open_from..].iter_mut() {
+            *value = best_tick;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::order::Order;
+    use crate::orderbook::synthetic::{FlowConfig, SyntheticFlow};
+    use std::collections::HashSet;
+
+    const START: i64 = 20231201091500000;
+
+    #[test]
+    #[cfg(feature = "data")]
+    fn test_message_stream_round_trip() {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SZ,
+            "STOCK".to_string(),
+            "000001.SZ".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let history = SyntheticFlow::new(FlowConfig::default())
+            .to_history("000001.SZ", START, 500, 2000, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(START);
+        let writer = MessageStreamWriter::new();
+        assert!(writer.attach(&mut broker));
+
+        broker.goto(20231201093005000).unwrap();
+        // 集合竞价之后在买卖价之间挂买单，不会立即成交，之后被历史卖单成交
+        let bid = broker.market_depth.best_bid_tick;
+        let ask = broker.market_depth.best_ask_tick;
+        assert!(bid != INVALID_MIN && ask - bid > 1);
+        let order_ref = Order::new_ref(
+            None,
+            "000001.SZ".to_string(),
+            20231201093005000,
+            (bid + 1) as f64 * 0.01,
+            100.0,
+            "Buy",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        broker.submit_order(order_ref.clone()).unwrap();
+        broker.elapse(0).unwrap();
+        broker.goto_end_of_day().unwrap();
+        assert!(!writer.is_empty());
+        assert!(order_ref.borrow().filled_qty_passive > 0.0);
+
+        let mut buf = Vec::new();
+        writer.write_parquet(&mut buf).unwrap();
+        let df = ParquetReader::new(io::Cursor::new(buf)).finish().unwrap();
+        assert_eq!(df.height(), writer.len());
+
+        let seq = df.column("seq").unwrap().i64().unwrap();
+        let user_flag = df.column("user_flag").unwrap().bool().unwrap();
+        let order_id = df.column("order_id").unwrap().i64().unwrap();
+        let event_type = df.column("event_type").unwrap().str().unwrap();
+        // 每条历史订单至少产生一条记录，记录的序列号就是历史订单的序列号；
+        // 用户订单和集合竞价的成交记录序列号为 0
+        let history_seqs: HashSet<i64> = (0..df.height())
+            .filter_map(|row| seq.get(row))
+            .filter(|seq| *seq > 0)
+            .collect();
+        assert_eq!(history_seqs.len(), broker.events_processed);
+
+        let is_user_order = |row: usize| order_id.get(row) == Some(1) && seq.get(row) == Some(0);
+        assert!(
+            (0..df.height()).any(|row| is_user_order(row) && event_type.get(row) == Some("add"))
+        );
+        for row in 0..df.height() {
+            if is_user_order(row) {
+                assert_eq!(user_flag.get(row), Some(true));
+            }
+        }
+        // 历史订单与用户挂单成交时，成交记录的编号是历史订单的编号，同样标记为用户记录
+        assert!((0..df.height()).any(|row| {
+            !is_user_order(row)
+                && event_type.get(row) == Some("trade")
+                && user_flag.get(row) == Some(true)
+        }));
+        // 最后一条记录之后的最优价就是订单簿当前的最优价
+        let best_bid = df.column("best_bid_after").unwrap().f64().unwrap();
+        let last_best_bid = best_bid.get(df.height() - 1);
+        match broker.market_depth.best_bid_tick {
+            INVALID_MIN => assert_eq!(last_best_bid, None),
+            tick => assert_eq!(last_best_bid, Some(tick as f64 * 0.01)),
+        }
+    }
+}
@@ -1,37 +1,83 @@
+/// `analytics` 模块统计回放期间订单簿的时间加权价差和深度。
+pub mod analytics;
+/// `backtest` 模块提供不依赖 Python 的单只股票回测入口。
+pub mod backtest;
+/// `bench` 模块提供基于合成委托流的基准测试场景。
+pub mod bench;
 /// `broker` 模块提供与经纪人相关的功能。
 pub mod broker;
+/// `calendar` 模块记录半日交易日并据此划分交易时段。
+pub mod calendar;
 
-/// `dataloader` 模块处理数据加载操作。
+/// `dataloader` 模块处理数据加载操作，从文件加载行情需要启用 `data` feature。
 pub mod dataloader;
 
 /// `exchange` 模块定义交易所的行为。
 pub mod exchange;
 
+/// `fee` 模块按成交的流动性角色计算用户订单的手续费和返佣。
+pub mod fee;
+
 /// `order` 模块管理订单相关操作和定义。
 pub mod order;
 
+/// `risk` 模块提供账户级别的日内风控熔断。
+pub mod risk;
+/// `schema` 模块定义对外快照的格式版本和 JSON Schema。
+pub mod schema;
 /// `skiplist_helper` 模块包含跳表操作的辅助函数。
 pub mod skiplist_helper;
 
 /// `skiplist_orderbook` 模块定义基于跳表的订单簿。
 pub mod skiplist_orderbook;
 
+/// `depth_manager` 模块提供按价格排序的深度容器以及跨股票合并的最优价行情。
 pub mod depth_manager;
 /// `statistics` 模块收集和处理交易统计数据。
 pub mod statistics;
 
+/// `dataapi` 模块从本地文件或 HDFS 读取行情数据，需要启用 `data` feature。
+#[cfg(feature = "data")]
 pub mod dataapi;
 pub mod hook;
+/// `fixture` 模块按价格档位构造订单簿，供测试使用，需要启用 `test-utils` feature。
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fixture;
+/// `idempotency` 模块记录带幂等键的请求，使重试的请求不会重复执行。
+pub mod idempotency;
+/// `linked` 模块定义同时进入市场、并可在成交后互相撤销的关联订单。
+pub mod linked;
+/// `market_view` 模块提供策略可见的延迟行情视图。
+pub mod market_view;
+/// `message_stream` 模块记录撮合引擎处理的逐笔消息流，并以 parquet 格式输出。
+pub mod message_stream;
+/// `observer` 模块定义市场深度事件的观察者。
+pub mod observer;
 pub mod prelude;
+/// `publish` 模块在回放过程中向其他线程发布经纪商状态，供看板等只读的消费者使用。
+pub mod publish;
+/// `queue_model` 模块定义回测模式下用户挂单排队位置的推进模型。
+pub mod queue_model;
+/// `reconciliation` 模块核对统计数据、用户订单和订单簿之间的数量是否一致。
+pub mod reconciliation;
+/// `reconstruction` 模块校验 L2P 模式下重建的订单簿与交易所快照是否一致。
+pub mod reconstruction;
+/// `recording` 模块录制订单簿的变化，并在不运行撮合引擎的情况下回放。
+pub mod recording;
+/// `synthetic` 模块生成确定性的合成逐笔委托流。
+pub mod synthetic;
 /// `types` 模块定义系统中使用的各种类型。
 pub mod types;
 pub mod utils;
+/// `worker` 模块提供常驻的回放线程，每只股票的经纪商在各自的线程上创建和推进。
+pub mod worker;
 use log::{debug, info};
 use order::OrderRef;
 use serde::{Deserialize, Serialize};
 use statistics::Statistics;
 use std::cell::RefCell;
 use std::cmp;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 use std::{collections::HashMap, io::Error as IoError};
 use std::{i64, usize};
@@ -75,20 +121,189 @@ pub enum MarketError {
     OrderTypeUnsupported,
     #[error("Order request is in process")]
     OrderRequestInProcess,
+    #[error("Order queue is full, capacity {capacity}")]
+    OrderQueueFull { capacity: usize },
     #[error("Order not found")]
     OrderNotFound,
     #[error("order request is invalid")]
     InvalidOrderRequest,
+    #[error("order price is not a multiple of the tick size")]
+    PriceNotOnTick,
+    #[error("order price is outside the price limits")]
+    PriceOutOfLimit,
+    #[error("price tick {0} is outside the valid range")]
+    PriceTickOutOfRange(i64),
+    #[error("invalid corporate action: {0}")]
+    InvalidCorporateAction(String),
+    #[error("grid interval {0} ms is not positive")]
+    InvalidGridInterval(i64),
+    #[error("pacing speed {0} is not positive")]
+    InvalidPacingSpeed(String),
     #[error("order status is invalid to proceed the request")]
     InvalidOrderStatus,
     #[error("end of data")]
     EndOfData,
     #[error("exchange mode is not supported")]
     ExchangeModeUnsupproted,
+    #[error("unknown exchange mode {0:?}, expected one of: backtest, live")]
+    UnknownExchangeMode(String),
+    #[error("data load error: {0}")]
+    DataLoadError(String),
+    #[error("data export error: {0}")]
+    DataExportError(String),
+    #[error("parent order not found")]
+    ParentOrderNotExist,
+    #[error("child order quantity exceeds the remaining parent order quantity")]
+    ParentQtyExceeded,
+    #[error("account is halted by the circuit breaker")]
+    AccountHalted,
+    #[error("order gateway is unavailable")]
+    GatewayUnavailable,
+    #[error("circuit breaker for account not found")]
+    CircuitBreakerNotExist,
+    #[error("hook named {0} already exists")]
+    HookNameExists(String),
+    #[error("hooks cannot be modified while time is elapsing")]
+    HookModifyWhileElapsing,
+    #[error("idempotency key {0} was used with different parameters")]
+    IdempotencyConflict(String),
+    #[error("invalid backtest arguments: {0}")]
+    InvalidBacktestArgs(String),
+    #[error("replay worker is unavailable")]
+    WorkerUnavailable,
+    #[error("broker orders are not initialized")]
+    OrdersNotInitialized,
+    #[error("brokers for stocks are not exist: {stock_codes:?}, {applied} applied")]
+    StockBrokersNotExist {
+        applied: usize,
+        stock_codes: Vec<String>,
+    },
     // #[error("data error: {0:?}")]
     // DataError(#[from] IoError),
 }
 
+/// `MarketError` 的分类，调用方可以据此统一处理同一类错误，例如区分风控拒单和数据错误。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// 请求的经纪人、订单或其他对象不存在
+    NotFound,
+    /// 请求的参数、订单类型或当前状态不允许执行该请求
+    InvalidRequest,
+    /// 行情数据缺失、解析失败或无法恢复
+    DataError,
+    /// 被风控或容量限制拒绝
+    RiskRejected,
+    /// 报单通道中断，请求没有到达交易所
+    Connectivity,
+    /// 历史数据已经用尽
+    EndOfData,
+}
+
+impl MarketError {
+    /// 返回错误所属的分类。
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            MarketError::StockBrokerNotExist
+            | MarketError::StockBrokersNotExist { .. }
+            | MarketError::OrderNotFound
+            | MarketError::ParentOrderNotExist
+            | MarketError::CircuitBreakerNotExist => ErrorCategory::NotFound,
+            MarketError::RecoverFailed
+            | MarketError::ParseError
+            | MarketError::HistoryIsNone
+            | MarketError::DataLoadError(_)
+            | MarketError::DataExportError(_) => ErrorCategory::DataError,
+            MarketError::OrderQueueFull { .. }
+            | MarketError::ParentQtyExceeded
+            | MarketError::AccountHalted => ErrorCategory::RiskRejected,
+            MarketError::GatewayUnavailable => ErrorCategory::Connectivity,
+            MarketError::EndOfData => ErrorCategory::EndOfData,
+            MarketError::MarketTypeUnknownError
+            | MarketError::InvalidTimestamp
+            | MarketError::StockTypeUnSupported
+            | MarketError::MarketSideError
+            | MarketError::StockBrokerIdExist
+            | MarketError::StockDataExist
+            | MarketError::OrderIdExist
+            | MarketError::OrderTypeUnsupported
+            | MarketError::OrderRequestInProcess
+            | MarketError::InvalidOrderRequest
+            | MarketError::PriceNotOnTick
+            | MarketError::PriceOutOfLimit
+            | MarketError::PriceTickOutOfRange(_)
+            | MarketError::InvalidGridInterval(_)
+            | MarketError::InvalidPacingSpeed(_)
+            | MarketError::InvalidCorporateAction(_)
+            | MarketError::InvalidOrderStatus
+            | MarketError::ExchangeModeUnsupproted
+            | MarketError::UnknownExchangeMode(_)
+            | MarketError::HookNameExists(_)
+            | MarketError::HookModifyWhileElapsing
+            | MarketError::IdempotencyConflict(_)
+            | MarketError::InvalidBacktestArgs(_)
+            | MarketError::WorkerUnavailable
+            | MarketError::OrdersNotInitialized => ErrorCategory::InvalidRequest,
+        }
+    }
+
+    /// 返回错误的稳定代码，即变体的名称，不随错误信息的措辞变化。
+    pub fn code(&self) -> &'static str {
+        match self {
+            MarketError::MarketTypeUnknownError => "MarketTypeUnknownError",
+            MarketError::RecoverFailed => "RecoverFailed",
+            MarketError::InvalidTimestamp => "InvalidTimestamp",
+            MarketError::ParseError => "ParseError",
+            MarketError::StockTypeUnSupported => "StockTypeUnSupported",
+            MarketError::HistoryIsNone => "HistoryIsNone",
+            MarketError::MarketSideError => "MarketSideError",
+            MarketError::StockBrokerIdExist => "StockBrokerIdExist",
+            MarketError::StockBrokerNotExist => "StockBrokerNotExist",
+            MarketError::StockDataExist => "StockDataExist",
+            MarketError::OrderIdExist => "OrderIdExist",
+            MarketError::OrderTypeUnsupported => "OrderTypeUnsupported",
+            MarketError::OrderRequestInProcess => "OrderRequestInProcess",
+            MarketError::OrderQueueFull { .. } => "OrderQueueFull",
+            MarketError::OrderNotFound => "OrderNotFound",
+            MarketError::InvalidOrderRequest => "InvalidOrderRequest",
+            MarketError::PriceNotOnTick => "PriceNotOnTick",
+            MarketError::PriceOutOfLimit => "PriceOutOfLimit",
+            MarketError::PriceTickOutOfRange(_) => "PriceTickOutOfRange",
+            MarketError::InvalidGridInterval(_) => "InvalidGridInterval",
+            MarketError::InvalidPacingSpeed(_) => "InvalidPacingSpeed",
+            MarketError::InvalidCorporateAction(_) => "InvalidCorporateAction",
+            MarketError::InvalidOrderStatus => "InvalidOrderStatus",
+            MarketError::EndOfData => "EndOfData",
+            MarketError::ExchangeModeUnsupproted => "ExchangeModeUnsupproted",
+            MarketError::UnknownExchangeMode(_) => "UnknownExchangeMode",
+            MarketError::DataLoadError(_) => "DataLoadError",
+            MarketError::DataExportError(_) => "DataExportError",
+            MarketError::ParentOrderNotExist => "ParentOrderNotExist",
+            MarketError::ParentQtyExceeded => "ParentQtyExceeded",
+            MarketError::AccountHalted => "AccountHalted",
+            MarketError::GatewayUnavailable => "GatewayUnavailable",
+            MarketError::CircuitBreakerNotExist => "CircuitBreakerNotExist",
+            MarketError::HookNameExists(_) => "HookNameExists",
+            MarketError::HookModifyWhileElapsing => "HookModifyWhileElapsing",
+            MarketError::IdempotencyConflict(_) => "IdempotencyConflict",
+            MarketError::InvalidBacktestArgs(_) => "InvalidBacktestArgs",
+            MarketError::StockBrokersNotExist { .. } => "StockBrokersNotExist",
+            MarketError::WorkerUnavailable => "WorkerUnavailable",
+            MarketError::OrdersNotInitialized => "OrdersNotInitialized",
+        }
+    }
+}
+
+/// 快照读写过程中可能出现的错误。
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("snapshot io error: {0}")]
+    Io(#[from] IoError),
+    #[error("snapshot serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("broker is not exists")]
+    StockBrokerNotExist,
+}
+
 /// 定义市场深度操作的方法的 trait。
 pub trait MarketDepth {
     /// 使用给定的模式、tick 大小和 lot 大小创建新的实现类型实例。
@@ -113,6 +328,46 @@ pub trait MarketDepth {
     /// 如果没有最佳卖出价，返回 [`INVALID_MAX`]。
     fn best_ask_tick(&self, source: &OrderSourceType) -> i64;
 
+    /// 按 `mode` 返回最佳买入价格的 ticks 值。
+    /// 如果没有最佳买入价，返回 [`INVALID_MIN`]。
+    fn best_bid_tick_with_mode(&self, mode: BestMode) -> i64 {
+        let real = self.best_bid_tick(&OrderSourceType::LocalOrder);
+        let shadow = self.best_bid_tick(&OrderSourceType::UserOrder);
+        match mode {
+            BestMode::RealOnly => real,
+            BestMode::ShadowOnly => shadow,
+            BestMode::Combined => cmp::max(real, shadow),
+        }
+    }
+
+    /// 按 `mode` 返回最佳卖出价格的 ticks 值。
+    /// 如果没有最佳卖出价，返回 [`INVALID_MAX`]。
+    fn best_ask_tick_with_mode(&self, mode: BestMode) -> i64 {
+        let real = self.best_ask_tick(&OrderSourceType::LocalOrder);
+        let shadow = self.best_ask_tick(&OrderSourceType::UserOrder);
+        match mode {
+            BestMode::RealOnly => real,
+            BestMode::ShadowOnly => shadow,
+            BestMode::Combined => cmp::min(real, shadow),
+        }
+    }
+
+    /// 按 `mode` 返回最佳买入价格，没有最佳买入价时返回 [`f64::NAN`]。
+    fn best_bid_with_mode(&self, mode: BestMode) -> f64 {
+        match self.best_bid_tick_with_mode(mode) {
+            INVALID_MIN => f64::NAN,
+            best_tick => best_tick as f64 * self.tick_size(),
+        }
+    }
+
+    /// 按 `mode` 返回最佳卖出价格，没有最佳卖出价时返回 [`f64::NAN`]。
+    fn best_ask_with_mode(&self, mode: BestMode) -> f64 {
+        match self.best_ask_tick_with_mode(mode) {
+            INVALID_MAX => f64::NAN,
+            best_tick => best_tick as f64 * self.tick_size(),
+        }
+    }
+
     /// 返回 tick 大小。
     fn tick_size(&self) -> f64;
 
@@ -128,18 +383,44 @@ pub trait MarketDepth {
     /// 将订单添加到市场深度中，并返回结果。
     fn add(&mut self, order: L3OrderRef) -> Result<i64, MarketError>;
 
-    /// 匹配订单并返回结果。
-    fn match_order(&mut self, order_ref: L3OrderRef, max_depth: i64) -> Result<i64, MarketError>;
+    /// 匹配订单并返回结果，只与价格不差于订单 `price_tick` 的档位成交。
+    fn match_order(&mut self, order_ref: L3OrderRef, max_depth: i64) -> Result<i64, MarketError> {
+        self.match_order_with_bound(order_ref, max_depth, MatchBound::Limit)
+    }
+
+    /// 按给定的价格边界匹配订单并返回成交量。
+    ///
+    /// 市价订单使用 `MatchBound::Unbounded`，不需要把 `price_tick` 设置为哨兵值。
+    fn match_order_with_bound(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<i64, MarketError>;
+
+    /// 判断订单能否在 `max_depth` 档以内完全成交，只计算价格不差于订单 `price_tick` 的档位。
     fn try_match_order(
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+    ) -> Result<bool, MarketError> {
+        self.try_match_order_with_bound(order_ref, max_depth, MatchBound::Limit)
+    }
+
+    /// 按给定的价格边界判断订单能否完全成交。
+    fn try_match_order_with_bound(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
     ) -> Result<bool, MarketError>;
+
     /// 匹配买入深度并返回结果。
     fn match_bid_depth(
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<i64, MarketError>;
 
     /// 匹配卖出深度并返回结果。
@@ -147,13 +428,29 @@ pub trait MarketDepth {
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<i64, MarketError>;
 
     fn get_bid_level(&self, level_num: usize) -> String;
     fn get_ask_level(&self, level_num: usize) -> String;
     ///返回开盘价和成交量，如果时间不在集合竞价阶段返回错误
     fn call_auction(&mut self) -> Result<(i64, i64), MarketError>;
-    fn set_previous_close_tick(&mut self, previous_close_price: i64);
+    /// 在涨跌停价格范围内进行集合竞价，返回成交价和成交量。
+    ///
+    /// 清算价格超出涨停价（跌停价）时按涨停价（跌停价）成交，该价格上无法满足的数量保留在订单簿中。
+    ///
+    /// # 参数
+    /// - `limit_down_tick`: 跌停价的 tick 价格，`None` 表示不设下限。
+    /// - `limit_up_tick`: 涨停价的 tick 价格，`None` 表示不设上限。
+    fn call_auction_with_limits(
+        &mut self,
+        limit_down_tick: Option<i64>,
+        limit_up_tick: Option<i64>,
+    ) -> Result<(i64, i64), MarketError>;
+    /// 设置前收盘价的 tick 价格，`None` 表示前收盘价未知。
+    fn set_previous_close_tick(&mut self, previous_close_tick: Option<i64>);
+    /// 在给定时间对订单簿的最优价和最优价挂单量采样，用于时间加权统计。
+    fn sample_analytics(&mut self, timestamp: i64);
 }
 
 ///用于辅助还原市场下单的
@@ -170,6 +467,9 @@ pub struct L30LocalOrderInfo {
     pub initial_seq: i64,
     pub initial_price: f64,
     pub cancel_seq: i64,
+    /// 撤单消息中的撤销数量，为 0 时撤销全部剩余数量
+    #[serde(default)]
+    pub cancel_qty: f64,
 }
 
 impl Default for L30LocalOrderInfo {
@@ -186,6 +486,7 @@ impl Default for L30LocalOrderInfo {
             initial_seq: i64::MAX,
             initial_price: 0.0,
             cancel_seq: i64::MAX,
+            cancel_qty: 0.0,
         }
     }
 }
@@ -221,6 +522,9 @@ pub struct L3Order {
     pub seq: i64,
     pub source: OrderSourceType,
     pub account: Option<String>,
+    /// 用户订单所属的策略编号，历史订单为 0
+    #[serde(default)]
+    pub strategy_id: StrategyId,
     pub order_id: OrderId,
     pub side: Side,
     /// 除以tick size后的值
@@ -239,6 +543,21 @@ pub struct L3Order {
     // pub should_add: i64,–
     #[serde(skip)]
     pub dirty: bool,
+    /// 为情景分析注入的假设挂单，参与撮合但不计入市场统计
+    #[serde(default)]
+    pub phantom: bool,
+    /// 排在该用户订单之前被撤销、但按排队模型没有推进其位置的数量，之后的成交先抵扣这部分
+    #[serde(default)]
+    pub queue_debt: i64,
+    /// 用户挂单加入价格层级时该层级的累计成交量，用于计算排在它前面成交的量
+    #[serde(default)]
+    pub traded_mark: i64,
+    /// 限价订单以优于限价的价格成交时累计的价格改善，以 tick × 手为单位，买卖方向均以正数表示改善
+    #[serde(default)]
+    pub price_improvement_tick: i64,
+    /// 作为主动方成交的成交金额，以 tick × 手为单位
+    #[serde(default)]
+    pub aggressive_amount_tick: i64,
     pub auxiliary_info: Option<L30LocalOrderInfo>,
 }
 
@@ -268,6 +587,7 @@ impl L3Order {
             seq: 0,
             source: source,
             account: account,
+            strategy_id: 0,
             order_id: order_id,
             side: side,
             price_tick: price_tick,
@@ -277,6 +597,11 @@ impl L3Order {
             timestamp: timestamp,
             total_vol_before: 0,
             dirty: false,
+            phantom: false,
+            queue_debt: 0,
+            traded_mark: 0,
+            price_improvement_tick: 0,
+            aggressive_amount_tick: 0,
             auxiliary_info: auxiliary_info,
             order_type: order_type,
         }
@@ -296,6 +621,54 @@ impl L3Order {
             source, account, order_id, side, price_tick, vol, timestamp, order_type,
         )))
     }
+    /// 剩余未成交的数量，即 `vol` 乘以 `lot_size`。
+    pub fn left_qty(&self, lot_size: f64) -> f64 {
+        utils::lots_to_qty(self.vol, lot_size)
+    }
+
+    /// 委托数量为 `qty` 时已成交的数量。
+    ///
+    /// `L3Order` 只保存剩余的数量，委托数量由调用方提供。
+    pub fn filled_qty(&self, qty: f64, lot_size: f64) -> f64 {
+        qty - self.left_qty(lot_size)
+    }
+
+    /// 记录订单作为主动方在 `price_tick` 成交 `vol` 手，累计成交金额和价格改善。
+    pub fn add_aggressive_fill(&mut self, price_tick: i64, vol: i64) {
+        self.aggressive_amount_tick += price_tick * vol;
+        self.add_price_improvement(price_tick, vol);
+    }
+
+    /// 记录限价订单在 `price_tick` 成交 `vol` 手时相对限价的价格改善。
+    ///
+    /// 买单的改善为限价减去成交价，卖单为成交价减去限价，其他类型的订单不记录。
+    pub fn add_price_improvement(&mut self, price_tick: i64, vol: i64) {
+        if self.order_type != OrderType::L {
+            return;
+        }
+        let improvement = match self.side {
+            Side::Buy => self.price_tick - price_tick,
+            Side::Sell => price_tick - self.price_tick,
+            _ => return,
+        };
+        self.price_improvement_tick += improvement * vol;
+    }
+}
+
+/// 订单的相等性和哈希只取决于 `(source, order_id)`，历史订单和用户订单的 ID 可能重复。
+impl PartialEq for L3Order {
+    fn eq(&self, other: &L3Order) -> bool {
+        self.source == other.source && self.order_id == other.order_id
+    }
+}
+
+impl Eq for L3Order {}
+
+impl Hash for L3Order {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+        self.order_id.hash(state);
+    }
 }
 
 pub type L3OrderRef = Rc<RefCell<L3Order>>;
@@ -338,18 +711,38 @@ pub trait L3MarketDepth: MarketDepth {
         &mut self,
         order_ref: L3OrderRef,
     ) -> Result<(Side, i64, i64), Self::Error>;
+
+    /// Cancels `vol` of the order's remaining volume while keeping its queue position, and returns
+    /// a tuple containing (side, the previous best in ticks, the current best in ticks). Cancelling
+    /// the whole remaining volume deletes the order.
+    fn reduce_order_from_ref(
+        &mut self,
+        order_ref: L3OrderRef,
+        vol: i64,
+    ) -> Result<(Side, i64, i64), Self::Error>;
+
+    /// Sets the last traded price in ticks.
+    fn set_last_tick(&mut self, last_tick: i64);
+
+    /// 按除权除息调整所有挂单的价格和数量，数量四舍五入后为 0 的挂单被撤销。
+    fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<(), MarketError>;
+
+    /// Moves the resting order to the front of its price level without changing its volume.
+    fn move_to_front(&mut self, order_ref: L3OrderRef) -> Result<(), Self::Error>;
     fn update_bid_depth(&mut self) -> Result<i64, MarketError>;
     fn update_ask_depth(&mut self) -> Result<i64, MarketError>;
 
-    /// Modifies the order in the order book and returns a tuple containing (side, the previous best
-    /// in ticks, the current best in ticks).
+    /// Takes the order out of the order book and resets its price, remaining quantity and
+    /// timestamp, returning it for re-entry. `qty` is the remaining quantity after the modify.
+    /// The caller submits the returned order again so that it is matched against the opposite
+    /// side before resting, see `Broker::modify_order`.
     fn modify_order(
         &mut self,
         order_id: OrderId,
         px: f64,
         qty: f64,
         timestamp: i64,
-    ) -> Result<(Side, i64, i64), Self::Error>;
+    ) -> Result<L3OrderRef, Self::Error>;
 
     /// clean filled orders and canceled orders
     fn clean_orders(&mut self);
@@ -357,6 +750,12 @@ pub trait L3MarketDepth: MarketDepth {
     /// Returns the orders held in the order book.
     fn orders(&self) -> &HashMap<OrderId, L3OrderRef>;
     fn orders_mut(&mut self) -> &mut HashMap<OrderId, L3OrderRef>;
+    /// 设置回测模式下用户挂单的排队模型，`None` 表示排在前面的撤单量全部推进排队位置。
+    fn set_queue_model(&mut self, model: Option<Box<dyn queue_model::QueueModel>>);
+    /// 假设挂单全部移除后调用，之后的撮合不再统计与假设挂单的成交量。默认不做任何处理。
+    fn clear_phantoms(&mut self) {}
+    /// 返回 (所有价格档位记录的挂单量之和, 档位中存活订单的剩余数量之和)，用于对账。
+    fn resting_vol(&self) -> (i64, i64);
     fn get_orderbook_level(
         &self,
         bid_vec: &mut Vec<(f64, f64, i64)>,
@@ -378,7 +777,7 @@ pub trait Processor {
         current_timestamp: i64,
     ) -> Result<(), MarketError>;
     fn cancel(&mut self, order_id: OrderId, current_timestamp: i64) -> Result<(), MarketError>;
-    fn orders(&self) -> &HashMap<OrderId, OrderRef>;
+    fn orders(&self) -> Result<&HashMap<OrderId, OrderRef>, MarketError>;
 }
 
 pub trait OrderIter {
@@ -403,10 +802,16 @@ pub trait SnapshotOp {
 
 pub trait StatisticsOp {
     fn get_statistics(&self) -> &Statistics;
+    fn get_statistics_mut(&mut self) -> &mut Statistics;
 }
 
 pub trait RecoverOp {
     fn recover(&mut self) -> Result<bool, MarketError>;
+
+    /// 返回订单簿中所有存活的挂单，包括历史订单。默认返回空列表。
+    fn resting_orders(&self) -> Vec<L3OrderRef> {
+        Vec::new()
+    }
 }
 
 pub trait PriceLevelOp {
@@ -414,3 +819,84 @@ pub trait PriceLevelOp {
     fn is_deleted(&self) -> bool;
     fn set_deleted(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_l3order_set_by_id() {
+        let order = |source, order_id, price_tick| {
+            L3Order::new(
+                source,
+                None,
+                order_id,
+                Side::Buy,
+                price_tick,
+                1,
+                20231201093000000,
+                OrderType::L,
+            )
+        };
+        let mut orders = HashSet::new();
+        assert!(orders.insert(order(OrderSourceType::LocalOrder, 1, 1000)));
+        assert!(orders.insert(order(OrderSourceType::LocalOrder, 2, 1000)));
+        // 价格不同但 ID 相同的订单被视为同一订单
+        assert!(!orders.insert(order(OrderSourceType::LocalOrder, 1, 1001)));
+        // 用户订单可以与历史订单使用相同的 ID
+        assert!(orders.insert(order(OrderSourceType::UserOrder, 1, 1000)));
+        assert_eq!(orders.len(), 3);
+        assert!(orders.contains(&order(OrderSourceType::UserOrder, 1, 999)));
+        assert_ne!(
+            order(OrderSourceType::LocalOrder, 1, 1000),
+            order(OrderSourceType::UserOrder, 1, 1000)
+        );
+    }
+
+    #[test]
+    fn test_error_category_and_code() {
+        let cases = [
+            (MarketError::OrderNotFound, ErrorCategory::NotFound),
+            (MarketError::StockBrokerNotExist, ErrorCategory::NotFound),
+            (
+                MarketError::StockBrokersNotExist {
+                    applied: 0,
+                    stock_codes: vec!["600000.SH".to_string()],
+                },
+                ErrorCategory::NotFound,
+            ),
+            (MarketError::InvalidOrderRequest, ErrorCategory::InvalidRequest),
+            (MarketError::PriceOutOfLimit, ErrorCategory::InvalidRequest),
+            (
+                MarketError::HookNameExists("snapshot".to_string()),
+                ErrorCategory::InvalidRequest,
+            ),
+            (
+                MarketError::DataLoadError("missing".to_string()),
+                ErrorCategory::DataError,
+            ),
+            (MarketError::HistoryIsNone, ErrorCategory::DataError),
+            (MarketError::AccountHalted, ErrorCategory::RiskRejected),
+            (
+                MarketError::OrderQueueFull { capacity: 1 },
+                ErrorCategory::RiskRejected,
+            ),
+            (MarketError::GatewayUnavailable, ErrorCategory::Connectivity),
+            (MarketError::EndOfData, ErrorCategory::EndOfData),
+        ];
+        for (err, category) in cases {
+            assert_eq!(err.category(), category, "{err:?}");
+        }
+        // 代码不包含错误信息中的参数
+        assert_eq!(
+            MarketError::DataLoadError("missing".to_string()).code(),
+            "DataLoadError"
+        );
+        assert_eq!(
+            MarketError::OrderQueueFull { capacity: 1 }.code(),
+            "OrderQueueFull"
+        );
+        assert_eq!(MarketError::OrderNotFound.code(), "OrderNotFound");
+    }
+}
@@ -0,0 +1,195 @@
+use super::hook::DEFAULT_MAX_HOOK_PANICS;
+use super::*;
+use log::warn;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+/// 单个市场深度最多可注册的观察者数量。
+pub const MAX_DEPTH_OBSERVERS: usize = 4;
+
+/// `FillEvent` 描述订单在某个价格档位上的一次成交。
+#[derive(Debug, Clone, Copy)]
+pub struct FillEvent {
+    /// 被匹配的深度方向，`Side::Buy` 表示买盘被吃掉
+    pub side: Side,
+    /// 成交价格，以 tick 表示
+    pub price_tick: i64,
+    /// 被匹配的价格档位，以 tick 表示。开盘前按订单价格成交时可能与 `price_tick` 不同
+    pub level_tick: i64,
+    /// 本档位的成交量
+    pub vol: i64,
+    /// 订单截至本档位的累计成交量
+    pub total_vol: i64,
+    /// 本档位成交量中与假设挂单成交的部分
+    pub phantom_vol: i64,
+    /// 本档位成交量中与用户挂单成交的部分，只在注册了观察者时统计
+    pub passive_user_vol: i64,
+    /// 主动成交订单的来源
+    pub source: OrderSourceType,
+    /// 主动成交订单的编号
+    pub order_id: OrderId,
+    /// 主动成交订单的序列号
+    pub seq: i64,
+    /// 主动成交订单的时间戳
+    pub timestamp: i64,
+}
+
+/// 订阅市场深度变化事件的观察者。
+///
+/// 所有方法都有空的默认实现，观察者只需要实现关心的事件。
+pub trait DepthObserver {
+    /// 订单在某个价格档位成交时调用。
+    fn on_fill(&mut self, _event: &FillEvent) {}
+
+    /// 订单被加入市场深度时调用。
+    fn on_add(&mut self, _order: &L3Order) {}
+
+    /// 订单从市场深度中撤销时调用。
+    fn on_cancel(&mut self, _order: &L3Order) {}
+
+    /// 最优价格发生变化时调用，总是在引起变化的成交、挂单或撤单事件之后。
+    fn on_best_change(&mut self, _side: Side, _prev_tick: i64, _best_tick: i64) {}
+}
+
+/// `DepthObservers` 以定长数组保存已注册的观察者，分发事件时不产生额外的内存分配。
+///
+/// 观察者 panic 时事件分发不会中断，panic 达到 `max_panics` 次的观察者被移除。
+pub struct DepthObservers {
+    observers: [Option<Box<dyn DepthObserver>>; MAX_DEPTH_OBSERVERS],
+    panics: [u32; MAX_DEPTH_OBSERVERS],
+    len: usize,
+    max_panics: u32,
+}
+
+impl Default for DepthObservers {
+    fn default() -> Self {
+        Self {
+            observers: Default::default(),
+            panics: [0; MAX_DEPTH_OBSERVERS],
+            len: 0,
+            max_panics: DEFAULT_MAX_HOOK_PANICS,
+        }
+    }
+}
+
+impl fmt::Debug for DepthObservers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DepthObservers")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl DepthObservers {
+    /// 注册一个观察者。
+    ///
+    /// # 返回值
+    /// 注册成功返回 `true`，观察者数量已达到 `MAX_DEPTH_OBSERVERS` 时返回 `false`。
+    pub fn register(&mut self, observer: Box<dyn DepthObserver>) -> bool {
+        if self.len >= MAX_DEPTH_OBSERVERS {
+            return false;
+        }
+        self.observers[self.len] = Some(observer);
+        self.panics[self.len] = 0;
+        self.len += 1;
+        true
+    }
+
+    /// 移除所有观察者。
+    pub fn clear(&mut self) {
+        for observer in self.observers.iter_mut() {
+            *observer = None;
+        }
+        self.len = 0;
+    }
+
+    /// 设置观察者允许 panic 的次数，达到后观察者被移除，默认为 1。
+    ///
+    /// # 参数
+    /// - `max_panics`: 允许的次数，小于 1 时按 1 处理。
+    pub fn set_max_panics(&mut self, max_panics: u32) {
+        self.max_panics = cmp::max(max_panics, 1);
+    }
+
+    /// 依次通知观察者，捕获观察者的 panic。
+    ///
+    /// 观察者只拿到事件的共享引用，panic 不会破坏市场深度；panic 的观察者自身的状态
+    /// 可能不完整，达到次数后被移除，不再使用。
+    fn dispatch(&mut self, mut notify: impl FnMut(&mut dyn DepthObserver)) {
+        let mut removed = false;
+        for (slot, observer) in self.observers[..self.len].iter_mut().enumerate() {
+            let Some(inner) = observer else {
+                continue;
+            };
+            if panic::catch_unwind(AssertUnwindSafe(|| notify(inner.as_mut()))).is_ok() {
+                continue;
+            }
+            self.panics[slot] += 1;
+            warn!(
+                "depth observer {slot} panicked ({}/{})",
+                self.panics[slot], self.max_panics
+            );
+            if self.panics[slot] >= self.max_panics {
+                warn!("depth observer {slot} removed after panicking");
+                *observer = None;
+                removed = true;
+            }
+        }
+        if removed {
+            self.compact();
+        }
+    }
+
+    /// 把剩余的观察者移到数组前部，保持注册顺序。
+    fn compact(&mut self) {
+        let mut len = 0;
+        for slot in 0..self.len {
+            if self.observers[slot].is_some() {
+                self.observers.swap(len, slot);
+                self.panics[len] = self.panics[slot];
+                len += 1;
+            }
+        }
+        self.len = len;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    pub fn on_fill(&mut self, event: &FillEvent) {
+        if self.len == 0 {
+            return;
+        }
+        self.dispatch(|observer| observer.on_fill(event));
+    }
+
+    #[inline(always)]
+    pub fn on_add(&mut self, order: &L3Order) {
+        if self.len == 0 {
+            return;
+        }
+        self.dispatch(|observer| observer.on_add(order));
+    }
+
+    #[inline(always)]
+    pub fn on_cancel(&mut self, order: &L3Order) {
+        if self.len == 0 {
+            return;
+        }
+        self.dispatch(|observer| observer.on_cancel(order));
+    }
+
+    #[inline(always)]
+    pub fn on_best_change(&mut self, side: Side, prev_tick: i64, best_tick: i64) {
+        if self.len == 0 || prev_tick == best_tick {
+            return;
+        }
+        self.dispatch(|observer| observer.on_best_change(side, prev_tick, best_tick));
+    }
+}
@@ -1,7 +1,12 @@
-use super::{L3Order, L3OrderRef, OrderId, OrderSourceType, OrderStatus, OrderType, Side};
+use super::utils::{qty_to_lots, validate_price_qty};
+use super::{
+    FillLiquidity, L3Order, L3OrderRef, MarketError, OrderId, OrderSourceType, OrderStatus,
+    OrderType, Side, StrategyId,
+};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use serde::Deserialize;
 use std::cmp::{Ord, Ordering};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::{cell::RefCell, rc::Rc};
 #[derive(Debug, Deserialize)]
@@ -16,6 +21,9 @@ pub struct Order {
     /// 交易所处理订单的时间
     /// 格式为 `20230801093939123`（年-月-日-时-分-秒-毫秒）
     pub exch_time: i64,
+    /// 交易所确认订单的时间，订单得到确认之前为 `None`
+    #[serde(default)]
+    pub ack_time: Option<i64>,
     pub qty: f64,   // 订单数量
     pub price: f64, // 订单价格
     #[serde(skip_serializing)]
@@ -26,6 +34,9 @@ pub struct Order {
     #[serde(skip_serializing)]
     pub source: OrderSourceType, // 订单来源类型
     pub account: Option<String>, // 账户信息
+    /// 下单的策略编号，只用于成交归属，不影响撮合
+    #[serde(default)]
+    pub strategy_id: StrategyId,
     #[serde(skip_serializing)]
     pub seq: i64, // 序列号
     pub queue: f64, // 排队量
@@ -33,8 +44,35 @@ pub struct Order {
     pub filled_qty: f64,
     /// 剩余未成交的数量
     pub left_qty: f64,
+    /// 进入时穿过对手方价格主动成交的数量
+    #[serde(default)]
+    pub filled_qty_aggressive: f64,
+    /// 挂单被后续订单成交的数量
+    #[serde(default)]
+    pub filled_qty_passive: f64,
+    /// 在集合竞价中成交的数量
+    #[serde(default)]
+    pub filled_qty_auction: f64,
+    /// 累计成交金额，即各笔成交的成交价 × 成交数量之和
+    #[serde(default)]
+    pub filled_amount: f64,
+    /// 被动成交的成交金额
+    #[serde(default)]
+    pub filled_amount_passive: f64,
+    /// 未成交即被撤销的剩余数量，例如最优五档即时成交剩余撤销订单没有成交的部分
+    #[serde(default)]
+    pub canceled_qty: f64,
+    /// 主动成交时相对限价的价格改善金额，即按方向计算的 (限价 - 成交价) × 成交数量之和
+    #[serde(default)]
+    pub price_improvement: f64,
     #[serde(skip_serializing)]
     pub dirty: bool, // 数据是否被修改标志
+    /// 母单 ID，拆单下单时指向所属的母单
+    #[serde(default)]
+    pub parent_id: Option<OrderId>,
+    /// 策略附加在订单上的自定义数据，撮合引擎不会读取或修改
+    #[serde(default)]
+    pub user_data: Option<serde_json::Value>,
 }
 
 impl Serialize for Order {
@@ -42,20 +80,31 @@ impl Serialize for Order {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Order", 11)?;
+        let mut state = serializer.serialize_struct("Order", 24)?;
         state.serialize_field("order_id", &self.order_id)?;
         state.serialize_field("stock_code", &self.stock_code)?;
         state.serialize_field("local_time", &self.local_time)?;
         state.serialize_field("exch_time", &self.exch_time)?;
+        state.serialize_field("ack_time", &self.ack_time)?;
         state.serialize_field("qty", &self.qty)?;
         state.serialize_field("price", &self.price)?;
         state.serialize_field("order_type", &self.order_type.to_i32())?;
         state.serialize_field("side", &self.side.to_i32())?;
         state.serialize_field("status", &self.status)?;
         state.serialize_field("account", &self.account)?;
+        state.serialize_field("strategy_id", &self.strategy_id)?;
         state.serialize_field("queue", &self.queue)?;
         state.serialize_field("filled_qty", &self.filled_qty)?;
         state.serialize_field("left_qty", &self.left_qty)?;
+        state.serialize_field("filled_qty_aggressive", &self.filled_qty_aggressive)?;
+        state.serialize_field("filled_qty_passive", &self.filled_qty_passive)?;
+        state.serialize_field("filled_qty_auction", &self.filled_qty_auction)?;
+        state.serialize_field("filled_amount", &self.filled_amount)?;
+        state.serialize_field("filled_amount_passive", &self.filled_amount_passive)?;
+        state.serialize_field("canceled_qty", &self.canceled_qty)?;
+        state.serialize_field("price_improvement", &self.price_improvement)?;
+        state.serialize_field("parent_id", &self.parent_id)?;
+        state.serialize_field("user_data", &self.user_data)?;
         state.end()
     }
 }
@@ -74,6 +123,7 @@ impl Order {
         Self {
             local_time: timestamp,
             exch_time: 0,
+            ack_time: None,
             stock_code: stock_code,
             qty: qty,
             price: price,
@@ -84,11 +134,21 @@ impl Order {
             status: OrderStatus::New,
             source: source,
             account: account,
+            strategy_id: 0,
             filled_qty: 0.0,
             left_qty: qty,
+            filled_qty_aggressive: 0.0,
+            filled_qty_passive: 0.0,
+            filled_qty_auction: 0.0,
+            filled_amount: 0.0,
+            filled_amount_passive: 0.0,
+            canceled_qty: 0.0,
+            price_improvement: 0.0,
             queue: 0.0,
             seq: 0,
             dirty: false,
+            parent_id: None,
+            user_data: None,
         }
     }
 
@@ -114,8 +174,28 @@ impl Order {
         )))
     }
 
+    /// 校验价格和数量后创建订单引用。
+    ///
+    /// # 错误
+    /// 如果价格或数量不合法（非有限值或小于等于 0，市价订单的价格除外），返回 `MarketError::InvalidOrderRequest`。
+    pub fn try_new_ref(
+        account: Option<String>,
+        stock_code: String,
+        timestamp: i64,
+        price: f64,
+        qty: f64,
+        bs_flag: &str,
+        order_type: OrderType,
+        source: OrderSourceType,
+    ) -> Result<OrderRef, MarketError> {
+        validate_price_qty(price, qty, order_type)?;
+        Ok(Self::new_ref(
+            account, stock_code, timestamp, price, qty, bs_flag, order_type, source,
+        ))
+    }
+
     pub fn to_l3order_ref(&self, tick_size: f64, lot_size: f64) -> L3OrderRef {
-        let vol = (self.qty / lot_size).round() as i64;
+        let vol = qty_to_lots(self.qty, lot_size);
         let l30order_ref = L3Order::new_ref(
             self.source.clone(),
             self.account.clone(),
@@ -126,6 +206,7 @@ impl Order {
             self.local_time,
             self.order_type,
         );
+        l30order_ref.borrow_mut().strategy_id = self.strategy_id;
         if self.source == OrderSourceType::LocalOrder {
             let mut order = l30order_ref.borrow_mut();
             let mut auxiliary_info = order.auxiliary_info.as_mut().unwrap();
@@ -145,6 +226,86 @@ impl Order {
             self.left_qty = 0.0;
         }
     }
+
+    /// 撤销未成交的剩余数量，订单变为已撤销状态。
+    pub fn cancel_residual(&mut self, qty: f64) {
+        self.canceled_qty += qty;
+        self.left_qty = 0.0;
+        self.status = OrderStatus::Canceled;
+    }
+
+    /// 按流动性角色累计成交数量和成交金额。
+    ///
+    /// # 参数
+    /// - `liquidity`: 成交的流动性角色。
+    /// - `qty`: 成交数量。
+    /// - `price`: 成交均价。
+    /// - `timestamp`: 成交时间。
+    ///
+    /// # 返回值
+    /// 返回这笔成交的成交回报。
+    pub fn add_fill(
+        &mut self,
+        liquidity: FillLiquidity,
+        qty: f64,
+        price: f64,
+        timestamp: i64,
+    ) -> UserFill {
+        match liquidity {
+            FillLiquidity::Aggressive => self.filled_qty_aggressive += qty,
+            FillLiquidity::Passive => {
+                self.filled_qty_passive += qty;
+                self.filled_amount_passive += qty * price;
+            }
+            FillLiquidity::Auction => self.filled_qty_auction += qty,
+        }
+        self.filled_amount += qty * price;
+        UserFill {
+            order_id: self.order_id,
+            account: self.account.clone(),
+            strategy_id: self.strategy_id,
+            side: self.side,
+            price,
+            qty,
+            liquidity,
+            timestamp,
+        }
+    }
+
+    /// 按成交数量加权的成交均价，没有成交时为 0。
+    pub fn avg_fill_price(&self) -> f64 {
+        let qty = self.classified_fill_qty();
+        if qty > 0.0 {
+            self.filled_amount / qty
+        } else {
+            0.0
+        }
+    }
+
+    /// 已区分流动性角色的成交数量之和。
+    pub fn classified_fill_qty(&self) -> f64 {
+        self.filled_qty_aggressive + self.filled_qty_passive + self.filled_qty_auction
+    }
+
+    /// 已成交的数量，以 `lot_size` 为单位，按四舍五入取整。
+    pub fn filled_lots(&self, lot_size: f64) -> i64 {
+        qty_to_lots(self.filled_qty, lot_size)
+    }
+
+    /// 剩余未成交的数量，以 `lot_size` 为单位，按四舍五入取整。
+    pub fn left_lots(&self, lot_size: f64) -> i64 {
+        qty_to_lots(self.left_qty, lot_size)
+    }
+
+    /// 订单是否仍在交易中，见 `OrderStatus::is_open`。
+    pub fn is_open(&self) -> bool {
+        self.status.is_open()
+    }
+
+    /// 订单是否处于终止状态，见 `OrderStatus::is_terminal`。
+    pub fn is_terminal(&self) -> bool {
+        self.status.is_terminal()
+    }
 }
 
 impl Eq for Order {}
@@ -169,6 +330,301 @@ impl PartialEq for Order {
 
 pub type OrderRef = Rc<RefCell<Order>>;
 
+/// `OrderUserData` 是通过 `Broker::with_order_mut` 访问订单时得到的视图。
+///
+/// 只有 `user_data` 可以修改，撮合引擎依赖的字段（价格、数量、方向、状态等）只能通过
+/// [`OrderUserData::order`] 只读访问，从而不会破坏订单簿的一致性。
+///
+/// ```compile_fail
+/// use hello_cargo::orderbook::order::OrderUserData;
+///
+/// fn resize(meta: &mut OrderUserData) {
+///     meta.order().qty = 0.0;
+/// }
+/// ```
+pub struct OrderUserData<'a> {
+    order: &'a mut Order,
+}
+
+impl<'a> OrderUserData<'a> {
+    pub(crate) fn new(order: &'a mut Order) -> Self {
+        Self { order: order }
+    }
+
+    /// 返回订单的只读视图。
+    pub fn order(&self) -> &Order {
+        self.order
+    }
+
+    pub fn get(&self) -> Option<&serde_json::Value> {
+        self.order.user_data.as_ref()
+    }
+
+    pub fn set(&mut self, value: serde_json::Value) {
+        self.order.user_data = Some(value);
+    }
+
+    pub fn take(&mut self) -> Option<serde_json::Value> {
+        self.order.user_data.take()
+    }
+}
+
+/// `CancelFilter` 描述批量撤单时要撤销的订单，所有条件同时满足的订单才会被撤销，
+/// 为 `None` 的条件不做限制。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CancelFilter {
+    /// 订单所属的账户
+    pub account: Option<String>,
+    /// 订单的买卖方向
+    pub side: Option<Side>,
+    /// 订单价格所在的闭区间 (最低价, 最高价)
+    pub price_range: Option<(f64, f64)>,
+    /// 只撤销 `local_time` 早于该时间戳的订单
+    pub older_than: Option<i64>,
+}
+
+impl CancelFilter {
+    /// 判断订单是否满足所有过滤条件。
+    pub fn matches(&self, order: &Order) -> bool {
+        if let Some(account) = self.account.as_deref() {
+            if order.account.as_deref() != Some(account) {
+                return false;
+            }
+        }
+        if let Some(side) = self.side {
+            if order.side != side {
+                return false;
+            }
+        }
+        if let Some((low, high)) = self.price_range {
+            if order.price < low || order.price > high {
+                return false;
+            }
+        }
+        if let Some(older_than) = self.older_than {
+            if order.local_time >= older_than {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// `ParentOrder` 记录一个母单及其拆分出的子单。
+#[derive(Debug, Clone)]
+pub struct ParentOrder {
+    /// 母单 ID
+    pub parent_id: OrderId,
+    /// 母单的买卖方向，子单必须与其一致
+    pub side: Side,
+    /// 母单的总数量
+    pub qty: f64,
+    /// 已分配给子单的数量
+    pub allocated_qty: f64,
+    /// 按提交顺序排列的子单 ID
+    pub children: Vec<OrderId>,
+    /// 母单是否已撤销，撤销后不再接受新的子单
+    pub canceled: bool,
+}
+
+impl ParentOrder {
+    pub fn new(parent_id: OrderId, side: Side, qty: f64) -> Self {
+        Self {
+            parent_id,
+            side,
+            qty,
+            allocated_qty: 0.0,
+            children: Vec::new(),
+            canceled: false,
+        }
+    }
+
+    /// 剩余可分配给子单的数量。
+    pub fn remaining_qty(&self) -> f64 {
+        self.qty - self.allocated_qty
+    }
+}
+
+/// `ParentSummary` 汇总母单下所有子单的成交情况。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParentSummary {
+    /// 母单的总数量
+    pub total_qty: f64,
+    /// 所有子单的累计成交数量
+    pub filled_qty: f64,
+    /// 按成交数量加权的成交均价，没有成交时为 0
+    pub avg_price: f64,
+    /// 按提交顺序排列的子单 ID
+    pub children: Vec<OrderId>,
+    /// 仍可能成交的子单数量
+    pub open_children: usize,
+}
+
+/// `UserFill` 记录用户订单的一笔成交回报。
+///
+/// 同一订单在一次撮合或同步中的多笔成交合并为一条记录，价格为这些成交的均价。
+#[derive(Debug, Clone, PartialEq, serde::Serialize, Deserialize)]
+pub struct UserFill {
+    pub order_id: OrderId,
+    pub account: Option<String>,
+    pub strategy_id: StrategyId,
+    pub side: Side,
+    /// 成交均价
+    pub price: f64,
+    pub qty: f64,
+    pub liquidity: FillLiquidity,
+    pub timestamp: i64,
+}
+
+/// `StrategySummary` 汇总一个策略所有用户订单的成交情况。
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, Deserialize)]
+pub struct StrategySummary {
+    /// 订单数量
+    pub order_count: usize,
+    /// 累计成交数量
+    pub filled_qty: f64,
+    /// 主动成交的数量
+    pub filled_qty_aggressive: f64,
+    /// 被动成交的数量
+    pub filled_qty_passive: f64,
+    /// 集合竞价成交的数量
+    pub filled_qty_auction: f64,
+    /// 按成交数量加权的成交均价，没有成交时为 0
+    pub avg_price: f64,
+}
+
+/// 用户订单在日终的处置结果，由订单的最终状态和原因共同决定。
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, Deserialize,
+)]
+pub enum Disposition {
+    /// 全部成交
+    Filled,
+    /// 部分成交，剩余部分仍在订单簿中
+    PartiallyFilled,
+    /// 仍在订单簿中，没有成交
+    Open,
+    /// 没有成交即被撤销，包括交易时段不接受的订单
+    Canceled,
+    /// 部分成交后剩余部分被撤销
+    PartiallyFilledCanceled,
+    /// 已过期
+    Expired,
+    /// 提交时被风控或容量限制拒绝，参见 `ErrorCategory::RiskRejected`
+    RiskRejected,
+    /// 提交时因其他原因被拒绝
+    Rejected,
+    /// 日终仍在等待确认或生效，从未进入订单簿
+    Unactivated,
+}
+
+impl Disposition {
+    /// 根据已进入订单簿的订单的状态确定处置结果。
+    pub fn of(order: &Order) -> Self {
+        match order.status {
+            OrderStatus::Filled => Disposition::Filled,
+            OrderStatus::PartiallyFilled => Disposition::PartiallyFilled,
+            OrderStatus::Canceled if order.filled_qty > 0.0 => Disposition::PartiallyFilledCanceled,
+            OrderStatus::Canceled => Disposition::Canceled,
+            OrderStatus::Expired => Disposition::Expired,
+            OrderStatus::Rejected | OrderStatus::Unsupported => Disposition::Rejected,
+            OrderStatus::None | OrderStatus::New | OrderStatus::PendingAck => Disposition::Open,
+        }
+    }
+}
+
+/// `DispositionStats` 汇总同一处置结果下的订单。
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, Deserialize)]
+pub struct DispositionStats {
+    /// 订单数量
+    pub order_count: usize,
+    /// 订单数量之和
+    pub total_qty: f64,
+    /// 累计成交数量
+    pub filled_qty: f64,
+}
+
+/// `DispositionReport` 中最多列出的被拒绝订单 ID 数量，超出的部分只计数。
+pub const MAX_REJECTED_IDS: usize = 10_000;
+
+/// `DispositionReport` 按策略、账户和处置结果汇总当日提交的所有用户订单。
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, Deserialize)]
+pub struct DispositionReport {
+    /// 策略编号 → 账户 → 处置结果 → 汇总，没有账户的订单记在空字符串下
+    pub strategies: BTreeMap<StrategyId, BTreeMap<String, BTreeMap<Disposition, DispositionStats>>>,
+    /// 被拒绝的订单 ID，按从小到大排列，最多 `MAX_REJECTED_IDS` 个
+    pub rejected: Vec<OrderId>,
+    /// 超过 `MAX_REJECTED_IDS` 而没有列出 ID 的被拒绝订单数量
+    #[serde(default)]
+    pub rejected_omitted: usize,
+    /// 日终仍未生效的订单 ID，按从小到大排列
+    pub unactivated: Vec<OrderId>,
+}
+
+impl DispositionReport {
+    /// 将订单计入指定的处置结果。
+    pub fn record(&mut self, order: &Order, disposition: Disposition) {
+        let stats = self
+            .strategies
+            .entry(order.strategy_id)
+            .or_default()
+            .entry(order.account.clone().unwrap_or_default())
+            .or_default()
+            .entry(disposition)
+            .or_default();
+        stats.order_count += 1;
+        stats.total_qty += order.qty;
+        stats.filled_qty += order.filled_qty;
+        match disposition {
+            Disposition::RiskRejected | Disposition::Rejected => {
+                if self.rejected.len() < MAX_REJECTED_IDS {
+                    self.rejected.push(order.order_id);
+                } else {
+                    self.rejected_omitted += 1;
+                }
+            }
+            Disposition::Unactivated => self.unactivated.push(order.order_id),
+            _ => {}
+        }
+    }
+
+    /// 合并另一份报告，用于汇总多只股票。
+    pub fn merge(&mut self, other: &DispositionReport) {
+        for (strategy_id, accounts) in other.strategies.iter() {
+            let strategy = self.strategies.entry(*strategy_id).or_default();
+            for (account, buckets) in accounts.iter() {
+                let account = strategy.entry(account.clone()).or_default();
+                for (disposition, other_stats) in buckets.iter() {
+                    let stats = account.entry(*disposition).or_default();
+                    stats.order_count += other_stats.order_count;
+                    stats.total_qty += other_stats.total_qty;
+                    stats.filled_qty += other_stats.filled_qty;
+                }
+            }
+        }
+        self.rejected.extend_from_slice(&other.rejected);
+        self.rejected.sort_unstable();
+        self.rejected_omitted +=
+            other.rejected_omitted + self.rejected.len().saturating_sub(MAX_REJECTED_IDS);
+        self.rejected.truncate(MAX_REJECTED_IDS);
+        self.unactivated.extend_from_slice(&other.unactivated);
+        self.unactivated.sort_unstable();
+    }
+
+    /// 返回指定策略、账户和处置结果的汇总，没有对应的订单时返回 `None`。
+    pub fn get(
+        &self,
+        strategy_id: StrategyId,
+        account: &str,
+        disposition: Disposition,
+    ) -> Option<&DispositionStats> {
+        self.strategies
+            .get(&strategy_id)?
+            .get(account)?
+            .get(&disposition)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -320,4 +776,87 @@ mod tests {
 
         assert_eq!(order1, order2);
     }
+
+    #[test]
+    fn test_try_new_ref() {
+        let order_ref = Order::try_new_ref(
+            None,
+            "AAPL".to_string(),
+            20231201093021355,
+            150.0,
+            10.0,
+            "B",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        assert!(order_ref.is_ok());
+
+        let order_ref = Order::try_new_ref(
+            None,
+            "AAPL".to_string(),
+            20231201093021355,
+            0.0,
+            10.0,
+            "B",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        assert_eq!(order_ref.err(), Some(MarketError::InvalidOrderRequest));
+
+        let order_ref = Order::try_new_ref(
+            None,
+            "AAPL".to_string(),
+            20231201093021355,
+            0.0,
+            10.0,
+            "B",
+            OrderType::C,
+            OrderSourceType::UserOrder,
+        );
+        assert!(order_ref.is_ok());
+
+        let order_ref = Order::try_new_ref(
+            None,
+            "AAPL".to_string(),
+            20231201093021355,
+            150.0,
+            -10.0,
+            "B",
+            OrderType::L,
+            OrderSourceType::UserOrder,
+        );
+        assert_eq!(order_ref.err(), Some(MarketError::InvalidOrderRequest));
+    }
+
+    /// 测试处置报告最多列出 `MAX_REJECTED_IDS` 个被拒绝订单的 ID
+    /// 超出的订单仍计入汇总，合并报告时同样截断
+    #[test]
+    fn test_disposition_report_caps_rejected_ids() {
+        let mut order = Order::new(
+            None,
+            "AAPL".to_string(),
+            10.0,
+            100.0,
+            Side::Buy,
+            OrderType::L,
+            1234567890,
+            OrderSourceType::UserOrder,
+        );
+        let mut report = DispositionReport::default();
+        for order_id in 0..MAX_REJECTED_IDS as OrderId + 2 {
+            order.order_id = order_id;
+            report.record(&order, Disposition::Rejected);
+        }
+        assert_eq!(report.rejected.len(), MAX_REJECTED_IDS);
+        assert_eq!(report.rejected_omitted, 2);
+        let stats = report.get(0, "", Disposition::Rejected).unwrap();
+        assert_eq!(stats.order_count as usize, MAX_REJECTED_IDS + 2);
+
+        let mut other = DispositionReport::default();
+        order.order_id = MAX_REJECTED_IDS as OrderId + 2;
+        other.record(&order, Disposition::RiskRejected);
+        report.merge(&other);
+        assert_eq!(report.rejected.len(), MAX_REJECTED_IDS);
+        assert_eq!(report.rejected_omitted, 3);
+    }
 }
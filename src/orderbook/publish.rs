@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::market_view::MarketView;
+use super::statistics::Statistics;
+
+/// 每个订阅者最多缓存的状态数量，超出时丢弃最早的状态。
+pub const STATE_CHANNEL_CAPACITY: usize = 64;
+
+/// `PublishedState` 是回放过程中定期发布的经纪商状态，只包含普通数据，可以发送到其他线程。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedState {
+    /// 发布序号，从 1 开始，每次发布加 1，被丢弃的状态也占用序号
+    pub seq: u64,
+    /// 发布时的时间戳
+    pub timestamp: i64,
+    /// 发布时已处理的历史逐笔消息数量
+    pub events_processed: usize,
+    /// 最优价和前 10 档行情
+    pub view: MarketView,
+    /// 当日的市场统计数据
+    pub statistics: Statistics,
+    /// 仍在交易中的用户订单数量
+    pub open_orders: usize,
+}
+
+#[derive(Debug, Default)]
+struct StateQueue {
+    states: VecDeque<PublishedState>,
+    dropped: usize,
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    queue: Mutex<StateQueue>,
+    ready: Condvar,
+}
+
+impl Shared {
+    /// 锁定缓存。持有锁的一方 panic 不影响缓存中的数据，因此忽略锁的中毒状态。
+    fn lock(&self) -> MutexGuard<'_, StateQueue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// 创建一对发布端和订阅端。
+pub fn state_channel() -> (StatePublisher, StateReceiver) {
+    let shared = Arc::new(Shared::default());
+    (
+        StatePublisher {
+            shared: shared.clone(),
+        },
+        StateReceiver { shared },
+    )
+}
+
+/// 状态的发布端，由经纪商持有。发布从不等待订阅者，缓存已满时丢弃最早的状态。
+#[derive(Debug)]
+pub struct StatePublisher {
+    shared: Arc<Shared>,
+}
+
+impl StatePublisher {
+    /// 发布一个状态，缓存已满时丢弃最早的状态。
+    pub fn publish(&self, state: PublishedState) {
+        let mut queue = self.shared.lock();
+        if queue.states.len() == STATE_CHANNEL_CAPACITY {
+            queue.states.pop_front();
+            queue.dropped += 1;
+        }
+        queue.states.push_back(state);
+        drop(queue);
+        self.shared.ready.notify_one();
+    }
+
+    /// 订阅端是否还存在。
+    pub fn is_subscribed(&self) -> bool {
+        Arc::strong_count(&self.shared) > 1
+    }
+}
+
+impl Drop for StatePublisher {
+    fn drop(&mut self) {
+        let mut queue = self.shared.lock();
+        queue.closed = true;
+        drop(queue);
+        self.shared.ready.notify_all();
+    }
+}
+
+/// 状态的订阅端，可以发送到其他线程按自己的节奏读取。
+#[derive(Debug)]
+pub struct StateReceiver {
+    shared: Arc<Shared>,
+}
+
+impl StateReceiver {
+    /// 取出最早的一个状态，没有状态时立即返回 `None`。
+    pub fn try_recv(&self) -> Option<PublishedState> {
+        self.shared.lock().states.pop_front()
+    }
+
+    /// 等待并取出最早的一个状态。
+    ///
+    /// # 返回值
+    /// 发布端已经丢弃且没有剩余的状态时返回 `None`。
+    pub fn recv(&self) -> Option<PublishedState> {
+        let mut queue = self.shared.lock();
+        loop {
+            if let Some(state) = queue.states.pop_front() {
+                return Some(state);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self
+                .shared
+                .ready
+                .wait(queue)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// 最多等待 `timeout` 取出最早的一个状态，超时或发布端已经丢弃时返回 `None`。
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<PublishedState> {
+        let queue = self.shared.lock();
+        let (mut queue, _) = self
+            .shared
+            .ready
+            .wait_timeout_while(queue, timeout, |queue| {
+                queue.states.is_empty() && !queue.closed
+            })
+            .unwrap_or_else(|err| err.into_inner());
+        queue.states.pop_front()
+    }
+
+    /// 取出所有缓存的状态，按发布顺序排列。
+    pub fn drain(&self) -> Vec<PublishedState> {
+        self.shared.lock().states.drain(..).collect()
+    }
+
+    /// 因缓存已满被丢弃的状态数量。
+    pub fn dropped(&self) -> usize {
+        self.shared.lock().dropped
+    }
+
+    /// 发布端是否已经丢弃，之后不会再有新的状态。
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+}
@@ -0,0 +1,73 @@
+use super::*;
+use std::fmt;
+
+/// `QueueModel` 决定回测模式下排在用户挂单之前的量消失时，用户挂单的排队位置推进多少。
+///
+/// 回测时用户挂单只占用影子成交量，排在它之前的历史挂单成交后用户挂单才会成交。
+/// 历史挂单撤单时，默认把撤销的量全部计为推进；设置模型后，未被计为推进的撤单量
+/// 记录在用户订单的 `queue_debt` 中，之后到达的成交先抵扣这部分，再与用户挂单成交。
+pub trait QueueModel: fmt::Debug {
+    /// 返回排在 `order` 之前消失的量中，可以推进其排队位置的数量。
+    ///
+    /// # 参数
+    /// - `order`: 排在后面的用户挂单。
+    /// - `level_traded`: 排在前面并且已经成交的量，以手表示。
+    /// - `level_canceled`: 排在前面并且已经撤销的量，以手表示。
+    ///
+    /// # 返回值
+    /// 推进的数量，超出 `[0, level_traded + level_canceled]` 的部分会被截断。
+    fn adjust_fill(&self, order: &L3Order, level_traded: i64, level_canceled: i64) -> i64;
+}
+
+/// 保守模型：只有排在前面的成交量推进排队位置，撤单不推进。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Conservative;
+
+impl QueueModel for Conservative {
+    fn adjust_fill(&self, _order: &L3Order, level_traded: i64, _level_canceled: i64) -> i64 {
+        level_traded
+    }
+}
+
+/// 概率模型：排在前面的撤单量按 `cancel_credit` 的比例推进排队位置。
+///
+/// `cancel_credit` 为 0 时与 `Conservative` 相同，为 1 时与不设置模型的默认行为相同。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Probabilistic {
+    /// 撤单量计为推进的比例，取值范围为 `[0, 1]`
+    pub cancel_credit: f64,
+}
+
+impl QueueModel for Probabilistic {
+    fn adjust_fill(&self, _order: &L3Order, level_traded: i64, level_canceled: i64) -> i64 {
+        let credit = self.cancel_credit.clamp(0.0, 1.0);
+        level_traded + (level_canceled as f64 * credit).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust_fill() {
+        let order = L3Order::new(
+            OrderSourceType::UserOrder,
+            None,
+            1,
+            Side::Buy,
+            1000,
+            1,
+            1,
+            OrderType::L,
+        );
+        assert_eq!(Conservative.adjust_fill(&order, 3, 5), 3);
+        let half = Probabilistic { cancel_credit: 0.5 };
+        assert_eq!(half.adjust_fill(&order, 3, 5), 6);
+        let full = Probabilistic { cancel_credit: 1.0 };
+        assert_eq!(full.adjust_fill(&order, 3, 5), 8);
+        // 超出范围的比例被截断
+        let invalid = Probabilistic { cancel_credit: 2.0 };
+        assert_eq!(invalid.adjust_fill(&order, 0, 5), 5);
+    }
+}
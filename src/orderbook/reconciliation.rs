@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::FillLiquidity;
+
+/// 对账时比较的数据类别。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DiscrepancyKind {
+    /// 统计数据中的总成交量与已处理的历史逐笔数据的主动成交量及用户成交量之和
+    MarketVolume,
+    /// 用户订单按流动性角色汇总的成交量与统计数据中对应的成交量
+    UserFill(FillLiquidity),
+    /// 价格档位记录的挂单量与档位中存活订单的剩余数量之和
+    RestingVolume,
+}
+
+/// `Discrepancy` 描述一类数据在对账时的差异，数量均以手表示。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Discrepancy {
+    pub kind: DiscrepancyKind,
+    /// 由独立来源汇总得到的期望值
+    pub expected: i64,
+    /// 被核对的数据中记录的值
+    pub actual: i64,
+}
+
+impl Discrepancy {
+    /// 差异的大小，正数表示被核对的数据多于期望值。
+    pub fn magnitude(&self) -> i64 {
+        self.actual - self.expected
+    }
+}
+
+/// `ReconciliationReport` 记录一次对账发现的所有差异。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    /// 对账时已处理的历史逐笔消息数量
+    pub events_processed: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+    pub fn new(events_processed: usize) -> Self {
+        Self {
+            events_processed,
+            discrepancies: Vec::new(),
+        }
+    }
+
+    /// 比较期望值和实际值，不一致时记录一条差异。
+    pub fn check(&mut self, kind: DiscrepancyKind, expected: i64, actual: i64) {
+        if expected != actual {
+            self.discrepancies.push(Discrepancy {
+                kind,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    /// 返回某一类数据的差异，一致时返回 `None`。
+    pub fn get(&self, kind: DiscrepancyKind) -> Option<&Discrepancy> {
+        self.discrepancies
+            .iter()
+            .find(|discrepancy| discrepancy.kind == kind)
+    }
+
+    /// 所有数据是否一致。
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// `ReconciliationLog` 记录周期性对账中每一类差异第一次出现时已处理的逐笔消息数量。
+///
+/// 差异第一次出现在上一次对账和这一次对账之间处理的逐笔消息中，
+/// 缩小对账间隔可以进一步定位。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconciliationLog {
+    /// 已执行的对账次数
+    pub runs: usize,
+    first_seen: HashMap<DiscrepancyKind, usize>,
+}
+
+impl ReconciliationLog {
+    /// 记录一次对账的结果。
+    pub fn record(&mut self, report: &ReconciliationReport) {
+        self.runs += 1;
+        for discrepancy in report.discrepancies.iter() {
+            self.first_seen
+                .entry(discrepancy.kind)
+                .or_insert(report.events_processed);
+        }
+    }
+
+    /// 返回某一类差异第一次被发现时已处理的逐笔消息数量。
+    pub fn first_seen(&self, kind: DiscrepancyKind) -> Option<usize> {
+        self.first_seen.get(&kind).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_keeps_first_event_index() {
+        let mut log = ReconciliationLog::default();
+        let mut report = ReconciliationReport::new(10);
+        report.check(DiscrepancyKind::MarketVolume, 5, 5);
+        assert!(report.is_consistent());
+        log.record(&report);
+        assert_eq!(log.first_seen(DiscrepancyKind::MarketVolume), None);
+
+        let mut report = ReconciliationReport::new(20);
+        report.check(DiscrepancyKind::MarketVolume, 5, 7);
+        assert_eq!(
+            report
+                .get(DiscrepancyKind::MarketVolume)
+                .map(|discrepancy| discrepancy.magnitude()),
+            Some(2)
+        );
+        log.record(&report);
+        let mut report = ReconciliationReport::new(30);
+        report.check(DiscrepancyKind::MarketVolume, 5, 8);
+        log.record(&report);
+        assert_eq!(log.runs, 3);
+        assert_eq!(log.first_seen(DiscrepancyKind::MarketVolume), Some(20));
+        assert_eq!(log.first_seen(DiscrepancyKind::RestingVolume), None);
+    }
+}
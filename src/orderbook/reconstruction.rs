@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::{cmp, collections::VecDeque};
+
+use super::market_view::LevelView;
+use super::{OrderId, Side};
+
+/// 比较价格和数量时允许的误差。
+const LEVEL_EPSILON: f64 = 1e-6;
+
+/// `L2Snapshot` 表示交易所记录的某一时刻的 L2 行情快照。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct L2Snapshot {
+    /// 快照时间戳，快照反映该时刻及之前所有逐笔数据处理后的订单簿
+    pub timestamp: i64,
+    /// 买方档位 (价格, 数量)，按价格从高到低排列
+    pub bid_levels: Vec<(f64, f64)>,
+    /// 卖方档位 (价格, 数量)，按价格从低到高排列
+    pub ask_levels: Vec<(f64, f64)>,
+}
+
+/// 差异的类型。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DivergenceKind {
+    /// 重建的订单簿与交易所快照在某一档位上不一致
+    #[default]
+    Level,
+    /// 历史主动订单在重建的订单簿中的成交价与历史成交价不一致
+    TradePrint {
+        /// 历史逐笔消息的序列号
+        seq: i64,
+        /// 历史主动订单的订单 ID
+        order_id: OrderId,
+    },
+}
+
+/// `Divergence` 描述重建的订单簿与交易所记录的一处差异。
+///
+/// 档位差异比较快照中的 (价格, 数量)；成交价差异的 `level` 为 0，
+/// `expected` 和 `actual` 分别为 (历史成交价, 成交数量) 和 (重建订单簿中的最后成交价, 成交数量)。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Divergence {
+    /// 快照时间戳，成交价差异为成交时间戳
+    pub timestamp: i64,
+    /// 出现差异的买卖方向，成交价差异为主动订单的方向
+    pub side: Side,
+    /// 出现差异的档位，从 1 开始
+    pub level: usize,
+    /// 交易所快照中的 (价格, 数量)，档位不存在时为 `None`
+    pub expected: Option<(f64, f64)>,
+    /// 重建订单簿中的 (价格, 数量)，档位不存在时为 `None`
+    pub actual: Option<(f64, f64)>,
+    #[serde(default)]
+    pub kind: DivergenceKind,
+}
+
+/// `ReconstructionValidator` 用于校验 L2P 模式下由逐笔委托和成交重建的订单簿。
+///
+/// 回放过程中，每当逐笔数据的时间越过某个快照的时间戳，就将重建的订单簿与该快照逐档比较，
+/// 并记录所有不一致的档位。
+#[derive(Debug, Default)]
+pub struct ReconstructionValidator {
+    snapshots: VecDeque<L2Snapshot>,
+    max_level: usize,
+    checked: usize,
+    divergences: Vec<Divergence>,
+}
+
+impl ReconstructionValidator {
+    /// 创建一个新的 `ReconstructionValidator` 实例。
+    ///
+    /// # 参数
+    /// - `snapshots`: 交易所记录的 L2 快照，会按时间戳排序。
+    /// - `max_level`: 每一方比较的最大档位数量。
+    pub fn new(mut snapshots: Vec<L2Snapshot>, max_level: usize) -> Self {
+        snapshots.sort_by_key(|snapshot| snapshot.timestamp);
+        Self {
+            snapshots: snapshots.into(),
+            max_level: max_level,
+            checked: 0,
+            divergences: Vec::new(),
+        }
+    }
+
+    pub fn max_level(&self) -> usize {
+        self.max_level
+    }
+
+    /// 返回下一个待校验快照的时间戳。
+    pub fn next_timestamp(&self) -> Option<i64> {
+        self.snapshots.front().map(|snapshot| snapshot.timestamp)
+    }
+
+    /// 取出下一个待校验的快照。
+    pub fn pop_snapshot(&mut self) -> Option<L2Snapshot> {
+        self.snapshots.pop_front()
+    }
+
+    /// 将重建的订单簿档位与快照逐档比较，并记录差异。
+    ///
+    /// # 参数
+    /// - `snapshot`: 交易所记录的快照。
+    /// - `levels`: 重建订单簿的档位，由 `LevelView::from_levels` 生成。
+    ///
+    /// # 返回值
+    /// 本次比较发现的差异数量。
+    pub fn verify(&mut self, snapshot: &L2Snapshot, levels: &[LevelView]) -> usize {
+        let before = self.divergences.len();
+        self.compare_side(snapshot.timestamp, Side::Buy, &snapshot.bid_levels, levels);
+        self.compare_side(snapshot.timestamp, Side::Sell, &snapshot.ask_levels, levels);
+        self.checked += 1;
+        self.divergences.len() - before
+    }
+
+    fn compare_side(
+        &mut self,
+        timestamp: i64,
+        side: Side,
+        expected: &[(f64, f64)],
+        levels: &[LevelView],
+    ) {
+        let actual: Vec<(f64, f64)> = levels
+            .iter()
+            .filter(|level| level.side == side)
+            .map(|level| (level.price, level.qty))
+            .collect();
+        let levels = cmp::max(expected.len(), actual.len()).min(self.max_level);
+        for idx in 0..levels {
+            let expected_level = expected.get(idx).cloned();
+            let actual_level = actual.get(idx).cloned();
+            let is_same = match (expected_level, actual_level) {
+                (Some((p1, q1)), Some((p2, q2))) => {
+                    (p1 - p2).abs() < LEVEL_EPSILON && (q1 - q2).abs() < LEVEL_EPSILON
+                }
+                (None, None) => true,
+                _ => false,
+            };
+            if !is_same {
+                self.divergences.push(Divergence {
+                    timestamp,
+                    side,
+                    level: idx + 1,
+                    expected: expected_level,
+                    actual: actual_level,
+                    kind: DivergenceKind::Level,
+                });
+            }
+        }
+    }
+
+    /// 记录一处不是由快照比较发现的差异，例如历史成交价差异。
+    pub fn record(&mut self, divergence: Divergence) {
+        self.divergences.push(divergence);
+    }
+
+    /// 已校验的快照数量。
+    pub fn checked(&self) -> usize {
+        self.checked
+    }
+
+    /// 返回所有已发现的差异。
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    /// 是否没有发现任何差异。
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_reports_divergence() {
+        let snapshot = L2Snapshot {
+            timestamp: 20231201093000000,
+            bid_levels: vec![(10.0, 300.0), (9.99, 100.0)],
+            ask_levels: vec![(10.1, 200.0)],
+        };
+        let mut validator = ReconstructionValidator::new(vec![snapshot.clone()], 5);
+        assert_eq!(validator.next_timestamp(), Some(20231201093000000));
+
+        let levels = LevelView::from_levels(&[(10.0, 200.0, 1)], &[(10.1, 200.0, 1)], false);
+        assert_eq!(validator.verify(&snapshot, &levels), 2);
+        assert_eq!(validator.checked(), 1);
+        assert!(!validator.is_consistent());
+
+        let divergence = &validator.divergences()[0];
+        assert_eq!(divergence.side, Side::Buy);
+        assert_eq!(divergence.level, 1);
+        assert_eq!(divergence.expected, Some((10.0, 300.0)));
+        assert_eq!(divergence.actual, Some((10.0, 200.0)));
+        assert_eq!(validator.divergences()[1].actual, None);
+    }
+}
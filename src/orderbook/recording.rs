@@ -0,0 +1,1257 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use super::broker::Broker;
+use super::hook::ReplayEvent;
+use super::observer::{DepthObserver, FillEvent};
+use super::skiplist_orderbook::SkipListMarketDepth;
+use super::utils::{qty_to_lots, validate_price_qty};
+use super::*;
+
+/// 录制文件开头的标识。
+pub const RECORDING_MAGIC: [u8; 4] = *b"RDEP";
+/// 录制文件的格式版本。
+pub const RECORDING_VERSION: u32 = 1;
+
+const LEVEL_KIND: u8 = 0;
+const TRADE_KIND: u8 = 1;
+/// 读取录制文件时预先分配的事件数量上限，文件中的事件数量不可信，不能直接用于分配内存。
+const MAX_PREALLOCATED_EVENTS: usize = 1 << 16;
+
+/// 订单簿的一次变化。
+///
+/// 最优价由价格档位推导，不单独记录。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookMutation {
+    /// 挂单或撤单使 `side` 一方 `price_tick` 档位的挂单量变为 `vol`，`vol` 为 0 表示档位被移除
+    Level {
+        side: Side,
+        price_tick: i64,
+        vol: i64,
+    },
+    /// `side` 一方 `level_tick` 档位上的挂单被成交 `vol`，成交价为 `price_tick`
+    Trade {
+        side: Side,
+        level_tick: i64,
+        price_tick: i64,
+        vol: i64,
+    },
+}
+
+/// 带有时间戳和序列号的订单簿变化，时间戳和序列号取自引起该变化的逐笔数据。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedEvent {
+    pub timestamp: i64,
+    pub seq: i64,
+    pub mutation: BookMutation,
+}
+
+/// 一个交易日订单簿变化的录制结果。
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookRecording {
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub events: Vec<RecordedEvent>,
+}
+
+impl BookRecording {
+    /// 以紧凑的二进制格式写出录制结果。
+    ///
+    /// 文件以 `RECORDING_MAGIC`、版本号、`tick_size`、`lot_size` 和事件数量开头，
+    /// 之后每个事件以一个字节的类型开头，其余字段均为小端序的定长整数。
+    pub fn write_to<W: Write>(&self, w: W) -> Result<(), SnapshotError> {
+        let mut writer = io::BufWriter::new(w);
+        writer.write_all(&RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+        writer.write_all(&self.tick_size.to_le_bytes())?;
+        writer.write_all(&self.lot_size.to_le_bytes())?;
+        writer.write_all(&(self.events.len() as u64).to_le_bytes())?;
+        for event in &self.events {
+            let (kind, side, fields) = match event.mutation {
+                BookMutation::Level {
+                    side,
+                    price_tick,
+                    vol,
+                } => (LEVEL_KIND, side, [price_tick, price_tick, vol]),
+                BookMutation::Trade {
+                    side,
+                    level_tick,
+                    price_tick,
+                    vol,
+                } => (TRADE_KIND, side, [level_tick, price_tick, vol]),
+            };
+            writer.write_all(&[kind, side as u8])?;
+            writer.write_all(&event.timestamp.to_le_bytes())?;
+            writer.write_all(&event.seq.to_le_bytes())?;
+            for field in fields {
+                writer.write_all(&field.to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 读取由 [`BookRecording::write_to`] 写出的录制结果。
+    ///
+    /// # 错误
+    /// 文件标识、版本号或事件类型不正确时返回 `SnapshotError::Io`。
+    pub fn read_from<R: Read>(r: R) -> Result<Self, SnapshotError> {
+        let mut reader = io::BufReader::new(r);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != RECORDING_MAGIC {
+            return Err(invalid_data("not a book recording".to_string()));
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+        if version != RECORDING_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported recording version {}",
+                version
+            )));
+        }
+        let tick_size = f64::from_le_bytes(read_bytes(&mut reader)?);
+        let lot_size = f64::from_le_bytes(read_bytes(&mut reader)?);
+        let len = u64::from_le_bytes(read_bytes(&mut reader)?) as usize;
+        let mut events = Vec::with_capacity(cmp::min(len, MAX_PREALLOCATED_EVENTS));
+        for _ in 0..len {
+            let [kind, side] = read_bytes::<_, 2>(&mut reader)?;
+            let side = Side::from_i32(side as i8 as i32)
+                .map_err(|_| invalid_data(format!("invalid side {}", side)))?;
+            let timestamp = i64::from_le_bytes(read_bytes(&mut reader)?);
+            let seq = i64::from_le_bytes(read_bytes(&mut reader)?);
+            let level_tick = i64::from_le_bytes(read_bytes(&mut reader)?);
+            let price_tick = i64::from_le_bytes(read_bytes(&mut reader)?);
+            let vol = i64::from_le_bytes(read_bytes(&mut reader)?);
+            let mutation = match kind {
+                LEVEL_KIND => BookMutation::Level {
+                    side,
+                    price_tick,
+                    vol,
+                },
+                TRADE_KIND => BookMutation::Trade {
+                    side,
+                    level_tick,
+                    price_tick,
+                    vol,
+                },
+                _ => return Err(invalid_data(format!("invalid event kind {}", kind))),
+            };
+            events.push(RecordedEvent {
+                timestamp,
+                seq,
+                mutation,
+            });
+        }
+        Ok(Self {
+            tick_size,
+            lot_size,
+            events,
+        })
+    }
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid_data(message: String) -> SnapshotError {
+    SnapshotError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    timestamp: i64,
+    seq: i64,
+    bid_levels: HashMap<i64, i64>,
+    ask_levels: HashMap<i64, i64>,
+    events: Vec<RecordedEvent>,
+}
+
+impl RecorderState {
+    fn levels(&mut self, side: Side) -> &mut HashMap<i64, i64> {
+        match side {
+            Side::Buy => &mut self.bid_levels,
+            _ => &mut self.ask_levels,
+        }
+    }
+
+    fn push(&mut self, mutation: BookMutation) {
+        self.events.push(RecordedEvent {
+            timestamp: self.timestamp,
+            seq: self.seq,
+            mutation,
+        });
+    }
+
+    /// 调整档位的挂单量并返回调整后的挂单量，挂单量为 0 的档位被移除。
+    fn adjust_level(&mut self, side: Side, price_tick: i64, delta: i64) -> i64 {
+        let levels = self.levels(side);
+        let vol = levels.get(&price_tick).copied().unwrap_or(0) + delta;
+        if vol > 0 {
+            levels.insert(price_tick, vol);
+        } else {
+            levels.remove(&price_tick);
+        }
+        cmp::max(vol, 0)
+    }
+}
+
+/// `DepthRecorder` 作为市场深度的观察者，记录回放历史数据时订单簿的变化。
+///
+/// 录制时不应提交用户订单：用户订单只在影子订单簿上成交，不会被记录。
+/// 收盘集合竞价在推进时间结束时进行，其变化使用最后一笔逐笔数据的时间戳和序列号。
+#[derive(Debug, Clone, Default)]
+pub struct DepthRecorder {
+    state: Rc<RefCell<RecorderState>>,
+}
+
+impl DepthRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 将录制器注册到经纪人的市场深度上，并设置经纪人的逐笔回调以获取时间戳和序列号。
+    ///
+    /// 经纪人已有的逐笔回调会被替换。
+    ///
+    /// # 返回值
+    /// 注册成功返回 `true`，市场深度的观察者数量已满时返回 `false`。
+    pub fn attach(&self, broker: &mut Broker<SkipListMarketDepth>) -> bool {
+        if !broker
+            .market_depth
+            .register_observer(Box::new(self.clone()))
+        {
+            return false;
+        }
+        let object: Rc<RefCell<dyn Any>> = self.state.clone();
+        broker.set_event_callback(object, update_clock);
+        true
+    }
+
+    /// 已经录制的变化数量。
+    pub fn len(&self) -> usize {
+        self.state.borrow().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().events.is_empty()
+    }
+
+    /// 返回到目前为止的录制结果。
+    pub fn recording(&self, tick_size: f64, lot_size: f64) -> BookRecording {
+        BookRecording {
+            tick_size,
+            lot_size,
+            events: self.state.borrow().events.clone(),
+        }
+    }
+}
+
+fn update_clock(object: &Rc<RefCell<dyn Any>>, event: &ReplayEvent) {
+    if let Some(state) = object.borrow_mut().downcast_mut::<RecorderState>() {
+        state.timestamp = event.timestamp;
+        state.seq = event.seq;
+    }
+}
+
+impl DepthObserver for DepthRecorder {
+    fn on_fill(&mut self, event: &FillEvent) {
+        if event.source == OrderSourceType::UserOrder || event.vol == 0 {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        state.adjust_level(event.side, event.level_tick, -event.vol);
+        state.push(BookMutation::Trade {
+            side: event.side,
+            level_tick: event.level_tick,
+            price_tick: event.price_tick,
+            vol: event.vol,
+        });
+    }
+
+    fn on_add(&mut self, order: &L3Order) {
+        if order.source == OrderSourceType::UserOrder || order.vol == 0 {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        let vol = state.adjust_level(order.side, order.price_tick, order.vol);
+        state.push(BookMutation::Level {
+            side: order.side,
+            price_tick: order.price_tick,
+            vol,
+        });
+    }
+
+    fn on_cancel(&mut self, order: &L3Order) {
+        if order.source == OrderSourceType::UserOrder || order.vol == 0 {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        let vol = state.adjust_level(order.side, order.price_tick, -order.vol);
+        state.push(BookMutation::Level {
+            side: order.side,
+            price_tick: order.price_tick,
+            vol,
+        });
+    }
+}
+
+/// 挂在录制的订单簿上的用户订单。
+#[derive(Debug)]
+struct RestingOrder {
+    order_ref: L3OrderRef,
+    /// 排在该订单之前的录制挂单量
+    queue_ahead: i64,
+}
+
+/// `RecordedDepth` 按录制结果直接重建订单簿，不运行撮合引擎。
+///
+/// 用户订单按影子订单簿的规则与录制的流动性成交：主动成交只在本地扣减档位上可成交的数量，
+/// 不改变录制结果；挂单在录制的成交消耗完排在其前面的挂单量后被动成交。
+/// 录制结果不包含档位上的订单笔数，`get_orderbook_level` 返回的笔数总是 0。
+/// 录制的订单簿按录制时的价格回放，不支持除权除息调整。
+#[derive(Debug)]
+pub struct RecordedDepth {
+    tick_size: f64,
+    lot_size: f64,
+    events: Vec<RecordedEvent>,
+    cursor: usize,
+    timestamp: i64,
+    bid_levels: BTreeMap<i64, i64>,
+    ask_levels: BTreeMap<i64, i64>,
+    /// 用户订单在各档位上已经成交的数量
+    bid_consumed: HashMap<i64, i64>,
+    ask_consumed: HashMap<i64, i64>,
+    user_orders: Vec<RestingOrder>,
+    /// 挂出的用户订单，成交或撤销后由经纪人同步订单信息时移除
+    orders: HashMap<OrderId, L3OrderRef>,
+    last_tick: i64,
+    /// 用户订单最近一次成交的价格
+    shadow_last_tick: i64,
+    previous_close_tick: Option<i64>,
+}
+
+impl RecordedDepth {
+    pub fn new(recording: BookRecording) -> Self {
+        Self {
+            tick_size: recording.tick_size,
+            lot_size: recording.lot_size,
+            events: recording.events,
+            cursor: 0,
+            timestamp: 0,
+            bid_levels: BTreeMap::new(),
+            ask_levels: BTreeMap::new(),
+            bid_consumed: HashMap::new(),
+            ask_consumed: HashMap::new(),
+            user_orders: Vec::new(),
+            orders: HashMap::new(),
+            last_tick: INVALID_MIN,
+            shadow_last_tick: INVALID_MIN,
+            previous_close_tick: None,
+        }
+    }
+
+    /// 最后一个已回放的变化的时间戳。
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// 录制结果是否已经全部回放。
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.events.len()
+    }
+
+    /// 按录制的顺序回放变化，直到下一个变化的时间戳晚于 `time_point`。
+    ///
+    /// 与 `Broker::goto` 一样在第一个晚于 `time_point` 的变化处停止。
+    ///
+    /// # 返回值
+    /// 本次回放的变化数量。
+    pub fn advance_to(&mut self, time_point: i64) -> usize {
+        let start = self.cursor;
+        while let Some(event) = self.events.get(self.cursor).copied() {
+            if event.timestamp > time_point {
+                break;
+            }
+            self.timestamp = event.timestamp;
+            self.apply(event.mutation);
+            self.cursor += 1;
+        }
+        self.cursor - start
+    }
+
+    /// 返回用户订单仍然挂在订单簿上的数量。
+    pub fn user_order_count(&self) -> usize {
+        self.user_orders.len()
+    }
+
+    fn apply(&mut self, mutation: BookMutation) {
+        match mutation {
+            BookMutation::Level {
+                side,
+                price_tick,
+                vol,
+            } => {
+                self.set_level(side, price_tick, vol);
+                // 排在用户订单前面的挂单被撤销时，用户订单的排队位置随之前移
+                for resting in self.user_orders.iter_mut() {
+                    let order = resting.order_ref.borrow();
+                    if order.side == side && order.price_tick == price_tick {
+                        resting.queue_ahead = cmp::min(resting.queue_ahead, vol);
+                    }
+                }
+            }
+            BookMutation::Trade {
+                side,
+                level_tick,
+                price_tick,
+                vol,
+            } => {
+                let left = self.levels(side).get(&level_tick).copied().unwrap_or(0) - vol;
+                self.set_level(side, level_tick, left);
+                self.last_tick = price_tick;
+                self.fill_resting(side, level_tick, vol);
+            }
+        }
+    }
+
+    fn set_level(&mut self, side: Side, price_tick: i64, vol: i64) {
+        if vol > 0 {
+            self.levels_mut(side).insert(price_tick, vol);
+        } else {
+            self.levels_mut(side).remove(&price_tick);
+        }
+        // 用户订单的成交数量不超过档位上剩余的挂单量
+        let consumed = self.consumed_mut(side);
+        if let Some(filled) = consumed.get_mut(&price_tick) {
+            *filled = cmp::min(*filled, cmp::max(vol, 0));
+        }
+    }
+
+    /// 录制的成交在 `level_tick` 档位上消耗了 `vol`，价格更优的用户挂单先成交，
+    /// 同价位的用户挂单在排在前面的挂单量被消耗完后成交。
+    fn fill_resting(&mut self, side: Side, level_tick: i64, vol: i64) {
+        let mut left = vol;
+        for resting in self.user_orders.iter_mut() {
+            let mut order = resting.order_ref.borrow_mut();
+            if order.side != side || left == 0 {
+                continue;
+            }
+            let better = match side {
+                Side::Buy => order.price_tick > level_tick,
+                _ => order.price_tick < level_tick,
+            };
+            let available = if better {
+                left
+            } else if order.price_tick == level_tick {
+                let passed = cmp::min(resting.queue_ahead, left);
+                resting.queue_ahead -= passed;
+                left - passed
+            } else {
+                0
+            };
+            let filled = cmp::min(available, order.vol);
+            if filled > 0 {
+                order.vol -= filled;
+                order.dirty = true;
+                left -= filled;
+                self.shadow_last_tick = order.price_tick;
+            }
+        }
+        self.user_orders
+            .retain(|resting| resting.order_ref.borrow().vol > 0);
+    }
+
+    fn levels(&self, side: Side) -> &BTreeMap<i64, i64> {
+        match side {
+            Side::Buy => &self.bid_levels,
+            _ => &self.ask_levels,
+        }
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, i64> {
+        match side {
+            Side::Buy => &mut self.bid_levels,
+            _ => &mut self.ask_levels,
+        }
+    }
+
+    fn consumed(&self, side: Side) -> &HashMap<i64, i64> {
+        match side {
+            Side::Buy => &self.bid_consumed,
+            _ => &self.ask_consumed,
+        }
+    }
+
+    fn consumed_mut(&mut self, side: Side) -> &mut HashMap<i64, i64> {
+        match side {
+            Side::Buy => &mut self.bid_consumed,
+            _ => &mut self.ask_consumed,
+        }
+    }
+
+    /// 按价格优先的顺序返回一方有挂单的档位，包括只有用户挂单的档位。
+    fn level_ticks(&self, side: Side) -> impl Iterator<Item = i64> {
+        let mut ticks: Vec<i64> = self.levels(side).keys().copied().collect();
+        for resting in &self.user_orders {
+            let order = resting.order_ref.borrow();
+            if order.side == side && !ticks.contains(&order.price_tick) {
+                ticks.push(order.price_tick);
+            }
+        }
+        ticks.sort_unstable();
+        if side == Side::Buy {
+            ticks.reverse();
+        }
+        ticks.into_iter()
+    }
+
+    /// 录制的挂单量中用户订单还可以成交的数量。
+    fn available_vol(&self, side: Side, price_tick: i64) -> i64 {
+        let recorded = self.levels(side).get(&price_tick).copied().unwrap_or(0);
+        let consumed = self.consumed(side).get(&price_tick).copied().unwrap_or(0);
+        cmp::max(recorded - consumed, 0)
+    }
+
+    /// 用户订单看到的档位挂单量：录制的挂单量扣除用户已成交的数量，再加上用户的挂单。
+    fn visible_vol(&self, side: Side, price_tick: i64) -> i64 {
+        let user_vol: i64 = self
+            .user_orders
+            .iter()
+            .map(|resting| resting.order_ref.borrow())
+            .filter(|order| order.side == side && order.price_tick == price_tick)
+            .map(|order| order.vol)
+            .sum();
+        self.available_vol(side, price_tick) + user_vol
+    }
+
+    fn best_tick(&self, side: Side, source: &OrderSourceType) -> i64 {
+        let invalid = match side {
+            Side::Buy => INVALID_MIN,
+            _ => INVALID_MAX,
+        };
+        if source == &OrderSourceType::UserOrder {
+            self.level_ticks(side)
+                .find(|tick| self.visible_vol(side, *tick) > 0)
+                .unwrap_or(invalid)
+        } else {
+            let best = match side {
+                Side::Buy => self.bid_levels.keys().next_back(),
+                _ => self.ask_levels.keys().next(),
+            };
+            best.copied().unwrap_or(invalid)
+        }
+    }
+
+    fn price(&self, tick: i64, invalid: i64) -> f64 {
+        if tick == invalid {
+            f64::NAN
+        } else {
+            tick as f64 * self.tick_size
+        }
+    }
+
+    /// 用户订单与 `side` 一方录制的流动性成交，返回成交的数量。
+    fn match_depth(
+        &mut self,
+        side: Side,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> i64 {
+        let ticks: Vec<i64> = match side {
+            Side::Buy => self.bid_levels.keys().rev().copied().collect(),
+            _ => self.ask_levels.keys().copied().collect(),
+        };
+        let mut filled = 0;
+        let mut count = 1;
+        for price_tick in ticks {
+            let mut order = order_ref.borrow_mut();
+            let crosses = match (bound, side) {
+                (MatchBound::Unbounded, _) => true,
+                (MatchBound::Limit, Side::Buy) => order.price_tick <= price_tick,
+                (MatchBound::Limit, _) => order.price_tick >= price_tick,
+            };
+            if count > max_depth || !crosses || order.vol == 0 {
+                break;
+            }
+            count += 1;
+            let this_filled = cmp::min(self.available_vol(side, price_tick), order.vol);
+            if this_filled == 0 {
+                continue;
+            }
+            order.vol -= this_filled;
+            order.dirty = true;
+            *self.consumed_mut(side).entry(price_tick).or_insert(0) += this_filled;
+            self.shadow_last_tick = price_tick;
+            filled += this_filled;
+        }
+        filled
+    }
+
+    fn ensure_user_order(order_ref: &L3OrderRef) -> Result<(), MarketError> {
+        if order_ref.borrow().source != OrderSourceType::UserOrder {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        Ok(())
+    }
+
+    /// 挂出一笔新的用户订单，返回 (挂出前的最优价, 挂出后的最优价)。
+    fn add_new_order(
+        &mut self,
+        side: Side,
+        source: OrderSourceType,
+        account: Option<String>,
+        order_id: OrderId,
+        price: f64,
+        vol: i64,
+        timestamp: i64,
+        order_type: OrderType,
+    ) -> Result<(i64, i64), MarketError> {
+        validate_price_qty(price, vol as f64, order_type)?;
+        let price_tick = (price / self.tick_size).round() as i64;
+        let order_ref = L3Order::new_ref(
+            source, account, order_id, side, price_tick, vol, timestamp, order_type,
+        );
+        let prev_best_tick = self.best_tick(side, &OrderSourceType::UserOrder);
+        let best_tick = self.add(order_ref)?;
+        Ok((prev_best_tick, best_tick))
+    }
+
+    /// 返回仍然挂在订单簿上的用户订单的下标。
+    fn resting_index(&self, order_ref: &L3OrderRef) -> Option<usize> {
+        self.user_orders
+            .iter()
+            .position(|resting| Rc::ptr_eq(&resting.order_ref, order_ref))
+    }
+}
+
+impl MarketDepth for RecordedDepth {
+    fn new_box(_mode: ExchangeMode, tick_size: f64, lot_size: f64) -> Box<Self> {
+        Box::new(Self::new(BookRecording {
+            tick_size,
+            lot_size,
+            events: Vec::new(),
+        }))
+    }
+
+    fn best_bid(&self, source: &OrderSourceType) -> f64 {
+        self.price(self.best_bid_tick(source), INVALID_MIN)
+    }
+
+    fn best_ask(&self, source: &OrderSourceType) -> f64 {
+        self.price(self.best_ask_tick(source), INVALID_MAX)
+    }
+
+    fn best_bid_tick(&self, source: &OrderSourceType) -> i64 {
+        self.best_tick(Side::Buy, source)
+    }
+
+    fn last_tick(&self, source: &OrderSourceType) -> i64 {
+        if source == &OrderSourceType::UserOrder {
+            self.shadow_last_tick
+        } else {
+            self.last_tick
+        }
+    }
+
+    fn last_price(&self, source: &OrderSourceType) -> f64 {
+        self.last_tick(source) as f64 * self.tick_size
+    }
+
+    fn best_ask_tick(&self, source: &OrderSourceType) -> i64 {
+        self.best_tick(Side::Sell, source)
+    }
+
+    fn tick_size(&self) -> f64 {
+        self.tick_size
+    }
+
+    fn lot_size(&self) -> f64 {
+        self.lot_size
+    }
+
+    fn bid_vol_at_tick(&self, price_tick: i64) -> i64 {
+        self.visible_vol(Side::Buy, price_tick)
+    }
+
+    fn ask_vol_at_tick(&self, price_tick: i64) -> i64 {
+        self.visible_vol(Side::Sell, price_tick)
+    }
+
+    /// 挂出用户订单，录制的订单簿不接受历史订单。
+    fn add(&mut self, order_ref: L3OrderRef) -> Result<i64, MarketError> {
+        Self::ensure_user_order(&order_ref)?;
+        let (side, price_tick) = {
+            let order = order_ref.borrow();
+            (order.side, order.price_tick)
+        };
+        if !matches!(side, Side::Buy | Side::Sell) {
+            return Err(MarketError::MarketSideError);
+        }
+        PriceTick::try_new(price_tick, false)?;
+        let queue_ahead = self.available_vol(side, price_tick);
+        let order_id = order_ref.borrow().order_id;
+        self.orders.insert(order_id, order_ref.clone());
+        self.user_orders.push(RestingOrder {
+            order_ref: order_ref,
+            queue_ahead: queue_ahead,
+        });
+        Ok(self.best_tick(side, &OrderSourceType::UserOrder))
+    }
+
+    fn match_order_with_bound(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<i64, MarketError> {
+        let side = order_ref.borrow().side;
+        match side {
+            Side::Buy => self.match_ask_depth(order_ref, max_depth, bound),
+            Side::Sell => self.match_bid_depth(order_ref, max_depth, bound),
+            _ => Err(MarketError::MarketSideError),
+        }
+    }
+
+    fn try_match_order_with_bound(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<bool, MarketError> {
+        let order = order_ref.borrow();
+        let (depth_side, ticks): (Side, Vec<i64>) = match order.side {
+            Side::Buy => (Side::Sell, self.ask_levels.keys().copied().collect()),
+            Side::Sell => (Side::Buy, self.bid_levels.keys().rev().copied().collect()),
+            _ => return Err(MarketError::MarketSideError),
+        };
+        let available: i64 = ticks
+            .into_iter()
+            .take(cmp::max(max_depth, 0) as usize)
+            .take_while(|tick| match (bound, order.side) {
+                (MatchBound::Unbounded, _) => true,
+                (MatchBound::Limit, Side::Buy) => order.price_tick >= *tick,
+                (MatchBound::Limit, _) => order.price_tick <= *tick,
+            })
+            .map(|tick| self.available_vol(depth_side, tick))
+            .sum();
+        Ok(available >= order.vol)
+    }
+
+    fn match_bid_depth(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<i64, MarketError> {
+        Self::ensure_user_order(&order_ref)?;
+        Ok(self.match_depth(Side::Buy, order_ref, max_depth, bound))
+    }
+
+    fn match_ask_depth(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<i64, MarketError> {
+        Self::ensure_user_order(&order_ref)?;
+        Ok(self.match_depth(Side::Sell, order_ref, max_depth, bound))
+    }
+
+    /// 与 `SkipListMarketDepth` 一致，买方档位的价格以负的 tick 表示。
+    fn get_bid_level(&self, level_num: usize) -> String {
+        let levels: Vec<(i64, i64)> = self
+            .bid_levels
+            .iter()
+            .rev()
+            .take(level_num)
+            .map(|(tick, vol)| (-tick, *vol))
+            .collect();
+        serde_json::to_string(&levels).unwrap()
+    }
+
+    fn get_ask_level(&self, level_num: usize) -> String {
+        let levels: Vec<(i64, i64)> = self
+            .ask_levels
+            .iter()
+            .take(level_num)
+            .map(|(tick, vol)| (*tick, *vol))
+            .collect();
+        serde_json::to_string(&levels).unwrap()
+    }
+
+    /// 集合竞价的结果已经包含在录制结果中，不再重新撮合。
+    fn call_auction(&mut self) -> Result<(i64, i64), MarketError> {
+        Ok((0, 0))
+    }
+
+    fn call_auction_with_limits(
+        &mut self,
+        _limit_down_tick: Option<i64>,
+        _limit_up_tick: Option<i64>,
+    ) -> Result<(i64, i64), MarketError> {
+        Ok((0, 0))
+    }
+
+    fn set_previous_close_tick(&mut self, previous_close_tick: Option<i64>) {
+        self.previous_close_tick = previous_close_tick;
+    }
+
+    fn sample_analytics(&mut self, _timestamp: i64) {}
+}
+
+/// 录制的订单簿只保存用户订单，历史订单的变化来自录制结果。
+impl L3MarketDepth for RecordedDepth {
+    type Error = MarketError;
+
+    fn add_buy_order(
+        &mut self,
+        source: OrderSourceType,
+        account: Option<String>,
+        order_id: OrderId,
+        price: f64,
+        vol: i64,
+        timestamp: i64,
+        order_type: OrderType,
+    ) -> Result<(i64, i64), Self::Error> {
+        self.add_new_order(
+            Side::Buy,
+            source,
+            account,
+            order_id,
+            price,
+            vol,
+            timestamp,
+            order_type,
+        )
+    }
+
+    fn add_sell_order(
+        &mut self,
+        source: OrderSourceType,
+        account: Option<String>,
+        order_id: OrderId,
+        price: f64,
+        vol: i64,
+        timestamp: i64,
+        order_type: OrderType,
+    ) -> Result<(i64, i64), Self::Error> {
+        self.add_new_order(
+            Side::Sell,
+            source,
+            account,
+            order_id,
+            price,
+            vol,
+            timestamp,
+            order_type,
+        )
+    }
+
+    fn cancel_order(&mut self, order_id: OrderId) -> Result<(Side, i64, i64), Self::Error> {
+        let order_ref = self
+            .orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(MarketError::OrderNotFound)?;
+        self.cancel_order_from_ref(order_ref)
+    }
+
+    /// 撤销用户挂单，订单的方向标记为 `Side::None`。
+    fn cancel_order_from_ref(
+        &mut self,
+        order_ref: L3OrderRef,
+    ) -> Result<(Side, i64, i64), Self::Error> {
+        let idx = self
+            .resting_index(&order_ref)
+            .ok_or(MarketError::OrderNotFound)?;
+        let side = order_ref.borrow().side;
+        let prev_best_tick = self.best_tick(side, &OrderSourceType::UserOrder);
+        self.user_orders.remove(idx);
+        {
+            let mut order = order_ref.borrow_mut();
+            order.side = Side::None;
+            order.dirty = true;
+        }
+        Ok((
+            side,
+            prev_best_tick,
+            self.best_tick(side, &OrderSourceType::UserOrder),
+        ))
+    }
+
+    fn reduce_order_from_ref(
+        &mut self,
+        order_ref: L3OrderRef,
+        vol: i64,
+    ) -> Result<(Side, i64, i64), Self::Error> {
+        if vol <= 0 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        if vol >= order_ref.borrow().vol {
+            return self.cancel_order_from_ref(order_ref);
+        }
+        if self.resting_index(&order_ref).is_none() {
+            return Err(MarketError::OrderNotFound);
+        }
+        let side = {
+            let mut order = order_ref.borrow_mut();
+            order.vol -= vol;
+            order.dirty = true;
+            order.side
+        };
+        let best_tick = self.best_tick(side, &OrderSourceType::UserOrder);
+        Ok((side, best_tick, best_tick))
+    }
+
+    fn set_last_tick(&mut self, last_tick: i64) {
+        self.last_tick = last_tick;
+    }
+
+    /// 录制结果已经是除权除息后的价格，不支持调整。
+    ///
+    /// # 错误
+    /// 总是返回 `MarketError::InvalidCorporateAction`。
+    fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<(), MarketError> {
+        Err(MarketError::InvalidCorporateAction(format!(
+            "{:?} cannot be applied to a recorded depth",
+            action
+        )))
+    }
+
+    fn move_to_front(&mut self, order_ref: L3OrderRef) -> Result<(), Self::Error> {
+        let idx = self
+            .resting_index(&order_ref)
+            .ok_or(MarketError::OrderNotFound)?;
+        self.user_orders[idx].queue_ahead = 0;
+        Ok(())
+    }
+
+    fn update_bid_depth(&mut self) -> Result<i64, MarketError> {
+        Ok(self.best_tick(Side::Buy, &OrderSourceType::LocalOrder))
+    }
+
+    fn update_ask_depth(&mut self) -> Result<i64, MarketError> {
+        Ok(self.best_tick(Side::Sell, &OrderSourceType::LocalOrder))
+    }
+
+    /// 从订单簿中撤出用户挂单，并按新的价格和数量重置，供调用方重新提交，参见 `Broker::modify_order`。
+    fn modify_order(
+        &mut self,
+        order_id: OrderId,
+        price: f64,
+        qty: f64,
+        timestamp: i64,
+    ) -> Result<L3OrderRef, Self::Error> {
+        let order_ref = self
+            .orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(MarketError::OrderNotFound)?;
+        let idx = self
+            .resting_index(&order_ref)
+            .ok_or(MarketError::OrderNotFound)?;
+        let price_tick = (price / self.tick_size).round() as i64;
+        let vol = qty_to_lots(qty, self.lot_size);
+        if price_tick <= 0 || vol <= 0 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        PriceTick::try_new(price_tick, false)?;
+
+        self.user_orders.remove(idx);
+        self.orders.remove(&order_id);
+        {
+            let mut order = order_ref.borrow_mut();
+            order.price_tick = price_tick;
+            order.vol = vol;
+            order.vol_shadow = vol;
+            order.timestamp = timestamp;
+            order.queue_debt = 0;
+            order.order_type = OrderType::L;
+            order.aggressive_amount_tick = 0;
+            order.dirty = true;
+        }
+        Ok(order_ref)
+    }
+
+    fn clean_orders(&mut self) {}
+
+    fn orders(&self) -> &HashMap<OrderId, L3OrderRef> {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut HashMap<OrderId, L3OrderRef> {
+        &mut self.orders
+    }
+
+    /// 用户挂单按录制的成交推进排队位置，不使用排队模型。
+    fn set_queue_model(&mut self, _model: Option<Box<dyn queue_model::QueueModel>>) {}
+
+    /// 录制结果只有档位的挂单量，两项都返回用户可见的挂单量之和。
+    fn resting_vol(&self) -> (i64, i64) {
+        let vol: i64 = [Side::Buy, Side::Sell]
+            .into_iter()
+            .flat_map(|side| {
+                self.level_ticks(side)
+                    .map(move |price_tick| self.visible_vol(side, price_tick))
+            })
+            .sum();
+        (vol, vol)
+    }
+
+    /// 获取前 `max_level` 档的价格、数量和笔数，录制结果不包含笔数，笔数总是 0。
+    fn get_orderbook_level(
+        &self,
+        bid_vec: &mut Vec<(f64, f64, i64)>,
+        ask_vec: &mut Vec<(f64, f64, i64)>,
+        max_level: usize,
+    ) {
+        for (side, vec) in [(Side::Buy, bid_vec), (Side::Sell, ask_vec)] {
+            for price_tick in self.level_ticks(side).take(max_level) {
+                let vol = self.visible_vol(side, price_tick);
+                if vol > 0 {
+                    vec.push((
+                        price_tick as f64 * self.tick_size,
+                        vol as f64 * self.lot_size,
+                        0,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::synthetic::{FlowConfig, SyntheticFlow};
+
+    const START: i64 = 20231201091500000;
+    const SAMPLES: [i64; 4] = [
+        20231201092000000,
+        20231201093001000,
+        20231201093030000,
+        20231201093130000,
+    ];
+
+    fn new_broker() -> Broker<SkipListMarketDepth> {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Backtest,
+            MarketType::SZ,
+            "STOCK".to_string(),
+            "000001.SZ".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        let history = SyntheticFlow::new(FlowConfig::default())
+            .to_history("000001.SZ", START, 500, 2000, 0.01, 100.0)
+            .unwrap();
+        broker.add_data(Some(history)).unwrap();
+        broker.set_current_time(START);
+        broker
+    }
+
+    fn levels(
+        f: impl Fn(&mut Vec<(f64, f64, i64)>, &mut Vec<(f64, f64, i64)>),
+    ) -> (Vec<(f64, f64)>, Vec<(f64, f64)>) {
+        let (mut bids, mut asks) = (Vec::new(), Vec::new());
+        f(&mut bids, &mut asks);
+        let strip = |v: Vec<(f64, f64, i64)>| v.into_iter().map(|(p, q, _)| (p, q)).collect();
+        (strip(bids), strip(asks))
+    }
+
+    fn user_buy(price_tick: i64, vol: i64) -> L3OrderRef {
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            1,
+            Side::Buy,
+            price_tick,
+            vol,
+            SAMPLES[2],
+            OrderType::L,
+        );
+        order_ref.borrow_mut().order_id = 1;
+        order_ref
+    }
+
+    #[test]
+    fn test_record_and_playback() {
+        let mut broker = new_broker();
+        let recorder = DepthRecorder::new();
+        assert!(recorder.attach(&mut broker));
+
+        let mut live = Vec::new();
+        for time_point in SAMPLES {
+            broker.goto(time_point).unwrap();
+            let depth = &broker.market_depth;
+            live.push((
+                levels(|bids, asks| depth.get_orderbook_level(bids, asks, 10)),
+                depth.best_bid_tick(&OrderSourceType::LocalOrder),
+                depth.best_ask_tick(&OrderSourceType::LocalOrder),
+                depth.last_tick(&OrderSourceType::LocalOrder),
+            ));
+        }
+        assert!(!recorder.is_empty());
+
+        // 二进制格式往返后保持不变
+        let recording = recorder.recording(0.01, 100.0);
+        let mut buf = Vec::new();
+        recording.write_to(&mut buf).unwrap();
+        let decoded = BookRecording::read_from(buf.as_slice()).unwrap();
+        assert_eq!(decoded, recording);
+        assert!(BookRecording::read_from(&b"JSON"[..]).is_err());
+
+        // 不提交用户订单时，回放的订单簿与实时撮合的订单簿一致
+        let mut playback = RecordedDepth::new(decoded);
+        for (time_point, expected) in SAMPLES.iter().zip(&live) {
+            playback.advance_to(*time_point);
+            let actual = (
+                levels(|bids, asks| playback.get_orderbook_level(bids, asks, 10)),
+                playback.best_bid_tick(&OrderSourceType::LocalOrder),
+                playback.best_ask_tick(&OrderSourceType::LocalOrder),
+                playback.last_tick(&OrderSourceType::LocalOrder),
+            );
+            assert_eq!(&actual, expected);
+        }
+        // 采样点覆盖集合竞价和连续竞价，订单簿各不相同
+        assert!(live.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(live[0].3, INVALID_MIN);
+        assert_ne!(live[1].3, INVALID_MIN);
+    }
+
+    #[test]
+    fn test_playback_user_fill_matches_live() {
+        let mut broker = new_broker();
+        let recorder = DepthRecorder::new();
+        assert!(recorder.attach(&mut broker));
+        broker.goto(SAMPLES[2]).unwrap();
+
+        let mut playback = RecordedDepth::new(recorder.recording(0.01, 100.0));
+        playback.advance_to(SAMPLES[2]);
+
+        // 以卖三价买入，吃掉多个价格档位
+        let (_, asks) = levels(|bids, asks| playback.get_orderbook_level(bids, asks, 3));
+        let price_tick = (asks.last().unwrap().0 / 0.01).round() as i64;
+        let live_order = user_buy(price_tick, 100_000);
+        let recorded_order = user_buy(price_tick, 100_000);
+
+        let live_filled = broker
+            .market_depth
+            .match_order(live_order.clone(), i64::MAX)
+            .unwrap();
+        let recorded_filled = playback
+            .match_order(recorded_order.clone(), i64::MAX)
+            .unwrap();
+        assert!(live_filled > 0);
+        assert_eq!(recorded_filled, live_filled);
+        assert_eq!(recorded_order.borrow().vol, live_order.borrow().vol);
+        assert_eq!(
+            playback.last_tick(&OrderSourceType::UserOrder),
+            broker.market_depth.last_tick(&OrderSourceType::UserOrder)
+        );
+        assert_eq!(
+            playback.best_ask_tick(&OrderSourceType::UserOrder),
+            broker
+                .market_depth
+                .best_ask_tick(&OrderSourceType::UserOrder)
+        );
+
+        // 用户订单的成交不改变录制的订单簿
+        assert_eq!(
+            playback.best_ask_tick(&OrderSourceType::LocalOrder),
+            broker
+                .market_depth
+                .best_ask_tick(&OrderSourceType::LocalOrder)
+        );
+
+        // 录制的订单簿只接受用户订单
+        let local = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            2,
+            Side::Buy,
+            price_tick,
+            1,
+            SAMPLES[2],
+            OrderType::L,
+        );
+        assert!(playback.add(local).is_err());
+    }
+
+    /// 测试通过 `L3MarketDepth` 接口在录制的订单簿上挂单、减量、改单和撤单
+    /// 录制的订单簿不支持除权除息
+    #[test]
+    fn test_recorded_depth_as_l3_market_depth() {
+        let mut broker = new_broker();
+        let recorder = DepthRecorder::new();
+        assert!(recorder.attach(&mut broker));
+        broker.goto(SAMPLES[2]).unwrap();
+        let mut playback = RecordedDepth::new(recorder.recording(0.01, 100.0));
+        playback.advance_to(SAMPLES[2]);
+
+        let bid_tick = playback.best_bid_tick(&OrderSourceType::LocalOrder);
+        let price = (bid_tick - 5) as f64 * 0.01;
+        playback
+            .add_buy_order(
+                OrderSourceType::UserOrder,
+                None,
+                1,
+                price,
+                10,
+                SAMPLES[2],
+                OrderType::L,
+            )
+            .unwrap();
+        assert_eq!(playback.user_order_count(), 1);
+        let order_ref = playback.orders().get(&1).unwrap().clone();
+        assert!(playback
+            .reduce_order_from_ref(order_ref.clone(), 0)
+            .is_err());
+        playback
+            .reduce_order_from_ref(order_ref.clone(), 4)
+            .unwrap();
+        assert_eq!(order_ref.borrow().vol, 6);
+
+        // 改单撤出原订单并按新价格重置，由调用方重新挂出
+        let modified = playback
+            .modify_order(1, price - 0.01, 500.0, SAMPLES[2])
+            .unwrap();
+        assert!(Rc::ptr_eq(&modified, &order_ref));
+        assert_eq!(playback.user_order_count(), 0);
+        assert!(playback.orders().is_empty());
+        assert_eq!(modified.borrow().price_tick, bid_tick - 6);
+        assert_eq!(modified.borrow().vol, 5);
+        playback.add(modified).unwrap();
+
+        let (side, _, best) = playback.cancel_order(1).unwrap();
+        assert_eq!(side, Side::Buy);
+        assert_eq!(best, bid_tick);
+        assert_eq!(order_ref.borrow().side, Side::None);
+        assert_eq!(playback.user_order_count(), 0);
+        assert!(playback.cancel_order_from_ref(order_ref).is_err());
+
+        assert!(matches!(
+            playback.apply_corporate_action(CorporateAction::Split(2.0)),
+            Err(MarketError::InvalidCorporateAction(_))
+        ));
+    }
+
+    /// 测试录制文件声明的事件数量远大于实际内容时返回错误
+    /// 事件数量不可信，不能按声明的数量预先分配内存
+    #[test]
+    fn test_read_truncated_recording() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&RECORDING_MAGIC);
+        buf.extend_from_slice(&RECORDING_VERSION.to_le_bytes());
+        buf.extend_from_slice(&0.01f64.to_le_bytes());
+        buf.extend_from_slice(&100.0f64.to_le_bytes());
+        buf.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(BookRecording::read_from(buf.as_slice()).is_err());
+    }
+}
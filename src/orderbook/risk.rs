@@ -0,0 +1,310 @@
+use super::order::UserFill;
+use super::*;
+
+/// `CircuitBreakerConfig` 描述单个账户的日内风控阈值，`None` 表示不检查该项。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// 日内最大亏损（已实现加未实现），为正数
+    pub max_loss: Option<f64>,
+    /// 持仓市值绝对值之和的上限
+    pub max_position_value: Option<f64>,
+    /// 连续被拒绝的订单数量上限
+    pub max_consecutive_rejects: Option<usize>,
+    /// 熔断时是否撤销该账户所有仍在交易中的订单
+    pub cancel_on_halt: bool,
+}
+
+/// 触发熔断的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HaltReason {
+    /// 日内亏损超过上限
+    LossLimit,
+    /// 持仓市值超过上限
+    PositionLimit,
+    /// 连续被拒绝的订单过多
+    ConsecutiveRejects,
+}
+
+/// 账户的交易状态。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BreakerState {
+    /// 正常交易
+    #[default]
+    Active,
+    /// 已熔断，新订单会被拒绝
+    Halted(HaltReason),
+}
+
+/// 账户状态变化事件。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountEvent {
+    pub account: String,
+    pub timestamp: i64,
+    /// 变化后的状态
+    pub state: BreakerState,
+}
+
+/// 账户当前的风控状态以及距离各项阈值的余量，未设置的阈值余量为 `None`。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    /// 日内盈亏，已实现加未实现
+    pub pnl: f64,
+    /// 持仓市值绝对值之和
+    pub position_value: f64,
+    pub consecutive_rejects: usize,
+    /// 距离亏损上限的余量
+    pub loss_headroom: Option<f64>,
+    /// 距离持仓市值上限的余量
+    pub position_headroom: Option<f64>,
+    /// 距离连续拒单上限的余量
+    pub reject_headroom: Option<usize>,
+}
+
+/// 单只股票上的持仓。
+#[derive(Debug, Clone, Default)]
+struct Position {
+    /// 持仓数量，卖出为负
+    qty: f64,
+    /// 用于计算市值的标记价格
+    mark_price: f64,
+}
+
+/// `CircuitBreaker` 根据成交回报跟踪单个账户的持仓和盈亏，并在超过阈值时熔断。
+///
+/// 成交回报来自 `Broker::user_fills`，按实际成交价格计入现金流；
+/// 持仓按每只股票的最新成交价计算市值。
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: BreakerState,
+    /// 买卖产生的现金流
+    cash: f64,
+    positions: HashMap<String, Position>,
+    /// 每只股票已处理的成交回报数量，键为股票代码
+    fill_cursors: HashMap<String, usize>,
+    consecutive_rejects: usize,
+    /// 计算亏损上限的起点，手动恢复交易时更新
+    loss_baseline: f64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config: config,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    pub fn is_halted(&self) -> bool {
+        matches!(self.state, BreakerState::Halted(_))
+    }
+
+    /// 处理股票上新增的成交回报，只计入属于 `account` 的成交。
+    ///
+    /// 每只股票记录已处理的回报数量，重复调用不会重复计入；回报数量少于已处理的数量时，
+    /// 说明经纪商已经重建，从头开始处理。
+    ///
+    /// # 参数
+    /// - `account`: 熔断器所属的账户。
+    /// - `stock_code`: 股票代码。
+    /// - `fills`: 该股票全部的成交回报。
+    pub fn on_fills(&mut self, account: &str, stock_code: &str, fills: &[UserFill]) {
+        let cursor = self.fill_cursors.entry(stock_code.to_string()).or_insert(0);
+        if *cursor > fills.len() {
+            *cursor = 0;
+        }
+        let start = std::mem::replace(cursor, fills.len());
+        for fill in fills[start..].iter() {
+            if fill.account.as_deref() == Some(account) {
+                self.on_fill(stock_code, fill.side, fill.qty, fill.price);
+            }
+        }
+    }
+
+    /// 根据一笔成交更新持仓和现金流。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `side`: 成交方向。
+    /// - `qty`: 成交数量。
+    /// - `price`: 成交价格。
+    pub fn on_fill(&mut self, stock_code: &str, side: Side, qty: f64, price: f64) {
+        let signed_qty = match side {
+            Side::Buy => qty,
+            Side::Sell => -qty,
+            _ => return,
+        };
+        self.cash -= signed_qty * price;
+        let position = self.positions.entry(stock_code.to_string()).or_default();
+        position.qty += signed_qty;
+        position.mark_price = price;
+    }
+
+    /// 更新股票的标记价格，价格无效时忽略。
+    pub fn set_mark_price(&mut self, stock_code: &str, price: f64) {
+        if !price.is_finite() || price <= 0.0 {
+            return;
+        }
+        if let Some(position) = self.positions.get_mut(stock_code) {
+            position.mark_price = price;
+        }
+    }
+
+    pub fn record_accept(&mut self) {
+        self.consecutive_rejects = 0;
+    }
+
+    pub fn record_reject(&mut self) {
+        self.consecutive_rejects += 1;
+    }
+
+    /// 日内盈亏，已实现加未实现。
+    pub fn pnl(&self) -> f64 {
+        self.cash
+            + self
+                .positions
+                .values()
+                .map(|position| position.qty * position.mark_price)
+                .sum::<f64>()
+    }
+
+    /// 自上次恢复交易以来的亏损，盈利时为负。
+    fn loss(&self) -> f64 {
+        self.loss_baseline - self.pnl()
+    }
+
+    /// 持仓市值绝对值之和。
+    pub fn position_value(&self) -> f64 {
+        self.positions
+            .values()
+            .map(|position| (position.qty * position.mark_price).abs())
+            .sum()
+    }
+
+    /// 检查各项阈值，首次超过时将账户置为熔断状态。
+    ///
+    /// # 返回值
+    /// 本次检查触发熔断时返回原因，已熔断或未超过阈值时返回 `None`。
+    pub fn check(&mut self) -> Option<HaltReason> {
+        if self.is_halted() {
+            return None;
+        }
+        let reason = if self
+            .config
+            .max_loss
+            .map_or(false, |max_loss| self.loss() > max_loss)
+        {
+            Some(HaltReason::LossLimit)
+        } else if self
+            .config
+            .max_position_value
+            .map_or(false, |max_value| self.position_value() > max_value)
+        {
+            Some(HaltReason::PositionLimit)
+        } else if self
+            .config
+            .max_consecutive_rejects
+            .map_or(false, |max_rejects| self.consecutive_rejects >= max_rejects)
+        {
+            Some(HaltReason::ConsecutiveRejects)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            self.state = BreakerState::Halted(reason);
+        }
+        reason
+    }
+
+    /// 手动恢复交易，清空连续拒单计数，持仓和盈亏保持不变。
+    ///
+    /// 恢复后亏损上限从当前的亏损重新计算，避免下一次检查立即再次熔断。
+    pub fn reset(&mut self) {
+        self.state = BreakerState::Active;
+        self.consecutive_rejects = 0;
+        self.loss_baseline = self.pnl().min(0.0);
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let pnl = self.pnl();
+        let position_value = self.position_value();
+        BreakerStatus {
+            state: self.state,
+            pnl: pnl,
+            position_value: position_value,
+            consecutive_rejects: self.consecutive_rejects,
+            loss_headroom: self.config.max_loss.map(|max_loss| max_loss - self.loss()),
+            position_headroom: self
+                .config
+                .max_position_value
+                .map(|max_value| max_value - position_value),
+            reject_headroom: self
+                .config
+                .max_consecutive_rejects
+                .map(|max_rejects| max_rejects.saturating_sub(self.consecutive_rejects)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_tracks_pnl_and_halts() {
+        let mut breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            max_loss: Some(500.0),
+            max_consecutive_rejects: Some(2),
+            ..Default::default()
+        });
+        let fill = UserFill {
+            order_id: 1,
+            account: Some("strat".to_string()),
+            strategy_id: 0,
+            side: Side::Buy,
+            price: 10.0,
+            qty: 100.0,
+            liquidity: FillLiquidity::Aggressive,
+            timestamp: 20231201093000000,
+        };
+        // 其他账户的成交不计入
+        let other = UserFill {
+            account: Some("other".to_string()),
+            ..fill.clone()
+        };
+        let fills = vec![fill, other];
+        breaker.on_fills("strat", "600000.SH", &fills);
+        // 重复的成交回报不会重复计入
+        breaker.on_fills("strat", "600000.SH", &fills);
+        assert_eq!(breaker.status().position_value, 1000.0);
+        assert_eq!(breaker.pnl(), 0.0);
+
+        breaker.set_mark_price("600000.SH", 7.0);
+        assert_eq!(breaker.pnl(), -300.0);
+        assert_eq!(breaker.status().loss_headroom, Some(200.0));
+        assert_eq!(breaker.check(), None);
+
+        breaker.set_mark_price("600000.SH", 4.0);
+        assert_eq!(breaker.check(), Some(HaltReason::LossLimit));
+        assert_eq!(breaker.state(), BreakerState::Halted(HaltReason::LossLimit));
+        assert_eq!(breaker.check(), None);
+
+        breaker.reset();
+        assert!(!breaker.is_halted());
+        assert_eq!(breaker.check(), None);
+        assert_eq!(breaker.status().loss_headroom, Some(500.0));
+        breaker.record_reject();
+        breaker.record_accept();
+        breaker.record_reject();
+        assert_eq!(breaker.status().reject_headroom, Some(1));
+    }
+}
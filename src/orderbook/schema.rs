@@ -0,0 +1,329 @@
+use serde::de::Error as DeError;
+use serde::{Deserializer, Serializer};
+use serde_json::{json, Value};
+
+use super::*;
+
+/// 当前快照格式的版本号。
+///
+/// 对外序列化的顶层结构（`Broker` 和 `SkipListMarketDepth` 的快照）都带有
+/// `schema_version` 字段，序列化时总是写出该版本。
+///
+/// # 版本历史
+/// - 版本 1：没有 `schema_version` 字段；`Broker` 没有 `lot_rule`、`price_limit_ratio`、
+///   `price_limit_policy` 和 `same_time_priority`，`Statistics` 没有 `total_cancel_count`
+///   和 `total_cancel_vol`。读取时这些字段取默认值。
+/// - 版本 2：增加 `schema_version` 字段以及上述字段。
+/// - 版本 3：前收盘价（`previous_close_price` 和 `previous_close_tick`）可以为 `null`，表示前收盘价
+///   未知；`Broker` 增加 `opening_reference`，`Statistics` 增加 `first_trade_tick`。之前的版本用 0
+///   表示前收盘价未知，读取时转换为 `null`。`Broker` 增加 `calendar`。
+/// - 版本 4：价格档位保存订单队列 `orders`，从快照恢复后可以继续回放。之前的版本读取为空队列，
+///   `recover` 只能放回用户订单。`Broker` 增加 `cancel_latency`、`ack_latency`
+///   和尚未生效的撤单 `pending_cancels`。
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// 仍然可以读取的最早的快照格式版本。
+pub const MIN_SCHEMA_VERSION: u32 = 1;
+
+/// 缺少 `schema_version` 字段的快照按版本 1 读取。
+pub(crate) fn legacy_version() -> u32 {
+    MIN_SCHEMA_VERSION
+}
+
+/// 序列化时忽略字段当前的值，总是写出 `SCHEMA_VERSION`。
+pub(crate) fn serialize_version<S: Serializer>(_version: &u32, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u32(SCHEMA_VERSION)
+}
+
+/// 读取快照的格式版本，不在 `MIN_SCHEMA_VERSION..=SCHEMA_VERSION` 范围内时返回错误。
+pub(crate) fn deserialize_version<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(d)?;
+    if !(MIN_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) {
+        return Err(D::Error::custom(format!(
+            "unsupported schema version {}, supported versions are {}..={}",
+            version, MIN_SCHEMA_VERSION, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// 读取以 tick 价格表示的前收盘价，版本 3 之前的快照中的 0 读取为 `None`。
+pub(crate) fn deserialize_previous_close_tick<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<i64>, D::Error> {
+    Ok(Option::<i64>::deserialize(d)?.filter(|tick| *tick > 0))
+}
+
+/// 读取前收盘价，版本 3 之前的快照中的 0 读取为 `None`。
+pub(crate) fn deserialize_previous_close_price<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<f64>, D::Error> {
+    Ok(Option::<f64>::deserialize(d)?.filter(|price| *price > 0.0))
+}
+
+/// 返回当前版本各个快照结构的 JSON Schema，供下游在解析前校验。
+///
+/// `properties` 列出当前版本写出的所有字段，`required` 只包含读取时必须存在的字段，
+/// 因此版本 1 的快照同样可以通过校验。
+///
+/// # 返回值
+/// 以类型名为键的 JSON 对象，同时包含 `schema_version` 和 `min_schema_version`。
+pub fn describe() -> Value {
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "min_schema_version": MIN_SCHEMA_VERSION,
+        "types": {
+            "Broker": broker_schema(),
+            "SkipListMarketDepth": depth_schema(),
+        },
+    })
+}
+
+fn broker_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Broker",
+        "type": "object",
+        "properties": {
+            "schema_version": { "type": "integer" },
+            "mode": { "type": "string" },
+            "market_type": { "type": "string" },
+            "stock_type": { "type": "string" },
+            "stock_code": { "type": "string" },
+            "open_tick": { "type": "integer" },
+            "close_tick": { "type": "integer" },
+            "market_depth": depth_schema(),
+            "latest_seq_number": { "type": "integer" },
+            "tick_size": { "type": "number" },
+            "lot_size": { "type": "number" },
+            "lot_rule": { "type": "object" },
+            "previous_close_price": { "type": ["number", "null"] },
+            "opening_reference": { "type": ["string", "object"] },
+            "price_limit_ratio": { "type": "number" },
+            "price_limit_policy": { "type": "string" },
+            "same_time_priority": { "type": "string" },
+            "calendar": { "type": "object" },
+            "cancel_latency": { "type": "integer" },
+            "ack_latency": { "type": "integer" },
+            "pending_cancels": { "type": "array" },
+            "timestamp": { "type": "integer" },
+            "history": { "type": ["object", "null"] },
+        },
+        "required": [
+            "mode", "market_type", "stock_type", "stock_code", "open_tick", "close_tick",
+            "market_depth", "latest_seq_number", "tick_size", "lot_size",
+            "previous_close_price", "timestamp", "history",
+        ],
+    })
+}
+
+fn depth_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "SkipListMarketDepth",
+        "type": "object",
+        "properties": {
+            "schema_version": { "type": "integer" },
+            "ask_depth": { "type": "object" },
+            "bid_depth": { "type": "object" },
+            "tick_size": { "type": "number" },
+            "lot_size": { "type": "number" },
+            "timestamp": { "type": "integer" },
+            "best_bid_tick": { "type": "integer" },
+            "best_ask_tick": { "type": "integer" },
+            "last_tick": { "type": "integer" },
+            "previous_close_tick": { "type": ["integer", "null"] },
+            "orders": { "type": "object" },
+            "mode": { "type": "string" },
+            "market_statistics": statistics_schema(),
+            "market_shadow": { "type": ["object", "null"] },
+        },
+        "required": [
+            "ask_depth", "bid_depth", "tick_size", "lot_size", "timestamp", "best_bid_tick",
+            "best_ask_tick", "last_tick", "previous_close_tick", "orders", "mode",
+            "market_statistics", "market_shadow",
+        ],
+    })
+}
+
+fn statistics_schema() -> Value {
+    json!({
+        "title": "Statistics",
+        "type": "object",
+        "properties": {
+            "total_bid_num": { "type": "integer" },
+            "total_ask_num": { "type": "integer" },
+            "total_cancel": { "type": "integer" },
+            "total_cancel_count": { "type": "integer" },
+            "total_cancel_vol": { "type": "integer" },
+            "total_bid_tick": { "type": "integer" },
+            "total_ask_tick": { "type": "integer" },
+            "total_bid_vol": { "type": "integer" },
+            "total_ask_vol": { "type": "integer" },
+            "total_bid_order": { "type": "integer" },
+            "total_ask_order": { "type": "integer" },
+            "user_aggressive_vol": { "type": "integer" },
+            "user_passive_vol": { "type": "integer" },
+            "user_auction_vol": { "type": "integer" },
+            "high": { "type": "integer" },
+            "low": { "type": "integer" },
+            "open_tick": { "type": "integer" },
+            "close_tick": { "type": "integer" },
+            "previous_close_tick": { "type": ["integer", "null"] },
+            "first_trade_tick": { "type": ["integer", "null"] },
+        },
+        "required": [
+            "total_bid_num", "total_ask_num", "total_cancel", "total_bid_tick", "total_ask_tick",
+            "total_bid_vol", "total_ask_vol", "total_bid_order", "total_ask_order", "high", "low",
+            "open_tick", "close_tick", "previous_close_tick",
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::broker::Broker;
+    use super::skiplist_orderbook::SkipListMarketDepth;
+    use super::*;
+
+    const BROKER_V1: &str = include_str!("../../tests/fixtures/broker_snapshot_v1.json");
+    const DEPTH_V1: &str = include_str!("../../tests/fixtures/depth_snapshot_v1.json");
+
+    /// 按 `describe` 给出的 schema 校验字段名和字段类型，嵌套对象递归校验。
+    fn validate(schema: &Value, value: &Value, path: &str) {
+        let object = value.as_object().expect(path);
+        let properties = schema["properties"].as_object().unwrap();
+        for key in object.keys() {
+            assert!(
+                properties.contains_key(key),
+                "{}.{} not in schema",
+                path,
+                key
+            );
+        }
+        for key in schema["required"].as_array().unwrap() {
+            let key = key.as_str().unwrap();
+            assert!(object.contains_key(key), "{}.{} is missing", path, key);
+        }
+        for (key, field) in object {
+            let field_schema = &properties[key];
+            let types: Vec<&str> = match &field_schema["type"] {
+                Value::String(ty) => vec![ty.as_str()],
+                Value::Array(types) => types.iter().map(|ty| ty.as_str().unwrap()).collect(),
+                _ => unreachable!(),
+            };
+            let matched = types.iter().any(|ty| match *ty {
+                "integer" => field.is_i64() || field.is_u64(),
+                "number" => field.is_number(),
+                "string" => field.is_string(),
+                "object" => field.is_object(),
+                "array" => field.is_array(),
+                "null" => field.is_null(),
+                _ => false,
+            });
+            assert!(matched, "{}.{} has unexpected type", path, key);
+            if field_schema.get("properties").is_some() {
+                validate(field_schema, field, &format!("{}.{}", path, key));
+            }
+        }
+    }
+
+    fn sample_broker() -> Broker<SkipListMarketDepth> {
+        let mut broker: Broker<SkipListMarketDepth> = Broker::new(
+            ExchangeMode::Live,
+            MarketType::SH,
+            "stock".to_string(),
+            "600000.SH".to_string(),
+            0.01,
+            100.0,
+        );
+        broker.init();
+        for (source, order_id, side, price_tick) in [
+            (OrderSourceType::LocalOrder, 1, Side::Buy, 1000),
+            (OrderSourceType::UserOrder, 2, Side::Sell, 1001),
+        ] {
+            let order_ref = L3Order::new_ref(
+                source,
+                None,
+                order_id,
+                side,
+                price_tick,
+                3,
+                20231201093000000,
+                OrderType::L,
+            );
+            broker.market_depth.add(order_ref).unwrap();
+        }
+        broker
+    }
+
+    #[test]
+    fn test_current_version_round_trip() {
+        let broker = sample_broker();
+        let snapshot: Value = serde_json::from_str(&broker.snapshot()).unwrap();
+        assert_eq!(snapshot["schema_version"], json!(SCHEMA_VERSION));
+        assert_eq!(
+            snapshot["market_depth"]["schema_version"],
+            json!(SCHEMA_VERSION)
+        );
+        validate(&describe()["types"]["Broker"], &snapshot, "Broker");
+
+        let restored: Broker<SkipListMarketDepth> =
+            serde_json::from_str(&broker.snapshot()).unwrap();
+        assert_eq!(restored.schema_version, SCHEMA_VERSION);
+        let restored_snapshot: Value = serde_json::from_str(&restored.snapshot()).unwrap();
+        assert_eq!(restored_snapshot, snapshot);
+
+        let depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        let depth_snapshot: Value = serde_json::from_str(&depth.snapshot()).unwrap();
+        validate(
+            &describe()["types"]["SkipListMarketDepth"],
+            &depth_snapshot,
+            "SkipListMarketDepth",
+        );
+    }
+
+    #[test]
+    fn test_previous_version_fixtures() {
+        let fixture: Value = serde_json::from_str(BROKER_V1).unwrap();
+        assert!(fixture.get("schema_version").is_none());
+        validate(&describe()["types"]["Broker"], &fixture, "Broker");
+
+        let broker: Broker<SkipListMarketDepth> = serde_json::from_str(BROKER_V1).unwrap();
+        assert_eq!(broker.schema_version, MIN_SCHEMA_VERSION);
+        assert_eq!(broker.market_depth.schema_version, MIN_SCHEMA_VERSION);
+        assert_eq!(broker.stock_code, "600000.SH");
+        assert_eq!(broker.lot_rule, LotRule::default());
+        assert_eq!(broker.price_limit_ratio, 0.0);
+        assert_eq!(broker.market_depth.best_bid_tick, 1000);
+        assert_eq!(broker.market_depth.best_ask_tick, 1001);
+        assert_eq!(broker.market_depth.orders().len(), 1);
+        assert_eq!(broker.market_depth.market_statistics.total_cancel_count, 0);
+        // 版本 1 用 0 表示前收盘价未知
+        assert_eq!(broker.previous_close_price, None);
+        assert_eq!(broker.market_depth.previous_close_tick, None);
+        assert_eq!(
+            broker.market_depth.market_statistics.previous_close_tick,
+            None
+        );
+        // 重新写出的快照升级为当前版本
+        let upgraded: Value = serde_json::from_str(&broker.snapshot()).unwrap();
+        assert_eq!(upgraded["schema_version"], json!(SCHEMA_VERSION));
+        validate(&describe()["types"]["Broker"], &upgraded, "Broker");
+
+        let depth = SkipListMarketDepth::read_snapshot(DEPTH_V1.as_bytes()).unwrap();
+        assert_eq!(depth.schema_version, MIN_SCHEMA_VERSION);
+        assert_eq!(depth.mode, ExchangeMode::Backtest);
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let mut snapshot: Value = serde_json::from_str(&sample_broker().snapshot()).unwrap();
+        snapshot["schema_version"] = json!(SCHEMA_VERSION + 1);
+        let result: Result<Broker<SkipListMarketDepth>, _> = serde_json::from_value(snapshot);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unsupported schema version"));
+    }
+}
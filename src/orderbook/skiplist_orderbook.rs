@@ -1,14 +1,19 @@
 use super::skiplist_helper::skiplist_serde;
 use super::types::ExchangeMode;
+use super::utils::validate_price_qty;
 use super::*;
 use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
-use polars::prelude::LhsNumOps;
 use serde::de::Expected;
 use serde::{Deserialize, Serialize};
 use skiplist::SkipMap;
 use statistics::Statistics;
 use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Write};
 
+use super::analytics::{BookAnalytics, BookAnalyticsReport};
+use super::observer::{DepthObserver, DepthObservers, FillEvent};
+use super::queue_model::QueueModel;
+use super::schema::SCHEMA_VERSION;
 use super::ValueOp;
 use std::cmp;
 use std::collections::{hash_map::Entry, HashMap};
@@ -21,8 +26,8 @@ pub struct PriceLevel {
     pub direction: Side,
     // 当前的交易模式
     pub mode: ExchangeMode,
-    // 存储当前价格层级中的所有订单
-    #[serde(skip)]
+    // 存储当前价格层级中的所有订单，版本 4 之前的快照中没有该字段
+    #[serde(default)]
     pub orders: VecDeque<Option<L3OrderRef>>,
     // 当前价格层级的总交易量
     pub vol: i64,
@@ -30,6 +35,9 @@ pub struct PriceLevel {
     pub vol_shadow: i64,
     // 当前价格层级中的订单总数
     pub count: i64,
+    /// 历史订单在当前价格层级累计成交的量，仅在回测模式下用于排队模型
+    #[serde(default)]
+    pub traded: i64,
 }
 
 impl ValueOp for PriceLevel {
@@ -54,6 +62,7 @@ impl PriceLevel {
             vol: 0,
             vol_shadow: 0,
             count: 0,
+            traded: 0,
         }
     }
 
@@ -77,6 +86,7 @@ impl PriceLevel {
             self.vol_shadow += order.vol;
         } else {
             order.total_vol_before = self.vol_shadow;
+            order.traded_mark = self.traded;
             self.vol_shadow += order.vol_shadow;
         };
 
@@ -120,8 +130,58 @@ impl PriceLevel {
         self.count -= 1;
         // 标记订单为删除状态
         order.side = Side::None;
+        // 没有抵扣完的撤单量排在后面的用户挂单之前，转给其中的第一个
+        if order.queue_debt > 0 {
+            if let Some(next_ref) = self.next_user_order(order.idx) {
+                next_ref.borrow_mut().queue_debt += order.queue_debt;
+            }
+            order.queue_debt = 0;
+        }
         Ok(true)
     }
+
+    /// 返回排在第 `idx` 个订单之后的第一个用户挂单，`idx` 从 1 开始。
+    fn next_user_order(&self, idx: usize) -> Option<&L3OrderRef> {
+        self.orders
+            .iter()
+            .skip(idx)
+            .flatten()
+            .find(|order_ref| order_ref.borrow().source == OrderSourceType::UserOrder)
+    }
+
+    /// 减少当前价格层级中订单的剩余数量，订单保留原有的排队位置。
+    ///
+    /// 影子数量最多减少到零，已经被用户订单消耗的部分不再扣除。
+    ///
+    /// # 参数
+    /// - `order_ref`: 要减少数量的订单的引用。
+    /// - `vol`: 减少的数量，必须小于订单的剩余数量。
+    ///
+    /// # 返回值
+    /// 返回实际减少的影子数量。
+    ///
+    /// # 错误
+    /// 订单不在当前价格层级中时返回 `MarketError::OrderNotFound`。
+    pub fn reduce_order(&mut self, order_ref: &L3OrderRef, vol: i64) -> Result<i64, MarketError> {
+        let idx = order_ref.borrow().idx;
+        let resting = match self.orders.get(idx.wrapping_sub(1)) {
+            Some(Some(resting)) => resting.borrow().order_id == order_ref.borrow().order_id,
+            _ => false,
+        };
+        if !resting {
+            return Err(MarketError::OrderNotFound);
+        }
+
+        let mut order = order_ref.borrow_mut();
+        let shadow = vol.min(order.vol_shadow);
+        if self.mode == ExchangeMode::Live || order.source == OrderSourceType::LocalOrder {
+            self.vol -= vol;
+        }
+        self.vol_shadow -= shadow;
+        order.vol -= vol;
+        order.vol_shadow -= shadow;
+        Ok(shadow)
+    }
     /// 更新当前价格层级中所有订单的位置。
     ///
     /// 该方法遍历价格层级中的所有订单，重新计算并更新每个订单的位置。订单的位置是根据订单的来源（市场订单或用户订单）和其在价格层级中的相对位置来确定的。
@@ -147,9 +207,150 @@ impl PriceLevel {
         }
     }
 
+    /// 将队列中的订单移到队首，档位的成交量和订单数不变。
+    ///
+    /// 移动后重新计算所有订单的位置和排在前面的成交量。
+    ///
+    /// # 错误
+    /// 订单不在当前价格层级中时返回 `MarketError::OrderNotFound`。
+    pub fn move_to_front(&mut self, order_ref: &L3OrderRef) -> Result<(), MarketError> {
+        let idx = order_ref.borrow().idx;
+        let resting = match self.orders.get(idx.wrapping_sub(1)) {
+            Some(Some(resting)) => resting.borrow().order_id == order_ref.borrow().order_id,
+            _ => false,
+        };
+        if !resting {
+            return Err(MarketError::OrderNotFound);
+        }
+
+        let order = self.orders.remove(idx - 1).flatten();
+        self.orders.push_front(order);
+        for (idx, order_ref) in self.orders.iter().enumerate() {
+            if let Some(order_ref) = order_ref {
+                order_ref.borrow_mut().idx = idx + 1;
+            }
+        }
+        self.update_order_position();
+        Ok(())
+    }
+
+    /// 将订单追加到队列末尾，档位的成交量按订单当前的剩余数量和影子数量累加。
+    ///
+    /// 与 `add_order` 不同，历史订单已经被用户订单消耗的影子数量不会被恢复，用于重建订单簿。
+    pub fn append_order(&mut self, order_ref: L3OrderRef) {
+        {
+            let mut order = order_ref.borrow_mut();
+            if self.mode == ExchangeMode::Live || order.source == OrderSourceType::LocalOrder {
+                self.vol += order.vol;
+            }
+            self.vol_shadow += order.vol_shadow;
+            self.count += 1;
+            order.idx = self.orders.len() + 1;
+        }
+        self.orders.push_back(Some(order_ref));
+    }
+
     pub fn clear(&mut self) {
         self.orders.clear();
     }
+
+    /// 将从快照恢复的订单放回它原来在队列中的位置，不修改成交量和订单数。
+    ///
+    /// 该位置上是同一订单的副本时用 `order_ref` 替换；版本 4 之前的快照不保存订单队列，
+    /// 该位置为空，档位的成交量和订单数已经包含了这个订单。
+    ///
+    /// # 错误
+    /// 订单的位置无效或已被其他订单占用时返回 `MarketError::RecoverFailed`。
+    pub fn restore_order(&mut self, order_ref: L3OrderRef) -> Result<(), MarketError> {
+        let (idx, order_id) = {
+            let order = order_ref.borrow();
+            (order.idx, order.order_id)
+        };
+        if idx == 0 {
+            return Err(MarketError::RecoverFailed);
+        }
+        if self.orders.len() < idx {
+            self.orders.resize(idx, None);
+        }
+        if let Some(resting) = &self.orders[idx - 1] {
+            if resting.borrow().order_id != order_id {
+                return Err(MarketError::RecoverFailed);
+            }
+        }
+        self.orders[idx - 1] = Some(order_ref);
+        Ok(())
+    }
+
+    /// 按排队模型记录历史挂单撤单对排在它后面的用户挂单的影响。
+    ///
+    /// 只在回测模式下处理仍在队列中的历史挂单。撤销的影子成交量中没有被模型计为推进的部分
+    /// 只累加到排在它后面的第一个用户挂单的 `queue_debt` 中：更靠后的用户挂单要等前面的
+    /// 用户挂单成交后才轮到，这部分量已经在前面抵扣过，不能重复计入。该用户挂单离开队列时，
+    /// 没有抵扣完的部分转给下一个用户挂单。
+    ///
+    /// # 参数
+    /// - `order`: 即将撤销的订单。
+    /// - `model`: 排队模型。
+    pub fn charge_cancel_ahead(&self, order: &L3Order, model: &dyn QueueModel) {
+        if self.mode != ExchangeMode::Backtest || order.source != OrderSourceType::LocalOrder {
+            return;
+        }
+        let resting = match self.orders.get(order.idx.wrapping_sub(1)) {
+            Some(Some(order_ref)) => order_ref.borrow().order_id == order.order_id,
+            _ => false,
+        };
+        if !resting || order.vol_shadow == 0 {
+            return;
+        }
+        let user_ref = match self.next_user_order(order.idx) {
+            Some(user_ref) => user_ref,
+            None => return,
+        };
+        let mut user = user_ref.borrow_mut();
+        let canceled = order.vol_shadow;
+        // 模型对已成交量的推进在成交时已经生效，这里只计入撤单带来的增量
+        let traded = self.traded - user.traded_mark;
+        let credited =
+            model.adjust_fill(&user, traded, canceled) - model.adjust_fill(&user, traded, 0);
+        user.queue_debt += canceled - credited.clamp(0, canceled);
+    }
+
+    /// 返回当前价格层级中假设挂单的剩余数量。
+    pub fn phantom_vol(&self) -> i64 {
+        self.orders
+            .iter()
+            .flatten()
+            .map(|order_ref| order_ref.borrow())
+            .filter(|order| order.phantom)
+            .map(|order| order.vol)
+            .sum()
+    }
+
+    /// 返回价格层级中用户订单的剩余数量之和。
+    pub fn user_vol(&self) -> i64 {
+        self.orders
+            .iter()
+            .flatten()
+            .map(|order_ref| order_ref.borrow())
+            .filter(|order| order.source == OrderSourceType::UserOrder)
+            .map(|order| order.vol)
+            .sum()
+    }
+
+    /// 返回价格层级中计入 `vol` 的订单剩余数量之和。
+    ///
+    /// 实时模式下所有订单都计入，回测模式下用户订单只占用影子成交量，不计入。
+    pub fn orders_vol(&self) -> i64 {
+        self.orders
+            .iter()
+            .flatten()
+            .map(|order_ref| order_ref.borrow())
+            .filter(|order| {
+                self.mode == ExchangeMode::Live || order.source == OrderSourceType::LocalOrder
+            })
+            .map(|order| order.vol)
+            .sum()
+    }
     /// 根据市场模式匹配订单并返回成交量。
     ///
     /// - 在回测模式下，调用 `shadow_match` 方法进行匹配。
@@ -160,7 +361,14 @@ impl PriceLevel {
     ///
     /// # 返回值
     /// 成功匹配时，返回已成交的总量；如果发生错误（如模式不支持），则返回相应的 `MarketError`。
+    ///
+    /// # 错误
+    /// 订单与价格档位方向相同时返回 `MarketError::MarketSideError`，
+    /// 同方向的档位中可能包含订单自己，撮合时会重复借用同一个订单。
     pub fn match_order(&mut self, order: L3OrderRef) -> Result<i64, MarketError> {
+        if order.borrow().side == self.direction {
+            return Err(MarketError::MarketSideError);
+        }
         match self.mode {
             ExchangeMode::Backtest => self.shadow_match(order),
             ExchangeMode::Live => self.live_match(order),
@@ -215,6 +423,7 @@ impl PriceLevel {
                 if other.source == OrderSourceType::LocalOrder {
                     if order.vol >= other.vol {
                         filled += other.vol;
+                        self.traded += other.vol;
                         order.vol -= other.vol;
                         self.vol -= other.vol;
                         self.vol_shadow -= other.vol_shadow;
@@ -225,6 +434,7 @@ impl PriceLevel {
                         self.count -= 1;
                     } else {
                         filled += order.vol;
+                        self.traded += order.vol;
                         other.vol -= order.vol;
                         self.vol -= order.vol;
                         self.vol_shadow -= other.vol_shadow;
@@ -233,6 +443,10 @@ impl PriceLevel {
                         order.vol = 0;
                     }
                 } else if other.source == OrderSourceType::UserOrder {
+                    // 先抵扣排队模型没有计为推进的撤单量
+                    let paid = cmp::min(other.queue_debt, order.vol_shadow);
+                    other.queue_debt -= paid;
+                    order.vol_shadow -= paid;
                     if order.vol_shadow >= other.vol {
                         filled += other.vol;
                         order.vol_shadow -= other.vol;
@@ -243,6 +457,7 @@ impl PriceLevel {
                     } else {
                         filled += order.vol_shadow;
                         other.vol -= order.vol_shadow;
+                        other.vol_shadow -= order.vol_shadow;
                         self.vol_shadow -= order.vol_shadow;
                         order.vol_shadow = 0;
                     }
@@ -263,6 +478,7 @@ impl PriceLevel {
                 } else if other.source == OrderSourceType::UserOrder {
                     if order.vol >= other.vol {
                         filled += other.vol;
+                        order.vol -= other.vol;
                         self.vol_shadow -= other.vol;
                         other.vol = 0;
                         self.orders[idx] = None;
@@ -270,7 +486,7 @@ impl PriceLevel {
                     } else {
                         filled += order.vol;
                         other.vol -= order.vol;
-                        other.vol_shadow -= other.vol;
+                        other.vol_shadow -= order.vol;
                         self.vol_shadow -= order.vol;
                         order.vol = 0;
                     }
@@ -392,6 +608,13 @@ type DepthType = SkipMap<i64, PriceLevel>;
 /// - `market_statistics`: 与市场活动相关的统计数据
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SkipListMarketDepth {
+    /// 快照的格式版本，序列化时总是写出当前版本，缺失时按版本 1 读取
+    #[serde(
+        default = "schema::legacy_version",
+        serialize_with = "schema::serialize_version",
+        deserialize_with = "schema::deserialize_version"
+    )]
+    pub schema_version: u32,
     /// 卖盘深度信息，使用跳表结构进行序列化和反序列化。
     #[serde(with = "skiplist_serde")]
     pub ask_depth: DepthType,
@@ -416,8 +639,9 @@ pub struct SkipListMarketDepth {
     /// 最新交易的 tick 价格。
     pub last_tick: i64,
 
-    /// 前一交易日的收盘价，用 tick 价格表示。
-    pub previous_close_tick: i64,
+    /// 前一交易日的收盘价，用 tick 价格表示，`None` 表示前收盘价未知。
+    #[serde(deserialize_with = "schema::deserialize_previous_close_tick")]
+    pub previous_close_tick: Option<i64>,
 
     /// 活跃订单的哈希映射，通过唯一标识符索引。
     pub orders: HashMap<OrderId, L3OrderRef>,
@@ -430,6 +654,35 @@ pub struct SkipListMarketDepth {
 
     /// 市场深度的影子副本，用于某些特殊场景的市场深度处理。
     market_shadow: Option<MarketDepthShadow>,
+
+    /// 订阅成交、挂单、撤单和最优价变化事件的观察者，`market_statistics` 总是最先被通知。
+    #[serde(skip)]
+    pub observers: DepthObservers,
+
+    /// 订单簿的时间加权统计，调用 `enable_analytics` 后才会采样。
+    #[serde(skip)]
+    pub book_analytics: Option<BookAnalytics>,
+
+    /// 是否加入过假设挂单，没有时撮合不需要统计与假设挂单的成交量。
+    /// 经纪商撤销全部假设挂单后通过 `clear_phantoms` 重置。
+    #[serde(skip)]
+    has_phantoms: bool,
+
+    /// 回测模式下用户挂单的排队模型，`None` 表示排在前面的撤单量全部推进排队位置。
+    #[serde(skip)]
+    queue_model: Option<Box<dyn QueueModel>>,
+
+    /// 挂单价格允许的最大 tick，超出时 `add` 返回 `MarketError::PriceTickOutOfRange`。
+    #[serde(skip, default = "default_price_tick_limit")]
+    price_tick_limit: i64,
+
+    /// 撮合时访问过的价格档位数量，不能与对手方成交的限价订单不访问任何档位。
+    #[serde(skip)]
+    pub levels_visited: u64,
+}
+
+fn default_price_tick_limit() -> i64 {
+    PRICE_TICK_LIMIT
 }
 
 impl SkipListMarketDepth {
@@ -440,6 +693,7 @@ impl SkipListMarketDepth {
         };
 
         Self {
+            schema_version: SCHEMA_VERSION,
             ask_depth: SkipMap::with_capacity(200),
             bid_depth: SkipMap::with_capacity(200),
             tick_size: tick_size,
@@ -448,23 +702,293 @@ impl SkipListMarketDepth {
             best_bid_tick: INVALID_MIN,
             best_ask_tick: INVALID_MAX,
             last_tick: INVALID_MIN,
-            previous_close_tick: 0,
+            previous_close_tick: None,
             orders: HashMap::new(),
             mode: mode,
             market_statistics: Statistics::new(),
             market_shadow: market_shadow,
+            observers: DepthObservers::default(),
+            book_analytics: None,
+            has_phantoms: false,
+            queue_model: None,
+            price_tick_limit: PRICE_TICK_LIMIT,
+            levels_visited: 0,
+        }
+    }
+
+    /// 使影子订单簿与交易模式一致，只有回测模式保留影子订单簿。
+    ///
+    /// 快照中缺少影子订单簿时（例如快照被编辑过，或者由其他模式的订单簿改写而来），
+    /// 回测模式根据各档位的影子成交量重建影子最优价，影子最新成交价取真实订单簿的最新成交价。
+    fn restore_market_shadow(&mut self) {
+        if self.mode != ExchangeMode::Backtest {
+            self.market_shadow = None;
+            return;
+        }
+        if self.market_shadow.is_some() {
+            return;
+        }
+        let mut market_shadow = MarketDepthShadow::new();
+        if let Some((price_tick, _)) = self
+            .bid_depth
+            .iter()
+            .find(|(_, price_level)| price_level.vol_shadow > 0)
+        {
+            market_shadow.best_bid_tick = price_tick.abs();
+        }
+        if let Some((price_tick, _)) = self
+            .ask_depth
+            .iter()
+            .find(|(_, price_level)| price_level.vol_shadow > 0)
+        {
+            market_shadow.best_ask_tick = *price_tick;
+        }
+        market_shadow.last_tick = self.last_tick;
+        self.market_shadow = Some(market_shadow);
+    }
+
+    /// 是否加入过仍需统计成交量的假设挂单。
+    pub fn has_phantoms(&self) -> bool {
+        self.has_phantoms
+    }
+
+    /// 以流式方式将市场深度序列化写入给定的输出。
+    ///
+    /// 与 [`SnapshotOp::snapshot`] 不同，该方法不会在内存中构造完整的 JSON 字符串，
+    /// 而是通过带缓冲的写入器逐步输出，适用于订单簿较大的场景。
+    ///
+    /// # 参数
+    /// - `w`: 快照的输出目标，例如文件。
+    ///
+    /// # 错误
+    /// 序列化失败时返回 `SnapshotError::Serde`，写入失败时返回 `SnapshotError::Io`。
+    pub fn write_snapshot<W: io::Write>(&self, w: W) -> Result<(), SnapshotError> {
+        let mut writer = BufWriter::new(w);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 以流式方式从给定的输入中读取由 [`SkipListMarketDepth::write_snapshot`] 写出的快照。
+    ///
+    /// # 参数
+    /// - `r`: 快照的输入来源，例如文件。
+    ///
+    /// # 返回值
+    /// 返回反序列化得到的 `SkipListMarketDepth` 实例。
+    ///
+    /// # 错误
+    /// 读取或反序列化失败时返回相应的 `SnapshotError`。
+    pub fn read_snapshot<R: io::Read>(r: R) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(r);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// 注册一个市场深度事件的观察者。
+    ///
+    /// # 参数
+    /// - `observer`: 观察者，在成交、挂单、撤单以及最优价变化时被通知。
+    ///
+    /// # 返回值
+    /// 注册成功返回 `true`，观察者数量已满时返回 `false`。
+    pub fn register_observer(&mut self, observer: Box<dyn DepthObserver>) -> bool {
+        self.observers.register(observer)
+    }
+
+    /// 开始统计时间加权的买卖价差和最优价挂单量，已有的统计会被清空。
+    pub fn enable_analytics(&mut self) {
+        self.book_analytics = Some(BookAnalytics::new());
+    }
+
+    /// 返回订单簿的时间加权统计，未调用 `enable_analytics` 时返回 `None`。
+    pub fn analytics(&self) -> Option<BookAnalyticsReport> {
+        self.book_analytics
+            .as_ref()
+            .map(|analytics| analytics.report(self.tick_size, self.lot_size))
+    }
+
+    /// 设置挂单价格允许的最大 tick，默认为 `PRICE_TICK_LIMIT`，不会写入快照。
+    pub fn set_price_tick_limit(&mut self, limit: i64) {
+        self.price_tick_limit = limit;
+    }
+
+    /// 按拆股比例调整所有挂单，价格除以 `ratio`，数量乘以 `ratio`，挂单的名义金额基本不变。
+    ///
+    /// 调整后的价格和数量四舍五入到整数 tick 和手，调整后价格相同的档位按原来的价格优先顺序合并。
+    /// 数量四舍五入后为 0 的挂单被撤销，与普通撤单一样保留剩余数量并将方向置为 `Side::None`。
+    /// 最优价、最新成交价和前收盘价同样按比例调整，已经发生的成交统计保持不变。
+    ///
+    /// # 参数
+    /// - `ratio`: 每股拆分后的股数，例如 10 送 10 为 `2.0`。
+    ///
+    /// # 错误
+    /// - `ratio` 不是正数时返回 `MarketError::InvalidCorporateAction`。
+    /// - 调整后的价格超出有效范围时返回 `MarketError::PriceTickOutOfRange`，订单簿保持不变。
+    pub fn apply_split(&mut self, ratio: f64) -> Result<(), MarketError> {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(MarketError::InvalidCorporateAction(format!(
+                "split ratio {ratio} is not positive"
+            )));
+        }
+        self.adjust_depth(
+            |price_tick| (price_tick as f64 / ratio).round() as i64,
+            |vol| (vol as f64 * ratio).round() as i64,
+        )
+    }
+
+    /// 按每股现金分红调整所有挂单的价格，数量不变。
+    ///
+    /// 分红金额按 `tick_size` 四舍五入为 tick 后从价格中扣除，最优价、最新成交价和前收盘价同样调整。
+    ///
+    /// # 参数
+    /// - `amount`: 每股分红金额。
+    ///
+    /// # 错误
+    /// - `amount` 为负数时返回 `MarketError::InvalidCorporateAction`。
+    /// - 调整后的价格不是正数时返回 `MarketError::PriceTickOutOfRange`，订单簿保持不变。
+    pub fn apply_cash_dividend(&mut self, amount: f64) -> Result<(), MarketError> {
+        if !amount.is_finite() || amount < 0.0 {
+            return Err(MarketError::InvalidCorporateAction(format!(
+                "cash dividend {amount} is negative"
+            )));
+        }
+        let dividend_tick = (amount / self.tick_size).round() as i64;
+        self.adjust_depth(|price_tick| price_tick - dividend_tick, |vol| vol)
+    }
+
+    /// 调整所有挂单的价格和数量，并以调整后的价格重建买卖盘。
+    fn adjust_depth(
+        &mut self,
+        adjust_tick: impl Fn(i64) -> i64,
+        adjust_vol: impl Fn(i64) -> i64,
+    ) -> Result<(), MarketError> {
+        // 先检查所有调整后的价格，失败时不修改订单簿
+        for (price_tick, _) in self.bid_depth.iter().chain(self.ask_depth.iter()) {
+            let adjusted = adjust_tick(price_tick.abs());
+            if adjusted <= 0 || adjusted > self.price_tick_limit {
+                return Err(MarketError::PriceTickOutOfRange(adjusted));
+            }
+        }
+
+        let mode = self.mode.clone();
+        let bid_depth = std::mem::replace(&mut self.bid_depth, SkipMap::with_capacity(200));
+        let ask_depth = std::mem::replace(&mut self.ask_depth, SkipMap::with_capacity(200));
+        // 按原来的价格优先顺序遍历，合并后的档位中价格更优的订单排在前面
+        for (side, old_depth) in [(Side::Buy, bid_depth), (Side::Sell, ask_depth)] {
+            let depth = match side {
+                Side::Buy => &mut self.bid_depth,
+                _ => &mut self.ask_depth,
+            };
+            for (_, price_level) in old_depth {
+                for order_ref in price_level.orders.into_iter().flatten() {
+                    let key = {
+                        let mut order = order_ref.borrow_mut();
+                        order.price_tick = adjust_tick(order.price_tick);
+                        order.dirty = true;
+                        if adjust_vol(order.vol) == 0 {
+                            order.side = Side::None;
+                            continue;
+                        }
+                        order.vol = adjust_vol(order.vol);
+                        order.vol_shadow = adjust_vol(order.vol_shadow);
+                        order.queue_debt = adjust_vol(order.queue_debt);
+                        match side {
+                            Side::Buy => -order.price_tick,
+                            _ => order.price_tick,
+                        }
+                    };
+                    if depth.get(&key).is_none() {
+                        depth.insert(key, PriceLevel::new(mode.clone(), side));
+                    }
+                    depth.get_mut(&key).unwrap().append_order(order_ref);
+                }
+            }
+        }
+        for (_, price_level) in self.bid_depth.iter_mut().chain(self.ask_depth.iter_mut()) {
+            price_level.update_order_position();
+        }
+
+        let adjust_valid_tick = |tick: i64| match tick {
+            INVALID_MIN | INVALID_MAX => tick,
+            _ => adjust_tick(tick),
+        };
+        self.last_tick = adjust_valid_tick(self.last_tick);
+        if let Some(market_shadow) = self.market_shadow.as_mut() {
+            market_shadow.best_bid_tick = adjust_valid_tick(market_shadow.best_bid_tick);
+            market_shadow.best_ask_tick = adjust_valid_tick(market_shadow.best_ask_tick);
+            market_shadow.last_tick = adjust_valid_tick(market_shadow.last_tick);
+        }
+        let previous_close_tick = self.previous_close_tick.map(&adjust_tick);
+        self.set_previous_close_tick(previous_close_tick);
+        self.update_bid_depth()?;
+        self.update_ask_depth()?;
+        Ok(())
+    }
+
+    /// 以可读的形式输出订单簿，买卖盘并排显示，用于调试。
+    ///
+    /// 价格按 `tick_size` 换算为实际价格，数量按 `lot_size` 换算为实际数量。
+    ///
+    /// # 参数
+    /// - `levels`: 每一方显示的最大档位数量。
+    ///
+    /// # 返回值
+    /// 每行一个档位的文本，左侧为买盘，右侧为卖盘。
+    pub fn display_book(&self, levels: usize) -> String {
+        let mut bid_levels: Vec<(f64, f64, i64)> = Vec::with_capacity(levels);
+        let mut ask_levels: Vec<(f64, f64, i64)> = Vec::with_capacity(levels);
+        self.get_orderbook_level(&mut bid_levels, &mut ask_levels, levels);
+
+        // 价格显示的小数位数与 tick_size 保持一致
+        let price_decimals = (-self.tick_size.log10()).ceil().max(0.0) as usize;
+        let format_level = |level: Option<&(f64, f64, i64)>| match level {
+            Some(&(price, qty, _)) => (format!("{:.*}", price_decimals, price), format!("{}", qty)),
+            None => (String::new(), String::new()),
+        };
+
+        let mut book = format!(
+            "{:>12} {:>12} | {:<12} {:<12}\n",
+            "BID QTY", "BID PRICE", "ASK PRICE", "ASK QTY"
+        );
+        for idx in 0..cmp::max(bid_levels.len(), ask_levels.len()) {
+            let (bid_price, bid_qty) = format_level(bid_levels.get(idx));
+            let (ask_price, ask_qty) = format_level(ask_levels.get(idx));
+            book.push_str(&format!(
+                "{:>12} {:>12} | {:<12} {:<12}\n",
+                bid_qty, bid_price, ask_price, ask_qty
+            ));
         }
+        book
+    }
+
+    /// 以紧凑的 JSON 同时返回买卖盘的前若干档。
+    ///
+    /// 输出格式为 `{"bids":[[价格,数量,订单数],...],"asks":[...]}`，价格和数量为实际值，
+    /// 不包含 `PriceLevel` 的内部字段，适合提供给外部使用。
+    ///
+    /// # 参数
+    /// - `levels`: 每一方返回的最大档位数量。
+    pub fn top_levels_json(&self, levels: usize) -> String {
+        let mut bids: Vec<(f64, f64, i64)> = Vec::with_capacity(levels);
+        let mut asks: Vec<(f64, f64, i64)> = Vec::with_capacity(levels);
+        self.get_orderbook_level(&mut bids, &mut asks, levels);
+        serde_json::json!({ "bids": bids, "asks": asks }).to_string()
     }
 
     fn delete_order(&mut self, order_ref: L3OrderRef) -> Result<(Side, i64, i64), MarketError> {
         let side = order_ref.borrow().side.clone();
         let price_tick = order_ref.borrow().price_tick;
         order_ref.borrow_mut().dirty = true;
+        self.market_statistics.on_cancel(&order_ref.borrow());
+        self.observers.on_cancel(&order_ref.borrow());
         // 根据订单的买卖方向更新相应的市场深度
         if side == Side::Buy {
             let prev_best_tick = self.best_bid_tick;
 
             if let Some(price_level) = self.bid_depth.get_mut(&-price_tick) {
+                if let Some(model) = self.queue_model.as_deref() {
+                    price_level.charge_cancel_ahead(&order_ref.borrow(), model);
+                }
                 price_level.delete_order(&order_ref).map_err(|err| {
                     // 返回 MarketError::OrderDeleteFailed 错误
                     err
@@ -477,6 +1001,9 @@ impl SkipListMarketDepth {
             let prev_best_tick = self.best_ask_tick;
 
             if let Some(price_level) = self.ask_depth.get_mut(&price_tick) {
+                if let Some(model) = self.queue_model.as_deref() {
+                    price_level.charge_cancel_ahead(&order_ref.borrow(), model);
+                }
                 price_level.delete_order(&order_ref).map_err(|err| {
                     // 返回 MarketError::OrderDeleteFailed 错误
                     err
@@ -500,7 +1027,8 @@ impl SkipListMarketDepth {
     ///  # 集合竞价规则
     /// 1. 成交量最大化：选择能够实现最大成交量的价格。
     /// 2. 未成交量最小化：在最大成交量相同的情况下，选择未成交量最小的价格。
-    /// 3. 中间价优先：如果存在多个候选价格，选择中间价作为最终的开盘价。
+    /// 3. 接近前收盘价优先：如果存在多个候选价格，选择最接近前收盘价的价格。
+    /// 4. 中间价优先：前收盘价未知时，选择候选价格的中间价作为最终的开盘价。
     fn determine_auction_price_and_vol(&self) -> (i64, i64) {
         let mut open_price_tick = 0;
         let mut sells: VecDeque<(i64, i64)> = VecDeque::with_capacity(self.ask_depth.len());
@@ -530,9 +1058,11 @@ impl SkipListMarketDepth {
         let mut min_unfilled_vol = i64::MAX;
         let mut candidate_prices = vec![];
 
-        let mut sell_tick;
-        let mut sell_vol;
-        (sell_tick, sell_vol) = sells.pop_back().unwrap();
+        // 买卖盘没有交叉时不产生成交
+        let (mut sell_tick, mut sell_vol) = match sells.pop_back() {
+            Some(level) => level,
+            None => return (0, 0),
+        };
         let mut buy_tick;
         let mut buy_vol;
 
@@ -577,14 +1107,40 @@ impl SkipListMarketDepth {
             }
         }
 
-        // 选择符合条件的中间价作为最终成交价格
+        // 选择最接近前收盘价的候选价格，前收盘价未知时选择中间价
         if !candidate_prices.is_empty() {
-            open_price_tick = candidate_prices[candidate_prices.len() / 2];
+            open_price_tick = match self.previous_close_tick {
+                Some(previous_close_tick) => *candidate_prices
+                    .iter()
+                    .min_by_key(|tick| (**tick - previous_close_tick).abs())
+                    .unwrap(),
+                None => candidate_prices[candidate_prices.len() / 2],
+            };
         }
 
         (open_price_tick, max_vol)
     }
 
+    /// 计算以给定价格进行集合竞价时可以成交的数量。
+    ///
+    /// # 返回值
+    /// 价格不低于 `price_tick` 的买盘总量与价格不高于 `price_tick` 的卖盘总量中的较小值。
+    fn auction_vol_at(&self, price_tick: i64) -> i64 {
+        let bid_vol: i64 = self
+            .bid_depth
+            .iter()
+            .take_while(|(tick, _)| tick.abs() >= price_tick)
+            .map(|(_, level)| level.vol)
+            .sum();
+        let ask_vol: i64 = self
+            .ask_depth
+            .iter()
+            .take_while(|(tick, _)| **tick <= price_tick)
+            .map(|(_, level)| level.vol)
+            .sum();
+        cmp::min(bid_vol, ask_vol)
+    }
+
     /// 尝试在卖方深度中匹配给定的订单，并确定订单是否已全部成交。
     ///
     /// 该函数遍历卖方深度中的价格档位，尝试与给定的订单进行匹配，并更新订单的成交量。
@@ -593,6 +1149,7 @@ impl SkipListMarketDepth {
     /// # 参数
     /// - `order_ref`: 引用一个 `L3OrderRef`，表示待匹配的订单。
     /// - `max_depth`: 最大匹配深度，表示在卖方深度中最多遍历的价格档位数量。
+    /// - `bound`: 价格边界，`MatchBound::Unbounded` 时不检查订单价格。
     ///
     /// # 返回值
     /// 返回一个 `Result<bool, MarketError>`:
@@ -614,6 +1171,7 @@ impl SkipListMarketDepth {
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<bool, MarketError> {
         let mut filled: i64 = 0;
         let mut count = 0;
@@ -624,7 +1182,7 @@ impl SkipListMarketDepth {
         for (price_tick, price_level) in self.ask_depth.iter_mut() {
             count += 1;
             // 检查是否达到最大匹配深度，或者订单已完全成交，或者当前价格档位超过订单价格
-            if count > max_depth || order_price_tick < *price_tick {
+            if count > max_depth || (bound == MatchBound::Limit && order_price_tick < *price_tick) {
                 break;
             }
             // 匹配当前价格档位的订单，并更新成交量
@@ -653,6 +1211,7 @@ impl SkipListMarketDepth {
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<bool, MarketError> {
         let mut filled: i64 = 0;
         let mut count = 0;
@@ -663,7 +1222,10 @@ impl SkipListMarketDepth {
         for (price_tick, price_level) in self.bid_depth.iter_mut() {
             count += 1;
             // 检查是否达到最大匹配深度，或者订单已完全成交，或者当前价格档位超过订单价格
-            if count > max_depth || order_price_tick > *price_tick {
+            // 买方档位的键是负的 tick 价格
+            if count > max_depth
+                || (bound == MatchBound::Limit && order_price_tick > price_tick.abs())
+            {
                 break;
             }
             // 匹配当前价格档位的订单，并更新成交量
@@ -687,11 +1249,31 @@ impl SkipListMarketDepth {
 
         Ok(filled >= expected_filled)
     }
+
+    /// 价格为 `price_tick` 的 `side` 方向限价订单能否与对手方最优档位成交。
+    ///
+    /// 只比较对手方的第一个档位，该档位即使已经没有挂单也视为可以成交，交给撮合处理。
+    fn can_cross(&self, side: Side, price_tick: i64) -> bool {
+        match side {
+            Side::Buy => self
+                .ask_depth
+                .front()
+                .is_some_and(|(tick, _)| *tick <= price_tick),
+            _ => self
+                .bid_depth
+                .front()
+                .is_some_and(|(tick, _)| tick.abs() >= price_tick),
+        }
+    }
 }
 
 impl SnapshotOp for SkipListMarketDepth {
     fn snapshot(&self) -> String {
-        serde_json::to_string(self).unwrap_or("{}".to_string())
+        let mut buf = Vec::new();
+        match self.write_snapshot(&mut buf) {
+            Ok(_) => String::from_utf8(buf).unwrap_or("{}".to_string()),
+            Err(_) => "{}".to_string(),
+        }
     }
 }
 
@@ -699,22 +1281,62 @@ impl StatisticsOp for SkipListMarketDepth {
     fn get_statistics(&self) -> &Statistics {
         &self.market_statistics
     }
+
+    fn get_statistics_mut(&mut self) -> &mut Statistics {
+        &mut self.market_statistics
+    }
 }
 
 impl RecoverOp for SkipListMarketDepth {
+    /// 从快照恢复后将用户订单放回价格档位的订单队列。
+    ///
+    /// 反序列化后档位队列中的用户订单与 `orders` 中的是两份副本，版本 4 之前的快照还不保存档位队列。
+    /// `idx` 是订单在所属档位内的位置，按 (方向, 价格, 位置) 排序后逐个放回原来的位置，
+    /// 档位内的先后顺序与快照前一致。快照不保存订单之前的排队数量，放回后重新计算。
+    /// 影子订单簿与交易模式不一致时先按模式修正，参见 `restore_market_shadow`。
+    ///
+    /// # 错误
+    /// 存活的用户订单所在的档位不存在或位置冲突时返回 `MarketError::RecoverFailed`。
     fn recover(&mut self) -> Result<bool, MarketError> {
-        let mut sort_by_idx: VecDeque<(usize, i64)> = VecDeque::with_capacity(1000);
-        for (_, order_ref) in self.orders.iter_mut() {
-            sort_by_idx.push_back((order_ref.borrow().idx, order_ref.borrow().order_id));
+        self.restore_market_shadow();
+        let mut restored: Vec<(bool, i64, usize, OrderId)> = self
+            .orders
+            .values()
+            .map(|order_ref| order_ref.borrow())
+            .filter(|order| (order.side == Side::Buy || order.side == Side::Sell) && order.vol > 0)
+            .map(|order| {
+                (
+                    order.side == Side::Sell,
+                    order.price_tick,
+                    order.idx,
+                    order.order_id,
+                )
+            })
+            .collect();
+        restored.sort();
+
+        for (is_sell, price_tick, _, order_id) in restored {
+            let price_level = if is_sell {
+                self.ask_depth.get_mut(&price_tick)
+            } else {
+                self.bid_depth.get_mut(&-price_tick)
+            };
+            let price_level = price_level.ok_or(MarketError::RecoverFailed)?;
+            price_level.restore_order(self.orders[&order_id].clone())?;
         }
-        sort_by_idx.make_contiguous().sort();
-
-        for (_, order_id) in sort_by_idx {
-            let order_ref = self.orders.get(&order_id).unwrap();
-            let _ = self.add(order_ref.clone());
+        for (_, price_level) in self.bid_depth.iter_mut().chain(self.ask_depth.iter_mut()) {
+            price_level.update_order_position();
         }
         Ok(true)
     }
+
+    fn resting_orders(&self) -> Vec<L3OrderRef> {
+        self.bid_depth
+            .iter()
+            .chain(self.ask_depth.iter())
+            .flat_map(|(_, price_level)| price_level.orders.iter().flatten().cloned())
+            .collect()
+    }
 }
 
 impl MarketDepth for SkipListMarketDepth {
@@ -722,8 +1344,25 @@ impl MarketDepth for SkipListMarketDepth {
         Box::new(Self::new(mode, tick_size, lot_size))
     }
 
-    fn set_previous_close_tick(&mut self, previous_close_tick: i64) {
+    fn set_previous_close_tick(&mut self, previous_close_tick: Option<i64>) {
         self.previous_close_tick = previous_close_tick;
+        self.market_statistics.previous_close_tick = previous_close_tick;
+    }
+
+    fn sample_analytics(&mut self, timestamp: i64) {
+        let analytics = match self.book_analytics.as_mut() {
+            Some(analytics) => analytics,
+            None => return,
+        };
+        let bid_touch_vol = self.bid_depth.front().map_or(0, |(_, level)| level.vol);
+        let ask_touch_vol = self.ask_depth.front().map_or(0, |(_, level)| level.vol);
+        analytics.record(
+            timestamp,
+            self.best_bid_tick,
+            self.best_ask_tick,
+            bid_touch_vol,
+            ask_touch_vol,
+        );
     }
 
     fn get_bid_level(&self, level_num: usize) -> String {
@@ -814,6 +1453,36 @@ impl MarketDepth for SkipListMarketDepth {
         }
     }
 
+    /// 按 `mode` 返回最佳买入价格的 ticks 值，真实订单簿只统计有历史订单数量的档位。
+    fn best_bid_tick_with_mode(&self, mode: BestMode) -> i64 {
+        let real = self
+            .bid_depth
+            .iter()
+            .find(|(_, price_level)| price_level.vol > 0)
+            .map_or(INVALID_MIN, |(price_tick, _)| -price_tick);
+        let shadow = self.best_bid_tick(&OrderSourceType::UserOrder);
+        match mode {
+            BestMode::RealOnly => real,
+            BestMode::ShadowOnly => shadow,
+            BestMode::Combined => cmp::max(real, shadow),
+        }
+    }
+
+    /// 按 `mode` 返回最佳卖出价格的 ticks 值，真实订单簿只统计有历史订单数量的档位。
+    fn best_ask_tick_with_mode(&self, mode: BestMode) -> i64 {
+        let real = self
+            .ask_depth
+            .iter()
+            .find(|(_, price_level)| price_level.vol > 0)
+            .map_or(INVALID_MAX, |(price_tick, _)| *price_tick);
+        let shadow = self.best_ask_tick(&OrderSourceType::UserOrder);
+        match mode {
+            BestMode::RealOnly => real,
+            BestMode::ShadowOnly => shadow,
+            BestMode::Combined => cmp::min(real, shadow),
+        }
+    }
+
     #[inline(always)]
     fn last_tick(&self, source: &OrderSourceType) -> i64 {
         if self.market_shadow.is_some() && source == &OrderSourceType::UserOrder {
@@ -928,6 +1597,17 @@ impl MarketDepth for SkipListMarketDepth {
         let side = order_ref.borrow().side;
         let source = order_ref.borrow().source;
 
+        // 挂单的价格和数量必须大于 0，防止异常数据污染订单簿
+        if price_tick <= 0 || order_ref.borrow().vol <= 0 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        // 哨兵价格和超出范围的价格不能成为价格档位的键
+        PriceTick::try_new_within(price_tick, false, self.price_tick_limit)?;
+        // `Side::None` 是撤单后的标记，和 `Side::Unsupported` 一样不能当作卖单挂入订单簿
+        if side != Side::Buy && side != Side::Sell {
+            return Err(MarketError::MarketSideError);
+        }
+
         if source == OrderSourceType::UserOrder {
             match self.orders.entry(order_id) {
                 Entry::Occupied(_) => return Err(MarketError::OrderIdExist),
@@ -936,6 +1616,7 @@ impl MarketDepth for SkipListMarketDepth {
         }
 
         let mut best_tick: i64 = 0;
+        let prev_best_tick;
 
         if side == Side::Buy {
             let price_level = match self.bid_depth.get_mut(&-price_tick) {
@@ -951,9 +1632,9 @@ impl MarketDepth for SkipListMarketDepth {
             };
 
             let _ = price_level.add_order(order_ref.clone());
+            prev_best_tick = self.best_bid_tick;
             self.best_bid_tick = cmp::max(self.best_bid_tick, price_tick);
             best_tick = self.best_bid_tick.clone();
-            self.market_statistics.total_bid_order += 1;
         } else {
             let price_level = match self.ask_depth.get_mut(&price_tick) {
                 Some(value) => value,
@@ -966,32 +1647,53 @@ impl MarketDepth for SkipListMarketDepth {
                 }
             };
             let _ = price_level.add_order(order_ref.clone());
+            prev_best_tick = self.best_ask_tick;
             self.best_ask_tick = cmp::min(self.best_ask_tick, price_tick);
             best_tick = self.best_ask_tick.clone();
-            self.market_statistics.total_ask_order += 1;
         }
+
+        let order = order_ref.borrow();
+        self.has_phantoms |= order.phantom;
+        self.market_statistics.on_add(&order);
+        self.observers.on_add(&order);
+        // 最优价变化在挂单事件之后通知
+        self.observers
+            .on_best_change(side, prev_best_tick, best_tick);
         Ok(best_tick)
     }
 
-    fn match_order(&mut self, order_ref: L3OrderRef, max_depth: i64) -> Result<i64, MarketError> {
+    fn match_order_with_bound(
+        &mut self,
+        order_ref: L3OrderRef,
+        max_depth: i64,
+        bound: MatchBound,
+    ) -> Result<i64, MarketError> {
         let side = order_ref.borrow().side.clone();
+        // 大部分限价订单达不到对手方最优价，直接挂单，不需要遍历档位
+        if bound == MatchBound::Limit && !self.can_cross(side, order_ref.borrow().price_tick) {
+            return match side {
+                Side::Buy | Side::Sell => Ok(0),
+                _ => Err(MarketError::MarketSideError),
+            };
+        }
         let filled = match side {
-            Side::Buy => self.match_ask_depth(order_ref.clone(), max_depth),
-            Side::Sell => self.match_bid_depth(order_ref.clone(), max_depth),
+            Side::Buy => self.match_ask_depth(order_ref.clone(), max_depth, bound),
+            Side::Sell => self.match_bid_depth(order_ref.clone(), max_depth, bound),
             _ => return Err(MarketError::MarketSideError),
         };
         filled
     }
 
-    fn try_match_order(
+    fn try_match_order_with_bound(
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<bool, MarketError> {
         let side = order_ref.borrow().side.clone();
         let can_match_all = match side {
-            Side::Buy => self.try_match_ask_depth(order_ref.clone(), max_depth),
-            Side::Sell => self.try_match_bid_depth(order_ref.clone(), max_depth),
+            Side::Buy => self.try_match_ask_depth(order_ref.clone(), max_depth, bound),
+            Side::Sell => self.try_match_bid_depth(order_ref.clone(), max_depth, bound),
             _ => return Err(MarketError::MarketSideError),
         };
         can_match_all
@@ -1004,6 +1706,7 @@ impl MarketDepth for SkipListMarketDepth {
     ///
     /// * `order_ref` - 引用的订单对象。
     /// * `max_depth` - 最大的匹配深度（即最多可以匹配多少个价格档位）。
+    /// * `bound` - 价格边界，`MatchBound::Unbounded` 时不检查订单价格。
     ///
     /// # 返回值
     ///
@@ -1017,37 +1720,89 @@ impl MarketDepth for SkipListMarketDepth {
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<i64, MarketError> {
         let mut filled: i64 = 0;
         let mut count = 1;
+        let has_phantoms = self.has_phantoms;
+        // 与用户挂单成交的量只有观察者使用
+        let track_user = !self.observers.is_empty();
+        // 撮合过程中订单的价格、来源和编号不变，只借用一次，避免每个档位重复借用
+        let (order_tick, source, order_id, seq, timestamp) = {
+            let order = order_ref.borrow();
+            (
+                order.price_tick,
+                order.source,
+                order.order_id,
+                order.seq,
+                order.timestamp,
+            )
+        };
+        let shadow_fill = self.market_shadow.is_some()
+            && self.mode == ExchangeMode::Backtest
+            && source == OrderSourceType::UserOrder;
         for (price_tick, price_level) in &mut self.bid_depth {
             if count > max_depth
-                || &order_ref.borrow().price_tick > &price_tick.abs()
+                || (bound == MatchBound::Limit && order_tick > price_tick.abs())
                 || order_ref.borrow().vol == 0
             {
                 break;
             }
+            self.levels_visited += 1;
 
+            let phantom_before = if has_phantoms {
+                price_level.phantom_vol()
+            } else {
+                0
+            };
+            let user_before = if track_user {
+                price_level.user_vol()
+            } else {
+                0
+            };
             let this_filled = price_level.match_order(order_ref.clone()).unwrap();
+            let phantom_vol = if has_phantoms {
+                phantom_before - price_level.phantom_vol()
+            } else {
+                0
+            };
+            let passive_user_vol = if track_user {
+                user_before - price_level.user_vol()
+            } else {
+                0
+            };
             filled += this_filled;
             count += 1;
 
-            let real_tick = if self.market_statistics.open_tick == 0 {
-                order_ref.borrow().price_tick
+            // 市价订单没有自己的价格，总是以档位价格成交
+            let real_tick = if self.market_statistics.open_tick == 0 && bound == MatchBound::Limit {
+                order_tick
             } else {
                 price_tick.clone()
             };
 
             self.last_tick = real_tick.abs();
-            if self.market_shadow.is_some()
-                && self.mode == ExchangeMode::Backtest
-                && order_ref.borrow().source == OrderSourceType::UserOrder
-            {
+            order_ref
+                .borrow_mut()
+                .add_aggressive_fill(real_tick.abs(), this_filled);
+            if shadow_fill {
                 self.market_shadow.as_mut().unwrap().last_tick = real_tick.abs();
             }
-            self.market_statistics.total_bid_vol += this_filled;
-            self.market_statistics.total_bid_tick += filled * real_tick.abs();
-            self.market_statistics.update_high_low(real_tick.abs());
+            let event = FillEvent {
+                side: Side::Buy,
+                price_tick: real_tick.abs(),
+                level_tick: price_tick.abs(),
+                vol: this_filled,
+                total_vol: filled,
+                phantom_vol: phantom_vol,
+                passive_user_vol,
+                source,
+                order_id,
+                seq,
+                timestamp,
+            };
+            self.market_statistics.on_fill(&event);
+            self.observers.on_fill(&event);
         }
 
         self.update_bid_depth()?;
@@ -1061,6 +1816,7 @@ impl MarketDepth for SkipListMarketDepth {
     ///
     /// * `order_ref` - 引用的订单对象。
     /// * `max_depth` - 最大的匹配深度（即最多可以匹配多少个价格档位）。
+    /// * `bound` - 价格边界，`MatchBound::Unbounded` 时不检查订单价格。
     ///
     /// # 返回值
     ///
@@ -1074,41 +1830,93 @@ impl MarketDepth for SkipListMarketDepth {
         &mut self,
         order_ref: L3OrderRef,
         max_depth: i64,
+        bound: MatchBound,
     ) -> Result<i64, MarketError> {
         let mut filled: i64 = 0;
         let mut count = 1;
+        let has_phantoms = self.has_phantoms;
+        // 与用户挂单成交的量只有观察者使用
+        let track_user = !self.observers.is_empty();
+        // 撮合过程中订单的价格、来源和编号不变，只借用一次，避免每个档位重复借用
+        let (order_tick, source, order_id, seq, timestamp) = {
+            let order = order_ref.borrow();
+            (
+                order.price_tick,
+                order.source,
+                order.order_id,
+                order.seq,
+                order.timestamp,
+            )
+        };
+        let shadow_fill = self.market_shadow.is_some()
+            && self.mode == ExchangeMode::Backtest
+            && source == OrderSourceType::UserOrder;
 
         // 遍历卖方深度中的价格档位，进行订单匹配
         for (price_tick, price_level) in self.ask_depth.iter_mut() {
             // 检查是否达到最大匹配深度，或者订单已完全成交，或者当前价格档位超过订单价格
             if count > max_depth
-                || order_ref.borrow().price_tick < price_tick.clone()
+                || (bound == MatchBound::Limit && order_tick < *price_tick)
                 || order_ref.borrow().vol == 0
             {
                 break;
             }
+            self.levels_visited += 1;
             // 匹配当前价格档位的订单，并更新成交量
+            let phantom_before = if has_phantoms {
+                price_level.phantom_vol()
+            } else {
+                0
+            };
+            let user_before = if track_user {
+                price_level.user_vol()
+            } else {
+                0
+            };
             let this_filled = price_level.match_order(order_ref.clone()).unwrap();
+            let phantom_vol = if has_phantoms {
+                phantom_before - price_level.phantom_vol()
+            } else {
+                0
+            };
+            let passive_user_vol = if track_user {
+                user_before - price_level.user_vol()
+            } else {
+                0
+            };
             filled += this_filled;
             count += 1;
 
-            let real_tick = if self.market_statistics.open_tick == 0 {
-                order_ref.borrow().price_tick
+            // 市价订单没有自己的价格，总是以档位价格成交
+            let real_tick = if self.market_statistics.open_tick == 0 && bound == MatchBound::Limit {
+                order_tick
             } else {
                 price_tick.clone()
             };
 
             // 更新市场统计数据
             self.last_tick = real_tick.clone();
-            if self.market_shadow.is_some()
-                && self.mode == ExchangeMode::Backtest
-                && order_ref.borrow().source == OrderSourceType::UserOrder
-            {
+            order_ref
+                .borrow_mut()
+                .add_aggressive_fill(real_tick, this_filled);
+            if shadow_fill {
                 self.market_shadow.as_mut().unwrap().last_tick = real_tick.clone();
             }
-            self.market_statistics.total_ask_vol += this_filled;
-            self.market_statistics.total_ask_tick += filled * real_tick;
-            self.market_statistics.update_high_low(real_tick.clone());
+            let event = FillEvent {
+                side: Side::Sell,
+                price_tick: real_tick,
+                level_tick: *price_tick,
+                vol: this_filled,
+                total_vol: filled,
+                phantom_vol: phantom_vol,
+                passive_user_vol,
+                source,
+                order_id,
+                seq,
+                timestamp,
+            };
+            self.market_statistics.on_fill(&event);
+            self.observers.on_fill(&event);
         }
 
         self.update_ask_depth()?;
@@ -1116,7 +1924,32 @@ impl MarketDepth for SkipListMarketDepth {
     }
 
     fn call_auction(&mut self) -> Result<(i64, i64), MarketError> {
-        let (open_tick, vol) = self.determine_auction_price_and_vol();
+        self.call_auction_with_limits(None, None)
+    }
+
+    fn call_auction_with_limits(
+        &mut self,
+        limit_down_tick: Option<i64>,
+        limit_up_tick: Option<i64>,
+    ) -> Result<(i64, i64), MarketError> {
+        let (mut open_tick, mut vol) = self.determine_auction_price_and_vol();
+        if vol == 0 {
+            return Ok((0, 0));
+        }
+        // 清算价格超出涨跌停范围时按涨跌停价成交，超出部分不成交
+        if open_tick != 0 {
+            let mut clamped_tick = open_tick;
+            if let Some(limit_up_tick) = limit_up_tick {
+                clamped_tick = cmp::min(clamped_tick, limit_up_tick);
+            }
+            if let Some(limit_down_tick) = limit_down_tick {
+                clamped_tick = cmp::max(clamped_tick, limit_down_tick);
+            }
+            if clamped_tick != open_tick {
+                open_tick = clamped_tick;
+                vol = self.auction_vol_at(open_tick);
+            }
+        }
         let order_ref = L3Order::new_ref(
             OrderSourceType::LocalOrder,
             None,
@@ -1144,6 +1977,14 @@ impl MarketDepth for SkipListMarketDepth {
 impl L3MarketDepth for SkipListMarketDepth {
     type Error = MarketError;
 
+    /// 按除权除息调整订单簿，参见 `apply_split` 和 `apply_cash_dividend`。
+    fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<(), MarketError> {
+        match action {
+            CorporateAction::Split(ratio) => self.apply_split(ratio),
+            CorporateAction::CashDividend(amount) => self.apply_cash_dividend(amount),
+        }
+    }
+
     /// 向订单簿中添加买单。
     ///
     /// # 参数
@@ -1171,6 +2012,7 @@ impl L3MarketDepth for SkipListMarketDepth {
         timestamp: i64,
         order_type: OrderType,
     ) -> Result<(i64, i64), Self::Error> {
+        validate_price_qty(price, vol as f64, order_type)?;
         let price_tick = (price / self.tick_size).round() as i64;
         let order_ref = L3OrderRef::new(RefCell::new(L3Order::new(
             source,
@@ -1219,6 +2061,7 @@ impl L3MarketDepth for SkipListMarketDepth {
         timestamp: i64,
         order_type: OrderType,
     ) -> Result<(i64, i64), Self::Error> {
+        validate_price_qty(price, vol as f64, order_type)?;
         // 将价格转换为价格档位
         let price_tick = (price / self.tick_size).round() as i64;
 
@@ -1250,6 +2093,7 @@ impl L3MarketDepth for SkipListMarketDepth {
     }
 
     fn update_bid_depth(&mut self) -> Result<i64, MarketError> {
+        let prev_best_tick = self.best_bid_tick;
         loop {
             match self.bid_depth.front_mut() {
                 Some((price_tick, price_level)) => {
@@ -1277,6 +2121,8 @@ impl L3MarketDepth for SkipListMarketDepth {
             }
         }
 
+        self.observers
+            .on_best_change(Side::Buy, prev_best_tick, self.best_bid_tick);
         Ok(self.best_bid_tick)
     }
 
@@ -1292,6 +2138,7 @@ impl L3MarketDepth for SkipListMarketDepth {
     /// # 错误
     /// 方法可能会返回 `MarketError`，具体的错误类型取决于实现。
     fn update_ask_depth(&mut self) -> Result<i64, MarketError> {
+        let prev_best_tick = self.best_ask_tick;
         loop {
             match self.ask_depth.front_mut() {
                 // 如果卖方深度中有价格层次
@@ -1321,6 +2168,8 @@ impl L3MarketDepth for SkipListMarketDepth {
             }
         }
 
+        self.observers
+            .on_best_change(Side::Sell, prev_best_tick, self.best_ask_tick);
         Ok(self.best_ask_tick)
     }
 
@@ -1341,60 +2190,144 @@ impl L3MarketDepth for SkipListMarketDepth {
         self.delete_order(order_ref)
     }
 
-    /// 修改指定订单的价格和数量，并更新订单簿。
-    ///
-    /// # 参数
+    /// 设置最新成交价，回测模式下同时设置影子订单簿的最新成交价。
+    fn set_last_tick(&mut self, last_tick: i64) {
+        self.last_tick = last_tick;
+        if let Some(market_shadow) = self.market_shadow.as_mut() {
+            market_shadow.last_tick = last_tick;
+        }
+    }
+
+    /// 将仍在订单簿中的订单移到所在价格档位的队首。
+    ///
+    /// # 错误
+    /// 订单不在订单簿中时返回 `MarketError::OrderNotFound`。
+    fn move_to_front(&mut self, order_ref: L3OrderRef) -> Result<(), Self::Error> {
+        let (side, price_tick) = {
+            let order = order_ref.borrow();
+            (order.side, order.price_tick)
+        };
+        let price_level = match side {
+            Side::Buy => self.bid_depth.get_mut(&-price_tick),
+            Side::Sell => self.ask_depth.get_mut(&price_tick),
+            _ => None,
+        };
+        price_level
+            .ok_or(MarketError::OrderNotFound)?
+            .move_to_front(&order_ref)
+    }
+
+    /// 部分撤销市场订单，减少订单的剩余数量并保留它的排队位置。
+    ///
+    /// 撤销数量不小于剩余数量时按全部撤单处理。
+    ///
+    /// # 参数
+    /// - `order_ref`: 要部分撤销的订单的引用。
+    /// - `vol`: 撤销的数量。
+    ///
+    /// # 错误
+    /// - `MarketError::InvalidOrderRequest`: 撤销数量不是正数。
+    /// - `MarketError::OrderNotFound`: 订单不在订单簿中。
+    fn reduce_order_from_ref(
+        &mut self,
+        order_ref: L3OrderRef,
+        vol: i64,
+    ) -> Result<(Side, i64, i64), Self::Error> {
+        if vol <= 0 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        if vol >= order_ref.borrow().vol {
+            return self.delete_order(order_ref);
+        }
+
+        let (side, price_tick) = {
+            let order = order_ref.borrow();
+            (order.side, order.price_tick)
+        };
+        let (price_level, best_tick) = match side {
+            Side::Buy => (self.bid_depth.get_mut(&-price_tick), self.best_bid_tick),
+            Side::Sell => (self.ask_depth.get_mut(&price_tick), self.best_ask_tick),
+            _ => return Err(MarketError::OrderNotFound),
+        };
+        let price_level = price_level.ok_or(MarketError::OrderNotFound)?;
+
+        // 撤销的部分按一笔独立的撤单计入统计和排队模型
+        let mut canceled = order_ref.borrow().clone();
+        canceled.vol = vol;
+        canceled.vol_shadow = vol.min(canceled.vol_shadow);
+        if let Some(model) = self.queue_model.as_deref() {
+            price_level.charge_cancel_ahead(&canceled, model);
+        }
+        price_level.reduce_order(&order_ref, vol)?;
+        order_ref.borrow_mut().dirty = true;
+        self.market_statistics.on_cancel(&canceled);
+        self.observers.on_cancel(&canceled);
+        Ok((side, best_tick, best_tick))
+    }
+
+    /// 从订单簿中撤出指定订单，并按新的价格和数量重置，供调用方重新提交。
+    ///
+    /// 订单从原价格档位撤出后按限价订单重新进入，由调用方先与对手方撮合再挂入队尾，
+    /// 失去原有的排队位置，参见 `Broker::modify_order`。
+    ///
+    /// # 参数
     ///
     /// - `order_id`: 要修改的订单的唯一标识符。
     /// - `price`: 修改后的价格。
-    /// - `qty`: 修改后的数量。
+    /// - `qty`: 修改后剩余未成交的数量。订单簿只保存剩余数量，已成交的部分由调用方扣除，
+    ///   参见 `Broker::modify_order`。
     /// - `timestamp`: 修改操作的时间戳。
     ///
     /// # 返回值
     ///
-    /// 返回一个 `Result`，成功时包含一个元组 `(Side, i64, i64)`，其中：
-    ///
-    /// - `Side`: 订单的方向（买或卖）。
-    /// - `i64`: 修改前的最佳买入价或卖出价的 tick 价格。
-    /// - `i64`: 修改后的最佳买入价或卖出价的 tick 价格。
-    ///
-    /// 失败时返回 `Self::Error`，表示订单修改失败。
+    /// 撤出并重置后的订单，此时订单不在订单簿中。
     ///
     /// # 错误
     ///
-    /// - `MarketError::OrderNotFound`: 如果指定的订单未找到。
+    /// - `MarketError::OrderNotFound`: 如果指定的订单未找到或已被撤销。
+    /// - `MarketError::InvalidOrderRequest`: 如果修改后的价格或数量不是正数。
+    /// - 新价格超出价格范围时返回 `PriceTick::try_new_within` 的错误。
     fn modify_order(
         &mut self,
         order_id: OrderId,
         price: f64,
         qty: f64,
         timestamp: i64,
-    ) -> Result<(Side, i64, i64), Self::Error> {
-        let order_ref: L3OrderRef;
-
-        let order_ref = match self.orders.get_mut(&order_id) {
+    ) -> Result<L3OrderRef, Self::Error> {
+        let order_ref = match self.orders.get(&order_id) {
             Some(value) => value.clone(),
             None => return Err(MarketError::OrderNotFound),
         };
-
-        let mut order = order_ref.borrow_mut();
+        let side = order_ref.borrow().side;
+        if side != Side::Buy && side != Side::Sell {
+            return Err(MarketError::OrderNotFound);
+        }
 
         // 计算价格和数量的 tick 价格
         let price_tick = (price / self.tick_size).round() as i64;
         let vol = (qty / self.lot_size).round() as i64;
+        if price_tick <= 0 || vol <= 0 {
+            return Err(MarketError::InvalidOrderRequest);
+        }
+        // 在撤出原订单之前检查新价格，避免重新挂单失败时订单丢失
+        PriceTick::try_new_within(price_tick, false, self.price_tick_limit)?;
 
-        let _ = self.cancel_order(order_id);
-        order.price_tick = price_tick;
-        order.vol = vol;
-        order.vol_shadow = vol;
-        let _ = self.add(order_ref.clone());
-        if order.side == Side::Buy {
-            let prev_best_tick = self.best_bid_tick;
-            Ok((Side::Buy, prev_best_tick, self.best_bid_tick))
-        } else {
-            let prev_best_tick = self.best_ask_tick;
-            Ok((Side::Sell, self.best_ask_tick, self.best_ask_tick))
+        self.delete_order(order_ref.clone())?;
+        self.orders.remove(&order_id);
+        {
+            let mut order = order_ref.borrow_mut();
+            // 撤单时标记的方向恢复为原方向
+            order.side = side;
+            order.price_tick = price_tick;
+            order.vol = vol;
+            order.vol_shadow = vol;
+            order.timestamp = timestamp;
+            order.queue_debt = 0;
+            order.order_type = OrderType::L;
+            // 重新进入时的主动成交单独计算成交金额
+            order.aggressive_amount_tick = 0;
         }
+        Ok(order_ref)
     }
 
     fn clean_orders(&mut self) {}
@@ -1407,6 +2340,23 @@ impl L3MarketDepth for SkipListMarketDepth {
         &mut self.orders
     }
 
+    fn set_queue_model(&mut self, model: Option<Box<dyn QueueModel>>) {
+        self.queue_model = model;
+    }
+
+    fn clear_phantoms(&mut self) {
+        self.has_phantoms = false;
+    }
+
+    fn resting_vol(&self) -> (i64, i64) {
+        self.bid_depth.iter().chain(self.ask_depth.iter()).fold(
+            (0, 0),
+            |(level_vol, orders_vol), (_, level)| {
+                (level_vol + level.vol, orders_vol + level.orders_vol())
+            },
+        )
+    }
+
     fn get_orderbook_level(
         &self,
         bid_vec: &mut Vec<(f64, f64, i64)>,
@@ -1857,7 +2807,7 @@ mod tests {
             1,
             OrderType::L,
         );
-        let filled = depth.match_bid_depth(order_sell.clone(), 100);
+        let filled = depth.match_bid_depth(order_sell.clone(), 100, MatchBound::Limit);
         print!("{:?}\n", depth);
         print!("{:?}\n", filled.unwrap());
     }
@@ -2020,8 +2970,506 @@ mod tests {
             serde_json::from_str(&snapshot).expect("Failed to deserialize snapshot");
         print!("{:?}\n", new_depth);
     }
+
+    /// 返回每个价格档位中订单的 (队列位置, 订单 ID)，按排队顺序排列。
+    fn level_queues(
+        depth: &SkipListMarketDepth,
+        source: Option<OrderSourceType>,
+    ) -> Vec<(i64, Vec<(usize, OrderId)>)> {
+        depth
+            .bid_depth
+            .iter()
+            .chain(depth.ask_depth.iter())
+            .map(|(price_tick, level)| {
+                let queue = level
+                    .orders
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(pos, slot)| Some((pos, slot.as_ref()?.borrow())))
+                    .filter(|(_, order)| source.map_or(true, |source| order.source == source))
+                    .map(|(pos, order)| (pos, order.order_id))
+                    .collect();
+                (*price_tick, queue)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recover_keeps_queue_order() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        let mut canceled = Vec::new();
+        for i in 0..24 {
+            // 用户订单和历史订单交错排在三个买档和三个卖档上
+            let source = if i % 3 == 1 {
+                OrderSourceType::LocalOrder
+            } else {
+                OrderSourceType::UserOrder
+            };
+            let (side, price_tick) = if i % 2 == 0 {
+                (Side::Buy, 100 - i as i64 % 3)
+            } else {
+                (Side::Sell, 101 + i as i64 % 3)
+            };
+            let order_ref = create_test_order(source, None, side, price_tick, i + 1, i, i);
+            depth.add(order_ref.clone()).unwrap();
+            if i % 5 == 0 {
+                canceled.push(order_ref);
+            }
+        }
+        for order_ref in canceled {
+            depth.cancel_order_from_ref(order_ref).unwrap();
+        }
+        let expected = level_queues(&depth, None);
+
+        let mut recovered: SkipListMarketDepth =
+            serde_json::from_str(&depth.snapshot()).expect("Failed to deserialize snapshot");
+        recovered.recover().unwrap();
+        assert_eq!(level_queues(&recovered, None), expected);
+        assert_eq!(recovered.resting_orders().len(), 24 - 5);
+
+        // 版本 4 之前的快照不保存档位队列，只能放回用户订单
+        let mut legacy: serde_json::Value = serde_json::from_str(&depth.snapshot()).unwrap();
+        for side in ["bid_depth", "ask_depth"] {
+            for (_, level) in legacy[side].as_object_mut().unwrap() {
+                level.as_object_mut().unwrap().remove("orders");
+            }
+        }
+        let mut legacy: SkipListMarketDepth = serde_json::from_value(legacy).unwrap();
+        legacy.recover().unwrap();
+        assert_eq!(
+            level_queues(&legacy, Some(OrderSourceType::UserOrder)),
+            level_queues(&depth, Some(OrderSourceType::UserOrder))
+        );
+
+        for (price_tick, level) in recovered.bid_depth.iter().chain(recovered.ask_depth.iter()) {
+            // 档位中的用户订单与订单表中的是同一个订单，成交量和订单数没有被重复计算
+            for order_ref in level.orders.iter().flatten() {
+                let order_id = order_ref.borrow().order_id;
+                if let Some(user_order) = recovered.orders.get(&order_id) {
+                    assert!(Rc::ptr_eq(order_ref, user_order));
+                }
+            }
+            let original = match depth.bid_depth.get(price_tick) {
+                Some(level) => level,
+                None => depth.ask_depth.get(price_tick).unwrap(),
+            };
+            assert_eq!(
+                (level.vol, level.vol_shadow, level.count),
+                (original.vol, original.vol_shadow, original.count)
+            );
+        }
+        // 恢复后可以按订单 ID 撤销用户订单
+        let vol_shadow = recovered.ask_depth.get(&103).unwrap().vol_shadow;
+        assert_eq!(recovered.cancel_order(17).unwrap().0, Side::Sell);
+        assert_eq!(
+            recovered.ask_depth.get(&103).unwrap().vol_shadow,
+            vol_shadow - 18
+        );
+    }
+
+    #[test]
+    /// 测试影子订单簿的序列化往返。
+    /// 验证回测模式的快照恢复后仍有影子订单簿，缺失时按档位重建，实时模式不保留影子订单簿。
+    fn test_market_shadow_round_trip() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        for (i, (source, side, price_tick)) in [
+            (OrderSourceType::LocalOrder, Side::Buy, 100),
+            (OrderSourceType::UserOrder, Side::Buy, 101),
+            (OrderSourceType::LocalOrder, Side::Sell, 103),
+            (OrderSourceType::UserOrder, Side::Sell, 102),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let order_ref = create_test_order(source, None, side, price_tick, 10, 1, i as i64);
+            depth.add(order_ref).unwrap();
+        }
+        depth.update_bid_depth().unwrap();
+        depth.update_ask_depth().unwrap();
+        let source = OrderSourceType::UserOrder;
+        let expected = (
+            depth.best_bid_tick(&source),
+            depth.best_ask_tick(&source),
+            depth.last_tick(&source),
+        );
+        assert_eq!((expected.0, expected.1), (101, 102));
+
+        let mut recovered: SkipListMarketDepth = serde_json::from_str(&depth.snapshot()).unwrap();
+        recovered.recover().unwrap();
+        assert!(recovered.market_shadow.is_some());
+        assert_eq!(
+            (
+                recovered.best_bid_tick(&source),
+                recovered.best_ask_tick(&source),
+                recovered.last_tick(&source),
+            ),
+            expected
+        );
+
+        let mut snapshot: serde_json::Value = serde_json::from_str(&depth.snapshot()).unwrap();
+        snapshot["market_shadow"] = serde_json::Value::Null;
+        let mut rebuilt: SkipListMarketDepth = serde_json::from_value(snapshot).unwrap();
+        assert!(rebuilt.market_shadow.is_none());
+        rebuilt.recover().unwrap();
+        assert_eq!(
+            (
+                rebuilt.best_bid_tick(&source),
+                rebuilt.best_ask_tick(&source),
+                rebuilt.last_tick(&source),
+            ),
+            expected
+        );
+
+        let live = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        let mut snapshot: serde_json::Value = serde_json::from_str(&live.snapshot()).unwrap();
+        snapshot["market_shadow"] = serde_json::to_value(MarketDepthShadow::new()).unwrap();
+        let mut live: SkipListMarketDepth = serde_json::from_value(snapshot).unwrap();
+        live.recover().unwrap();
+        assert!(live.market_shadow.is_none());
+    }
+
+    #[test]
+    fn test_write_read_snapshot_large_book() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::fs::File;
+        use std::hash::{Hash, Hasher};
+
+        fn checksum(path: &std::path::Path) -> u64 {
+            let bytes = std::fs::read(path).expect("Failed to read snapshot file");
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        for i in 0..5000 {
+            depth
+                .add(L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    i,
+                    Side::Buy,
+                    10000 - i % 2000,
+                    100 + i,
+                    20230101093000000 + i,
+                    OrderType::L,
+                ))
+                .unwrap();
+            depth
+                .add(L3Order::new_ref(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    10000 + i,
+                    Side::Sell,
+                    20000 + i % 2000,
+                    100 + i,
+                    20230101093000000 + i,
+                    OrderType::L,
+                ))
+                .unwrap();
+        }
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("skiplist_snapshot_{}.json", id()));
+        let path_copy = dir.join(format!("skiplist_snapshot_{}_copy.json", id()));
+
+        depth
+            .write_snapshot(File::create(&path).unwrap())
+            .expect("Failed to write snapshot");
+        let new_depth = SkipListMarketDepth::read_snapshot(File::open(&path).unwrap())
+            .expect("Failed to read snapshot");
+        new_depth
+            .write_snapshot(File::create(&path_copy).unwrap())
+            .expect("Failed to write snapshot");
+
+        assert_eq!(new_depth.bid_depth.len(), 2000);
+        assert_eq!(new_depth.ask_depth.len(), 2000);
+        assert_eq!(new_depth.best_bid_tick, depth.best_bid_tick);
+        assert_eq!(new_depth.best_ask_tick, depth.best_ask_tick);
+        assert_eq!(checksum(&path), checksum(&path_copy));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), depth.snapshot());
+
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(&path_copy).ok();
+    }
+    #[test]
+    fn test_add_invalid_price_vol() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+
+        let order_ref = create_test_order(
+            OrderSourceType::LocalOrder,
+            None,
+            Side::Buy,
+            0,
+            10,
+            20231201093000000,
+            1,
+        );
+        assert_eq!(depth.add(order_ref), Err(MarketError::InvalidOrderRequest));
+        let order_ref = create_test_order(
+            OrderSourceType::LocalOrder,
+            None,
+            Side::Sell,
+            100,
+            -10,
+            20231201093000000,
+            2,
+        );
+        assert_eq!(depth.add(order_ref), Err(MarketError::InvalidOrderRequest));
+        assert!(depth.bid_depth.is_empty());
+        assert!(depth.ask_depth.is_empty());
+
+        let result = depth.add_buy_order(
+            OrderSourceType::UserOrder,
+            None,
+            3,
+            -1.0,
+            10,
+            20231201093000000,
+            OrderType::L,
+        );
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        let result = depth.add_sell_order(
+            OrderSourceType::UserOrder,
+            None,
+            4,
+            f64::NAN,
+            10,
+            20231201093000000,
+            OrderType::L,
+        );
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        let result = depth.add_sell_order(
+            OrderSourceType::UserOrder,
+            None,
+            5,
+            1.0,
+            0,
+            20231201093000000,
+            OrderType::L,
+        );
+        assert_eq!(result, Err(MarketError::InvalidOrderRequest));
+        assert!(depth.orders.is_empty());
+    }
+
+    #[derive(Debug, Default)]
+    struct EventCounts {
+        fills: i64,
+        filled_vol: i64,
+        adds: i64,
+        cancels: i64,
+        best_changes: i64,
+    }
+
+    struct CountingObserver(Rc<RefCell<EventCounts>>);
+
+    impl DepthObserver for CountingObserver {
+        fn on_fill(&mut self, event: &FillEvent) {
+            let mut counts = self.0.borrow_mut();
+            counts.fills += 1;
+            counts.filled_vol += event.vol;
+        }
+
+        fn on_add(&mut self, _order: &L3Order) {
+            self.0.borrow_mut().adds += 1;
+        }
+
+        fn on_cancel(&mut self, _order: &L3Order) {
+            self.0.borrow_mut().cancels += 1;
+        }
+
+        fn on_best_change(&mut self, _side: Side, _prev_tick: i64, _best_tick: i64) {
+            self.0.borrow_mut().best_changes += 1;
+        }
+    }
+
+    #[test]
+    fn test_depth_observer_counts() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        let counts = Rc::new(RefCell::new(EventCounts::default()));
+        assert!(depth.register_observer(Box::new(CountingObserver(counts.clone()))));
+
+        let buy_order = create_test_order(
+            OrderSourceType::LocalOrder,
+            None,
+            Side::Buy,
+            100,
+            100,
+            20231201093000000,
+            1,
+        );
+        depth.add(buy_order).unwrap();
+        let buy_order = create_test_order(
+            OrderSourceType::UserOrder,
+            None,
+            Side::Buy,
+            99,
+            50,
+            20231201093000000,
+            2,
+        );
+        depth.add(buy_order).unwrap();
+
+        let sell_order = create_test_order(
+            OrderSourceType::LocalOrder,
+            None,
+            Side::Sell,
+            100,
+            90,
+            20231201093000001,
+            3,
+        );
+        depth
+            .match_bid_depth(sell_order, 100, MatchBound::Limit)
+            .unwrap();
+        depth.cancel_order(2).unwrap();
+
+        let counts = counts.borrow();
+        assert_eq!(counts.adds, 2);
+        assert_eq!(counts.fills, 1);
+        assert_eq!(counts.filled_vol, 90);
+        assert_eq!(counts.cancels, 1);
+        assert!(counts.best_changes >= 1);
+
+        // 统计数据与观察者收到的事件保持一致
+        assert_eq!(depth.market_statistics.total_bid_order, 2);
+        assert_eq!(depth.market_statistics.total_bid_vol, 90);
+        assert_eq!(depth.market_statistics.total_bid_tick, 90 * 100);
+    }
+
+    #[test]
+    fn test_cancel_statistics() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        for (order_id, side, price_tick, vol) in [
+            (1, Side::Buy, 1000, 3),
+            (2, Side::Sell, 1001, 5),
+            (3, Side::Buy, 999, 2),
+        ] {
+            let order_ref = create_test_order(
+                OrderSourceType::UserOrder,
+                None,
+                side,
+                price_tick,
+                vol,
+                20231201093000000,
+                order_id,
+            );
+            depth.add(order_ref).unwrap();
+        }
+        depth.cancel_order(2).unwrap();
+        depth.cancel_order(3).unwrap();
+        assert_eq!(depth.cancel_order(4), Err(MarketError::OrderNotFound));
+
+        let stats = depth.get_statistics();
+        assert_eq!(stats.total_cancel_count, 2);
+        assert_eq!(stats.total_cancel_vol, 7);
+
+        let mut info = statistics::StatisticsInfo::new();
+        info.from_statistics(stats, depth.tick_size, depth.lot_size);
+        assert_eq!(info.total_cancel_count, 2);
+        assert_eq!(info.total_cancel_qty, 700.0);
+    }
+
     #[test]
     fn test_call_auction() {}
+
+    #[test]
+    fn test_call_auction_clamped_to_price_limit() {
+        let new_depth = || {
+            let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+            depth.set_previous_close_tick(Some(1000));
+            for (order_id, side, price_tick, vol) in [
+                (1, Side::Buy, 1150, 10),
+                (2, Side::Sell, 1050, 4),
+                (3, Side::Sell, 1120, 3),
+            ] {
+                let order_ref = create_test_order(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    side,
+                    price_tick,
+                    vol,
+                    20231201092500000,
+                    order_id,
+                );
+                depth.add(order_ref).unwrap();
+            }
+            depth
+        };
+
+        // 不设涨跌停时清算价格为 1120
+        let mut depth = new_depth();
+        assert_eq!(depth.call_auction(), Ok((1120, 7)));
+
+        // 涨停价 1100 时按涨停价成交，只有 1050 的卖单能够成交
+        let mut depth = new_depth();
+        assert_eq!(
+            depth.call_auction_with_limits(Some(900), Some(1100)),
+            Ok((1100, 4))
+        );
+        assert_eq!(depth.market_statistics.open_tick, 1100);
+        assert_eq!(depth.last_tick, 1100);
+        assert_eq!(depth.bid_vol_at_tick(1150), 6);
+        assert_eq!(depth.ask_vol_at_tick(1050), 0);
+        assert_eq!(depth.ask_vol_at_tick(1120), 3);
+    }
+
+    #[test]
+    fn test_call_auction_tie_break() {
+        let new_depth = |previous_close_tick: Option<i64>| {
+            let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+            depth.set_previous_close_tick(previous_close_tick);
+            for (order_id, side, price_tick) in [
+                (1, Side::Buy, 1050),
+                (2, Side::Buy, 1020),
+                (3, Side::Sell, 1000),
+                (4, Side::Sell, 1030),
+            ] {
+                let order_ref = create_test_order(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    side,
+                    price_tick,
+                    5,
+                    20231201092500000,
+                    order_id,
+                );
+                depth.add(order_ref).unwrap();
+            }
+            depth
+        };
+
+        // 1050 和 1000 的成交量和未成交量相同，选择最接近前收盘价的价格
+        let mut depth = new_depth(Some(1040));
+        assert_eq!(depth.call_auction(), Ok((1050, 5)));
+        assert_eq!(depth.market_statistics.previous_close_tick, Some(1040));
+
+        // 前收盘价未知时选择候选价格的中间价
+        let mut depth = new_depth(None);
+        assert_eq!(depth.call_auction(), Ok((1000, 5)));
+    }
+
+    #[test]
+    fn test_call_auction_without_crossing() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        assert_eq!(depth.call_auction(), Ok((0, 0)));
+        for (order_id, side, price_tick) in [(1, Side::Buy, 990), (2, Side::Sell, 1010)] {
+            let order_ref = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                side,
+                price_tick,
+                5,
+                20231201092500000,
+                order_id,
+            );
+            depth.add(order_ref).unwrap();
+        }
+        // 买卖盘没有交叉时不成交，挂单保持不变
+        assert_eq!(depth.call_auction(), Ok((0, 0)));
+        assert_eq!(depth.bid_vol_at_tick(990), 5);
+        assert_eq!(depth.ask_vol_at_tick(1010), 5);
+    }
     #[test]
     fn test_depth_performance() {
         let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 1.0);
@@ -2112,4 +3560,628 @@ mod tests {
             &mut vv,
         );
     }
+
+    #[test]
+    fn test_display_book() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        for (order_id, side, price_tick, vol) in [
+            (1, Side::Buy, 1000, 2),
+            (2, Side::Buy, 999, 1),
+            (3, Side::Sell, 1001, 3),
+        ] {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                price_tick,
+                vol,
+                1,
+                OrderType::L,
+            );
+            depth.add(order_ref).unwrap();
+        }
+
+        let book = depth.display_book(5);
+        let lines: Vec<&str> = book.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("BID PRICE"));
+        // 第一档买卖盘并排显示实际价格和数量
+        assert!(lines[1].contains("200"));
+        assert!(lines[1].contains("10.00"));
+        assert!(lines[1].contains("10.01"));
+        assert!(lines[1].contains("300"));
+        // 卖盘只有一档，第二行只显示买盘
+        assert!(lines[2].contains("9.99"));
+        assert!(lines[2].contains("100"));
+        assert!(lines[2].trim_end().ends_with('|'));
+    }
+
+    #[test]
+    fn test_top_levels_json() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        for (order_id, side, price_tick, vol) in [
+            (1, Side::Buy, 1000, 2),
+            (2, Side::Buy, 1000, 1),
+            (3, Side::Buy, 999, 1),
+            (4, Side::Sell, 1001, 3),
+        ] {
+            let order_ref = L3Order::new_ref(
+                OrderSourceType::LocalOrder,
+                None,
+                order_id,
+                side,
+                price_tick,
+                vol,
+                1,
+                OrderType::L,
+            );
+            depth.add(order_ref).unwrap();
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&depth.top_levels_json(1)).unwrap();
+        assert_eq!(json["bids"], serde_json::json!([[10.0, 300.0, 2]]));
+        assert_eq!(json["asks"], serde_json::json!([[10.01, 300.0, 1]]));
+
+        let json: serde_json::Value = serde_json::from_str(&depth.top_levels_json(5)).unwrap();
+        assert_eq!(json["bids"].as_array().unwrap().len(), 2);
+        assert_eq!(json["bids"][1], serde_json::json!([9.99, 100.0, 1]));
+        assert_eq!(json["asks"].as_array().unwrap().len(), 1);
+    }
+
+    /// 属性测试中对 `PriceLevel` 执行的一步操作。
+    #[derive(Debug, Clone)]
+    enum LevelOp {
+        Add(OrderId, OrderSourceType, Option<String>, i64),
+        Delete(OrderId),
+        Match(OrderSourceType, Option<String>, i64),
+    }
+
+    fn random_source(rng: &mut synthetic::SyntheticRng) -> OrderSourceType {
+        if rng.next_below(2) == 0 {
+            OrderSourceType::LocalOrder
+        } else {
+            OrderSourceType::UserOrder
+        }
+    }
+
+    fn random_account(rng: &mut synthetic::SyntheticRng) -> Option<String> {
+        match rng.next_below(3) {
+            0 => None,
+            1 => Some("A".to_string()),
+            _ => Some("B".to_string()),
+        }
+    }
+
+    /// 检查价格档位的成交量、订单数和订单位置是否一致。
+    fn check_level_invariants(level: &PriceLevel) -> Result<(), String> {
+        if level.vol < 0 || level.vol_shadow < 0 || level.count < 0 {
+            return Err(format!(
+                "negative level totals: vol {} vol_shadow {} count {}",
+                level.vol, level.vol_shadow, level.count
+            ));
+        }
+        let (mut count, mut vol, mut vol_shadow) = (0, 0, 0);
+        for (pos, slot) in level.orders.iter().enumerate() {
+            let Some(order_ref) = slot else { continue };
+            let order = order_ref.borrow();
+            if order.idx != pos + 1 {
+                return Err(format!(
+                    "order {} idx {} at slot {}",
+                    order.order_id, order.idx, pos
+                ));
+            }
+            if order.vol <= 0 || order.vol_shadow < 0 || order.vol_shadow > order.vol {
+                return Err(format!(
+                    "order {} vol {} vol_shadow {}",
+                    order.order_id, order.vol, order.vol_shadow
+                ));
+            }
+            count += 1;
+            if level.mode == ExchangeMode::Live || order.source == OrderSourceType::LocalOrder {
+                vol += order.vol;
+                vol_shadow += order.vol_shadow;
+            } else {
+                // 回测模式下用户订单只占用影子成交量
+                vol_shadow += order.vol;
+            }
+        }
+        if count != level.count {
+            return Err(format!("count {} but {} live orders", level.count, count));
+        }
+        if vol != level.vol {
+            return Err(format!("vol {} but orders sum to {}", level.vol, vol));
+        }
+        if vol_shadow != level.vol_shadow {
+            return Err(format!(
+                "vol_shadow {} but orders sum to {}",
+                level.vol_shadow, vol_shadow
+            ));
+        }
+        if level.mode == ExchangeMode::Live && level.vol_shadow != level.vol {
+            return Err(format!(
+                "live vol_shadow {} differs from vol {}",
+                level.vol_shadow, level.vol
+            ));
+        }
+        Ok(())
+    }
+
+    /// 对一个价格档位随机执行 `steps` 步操作，每一步之后检查不变量。
+    ///
+    /// 失败时返回种子、出错的步骤以及完整的操作记录，便于把该场景改写为回归测试。
+    fn run_level_scenario(mode: ExchangeMode, seed: u64, steps: usize) -> Result<(), String> {
+        let mut rng = synthetic::SyntheticRng::new(seed);
+        let mut level = PriceLevel::new(mode, Side::Buy);
+        let mut added: Vec<L3OrderRef> = Vec::new();
+        let mut log: Vec<LevelOp> = Vec::with_capacity(steps);
+        let mut next_id: OrderId = 1;
+
+        for step in 0..steps {
+            let op = match rng.next_below(10) {
+                0..=4 => {
+                    next_id += 1;
+                    LevelOp::Add(
+                        next_id,
+                        random_source(&mut rng),
+                        random_account(&mut rng),
+                        rng.next_below(10) as i64 + 1,
+                    )
+                }
+                5..=6 if !added.is_empty() => {
+                    let order_ref = &added[rng.next_below(added.len() as u64) as usize];
+                    LevelOp::Delete(order_ref.borrow().order_id)
+                }
+                _ => LevelOp::Match(
+                    random_source(&mut rng),
+                    random_account(&mut rng),
+                    rng.next_below(20) as i64 + 1,
+                ),
+            };
+            log.push(op.clone());
+
+            let result = match op {
+                LevelOp::Add(order_id, source, account, vol) => {
+                    let order_ref =
+                        create_test_order(source, account, Side::Buy, 1000, vol, 1, order_id);
+                    added.push(order_ref.clone());
+                    level
+                        .add_order(order_ref)
+                        .map(|_| ())
+                        .map_err(|err| format!("{err:?}"))
+                }
+                LevelOp::Delete(order_id) => {
+                    let order_ref = added
+                        .iter()
+                        .find(|order_ref| order_ref.borrow().order_id == order_id)
+                        .unwrap();
+                    level
+                        .delete_order(order_ref)
+                        .map(|_| ())
+                        .map_err(|err| format!("{err:?}"))
+                }
+                LevelOp::Match(source, account, vol) => {
+                    // 回测模式下本地订单同时与用户订单（影子）和本地订单成交，成交量可以超过委托量
+                    let exact = mode == ExchangeMode::Live || source == OrderSourceType::UserOrder;
+                    let order_ref = create_test_order(source, account, Side::Sell, 1000, vol, 1, 0);
+                    match level.match_order(order_ref.clone()) {
+                        Ok(filled) => {
+                            let remaining = order_ref.borrow().vol;
+                            if filled < 0
+                                || remaining < 0
+                                || remaining > vol
+                                || (exact && filled != vol - remaining)
+                            {
+                                Err(format!(
+                                    "filled {filled} remaining {remaining} for vol {vol}"
+                                ))
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Err(err) => Err(format!("{err:?}")),
+                    }
+                }
+            };
+            if let Err(err) = result.and_then(|_| check_level_invariants(&level)) {
+                return Err(format!(
+                    "{mode:?} seed {seed} failed at step {step}: {err}\noperations: {log:#?}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_level_invariants() {
+        for mode in [ExchangeMode::Backtest, ExchangeMode::Live] {
+            for seed in [1, 7, 42, 2023, 20231201] {
+                if let Err(report) = run_level_scenario(mode, seed, 3000) {
+                    panic!("{report}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_queue_model_cancel_ahead() {
+        use super::super::queue_model::{Conservative, Probabilistic};
+
+        // 历史买单 L1 排在用户买单前面，L1 撤单后到达一笔 2 手的历史卖单
+        let run = |model: Option<Box<dyn QueueModel>>| {
+            let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+            depth.set_queue_model(model);
+            let local_ref =
+                create_test_order(OrderSourceType::LocalOrder, None, Side::Buy, 1000, 2, 1, 1);
+            let user_ref =
+                create_test_order(OrderSourceType::UserOrder, None, Side::Buy, 1000, 1, 2, 100);
+            depth.add(local_ref.clone()).unwrap();
+            depth.add(user_ref.clone()).unwrap();
+            depth.cancel_order_from_ref(local_ref).unwrap();
+
+            let sell_ref =
+                create_test_order(OrderSourceType::LocalOrder, None, Side::Sell, 1000, 2, 3, 2);
+            depth.match_order(sell_ref, i64::MAX).unwrap();
+            let user = user_ref.borrow();
+            (user.vol, user.queue_debt)
+        };
+
+        // 默认行为：撤单量全部推进排队位置，随后的成交使用户订单成交
+        assert_eq!(run(None), (0, 0));
+        assert_eq!(
+            run(Some(Box::new(Probabilistic { cancel_credit: 1.0 }))),
+            (0, 0)
+        );
+        // 撤单不推进位置时，随后的成交先抵扣撤单量，用户订单没有成交
+        assert_eq!(
+            run(Some(Box::new(Probabilistic { cancel_credit: 0.0 }))),
+            (1, 0)
+        );
+        assert_eq!(run(Some(Box::new(Conservative))), (1, 0));
+        // 一半的撤单量计为推进，抵扣 1 手后剩余 1 手与用户订单成交
+        assert_eq!(
+            run(Some(Box::new(Probabilistic { cancel_credit: 0.5 }))),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    /// 测试两个用户挂单排在同一笔撤单之后。
+    /// 验证撤单量只由第一个用户挂单抵扣一次，第一个用户挂单撤销后没有抵扣完的部分转给下一个。
+    fn test_queue_model_cancel_ahead_of_two_users() {
+        use super::super::queue_model::Conservative;
+
+        // 历史买单 L1 撤销 2 手，排在后面的用户买单 U1、U2 各 1 手，随后到达 `sell_vol` 手的历史卖单
+        let run = |cancel_first_user: bool, sell_vol: i64| {
+            let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+            depth.set_queue_model(Some(Box::new(Conservative)));
+            let local_ref =
+                create_test_order(OrderSourceType::LocalOrder, None, Side::Buy, 1000, 2, 1, 1);
+            let first_ref =
+                create_test_order(OrderSourceType::UserOrder, None, Side::Buy, 1000, 1, 2, 100);
+            let second_ref =
+                create_test_order(OrderSourceType::UserOrder, None, Side::Buy, 1000, 1, 3, 101);
+            depth.add(local_ref.clone()).unwrap();
+            depth.add(first_ref.clone()).unwrap();
+            depth.add(second_ref.clone()).unwrap();
+            depth.cancel_order_from_ref(local_ref).unwrap();
+            assert_eq!(first_ref.borrow().queue_debt, 2);
+            assert_eq!(second_ref.borrow().queue_debt, 0);
+            if cancel_first_user {
+                depth.cancel_order(100).unwrap();
+                assert_eq!(second_ref.borrow().queue_debt, 2);
+            }
+
+            let sell_ref = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                Side::Sell,
+                1000,
+                sell_vol,
+                4,
+                2,
+            );
+            depth.match_order(sell_ref, i64::MAX).unwrap();
+            let second = second_ref.borrow();
+            (second.vol, second.queue_debt)
+        };
+
+        // 4 手的卖单抵扣 2 手撤单量后，两个用户挂单各成交 1 手
+        assert_eq!(run(false, 4), (0, 0));
+        // 第一个用户挂单撤销后，3 手的卖单抵扣 2 手撤单量，剩余 1 手与第二个用户挂单成交
+        assert_eq!(run(true, 3), (0, 0));
+        assert_eq!(run(true, 2), (1, 0));
+    }
+
+    #[test]
+    fn test_best_with_mode() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Backtest, 0.01, 100.0);
+        for (side, price_tick, vol, order_id) in [
+            (Side::Buy, 1000, 2, 1),
+            (Side::Buy, 990, 5, 2),
+            (Side::Sell, 1010, 3, 3),
+        ] {
+            let order_ref = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                side,
+                price_tick,
+                vol,
+                1,
+                order_id,
+            );
+            depth.add(order_ref).unwrap();
+        }
+        // 用户卖单吃掉 1000 档的全部影子数量，真实订单簿中该档位仍有挂单
+        let user_ref = create_test_order(
+            OrderSourceType::UserOrder,
+            None,
+            Side::Sell,
+            1000,
+            2,
+            2,
+            100,
+        );
+        assert_eq!(depth.match_order(user_ref, i64::MAX).unwrap(), 2);
+
+        assert_eq!(depth.best_bid_tick_with_mode(BestMode::RealOnly), 1000);
+        assert_eq!(depth.best_bid_tick_with_mode(BestMode::ShadowOnly), 990);
+        assert_eq!(depth.best_bid_tick_with_mode(BestMode::Combined), 1000);
+        assert_eq!(
+            depth.best_bid_tick_with_mode(BestMode::ShadowOnly),
+            depth.best_bid_tick(&OrderSourceType::UserOrder)
+        );
+        assert_eq!(depth.best_bid_with_mode(BestMode::RealOnly), 1000.0 * 0.01);
+        assert_eq!(depth.best_bid_with_mode(BestMode::ShadowOnly), 990.0 * 0.01);
+
+        // 用户卖单挂在价差之内，只有影子订单簿能看到
+        depth
+            .add_sell_order(
+                OrderSourceType::UserOrder,
+                None,
+                101,
+                10.05,
+                1,
+                3,
+                OrderType::L,
+            )
+            .unwrap();
+        depth.update_ask_depth().unwrap();
+        assert_eq!(depth.best_ask_tick_with_mode(BestMode::RealOnly), 1010);
+        assert_eq!(depth.best_ask_tick_with_mode(BestMode::ShadowOnly), 1005);
+        // 卖方的合并结果取较低价，包含只在影子订单簿中的用户订单
+        assert_eq!(depth.best_ask_tick_with_mode(BestMode::Combined), 1005);
+        assert_eq!(depth.best_ask_with_mode(BestMode::Combined), 1005.0 * 0.01);
+
+        // 实盘模式下没有影子订单簿，三种模式结果相同
+        let mut live = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        let order_ref =
+            create_test_order(OrderSourceType::LocalOrder, None, Side::Buy, 1000, 2, 1, 1);
+        live.add(order_ref).unwrap();
+        for mode in [BestMode::RealOnly, BestMode::ShadowOnly, BestMode::Combined] {
+            assert_eq!(live.best_bid_tick_with_mode(mode), 1000);
+            assert!(live.best_ask_with_mode(mode).is_nan());
+        }
+    }
+
+    #[test]
+    fn test_apply_split() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        depth.set_previous_close_tick(Some(1000));
+        let orders: Vec<L3OrderRef> = [
+            (Side::Buy, 1000, 3, 1),
+            (Side::Buy, 998, 1, 2),
+            (Side::Sell, 1010, 5, 3),
+            (Side::Sell, 1012, 2, 4),
+            (Side::Buy, 1000, 2, 5),
+        ]
+        .into_iter()
+        .map(|(side, price_tick, vol, order_id)| {
+            let order_ref = create_test_order(
+                OrderSourceType::UserOrder,
+                None,
+                side,
+                price_tick,
+                vol,
+                1,
+                order_id,
+            );
+            depth.add(order_ref.clone()).unwrap();
+            order_ref
+        })
+        .collect();
+        let before: Vec<(i64, i64)> = orders
+            .iter()
+            .map(|order_ref| (order_ref.borrow().price_tick, order_ref.borrow().vol))
+            .collect();
+        let notional = |orders: &[L3OrderRef]| -> i64 {
+            orders
+                .iter()
+                .map(|order_ref| order_ref.borrow().price_tick * order_ref.borrow().vol)
+                .sum()
+        };
+        let notional_before = notional(&orders);
+
+        depth
+            .apply_corporate_action(CorporateAction::Split(2.0))
+            .unwrap();
+        for (order_ref, (price_tick, vol)) in orders.iter().zip(before) {
+            let order = order_ref.borrow();
+            assert_eq!(order.price_tick * 2, price_tick);
+            assert_eq!(order.vol, vol * 2);
+        }
+        assert_eq!(notional(&orders), notional_before);
+        assert_eq!(depth.best_bid_tick, 500);
+        assert_eq!(depth.best_ask_tick, 505);
+        assert_eq!(depth.previous_close_tick, Some(500));
+        assert_eq!(depth.bid_vol_at_tick(500), 10);
+        assert_eq!(depth.ask_vol_at_tick(506), 4);
+        // 同一档位中的订单保持原来的排队顺序
+        let level = depth.bid_depth.get(&-500).unwrap();
+        let queue: Vec<OrderId> = level
+            .orders
+            .iter()
+            .flatten()
+            .map(|order_ref| order_ref.borrow().order_id)
+            .collect();
+        assert_eq!(queue, vec![1, 5]);
+        for (_, level) in depth.bid_depth.iter().chain(depth.ask_depth.iter()) {
+            check_level_invariants(level).unwrap();
+        }
+
+        // 现金分红只调整价格
+        depth.apply_cash_dividend(0.05).unwrap();
+        assert_eq!(depth.best_bid_tick, 495);
+        assert_eq!(orders[2].borrow().price_tick, 500);
+        assert_eq!(orders[2].borrow().vol, 10);
+
+        assert!(matches!(
+            depth.apply_split(0.0),
+            Err(MarketError::InvalidCorporateAction(_))
+        ));
+        // 调整后的价格无效时订单簿保持不变
+        assert_eq!(
+            depth.apply_cash_dividend(10.0),
+            Err(MarketError::PriceTickOutOfRange(-505))
+        );
+        assert_eq!(depth.best_bid_tick, 495);
+        assert_eq!(orders[0].borrow().price_tick, 495);
+    }
+
+    #[test]
+    fn test_add_rejects_side_none() {
+        let mut depth = SkipListMarketDepth::new(ExchangeMode::Live, 0.01, 100.0);
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::UserOrder,
+            None,
+            1,
+            Side::None,
+            1000,
+            1,
+            1,
+            OrderType::L,
+        );
+        assert_eq!(depth.add(order_ref), Err(MarketError::MarketSideError));
+        // 被拒绝的订单不会留在订单簿中
+        assert!(depth.orders().is_empty());
+        assert_eq!(
+            depth.best_ask_tick(&OrderSourceType::UserOrder),
+            INVALID_MAX
+        );
+        assert_eq!(depth.ask_vol_at_tick(1000), 0);
+    }
+
+    #[test]
+    /// 测试不能成交的限价订单跳过撮合。
+    /// 验证远离对手方最优价的买单和卖单没有成交，也不访问任何档位；能成交的订单照常撮合。
+    fn test_match_order_non_marketable_fast_path() {
+        use super::super::fixture::BookFixture;
+
+        let (mut depth, _) = BookFixture::new(ExchangeMode::Live)
+            .bid(10.00, 500.0, 2)
+            .ask(10.02, 300.0, 1)
+            .ask(10.03, 300.0, 1)
+            .build_depth()
+            .unwrap();
+        let limit_order = |side: Side, price_tick: i64, vol: i64| {
+            create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                side,
+                price_tick,
+                vol,
+                20231201093001000,
+                100,
+            )
+        };
+
+        assert_eq!(depth.match_order(limit_order(Side::Buy, 900, 1), 10), Ok(0));
+        assert_eq!(
+            depth.match_order(limit_order(Side::Sell, 1100, 1), 10),
+            Ok(0)
+        );
+        assert_eq!(depth.levels_visited, 0);
+        assert_eq!((depth.best_bid_tick, depth.best_ask_tick), (1000, 1002));
+
+        assert_eq!(
+            depth.match_order(limit_order(Side::Buy, 1003, 4), 10),
+            Ok(4)
+        );
+        assert_eq!(depth.levels_visited, 2);
+        assert_eq!(depth.best_ask_tick, 1003);
+    }
+
+    #[test]
+    /// 测试跨越大量价格档位的撮合。
+    /// 验证两种交易模式下订单逐档成交 100 个档位不会出现借用冲突，成交量和最新成交价正确。
+    fn test_match_order_across_many_levels() {
+        for mode in [ExchangeMode::Live, ExchangeMode::Backtest] {
+            let mut depth = SkipListMarketDepth::new(mode, 0.01, 100.0);
+            for idx in 0..200 {
+                let ask = create_test_order(
+                    OrderSourceType::LocalOrder,
+                    None,
+                    Side::Sell,
+                    1001 + idx / 2,
+                    1,
+                    20231201093001000,
+                    idx + 1,
+                );
+                depth.add(ask).unwrap();
+            }
+            let buy = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                Side::Buy,
+                1100,
+                250,
+                20231201093002000,
+                1000,
+            );
+
+            assert_eq!(depth.match_order(buy.clone(), i64::MAX), Ok(200));
+            assert_eq!(depth.levels_visited, 100);
+            assert_eq!(buy.borrow().vol, 50);
+            assert_eq!(depth.ask_vol_at_tick(1100), 0);
+            assert_eq!(depth.last_tick(&OrderSourceType::LocalOrder), 1100);
+        }
+    }
+
+    #[test]
+    /// 测试订单不能与同方向的价格档位撮合。
+    /// 验证档位中的订单自己撮合时返回错误而不是重复借用，对手方订单正常成交。
+    fn test_price_level_match_rejects_same_side() {
+        for mode in [ExchangeMode::Live, ExchangeMode::Backtest] {
+            let mut price_level = PriceLevel::new(mode, Side::Sell);
+            let resting = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                Side::Sell,
+                1000,
+                2,
+                20231201093001000,
+                1,
+            );
+            price_level.add_order(resting.clone()).unwrap();
+
+            assert_eq!(
+                price_level.match_order(resting.clone()),
+                Err(MarketError::MarketSideError)
+            );
+            assert_eq!(resting.borrow().vol, 2);
+
+            let incoming = create_test_order(
+                OrderSourceType::LocalOrder,
+                None,
+                Side::Buy,
+                1000,
+                1,
+                20231201093001000,
+                2,
+            );
+            assert_eq!(price_level.match_order(incoming.clone()), Ok(1));
+            assert_eq!(incoming.borrow().vol, 0);
+            assert_eq!(resting.borrow().vol, 1);
+        }
+    }
 }
@@ -1,8 +1,9 @@
-use std::{cmp, i64};
+use std::{cmp, i64, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
-use super::Side;
+use super::observer::{DepthObserver, FillEvent};
+use super::{schema, FillLiquidity, L3Order, Side};
 /// `Statistics` 结构体用于跟踪交易统计信息，包括委托数量、成交额、成交量、成交单等。
 ///
 /// 主要用途是提供对市场订单活动的详细统计信息，如总买入/卖出委托数量、成交总额、最高和最低成交价等。
@@ -14,9 +15,15 @@ pub struct Statistics {
     pub total_ask_num: usize,
     ///总的撤单委托数量
     pub total_cancel: usize,
-    ///总的买入成交额
+    ///从市场深度中撤销的订单数量
+    #[serde(default)]
+    pub total_cancel_count: usize,
+    ///撤单时订单剩余的总数量
+    #[serde(default)]
+    pub total_cancel_vol: i64,
+    ///总的买入成交额，以 tick × 手为单位的精确整数
     pub total_bid_tick: i64,
-    ///总的卖出成交额
+    ///总的卖出成交额，以 tick × 手为单位的精确整数
     pub total_ask_tick: i64,
     ///总的买入成交量
     pub total_bid_vol: i64,
@@ -26,13 +33,27 @@ pub struct Statistics {
     pub total_bid_order: i64,
     ///总的卖出成交委托单
     pub total_ask_order: i64,
+    ///用户订单主动成交的数量
+    #[serde(default)]
+    pub user_aggressive_vol: i64,
+    ///用户订单被动成交的数量
+    #[serde(default)]
+    pub user_passive_vol: i64,
+    ///用户订单在集合竞价中成交的数量
+    #[serde(default)]
+    pub user_auction_vol: i64,
     ///最高成交价
     pub high: i64,
     ///最低成交价
     pub low: i64,
     pub open_tick: i64,
     pub close_tick: i64,
-    pub previous_close_tick: i64,
+    /// 前收盘价的 tick 价格，`None` 表示前收盘价未知
+    #[serde(deserialize_with = "schema::deserialize_previous_close_tick")]
+    pub previous_close_tick: Option<i64>,
+    /// 当日第一笔成交的 tick 价格，没有成交时为 `None`
+    #[serde(default)]
+    pub first_trade_tick: Option<i64>,
 }
 
 impl Statistics {
@@ -41,17 +62,23 @@ impl Statistics {
             total_bid_num: 0,
             total_ask_num: 0,
             total_cancel: 0,
+            total_cancel_count: 0,
+            total_cancel_vol: 0,
             total_bid_tick: 0,
             total_ask_tick: 0,
             total_bid_vol: 0,
             total_ask_vol: 0,
             total_bid_order: 0,
             total_ask_order: 0,
+            user_aggressive_vol: 0,
+            user_passive_vol: 0,
+            user_auction_vol: 0,
             high: i64::MIN,
             low: i64::MAX,
             open_tick: 0,
             close_tick: 0,
-            previous_close_tick: 0,
+            previous_close_tick: None,
+            first_trade_tick: None,
         }
     }
     /// 计算并返回总成交量（买入成交量 + 卖出成交量）。
@@ -70,6 +97,23 @@ impl Statistics {
     pub fn total_price(&self) -> i64 {
         self.total_bid_tick + self.total_ask_tick
     }
+    /// 返回以 tick × 手为单位的精确总成交额，累加时不经过浮点数。
+    pub fn turnover_exact(&self) -> i128 {
+        self.total_bid_tick as i128 + self.total_ask_tick as i128
+    }
+    /// 将总成交额换算为以 `10^-scale` 元为单位的精确整数金额。
+    ///
+    /// # 参数
+    ///
+    /// - `tick_size`: 每个价格跳动的大小。
+    /// - `lot_size`: 每手的数量。
+    ///
+    /// # 返回
+    ///
+    /// 返回 `(金额, scale)`，金额除以 `10^scale` 即为成交额。
+    pub fn turnover_decimal(&self, tick_size: f64, lot_size: f64) -> (i128, u32) {
+        scale_amount(self.turnover_exact(), tick_size, lot_size)
+    }
     /// 计算并返回平均买入价格。若总成交量为0，则此方法可能会引发除以0的错误。
     ///
     /// # 返回
@@ -112,6 +156,19 @@ impl Statistics {
             _ => (),
         }
     }
+    /// 按流动性角色累计用户订单的成交量。
+    ///
+    /// # 参数
+    ///
+    /// - `liquidity`: 成交时用户订单的流动性角色。
+    /// - `vol`: 成交数量，以手表示。
+    pub fn add_user_fill(&mut self, liquidity: FillLiquidity, vol: i64) {
+        match liquidity {
+            FillLiquidity::Aggressive => self.user_aggressive_vol += vol,
+            FillLiquidity::Passive => self.user_passive_vol += vol,
+            FillLiquidity::Auction => self.user_auction_vol += vol,
+        }
+    }
     /// 更新最高和最低成交价。
     ///
     /// # 参数
@@ -123,21 +180,110 @@ impl Statistics {
     }
 }
 
+/// 十进制换算时支持的最大小数位数。
+const MAX_DECIMAL_SCALE: u32 = 8;
+
+/// 将 `value` 表示为 `mantissa / 10^scale`，`scale` 取满足精度的最小值。
+fn to_decimal(value: f64) -> (i128, u32) {
+    for scale in 0..MAX_DECIMAL_SCALE {
+        let scaled = value * 10f64.powi(scale as i32);
+        if (scaled - scaled.round()).abs() <= 1e-9 * scaled.abs().max(1.0) {
+            return (scaled.round() as i128, scale);
+        }
+    }
+    let scaled = value * 10f64.powi(MAX_DECIMAL_SCALE as i32);
+    (scaled.round() as i128, MAX_DECIMAL_SCALE)
+}
+
+/// 将以 tick × 手为单位的整数金额换算为以 `10^-scale` 元为单位的整数金额。
+///
+/// `tick_size` 和 `lot_size` 先分别换算为十进制数（最多 8 位小数，更多的小数位四舍五入），
+/// 之后只做整数乘法，结果是精确的。
+fn scale_amount(amount: i128, tick_size: f64, lot_size: f64) -> (i128, u32) {
+    let (tick, tick_scale) = to_decimal(tick_size);
+    let (lot, lot_scale) = to_decimal(lot_size);
+    (amount * tick * lot, tick_scale + lot_scale)
+}
+
+/// 将以 tick × 手为单位的整数金额换算为货币金额。
+///
+/// 整数部分的换算是精确的，只在最后转换为 `f64` 时舍入，误差不超过结果本身的浮点精度，
+/// 不会随成交笔数累积。需要与官方成交额逐分核对时使用 `Statistics::turnover_decimal`。
+///
+/// # 参数
+///
+/// - `amount`: 以 tick × 手为单位的金额。
+/// - `tick_size`: 每个价格跳动的大小。
+/// - `lot_size`: 每手的数量。
+pub fn ticks_to_currency(amount: i128, tick_size: f64, lot_size: f64) -> f64 {
+    let (scaled, scale) = scale_amount(amount, tick_size, lot_size);
+    let unit = 10i128.pow(scale);
+    (scaled / unit) as f64 + (scaled % unit) as f64 / unit as f64
+}
+
+/// `Statistics` 作为市场深度的第一个观察者，根据成交和挂单事件更新统计数据。
+impl DepthObserver for Statistics {
+    fn on_fill(&mut self, event: &FillEvent) {
+        // 与假设挂单的成交不计入统计
+        let vol = event.vol - event.phantom_vol;
+        if event.phantom_vol > 0 && vol == 0 {
+            return;
+        }
+        match event.side {
+            Side::Buy => {
+                self.total_bid_vol += vol;
+                self.total_bid_tick += vol * event.price_tick;
+            }
+            _ => {
+                self.total_ask_vol += vol;
+                self.total_ask_tick += vol * event.price_tick;
+            }
+        }
+        self.update_high_low(event.price_tick);
+        self.first_trade_tick.get_or_insert(event.price_tick);
+    }
+
+    fn on_add(&mut self, order: &L3Order) {
+        if order.phantom {
+            return;
+        }
+        match order.side {
+            Side::Buy => self.total_bid_order += 1,
+            _ => self.total_ask_order += 1,
+        }
+    }
+
+    fn on_cancel(&mut self, order: &L3Order) {
+        if order.phantom {
+            return;
+        }
+        self.total_cancel_count += 1;
+        self.total_cancel_vol += order.vol;
+    }
+}
+
 pub struct StatisticsInfo {
     pub tick_size: f64,
     pub lot_size: f64,
     pub last_price: f64,
-    pub prev_close_price: f64,
+    /// 前收盘价，`None` 表示前收盘价未知
+    pub prev_close_price: Option<f64>,
     /// 提交的总的买入委托数量
     pub total_bid_num: usize,
     /// 提交的总的卖出委托数量
     pub total_ask_num: usize,
     /// 总的撤单委托数量
     pub total_cancel: usize,
+    /// 从市场深度中撤销的订单数量
+    pub total_cancel_count: usize,
+    /// 撤单时订单剩余的总数量
+    pub total_cancel_qty: f64,
     /// 总的买入成交额
     pub total_bid: f64,
     /// 总的卖出成交额
     pub total_ask: f64,
+    /// 总成交额，由精确的整数金额一次换算得到
+    pub turnover: f64,
     /// 总的买入成交量
     pub total_bid_qty: f64,
     /// 总的卖出成交量
@@ -146,6 +292,12 @@ pub struct StatisticsInfo {
     pub total_bid_order: i64,
     /// 总的卖出成交委托单
     pub total_ask_order: i64,
+    /// 用户订单主动成交的总数量
+    pub user_aggressive_qty: f64,
+    /// 用户订单被动成交的总数量
+    pub user_passive_qty: f64,
+    /// 用户订单在集合竞价中成交的总数量
+    pub user_auction_qty: f64,
     /// 最高成交价
     pub high: f64,
     /// 最低成交价
@@ -160,16 +312,22 @@ impl StatisticsInfo {
             tick_size: 0.0,
             lot_size: 0.0,
             last_price: 0.0,
-            prev_close_price: 0.0,
+            prev_close_price: None,
             total_bid_num: 0,
             total_ask_num: 0,
             total_cancel: 0,
+            total_cancel_count: 0,
+            total_cancel_qty: 0.0,
             total_bid: 0.0,
             total_ask: 0.0,
+            turnover: 0.0,
             total_bid_qty: 0.0,
             total_ask_qty: 0.0,
             total_bid_order: 0,
             total_ask_order: 0,
+            user_aggressive_qty: 0.0,
+            user_passive_qty: 0.0,
+            user_auction_qty: 0.0,
             high: 0.0,
             low: 0.0,
             avg_price: 0.0,
@@ -188,12 +346,18 @@ impl StatisticsInfo {
         self.total_bid_num = statistics.total_bid_num;
         self.total_ask_num = statistics.total_ask_num;
         self.total_cancel = statistics.total_cancel;
-        self.total_bid = statistics.total_bid_tick as f64 * tick_size;
-        self.total_ask = statistics.total_ask_tick as f64 * tick_size;
+        self.total_cancel_count = statistics.total_cancel_count;
+        self.total_cancel_qty = statistics.total_cancel_vol as f64 * lot_size;
+        self.total_bid = ticks_to_currency(statistics.total_bid_tick as i128, tick_size, lot_size);
+        self.total_ask = ticks_to_currency(statistics.total_ask_tick as i128, tick_size, lot_size);
+        self.turnover = ticks_to_currency(statistics.turnover_exact(), tick_size, lot_size);
         self.total_bid_qty = statistics.total_bid_vol as f64 * lot_size;
         self.total_ask_qty = statistics.total_ask_vol as f64 * lot_size;
         self.total_bid_order = statistics.total_bid_order;
         self.total_ask_order = statistics.total_ask_order;
+        self.user_aggressive_qty = statistics.user_aggressive_vol as f64 * lot_size;
+        self.user_passive_qty = statistics.user_passive_vol as f64 * lot_size;
+        self.user_auction_qty = statistics.user_auction_vol as f64 * lot_size;
         self.high = statistics.high as f64 * tick_size;
         self.low = statistics.low as f64 * tick_size;
         self.avg_price =
@@ -203,6 +367,31 @@ impl StatisticsInfo {
     }
 }
 
+/// `ReplaySummary` 汇总一次回放的结果，价格和数量已经按最小价格变动单位和最小交易单位换算。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplaySummary {
+    /// 已处理的历史逐笔消息数量，包括因数据异常被跳过的消息
+    pub events_processed: usize,
+    /// 因数据异常被跳过的历史逐笔消息数量
+    pub events_skipped: usize,
+    /// 时间戳早于之前已处理消息、按当前时间处理的历史逐笔消息数量
+    pub events_out_of_order: usize,
+    /// 总成交数量
+    pub total_qty: f64,
+    /// 总成交额
+    pub turnover: f64,
+    /// 开盘价，尚未开盘时为 `None`
+    pub open: Option<f64>,
+    /// 收盘价，尚未收盘时为 `None`
+    pub close: Option<f64>,
+    /// 最高成交价，没有成交时为 `None`
+    pub high: Option<f64>,
+    /// 最低成交价，没有成交时为 `None`
+    pub low: Option<f64>,
+    /// 推进时间实际耗费的时间
+    pub wall_time: Duration,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -246,6 +435,8 @@ mod tests {
         stats.total_bid_num = 10;
         stats.total_ask_num = 15;
         stats.total_cancel = 5;
+        stats.total_cancel_count = 3;
+        stats.total_cancel_vol = 12;
         stats.total_bid_tick = 5000;
         stats.total_ask_tick = 3000;
         stats.total_bid_vol = 200;
@@ -264,8 +455,11 @@ mod tests {
         assert_eq!(stats_out.total_bid_num, 10);
         assert_eq!(stats_out.total_ask_num, 15);
         assert_eq!(stats_out.total_cancel, 5);
-        assert_eq!(stats_out.total_bid, 50.0); // 5000 * 0.01
-        assert_eq!(stats_out.total_ask, 30.0); // 3000 * 0.01
+        assert_eq!(stats_out.total_cancel_count, 3);
+        assert_eq!(stats_out.total_cancel_qty, 1200.0); // 12 * 100.0
+        assert_eq!(stats_out.total_bid, 5000.0); // 5000 * 0.01 * 100.0
+        assert_eq!(stats_out.total_ask, 3000.0); // 3000 * 0.01 * 100.0
+        assert_eq!(stats_out.turnover, 8000.0);
         assert_eq!(stats_out.total_bid_qty, 20000.0); // 200 * 100.0
         assert_eq!(stats_out.total_ask_qty, 15000.0); // 150 * 100.0
         assert_eq!(stats_out.total_bid_order, 7);
@@ -277,4 +471,58 @@ mod tests {
             ((stats.avg_price() as f64 * tick_size / lot_size) * 1000.0).round() / 1000.0;
         assert_eq!(stats_out.avg_price, expected_avg_price);
     }
+
+    #[test]
+    fn test_exact_turnover() {
+        use super::super::OrderSourceType;
+
+        // 科创板高价股按 0.01 元的 tick 和 1 股的申报单位计算，逐笔成交一百万次
+        let tick_size = 0.01;
+        let lot_size = 1.0;
+        let mut stats = Statistics::new();
+        let mut reference_cents: i128 = 0;
+        let mut float_turnover = 0.0;
+        let mut state: u64 = 20231201;
+        for _ in 0..1_000_000 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let price_tick = 169_000 + (state >> 33) as i64 % 2_000;
+            let vol = 200 + (state >> 17) as i64 % 5_000;
+            let side = if state & 1 == 0 {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            stats.on_fill(&FillEvent {
+                side: side,
+                price_tick: price_tick,
+                level_tick: price_tick,
+                vol: vol,
+                total_vol: vol,
+                phantom_vol: 0,
+                passive_user_vol: 0,
+                source: OrderSourceType::LocalOrder,
+                order_id: 1,
+                seq: 1,
+                timestamp: 20231201093000000,
+            });
+            // 十进制参考值：价格以分表示，数量以股表示
+            reference_cents += price_tick as i128 * vol as i128;
+            float_turnover += price_tick as f64 * tick_size * (vol as f64 * lot_size);
+        }
+
+        assert_eq!(
+            stats.turnover_decimal(tick_size, lot_size),
+            (reference_cents, 2)
+        );
+        let turnover = ticks_to_currency(stats.turnover_exact(), tick_size, lot_size);
+        assert_eq!(turnover, reference_cents as f64 / 100.0);
+        // 逐笔的浮点累加与精确值不一致
+        assert_ne!(float_turnover, turnover);
+
+        let mut info = StatisticsInfo::new();
+        info.from_statistics(&stats, tick_size, lot_size);
+        assert_eq!(info.turnover, turnover);
+    }
 }
@@ -0,0 +1,293 @@
+use super::dataloader::DataCollator;
+use super::utils::adjust_timestamp_milliseconds_i64;
+use super::*;
+use std::collections::VecDeque;
+
+/// `SyntheticRng` 是一个基于 SplitMix64 的确定性伪随机数生成器。
+///
+/// 相同的种子总是生成相同的序列，用于构造可复现的测试和基准数据。
+#[derive(Debug, Clone)]
+pub struct SyntheticRng {
+    state: u64,
+}
+
+impl SyntheticRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// 返回 `[0, n)` 范围内的随机整数，`n` 为 0 时返回 0。
+    pub fn next_below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.next_u64() % n
+    }
+}
+
+/// `FlowConfig` 描述合成逐笔委托流的参数。
+#[derive(Debug, Clone)]
+pub struct FlowConfig {
+    /// 随机数种子
+    pub seed: u64,
+    /// 中间价，以 tick 表示，挂单围绕该价格分布
+    pub mid_tick: i64,
+    /// 每一方挂单分布的档位数量
+    pub levels: i64,
+    /// 单笔委托的最大数量，以手表示
+    pub max_vol: i64,
+    /// 撤单占全部事件的千分比
+    pub cancel_permille: u64,
+    /// 主动成交委托占全部事件的千分比
+    pub take_permille: u64,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        Self {
+            seed: 20231201,
+            mid_tick: 1000,
+            levels: 10,
+            max_vol: 10,
+            cancel_permille: 300,
+            take_permille: 100,
+        }
+    }
+}
+
+/// 合成委托流中的一个事件。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEvent {
+    /// 在本方挂出的被动委托
+    Add {
+        order_id: OrderId,
+        side: Side,
+        price_tick: i64,
+        vol: i64,
+    },
+    /// 撤销之前挂出的委托
+    Cancel { order_id: OrderId },
+    /// 穿过对手方最优价的主动委托
+    Take {
+        order_id: OrderId,
+        side: Side,
+        price_tick: i64,
+        vol: i64,
+    },
+}
+
+/// `SyntheticFlow` 按 `FlowConfig` 生成围绕中间价的确定性逐笔委托流。
+///
+/// 买单挂在中间价以下、卖单挂在中间价以上，主动委托以对手方最深一档为限价，
+/// 撤单从尚未撤销的挂单中随机选择。
+#[derive(Debug, Clone)]
+pub struct SyntheticFlow {
+    config: FlowConfig,
+    rng: SyntheticRng,
+    next_order_id: OrderId,
+    resting: Vec<OrderId>,
+}
+
+impl SyntheticFlow {
+    pub fn new(config: FlowConfig) -> Self {
+        Self {
+            rng: SyntheticRng::new(config.seed),
+            config: config,
+            next_order_id: 1,
+            resting: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FlowConfig {
+        &self.config
+    }
+
+    /// 生成下一个事件。
+    pub fn next_event(&mut self) -> FlowEvent {
+        let roll = self.rng.next_below(1000);
+        let is_cancel = roll < self.config.cancel_permille;
+        if is_cancel && !self.resting.is_empty() {
+            let idx = self.rng.next_below(self.resting.len() as u64) as usize;
+            let order_id = self.resting.swap_remove(idx);
+            return FlowEvent::Cancel { order_id };
+        }
+        // 没有可撤的挂单时，撤单事件改为挂单
+
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let side = if self.rng.next_below(2) == 0 {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+        let vol = 1 + self.rng.next_below(self.config.max_vol.max(1) as u64) as i64;
+
+        if !is_cancel && roll < self.config.cancel_permille + self.config.take_permille {
+            let price_tick = match side {
+                Side::Buy => self.config.mid_tick + self.config.levels,
+                _ => self.config.mid_tick - self.config.levels,
+            };
+            return FlowEvent::Take {
+                order_id,
+                side,
+                price_tick,
+                vol,
+            };
+        }
+
+        let offset = 1 + self.rng.next_below(self.config.levels.max(1) as u64) as i64;
+        let price_tick = match side {
+            Side::Buy => self.config.mid_tick - offset,
+            _ => self.config.mid_tick + offset,
+        };
+        self.resting.push(order_id);
+        FlowEvent::Add {
+            order_id,
+            side,
+            price_tick,
+            vol,
+        }
+    }
+
+    /// 将接下来的 `events` 个事件构造成 ORDER 模式的历史数据。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码。
+    /// - `start_time`: 第一笔委托的时间戳。
+    /// - `step_ms`: 相邻两笔委托的时间间隔，以毫秒表示。
+    /// - `events`: 事件数量。
+    /// - `tick_size`: 价格最小变动单位。
+    /// - `lot_size`: 每手数量。
+    ///
+    /// # 返回值
+    /// 可以直接传给 `Broker::add_data` 的 `DataCollator`。
+    ///
+    /// # 错误
+    /// 委托时间超出有效的时间戳范围时返回 `MarketError::InvalidTimestamp`。
+    pub fn to_history(
+        &mut self,
+        stock_code: &str,
+        start_time: i64,
+        step_ms: i64,
+        events: usize,
+        tick_size: f64,
+        lot_size: f64,
+    ) -> Result<DataCollator, MarketError> {
+        let mut data = DataCollator::new(
+            stock_code.to_string(),
+            "vector".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "ORDER",
+        );
+        let mut orders: HashMap<OrderId, L3OrderRef> = HashMap::with_capacity(events);
+        let mut index_by_seq = VecDeque::with_capacity(events);
+        let mut order_time = start_time;
+
+        for idx in 0..events {
+            let seq = idx as i64 + 1;
+            if idx > 0 {
+                order_time = adjust_timestamp_milliseconds_i64(order_time, step_ms)?;
+            }
+            match self.next_event() {
+                FlowEvent::Add {
+                    order_id,
+                    side,
+                    price_tick,
+                    vol,
+                }
+                | FlowEvent::Take {
+                    order_id,
+                    side,
+                    price_tick,
+                    vol,
+                } => {
+                    let order_ref = L3Order::new_ref(
+                        OrderSourceType::LocalOrder,
+                        None,
+                        order_id,
+                        side,
+                        0,
+                        0,
+                        order_time,
+                        OrderType::L,
+                    );
+                    {
+                        let mut order = order_ref.borrow_mut();
+                        let auxiliary_info = order.auxiliary_info.as_mut().unwrap();
+                        auxiliary_info.initial_price = price_tick as f64 * tick_size;
+                        auxiliary_info.initial_qty = vol as f64 * lot_size;
+                        auxiliary_info.initial_seq = seq;
+                    }
+                    orders.insert(order_id, order_ref);
+                    index_by_seq.push_back((seq, order_id));
+                }
+                FlowEvent::Cancel { order_id } => {
+                    if let Some(order_ref) = orders.get(&order_id) {
+                        let mut order = order_ref.borrow_mut();
+                        order.auxiliary_info.as_mut().unwrap().cancel_seq = seq;
+                        index_by_seq.push_back((seq, order_id));
+                    }
+                }
+            }
+        }
+
+        data.len = index_by_seq.len();
+        data.orders = Some(orders);
+        data.index_by_seq = Some(index_by_seq);
+        Ok(data)
+    }
+}
+
+impl Iterator for SyntheticFlow {
+    type Item = FlowEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_is_deterministic() {
+        let config = FlowConfig::default();
+        let first: Vec<FlowEvent> = SyntheticFlow::new(config.clone()).take(1000).collect();
+        let second: Vec<FlowEvent> = SyntheticFlow::new(config.clone()).take(1000).collect();
+        assert_eq!(first, second);
+
+        let mut added = HashMap::new();
+        for event in first {
+            match event {
+                FlowEvent::Add {
+                    order_id,
+                    side,
+                    price_tick,
+                    ..
+                } => {
+                    // 被动委托不会穿过中间价
+                    match side {
+                        Side::Buy => assert!(price_tick < config.mid_tick),
+                        _ => assert!(price_tick > config.mid_tick),
+                    }
+                    added.insert(order_id, true);
+                }
+                FlowEvent::Cancel { order_id } => {
+                    // 只撤销挂出且尚未撤销的委托
+                    assert_eq!(added.insert(order_id, false), Some(true));
+                }
+                FlowEvent::Take { .. } => {}
+            }
+        }
+    }
+}
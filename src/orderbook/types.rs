@@ -4,7 +4,6 @@ use std::i32;
 use std::str::FromStr;
 
 use super::{KeyOp, MarketError};
-pub type OrderId = u64;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Deserialize, Serialize)]
 #[repr(i8)]
@@ -143,6 +142,14 @@ impl OrderType {
             _ => 255,
         }
     }
+
+    /// 判断订单是否为市价订单，市价订单的申报价格会被忽略。
+    pub fn is_market_order(&self) -> bool {
+        matches!(
+            self,
+            OrderType::M | OrderType::N | OrderType::B | OrderType::C | OrderType::D
+        )
+    }
 }
 
 impl FromStr for OrderType {
@@ -182,7 +189,21 @@ impl FromStr for MarketType {
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+/// 用户订单成交时的流动性角色。
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum FillLiquidity {
+    /// 订单进入时穿过对手方价格主动成交
+    Aggressive,
+    /// 挂单被后续进入的订单成交
+    Passive,
+    /// 在集合竞价中成交
+    Auction,
+}
+
+/// 策略编号，用于在同一次回放中区分多个策略的用户订单，0 表示默认策略。
+pub type StrategyId = u16;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, Hash)]
 #[repr(u8)]
 pub enum OrderSourceType {
     /// 代表本地订单。
@@ -222,10 +243,68 @@ pub enum OrderStatus {
     PartiallyFilled = 5,
     /// 代表订单被拒绝。
     Rejected = 6,
+    /// 代表订单已发出，尚未得到交易所确认。
+    PendingAck = 7,
     /// 代表不支持的状态。
     Unsupported = 255,
 }
 
+impl OrderStatus {
+    /// 订单是否处于终止状态，即已成交、已撤销、已过期或被拒绝，之后不会再发生变化。
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Filled
+                | OrderStatus::Canceled
+                | OrderStatus::Expired
+                | OrderStatus::Rejected
+        )
+    }
+
+    /// 订单是否仍在交易中，即尚未得到确认、新订单或部分成交的订单。
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::New | OrderStatus::PartiallyFilled | OrderStatus::PendingAck
+        )
+    }
+}
+
+/// 对尚未得到交易所确认的订单发出撤单时的处理方式。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum PendingAckCancel {
+    /// 撤单暂存，订单得到确认后立即撤销，订单不会进入订单簿
+    #[default]
+    Defer,
+    /// 撤单被拒绝，与多数交易所的行为一致
+    Reject,
+}
+
+/// 回测中历史订单主动成交后，剩余部分挂入订单簿时的排队位置。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum ResidualPriority {
+    /// 剩余部分排在价格档位的队尾，与新挂入的订单相同
+    #[default]
+    Reset,
+    /// 剩余部分保留订单进入订单簿时的优先级，排在价格档位的队首
+    Keep,
+}
+
+/// 回测中历史主动订单的成交价与历史成交价不一致时的处理方式。
+///
+/// 数据缺失或用户订单改变了重建的订单簿时，历史主动订单可能在比历史成交价更优的档位成交。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum TradeRepairPolicy {
+    /// 按重建的订单簿撮合，不做处理
+    #[default]
+    MatchAsIs,
+    /// 对手方缺少历史成交价对应的档位时，先在该价格补上历史成交数量的挂单再撮合；
+    /// 成交后最新成交价仍与历史成交价不一致时修正为历史成交价
+    ForcePrice,
+    /// 按重建的订单簿撮合，并在 `Broker::divergences` 中记录价差
+    Flag,
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum ExchangeMode {
@@ -237,14 +316,194 @@ pub enum ExchangeMode {
     Unsupported = 255,
 }
 
+/// 模拟的交易所故障类型，参见 `Broker::schedule_outage`。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum OutageKind {
+    /// 行情中断，策略收不到行情更新，可见的行情停留在中断前的状态。
+    MarketData,
+    /// 报单通道中断，新订单和撤单被拒绝，已挂出的订单照常撮合。
+    OrderEntry,
+}
+
+/// 涨跌停且对手方没有流动性时，市价订单的处理方式。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum PriceLimitPolicy {
+    /// 不做特殊处理，按订单类型原有的规则撮合。
+    #[default]
+    Sweep = 0,
+    /// 订单簿在涨跌停价上单边时，将缺少对手方流动性一侧的市价订单挂在涨跌停价上。
+    RestAtLimit = 1,
+}
+
+/// 没有前收盘价（例如新股上市首日）时，无价格限制的新股涨幅上限。
+pub const IPO_LIMIT_UP_RATIO: f64 = 0.44;
+/// 没有前收盘价（例如新股上市首日）时，无价格限制的新股跌幅上限。
+pub const IPO_LIMIT_DOWN_RATIO: f64 = 0.36;
+
+/// 前收盘价未知时，用于代替前收盘价的参考价格。
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum OpeningReference {
+    /// 以开盘集合竞价的成交价作为参考价格。
+    #[default]
+    FirstAuctionPrice,
+    /// 以当日第一笔成交的价格作为参考价格。
+    FirstTradePrice,
+    /// 使用指定的参考价格（例如新股的发行价），同时启用新股上市首日的涨跌幅限制。
+    Manual(f64),
+}
+
+/// 用户订单与历史订单在同一时间戳到达时的处理顺序。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum SameTimePriority {
+    /// 先处理同一时间戳的所有历史订单，再处理用户订单。
+    #[default]
+    HistoryFirst = 0,
+    /// 先处理用户订单，再处理同一时间戳的历史订单。
+    UserFirst = 1,
+    /// 按序列号交错处理，序列号小于用户订单的历史订单先处理。
+    BySeq = 2,
+}
+
+/// 交易时段，与 `is_in_call_auction` 使用相同的时间划分。
+///
+/// # 订单类型与时段
+/// | 时段 | 限价订单 | 市价订单（M/N/B/C/D） |
+/// | --- | --- | --- |
+/// | 开盘集合竞价（09:30 之前） | 接受 | 拒绝 |
+/// | 连续竞价 | 接受 | 接受 |
+/// | 午间休市（11:30-13:00） | 接受 | 接受 |
+/// | 收盘集合竞价（14:57 起） | 接受 | 拒绝 |
+///
+/// 上海和深圳市场使用相同的规则，撤单不受该表限制。
+///
+/// # 撤单与时段
+/// 深圳市场在收盘集合竞价（14:57-15:00）期间不接受撤单，上海市场各时段都接受撤单，
+/// 参见 `accepts_cancel`。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TradingPhase {
+    /// 开盘集合竞价
+    OpeningAuction,
+    /// 连续竞价
+    Continuous,
+    /// 午间休市
+    Break,
+    /// 收盘集合竞价
+    ClosingAuction,
+}
+
+impl TradingPhase {
+    /// 根据时间戳判断所处的交易时段。
+    ///
+    /// # 参数
+    /// - `timestamp`: 格式为 `20230801093939123` 的时间戳。
+    /// - `market`: 市场类型。
+    ///
+    /// # 错误
+    /// 市场类型未知时返回 `MarketError::MarketTypeUnknownError`。
+    pub fn from_timestamp(timestamp: i64, market: MarketType) -> Result<Self, MarketError> {
+        let only_time = timestamp % 1_000_000_000;
+        match market {
+            MarketType::SH | MarketType::SZ => Ok(if only_time < 93000000 {
+                TradingPhase::OpeningAuction
+            } else if only_time >= 145700000 {
+                TradingPhase::ClosingAuction
+            } else if (113000000..130000000).contains(&only_time) {
+                TradingPhase::Break
+            } else {
+                TradingPhase::Continuous
+            }),
+            _ => Err(MarketError::MarketTypeUnknownError),
+        }
+    }
+
+    /// 判断该时段是否接受给定类型的订单申报，集合竞价阶段只接受限价订单。
+    pub fn accepts(&self, order_type: OrderType) -> bool {
+        match self {
+            TradingPhase::OpeningAuction | TradingPhase::ClosingAuction => {
+                !order_type.is_market_order()
+            }
+            TradingPhase::Continuous | TradingPhase::Break => true,
+        }
+    }
+
+    /// 判断该时段是否接受 `market` 市场的撤单申报，深圳市场的收盘集合竞价不接受撤单。
+    pub fn accepts_cancel(&self, market: MarketType) -> bool {
+        !(market == MarketType::SZ && *self == TradingPhase::ClosingAuction)
+    }
+}
+
+/// 委托数量的申报规则。
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum LotRule {
+    /// 按整手申报，数量以 `lot` 为单位取整。
+    BoardLot { lot: f64 },
+    /// 单笔申报数量不低于 `min`，超出部分以 `increment` 递增，例如科创板的 200 股起、1 股递增。
+    MinPlusIncrement { min: f64, increment: f64 },
+}
+
+impl Default for LotRule {
+    fn default() -> Self {
+        LotRule::BoardLot { lot: 100.0 }
+    }
+}
+
+impl LotRule {
+    /// 根据股票代码推断申报规则，科创板股票（688、689 开头）使用 200 股起、1 股递增的规则，
+    /// 其余股票按 `lot_size` 整手申报。
+    ///
+    /// # 参数
+    /// - `stock_code`: 股票代码，例如 `688007.SH`。
+    /// - `lot_size`: 整手申报时每手的数量。
+    pub fn from_stock_code(stock_code: &str, lot_size: f64) -> Self {
+        if stock_code.starts_with("688") || stock_code.starts_with("689") {
+            LotRule::MinPlusIncrement {
+                min: 200.0,
+                increment: 1.0,
+            }
+        } else {
+            LotRule::BoardLot { lot: lot_size }
+        }
+    }
+
+    /// 市场深度中一个数量单位对应的股数，订单数量除以该值后以整数保存。
+    pub fn unit(&self) -> f64 {
+        match self {
+            LotRule::BoardLot { lot } => *lot,
+            LotRule::MinPlusIncrement { increment, .. } => *increment,
+        }
+    }
+
+    /// 检查申报数量是否符合规则。
+    ///
+    /// 整手申报的数量在转换时四舍五入到整手，因此不做额外限制。
+    pub fn is_valid_qty(&self, qty: f64) -> bool {
+        match self {
+            LotRule::BoardLot { .. } => true,
+            LotRule::MinPlusIncrement { min, increment } => {
+                if qty < *min {
+                    return false;
+                }
+                let steps = (qty - min) / increment;
+                (steps - steps.round()).abs() < 1e-9
+            }
+        }
+    }
+}
+
+/// 从字符串解析交易模式，不区分大小写。
+///
+/// # 错误
+/// 不是 `backtest` 或 `live` 时返回 `MarketError::UnknownExchangeMode`。
 impl FromStr for ExchangeMode {
-    type Err = ();
+    type Err = MarketError;
 
     fn from_str(input: &str) -> Result<ExchangeMode, Self::Err> {
         match input.to_lowercase().as_str() {
             "backtest" => Ok(ExchangeMode::Backtest),
             "live" => Ok(ExchangeMode::Live),
-            _ => Ok(ExchangeMode::Unsupported),
+            _ => Err(MarketError::UnknownExchangeMode(input.to_string())),
         }
     }
 }
@@ -259,6 +518,9 @@ impl AsRef<str> for ExchangeMode {
     }
 }
 
+/// 合法 tick 价格的默认范围，绝对值超过该值的 tick 视为哨兵值或异常数据。
+pub const PRICE_TICK_LIMIT: i64 = 1_000_000_000;
+
 #[derive(Eq, Debug, Deserialize, Serialize, Clone, Copy)]
 pub struct PriceTick {
     /// 价格跳动的整数值。
@@ -275,6 +537,62 @@ impl PriceTick {
             reverse: reverse,
         }
     }
+
+    /// 检查 tick 价格是否在 `±PRICE_TICK_LIMIT` 范围内，参见 `PriceTick::try_new_within`。
+    pub fn try_new(price_tick: i64, reverse: bool) -> Result<Self, MarketError> {
+        Self::try_new_within(price_tick, reverse, PRICE_TICK_LIMIT)
+    }
+
+    /// 构造范围内的 tick 价格。
+    ///
+    /// `INVALID_MIN`、`INVALID_MAX` 等哨兵值总是超出范围，因此不会被当作价格档位使用。
+    ///
+    /// # 参数
+    /// - `price_tick`: tick 价格。
+    /// - `reverse`: 是否反转排序。
+    /// - `limit`: 允许的最大绝对值。
+    ///
+    /// # 错误
+    /// 绝对值超过 `limit` 时返回 `MarketError::PriceTickOutOfRange`。
+    pub fn try_new_within(price_tick: i64, reverse: bool, limit: i64) -> Result<Self, MarketError> {
+        match price_tick.checked_abs() {
+            Some(abs) if abs <= limit => Ok(Self::new(price_tick, reverse)),
+            _ => Err(MarketError::PriceTickOutOfRange(price_tick)),
+        }
+    }
+}
+
+/// 撮合时对手方价格档位的边界。
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MatchBound {
+    /// 只与价格不差于订单 `price_tick` 的档位成交。
+    Limit,
+    /// 不检查价格，只受最大档位数量限制，用于市价订单。订单的 `price_tick` 不会被读取。
+    Unbounded,
+}
+
+/// 查询最优价时使用的订单簿。
+///
+/// 回测模式下用户订单只与影子数量成交，影子订单簿的最优价是仍有影子数量的最优档位，
+/// 即扣除用户已成交数量后策略可见的流动性；实盘模式下两者相同。
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BestMode {
+    /// 只使用真实订单簿，即历史订单的最优价，回测模式下只有用户订单的档位不参与比较。
+    RealOnly,
+    /// 只使用影子订单簿，与 `UserOrder` 来源的查询结果一致。
+    ShadowOnly,
+    /// 取真实和影子订单簿中更优的价格，买方取较高价，卖方取较低价。
+    /// 只要某一档位在任一订单簿中有挂单，就参与比较。
+    Combined,
+}
+
+/// 除权除息时对订单簿的调整。
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum CorporateAction {
+    /// 拆股，参数为每股拆分后的股数，例如 10 送 10 为 `2.0`。
+    Split(f64),
+    /// 现金分红，参数为每股分红金额。
+    CashDividend(f64),
 }
 
 impl KeyOp for PriceTick {
@@ -348,6 +666,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_order_status_classification() {
+        for (status, is_terminal, is_open) in [
+            (OrderStatus::None, false, false),
+            (OrderStatus::New, false, true),
+            (OrderStatus::Expired, true, false),
+            (OrderStatus::Filled, true, false),
+            (OrderStatus::Canceled, true, false),
+            (OrderStatus::PartiallyFilled, false, true),
+            (OrderStatus::Rejected, true, false),
+            (OrderStatus::PendingAck, false, true),
+            (OrderStatus::Unsupported, false, false),
+        ] {
+            assert_eq!(status.is_terminal(), is_terminal, "{status:?}");
+            assert_eq!(status.is_open(), is_open, "{status:?}");
+        }
+    }
+
     #[test]
     fn test_price_tick() {
         let price_tick1: PriceTick = PriceTick::new(100, true);
@@ -369,4 +705,28 @@ mod tests {
         let price_tick2: PriceTick = PriceTick::new(100, false);
         assert_eq!(price_tick1, price_tick2);
     }
+
+    #[test]
+    fn test_price_tick_range() {
+        assert_eq!(
+            PriceTick::try_new(PRICE_TICK_LIMIT, false)
+                .unwrap()
+                .price_tick,
+            PRICE_TICK_LIMIT
+        );
+        assert_eq!(
+            PriceTick::try_new(-PRICE_TICK_LIMIT, true)
+                .unwrap()
+                .price_tick,
+            -PRICE_TICK_LIMIT
+        );
+        for price_tick in [i64::MAX, i64::MIN, PRICE_TICK_LIMIT + 1] {
+            assert_eq!(
+                PriceTick::try_new(price_tick, false),
+                Err(MarketError::PriceTickOutOfRange(price_tick))
+            );
+        }
+        assert!(PriceTick::try_new_within(1001, false, 1000).is_err());
+        assert!(PriceTick::try_new_within(-1000, false, 1000).is_ok());
+    }
 }
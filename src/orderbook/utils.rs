@@ -1,6 +1,7 @@
-use super::types::MarketType;
+use super::types::{MarketType, OrderType};
 use super::MarketError;
-use chrono::{Duration, NaiveDateTime};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use std::cmp;
 /// 解析时间戳字符串为 `NaiveDateTime` 对象。
 ///
 /// 时间戳字符串格式应为“年月日时分秒毫秒”，总共17位字符。
@@ -30,6 +31,64 @@ pub fn parse_timestamp(timestamp: &str) -> Result<NaiveDateTime, MarketError> {
     NaiveDateTime::parse_from_str(timestamp, format).map_err(|_| MarketError::ParseError)
 }
 
+/// 将 17 位整数时间戳解码为 `NaiveDateTime` 对象。
+///
+/// # 参数
+/// - `timestamp`: 日期时间戳，格式为“年月日时分秒毫秒”，如 `20230801093939123`。
+///
+/// # 返回
+/// - `Ok(NaiveDateTime)`: 解码成功，返回对应的 `NaiveDateTime` 对象。
+/// - `Err(MarketError::ParseError)`: 位数不是 17 位，或日期、时间字段不合法。
+///
+/// # 示例
+/// ```
+/// let datetime = decode_timestamp(20230801093939123).unwrap();
+/// ```
+#[inline(always)]
+pub fn decode_timestamp(timestamp: i64) -> Result<NaiveDateTime, MarketError> {
+    if !(10_000_000_000_000_000..=99_999_999_999_999_999).contains(&timestamp) {
+        return Err(MarketError::ParseError);
+    }
+
+    let milli = (timestamp % 1_000) as u32;
+    let second = (timestamp / 1_000 % 100) as u32;
+    let minute = (timestamp / 100_000 % 100) as u32;
+    let hour = (timestamp / 10_000_000 % 100) as u32;
+    let day = (timestamp / 1_000_000_000 % 100) as u32;
+    let month = (timestamp / 100_000_000_000 % 100) as u32;
+    let year = (timestamp / 10_000_000_000_000) as i32;
+
+    NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|date| date.and_hms_milli_opt(hour, minute, second, milli))
+        .ok_or(MarketError::ParseError)
+}
+
+/// 将 `NaiveDateTime` 对象编码为 17 位整数时间戳。
+///
+/// # 参数
+/// - `datetime`: 要编码的 `NaiveDateTime` 对象。
+///
+/// # 返回
+/// - `Ok(i64)`: 编码后的日期时间戳，格式为“年月日时分秒毫秒”。
+/// - `Err(MarketError::InvalidTimestamp)`: 年份超出 4 位数的范围，无法编码。
+#[inline(always)]
+pub fn encode_timestamp(datetime: NaiveDateTime) -> Result<i64, MarketError> {
+    let year = datetime.year();
+    if !(1000..=9999).contains(&year) {
+        return Err(MarketError::InvalidTimestamp);
+    }
+
+    // 闰秒以超过 1000 的纳秒表示，按该秒的最后一毫秒处理
+    let milli = cmp::min(datetime.nanosecond() / 1_000_000, 999);
+    Ok(year as i64 * 10_000_000_000_000
+        + datetime.month() as i64 * 100_000_000_000
+        + datetime.day() as i64 * 1_000_000_000
+        + datetime.hour() as i64 * 10_000_000
+        + datetime.minute() as i64 * 100_000
+        + datetime.second() as i64 * 1_000
+        + milli as i64)
+}
+
 /// 调整 `NaiveDateTime` 对象的毫秒数。
 ///
 /// 输入一个 `NaiveDateTime` 对象和要调整的毫秒数（正值表示增加，负值表示减少）。
@@ -99,6 +158,9 @@ pub fn time_difference_ms(datetime1: NaiveDateTime, datetime2: NaiveDateTime) ->
 /// # 返回
 /// - 两个时间戳之间的时间差，以毫秒为单位。如果 `timestamp2` 在 `timestamp1` 之前，返回负值。
 ///
+/// # 错误
+/// - `MarketError::ParseError`: 任一时间戳不是合法的 17 位编码。
+///
 /// # 示例
 /// ```
 /// let timestamp1: i64 = 20230801093939123;
@@ -109,17 +171,10 @@ pub fn time_difference_ms(datetime1: NaiveDateTime, datetime2: NaiveDateTime) ->
 ///
 #[inline(always)]
 pub fn time_difference_ms_i64(timestamp1: i64, timestamp2: i64) -> Result<i64, MarketError> {
-    // 将 i64 类型的时间戳转换为字符串
-    let timestamp1_str = timestamp1.to_string();
-    let timestamp2_str = timestamp2.to_string();
-
-    // 解析时间戳字符串为 NaiveDateTime 对象
-    let datetime1 = parse_timestamp(&timestamp1_str)?;
-    let datetime2 = parse_timestamp(&timestamp2_str)?;
+    let datetime1 = decode_timestamp(timestamp1)?;
+    let datetime2 = decode_timestamp(timestamp2)?;
 
-    // 计算时间差
-    let duration = datetime2.signed_duration_since(datetime1);
-    Ok(duration.num_milliseconds())
+    Ok(time_difference_ms(datetime1, datetime2))
 }
 
 /// 调整原始格式的日期时间字符串中的毫秒数。
@@ -164,6 +219,10 @@ pub fn adjust_timestamp_milliseconds(
 /// # 返回
 /// - 调整后的日期时间戳 `i64`，格式为“年月日时分秒毫秒”。
 ///
+/// # 错误
+/// - `MarketError::ParseError`: 输入的时间戳不是合法的 17 位编码。
+/// - `MarketError::InvalidTimestamp`: 调整后的时间超出可编码的范围。
+///
 /// # 示例
 /// ```
 /// let timestamp: i64 = 20230801093939123;
@@ -175,22 +234,14 @@ pub fn adjust_timestamp_milliseconds_i64(
     timestamp: i64,
     milliseconds: i64,
 ) -> Result<i64, MarketError> {
-    // 将 i64 类型的时间戳转换为字符串
-    let timestamp_str = timestamp.to_string();
-
-    // 解析时间戳字符串为 NaiveDateTime 对象
-    let datetime = parse_timestamp(&timestamp_str)?;
-
-    // 调整时间
-    let adjusted_datetime = adjust_milliseconds(datetime, milliseconds);
+    let datetime = decode_timestamp(timestamp)?;
 
-    // 格式化为原始时间戳格式字符串
-    let new_timestamp_str = format_timestamp(adjusted_datetime);
+    // 跨越午夜、月末或年末时由 chrono 负责进位到日期部分
+    let adjusted_datetime = Duration::try_milliseconds(milliseconds)
+        .and_then(|duration| datetime.checked_add_signed(duration))
+        .ok_or(MarketError::InvalidTimestamp)?;
 
-    // 将调整后的时间戳字符串转换回 i64
-    new_timestamp_str
-        .parse::<i64>()
-        .map_err(|_| MarketError::InvalidTimestamp)
+    encode_timestamp(adjusted_datetime)
 }
 
 /// 计算两个原始格式的时间戳字符串之间的时间差（以毫秒为单位）。
@@ -242,7 +293,7 @@ pub fn is_in_call_auction(timestamp: i64, market: MarketType) -> Result<bool, Ma
     let only_time = timestamp % 1_000_000_000;
     match market {
         MarketType::SH | MarketType::SZ => {
-            let yes_or_no: bool = only_time < 93000000 || only_time > 145700000;
+            let yes_or_no: bool = only_time < 93000000 || only_time >= 145700000;
             Ok(yes_or_no)
         }
         _ => Err(MarketError::MarketTypeUnknownError),
@@ -254,6 +305,55 @@ pub fn extract_market_code(stock_code: &str) -> &str {
     stock_code.split('.').last().unwrap_or("SH")
 }
 
+/// 校验订单的价格和数量是否合法。
+///
+/// 数量必须为大于 0 的有限值；除市价订单和撤单外，价格也必须为大于 0 的有限值。
+///
+/// # 参数
+/// - `price`: 订单价格。
+/// - `qty`: 订单数量。
+/// - `order_type`: 订单类型，市价订单的价格不做校验。
+///
+/// # 返回
+/// - `Ok(())`: 价格和数量合法。
+/// - `Err(MarketError::InvalidOrderRequest)`: 价格或数量不合法。
+pub fn validate_price_qty(price: f64, qty: f64, order_type: OrderType) -> Result<(), MarketError> {
+    if order_type == OrderType::Cancel {
+        return Ok(());
+    }
+    if !qty.is_finite() || qty <= 0.0 {
+        return Err(MarketError::InvalidOrderRequest);
+    }
+    if !order_type.is_market_order() && (!price.is_finite() || price <= 0.0) {
+        return Err(MarketError::InvalidOrderRequest);
+    }
+    Ok(())
+}
+
+/// 将数量换算为以 `lot_size` 为单位的手数，按四舍五入取整。
+///
+/// # 参数
+/// - `qty`: 数量。
+/// - `lot_size`: 每手的数量。
+///
+/// # 返回
+/// - 换算后的手数。
+pub fn qty_to_lots(qty: f64, lot_size: f64) -> i64 {
+    (qty / lot_size).round() as i64
+}
+
+/// 将以 `lot_size` 为单位的手数换算为数量。
+///
+/// # 参数
+/// - `lots`: 手数。
+/// - `lot_size`: 每手的数量。
+///
+/// # 返回
+/// - 换算后的数量。
+pub fn lots_to_qty(lots: i64, lot_size: f64) -> f64 {
+    lots as f64 * lot_size
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -431,4 +531,136 @@ mod tests {
         let result = time_difference_ms_i64(timestamp1, timestamp2);
         assert!(result.is_err()); // 应该返回错误
     }
+
+    #[test]
+    fn test_decode_timestamp_invalid() {
+        for timestamp in [
+            2023080109393912,   // 16 位
+            -20230801093939123, // 负数
+            20231301093939123,  // 13 月
+            20230230093939123,  // 2 月 30 日
+            20230229093939123,  // 非闰年的 2 月 29 日
+            20230801243939123,  // 24 时
+            20230801096039123,  // 60 分
+            20230801093960123,  // 60 秒
+        ] {
+            assert_eq!(decode_timestamp(timestamp), Err(MarketError::ParseError));
+            assert_eq!(
+                adjust_timestamp_milliseconds_i64(timestamp, 1),
+                Err(MarketError::ParseError)
+            );
+            assert_eq!(
+                time_difference_ms_i64(20230801093939123, timestamp),
+                Err(MarketError::ParseError)
+            );
+        }
+    }
+
+    #[test]
+    fn test_adjust_timestamp_milliseconds_i64_rollover() {
+        let cases = [
+            // 同一天
+            (20230801093939123, 877, 20230801093940000),
+            // 跨越午夜
+            (20230801235959900, 200, 20230802000000100),
+            // 跨越月末
+            (20231130235959900, 200, 20231201000000100),
+            // 跨越年末
+            (20231231235959900, 200, 20240101000000100),
+            (20240101000000100, -200, 20231231235959900),
+            // 闰日
+            (20240228235959999, 1, 20240229000000000),
+            (20240229235959999, 1, 20240301000000000),
+            (20230228235959999, 1, 20230301000000000),
+            (20240301000000000, -1, 20240229235959999),
+            // 前进 24 小时
+            (20231201093000000, 24 * 3600 * 1000, 20231202093000000),
+            (20240228120000000, 24 * 3600 * 1000, 20240229120000000),
+        ];
+        for (timestamp, milliseconds, expected) in cases {
+            assert_eq!(
+                adjust_timestamp_milliseconds_i64(timestamp, milliseconds),
+                Ok(expected),
+                "{timestamp} + {milliseconds}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_adjust_then_diff_round_trip() {
+        let timestamps = [
+            20230801093939123,
+            20231130235959900,
+            20231231235959999,
+            20240228235959999,
+            20240229000000000,
+        ];
+        let offsets = [
+            0,
+            1,
+            -1,
+            999,
+            -1_001,
+            60_000,
+            24 * 3600 * 1000,
+            -24 * 3600 * 1000,
+            366 * 24 * 3600 * 1000,
+        ];
+        for timestamp in timestamps {
+            for milliseconds in offsets {
+                let adjusted = adjust_timestamp_milliseconds_i64(timestamp, milliseconds).unwrap();
+                assert_eq!(
+                    time_difference_ms_i64(timestamp, adjusted),
+                    Ok(milliseconds)
+                );
+                assert_eq!(
+                    adjust_timestamp_milliseconds_i64(adjusted, -milliseconds),
+                    Ok(timestamp)
+                );
+                assert_eq!(
+                    encode_timestamp(decode_timestamp(adjusted).unwrap()),
+                    Ok(adjusted)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_adjust_timestamp_milliseconds_i64_out_of_range() {
+        assert_eq!(
+            adjust_timestamp_milliseconds_i64(99991231235959999, 1),
+            Err(MarketError::InvalidTimestamp)
+        );
+        assert_eq!(
+            adjust_timestamp_milliseconds_i64(20230801093939123, i64::MAX),
+            Err(MarketError::InvalidTimestamp)
+        );
+    }
+
+    #[test]
+    fn test_validate_price_qty() {
+        assert!(validate_price_qty(10.0, 100.0, OrderType::L).is_ok());
+        assert!(validate_price_qty(0.0, 100.0, OrderType::C).is_ok());
+        assert!(validate_price_qty(0.0, 0.0, OrderType::Cancel).is_ok());
+        assert_eq!(
+            validate_price_qty(0.0, 100.0, OrderType::L),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            validate_price_qty(-1.0, 100.0, OrderType::L),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            validate_price_qty(f64::NAN, 100.0, OrderType::L),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            validate_price_qty(10.0, 0.0, OrderType::L),
+            Err(MarketError::InvalidOrderRequest)
+        );
+        assert_eq!(
+            validate_price_qty(0.0, -100.0, OrderType::C),
+            Err(MarketError::InvalidOrderRequest)
+        );
+    }
 }
@@ -0,0 +1,113 @@
+use super::broker::Broker;
+use super::MarketError;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+
+type Job<MD> = Box<dyn FnOnce(&mut Broker<MD>) + Send + 'static>;
+
+/// 常驻的回放线程，`Exchange::spawn_workers` 为每只股票创建一个。
+///
+/// 经纪商在回放线程上创建，之后也只在该线程上被访问，调用方只能发送可以跨线程的任务，
+/// 任务的结果通过通道返回。线程在 `ReplayWorker` 被丢弃时退出，经纪商随之丢弃。
+pub struct ReplayWorker<MD> {
+    pub stock_code: String,
+    sender: Option<mpsc::Sender<Job<MD>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<MD> fmt::Debug for ReplayWorker<MD> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayWorker")
+            .field("stock_code", &self.stock_code)
+            .finish()
+    }
+}
+
+impl<MD: 'static> ReplayWorker<MD> {
+    /// 为指定的股票创建回放线程，线程名称为 `replay-{stock_code}`，并在线程上调用 `build` 创建经纪商。
+    ///
+    /// 经纪商创建完成后才返回，加载历史数据等耗时的准备工作可以放在 `build` 中，与其他股票并行执行。
+    ///
+    /// # 错误
+    /// - `build` 返回的错误。
+    /// - `WorkerUnavailable`: 无法创建线程，或者 `build` 发生 panic。
+    pub fn spawn<F>(stock_code: &str, build: F) -> Result<Self, MarketError>
+    where
+        F: FnOnce() -> Result<Broker<MD>, MarketError> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<Job<MD>>();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(format!("replay-{stock_code}"))
+            .spawn(move || {
+                let mut broker = match build() {
+                    Ok(broker) => broker,
+                    Err(err) => {
+                        let _ = ready_sender.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready_sender.send(Ok(()));
+                while let Ok(job) = receiver.recv() {
+                    job(&mut broker);
+                }
+            })
+            .map_err(|_| MarketError::WorkerUnavailable)?;
+
+        let ready = ready_receiver
+            .recv()
+            .unwrap_or(Err(MarketError::WorkerUnavailable));
+        if let Err(err) = ready {
+            let _ = thread.join();
+            return Err(err);
+        }
+        Ok(Self {
+            stock_code: stock_code.to_string(),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// 把 `f` 发送到回放线程上执行，不等待执行完成，结果从返回的通道中读取。
+    ///
+    /// # 错误
+    /// `WorkerUnavailable`: 回放线程已经退出。
+    pub fn start<R, F>(&self, f: F) -> Result<mpsc::Receiver<R>, MarketError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Broker<MD>) -> R + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let job: Job<MD> = Box::new(move |broker| {
+            let _ = sender.send(f(broker));
+        });
+        match &self.sender {
+            Some(jobs) if jobs.send(job).is_ok() => Ok(receiver),
+            _ => Err(MarketError::WorkerUnavailable),
+        }
+    }
+
+    /// 在回放线程上执行 `f` 并等待结果。
+    ///
+    /// # 错误
+    /// `WorkerUnavailable`: 回放线程已经退出，或者 `f` 发生 panic。
+    pub fn call<R, F>(&self, f: F) -> Result<R, MarketError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Broker<MD>) -> R + Send + 'static,
+    {
+        self.start(f)?
+            .recv()
+            .map_err(|_| MarketError::WorkerUnavailable)
+    }
+}
+
+impl<MD> Drop for ReplayWorker<MD> {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
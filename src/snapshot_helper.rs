@@ -10,6 +10,7 @@ use super::orderbook::L3OrderRef;
 use polars::export::num::ToPrimitive;
 use polars::prelude::*;
 use std::any::{Any, TypeId};
+use std::cmp;
 use std::marker::PhantomData;
 use std::time;
 use std::{any, fmt};
@@ -82,6 +83,8 @@ big_array! {
 }
 
 const LEVELNUM: usize = 50;
+/// 默认计算累计挂单不平衡度的档位数量。
+pub const IMBALANCE_LEVELS: usize = 5;
 type F64ArrLvl = [f64; LEVELNUM];
 type I32ArrLvl = [i32; LEVELNUM];
 
@@ -118,6 +121,8 @@ pub struct OrderBookSnapshot {
     total_trade_num: i32,
     avg_ask_price: f64,
     avg_bid_price: f64,
+    /// 前 `imbalance_levels` 档买方挂单量之和占买卖双方挂单量之和的比例
+    depth_imbalance: f64,
     // ask_num: i32,
     // bid_num: i32,
     // ask_qty: i32,
@@ -178,6 +183,8 @@ pub struct OrderBookSnapshot {
     vec_avg_ask_price: Vec<f64>,
     #[serde(skip_serializing)]
     vec_avg_bid_price: Vec<f64>,
+    #[serde(skip_serializing)]
+    vec_depth_imbalance: Vec<f64>,
     // #[serde(skip_serializing)]
     // vec_ask_num: Vec<i32>,
     // #[serde(skip_serializing)]
@@ -210,6 +217,12 @@ pub struct OrderBookSnapshot {
     vec_msg_amt: Vec<f64>,
     #[serde(skip_serializing)]
     need_output: bool,
+    /// 计算 `depth_imbalance` 的档位数量
+    #[serde(skip_serializing)]
+    imbalance_levels: usize,
+    /// 输出的最大档位数量，更深的档位写出为 0，`None` 表示输出全部 `LEVELNUM` 档
+    #[serde(skip_serializing)]
+    trim_to_level: Option<usize>,
 }
 
 impl OrderBookSnapshot {
@@ -239,6 +252,7 @@ impl OrderBookSnapshot {
             total_trade_num: 0,
             avg_ask_price: 0.0,
             avg_bid_price: 0.0,
+            depth_imbalance: f64::NAN,
             // ask_num: 0,
             // bid_num: 0,
             // ask_qty: 0,
@@ -276,6 +290,7 @@ impl OrderBookSnapshot {
             vec_total_trade_num: Vec::<i32>::with_capacity(size),
             vec_avg_ask_price: Vec::<f64>::with_capacity(size),
             vec_avg_bid_price: Vec::<f64>::with_capacity(size),
+            vec_depth_imbalance: Vec::<f64>::with_capacity(size),
             // vec_ask_num: Vec::<i32>::with_capacity(size),
             // vec_bid_num: Vec::<i32>::with_capacity(size),
             // vec_ask_qty: Vec::<i32>::with_capacity(size),
@@ -292,9 +307,24 @@ impl OrderBookSnapshot {
             vec_msg_qty: Vec::<i32>::with_capacity(size),
             vec_msg_amt: Vec::<f64>::with_capacity(size),
             need_output: false,
+            imbalance_levels: IMBALANCE_LEVELS,
+            trim_to_level: None,
         }
     }
 
+    /// 设置计算累计挂单不平衡度的档位数量，默认为 `IMBALANCE_LEVELS`。
+    pub fn set_imbalance_levels(&mut self, levels: usize) {
+        self.imbalance_levels = levels;
+    }
+
+    /// 设置输出的最大档位数量，用于只能公开前 N 档行情的场景。
+    ///
+    /// 超过的档位在输出中为 0，`depth_imbalance`、总量、订单数和均价也只统计前 N 档，不会泄露更深档位的信息。
+    /// `None` 表示输出全部档位。
+    pub fn set_trim_to_level(&mut self, levels: Option<usize>) {
+        self.trim_to_level = levels;
+    }
+
     pub fn snapshot_once(
         &mut self,
         recvtime: i64,
@@ -319,6 +349,7 @@ impl OrderBookSnapshot {
         total_trade_num: i32,
         avg_ask_price: f64,
         avg_bid_price: f64,
+        depth_imbalance: f64,
         msg_buy_no: i64,
         msg_sell_no: i64,
         msg_trade_type: i32,
@@ -352,6 +383,7 @@ impl OrderBookSnapshot {
         self.total_trade_num = total_trade_num;
         self.avg_ask_price = avg_ask_price;
         self.avg_bid_price = avg_bid_price;
+        self.depth_imbalance = depth_imbalance;
         // self.ask_num = ask_num;
         // self.bid_num = bid_num;
         // self.ask_qty = ask_qty;
@@ -391,6 +423,7 @@ impl OrderBookSnapshot {
             self.vec_total_trade_num.push(total_trade_num);
             self.vec_avg_ask_price.push(avg_ask_price);
             self.vec_avg_bid_price.push(avg_bid_price);
+            self.vec_depth_imbalance.push(depth_imbalance);
             // self.vec_ask_num.push(ask_num);
             // self.vec_bid_num.push(bid_num);
             // self.vec_ask_qty.push(ask_qty);
@@ -576,6 +609,7 @@ impl OrderBookSnapshot {
         let sr_total_trade_num = Series::new("ttl_trade_num", &self.vec_total_trade_num);
         let sr_avg_ask_price = Series::new("avg_ask_price", &self.vec_avg_ask_price);
         let sr_avg_bid_price = Series::new("avg_bid_price", &self.vec_avg_bid_price);
+        let sr_depth_imbalance = Series::new("depth_imbalance", &self.vec_depth_imbalance);
         // let sr_ask_num = Series::new("ask_num", &self.vec_ask_num);
         // let sr_bid_num = Series::new("bid_num", &self.vec_bid_num);
         // let sr_ask_qty = Series::new("ask_qty", &self.vec_ask_qty);
@@ -613,6 +647,7 @@ impl OrderBookSnapshot {
             // sr_trade_num,
             sr_avg_ask_price,
             sr_avg_bid_price,
+            sr_depth_imbalance,
             // sr_ask_num,
             // sr_bid_num,
             // sr_ask_qty,
@@ -663,11 +698,7 @@ impl OrderBookSnapshot {
 pub type OrderBookSnapshotRef = Rc<RefCell<OrderBookSnapshot>>;
 
 pub fn get_hook(ob_snapshot: OrderBookSnapshotRef) -> Hook {
-    Hook {
-        object: ob_snapshot,
-        handler: handler,
-        max_level: 50,
-    }
+    Hook::new(ob_snapshot, handler, 50)
 }
 
 pub fn handler(
@@ -687,37 +718,12 @@ pub fn handler(
         let last_price = info.last_price;
         let high_price = info.high;
         let low_price = info.low;
-        let total_turnover = ((info.total_bid + info.total_ask)*1000.0).round()/1000.0;
-        let total_volume = (info.total_bid_qty + info.total_ask_qty).round() as i32;
-        let prev_close_price = info.prev_close_price;
-        let mut sub_asks_p: F64ArrLvl = [0.0; LEVELNUM];
-        let mut sub_asks_vol: I32ArrLvl = [0; LEVELNUM];
-        let mut sub_asks_num: I32ArrLvl = [0; LEVELNUM];
-        let mut sub_bids_p: F64ArrLvl = [0.0; LEVELNUM];
-        let mut sub_bids_vol: I32ArrLvl = [0; LEVELNUM];
-        let mut sub_bids_num: I32ArrLvl = [0; LEVELNUM];
-
-        sub_bids_p
-            .iter_mut()
-            .zip(sub_bids_vol.iter_mut())
-            .zip(sub_bids_num.iter_mut())
-            .zip(bid_vec.iter())
-            .for_each(|(((p, vol), num), &(price, qty, count))| {
-                *p = (price * 1000.0).round() / 1000.0;
-                *vol = qty.round() as i32;
-                *num = count as i32;
-            });
-
-        sub_asks_p
-            .iter_mut()
-            .zip(sub_asks_vol.iter_mut())
-            .zip(sub_asks_num.iter_mut())
-            .zip(ask_vec.iter())
-            .for_each(|(((p, vol), num), &(price, qty, count))| {
-                *p = (price * 1000.0).round() / 1000.0;
-                *vol = qty.round() as i32;
-                *num = count as i32;
-            });
+        let total_turnover = info.turnover;
+        // 前收盘价未知时写出 NaN，避免下游把 0 当作真实价格计算涨跌幅
+        let prev_close_price = info.prev_close_price.unwrap_or(f64::NAN);
+        let levels = snapshot.trim_to_level.unwrap_or(LEVELNUM);
+        let (sub_bids_p, sub_bids_vol, sub_bids_num) = fill_levels(bid_vec, levels);
+        let (sub_asks_p, sub_asks_vol, sub_asks_num) = fill_levels(ask_vec, levels);
 
         let msg_buy_no = order.order_id;
         let msg_sell_no = order.order_id;
@@ -728,9 +734,37 @@ pub fn handler(
         let msg_qty = (order.vol as f64 * info.lot_size).round() as i32;
         let msg_amt = (msg_price * (order.vol as f64 * info.lot_size) * 1000.0).round() / 1000.0;
         let modified = true;
-        let total_trade_num = (info.total_bid_order + info.total_ask_order) as i32;
-        let avg_ask_price = ((info.total_ask / info.total_ask_qty) * 1000.0).round() / 1000.0;
-        let avg_bid_price = ((info.total_bid / info.total_bid_qty) * 1000.0).round() / 1000.0;
+        // 只公开前 N 档时，汇总字段同样只由这些档位计算，不泄露更深档位的信息
+        let (total_volume, total_trade_num, total_ask, total_ask_qty, total_bid, total_bid_qty) =
+            match snapshot.trim_to_level {
+                Some(levels) => {
+                    let (ask_qty, ask_num, ask_amount) = level_totals(ask_vec, levels);
+                    let (bid_qty, bid_num, bid_amount) = level_totals(bid_vec, levels);
+                    (
+                        (bid_qty + ask_qty).round() as i32,
+                        (bid_num + ask_num) as i32,
+                        ask_amount,
+                        ask_qty,
+                        bid_amount,
+                        bid_qty,
+                    )
+                }
+                None => (
+                    (info.total_bid_qty + info.total_ask_qty).round() as i32,
+                    (info.total_bid_order + info.total_ask_order) as i32,
+                    info.total_ask,
+                    info.total_ask_qty,
+                    info.total_bid,
+                    info.total_bid_qty,
+                ),
+            };
+        let avg_ask_price = ((total_ask / total_ask_qty) * 1000.0).round() / 1000.0;
+        let avg_bid_price = ((total_bid / total_bid_qty) * 1000.0).round() / 1000.0;
+        let depth_imbalance = cumulative_imbalance(
+            bid_vec,
+            ask_vec,
+            cmp::min(snapshot.imbalance_levels, levels),
+        );
         let need_output = snapshot.need_output;
         snapshot.snapshot_once(
             timestamp,
@@ -752,6 +786,7 @@ pub fn handler(
             total_trade_num,
             avg_ask_price,
             avg_bid_price,
+            depth_imbalance,
             msg_buy_no,
             msg_sell_no,
             msg_trade_type,
@@ -768,3 +803,137 @@ pub fn handler(
         false
     }
 }
+
+/// 把前 `levels` 档写入定长数组，价格保留三位小数，其余档位为 0。
+///
+/// 档位按最优价在前排列，元素为 (价格, 数量, 订单数)。
+fn fill_levels(levels_vec: &[(f64, f64, i64)], levels: usize) -> (F64ArrLvl, I32ArrLvl, I32ArrLvl) {
+    let mut prices: F64ArrLvl = [0.0; LEVELNUM];
+    let mut vols: I32ArrLvl = [0; LEVELNUM];
+    let mut nums: I32ArrLvl = [0; LEVELNUM];
+    prices
+        .iter_mut()
+        .zip(vols.iter_mut())
+        .zip(nums.iter_mut())
+        .zip(levels_vec.iter().take(levels))
+        .for_each(|(((p, vol), num), &(price, qty, count))| {
+            *p = (price * 1000.0).round() / 1000.0;
+            *vol = qty.round() as i32;
+            *num = count as i32;
+        });
+    (prices, vols, nums)
+}
+
+/// 汇总前 `levels` 档的挂单，返回 (数量之和, 订单数之和, 价格 × 数量之和)。
+fn level_totals(levels_vec: &[(f64, f64, i64)], levels: usize) -> (f64, i64, f64) {
+    levels_vec.iter().take(levels).fold(
+        (0.0, 0, 0.0),
+        |(qty, num, amount), &(price, level_qty, count)| {
+            (qty + level_qty, num + count, amount + price * level_qty)
+        },
+    )
+}
+
+/// 计算前 `levels` 档的累计挂单不平衡度，即买方挂单量之和占买卖双方挂单量之和的比例。
+///
+/// 档位按最优价在前排列，元素为 (价格, 数量, 订单数)。双方都没有挂单时返回 `NaN`。
+pub fn cumulative_imbalance(
+    bid_vec: &[(f64, f64, i64)],
+    ask_vec: &[(f64, f64, i64)],
+    levels: usize,
+) -> f64 {
+    let bid_qty: f64 = bid_vec.iter().take(levels).map(|&(_, qty, _)| qty).sum();
+    let ask_qty: f64 = ask_vec.iter().take(levels).map(|&(_, qty, _)| qty).sum();
+    let total = bid_qty + ask_qty;
+    if total > 0.0 {
+        bid_qty / total
+    } else {
+        f64::NAN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 测试多档订单簿的累计挂单不平衡度。
+    /// 验证只统计前 N 档，单边订单簿和空订单簿的结果。
+    fn test_cumulative_imbalance() {
+        let bid_vec = vec![(10.01, 200.0, 1), (10.00, 500.0, 2), (9.99, 300.0, 1)];
+        let ask_vec = vec![(10.02, 300.0, 1), (10.03, 100.0, 1), (10.04, 900.0, 3)];
+        // 前两档：买方 700，卖方 400
+        assert!((cumulative_imbalance(&bid_vec, &ask_vec, 2) - 700.0 / 1100.0).abs() < 1e-12);
+        // 档位数量超过订单簿深度时统计全部档位
+        assert!((cumulative_imbalance(&bid_vec, &ask_vec, 10) - 1000.0 / 2300.0).abs() < 1e-12);
+        assert_eq!(cumulative_imbalance(&bid_vec, &[], 5), 1.0);
+        assert_eq!(cumulative_imbalance(&[], &ask_vec, 5), 0.0);
+        assert!(cumulative_imbalance(&[], &[], 5).is_nan());
+        assert!(cumulative_imbalance(&bid_vec, &ask_vec, 0).is_nan());
+    }
+
+    #[test]
+    /// 测试只输出前 N 档行情。
+    /// 验证 10 档订单簿只输出前 3 档时，第 4 到 50 档全部为 0，挂单不平衡度和汇总字段只统计前 3 档。
+    fn test_trim_to_level() {
+        use crate::orderbook::types::OrderSourceType;
+        use crate::orderbook::L3Order;
+
+        let bid_vec: Vec<(f64, f64, i64)> = (0..10)
+            .map(|i| (10.0 - i as f64 * 0.01, 100.0 * (i + 1) as f64, 1))
+            .collect();
+        let ask_vec: Vec<(f64, f64, i64)> = (0..10)
+            .map(|i| (10.01 + i as f64 * 0.01, 100.0, 1))
+            .collect();
+        let mut snapshot =
+            OrderBookSnapshot::new("600000.SH".to_string(), "20231201".to_string(), 1);
+        snapshot.need_output = true;
+        snapshot.set_trim_to_level(Some(3));
+        let snapshot_ref: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(snapshot));
+        let order_ref = L3Order::new_ref(
+            OrderSourceType::LocalOrder,
+            None,
+            1,
+            Side::Buy,
+            1000,
+            1,
+            20231201093000000,
+            OrderType::L,
+        );
+        assert!(handler(
+            &snapshot_ref,
+            &StatisticsInfo::new(),
+            &bid_vec,
+            &ask_vec,
+            &order_ref
+        ));
+
+        let snapshot = snapshot_ref.borrow();
+        let snapshot = snapshot.downcast_ref::<OrderBookSnapshot>().unwrap();
+        assert_eq!(&snapshot.vec_bids_p[0][..3], &[10.0, 9.99, 9.98]);
+        assert_eq!(&snapshot.vec_bids_vol[0][..3], &[100, 200, 300]);
+        assert_eq!(&snapshot.vec_asks_p[0][..3], &[10.01, 10.02, 10.03]);
+        for levels in [&snapshot.vec_bids_p[0][3..], &snapshot.vec_asks_p[0][3..]] {
+            assert!(levels.iter().all(|&p| p == 0.0));
+        }
+        for levels in [
+            &snapshot.vec_bids_vol[0][3..],
+            &snapshot.vec_asks_vol[0][3..],
+            &snapshot.vec_bids_num[0][3..],
+            &snapshot.vec_asks_num[0][3..],
+        ] {
+            assert!(levels.iter().all(|&v| v == 0));
+        }
+        // 前 3 档：买方 600，卖方 300
+        assert!((snapshot.vec_depth_imbalance[0] - 600.0 / 900.0).abs() < 1e-12);
+        // 汇总字段同样只统计前 3 档
+        assert_eq!(snapshot.vec_total_volume[0], 900);
+        assert_eq!(snapshot.vec_total_trade_num[0], 6);
+        let avg_bid: f64 = (10.0 * 100.0 + 9.99 * 200.0 + 9.98 * 300.0) / 600.0;
+        assert_eq!(
+            snapshot.vec_avg_bid_price[0],
+            (avg_bid * 1000.0).round() / 1000.0
+        );
+        assert_eq!(snapshot.vec_avg_ask_price[0], 10.02);
+    }
+}
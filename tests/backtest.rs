@@ -0,0 +1,94 @@
+use hello_cargo::orderbook::backtest::{self, BacktestConfig, Manifest};
+use hello_cargo::orderbook::synthetic::{FlowConfig, SyntheticFlow};
+use hello_cargo::orderbook::MarketError;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("backtest_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn config(dir: &PathBuf) -> BacktestConfig {
+    BacktestConfig::from_args(
+        [
+            "--data",
+            dir.join("data").to_str().unwrap(),
+            "--symbol",
+            "000001.SZ",
+            "--date",
+            "20231201",
+            "--orders",
+            dir.join("orders.csv").to_str().unwrap(),
+            "--output",
+            dir.join("output").to_str().unwrap(),
+        ]
+        .map(String::from),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_backtest_on_synthetic_data() {
+    let dir = temp_dir("synthetic");
+    fs::write(
+        dir.join("orders.csv"),
+        "time,side,price,qty,type\n\
+         093100000,buy,10.10,200,L\n\
+         # 开盘集合竞价阶段的市价订单被拒绝\n\
+         091600000,sell,0,100,M\n\
+         20231201093200000,sell,9.90,100\n",
+    )
+    .unwrap();
+    let config = config(&dir);
+    let history = SyntheticFlow::new(FlowConfig::default())
+        .to_history("000001.SZ", 20231201093000000, 100, 2000, 0.01, 100.0)
+        .unwrap();
+
+    let summary = backtest::run_with_data(&config, history).unwrap();
+    assert_eq!(summary.history_events, 2000);
+    assert_eq!(summary.orders_submitted, 2);
+    assert_eq!(summary.orders_rejected, 1);
+    assert!(summary.book_updates >= 2000);
+    assert!(summary.filled_qty > 0.0);
+    assert_eq!(
+        summary.filled_qty,
+        summary.filled_qty_aggressive + summary.filled_qty_passive + summary.filled_qty_auction
+    );
+    assert!(summary.total_volume > 0.0);
+    assert!(summary.best_bid < summary.best_ask);
+
+    let path =
+        backtest::write_manifest(config.output_dir.as_ref().unwrap(), &config, &summary).unwrap();
+    let manifest: Manifest = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(manifest.config, config);
+    assert_eq!(manifest.summary, summary);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_backtest_reports_missing_data() {
+    let dir = temp_dir("missing");
+    fs::write(dir.join("orders.csv"), "").unwrap();
+    let config = config(&dir);
+    assert!(matches!(
+        backtest::run(&config),
+        Err(MarketError::DataLoadError(_))
+    ));
+    assert!(matches!(
+        backtest::parse_order_script("0930,buy,10.0,100,L", "20231201"),
+        Err(MarketError::InvalidBacktestArgs(_))
+    ));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_backtest"))
+        .args(["--data", dir.join("data").to_str().unwrap()])
+        .args(["--symbol", "000001.SZ", "--date", "20231201"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error:"));
+    fs::remove_dir_all(&dir).unwrap();
+}